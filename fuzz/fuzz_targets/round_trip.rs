@@ -0,0 +1,6 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use plt::fuzz::TemplateBuilder;
+
+fuzz_target!(|template: TemplateBuilder| plt::fuzz::fuzz_round_trip(&template));
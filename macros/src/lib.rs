@@ -0,0 +1,315 @@
+// Proc-macro front-end of plt, generating template functions at macro expansion time
+// instead of in a build script:
+//
+//     plt_macros::include_template!("templates/index.plt");
+//     plt_macros::include_templates!("templates");
+//
+//     #[derive(plt_macros::TemplateContext)]
+//     #[template(path = "templates/index.plt")]
+//     struct Index { title: String }
+//
+// Paths are relative to the directory of the crate's `Cargo.toml`. The options and search path
+// of the crate's `plt.toml`, if it has one, apply as in build scripts.
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context};
+use plt::build::template_location;
+use plt::prelude::*;
+use plt::project::ProjectConfig;
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Data, DataStruct, DeriveInput, Fields, LitStr};
+
+// Expands to the function generated from a single template, named after its file.
+// The template can't include other templates, use `include_templates!` for those.
+#[proc_macro]
+pub fn include_template(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let path = syn::parse_macro_input!(input as LitStr);
+
+    expand_or_error(&path, expand_template(&manifest_dir().join(path.value())))
+}
+
+// Expands to a `mod <dir name> { ... }` with the functions of all templates in the directory,
+// nested in modules mirroring its subdirectories.
+#[proc_macro]
+pub fn include_templates(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let path = syn::parse_macro_input!(input as LitStr);
+
+    expand_or_error(&path, expand_templates(&manifest_dir().join(path.value())))
+}
+
+// Implements `plt::prelude::TemplateContext` for a struct by passing its fields to the
+// template's arguments of the same name. Reference arguments get a reference to the field,
+// others a clone of it.
+#[proc_macro_derive(TemplateContext, attributes(template))]
+pub fn derive_template_context(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+
+    match expand_template_context(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+fn manifest_dir() -> PathBuf {
+    PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default())
+}
+
+// Config of the crate's `plt.toml`, if it has one.
+fn project_config() -> Result<Option<ProjectConfig>> {
+    ProjectConfig::find(&manifest_dir())
+}
+
+// Generator options of the `plt.toml`, or the default ones without it.
+fn project_options(config: Option<&ProjectConfig>) -> GeneratorOptions {
+    config.map(|config| config.build.generator.clone()).unwrap_or_default()
+}
+
+// Makes cargo recompile the crate when its `plt.toml` changes.
+fn track_config(config: Option<&ProjectConfig>) -> TokenStream {
+    config.map(|config| track_file(&config.file)).unwrap_or_default()
+}
+
+// Errors are reported at the path literal, as that's the closest span to the template. Their
+// location in the template is part of the message, see `locate_error`.
+fn expand_or_error(path: &LitStr, expansion: Result<TokenStream>) -> proc_macro::TokenStream {
+    match expansion {
+        Ok(tokens) => tokens.into(),
+        Err(error) => syn::Error::new(path.span(), format!("{error:#}")).to_compile_error().into(),
+    }
+}
+
+// Error of a template with the path of its file in its location, e.g.
+// `templates/index.plt:3:5: ...`, since spans of the macro input can't point into templates.
+// `location` turns the path of a template within its set into the path of its file.
+fn locate_error(error: anyhow::Error, location: impl Fn(&str) -> String) -> anyhow::Error {
+    let mut diagnostic = Diagnostic::from_error("", &error);
+    if diagnostic.span.is_none() {
+        return error;
+    }
+
+    diagnostic.file = location(&diagnostic.file);
+    diagnostic.into()
+}
+
+// Makes cargo recompile the crate when the template changes.
+fn track_file(file: &Path) -> TokenStream {
+    let file = file.display().to_string();
+
+    quote! { const _: &[u8] = include_bytes!(#file); }
+}
+
+// Generates the function of a single template, which is returned along with it.
+fn generate_template(file: &Path, options: &GeneratorOptions) -> Result<(Template, String)> {
+    let source = std::fs::read_to_string(file)
+        .with_context(|| format!("failed to read template `{}`", file.display()))?;
+
+    let file_name = file.file_name().unwrap_or_default().to_string_lossy().to_string();
+
+    let mut set = TemplateSet::new();
+    set.add_template(file_name.clone(), Vec::new(), source);
+    let code = set
+        .generate_with(&file_name, options)
+        .map_err(|error| locate_error(error, |_| file.display().to_string()))?
+        .join("\n");
+
+    Ok((set.get(&file_name).unwrap().clone(), code))
+}
+
+fn expand_template(file: &Path) -> Result<TokenStream> {
+    let config = project_config()?;
+    let options = GeneratorOptions { module_layout: ModuleLayout::Flat, ..project_options(config.as_ref()) };
+    let (_, code) = generate_template(file, &options)?;
+
+    let mut tokens = parse_code(&code)?;
+    tokens.extend(track_file(file));
+    tokens.extend(track_config(config.as_ref()));
+
+    Ok(tokens)
+}
+
+fn template_path_attribute(input: &DeriveInput) -> syn::Result<LitStr> {
+    let mut path = None;
+
+    for attribute in input.attrs.iter().filter(|attribute| attribute.path().is_ident("template")) {
+        attribute.parse_nested_meta(|meta| {
+            if meta.path.is_ident("path") {
+                path = Some(meta.value()?.parse::<LitStr>()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `path = \"...\"`"))
+            }
+        })?;
+    }
+
+    path.ok_or_else(|| syn::Error::new(input.ident.span(), "missing `#[template(path = \"...\")]` attribute"))
+}
+
+fn expand_template_context(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let path = template_path_attribute(input)?;
+    let to_error = |error: anyhow::Error| syn::Error::new(path.span(), format!("{error:#}"));
+
+    let Data::Struct(DataStruct { fields: Fields::Named(fields), .. }) = &input.data else {
+        return Err(syn::Error::new(input.ident.span(), "`TemplateContext` can only be derived for structs with named fields"));
+    };
+    let field_names: Vec<String> = fields
+        .named
+        .iter()
+        .filter_map(|field| field.ident.as_ref().map(|ident| ident.to_string()))
+        .collect();
+
+    let file = manifest_dir().join(path.value());
+    let config = project_config().map_err(to_error)?;
+    let options = GeneratorOptions { module_layout: ModuleLayout::Flat, visibility: String::new(), ..project_options(config.as_ref()) };
+    let (template, code) = generate_template(&file, &options).map_err(to_error)?;
+
+    let mut args = template.args.clone();
+    if template.has_slot().map_err(to_error)? {
+        args.push("slot: &str".to_string());
+    }
+
+    let mut call_args = Vec::new();
+    for arg in &args {
+        let arg = TemplateArg::parse(arg).map_err(to_error)?;
+
+        if !field_names.contains(&arg.name) {
+            return Err(syn::Error::new(
+                input.ident.span(),
+                format!("template `{}` expects `{}`, which `{}` doesn't provide", path.value(), arg.name, input.ident),
+            ));
+        }
+
+        let field = syn::Ident::new(&arg.name, proc_macro2::Span::call_site());
+        if arg.is_reference {
+            call_args.push(quote! { &self.#field });
+        } else {
+            call_args.push(quote! { self.#field.clone() });
+        }
+    }
+    for _ in template.blocks().map_err(to_error)? {
+        call_args.push(quote! { None });
+    }
+
+    let function = parse_code(&code).map_err(to_error)?;
+    let fn_name = syn::Ident::new(&TemplateSet::new().function_name(&template.path, &options), proc_macro2::Span::call_site());
+    let track_file = track_file(&file);
+    let track_config = track_config(config.as_ref());
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics plt::prelude::TemplateContext for #ident #ty_generics #where_clause {
+            fn render(&self) -> plt::prelude::Result<String> {
+                #function
+                #fn_name(#(#call_args),*)
+            }
+        }
+        #track_file
+        #track_config
+    })
+}
+
+fn expand_templates(dir: &Path) -> Result<TokenStream> {
+    let module_name = template_function_name(&dir.file_name().unwrap_or_default().to_string_lossy(), NameMangling::default());
+
+    let config = project_config()?;
+    let mut roots = vec![TemplateRoot::new(dir)];
+    roots.extend(config.iter().flat_map(|config| config.build.search_path.iter().cloned()));
+    let set = TemplateSet::load_roots(&roots)?;
+    let options = GeneratorOptions { module_layout: ModuleLayout::Tree, ..project_options(config.as_ref()) };
+    let code = set
+        .generate_module(&module_name, &options)
+        .map_err(|error| locate_error(error, |path| template_location(&set, dir, path)))?;
+
+    let mut tokens = parse_code(&code)?;
+    for root in &roots {
+        for file in template_files(&root.dir)? {
+            tokens.extend(track_file(&file));
+        }
+    }
+    tokens.extend(track_config(config.as_ref()));
+
+    Ok(tokens)
+}
+
+fn parse_code(code: &str) -> Result<TokenStream> {
+    code.parse::<TokenStream>()
+        .map_err(|error| anyhow!("generated code is not valid Rust: {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{expand_template, expand_template_context, expand_templates};
+    use std::path::Path;
+
+    #[test]
+    fn it_expands_a_single_template() {
+        let tokens = expand_template(Path::new("../src/test-files/file_generator_02.plt")).unwrap().to_string();
+
+        assert!(tokens.starts_with("pub fn file_generator_02 ()"));
+        assert!(tokens.contains("include_bytes ! (\"../src/test-files/file_generator_02.plt\")"));
+    }
+
+    #[test]
+    fn it_leaves_dumps_to_the_build_of_the_crate() {
+        let tokens = expand_template(Path::new("../src/test-files/dump.plt")).unwrap().to_string();
+
+        assert!(tokens.contains("if cfg ! (debug_assertions) { output_buffer . push_str (& plt :: prelude :: dump (& (user))) ; }"), "{tokens}");
+    }
+
+    #[test]
+    fn it_expands_a_template_directory() {
+        let tokens = expand_templates(Path::new("../src/test-files/build")).unwrap().to_string();
+
+        assert!(tokens.starts_with("pub mod build {"));
+        assert!(tokens.contains("pub mod partials {"));
+        assert!(tokens.contains("include_bytes ! (\"../src/test-files/build/partials/header.plt\")"));
+    }
+
+    #[test]
+    fn it_derives_template_context() {
+        let input = syn::parse_quote! {
+            #[template(path = "../src/test-files/build/partials/header.plt")]
+            struct Header<'a> { title: &'a str, unused: u8 }
+        };
+
+        let tokens = expand_template_context(&input).unwrap().to_string();
+
+        assert!(tokens.starts_with("impl < 'a > plt :: prelude :: TemplateContext for Header < 'a >"));
+        assert!(tokens.contains("header (& self . title)"));
+    }
+
+    #[test]
+    fn it_rejects_structs_missing_template_args() {
+        let input = syn::parse_quote! {
+            #[template(path = "../src/test-files/build/partials/header.plt")]
+            struct Header { name: String }
+        };
+
+        let error = expand_template_context(&input).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "template `../src/test-files/build/partials/header.plt` expects `title`, which `Header` doesn't provide"
+        );
+    }
+
+    #[test]
+    fn it_reports_the_locations_of_template_errors() {
+        let error = expand_template(Path::new("../src/test-files/build-errors/index.plt")).unwrap_err();
+        assert_eq!(error.to_string(), "../src/test-files/build-errors/index.plt:3:5: `@endmatch` found without matching `@match`");
+
+        let error = expand_templates(Path::new("../src/test-files/build-errors")).unwrap_err();
+        assert_eq!(error.to_string(), "../src/test-files/build-errors/index.plt:3:5: `@endmatch` found without matching `@match`");
+    }
+
+    #[test]
+    fn it_reports_template_errors() {
+        let error = expand_template(Path::new("../src/test-files/template_set_01.plt")).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "template `template_set_01.plt` includes `partials/header.plt`, which is not part of the template set"
+        );
+    }
+}
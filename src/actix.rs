@@ -0,0 +1,150 @@
+// Integration with actix-web, so handlers can return template structs directly:
+//
+//     #[get("/")]
+//     async fn index() -> impl Responder {
+//         IndexTemplate { title: "Home" }
+//     }
+//
+// Structs implement `Responder` when generated with `GeneratorOptions::actix`. To respond with
+// another status code or content type, wrap the template in a `TemplateResponse`:
+//
+//     TemplateResponse::new(NotFoundTemplate { path }).with_status(StatusCode::NOT_FOUND)
+use std::future::{ready, Ready};
+use actix_web::dev::Payload;
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{FromRequest, HttpMessage};
+use crate::csrf::CsrfToken;
+use crate::prelude::*;
+
+pub use actix_web::body::BoxBody;
+pub use actix_web::{HttpRequest, HttpResponse, Responder};
+
+// Error of a template which failed to render, stored in the extensions of the 500 response
+// so middleware can log it.
+#[derive(Debug)]
+pub struct RenderError(pub anyhow::Error);
+
+// Template rendered with a custom status code and content type.
+#[derive(Debug, Clone)]
+pub struct TemplateResponse<T> {
+    pub template: T,
+    pub status: StatusCode,
+    pub content_type: HeaderValue,
+}
+
+impl<T: TemplateContext> TemplateResponse<T> {
+    pub fn new(template: T) -> Self {
+        Self {
+            template,
+            status: StatusCode::OK,
+            content_type: HeaderValue::from_static("text/html; charset=utf-8"),
+        }
+    }
+
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn with_content_type(mut self, content_type: HeaderValue) -> Self {
+        self.content_type = content_type;
+        self
+    }
+
+    // Renders the template. Render errors become a 500 response, without leaking the error
+    // to the client.
+    pub fn into_response(self) -> HttpResponse {
+        match self.template.render() {
+            Ok(html) => HttpResponse::build(self.status)
+                .insert_header((header::CONTENT_TYPE, self.content_type))
+                .body(html),
+            Err(error) => {
+                let mut response = HttpResponse::InternalServerError().body("Internal Server Error");
+                response.extensions_mut().insert(RenderError(error));
+                response
+            }
+        }
+    }
+}
+
+impl<T: TemplateContext> Responder for TemplateResponse<T> {
+    type Body = BoxBody;
+
+    fn respond_to(self, _request: &HttpRequest) -> HttpResponse {
+        self.into_response()
+    }
+}
+
+// Renders the template as an HTML response, used by the generated `Responder` implementations.
+pub fn respond(template: impl TemplateContext) -> HttpResponse {
+    TemplateResponse::new(template).into_response()
+}
+
+// Extracts the token which middleware stored in the request extensions. Requests without one
+// are rejected with a 500 response, as the middleware is missing.
+impl FromRequest for CsrfToken {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let token = request.extensions().get::<CsrfToken>().cloned();
+
+        ready(token.ok_or_else(|| actix_web::error::ErrorInternalServerError("Internal Server Error")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::actix::{respond, RenderError, TemplateResponse};
+    use crate::csrf::CsrfToken;
+    use crate::prelude::*;
+    use actix_web::test::TestRequest;
+    use actix_web::{FromRequest, HttpMessage};
+    use actix_web::http::header::{HeaderValue, CONTENT_TYPE};
+    use actix_web::http::StatusCode;
+
+    struct Page(Result<&'static str, &'static str>);
+
+    impl TemplateContext for Page {
+        fn render(&self) -> Result<String> {
+            self.0.map(str::to_string).map_err(anyhow::Error::msg)
+        }
+    }
+
+    #[test]
+    fn it_responds_with_html() {
+        let response = respond(Page(Ok("<p>Hi</p>")));
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "text/html; charset=utf-8");
+    }
+
+    #[test]
+    fn it_customizes_the_status_and_content_type() {
+        let response = TemplateResponse::new(Page(Ok("<feed/>")))
+            .with_status(StatusCode::NOT_FOUND)
+            .with_content_type(HeaderValue::from_static("application/atom+xml"))
+            .into_response();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "application/atom+xml");
+    }
+
+    #[test]
+    fn it_maps_render_errors_to_internal_server_errors() {
+        let response = respond(Page(Err("missing user")));
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.extensions().get::<RenderError>().unwrap().0.to_string(), "missing user");
+    }
+
+    #[test]
+    fn it_extracts_csrf_tokens_from_extensions() {
+        let request = TestRequest::default().to_http_request();
+        assert!(CsrfToken::extract(&request).into_inner().is_err());
+
+        request.extensions_mut().insert(CsrfToken("abc".to_string()));
+        assert_eq!(CsrfToken::extract(&request).into_inner().unwrap(), CsrfToken("abc".to_string()));
+    }
+}
@@ -0,0 +1,105 @@
+// Parsing into a bump arena, for hot paths parsing many small templates over and over, e.g. a
+// development server re-reading templates on each request. Parts borrow their content from the
+// source copied into the arena instead of owning a `String` each, and resetting the arena reuses
+// its memory for the next batch:
+//
+//     let mut arena = Arena::new();
+//     for source in sources {
+//         let parsed = parse_in(&arena, source);
+//         ...
+//     }
+//     arena.reset();
+use std::ops::Range;
+use crate::prelude::*;
+use crate::text_code_fsa::TextCodeFSAState;
+
+pub use bumpalo::Bump as Arena;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartRef<'a> {
+    Text(&'a str),
+    Code(&'a str),
+    EchoCode(&'a str),
+}
+
+impl<'a> PartRef<'a> {
+    pub fn is_text(&self) -> bool {
+        matches!(self, PartRef::Text(_))
+    }
+
+    pub fn get_content(&self) -> &'a str {
+        match self {
+            PartRef::Text(content) | PartRef::Code(content) | PartRef::EchoCode(content) => content,
+        }
+    }
+
+    pub fn to_part(&self) -> Part {
+        match self {
+            PartRef::Text(content) => Part::Text(content.to_string()),
+            PartRef::Code(content) => Part::Code(content.to_string()),
+            PartRef::EchoCode(content) => Part::EchoCode(content.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ParsedIn<'a> {
+    pub source: &'a str,
+    pub parts: &'a [PartRef<'a>],
+    // Byte ranges of the parts' content in the source, in the same order as the parts.
+    pub spans: &'a [Range<usize>],
+    // Byte offset of the tag which is still open at the end of the source, if any.
+    pub unterminated_tag: Option<usize>,
+}
+
+// Splits `input` into parts like `TextCodeFSA`, allocating only in the arena.
+pub fn parse_in<'a>(arena: &'a Arena, input: &str) -> ParsedIn<'a> {
+    let source: &'a str = arena.alloc_str(input);
+    let mut parts = bumpalo::collections::Vec::new_in(arena);
+    let mut spans = bumpalo::collections::Vec::new_in(arena);
+
+    let mut fsa = TextCodeFSA::new();
+    fsa.scan(source, |state, span| {
+        let content = &source[span.clone()];
+        parts.push(match state {
+            TextCodeFSAState::ParsingText => PartRef::Text(content),
+            TextCodeFSAState::ParsingCode => PartRef::Code(content),
+            TextCodeFSAState::ParsingEchoCode => PartRef::EchoCode(content),
+        });
+        spans.push(span);
+    });
+
+    ParsedIn { source, parts: parts.into_bump_slice(), spans: spans.into_bump_slice(), unterminated_tag: fsa.unterminated_tag() }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arena::{parse_in, Arena, PartRef};
+    use crate::prelude::*;
+
+    #[test]
+    fn it_parses_like_the_fsa() {
+        let arena = Arena::new();
+        let sources = ["", "a<?rs \"?>\" ?>é<?= b ?>", "<?rs /* ?> */ ?><?=?>c<?rs // ?>\n?>", "d<?rs e"];
+
+        for source in sources {
+            let parsed = parse_in(&arena, source);
+            let mut fsa = TextCodeFSA::new();
+            let parts = fsa.run(source.to_string()).clone();
+
+            assert_eq!(parsed.parts.iter().map(PartRef::to_part).collect::<Vec<_>>(), parts);
+            assert_eq!(parsed.spans, fsa.spans());
+            assert_eq!(parsed.unterminated_tag, fsa.unterminated_tag());
+        }
+    }
+
+    #[test]
+    fn it_reuses_the_arena_after_a_reset() {
+        let mut arena = Arena::new();
+        for _ in 0..3 {
+            let parsed = parse_in(&arena, "<p><?= title ?></p>");
+            assert_eq!(parsed.parts, [PartRef::Text("<p>"), PartRef::EchoCode(" title "), PartRef::Text("</p>")]);
+            arena.reset();
+        }
+    }
+}
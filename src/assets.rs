@@ -0,0 +1,377 @@
+// Fingerprinted static assets, which browsers can cache forever since their URL changes
+// with their content:
+//
+//     <link rel="stylesheet" href="<?rs @asset "css/app.css" ?>">
+//
+// With `GeneratorOptions::asset_dir` set to `static`, the directive reads `static/css/app.css`
+// while generating and renders `/assets/css/app.3f2a9c0b1d4e5f67.css`. The copies named after
+// the hashes are written by `AssetManifest::write_dir`, e.g. through `BuildOptions::asset_out_dir`.
+//
+// `@asset_sri` also renders the Subresource Integrity hash, so browsers refuse copies which
+// were tampered with, e.g. on a CDN. It renders the whole attributes, using `href` for
+// stylesheets and `src` otherwise:
+//
+//     <script <?rs @asset_sri "js/app.js" ?>></script>
+//
+// becomes `<script src="/assets/js/app.….js" integrity="sha384-…" crossorigin="anonymous"></script>`.
+//
+// Templates referencing assets only known at runtime use the manifest written next to them:
+//
+//     <?rs @args assets: &plt::assets::AssetManifest, script: &str ?>
+//     <script src="<?= assets.url(script)? ?>"></script>
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use anyhow::{bail, Context};
+use base64::Engine;
+use sha2::{Digest, Sha384};
+use crate::prelude::*;
+
+const MANIFEST_HEADER: &str = "plt-assets 2";
+
+// Hash of the content of an asset, as 16 hex digits.
+pub fn fingerprint(content: &[u8]) -> String {
+    let mut hasher = StableHasher::default();
+    hasher.write(content);
+
+    format!("{:016x}", hasher.finish())
+}
+
+// Subresource Integrity hash of the content of an asset, e.g. `sha384-oqVuAfXR…`.
+pub fn integrity(content: &[u8]) -> String {
+    format!("sha384-{}", base64::engine::general_purpose::STANDARD.encode(Sha384::digest(content)))
+}
+
+// Path of the copy of an asset, with the fingerprint of its content inserted before the
+// extension, e.g. `css/app.css` becomes `css/app.3f2a9c0b1d4e5f67.css`.
+pub fn fingerprinted_path(path: &str, content: &[u8]) -> String {
+    let fingerprint = fingerprint(content);
+    let name_start = path.rfind('/').map_or(0, |index| index + 1);
+
+    match path[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let (stem, extension) = path.split_at(name_start + dot);
+            format!("{stem}.{fingerprint}{extension}")
+        }
+        _ => format!("{path}.{fingerprint}"),
+    }
+}
+
+// URL of `path` below `url_prefix`, e.g. `/assets/css/app.css`.
+pub fn asset_url(url_prefix: &str, path: &str) -> String {
+    format!("{}/{path}", url_prefix.trim_end_matches('/'))
+}
+
+// All files under `dir`, sorted by path.
+fn asset_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let entries = std::fs::read_dir(&dir)
+            .with_context(|| format!("failed to read asset directory `{}`", dir.display()))?;
+
+        for entry in entries {
+            let path = entry?.path();
+
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+fn read_asset(asset_dir: &Path, path: &str) -> Result<Vec<u8>> {
+    // Absolute paths and `..` would read files outside of the asset directory.
+    if !Path::new(path).components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+        bail!("asset path `{path}` must be relative to the asset directory, without `..`");
+    }
+    let file = asset_dir.join(path);
+
+    std::fs::read(&file).with_context(|| format!("failed to read asset `{}`", file.display()))
+}
+
+// Content of an asset referenced by a template while generating it.
+fn read_generated_asset(options: &GeneratorOptions, path: &str) -> Result<Vec<u8>> {
+    let Some(asset_dir) = &options.asset_dir else {
+        bail!("asset `{path}` can't be resolved without `GeneratorOptions::asset_dir`");
+    };
+
+    read_asset(asset_dir, path)
+}
+
+// URL rendered by `@asset "<path>"`.
+pub(crate) fn resolve_asset(options: &GeneratorOptions, path: &str) -> Result<String> {
+    let content = read_generated_asset(options, path)?;
+
+    Ok(asset_url(&options.asset_url, &fingerprinted_path(path, &content)))
+}
+
+// Attributes rendered by `@asset_sri "<path>"`.
+pub(crate) fn resolve_asset_sri(options: &GeneratorOptions, path: &str) -> Result<String> {
+    let content = read_generated_asset(options, path)?;
+    let url = asset_url(&options.asset_url, &fingerprinted_path(path, &content));
+
+    Ok(integrity_attributes(path, &url, &integrity(&content)))
+}
+
+// `src` or `href` attribute along with the `integrity` one, as rendered by `@asset_sri`.
+pub fn integrity_attributes(path: &str, url: &str, integrity: &str) -> String {
+    let url_attribute = if path.ends_with(".css") { "href" } else { "src" };
+
+    format!("{url_attribute}=\"{}\" integrity=\"{integrity}\" crossorigin=\"anonymous\"", escape_html(url))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct AssetEntry {
+    fingerprinted: String,
+    integrity: String,
+}
+
+// Fingerprinted paths and integrity hashes of assets keyed by their paths, e.g. `css/app.css`,
+// persisted as an `assets.manifest` file next to the copies of the assets.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AssetManifest {
+    url_prefix: String,
+    entries: BTreeMap<String, AssetEntry>,
+}
+
+impl AssetManifest {
+    pub const FILE_NAME: &'static str = "assets.manifest";
+
+    pub fn new(url_prefix: impl Into<String>) -> AssetManifest {
+        AssetManifest { url_prefix: url_prefix.into(), entries: BTreeMap::new() }
+    }
+
+    // Fingerprints all files under `asset_dir`.
+    pub fn load_dir(asset_dir: impl AsRef<Path>, url_prefix: impl Into<String>) -> Result<AssetManifest> {
+        let asset_dir = asset_dir.as_ref();
+        let mut manifest = Self::new(url_prefix);
+
+        for file in asset_files(asset_dir)? {
+            let path = relative_template_path(asset_dir, &file)?;
+            let content = read_asset(asset_dir, &path)?;
+            manifest.insert(path, &content);
+        }
+
+        Ok(manifest)
+    }
+
+    // Loads a manifest written by `write_dir`.
+    pub fn load(path: impl AsRef<Path>) -> Result<AssetManifest> {
+        let path = path.as_ref();
+
+        let manifest = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read asset manifest `{}`", path.display()))?;
+
+        Self::parse(&manifest).with_context(|| format!("malformed asset manifest `{}`", path.display()))
+    }
+
+    pub fn insert(&mut self, path: impl Into<String>, content: &[u8]) {
+        let path = path.into();
+        let entry = AssetEntry { fingerprinted: fingerprinted_path(&path, content), integrity: integrity(content) };
+
+        self.entries.insert(path, entry);
+    }
+
+    // Paths of the assets, e.g. `css/app.css`.
+    pub fn paths(&self) -> impl Iterator<Item = &String> {
+        self.entries.keys()
+    }
+
+    // Fingerprinted path of the asset under `path`.
+    pub fn get(&self, path: &str) -> Option<&str> {
+        self.entries.get(path).map(|entry| entry.fingerprinted.as_str())
+    }
+
+    // Subresource Integrity hash of the asset under `path`.
+    pub fn integrity(&self, path: &str) -> Option<&str> {
+        self.entries.get(path).map(|entry| entry.integrity.as_str())
+    }
+
+    // URL of the fingerprinted copy of the asset under `path`.
+    pub fn url(&self, path: &str) -> Result<String> {
+        match self.get(path) {
+            Some(fingerprinted) => Ok(asset_url(&self.url_prefix, fingerprinted)),
+            None => bail!("unknown asset `{path}`"),
+        }
+    }
+
+    // Copies the assets of `asset_dir` into `out_dir` under their fingerprinted paths, next to
+    // the manifest itself.
+    pub fn write_dir(&self, asset_dir: impl AsRef<Path>, out_dir: impl AsRef<Path>) -> Result<()> {
+        let asset_dir = asset_dir.as_ref();
+        let out_dir = out_dir.as_ref();
+
+        for (path, entry) in &self.entries {
+            let file = out_dir.join(&entry.fingerprinted);
+            // Fingerprinted copies never change, so existing ones are kept.
+            if file.exists() {
+                continue;
+            }
+
+            std::fs::create_dir_all(file.parent().unwrap())
+                .with_context(|| format!("failed to create `{}`", out_dir.display()))?;
+            std::fs::write(&file, read_asset(asset_dir, path)?)
+                .with_context(|| format!("failed to write `{}`", file.display()))?;
+        }
+
+        let file = out_dir.join(Self::FILE_NAME);
+        std::fs::write(&file, self.to_manifest()?).with_context(|| format!("failed to write `{}`", file.display()))
+    }
+
+    // Files `write_dir` would write into `out_dir`: missing fingerprinted copies and the manifest
+    // if it changed.
+    pub fn stale_files(&self, out_dir: impl AsRef<Path>) -> Vec<PathBuf> {
+        let out_dir = out_dir.as_ref();
+
+        let mut files: Vec<_> = self
+            .entries
+            .values()
+            .map(|entry| out_dir.join(&entry.fingerprinted))
+            .filter(|file| !file.exists())
+            .collect();
+
+        let manifest = out_dir.join(Self::FILE_NAME);
+        if !std::fs::read_to_string(&manifest).is_ok_and(|existing| self.to_manifest().is_ok_and(|current| existing == current)) {
+            files.push(manifest);
+        }
+
+        files
+    }
+
+    // A `plt-assets 2<tab><url prefix>` header followed by a `<path><tab><fingerprinted
+    // path><tab><integrity>` line per asset, so paths may contain spaces.
+    fn to_manifest(&self) -> Result<String> {
+        let mut manifest = format!("{MANIFEST_HEADER}\t{}\n", self.url_prefix);
+
+        for (path, entry) in &self.entries {
+            if path.contains(['\t', '\n', '\r']) {
+                bail!("asset path {path:?} contains a tab or a line break, which the asset manifest can't hold");
+            }
+
+            manifest.push_str(&format!("{path}\t{}\t{}\n", entry.fingerprinted, entry.integrity));
+        }
+
+        Ok(manifest)
+    }
+
+    fn parse(manifest: &str) -> Result<AssetManifest> {
+        let mut lines = manifest.lines();
+
+        let Some(url_prefix) = lines.next().and_then(|header| header.strip_prefix(MANIFEST_HEADER)?.strip_prefix('\t')) else {
+            bail!("expected the `{MANIFEST_HEADER}` header");
+        };
+
+        let mut assets = Self::new(url_prefix);
+        for line in lines {
+            let mut fields = line.split('\t');
+            let (Some(path), Some(fingerprinted), Some(integrity), None) = (fields.next(), fields.next(), fields.next(), fields.next()) else {
+                bail!("malformed entry `{line}`");
+            };
+
+            let entry = AssetEntry { fingerprinted: fingerprinted.to_string(), integrity: integrity.to_string() };
+            assets.entries.insert(path.to_string(), entry);
+        }
+
+        Ok(assets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assets::{fingerprint, fingerprinted_path, integrity, resolve_asset, resolve_asset_sri, AssetManifest};
+    use crate::prelude::*;
+
+    #[test]
+    fn it_inserts_fingerprints_before_extensions() {
+        let fingerprint = fingerprint(b"body {}");
+
+        assert_eq!(fingerprint.len(), 16);
+        assert_eq!(fingerprinted_path("css/app.css", b"body {}"), format!("css/app.{fingerprint}.css"));
+        assert_eq!(fingerprinted_path("robots", b"body {}"), format!("robots.{fingerprint}"));
+        assert_eq!(fingerprinted_path("img/.hidden", b"body {}"), format!("img/.hidden.{fingerprint}"));
+    }
+
+    #[test]
+    fn it_resolves_assets_while_generating() {
+        let options = GeneratorOptions { asset_dir: Some("src/test-files/assets".into()), ..GeneratorOptions::default() };
+        let content = std::fs::read("src/test-files/assets/css/app.css").unwrap();
+
+        assert_eq!(
+            resolve_asset(&options, "css/app.css").unwrap(),
+            format!("/assets/css/app.{}.css", fingerprint(&content))
+        );
+        assert!(resolve_asset(&options, "css/missing.css").is_err());
+        assert!(resolve_asset(&GeneratorOptions::default(), "css/app.css").is_err());
+    }
+
+    #[test]
+    fn it_computes_sha384_integrity_hashes() {
+        // Test vector of FIPS 180-2.
+        assert_eq!(integrity(b"abc"), "sha384-ywB1P0WjXou1oD1pmsZQBycsMqsO3tFjGotgWkP/W+2AhgcroefMI1i67KE0yCWn");
+
+        let options = GeneratorOptions { asset_dir: Some("src/test-files/assets".into()), ..GeneratorOptions::default() };
+        let content = std::fs::read("src/test-files/assets/css/app.css").unwrap();
+        assert_eq!(
+            resolve_asset_sri(&options, "css/app.css").unwrap(),
+            format!(
+                "href=\"/assets/css/app.{}.css\" integrity=\"{}\" crossorigin=\"anonymous\"",
+                fingerprint(&content),
+                integrity(&content)
+            )
+        );
+    }
+
+    #[test]
+    fn it_writes_fingerprinted_copies_and_the_manifest() {
+        let out_dir = std::env::temp_dir().join(format!("plt-assets-{}", std::process::id()));
+        let manifest = AssetManifest::load_dir("src/test-files/assets", "https://cdn.example.com/").unwrap();
+        let stale = manifest.stale_files(&out_dir).len();
+
+        manifest.write_dir("src/test-files/assets", &out_dir).unwrap();
+        let loaded = AssetManifest::load(out_dir.join(AssetManifest::FILE_NAME)).unwrap();
+        let copied = out_dir.join(manifest.get("css/app.css").unwrap()).exists();
+        let stale_after_writing = manifest.stale_files(&out_dir);
+        std::fs::remove_dir_all(&out_dir).unwrap();
+
+        assert!(copied);
+        assert_eq!(stale, manifest.entries.len() + 1);
+        assert!(stale_after_writing.is_empty());
+        assert_eq!(loaded, manifest);
+        assert_eq!(
+            loaded.url("css/app.css").unwrap(),
+            format!("https://cdn.example.com/{}", manifest.get("css/app.css").unwrap())
+        );
+        assert_eq!(loaded.integrity("css/app.css"), manifest.integrity("css/app.css"));
+        assert!(loaded.url("js/missing.js").is_err());
+    }
+
+    #[test]
+    fn it_reads_back_manifests_of_paths_with_spaces() {
+        let mut manifest = AssetManifest::new("/assets");
+        manifest.insert("img/my logo.png", b"png");
+
+        assert_eq!(AssetManifest::parse(&manifest.to_manifest().unwrap()).unwrap(), manifest);
+
+        manifest.insert("img/a\tb.png", b"png");
+        assert!(manifest.to_manifest().is_err());
+    }
+
+    #[test]
+    fn it_rejects_asset_paths_outside_of_the_asset_dir() {
+        let options = GeneratorOptions { asset_dir: Some("src/test-files/assets/css".into()), ..GeneratorOptions::default() };
+
+        assert!(resolve_asset(&options, "app.css").is_ok());
+        assert_eq!(
+            resolve_asset(&options, "../css/app.css").unwrap_err().to_string(),
+            "asset path `../css/app.css` must be relative to the asset directory, without `..`"
+        );
+        assert!(resolve_asset(&options, "/etc/passwd").is_err());
+    }
+}
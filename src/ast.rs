@@ -0,0 +1,266 @@
+// Parsed form of a template for tools analyzing templates, e.g. custom linters or migration
+// scripts, as a list of nodes with their spans in the source. `plt parse --json` writes it as
+// JSON, with an object per node:
+//
+//     {"kind":"directive","span":{...},"name":"if","arguments":{"condition":"user.is_admin"}}
+use anyhow::bail;
+use crate::diagnostics::json_string;
+use crate::lint::Lint;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Text(String),
+    // Plain Rust code of a `<?rs ?>` tag, along with the whitespace around it.
+    Code(String),
+    // Expression of a `<?= ?>` tag, along with the whitespace around it.
+    Echo(String),
+    Directive(Directive),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedTemplate {
+    pub path: String,
+    pub nodes: Vec<Node>,
+    // Spans of the nodes in the source, of the content of the tag for code, echoes and directives.
+    pub spans: Vec<Span>,
+}
+
+impl ParsedTemplate {
+    pub fn parse(template: &Template) -> Result<ParsedTemplate> {
+        if let Some(offset) = template.unterminated_tag {
+            return Err(template.unterminated_tag_error(offset));
+        }
+
+        let mut nodes = Vec::new();
+        for (part, span) in template.parts.iter().zip(&template.spans) {
+            match node(part) {
+                Ok(node) => nodes.push(node),
+                Err(error) => return Err(template.diagnostic(span.clone(), "invalid-template", error).into()),
+            }
+        }
+        let spans = template.spans.iter().map(|span| Span::new(&template.source, span.start, span.end)).collect();
+
+        Ok(ParsedTemplate { path: template.path.clone(), nodes, spans })
+    }
+
+    // Source of the template, which parses back to the same nodes. Fails for nodes which can't be
+    // written that way, e.g. text containing `<?rs` or two text nodes in a row.
+    pub fn to_source(&self) -> Result<String> {
+        let mut source = String::new();
+        for node in &self.nodes {
+            match node {
+                Node::Text(text) => source.push_str(text),
+                Node::Code(code) => source.push_str(&format!("<?rs{code}?>")),
+                Node::Echo(code) => source.push_str(&format!("<?={code}?>")),
+                Node::Directive(directive) => source.push_str(&format!("<?rs {directive} ?>")),
+            }
+        }
+
+        let mut fsa = TextCodeFSA::new();
+        let parts = fsa.run(source.clone()).clone();
+        if fsa.unterminated_tag().is_some() {
+            bail!("`{}` can't be written as a template, it would contain an unterminated tag", self.path);
+        }
+        let nodes: Vec<_> = parts.iter().map(|part| node(part).ok()).collect();
+        let mismatch = (0..self.nodes.len().max(nodes.len())).find(|&index| self.nodes.get(index) != nodes.get(index).and_then(Option::as_ref));
+        if let Some(index) = mismatch {
+            bail!("`{}` can't be written as a template, node {index} wouldn't parse back the same", self.path);
+        }
+
+        Ok(source)
+    }
+
+    pub fn to_json(&self) -> String {
+        let nodes: Vec<_> = self.nodes.iter().zip(&self.spans).map(|(node, span)| node_json(node, span)).collect();
+
+        format!("{{\"path\":{},\"nodes\":[{}]}}", json_string(&self.path), nodes.join(","))
+    }
+}
+
+fn node(part: &Part) -> Result<Node> {
+    let node = match part {
+        Part::Text(text) => Node::Text(text.clone()),
+        Part::EchoCode(code) => Node::Echo(code.clone()),
+        Part::Code(code) => match Directive::parse(code)? {
+            Some(directive) => Node::Directive(directive),
+            None => Node::Code(code.clone()),
+        },
+    };
+
+    Ok(node)
+}
+
+// Argument of a directive as written to JSON.
+enum Argument<'a> {
+    Single(&'a str),
+    Optional(Option<&'a str>),
+    List(Vec<&'a str>),
+    // Named arguments in order, e.g. of `@call`, as objects with a `name` and a `value`.
+    Named(&'a [(String, String)]),
+}
+
+impl Argument<'_> {
+    fn to_json(&self) -> String {
+        match self {
+            Argument::Single(value) | Argument::Optional(Some(value)) => json_string(value),
+            Argument::Optional(None) => "null".to_string(),
+            Argument::List(values) => {
+                let values: Vec<_> = values.iter().map(|value| json_string(value)).collect();
+                format!("[{}]", values.join(","))
+            }
+            Argument::Named(args) => {
+                let args: Vec<_> = args
+                    .iter()
+                    .map(|(name, value)| format!("{{\"name\":{},\"value\":{}}}", json_string(name), json_string(value)))
+                    .collect();
+                format!("[{}]", args.join(","))
+            }
+        }
+    }
+}
+
+fn node_json(node: &Node, span: &Span) -> String {
+    let (kind, content) = match node {
+        Node::Text(text) => ("text", format!("\"text\":{}", json_string(text))),
+        Node::Code(code) => ("code", format!("\"code\":{}", json_string(code))),
+        Node::Echo(code) => ("echo", format!("\"code\":{}", json_string(code))),
+        Node::Directive(directive) => {
+            let arguments: Vec<_> = arguments(directive)
+                .iter()
+                .map(|(name, argument)| format!("{}:{}", json_string(name), argument.to_json()))
+                .collect();
+            ("directive", format!("\"name\":{},\"arguments\":{{{}}}", json_string(directive.name()), arguments.join(",")))
+        }
+    };
+
+    format!("{{\"kind\":\"{kind}\",\"span\":{},{content}}}", span.to_json())
+}
+
+fn arguments(directive: &Directive) -> Vec<(&'static str, Argument<'_>)> {
+    use Argument::*;
+
+    match directive {
+        Directive::Match(expression) | Directive::Json(expression) | Directive::Dump(expression) | Directive::Const(expression) => {
+            vec![("expression", Single(expression))]
+        }
+        Directive::When(pattern) => vec![("pattern", Single(pattern))],
+        Directive::Let(binding) => vec![("binding", Single(binding))],
+        Directive::Capture(name) | Directive::Block(name) | Directive::Yield(name) => vec![("name", Single(name))],
+        Directive::Include { path, args } | Directive::Extends { path, args } => vec![("path", Single(path)), ("args", Single(args))],
+        Directive::Use { name, args } => vec![("name", Single(name)), ("args", Single(args))],
+        Directive::Call { path, args } => vec![("path", Single(path)), ("args", Named(args))],
+        Directive::Args(args) => vec![("args", List(args.iter().map(AsRef::as_ref).collect()))],
+        Directive::If(condition) | Directive::ElseIf(condition) => vec![("condition", Single(condition))],
+        Directive::For { pattern, iterable } => vec![("pattern", Single(pattern)), ("iterable", Single(iterable))],
+        Directive::Translate { key, args } => vec![("key", Single(key)), ("args", Named(args))],
+        Directive::TranslatePlural { key, count, args } => vec![("key", Single(key)), ("count", Single(count)), ("args", Named(args))],
+        Directive::Asset(path) | Directive::AssetSri(path) => vec![("path", Single(path))],
+        Directive::Url { route, args } => vec![("route", Single(route)), ("args", Named(args))],
+        Directive::Rescue(binding) => vec![("binding", Optional(binding.as_deref()))],
+        Directive::Cache { key, ttl } => vec![("key", Single(key)), ("ttl", Optional(ttl.as_deref()))],
+        Directive::Env(variable) => vec![("variable", Single(variable))],
+        Directive::Cfg(predicate) => vec![("predicate", Single(predicate))],
+        Directive::Example(context) => vec![("context", Single(context))],
+        Directive::Lint { lints, .. } => vec![("lints", List(lints.iter().map(Lint::name).collect()))],
+        Directive::Template { name, args } | Directive::Def { name, args } => vec![("name", Single(name)), ("args", List(args.iter().map(AsRef::as_ref).collect()))],
+        Directive::EndMatch
+        | Directive::EndCapture
+        | Directive::EndBlock
+        | Directive::EndCall
+        | Directive::Slot
+        | Directive::Else
+        | Directive::EndIf
+        | Directive::EndFor
+        | Directive::Nonce
+        | Directive::Csrf
+        | Directive::Try
+        | Directive::EndTry
+        | Directive::EndCache
+        | Directive::BuiltAt
+        | Directive::GitSha
+        | Directive::EndCfg
+        | Directive::EndTemplate
+        | Directive::EndDef => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    fn parse(source: &str) -> Result<ParsedTemplate> {
+        let mut set = TemplateSet::new();
+        set.add_template("index.plt", Vec::new(), source.to_string());
+
+        ParsedTemplate::parse(set.get("index.plt").unwrap())
+    }
+
+    #[test]
+    fn it_parses_templates_into_nodes() {
+        let parsed = parse("<p>\n<?rs @if a ?><?= a ?><?rs let b = 1; ?><?rs @endif ?>").unwrap();
+
+        assert_eq!(
+            parsed.nodes,
+            vec![
+                Node::Text("<p>\n".to_string()),
+                Node::Directive(Directive::If("a".to_string())),
+                Node::Echo(" a ".to_string()),
+                Node::Code(" let b = 1; ".to_string()),
+                Node::Directive(Directive::EndIf),
+            ]
+        );
+        assert_eq!((parsed.spans[1].line, parsed.spans[1].column), (2, 5));
+    }
+
+    #[test]
+    fn it_writes_the_nodes_as_json() {
+        let parsed = parse("<?rs @call \"card.plt\"(title = \"A \\\"b\\\"\") ?><?rs @rescue ?>").unwrap();
+
+        assert_eq!(
+            parsed.to_json(),
+            "{\"path\":\"index.plt\",\"nodes\":[\
+             {\"kind\":\"directive\",\"span\":{\"start\":4,\"end\":41,\"line\":1,\"column\":5,\"end_line\":1,\"end_column\":42},\
+             \"name\":\"call\",\"arguments\":{\"path\":\"card.plt\",\"args\":[{\"name\":\"title\",\"value\":\"\\\"A \\\\\\\"b\\\\\\\"\\\"\"}]}},\
+             {\"kind\":\"directive\",\"span\":{\"start\":47,\"end\":56,\"line\":1,\"column\":48,\"end_line\":1,\"end_column\":57},\
+             \"name\":\"rescue\",\"arguments\":{\"binding\":null}}]}"
+        );
+    }
+
+    #[test]
+    fn it_writes_the_nodes_back_as_source() {
+        let source = "<?rs @args title: &str, items: &[Item] ?><h1><?= title ?></h1>\n\
+                      <?rs @for (i, item) in items.iter().enumerate() ?><?rs @if i > 0 ?>, <?rs @endif ?>\
+                      <?rs @call \"card.plt\"(title = item.name, n = 1) ?><?rs @endcall ?><?rs @endfor ?>\
+                      <?rs @include \"footer.plt\" with (title) ?><?rs @t_plural(\"items\", items.len(), n = 2) ?>\
+                      <?rs @cache(\"side\", ttl = 60) ?><?rs @endcache ?><?rs @cfg(feature = \"a\") ?><?rs @endcfg ?>\
+                      <?rs @url posts.show(id = 1) ?><?rs @deny(empty_code, long_text) ?><?rs @try ?><?rs @rescue e ?><?rs @endtry ?>\
+                      <?rs let x = \"?>\"; ?><?rs @env \"HOME\" ?><?rs @else if x ?>";
+        let parsed = parse(source).unwrap();
+
+        let written = parsed.to_source().unwrap();
+        assert_eq!(parse(&written).unwrap().nodes, parsed.nodes);
+        assert!(written.starts_with("<?rs @args title : & str, items : & [Item] ?><h1><?= title ?></h1>"));
+    }
+
+    #[test]
+    fn it_fails_to_write_nodes_which_wouldnt_parse_back() {
+        let mut parsed = parse("a").unwrap();
+        parsed.nodes.push(Node::Text("b".to_string()));
+        assert_eq!(parsed.to_source().unwrap_err().to_string(), "`index.plt` can't be written as a template, node 0 wouldn't parse back the same");
+
+        parsed.nodes = vec![Node::Code(" @if a ".to_string())];
+        assert!(parsed.to_source().is_err());
+        parsed.nodes = vec![Node::Text("<?= a".to_string())];
+        assert!(parsed.to_source().is_err());
+        parsed.nodes = vec![Node::Directive(Directive::Asset("a \"b\".css".to_string()))];
+        assert_eq!(parsed.to_source().unwrap(), "<?rs @asset \"a \\\"b\\\".css\" ?>");
+    }
+
+    #[test]
+    fn it_fails_on_malformed_directives() {
+        let error = parse("text\n<?rs @endif 1 ?>").unwrap_err();
+
+        assert_eq!(error.to_string(), "index.plt:2:5: directive `@endif` does not take an argument, found `1`");
+    }
+}
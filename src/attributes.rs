@@ -0,0 +1,208 @@
+// Helpers rendering attributes from echo tags:
+//
+//     <button<?= Attributes::new().set("type", "submit").flag("disabled", is_saving) ?>>
+//     <input<?= input_attributes.iter().collect::<Attributes>() ?>>
+//     <a class="<?= plt::classes!["nav-link", ("active", is_current)] ?>">
+//
+// `Attributes` render with a leading space and escape their values themselves, so they're
+// written as they are by `EscapeHtml`. Attributes whose names contain characters which aren't
+// allowed in names, e.g. from user provided maps, are left out rather than breaking the tag.
+// Class lists are escaped like other echoed values.
+use std::fmt;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Attributes {
+    // `None` values are boolean attributes, rendered without a value.
+    attributes: Vec<(String, Option<String>)>,
+}
+
+impl Attributes {
+    pub fn new() -> Attributes {
+        Self::default()
+    }
+
+    // Sets the attribute, replacing an earlier value of it.
+    pub fn set(mut self, name: impl Into<String>, value: impl fmt::Display) -> Self {
+        self.insert(name.into(), Some(value.to_string()));
+        self
+    }
+
+    // Sets the attribute only if there is a value, e.g. for an optional `title`.
+    pub fn set_some(self, name: impl Into<String>, value: Option<impl fmt::Display>) -> Self {
+        match value {
+            Some(value) => self.set(name, value),
+            None => self,
+        }
+    }
+
+    // Sets the boolean attribute, e.g. `disabled`, if `enabled`.
+    pub fn flag(mut self, name: impl Into<String>, enabled: bool) -> Self {
+        if enabled {
+            self.insert(name.into(), None);
+        }
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<Option<&str>> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_deref())
+    }
+
+    // Used by contextual escaping for echoes inside of tags, which only compile for `Attributes`.
+    pub fn in_tag(attributes: &Attributes) -> &Attributes {
+        attributes
+    }
+
+    fn insert(&mut self, name: String, value: Option<String>) {
+        match self.attributes.iter_mut().find(|(attribute, _)| *attribute == name) {
+            Some(attribute) => attribute.1 = value,
+            None => self.attributes.push((name, value)),
+        }
+    }
+}
+
+impl<K: Into<String>, V: fmt::Display> FromIterator<(K, V)> for Attributes {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(attributes: I) -> Self {
+        attributes.into_iter().fold(Attributes::new(), |attributes, (name, value)| attributes.set(name, value))
+    }
+}
+
+impl<K: Into<String>, V: fmt::Display> Extend<(K, V)> for Attributes {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, attributes: I) {
+        for (name, value) in attributes {
+            self.insert(name.into(), Some(value.to_string()));
+        }
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.chars().any(|c| c.is_whitespace() || c.is_control() || "\"'<>/=`".contains(c))
+}
+
+impl fmt::Display for Attributes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in self.attributes.iter().filter(|(name, _)| is_valid_name(name)) {
+            match value {
+                Some(value) => write!(f, " {}", Raw(format_args!("{name}=\"{}\"", EscapeHtml(value))))?,
+                None => write!(f, " {}", Raw(name))?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Space separated list of classes, built by `classes!`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Classes(Vec<String>);
+
+impl Classes {
+    pub fn new() -> Classes {
+        Self::default()
+    }
+
+    pub fn push(&mut self, class: impl ClassListItem) {
+        class.push_to(self);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Display for Classes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.join(" "))
+    }
+}
+
+// Item of `classes!`: a class, a `(class, condition)` pair or an optional class.
+pub trait ClassListItem {
+    fn push_to(self, classes: &mut Classes);
+}
+
+impl ClassListItem for &str {
+    fn push_to(self, classes: &mut Classes) {
+        classes.0.extend(self.split_whitespace().map(str::to_string));
+    }
+}
+
+impl ClassListItem for String {
+    fn push_to(self, classes: &mut Classes) {
+        self.as_str().push_to(classes);
+    }
+}
+
+impl ClassListItem for &String {
+    fn push_to(self, classes: &mut Classes) {
+        self.as_str().push_to(classes);
+    }
+}
+
+impl<T: ClassListItem> ClassListItem for (T, bool) {
+    fn push_to(self, classes: &mut Classes) {
+        if self.1 {
+            self.0.push_to(classes);
+        }
+    }
+}
+
+impl<T: ClassListItem> ClassListItem for Option<T> {
+    fn push_to(self, classes: &mut Classes) {
+        if let Some(class) = self {
+            class.push_to(classes);
+        }
+    }
+}
+
+// Builds a `Classes` list from classes, `(class, condition)` pairs and optional classes:
+//
+//     plt::classes!["btn", ("btn-primary", is_primary), size_class]
+#[macro_export]
+macro_rules! classes {
+    ($($class:expr),* $(,)?) => {{
+        #[allow(unused_mut)]
+        let mut classes = $crate::attributes::Classes::new();
+        $(classes.push($class);)*
+        classes
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::attributes::Attributes;
+    use crate::prelude::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn it_renders_escaped_attributes() {
+        let attributes = Attributes::new()
+            .set("type", "submit")
+            .set("title", "\"Save\" & close")
+            .flag("disabled", true)
+            .flag("hidden", false)
+            .set_some("form", None::<&str>);
+
+        assert_eq!(escape_html(&attributes), " type=\"submit\" title=\"&quot;Save&quot; &amp; close\" disabled");
+    }
+
+    #[test]
+    fn it_spreads_maps_of_attributes() {
+        let map = BTreeMap::from([("data-id", "1"), ("onclick x", "alert(1)"), ("name", "q")]);
+        let attributes: Attributes = map.into_iter().collect();
+
+        assert_eq!(attributes.to_string(), " data-id=\"1\" name=\"q\"");
+        assert_eq!(attributes.set("name", "r").get("name"), Some(Some("r")));
+    }
+
+    #[test]
+    fn it_builds_class_lists() {
+        let size = Some("btn-lg".to_string());
+
+        assert_eq!(crate::classes!["btn  nav", ("active", true), ("hidden", false), size, None::<&str>].to_string(), "btn nav active btn-lg");
+        assert!(crate::classes![].is_empty());
+    }
+}
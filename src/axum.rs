@@ -0,0 +1,106 @@
+// Integration with axum, so handlers can return template structs directly:
+//
+//     async fn index() -> IndexTemplate<'static> {
+//         IndexTemplate { title: "Home" }
+//     }
+//
+// Structs implement `IntoResponse` when generated with `GeneratorOptions::axum`.
+use std::sync::Arc;
+use ::axum::extract::FromRequestParts;
+use ::axum::http::request::Parts;
+use ::axum::http::{header, HeaderValue, StatusCode};
+use crate::csrf::CsrfToken;
+use crate::prelude::*;
+
+pub use ::axum::response::{IntoResponse, Response};
+
+// Error of a template which failed to render, stored in the extensions of the 500 response
+// so middleware can log it.
+#[derive(Debug, Clone)]
+pub struct RenderError(pub Arc<anyhow::Error>);
+
+// Renders the template as an HTML response. Render errors become a 500 response, without
+// leaking the error to the client.
+pub fn into_response(template: &impl TemplateContext) -> Response {
+    match template.render() {
+        Ok(html) => (
+            [(header::CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"))],
+            html,
+        )
+            .into_response(),
+        Err(error) => {
+            let mut response = (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response();
+            response.extensions_mut().insert(RenderError(Arc::new(error)));
+            response
+        }
+    }
+}
+
+// Extracts the token which middleware stored in the request extensions. Requests without one
+// are rejected with a 500 response, as the middleware is missing.
+impl<S: Send + Sync> FromRequestParts<S> for CsrfToken {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<CsrfToken>()
+            .cloned()
+            .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::axum::{into_response, RenderError};
+    use crate::csrf::CsrfToken;
+    use crate::prelude::*;
+    use ::axum::extract::FromRequestParts;
+    use ::axum::http::{header, Request, StatusCode};
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    struct Page(Result<&'static str, &'static str>);
+
+    impl TemplateContext for Page {
+        fn render(&self) -> Result<String> {
+            self.0.map(str::to_string).map_err(anyhow::Error::msg)
+        }
+    }
+
+    #[test]
+    fn it_responds_with_html() {
+        let response = into_response(&Page(Ok("<p>Hi</p>")));
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html; charset=utf-8");
+    }
+
+    #[test]
+    fn it_maps_render_errors_to_internal_server_errors() {
+        let response = into_response(&Page(Err("missing user")));
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.extensions().get::<RenderError>().unwrap().0.to_string(), "missing user");
+    }
+
+    fn extract_csrf_token(request: Request<()>) -> Result<CsrfToken, StatusCode> {
+        let (mut parts, _) = request.into_parts();
+        let extraction = pin!(CsrfToken::from_request_parts(&mut parts, &()));
+
+        match extraction.poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(result) => result.map_err(|(status, _)| status),
+            Poll::Pending => panic!("extracting the token doesn't wait for anything"),
+        }
+    }
+
+    #[test]
+    fn it_extracts_csrf_tokens_from_extensions() {
+        let request = Request::builder().extension(CsrfToken("abc".to_string())).body(()).unwrap();
+        assert_eq!(extract_csrf_token(request), Ok(CsrfToken("abc".to_string())));
+
+        let request = Request::builder().body(()).unwrap();
+        assert_eq!(extract_csrf_token(request), Err(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+}
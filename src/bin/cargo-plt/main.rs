@@ -0,0 +1,137 @@
+// Cargo subcommand running the `plt` binary installed along with it, so templates can be
+// compiled as part of cargo workflows:
+//
+//     cargo plt check
+//     cargo plt compile
+//     cargo plt watch --manifest-path crates/site/Cargo.toml
+//
+// Cargo runs it as `cargo-plt plt <args>`. The `plt.toml` of the current crate, or of the
+// workspace containing it, is passed to `plt` as `--config`, so it provides the template roots
+// and the output directory unless they're given as arguments.
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use anyhow::{bail, Context};
+use plt::prelude::*;
+use plt::project::ProjectConfig;
+
+// Arguments of `cargo plt` without `--manifest-path`, which `plt` doesn't know, along with its
+// value.
+fn split_manifest_path(args: impl IntoIterator<Item = OsString>) -> Result<(Vec<OsString>, Option<PathBuf>)> {
+    let mut args = args.into_iter();
+    let mut rest = Vec::new();
+    let mut manifest_path = None;
+
+    while let Some(arg) = args.next() {
+        if arg == "--manifest-path" {
+            let Some(path) = args.next() else {
+                bail!("`--manifest-path` requires a value");
+            };
+            manifest_path = Some(PathBuf::from(path));
+        } else if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--manifest-path=")) {
+            manifest_path = Some(PathBuf::from(path));
+        } else {
+            rest.push(arg);
+        }
+    }
+
+    Ok((rest, manifest_path))
+}
+
+// Arguments `plt` is run with: the ones given to `cargo plt`, with the `plt.toml` of the crate
+// unless they choose one themselves.
+fn plt_args(args: impl IntoIterator<Item = OsString>) -> Result<Vec<OsString>> {
+    let (args, manifest_path) = split_manifest_path(args)?;
+    if args.iter().any(|arg| arg == "--config" || arg.to_string_lossy().starts_with("--config=")) {
+        return Ok(args);
+    }
+
+    let crate_dir = match &manifest_path {
+        Some(manifest_path) => manifest_path.parent().unwrap_or(Path::new("")).to_path_buf(),
+        None => std::env::current_dir()?,
+    };
+    let Some(config) = ProjectConfig::find(&crate_dir)? else {
+        return Ok(args);
+    };
+
+    let mut plt_args = vec![OsString::from("--config"), config.file.into_os_string()];
+    plt_args.extend(args);
+
+    Ok(plt_args)
+}
+
+// The `plt` binary next to this one, as both are installed together, or the one on the `PATH`.
+fn plt_binary() -> PathBuf {
+    let file_name = format!("plt{}", std::env::consts::EXE_SUFFIX);
+    let sibling = std::env::current_exe().ok().map(|exe| exe.with_file_name(&file_name));
+
+    sibling.filter(|plt| plt.is_file()).unwrap_or_else(|| PathBuf::from(file_name))
+}
+
+fn run() -> Result<ExitCode> {
+    let mut args = std::env::args_os().skip(1).peekable();
+    // Left out when the binary is run directly instead of by cargo.
+    args.next_if(|arg| arg == OsStr::new("plt"));
+
+    let plt = plt_binary();
+    let status = std::process::Command::new(&plt)
+        .args(plt_args(args)?)
+        .status()
+        .with_context(|| format!("failed to run `{}`", plt.display()))?;
+
+    Ok(match status.code() {
+        Some(code) => ExitCode::from(u8::try_from(code).unwrap_or(1)),
+        None => ExitCode::FAILURE,
+    })
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("error: {error:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+    use crate::{plt_args, split_manifest_path};
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn it_splits_the_manifest_path() {
+        let (rest, manifest_path) = split_manifest_path(args(&["check", "--manifest-path", "site/Cargo.toml", "--sandbox"])).unwrap();
+        assert_eq!(rest, args(&["check", "--sandbox"]));
+        assert_eq!(manifest_path.as_deref().and_then(|path| path.to_str()), Some("site/Cargo.toml"));
+
+        let (_, manifest_path) = split_manifest_path(args(&["compile", "--manifest-path=site/Cargo.toml"])).unwrap();
+        assert_eq!(manifest_path.as_deref().and_then(|path| path.to_str()), Some("site/Cargo.toml"));
+        assert!(split_manifest_path(args(&["check", "--manifest-path"])).is_err());
+    }
+
+    #[test]
+    fn it_passes_the_config_of_the_crate() {
+        let dir = std::env::temp_dir().join(format!("plt-cargo-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("crates/site")).unwrap();
+        std::fs::write(dir.join("plt.toml"), "out_dir = \"gen\"").unwrap();
+        let manifest_path = dir.join("crates/site/Cargo.toml").into_os_string();
+
+        let mut expected = args(&["--config"]);
+        expected.push(dir.join("plt.toml").into_os_string());
+        expected.extend(args(&["check"]));
+        assert_eq!(plt_args([OsString::from("check"), OsString::from("--manifest-path"), manifest_path.clone()]).unwrap(), expected);
+
+        let explicit = args(&["check", "--config", "other.toml"]);
+        let mut given = explicit.clone();
+        given.extend([OsString::from("--manifest-path"), manifest_path]);
+        assert_eq!(plt_args(given).unwrap(), explicit);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1280 @@
+// Command line interface for compiling templates outside of cargo build scripts, e.g. from
+// Makefiles:
+//
+//     plt compile templates -o src/generated --layout tree --escape html
+//     cat page.plt | plt compile --stdin --fn-name page
+//     plt compile --config site/plt.toml -v
+//     plt check templates
+//     plt fmt templates --check
+//     plt parse templates/index.plt --json
+//     plt tokens templates/index.plt --format lsp
+//     plt watch templates -o src/generated
+//     plt serve templates --context data.json
+//     plt render templates/index.plt --context data.json > index.html
+//     plt render --stdin --context data.json < page.plt
+//     plt extract-messages templates -o messages.pot
+//     plt doc templates -o templates.html
+//     plt graph templates --format mermaid
+//     plt migrate --from tera templates
+//     plt new my-site
+//     plt init
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use anyhow::{bail, Context};
+use clap::{Args, Parser, Subcommand};
+use plt::build::{check_dir_observed, generate_dir_observed, template_location, BuildOptions, CompileError};
+use plt::doc::{generate_docs, DocFormat};
+use plt::dynamic::{Engine, EngineOptions, Undefined};
+use plt::i18n::{extract_messages, write_catalog, CatalogFormat};
+use plt::highlight::{semantic_tokens, tokens_to_json, TokenFormat};
+use plt::lint::{Lint, LintLevel, LintOptions};
+use plt::migrate::{migrate, SourceSyntax};
+use plt::project::{ProjectConfig, CONFIG_FILE_NAME};
+use plt::scaffold::{init_files, new_files, ScaffoldFile};
+use plt::prelude::*;
+use plt::serve::ServeOptions;
+use plt::watch::Watcher;
+
+#[derive(Debug, Parser)]
+#[command(name = "plt", version, about = "Compiles .plt templates into Rust code")]
+struct Cli {
+    /// Path of the `plt.toml` whose settings `compile`, `check` and `watch` start from, defaults to the nearest one in the current directory or above it
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Only prints errors and warnings, without the progress and summaries
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Prints each compiled template along with the time it took, and the slowest ones at the end
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    // Doc comments of clap items become the help text, so they're used here instead of `//`.
+    /// Compiles all `.plt` files under a directory into formatted `.rs` files
+    Compile(CompileArgs),
+    /// Checks all `.plt` files under a directory without generating any files
+    Check(CheckArgs),
+    /// Formats `.plt` files in place
+    Fmt(FmtArgs),
+    /// Prints the parsed nodes of `.plt` files, e.g. for tools analyzing templates
+    Parse(ParseArgs),
+    /// Prints the highlighting tokens of a `.plt` file, e.g. for editor plugins
+    Tokens(TokensArgs),
+    /// Compiles templates like `compile`, then again whenever they change
+    Watch(CompileArgs),
+    /// Serves templates rendered by the dynamic engine, reloading pages when they change
+    Serve(ServeArgs),
+    /// Renders a template with the dynamic engine against a JSON context and prints the output
+    Render(RenderArgs),
+    /// Writes the translation keys used by `@t` and `@t_plural` into a message catalog
+    ExtractMessages(ExtractMessagesArgs),
+    /// Writes a catalog of the templates with their arguments, blocks, includes, examples and sources
+    Doc(DocArgs),
+    /// Prints the graph of which templates include, extend, call and use macros of which others
+    Graph(GraphArgs),
+    /// Converts the templates of another template engine under a directory into `.plt` files
+    Migrate(MigrateArgs),
+    /// Creates a crate rendering an example page, with a layout, a build script compiling the templates and a `plt.toml`
+    New(NewArgs),
+    /// Adds example templates, a build script compiling them and a `plt.toml` to an existing crate
+    Init(InitArgs),
+}
+
+#[derive(Debug, Args)]
+struct CompileArgs {
+    /// Directory containing the templates, defaults to the `template_dir` of the `plt.toml`
+    template_dir: Option<PathBuf>,
+    /// Directory the generated files are written into, defaults to the `out_dir` of the `plt.toml`
+    #[arg(short, long)]
+    out_dir: Option<PathBuf>,
+    /// Compiles the template read from stdin and prints its functions instead of compiling a directory
+    #[arg(long, conflicts_with_all = ["template_dir", "out_dir", "check"])]
+    stdin: bool,
+    /// Name of the function of the template read from stdin, defaults to `template`
+    #[arg(long, conflicts_with_all = ["template_dir", "out_dir"])]
+    fn_name: Option<String>,
+    /// Name of the generated root module and its file [default: templates]
+    #[arg(long)]
+    module_name: Option<String>,
+    /// Writes the function of each template into its own file
+    #[arg(long)]
+    split_files: bool,
+    /// Writes each generated module into its own file, to be declared with `mod <module-name>;`
+    #[arg(long)]
+    module_files: bool,
+    /// Fails instead of writing the generated files if regenerating them would change them
+    #[arg(long)]
+    check: bool,
+    /// Prints the sizes, parts and generation times of the templates
+    #[arg(long)]
+    stats: bool,
+    /// Format of the errors of templates: `human` or `json`, writing a JSON object per line
+    #[arg(long, default_value = "human")]
+    message_format: MessageFormat,
+    /// Directory the fingerprinted copies of the assets under `--asset-dir` are written into
+    #[arg(long)]
+    asset_out_dir: Option<PathBuf>,
+    /// Further template directory searched after the template directory, whose templates are included as `namespace::path` when given a namespace
+    #[arg(long, value_name = "[NAMESPACE=]DIR")]
+    search_path: Vec<TemplateRoot>,
+    #[command(flatten)]
+    generator: GeneratorArgs,
+}
+
+#[derive(Debug, Args)]
+struct CheckArgs {
+    /// Directory containing the templates, defaults to the `template_dir` of the `plt.toml`
+    template_dir: Option<PathBuf>,
+    /// Format of the errors of templates: `human` or `json`, writing a JSON object per line
+    #[arg(long, default_value = "human")]
+    message_format: MessageFormat,
+    /// Further template directory searched after the template directory, whose templates are included as `namespace::path` when given a namespace
+    #[arg(long, value_name = "[NAMESPACE=]DIR")]
+    search_path: Vec<TemplateRoot>,
+    #[command(flatten)]
+    generator: GeneratorArgs,
+}
+
+#[derive(Debug, Args)]
+struct FmtArgs {
+    /// Template files, or directories whose `.plt` files are formatted
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    /// Only reports the files which aren't formatted, without changing them
+    #[arg(long)]
+    check: bool,
+    /// Removes spaces and tabs at the end of lines in text, which changes the rendered output
+    #[arg(long)]
+    trim_trailing_whitespace: bool,
+}
+
+#[derive(Debug, Args)]
+struct ParseArgs {
+    /// Template files, or directories whose `.plt` files are parsed
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+    /// Writes the nodes of each template as a JSON object per line
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Debug, Args)]
+struct TokensArgs {
+    /// Template file
+    file: PathBuf,
+    /// Format of the tokens: `lsp` for the data of LSP semantic tokens, or `textmate` for a
+    /// JSON object per token with its TextMate scope
+    #[arg(long, default_value = "textmate")]
+    format: TokenFormat,
+}
+
+#[derive(Debug, Args)]
+struct ServeArgs {
+    /// Directory containing the templates
+    template_dir: PathBuf,
+    /// Address the server listens on
+    #[arg(long, default_value = "127.0.0.1:8000")]
+    address: String,
+    /// JSON file holding the context of all templates
+    #[arg(long)]
+    context: Option<PathBuf>,
+    /// Escaping of echoed values: `none`, `html`, `contextual`, `xml`, `shell`, `csv`, `tsv`, `latex`, `toml`, `yaml` or `ini`
+    #[arg(long, default_value = "none")]
+    escape: Escaping,
+}
+
+#[derive(Debug, Args)]
+struct RenderArgs {
+    /// Template file
+    #[arg(required_unless_present = "stdin")]
+    file: Option<PathBuf>,
+    /// Renders the template read from stdin instead of a file
+    #[arg(long, conflicts_with = "file")]
+    stdin: bool,
+    /// Directory the paths of included, extended and called templates are relative to, defaults to the directory of the template, or the current one with `--stdin`
+    #[arg(long)]
+    template_dir: Option<PathBuf>,
+    /// JSON file holding the context of the template, which has to be an object
+    #[arg(long)]
+    context: Option<PathBuf>,
+    /// Escaping of echoed values: `none`, `html`, `contextual`, `xml`, `shell`, `csv`, `tsv`, `latex`, `toml`, `yaml` or `ini`
+    #[arg(long, default_value = "none")]
+    escape: Escaping,
+    /// What variables and fields missing from the context render: `error`, `empty` or `placeholder`, e.g. `[[missing: user.name]]`
+    #[arg(long, default_value = "error")]
+    undefined: Undefined,
+    /// File the output is written into instead of printing it
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct ExtractMessagesArgs {
+    /// Directory containing the templates
+    template_dir: PathBuf,
+    /// File the catalog is written into
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Format of the catalog: `pot` or `ftl`, defaults to the extension of the output file
+    #[arg(long)]
+    format: Option<CatalogFormat>,
+}
+
+#[derive(Debug, Args)]
+struct DocArgs {
+    /// Directory containing the templates
+    template_dir: PathBuf,
+    /// File the catalog is written into
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Format of the catalog: `html` or `markdown`, defaults to the extension of the output file
+    #[arg(long)]
+    format: Option<DocFormat>,
+    /// Further template directory searched after the template directory, whose templates are included as `namespace::path` when given a namespace
+    #[arg(long, value_name = "[NAMESPACE=]DIR")]
+    search_path: Vec<TemplateRoot>,
+    /// Escaping of echoed values in the rendered examples: `none`, `html`, `contextual`, `xml`, `shell`, `csv`, `tsv`, `latex`, `toml`, `yaml` or `ini`
+    #[arg(long, default_value = "none")]
+    escape: Escaping,
+}
+
+#[derive(Debug, Args)]
+struct GraphArgs {
+    /// Directory containing the templates
+    template_dir: PathBuf,
+    /// Format of the graph: `mermaid` or `dot`, for Graphviz
+    #[arg(long, default_value = "mermaid")]
+    format: GraphFormat,
+    /// File the graph is written into instead of printing it
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Further template directory searched after the template directory, whose templates are included as `namespace::path` when given a namespace
+    #[arg(long, value_name = "[NAMESPACE=]DIR")]
+    search_path: Vec<TemplateRoot>,
+}
+
+#[derive(Debug, Args)]
+struct MigrateArgs {
+    /// Directory containing the templates
+    template_dir: PathBuf,
+    /// Template engine the templates are written for: `tera` or `erb`, which also covers classic ASP
+    #[arg(long)]
+    from: SourceSyntax,
+    /// Directory the `.plt` files are written into, defaults to the template directory
+    #[arg(short, long)]
+    out_dir: Option<PathBuf>,
+    /// Overwrites existing `.plt` files
+    #[arg(long)]
+    force: bool,
+    /// Format of the constructs to convert by hand: `human` or `json`, writing a JSON object per line
+    #[arg(long, default_value = "human")]
+    message_format: MessageFormat,
+}
+
+#[derive(Debug, Args)]
+struct NewArgs {
+    /// Directory of the crate, whose name becomes the name of the crate
+    path: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct InitArgs {
+    /// Directory of the crate
+    #[arg(default_value = ".")]
+    path: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct GeneratorArgs {
+    /// Module layout of the generated functions: `flat`, `tree` or `modules` [default: tree]
+    #[arg(long)]
+    layout: Option<ModuleLayout>,
+    /// How template names are turned into identifiers: `underscore`, `snake` or `reject` [default: underscore]
+    #[arg(long)]
+    name_mangling: Option<NameMangling>,
+    /// Prefix of the generated function names, e.g. `render_`
+    #[arg(long)]
+    fn_prefix: Option<String>,
+    /// Suffix of the generated function names, e.g. `_html`
+    #[arg(long)]
+    fn_suffix: Option<String>,
+    /// How function names are derived from template paths: `path`, e.g. `admin_user_list` for `admin/user_list.plt` with the flat layout, or `file-name`, e.g. `user_list` [default: path]
+    #[arg(long)]
+    naming: Option<NamingStrategy>,
+    /// Visibility of the generated functions, empty for private ones [default: pub]
+    #[arg(long)]
+    visibility: Option<String>,
+    /// Escaping of echoed values: `none`, `html`, `contextual`, `xml`, `shell`, `csv`, `tsv`, `latex`, `toml`, `yaml` or `ini` [default: none]
+    #[arg(long)]
+    escape: Option<Escaping>,
+    /// Also generates a struct implementing `TemplateContext` per template
+    #[arg(long)]
+    struct_mode: bool,
+    /// Checks the Rust code inside of templates while generating
+    #[arg(long)]
+    strict: bool,
+    /// Implements axum's `IntoResponse` for the structs of `--struct-mode`
+    #[arg(long)]
+    axum: bool,
+    /// Implements actix-web's `Responder` for the structs of `--struct-mode`
+    #[arg(long)]
+    actix: bool,
+    /// Generates `render_stream` for the structs of `--struct-mode`
+    #[arg(long)]
+    stream: bool,
+    /// Also generates `<function>_write` functions rendering into a tokio `AsyncWrite`
+    #[arg(long)]
+    async_write: bool,
+    /// Directory the paths of `@asset` are relative to
+    #[arg(long)]
+    asset_dir: Option<PathBuf>,
+    /// Prefix of the URLs rendered by `@asset` [default: /assets]
+    #[arg(long)]
+    asset_url: Option<String>,
+    /// Writes echoes of constant expressions as text evaluated during generation
+    #[arg(long)]
+    fold_constants: bool,
+    /// Lints which aren't reported, e.g. `unused_parameters`
+    #[arg(long, value_name = "LINT")]
+    allow: Vec<Lint>,
+    /// Lints reported as warnings
+    #[arg(long, value_name = "LINT")]
+    warn: Vec<Lint>,
+    /// Lints failing the templates they're found in
+    #[arg(long, value_name = "LINT")]
+    deny: Vec<Lint>,
+    /// Length in bytes from which text parts are reported by the `long_text` lint [default: 16384]
+    #[arg(long)]
+    max_text_len: Option<usize>,
+    /// Rejects templates using `unsafe` code, raw pointers, `std::fs`, `std::process` or `include!`
+    #[arg(long)]
+    sandbox: bool,
+    /// Path which sandboxed templates can't use, along with everything below it, e.g. `crate::db`
+    #[arg(long, value_name = "PATH", requires = "sandbox")]
+    sandbox_deny_path: Vec<String>,
+    /// Macro which sandboxed templates can't call, e.g. `dbg`
+    #[arg(long, value_name = "NAME", requires = "sandbox")]
+    sandbox_deny_macro: Vec<String>,
+    /// Formatter of the generated files: `prettyplease`, `rustfmt`, which honors the project's `rustfmt.toml`, or `compact`, which skips formatting [default: prettyplease]
+    #[arg(long)]
+    formatter: Option<CodeFormatter>,
+    /// Puts `#[rustfmt::skip]` on the generated items and modules
+    #[arg(long)]
+    rustfmt_skip: bool,
+    /// Minification of the text of templates: `off`, `whitespace`, which removes whitespace between tags and HTML comments, or `full`, which also collapses the rest of the whitespace [default: off]
+    #[arg(long)]
+    minify: Option<Minify>,
+    /// Removes the lines of `<?rs ?>` tags standing on their own line, i.e. their indentation and the line break after them
+    #[arg(long)]
+    trim_tag_newlines: bool,
+    /// Line breaks at the end of templates: `keep`, `strip` or `single`, which renders exactly one [default: keep]
+    #[arg(long)]
+    trailing_newline: Option<TrailingNewline>,
+    /// Stores text at least this long compressed in the binary, decompressed on first render using plt's `compress` feature
+    #[arg(long, value_name = "MIN_LEN")]
+    compress_text: Option<usize>,
+    /// Maximum number of nested templates rendering themselves, e.g. to render trees, checked when rendering [default: 64]
+    #[arg(long)]
+    max_depth: Option<usize>,
+    /// Generates `render_by_name`, rendering templates by their path with arguments taken from a JSON object, which needs plt's `json` feature
+    #[arg(long)]
+    registry: bool,
+    /// Generates a `<NAME>_META` const describing each template, along with `ALL_TEMPLATES` listing all of them
+    #[arg(long)]
+    metadata: bool,
+    /// Placeholders of the parameters bound by `.sql.plt` templates: `dollar`, e.g. `$1` for PostgreSQL, or `question`, i.e. `?` for SQLite and MySQL [default: dollar]
+    #[arg(long)]
+    sql_placeholder: Option<plt::sql::Placeholder>,
+    /// Delimiter of the fields of `.csv.plt` templates, e.g. `;` for spreadsheets in locales using decimal commas [default: ,]
+    #[arg(long)]
+    csv_delimiter: Option<char>,
+    /// Generates functions for `no_std` crates using `alloc`, rendering with plt built without its `std` feature
+    #[arg(long)]
+    no_std: bool,
+}
+
+impl GeneratorArgs {
+    // `base`, e.g. the options of the `plt.toml`, with the options given as arguments. Flags can
+    // only turn options on.
+    fn options(&self, base: &GeneratorOptions) -> GeneratorOptions {
+        let base = base.clone();
+        let or = |flag: bool, base: bool| flag || base;
+
+        GeneratorOptions {
+            module_layout: self.layout.unwrap_or(base.module_layout),
+            name_mangling: self.name_mangling.unwrap_or(base.name_mangling),
+            visibility: self.visibility.clone().unwrap_or(base.visibility),
+            struct_mode: or(self.struct_mode, base.struct_mode),
+            function_prefix: self.fn_prefix.clone().unwrap_or(base.function_prefix),
+            function_suffix: self.fn_suffix.clone().unwrap_or(base.function_suffix),
+            naming: self.naming.clone().unwrap_or(base.naming),
+            escaping: self.escape.unwrap_or(base.escaping),
+            strict: or(self.strict, base.strict),
+            axum: or(self.axum, base.axum),
+            actix: or(self.actix, base.actix),
+            stream: or(self.stream, base.stream),
+            async_write: or(self.async_write, base.async_write),
+            asset_dir: self.asset_dir.clone().or(base.asset_dir),
+            asset_url: self.asset_url.clone().unwrap_or(base.asset_url),
+            fold_constants: or(self.fold_constants, base.fold_constants),
+            lints: self.lints(base.lints),
+            sandbox: match self.sandbox {
+                true => {
+                    let mut sandbox = base.sandbox.unwrap_or_default();
+                    sandbox.denied_paths.extend(self.sandbox_deny_path.iter().cloned());
+                    sandbox.denied_macros.extend(self.sandbox_deny_macro.iter().cloned());
+                    Some(sandbox)
+                }
+                false => base.sandbox,
+            },
+            formatter: self.formatter.unwrap_or(base.formatter),
+            rustfmt_skip: or(self.rustfmt_skip, base.rustfmt_skip),
+            minify: self.minify.unwrap_or(base.minify),
+            trim_tag_newlines: or(self.trim_tag_newlines, base.trim_tag_newlines),
+            trailing_newline: self.trailing_newline.unwrap_or(base.trailing_newline),
+            compress_text: self.compress_text.or(base.compress_text),
+            max_depth: self.max_depth.unwrap_or(base.max_depth),
+            registry: or(self.registry, base.registry),
+            metadata: or(self.metadata, base.metadata),
+            sql_placeholder: self.sql_placeholder.unwrap_or(base.sql_placeholder),
+            csv_delimiter: self.csv_delimiter.unwrap_or(base.csv_delimiter),
+            no_std: or(self.no_std, base.no_std),
+        }
+    }
+
+    fn lints(&self, mut lints: LintOptions) -> LintOptions {
+        if let Some(max_text_len) = self.max_text_len {
+            lints.max_text_len = max_text_len;
+        }
+        for (names, level) in [(&self.allow, LintLevel::Allow), (&self.warn, LintLevel::Warn), (&self.deny, LintLevel::Deny)] {
+            for lint in names {
+                lints.set(*lint, level);
+            }
+        }
+
+        lints
+    }
+}
+
+impl CompileArgs {
+    // Template and output directories, given as arguments or taken from the `plt.toml`.
+    fn dirs(&self, config: Option<&ProjectConfig>) -> Result<(PathBuf, PathBuf)> {
+        let template_dir = self.template_dir.clone().or_else(|| Some(config?.template_dir.clone()));
+        let out_dir = self.out_dir.clone().or_else(|| config?.out_dir.clone());
+
+        match (template_dir, out_dir) {
+            (Some(template_dir), Some(out_dir)) => Ok((template_dir, out_dir)),
+            (None, _) => bail!("the template directory is required without a `{CONFIG_FILE_NAME}` or `--stdin`"),
+            (Some(_), None) => bail!("`--out-dir` is required unless the `{CONFIG_FILE_NAME}` sets `out_dir`"),
+        }
+    }
+
+    // Options of the `plt.toml`, if there is one, with the options given as arguments.
+    fn options(&self, config: Option<&ProjectConfig>) -> BuildOptions {
+        let base = config.map(|config| config.build.clone()).unwrap_or_default();
+        let mut search_path = base.search_path;
+        search_path.extend(self.search_path.iter().cloned());
+
+        BuildOptions {
+            generator: self.generator.options(&base.generator),
+            module_name: self.module_name.clone().unwrap_or(base.module_name),
+            split_files: self.split_files || base.split_files,
+            module_files: self.module_files || base.module_files,
+            asset_out_dir: self.asset_out_dir.clone().or(base.asset_out_dir),
+            search_path,
+        }
+    }
+}
+
+// Config of the `plt.toml` given with `--config`, or of the nearest one, if there is one.
+fn project_config(file: Option<&Path>) -> Result<Option<ProjectConfig>> {
+    match file {
+        Some(file) => ProjectConfig::load(file).map(Some),
+        None => ProjectConfig::find(&std::env::current_dir()?),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+impl Cli {
+    fn verbosity(&self) -> Verbosity {
+        match (self.quiet, self.verbose) {
+            (true, _) => Verbosity::Quiet,
+            (false, true) => Verbosity::Verbose,
+            (false, false) => Verbosity::Normal,
+        }
+    }
+}
+
+// Progress of compiling a directory on stderr: a progress bar on terminals, or each template with
+// the time it took when verbose.
+struct Progress {
+    verbosity: Verbosity,
+    bar: bool,
+    templates: AtomicUsize,
+    generated: AtomicUsize,
+    // Keeps the lines of templates generated on different threads apart.
+    output: Mutex<()>,
+}
+
+impl Progress {
+    const BAR_WIDTH: usize = 30;
+
+    fn new(verbosity: Verbosity) -> Progress {
+        Progress {
+            verbosity,
+            bar: verbosity == Verbosity::Normal && std::io::stderr().is_terminal(),
+            templates: AtomicUsize::new(0),
+            generated: AtomicUsize::new(0),
+            output: Mutex::new(()),
+        }
+    }
+
+    fn templates(&self) -> usize {
+        self.templates.load(Ordering::Relaxed)
+    }
+}
+
+impl GenerationObserver for Progress {
+    fn started(&self, templates: usize) {
+        self.templates.store(templates, Ordering::Relaxed);
+    }
+
+    fn template_generated(&self, path: &str, stats: Option<&TemplateStats>) {
+        let generated = self.generated.fetch_add(1, Ordering::Relaxed) + 1;
+        let _output = self.output.lock().unwrap();
+
+        if self.verbosity == Verbosity::Verbose {
+            let time = stats.map_or("failed".to_string(), |stats| format!("{:.2?}", stats.total_time()));
+            eprintln!("{time:>10} {path}");
+        }
+        if self.bar {
+            // Clamped, as more templates than announced by `started` may be reported.
+            let filled = (generated * Self::BAR_WIDTH / self.templates().max(1)).min(Self::BAR_WIDTH);
+            let bar = format!("{}{}", "=".repeat(filled), " ".repeat(Self::BAR_WIDTH - filled));
+            eprint!("\r\x1b[2K[{bar}] {generated}/{} {path}", self.templates());
+        }
+    }
+
+    fn finished(&self, report: &GenerationReport) {
+        if self.bar {
+            eprint!("\r\x1b[2K");
+        }
+        if self.verbosity == Verbosity::Verbose && !report.templates.is_empty() {
+            let slowest: Vec<_> = report.slowest(3).iter().map(|stats| format!("{} ({:.2?})", stats.path, stats.total_time())).collect();
+            eprintln!("slowest: {}", slowest.join(", "));
+        }
+    }
+}
+
+// Path of the template read from stdin by `plt render --stdin`.
+const STDIN_PATH: &str = "<stdin>";
+
+fn read_stdin() -> Result<String> {
+    let mut source = String::new();
+    std::io::stdin().read_to_string(&mut source).context("failed to read stdin")?;
+
+    Ok(source.replace("\r\n", "\n"))
+}
+
+fn compile(args: &CompileArgs, config: Option<&ProjectConfig>, verbosity: Verbosity) -> Result<()> {
+    if args.stdin {
+        return compile_stdin(args, config);
+    }
+
+    let (template_dir, out_dir) = args.dirs(config)?;
+    let options = args.options(config);
+    let progress = Progress::new(verbosity);
+    let started = Instant::now();
+    let result = if args.check {
+        check_dir_observed(&template_dir, &out_dir, &options, &progress).map(|()| {
+            if verbosity != Verbosity::Quiet {
+                println!("checked {} templates, the generated files are up to date", progress.templates());
+            }
+        })
+    } else {
+        generate_dir_observed(&template_dir, &out_dir, &options, &progress).map(|report| {
+            print_diagnostics(&report.warnings, args.message_format);
+            if args.stats {
+                println!("{report}");
+            }
+            if verbosity != Verbosity::Quiet {
+                println!("compiled {} templates in {:.2?}", report.templates.len(), started.elapsed());
+            }
+        })
+    };
+
+    let Err(error) = result else {
+        return Ok(());
+    };
+    let Some(compile_error) = error.downcast_ref::<CompileError>() else {
+        return Err(error);
+    };
+
+    print_diagnostics(&compile_error.diagnostics, args.message_format);
+    bail!("{} templates failed to compile", failed_templates(&compile_error.diagnostics));
+}
+
+// Prints the functions of the template read from stdin, named after `--fn-name` as the template
+// `<fn-name>.plt` of the flat layout. Diagnostics go to stderr in both formats, as the code goes
+// to stdout.
+fn compile_stdin(args: &CompileArgs, config: Option<&ProjectConfig>) -> Result<()> {
+    let source = read_stdin()?;
+    let path = format!("{}.plt", args.fn_name.as_deref().unwrap_or("template"));
+    let options = GeneratorOptions { module_layout: ModuleLayout::Flat, naming: NamingStrategy::Path, ..args.options(config).generator };
+
+    let mut set = TemplateSet::new();
+    set.add_template(&path, Vec::new(), source.clone());
+    let diagnostics = set.diagnostics(&options);
+    let color = std::io::stderr().is_terminal();
+    for diagnostic in diagnostics.iter() {
+        match args.message_format {
+            MessageFormat::Json => eprintln!("{}", diagnostic.to_json()),
+            MessageFormat::Human => eprintln!("{}", diagnostic.render(Some(&source), color)),
+        }
+    }
+    if diagnostics.has_errors() {
+        bail!("the template failed to compile");
+    }
+
+    for function in set.generate_all(&options)?.functions {
+        println!("{}", function.code);
+    }
+
+    Ok(())
+}
+
+// Number of templates with errors, which can have more than one.
+fn failed_templates(diagnostics: &Diagnostics) -> usize {
+    let files: BTreeSet<_> = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .map(|diagnostic| &diagnostic.file)
+        .collect();
+
+    files.len()
+}
+
+// Writes JSON diagnostics to stdout, or renders them along with the excerpts of the templates
+// they point at to stderr.
+fn print_diagnostics(diagnostics: &Diagnostics, format: MessageFormat) {
+    let color = std::io::stderr().is_terminal();
+
+    for diagnostic in diagnostics.iter() {
+        match format {
+            MessageFormat::Json => println!("{}", diagnostic.to_json()),
+            MessageFormat::Human => {
+                let source = std::fs::read_to_string(&diagnostic.file).ok();
+                eprintln!("{}", diagnostic.render(source.as_deref(), color));
+            }
+        }
+    }
+}
+
+fn check(args: &CheckArgs, config: Option<&ProjectConfig>, verbosity: Verbosity) -> Result<()> {
+    let Some(template_dir) = args.template_dir.clone().or_else(|| Some(config?.template_dir.clone())) else {
+        bail!("the template directory is required without a `{CONFIG_FILE_NAME}`");
+    };
+    let base = config.map(|config| config.build.clone()).unwrap_or_default();
+    let mut roots = vec![TemplateRoot::new(&template_dir)];
+    roots.extend(base.search_path);
+    roots.extend(args.search_path.iter().cloned());
+    let set = TemplateSet::load_roots(&roots)?;
+
+    let diagnostics: Diagnostics = set
+        .diagnostics(&args.generator.options(&base.generator))
+        .iter()
+        .map(|diagnostic| Diagnostic { file: template_location(&set, &template_dir, &diagnostic.file), ..diagnostic.clone() })
+        .collect();
+    print_diagnostics(&diagnostics, args.message_format);
+
+    let template_count = set.paths().count();
+    if diagnostics.has_errors() {
+        bail!("{} of {template_count} templates failed the check", failed_templates(&diagnostics));
+    }
+
+    match diagnostics.len() {
+        _ if verbosity == Verbosity::Quiet => {}
+        0 => println!("checked {template_count} templates, no errors found"),
+        warnings => println!("checked {template_count} templates, no errors found, {warnings} warnings"),
+    }
+
+    Ok(())
+}
+
+// Template files given as arguments, with the `.plt` files of directories.
+fn files_of(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            files.extend(template_files(path)?);
+        } else {
+            files.push(path.clone());
+        }
+    }
+
+    Ok(files)
+}
+
+fn fmt(args: &FmtArgs) -> Result<()> {
+    let options = FormatOptions { trim_trailing_whitespace: args.trim_trailing_whitespace };
+
+    let files = files_of(&args.paths)?;
+    let mut unformatted = 0;
+    for file in &files {
+        let source = std::fs::read_to_string(file).with_context(|| format!("failed to read `{}`", file.display()))?;
+        let formatted = format_template(&source, &options).with_context(|| format!("failed to format `{}`", file.display()))?;
+
+        if formatted == source {
+            continue;
+        }
+        unformatted += 1;
+
+        if args.check {
+            println!("{} is not formatted", file.display());
+        } else {
+            std::fs::write(file, formatted).with_context(|| format!("failed to write `{}`", file.display()))?;
+            println!("formatted {}", file.display());
+        }
+    }
+
+    if args.check && unformatted > 0 {
+        bail!("{unformatted} of {} templates are not formatted", files.len());
+    }
+
+    Ok(())
+}
+
+fn parse(args: &ParseArgs) -> Result<()> {
+    for file in files_of(&args.paths)? {
+        let source = std::fs::read_to_string(&file).with_context(|| format!("failed to read `{}`", file.display()))?;
+        let path = file.display().to_string();
+        let mut set = TemplateSet::new();
+        set.add_template(&path, Vec::new(), source);
+
+        // Sections are printed after the file, as templates of their own.
+        let file = set.get(&path).expect("the template was just added");
+        for template in std::iter::once(file).chain(set.sections(&path)) {
+            let parsed = ParsedTemplate::parse(template)?;
+            if args.json {
+                println!("{}", parsed.to_json());
+                continue;
+            }
+
+            println!("{}", template.path);
+            for (node, span) in parsed.nodes.iter().zip(&parsed.spans) {
+                let node = match node {
+                    Node::Text(text) => format!("text {text:?}"),
+                    Node::Code(code) => format!("code {:?}", code.trim()),
+                    Node::Echo(code) => format!("echo {:?}", code.trim()),
+                    Node::Directive(directive) => format!("directive @{}", directive.name()),
+                };
+                println!("  {}:{} {node}", span.line, span.column);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn tokens(args: &TokensArgs) -> Result<()> {
+    let source = std::fs::read_to_string(&args.file).with_context(|| format!("failed to read `{}`", args.file.display()))?;
+    let path = args.file.display().to_string();
+    let mut set = TemplateSet::new();
+    set.add_template(&path, Vec::new(), source.clone());
+
+    let mut tokens = semantic_tokens(set.get(&path).expect("the template was just added"));
+    for section in set.sections(&path) {
+        tokens.extend(semantic_tokens(section));
+    }
+    tokens.sort_by_key(|token| token.span.start);
+    println!("{}", tokens_to_json(&tokens, &source, args.format));
+
+    Ok(())
+}
+
+fn watch(args: &CompileArgs, config: Option<&ProjectConfig>, verbosity: Verbosity) -> Result<()> {
+    if args.stdin {
+        bail!("templates read from stdin can't be watched");
+    }
+
+    let (template_dir, out_dir) = args.dirs(config)?;
+    let mut watcher = Watcher::with_options(&template_dir, &out_dir, args.options(config))?;
+    for error in watcher.errors() {
+        eprintln!("error: {error}");
+    }
+    if verbosity != Verbosity::Quiet {
+        println!("watching {}", template_dir.display());
+    }
+
+    watcher.watch(|summary| match summary {
+        Ok(_) if verbosity == Verbosity::Quiet => {}
+        Ok(summary) => println!("{summary}"),
+        Err(error) => eprintln!("error: {error:#}"),
+    })
+}
+
+fn serve(args: &ServeArgs) -> Result<()> {
+    let options = ServeOptions {
+        address: args.address.clone(),
+        context_file: args.context.clone(),
+        escaping: args.escape,
+    };
+    println!("serving {} on http://{}", args.template_dir.display(), options.address);
+
+    plt::serve::serve(&args.template_dir, &options)
+}
+
+// Loads the template read from stdin under `STDIN_PATH`, and the others from the template
+// directory.
+struct StdinLoader {
+    source: String,
+    files: FileSystemLoader,
+}
+
+impl TemplateLoader for StdinLoader {
+    fn load(&self, name: &str) -> Result<Cow<'_, str>> {
+        match name {
+            STDIN_PATH => Ok(Cow::Borrowed(&self.source)),
+            _ => self.files.load(name),
+        }
+    }
+}
+
+fn render(args: &RenderArgs) -> Result<()> {
+    let (loader, path): (Box<dyn TemplateLoader>, String) = match &args.file {
+        Some(file) => {
+            let template_dir = match &args.template_dir {
+                Some(template_dir) => template_dir.as_path(),
+                None => file.parent().unwrap_or(Path::new("")),
+            };
+            let path = file
+                .strip_prefix(template_dir)
+                .with_context(|| format!("`{}` is not under `{}`", file.display(), template_dir.display()))?
+                .to_string_lossy()
+                .replace('\\', "/");
+
+            (Box::new(FileSystemLoader::new(template_dir)), path)
+        }
+        None => {
+            let files = FileSystemLoader::new(args.template_dir.clone().unwrap_or_default());
+            (Box::new(StdinLoader { source: read_stdin()?, files }), STDIN_PATH.to_string())
+        }
+    };
+
+    let context = match &args.context {
+        Some(file) => {
+            let context = std::fs::read_to_string(file).with_context(|| format!("failed to read `{}`", file.display()))?;
+            serde_json::from_str(&context).with_context(|| format!("failed to parse `{}`", file.display()))?
+        }
+        None => serde_json::Value::Object(serde_json::Map::new()),
+    };
+
+    // The templates of the project itself, which render the same build constants as when compiled.
+    let options = EngineOptions { escaping: args.escape, undefined: args.undefined, build_constants: true, ..EngineOptions::default() };
+    let engine = Engine::from_loader_with(loader.as_ref(), &[&path], options)?;
+    let output = engine.render(&path, &context)?;
+
+    match &args.output {
+        Some(file) => std::fs::write(file, output).with_context(|| format!("failed to write `{}`", file.display()))?,
+        None => print!("{output}"),
+    }
+
+    Ok(())
+}
+
+fn extract(args: &ExtractMessagesArgs) -> Result<()> {
+    let format = match args.format {
+        Some(format) => format,
+        None if args.output.extension().is_some_and(|extension| extension == "ftl") => CatalogFormat::Fluent,
+        None => CatalogFormat::Pot,
+    };
+
+    let set = TemplateSet::load_dir(&args.template_dir)?;
+    let messages = extract_messages(&set)?;
+    std::fs::write(&args.output, write_catalog(&messages, format)).with_context(|| format!("failed to write `{}`", args.output.display()))?;
+
+    println!("extracted {} messages into {}", messages.len(), args.output.display());
+
+    Ok(())
+}
+
+fn doc(args: &DocArgs) -> Result<()> {
+    let format = match args.format {
+        Some(format) => format,
+        None if args.output.extension().is_some_and(|extension| extension == "md") => DocFormat::Markdown,
+        None => DocFormat::Html,
+    };
+
+    let mut roots = vec![TemplateRoot::new(&args.template_dir)];
+    roots.extend(args.search_path.iter().cloned());
+    let options = EngineOptions { escaping: args.escape, build_constants: true, ..EngineOptions::default() };
+    let engine = Engine::from_template_set(TemplateSet::load_roots(&roots)?, options);
+    std::fs::write(&args.output, generate_docs(&engine, format)?).with_context(|| format!("failed to write `{}`", args.output.display()))?;
+
+    println!("documented {} templates in {}", engine.template_set().paths().count(), args.output.display());
+
+    Ok(())
+}
+
+fn graph(args: &GraphArgs) -> Result<()> {
+    let mut roots = vec![TemplateRoot::new(&args.template_dir)];
+    roots.extend(args.search_path.iter().cloned());
+    let graph = TemplateSet::load_roots(&roots)?.dependency_graph()?.write(args.format);
+
+    match &args.output {
+        Some(output) => std::fs::write(output, graph).with_context(|| format!("failed to write `{}`", output.display()))?,
+        None => print!("{graph}"),
+    }
+
+    Ok(())
+}
+
+fn migrate_dir(args: &MigrateArgs) -> Result<()> {
+    let out_dir = args.out_dir.as_ref().unwrap_or(&args.template_dir);
+    let files = files_with_extensions(&args.template_dir, args.from.extensions())?;
+
+    let mut diagnostics = Diagnostics::new();
+    let mut migrated = Vec::new();
+    for file in &files {
+        let source = std::fs::read_to_string(file).with_context(|| format!("failed to read `{}`", file.display()))?;
+        let migration = migrate(args.from, &file.display().to_string(), &source);
+
+        let relative = file.strip_prefix(&args.template_dir).unwrap_or(file);
+        let out_file = out_dir.join(args.from.migrated_path(relative));
+        if out_file.exists() && !args.force {
+            bail!("`{}` already exists, pass `--force` to overwrite it", out_file.display());
+        }
+
+        diagnostics.extend(migration.diagnostics);
+        migrated.push((out_file, migration.source));
+    }
+
+    for (out_file, source) in &migrated {
+        if let Some(parent) = out_file.parent() {
+            std::fs::create_dir_all(parent).with_context(|| format!("failed to create `{}`", parent.display()))?;
+        }
+        std::fs::write(out_file, source).with_context(|| format!("failed to write `{}`", out_file.display()))?;
+    }
+    print_diagnostics(&diagnostics, args.message_format);
+
+    match diagnostics.len() {
+        0 => println!("migrated {} templates", files.len()),
+        todos => println!("migrated {} templates, {todos} constructs need to be converted by hand", files.len()),
+    }
+
+    Ok(())
+}
+
+// Writes the files of a scaffold under `dir`, keeping the ones which already exist. Returns the
+// paths of the kept files.
+fn write_scaffold(dir: &Path, files: &[ScaffoldFile]) -> Result<Vec<PathBuf>> {
+    let mut kept = Vec::new();
+    for file in files {
+        let path = dir.join(&file.path);
+        if path.exists() {
+            println!("kept {}, which already exists", path.display());
+            kept.push(file.path.clone());
+            continue;
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).with_context(|| format!("failed to create `{}`", parent.display()))?;
+        }
+        std::fs::write(&path, &file.contents).with_context(|| format!("failed to write `{}`", path.display()))?;
+        println!("created {}", path.display());
+    }
+
+    Ok(kept)
+}
+
+fn new(args: &NewArgs) -> Result<()> {
+    if args.path.read_dir().is_ok_and(|mut entries| entries.next().is_some()) {
+        bail!("`{}` already exists and is not empty, use `plt init` to add templates to an existing crate", args.path.display());
+    }
+    let Some(name) = args.path.file_name().and_then(|name| name.to_str()) else {
+        bail!("`{}` has no name to name the crate after", args.path.display());
+    };
+
+    write_scaffold(&args.path, &new_files(name))?;
+    println!("\nrender the example page with `cd {} && cargo run`", args.path.display());
+
+    Ok(())
+}
+
+fn init(args: &InitArgs) -> Result<()> {
+    let kept = write_scaffold(&args.path, &init_files())?;
+
+    if kept.iter().any(|path| path == Path::new("build.rs")) {
+        println!("\ncompile the templates from the existing build.rs, e.g. like this:\n\n{}", plt::scaffold::BUILD_SCRIPT);
+    }
+    let version = env!("CARGO_PKG_VERSION");
+    println!("\nadd `plt = \"{version}\"` to the [dependencies] of Cargo.toml and `plt = {{ version = \"{version}\", features = [\"project\"] }}` to its [build-dependencies]");
+    println!("and include the generated functions with `include!(concat!(env!(\"OUT_DIR\"), \"/templates.rs\"));`");
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let result = match &cli.command {
+        Command::Compile(args) => project_config(cli.config.as_deref()).and_then(|config| compile(args, config.as_ref(), cli.verbosity())),
+        Command::Check(args) => project_config(cli.config.as_deref()).and_then(|config| check(args, config.as_ref(), cli.verbosity())),
+        Command::Fmt(args) => fmt(args),
+        Command::Parse(args) => parse(args),
+        Command::Tokens(args) => tokens(args),
+        Command::Watch(args) => project_config(cli.config.as_deref()).and_then(|config| watch(args, config.as_ref(), cli.verbosity())),
+        Command::Serve(args) => serve(args),
+        Command::Render(args) => render(args),
+        Command::ExtractMessages(args) => extract(args),
+        Command::Doc(args) => doc(args),
+        Command::Graph(args) => graph(args),
+        Command::Migrate(args) => migrate_dir(args),
+        Command::New(args) => new(args),
+        Command::Init(args) => init(args),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {error:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cli, Command, Verbosity};
+    use clap::Parser;
+    use plt::prelude::*;
+
+    #[test]
+    fn it_parses_compile_arguments() {
+        let cli = Cli::try_parse_from([
+            "plt", "compile", "templates", "-o", "out", "--layout", "flat", "--fn-prefix", "render_", "--escape", "html", "--strict",
+            "--name-mangling", "snake", "--module-files", "--formatter", "rustfmt", "--minify", "whitespace", "--compress-text", "4096",
+            "--trim-tag-newlines", "--trailing-newline", "single", "--fn-suffix", "_html", "--naming", "file-name", "--max-depth", "16",
+            "--search-path", "emails=shared/emails", "--registry", "--metadata",
+            "--sql-placeholder", "question", "--csv-delimiter", ";", "--no-std",
+        ])
+        .unwrap();
+
+        let Command::Compile(args) = cli.command else {
+            panic!("expected the compile command");
+        };
+        let options = args.options(None).generator;
+
+        assert_eq!(args.out_dir.as_deref().and_then(|out_dir| out_dir.to_str()), Some("out"));
+        assert_eq!(args.options(None).search_path, vec![TemplateRoot::namespaced("emails", "shared/emails")]);
+        assert_eq!(options.module_layout, ModuleLayout::Flat);
+        assert_eq!(options.name_mangling, NameMangling::Snake);
+        assert!(args.module_files);
+        assert_eq!(options.function_prefix, "render_");
+        assert_eq!(options.function_suffix, "_html");
+        assert!(matches!(options.naming, NamingStrategy::FileName));
+        assert_eq!(options.escaping, Escaping::Html);
+        assert!(options.strict);
+        assert_eq!(options.formatter, CodeFormatter::Rustfmt);
+        assert_eq!(options.minify, Minify::Whitespace);
+        assert_eq!(options.compress_text, Some(4096));
+        assert!(options.trim_tag_newlines);
+        assert_eq!(options.trailing_newline, TrailingNewline::Single);
+        assert_eq!(options.max_depth, 16);
+        assert!(options.registry);
+        assert!(options.metadata);
+        assert_eq!(options.sql_placeholder, plt::sql::Placeholder::Question);
+        assert_eq!(options.csv_delimiter, ';');
+        assert!(options.no_std);
+    }
+
+    #[test]
+    fn it_overrides_the_project_config_with_arguments() {
+        let source = "template_dir = \"views\"\nout_dir = \"gen\"\nescape = \"html\"\nfn_prefix = \"render_\"\nsearch_path = [\"shared\"]\n";
+        let config = plt::project::ProjectConfig::parse(source, std::path::Path::new("site/plt.toml")).unwrap();
+        let cli = Cli::try_parse_from(["plt", "compile", "--fn-prefix", "", "--strict", "--search-path", "vendor"]).unwrap();
+
+        let Command::Compile(args) = cli.command else {
+            panic!("expected the compile command");
+        };
+        let (template_dir, out_dir) = args.dirs(Some(&config)).unwrap();
+        let options = args.options(Some(&config));
+
+        assert_eq!((template_dir.to_str(), out_dir.to_str()), (Some("site/views"), Some("site/gen")));
+        assert_eq!(options.generator.escaping, Escaping::Html);
+        assert_eq!(options.generator.function_prefix, "");
+        assert!(options.generator.strict);
+        assert_eq!(options.search_path, vec![TemplateRoot::new("site/shared"), TemplateRoot::new("vendor")]);
+    }
+
+    #[test]
+    fn it_parses_verbosity_levels() {
+        let verbosity = |args: &[&str]| Cli::try_parse_from(args).map(|cli| cli.verbosity());
+
+        assert_eq!(verbosity(&["plt", "compile", "templates", "-o", "out"]).unwrap(), Verbosity::Normal);
+        assert_eq!(verbosity(&["plt", "-q", "check", "templates"]).unwrap(), Verbosity::Quiet);
+        assert_eq!(verbosity(&["plt", "compile", "templates", "-o", "out", "--verbose"]).unwrap(), Verbosity::Verbose);
+        assert!(verbosity(&["plt", "compile", "-q", "-v"]).is_err());
+    }
+
+    #[test]
+    fn it_parses_message_formats() {
+        let cli = Cli::try_parse_from(["plt", "check", "templates", "--message-format", "json"]).unwrap();
+
+        let Command::Check(args) = cli.command else {
+            panic!("expected the check command");
+        };
+        assert_eq!(args.message_format, MessageFormat::Json);
+        assert!(Cli::try_parse_from(["plt", "check", "templates", "--message-format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn it_parses_parse_arguments() {
+        let cli = Cli::try_parse_from(["plt", "parse", "templates/index.plt", "--json"]).unwrap();
+
+        let Command::Parse(args) = cli.command else {
+            panic!("expected the parse command");
+        };
+        assert_eq!(args.paths, vec![std::path::PathBuf::from("templates/index.plt")]);
+        assert!(args.json);
+        assert!(Cli::try_parse_from(["plt", "parse"]).is_err());
+    }
+
+    #[test]
+    fn it_parses_migrate_arguments() {
+        let cli = Cli::try_parse_from(["plt", "migrate", "templates", "--from", "tera", "-o", "out"]).unwrap();
+
+        let Command::Migrate(args) = cli.command else {
+            panic!("expected the migrate command");
+        };
+        assert_eq!(args.from, plt::migrate::SourceSyntax::Tera);
+        assert_eq!(args.out_dir, Some(std::path::PathBuf::from("out")));
+        assert!(Cli::try_parse_from(["plt", "migrate", "templates", "--from", "handlebars"]).is_err());
+    }
+
+    #[test]
+    fn it_parses_sandbox_arguments() {
+        let cli = Cli::try_parse_from(["plt", "check", "templates", "--sandbox", "--sandbox-deny-path", "crate::db"]).unwrap();
+
+        let Command::Check(args) = cli.command else {
+            panic!("expected the check command");
+        };
+        let sandbox = args.generator.options(&GeneratorOptions::default()).sandbox.unwrap();
+        assert!(sandbox.denied_paths.contains(&"std::fs".to_string()));
+        assert!(sandbox.denied_paths.contains(&"crate::db".to_string()));
+        assert!(Cli::try_parse_from(["plt", "check", "templates", "--sandbox-deny-macro", "dbg"]).is_err());
+    }
+
+    #[test]
+    fn it_rejects_unknown_layouts() {
+        assert!(Cli::try_parse_from(["plt", "compile", "templates", "-o", "out", "--layout", "nested"]).is_err());
+    }
+
+    #[test]
+    fn it_parses_render_arguments() {
+        let cli = Cli::try_parse_from(["plt", "render", "templates/index.plt", "--context", "data.json", "--escape", "html", "--undefined", "placeholder"]).unwrap();
+
+        let Command::Render(args) = cli.command else {
+            panic!("expected the render command");
+        };
+
+        assert_eq!(args.file.as_deref().and_then(|file| file.to_str()), Some("templates/index.plt"));
+        assert_eq!(args.context.as_deref().and_then(|context| context.to_str()), Some("data.json"));
+        assert_eq!(args.escape, Escaping::Html);
+        assert_eq!(args.undefined, plt::dynamic::Undefined::Placeholder);
+        assert!(Cli::try_parse_from(["plt", "render", "templates/index.plt", "--undefined", "strict"]).is_err());
+    }
+
+    #[test]
+    fn it_parses_stdin_arguments() {
+        let cli = Cli::try_parse_from(["plt", "compile", "--stdin", "--fn-name", "page"]).unwrap();
+
+        let Command::Compile(args) = cli.command else {
+            panic!("expected the compile command");
+        };
+        assert!(args.stdin);
+        assert_eq!(args.fn_name.as_deref(), Some("page"));
+
+        let cli = Cli::try_parse_from(["plt", "render", "--stdin", "--template-dir", "templates"]).unwrap();
+        let Command::Render(args) = cli.command else {
+            panic!("expected the render command");
+        };
+        assert!(args.stdin && args.file.is_none());
+
+        let Command::Compile(args) = Cli::try_parse_from(["plt", "compile", "templates"]).unwrap().command else {
+            panic!("expected the compile command");
+        };
+        assert!(args.dirs(None).is_err());
+        assert!(Cli::try_parse_from(["plt", "compile", "templates", "-o", "out", "--fn-name", "page"]).is_err());
+        assert!(Cli::try_parse_from(["plt", "compile", "--stdin", "-o", "out"]).is_err());
+        assert!(Cli::try_parse_from(["plt", "render"]).is_err());
+        assert!(Cli::try_parse_from(["plt", "render", "index.plt", "--stdin"]).is_err());
+    }
+
+    #[test]
+    fn it_parses_extract_messages_arguments() {
+        let cli = Cli::try_parse_from(["plt", "extract-messages", "templates", "-o", "messages.ftl", "--format", "pot"]).unwrap();
+
+        let Command::ExtractMessages(args) = cli.command else {
+            panic!("expected the extract-messages command");
+        };
+
+        assert_eq!(args.output.to_str(), Some("messages.ftl"));
+        assert_eq!(args.format, Some(plt::i18n::CatalogFormat::Pot));
+    }
+
+    #[test]
+    fn it_parses_doc_arguments() {
+        let cli = Cli::try_parse_from(["plt", "doc", "templates", "-o", "templates.md", "--format", "html", "--escape", "html"]).unwrap();
+
+        let Command::Doc(args) = cli.command else {
+            panic!("expected the doc command");
+        };
+
+        assert_eq!(args.output.to_str(), Some("templates.md"));
+        assert_eq!(args.format, Some(plt::doc::DocFormat::Html));
+        assert_eq!(args.escape, Escaping::Html);
+    }
+
+    #[test]
+    fn it_parses_scaffolding_arguments() {
+        let Command::New(args) = Cli::try_parse_from(["plt", "new", "my-site"]).unwrap().command else {
+            panic!("expected the new command");
+        };
+        assert_eq!(args.path.to_str(), Some("my-site"));
+
+        let Command::Init(args) = Cli::try_parse_from(["plt", "init"]).unwrap().command else {
+            panic!("expected the init command");
+        };
+        assert_eq!(args.path.to_str(), Some("."));
+        assert!(Cli::try_parse_from(["plt", "new"]).is_err());
+    }
+
+    #[test]
+    fn it_parses_graph_arguments() {
+        let cli = Cli::try_parse_from(["plt", "graph", "templates", "--format", "dot"]).unwrap();
+
+        let Command::Graph(args) = cli.command else {
+            panic!("expected the graph command");
+        };
+
+        assert_eq!(args.format, GraphFormat::Dot);
+        assert!(args.output.is_none());
+    }
+}
@@ -0,0 +1,492 @@
+// Helpers for compiling templates from build scripts:
+//
+//     fn main() {
+//         let out_dir = std::env::var("OUT_DIR").unwrap();
+//         plt::build::compile_dir("templates", out_dir).unwrap();
+//     }
+//
+// and then in the crate:
+//
+//     include!(concat!(env!("OUT_DIR"), "/templates.rs"));
+//
+// With plt's `project` feature, `compile_project()` takes the template directory and the options
+// from the crate's `plt.toml` instead, see `plt::project`.
+use std::fmt;
+use std::path::{Path, PathBuf};
+use anyhow::{bail, Context};
+use crate::assets::AssetManifest;
+use crate::prelude::*;
+
+#[derive(Debug, Clone)]
+pub struct BuildOptions {
+    pub generator: GeneratorOptions,
+    // Name of the generated root module and of the `<module_name>.rs` file containing it.
+    pub module_name: String,
+    // Writes each template's function into its own `<module_name>/<path>.rs` file which the
+    // root module includes, instead of putting all of them into the root module file.
+    pub split_files: bool,
+    // Writes the root module and each of its modules into their own files, e.g.
+    // `<module_name>/mod.rs` and `<module_name>/admin/users.rs`, for generating into `src` and
+    // declaring the root module with `mod <module_name>;`. Unlike the root module file, they
+    // can't be `include!`d.
+    pub module_files: bool,
+    // Directory the fingerprinted copies of the assets under `GeneratorOptions::asset_dir` and
+    // their `AssetManifest` are written into, e.g. the directory served under `asset_url`.
+    pub asset_out_dir: Option<PathBuf>,
+    // Further template roots searched after the template directory, e.g. a library of partials
+    // shared by the crates of a workspace, see `TemplateSet::load_roots`.
+    pub search_path: Vec<TemplateRoot>,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            generator: GeneratorOptions {
+                module_layout: ModuleLayout::Tree,
+                ..GeneratorOptions::default()
+            },
+            module_name: "templates".to_string(),
+            split_files: false,
+            module_files: false,
+            asset_out_dir: None,
+            search_path: Vec::new(),
+        }
+    }
+}
+
+pub fn compile_dir(template_dir: impl AsRef<Path>, out_dir: impl AsRef<Path>) -> Result<()> {
+    compile_dir_with(template_dir, out_dir, &BuildOptions::default())
+}
+
+// Compiles the templates of the nearest `plt.toml` of the crate whose build script is running
+// into cargo's `OUT_DIR`, using its settings.
+#[cfg(feature = "project")]
+pub fn compile_project() -> Result<()> {
+    let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").context("`CARGO_MANIFEST_DIR` is not set, is this a build script?")?);
+    let Some(config) = crate::project::ProjectConfig::find(&manifest_dir)? else {
+        bail!("no `{}` found in `{}` or above it", crate::project::CONFIG_FILE_NAME, manifest_dir.display());
+    };
+    let out_dir = std::env::var("OUT_DIR").context("`OUT_DIR` is not set, is this a build script?")?;
+
+    println!("cargo:rerun-if-changed={}", config.file.display());
+    compile_dir_with(&config.template_dir, out_dir, &config.build)
+}
+
+// Generates the functions of all `.plt` files under `template_dir` into `out_dir`, telling
+// cargo to rerun the build script when any of them changes. Errors of all templates are
+// reported at once.
+pub fn compile_dir_with(
+    template_dir: impl AsRef<Path>,
+    out_dir: impl AsRef<Path>,
+    options: &BuildOptions,
+) -> Result<()> {
+    let template_dir = template_dir.as_ref();
+
+    // Watching the directories themselves also catches added and removed templates.
+    for root in template_roots(template_dir, options) {
+        println!("cargo:rerun-if-changed={}", root.dir.display());
+        for file in template_files(&root.dir)? {
+            println!("cargo:rerun-if-changed={}", file.display());
+        }
+    }
+    if let Some(asset_dir) = &options.generator.asset_dir {
+        println!("cargo:rerun-if-changed={}", asset_dir.display());
+    }
+    let set = TemplateSet::load_roots(&template_roots(template_dir, options))?;
+    for template in set.paths().filter_map(|path| set.get(path)) {
+        for directive in template.build_constants()? {
+            match directive {
+                Directive::Env(name) => println!("cargo:rerun-if-env-changed={name}"),
+                Directive::BuiltAt => println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH"),
+                _ => {}
+            }
+        }
+    }
+
+    let report = generate_dir(template_dir, out_dir, options)?;
+    for warning in report.warnings.iter() {
+        println!("cargo:warning={warning}");
+    }
+
+    Ok(())
+}
+
+// Same as `compile_dir_with`, without any output for cargo, for use outside of build scripts.
+// Returns the statistics of the generated templates.
+pub fn generate_dir(
+    template_dir: impl AsRef<Path>,
+    out_dir: impl AsRef<Path>,
+    options: &BuildOptions,
+) -> Result<GenerationReport> {
+    generate_dir_observed(template_dir, out_dir, options, &())
+}
+
+// Same as `generate_dir`, telling `observer` about the progress, e.g. to show a progress bar.
+pub fn generate_dir_observed(
+    template_dir: impl AsRef<Path>,
+    out_dir: impl AsRef<Path>,
+    options: &BuildOptions,
+    observer: &dyn GenerationObserver,
+) -> Result<GenerationReport> {
+    let report = generate_report(template_dir.as_ref(), options, observer)?;
+    write_functions(out_dir.as_ref(), report.functions.clone(), options)?;
+
+    Ok(report)
+}
+
+// Fails if generating the templates under `template_dir` would change the files in `out_dir`,
+// e.g. to verify that generated code committed into the repository is up to date.
+pub fn check_dir(
+    template_dir: impl AsRef<Path>,
+    out_dir: impl AsRef<Path>,
+    options: &BuildOptions,
+) -> Result<()> {
+    check_dir_observed(template_dir, out_dir, options, &())
+}
+
+// Same as `check_dir`, telling `observer` about the progress.
+pub fn check_dir_observed(
+    template_dir: impl AsRef<Path>,
+    out_dir: impl AsRef<Path>,
+    options: &BuildOptions,
+    observer: &dyn GenerationObserver,
+) -> Result<()> {
+    let out_dir = out_dir.as_ref();
+    let report = generate_report(template_dir.as_ref(), options, observer)?;
+
+    let mut stale = Vec::new();
+    for (file, content) in generated_files(report.functions, options)? {
+        let file = out_dir.join(file);
+        if !std::fs::read_to_string(&file).is_ok_and(|existing| existing == content) {
+            stale.push(file);
+        }
+    }
+    if let (Some(asset_dir), Some(asset_out_dir)) = (&options.generator.asset_dir, &options.asset_out_dir) {
+        stale.extend(AssetManifest::load_dir(asset_dir, &options.generator.asset_url)?.stale_files(asset_out_dir));
+    }
+
+    if !stale.is_empty() {
+        let files: Vec<_> = stale.iter().map(|file| file.display().to_string()).collect();
+        bail!("generated files are out of date:\n{}", files.join("\n"));
+    }
+
+    Ok(())
+}
+
+// Formatted functions of all templates under `template_dir` along with the warnings of their
+// lints, reporting the errors of all of them.
+fn generate_report(template_dir: &Path, options: &BuildOptions, observer: &dyn GenerationObserver) -> Result<GenerationReport> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!("generate_dir", template_dir = %template_dir.display()).entered();
+    let set = TemplateSet::load_roots(&template_roots(template_dir, options))?;
+
+    let mut report = GenerationReport::new();
+    let mut diagnostics = Diagnostics::new();
+    for (generated, lints) in set.generate_each_checked(&options.generator, observer) {
+        if let Some((function, stats)) = generated {
+            report.push(function, stats);
+        }
+
+        for mut diagnostic in lints {
+            diagnostic.file = template_location(&set, template_dir, &diagnostic.file);
+            match diagnostic.severity {
+                Severity::Error => diagnostics.push(diagnostic),
+                Severity::Warning => report.warnings.push(diagnostic),
+            }
+        }
+    }
+    observer.finished(&report);
+    #[cfg(feature = "tracing")]
+    tracing::info!(
+        templates = report.templates.len(),
+        warnings = report.warnings.len(),
+        errors = diagnostics.len(),
+        duration = ?report.total_time(),
+        "generated templates"
+    );
+
+    if !diagnostics.is_empty() {
+        return Err(CompileError { diagnostics }.into());
+    }
+
+    Ok(report)
+}
+
+// The template directory followed by the search path.
+fn template_roots(template_dir: &Path, options: &BuildOptions) -> Vec<TemplateRoot> {
+    let mut roots = vec![TemplateRoot::new(template_dir)];
+    roots.extend(options.search_path.iter().cloned());
+
+    roots
+}
+
+// Location of the template under `path` in diagnostics, e.g. `templates/partials/header.plt`.
+pub fn template_location(set: &TemplateSet, template_dir: &Path, path: &str) -> String {
+    let section = path.find('#').map_or("", |index| &path[index..]);
+    match set.file(path) {
+        Some(file) => format!("{}{section}", file.display()),
+        None => format!("{}/{path}", template_dir.display()),
+    }
+}
+
+// Error of `generate_dir` and `check_dir` when templates fail to compile, with the diagnostics
+// of all of them.
+#[derive(Debug)]
+pub struct CompileError {
+    pub diagnostics: Diagnostics,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to compile templates:\n{}", self.diagnostics)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+// Writes the root module file containing, or including, the generated functions, along with
+// the fingerprinted copies of the assets.
+pub(crate) fn write_functions(out_dir: &Path, functions: Vec<GeneratedFunction>, options: &BuildOptions) -> Result<()> {
+    std::fs::create_dir_all(out_dir)
+        .with_context(|| format!("failed to create `{}`", out_dir.display()))?;
+
+    if let (Some(asset_dir), Some(asset_out_dir)) = (&options.generator.asset_dir, &options.asset_out_dir) {
+        AssetManifest::load_dir(asset_dir, &options.generator.asset_url)?.write_dir(asset_dir, asset_out_dir)?;
+    }
+
+    for (file, content) in generated_files(functions, options)? {
+        let file = out_dir.join(file);
+        std::fs::create_dir_all(file.parent().unwrap())
+            .with_context(|| format!("failed to create `{}`", out_dir.display()))?;
+        write_file(&file, &content)?;
+    }
+
+    Ok(())
+}
+
+// Formatted files holding the generated functions, with their paths relative to the output
+// directory. Their content only depends on the templates and the options, so it's the same
+// wherever and whenever they're generated.
+fn generated_files(mut functions: Vec<GeneratedFunction>, options: &BuildOptions) -> Result<Vec<(PathBuf, String)>> {
+    if options.module_files {
+        if options.split_files {
+            bail!("`split_files` and `module_files` can't be used together");
+        }
+
+        return module_files(&options.module_name, &functions, &options.generator)?
+            .into_iter()
+            .map(|(file, code)| Ok((file, try_format_code_with(&code, options.generator.formatter)?)))
+            .collect();
+    }
+
+    let mut files = Vec::new();
+    if options.split_files {
+        for function in &mut functions {
+            // Included paths are relative to the root module file, and use `/` on all platforms.
+            let file = format!("{}/{}.rs", options.module_name, function.path.trim_end_matches(".plt"));
+            let mut code = std::mem::replace(&mut function.code, format!("include!({file:?});"));
+            // Functions are already formatted, other than by rustfmt.
+            if options.generator.formatter == CodeFormatter::Rustfmt {
+                code = try_format_code_with(&code, options.generator.formatter)?;
+            }
+            files.push((PathBuf::from(file), code));
+        }
+    }
+
+    let code = module_tree(&options.module_name, &functions, &options.generator)?;
+    files.insert(0, (PathBuf::from(format!("{}.rs", options.module_name)), try_format_code_with(&code, options.generator.formatter)?));
+
+    Ok(files)
+}
+
+// Only writes files whose content changed, so their modification time stays untouched.
+fn write_file(path: &Path, content: &str) -> Result<()> {
+    if std::fs::read_to_string(path).is_ok_and(|existing| existing == content) {
+        return Ok(());
+    }
+
+    std::fs::write(path, content).with_context(|| format!("failed to write `{}`", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::build::{check_dir, compile_dir, compile_dir_with, generate_dir, generate_dir_observed, BuildOptions, CompileError};
+    use crate::prelude::*;
+    use std::fs::read_to_string;
+
+    fn out_dir(name: &str) -> std::path::PathBuf {
+        let out_dir = std::env::temp_dir().join(format!("plt-build-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&out_dir);
+        out_dir
+    }
+
+    #[test]
+    fn it_compiles_a_template_directory() {
+        let out_dir = out_dir("combined");
+
+        compile_dir("src/test-files/build", &out_dir).unwrap();
+
+        let code = read_to_string(out_dir.join("templates.rs")).unwrap();
+        std::fs::remove_dir_all(&out_dir).unwrap();
+
+        assert!(code.starts_with("pub mod templates {\n    pub fn index(name: &str)"));
+        assert!(code.contains("    pub mod partials {\n        pub fn header(title: &str)"));
+        assert!(code.contains("output_buffer.push_str(&partials::header(name)?);"));
+    }
+
+    #[test]
+    fn it_splits_functions_into_files() {
+        let out_dir = out_dir("split");
+
+        let options = BuildOptions { split_files: true, ..BuildOptions::default() };
+        compile_dir_with("src/test-files/build", &out_dir, &options).unwrap();
+
+        let code = read_to_string(out_dir.join("templates.rs")).unwrap();
+        let header = read_to_string(out_dir.join("templates/partials/header.rs")).unwrap();
+        std::fs::remove_dir_all(&out_dir).unwrap();
+
+        assert!(code.contains("include!(\"templates/partials/header.rs\");"));
+        assert!(header.starts_with("pub fn header(title: &str)"));
+    }
+
+    #[test]
+    fn it_checks_whether_generated_files_are_up_to_date() {
+        let out_dir = out_dir("check");
+        let options = BuildOptions { split_files: true, ..BuildOptions::default() };
+
+        let missing = check_dir("src/test-files/build", &out_dir, &options).unwrap_err();
+        generate_dir("src/test-files/build", &out_dir, &options).unwrap();
+        let up_to_date = check_dir("src/test-files/build", &out_dir, &options);
+        std::fs::write(out_dir.join("templates/index.rs"), "").unwrap();
+        let changed = check_dir("src/test-files/build", &out_dir, &options).unwrap_err();
+        std::fs::remove_dir_all(&out_dir).unwrap();
+
+        assert!(missing.to_string().starts_with("generated files are out of date:\n"));
+        assert_eq!(missing.to_string().lines().count(), 4);
+        assert!(up_to_date.is_ok());
+        assert_eq!(
+            changed.to_string(),
+            format!("generated files are out of date:\n{}", out_dir.join("templates/index.rs").display())
+        );
+    }
+
+    #[test]
+    fn it_writes_module_files() {
+        let out_dir = out_dir("modules");
+
+        let mut options = BuildOptions { module_files: true, ..BuildOptions::default() };
+        options.generator.module_layout = ModuleLayout::Modules;
+        compile_dir_with("src/test-files/build", &out_dir, &options).unwrap();
+
+        let root = read_to_string(out_dir.join("templates/mod.rs")).unwrap();
+        let partials = read_to_string(out_dir.join("templates/partials/mod.rs")).unwrap();
+        let header = read_to_string(out_dir.join("templates/partials/header.rs")).unwrap();
+        let exists = out_dir.join("templates.rs").exists();
+        std::fs::remove_dir_all(&out_dir).unwrap();
+
+        assert_eq!(root, "mod index;\npub use self::index::*;\npub mod partials;\n");
+        assert_eq!(partials, "mod header;\npub use self::header::*;\n");
+        assert!(header.starts_with("pub fn header(title: &str)"));
+        assert!(!exists);
+    }
+
+    #[test]
+    fn it_reports_errors_with_locations() {
+        let out_dir = out_dir("errors");
+
+        let error = compile_dir("src/test-files/build-errors", &out_dir).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "failed to compile templates:\nsrc/test-files/build-errors/index.plt:3:5: `@endmatch` found without matching `@match`"
+        );
+        let diagnostics = &error.downcast_ref::<CompileError>().unwrap().diagnostics;
+        assert_eq!(diagnostics.diagnostics[0].file, "src/test-files/build-errors/index.plt");
+        assert_eq!(diagnostics.diagnostics[0].code, "unbalanced_directives");
+        assert!(!out_dir.exists());
+    }
+
+    #[derive(Default)]
+    struct Recorder(std::sync::Mutex<Vec<String>>);
+
+    impl GenerationObserver for Recorder {
+        fn started(&self, templates: usize) {
+            self.0.lock().unwrap().push(format!("started {templates}"));
+        }
+
+        fn template_generated(&self, path: &str, stats: Option<&TemplateStats>) {
+            self.0.lock().unwrap().push(format!("{path} {}", stats.is_some()));
+        }
+
+        fn finished(&self, report: &GenerationReport) {
+            self.0.lock().unwrap().push(format!("finished {}", report.templates.len()));
+        }
+    }
+
+    #[test]
+    fn it_tells_observers_about_the_progress() {
+        let out_dir = out_dir("observed");
+
+        let recorder = Recorder::default();
+        generate_dir_observed("src/test-files/build", &out_dir, &BuildOptions::default(), &recorder).unwrap();
+        std::fs::remove_dir_all(&out_dir).unwrap();
+
+        let mut events = recorder.0.into_inner().unwrap();
+        // Templates are generated in any order with the `parallel` feature.
+        events[1..3].sort();
+        assert_eq!(events, ["started 2", "index.plt true", "partials/header.plt true", "finished 2"]);
+
+        let recorder = Recorder::default();
+        assert!(generate_dir_observed("src/test-files/build-errors", &out_dir, &BuildOptions::default(), &recorder).is_err());
+        assert_eq!(recorder.0.into_inner().unwrap(), ["started 1", "index.plt false", "finished 0"]);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn it_traces_the_generation() {
+        use std::sync::Mutex;
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Level, Metadata, Subscriber};
+
+        // Records the names of the spans and the levels of the events.
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<String>>);
+
+        impl Subscriber for Recorder {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut records = self.0.lock().unwrap();
+                records.push(span.metadata().name().to_string());
+                Id::from_u64(records.len() as u64)
+            }
+
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+            fn event(&self, event: &Event<'_>) {
+                self.0.lock().unwrap().push(format!("{} event", event.metadata().level()));
+            }
+
+            fn enter(&self, _: &Id) {}
+
+            fn exit(&self, _: &Id) {}
+        }
+
+        let out_dir = out_dir("traced");
+        let recorder = std::sync::Arc::new(Recorder::default());
+        tracing::subscriber::with_default(recorder.clone(), || generate_dir("src/test-files/build", &out_dir, &BuildOptions::default()).unwrap());
+        std::fs::remove_dir_all(&out_dir).unwrap();
+
+        let records = recorder.0.lock().unwrap();
+        let count = |name: &str| records.iter().filter(|record| *record == name).count();
+        assert_eq!(records[..2], ["generate_dir", "load_templates"]);
+        assert_eq!(count("parse_template"), 2);
+        assert_eq!(count("generate_template"), 2);
+        assert!(count("format_code") >= 2);
+        assert_eq!(count(&format!("{} event", Level::INFO)), 1);
+    }
+}
@@ -0,0 +1,101 @@
+// Values of `@env "NAME"`, `@built_at` and `@git_sha`, which are resolved during generation and
+// written into the generated code as text:
+//
+//     <footer>v<?rs @env "CARGO_PKG_VERSION" ?> (<?rs @git_sha ?>, built <?rs @built_at ?>)</footer>
+//
+// `@built_at` uses `SOURCE_DATE_EPOCH` instead of the current time when it's set, for
+// reproducible builds.
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{bail, Context};
+use crate::prelude::*;
+
+// Value of a directive resolved during generation, or `None` for other directives.
+pub fn build_constant(directive: &Directive) -> Option<Result<String>> {
+    match directive {
+        Directive::Env(name) => Some(env_value(name)),
+        Directive::BuiltAt => Some(built_at()),
+        Directive::GitSha => Some(git_sha()),
+        _ => None,
+    }
+}
+
+pub fn env_value(name: &str) -> Result<String> {
+    match std::env::var(name) {
+        Ok(value) => Ok(value),
+        Err(std::env::VarError::NotPresent) => bail!("environment variable `{name}` used by `@env` is not set"),
+        Err(std::env::VarError::NotUnicode(_)) => bail!("environment variable `{name}` used by `@env` is not valid unicode"),
+    }
+}
+
+// Time of the build in UTC, e.g. `2024-05-01T12:30:00Z`.
+pub fn built_at() -> Result<String> {
+    let timestamp = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch
+            .trim()
+            .parse::<u64>()
+            .with_context(|| format!("`SOURCE_DATE_EPOCH` used by `@built_at` is not a timestamp, found `{epoch}`"))?,
+        Err(_) => SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+    };
+
+    Ok(format_timestamp(timestamp))
+}
+
+fn format_timestamp(timestamp: u64) -> String {
+    let (days, seconds) = (timestamp / 86400, timestamp % 86400);
+    let (year, month, day) = civil_date(days);
+
+    format!("{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+// Date of the day `days` after 1970-01-01, using Howard Hinnant's `civil_from_days`.
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    (year, month, day)
+}
+
+// Commit checked out in the working directory, e.g. the crate of the running build script.
+pub fn git_sha() -> Result<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .context("`@git_sha` failed to run `git`")?;
+
+    if !output.status.success() {
+        bail!("`@git_sha` failed to read the current commit: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::build_constants::{build_constant, format_timestamp};
+    use crate::prelude::*;
+
+    #[test]
+    fn it_formats_build_times() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(951782400 + 3661), "2000-02-29T01:01:01Z");
+        assert_eq!(format_timestamp(1714566600), "2024-05-01T12:30:00Z");
+    }
+
+    #[test]
+    fn it_resolves_environment_variables() {
+        let value = build_constant(&Directive::Env("CARGO_PKG_NAME".to_string())).unwrap();
+        assert_eq!(value.unwrap(), "plt");
+
+        let error = build_constant(&Directive::Env("PLT_TEST_MISSING".to_string())).unwrap().unwrap_err();
+        assert_eq!(error.to_string(), "environment variable `PLT_TEST_MISSING` used by `@env` is not set");
+        assert!(build_constant(&Directive::Slot).is_none());
+    }
+}
@@ -0,0 +1,522 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use anyhow::{bail, Context};
+pub use crate::prelude::*;
+
+const MANIFEST_HEADER: &str = "plt-cache 2";
+
+// FNV-1a, used instead of `DefaultHasher` since its output must stay the same across
+// runs and compiler versions for the manifest to be reusable. Values are fed as explicit
+// bytes rather than through `Hash`, whose impls write lengths as native-endian `usize`s and
+// may change between compiler versions.
+#[derive(Debug)]
+pub struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl StableHasher {
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    // Writes the bytes after their length, so consecutive values can't run into each other.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u64(bytes.len() as u64);
+        self.write(bytes);
+    }
+
+    pub fn write_str(&mut self, value: &str) {
+        self.write_bytes(value.as_bytes());
+    }
+
+    pub fn write_option(&mut self, bytes: Option<&[u8]>) {
+        match bytes {
+            Some(bytes) => {
+                self.write(&[1]);
+                self.write_bytes(bytes);
+            }
+            None => self.write(&[0]),
+        }
+    }
+}
+
+// What the generated code of a template depends on besides its own source.
+#[derive(Debug, Clone, PartialEq)]
+enum Dependency {
+    // Included, called, extended or used as a macro.
+    Template(String),
+    Asset(String),
+    // `@env`, `@built_at` or `@git_sha`, whose values are resolved again for every run.
+    Constant(Directive),
+}
+
+impl Dependency {
+    // Written as a line of the manifest, e.g. `template partials/header.plt`.
+    fn to_line(&self) -> Result<String> {
+        let line = match self {
+            Dependency::Template(path) => format!("template {path}"),
+            Dependency::Asset(path) => format!("asset {path}"),
+            Dependency::Constant(directive) => format!("constant {directive}"),
+        };
+        if line.contains(['\n', '\r']) {
+            bail!("dependency {line:?} contains a line break, which the cache manifest can't hold");
+        }
+
+        Ok(line)
+    }
+
+    fn parse(line: &str) -> Result<Dependency> {
+        let dependency = match line.split_once(' ') {
+            Some(("template", path)) => Dependency::Template(path.to_string()),
+            Some(("asset", path)) => Dependency::Asset(path.to_string()),
+            Some(("constant", directive)) => match Directive::parse(directive) {
+                Ok(Some(directive)) => Dependency::Constant(directive),
+                _ => bail!("malformed constant `{directive}`"),
+            },
+            _ => bail!("malformed dependency `{line}`"),
+        };
+
+        Ok(dependency)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct CacheEntry {
+    hash: u64,
+    // Hash of the source the dependencies were found in, see `TemplateSet::source_hash`.
+    source: u64,
+    dependencies: Vec<Dependency>,
+    code: String,
+}
+
+// Cache of formatted template functions keyed by template path, persisted as a `.plt-cache`
+// manifest. An entry is reused as long as the hash of the template, the templates it depends
+// on and the generator options is unchanged. The dependencies of templates whose source is
+// unchanged are read from it too, so cached templates aren't parsed for directives again.
+#[derive(Debug, Default)]
+pub struct GenerationCache {
+    entries: BTreeMap<String, CacheEntry>,
+    hits: usize,
+    misses: usize,
+}
+
+impl GenerationCache {
+    pub const FILE_NAME: &'static str = ".plt-cache";
+
+    pub fn new() -> GenerationCache {
+        Self::default()
+    }
+
+    // Loads the manifest, starting with an empty cache when it doesn't exist yet.
+    pub fn load(path: impl AsRef<Path>) -> Result<GenerationCache> {
+        let path = path.as_ref();
+
+        if !path.exists() {
+            return Ok(Self::new());
+        }
+
+        let manifest = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read cache manifest `{}`", path.display()))?;
+
+        Self::parse(&manifest).with_context(|| format!("malformed cache manifest `{}`", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+
+        std::fs::write(path, self.to_manifest()?)
+            .with_context(|| format!("failed to write cache manifest `{}`", path.display()))
+    }
+
+    // Each entry is a `<hash> <source hash> <dependency count> <code length> <path>` line
+    // followed by a line per dependency and the code itself.
+    fn to_manifest(&self) -> Result<String> {
+        let mut manifest = format!("{MANIFEST_HEADER}\n");
+
+        for (path, entry) in &self.entries {
+            if path.contains(['\n', '\r']) {
+                bail!("template path {path:?} contains a line break, which the cache manifest can't hold");
+            }
+
+            manifest.push_str(&format!(
+                "{:016x} {:016x} {} {} {}\n",
+                entry.hash,
+                entry.source,
+                entry.dependencies.len(),
+                entry.code.len(),
+                path
+            ));
+            for dependency in &entry.dependencies {
+                manifest.push_str(&dependency.to_line()?);
+                manifest.push('\n');
+            }
+            manifest.push_str(&entry.code);
+            manifest.push('\n');
+        }
+
+        Ok(manifest)
+    }
+
+    fn parse(manifest: &str) -> Result<GenerationCache> {
+        let Some(mut rest) = manifest.strip_prefix(MANIFEST_HEADER).and_then(|rest| rest.strip_prefix('\n')) else {
+            bail!("missing `{MANIFEST_HEADER}` header");
+        };
+
+        let mut cache = Self::new();
+        while !rest.is_empty() {
+            let Some((line, after_line)) = rest.split_once('\n') else {
+                bail!("unterminated entry header");
+            };
+
+            let mut fields = line.splitn(5, ' ');
+            let (Some(hash), Some(source), Some(count), Some(length), Some(path)) =
+                (fields.next(), fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                bail!("malformed entry header `{line}`");
+            };
+            let hash = u64::from_str_radix(hash, 16).with_context(|| format!("malformed hash `{hash}`"))?;
+            let source = u64::from_str_radix(source, 16).with_context(|| format!("malformed hash `{source}`"))?;
+            let count: usize = count.parse().with_context(|| format!("malformed dependency count `{count}`"))?;
+            let length: usize = length.parse().with_context(|| format!("malformed length `{length}`"))?;
+
+            let mut after_line = after_line;
+            let mut dependencies = Vec::new();
+            for _ in 0..count {
+                let Some((line, after_dependency)) = after_line.split_once('\n') else {
+                    bail!("truncated dependencies of `{path}`");
+                };
+                dependencies.push(Dependency::parse(line)?);
+                after_line = after_dependency;
+            }
+
+            let Some(code) = after_line.get(..length) else {
+                bail!("truncated code of `{path}`");
+            };
+            let Some(after_code) = after_line[length..].strip_prefix('\n') else {
+                bail!("unterminated code of `{path}`");
+            };
+
+            cache.entries.insert(path.to_string(), CacheEntry { hash, source, dependencies, code: code.to_string() });
+            rest = after_code;
+        }
+
+        Ok(cache)
+    }
+
+    // Number of functions reused from the cache since it was created or loaded.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    // Number of functions which had to be generated since the cache was created or loaded.
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+}
+
+impl TemplateSet {
+    // Hash of the layout of the set which resolving the dependencies of templates depends on:
+    // the paths of all templates and the sources of the files with sections, which may define
+    // the macros used by other templates.
+    fn layout_hash(&self) -> u64 {
+        let files_with_sections: BTreeSet<&str> = self.paths().filter_map(|path| path.split_once('#')).map(|(file, _)| file).collect();
+
+        let mut hasher = StableHasher::default();
+        for path in self.paths() {
+            hasher.write_str(path);
+            match self.get(path).filter(|_| files_with_sections.contains(path.as_str())) {
+                Some(template) => hasher.write_option(Some(template.source.as_bytes())),
+                None => hasher.write_option(None),
+            }
+        }
+
+        hasher.finish()
+    }
+
+    // Hash of what the dependencies of `template` are found in: its path, arguments and source
+    // along with the layout of the set.
+    fn source_hash(template: &Template, layout: u64) -> u64 {
+        let mut hasher = StableHasher::default();
+        hasher.write_u64(layout);
+        hasher.write_str(&template.path);
+        hasher.write_u64(template.args.len() as u64);
+        for arg in &template.args {
+            hasher.write_str(arg);
+        }
+        hasher.write_str(&template.source);
+
+        hasher.finish()
+    }
+
+    // Parses the dependencies out of the directives of `template`.
+    fn find_dependencies(&self, template: &Template) -> Result<Vec<Dependency>> {
+        let mut dependencies: Vec<_> = self.dependencies(template)?.into_iter().map(Dependency::Template).collect();
+        dependencies.extend(template.assets()?.into_iter().map(Dependency::Asset));
+        dependencies.extend(template.build_constants()?.into_iter().map(Dependency::Constant));
+
+        Ok(dependencies)
+    }
+
+    // Hash of everything the generated code of `path` depends on, given the source hashes and
+    // dependencies of the templates.
+    fn generation_hash(
+        &self,
+        path: &str,
+        options: &GeneratorOptions,
+        dependencies: &BTreeMap<String, (u64, Vec<Dependency>)>,
+    ) -> u64 {
+        let mut hasher = StableHasher::default();
+        hasher.write_str(env!("CARGO_PKG_VERSION"));
+        // The derived `Debug` covers all fields of the options, including ones added later.
+        hasher.write_str(&format!("{options:?}"));
+
+        let mut visited = BTreeSet::new();
+        let mut queue = vec![path.to_string()];
+        while let Some(path) = queue.pop() {
+            if !visited.insert(path.clone()) {
+                continue;
+            }
+
+            for dependency in dependencies.get(&path).into_iter().flat_map(|(_, dependencies)| dependencies) {
+                if let Dependency::Template(path) = dependency {
+                    queue.push(path.clone());
+                }
+            }
+        }
+
+        for path in visited {
+            hasher.write_str(&path);
+            // Includes call the functions of other templates by their names.
+            hasher.write_str(&self.function_name(&path, options));
+
+            let Some((source, dependencies)) = dependencies.get(&path) else {
+                hasher.write(&[0]);
+                continue;
+            };
+            hasher.write(&[1]);
+            hasher.write_u64(*source);
+
+            for dependency in dependencies {
+                match dependency {
+                    Dependency::Template(_) => {}
+                    // The URLs rendered by `@asset` change with the content of the assets.
+                    Dependency::Asset(asset) => {
+                        let content = options.asset_dir.as_ref().and_then(|asset_dir| std::fs::read(asset_dir.join(asset)).ok());
+                        hasher.write_option(content.as_deref());
+                    }
+                    // Values resolved during generation, e.g. by `@env`, change without the template.
+                    Dependency::Constant(directive) => {
+                        let value = build_constant(directive).and_then(Result::ok);
+                        hasher.write_option(value.as_ref().map(String::as_bytes));
+                    }
+                }
+            }
+        }
+
+        hasher.finish()
+    }
+
+    // Same as `generate_all`, but reuses the code of templates which didn't change since
+    // it was cached. The cache is updated to contain exactly the current templates.
+    pub fn generate_all_cached(
+        &self,
+        options: &GeneratorOptions,
+        cache: &mut GenerationCache,
+    ) -> Result<Vec<GeneratedFunction>> {
+        let layout = self.layout_hash();
+        let mut dependencies = BTreeMap::new();
+        for path in self.paths() {
+            let Some(template) = self.get(path) else { continue };
+            let source = Self::source_hash(template, layout);
+
+            let template_dependencies = match cache.entries.get(path) {
+                Some(entry) if entry.source == source => entry.dependencies.clone(),
+                _ => self.find_dependencies(template)?,
+            };
+            dependencies.insert(path.clone(), (source, template_dependencies));
+        }
+
+        let mut entries = BTreeMap::new();
+        let mut functions = Vec::new();
+
+        for path in self.paths() {
+            let hash = self.generation_hash(path, options, &dependencies);
+
+            let function = match cache.entries.get(path) {
+                Some(entry) if entry.hash == hash => {
+                    cache.hits += 1;
+
+                    GeneratedFunction {
+                        path: path.clone(),
+                        module_path: self.module_path(path, options),
+                        name: self.function_name(path, options),
+                        code: entry.code.clone(),
+                        dispatch: self.dispatch(path, options)?,
+                    }
+                }
+                _ => {
+                    cache.misses += 1;
+                    self.generate_function(path, options)?
+                }
+            };
+
+            let (source, template_dependencies) = dependencies[path].clone();
+            entries.insert(path.clone(), CacheEntry { hash, source, dependencies: template_dependencies, code: function.code.clone() });
+            functions.push(function);
+        }
+
+        cache.entries = entries;
+
+        Ok(functions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::Dependency;
+    use crate::prelude::*;
+
+    fn test_set() -> TemplateSet {
+        let mut set = TemplateSet::new();
+        set.add_template("index.plt", vec!["name: &str".to_string()], "<?rs @include \"header.plt\" ?>Hello <?= name ?>".to_string());
+        set.add_template("header.plt", Vec::new(), "<h1>Header</h1>".to_string());
+        set.add_template("about.plt", Vec::new(), "About".to_string());
+        set
+    }
+
+    #[test]
+    fn it_reuses_unchanged_templates() {
+        let options = GeneratorOptions::default();
+        let mut cache = GenerationCache::new();
+
+        let set = test_set();
+        let uncached = set.generate_all_cached(&options, &mut cache).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (0, 3));
+
+        let cached = set.generate_all_cached(&options, &mut cache).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (3, 3));
+        assert_eq!(cached, uncached);
+        assert_eq!(cached, set.generate_all(&options).unwrap().functions);
+    }
+
+    #[test]
+    fn it_regenerates_templates_when_dependencies_change() {
+        let options = GeneratorOptions::default();
+        let mut cache = GenerationCache::new();
+
+        let mut set = test_set();
+        set.generate_all_cached(&options, &mut cache).unwrap();
+
+        set.add_template("header.plt", Vec::new(), "<h1>Changed</h1>".to_string());
+        set.generate_all_cached(&options, &mut cache).unwrap();
+
+        // `header.plt` and its includer `index.plt` are regenerated, `about.plt` is reused.
+        assert_eq!((cache.hits(), cache.misses()), (1, 5));
+    }
+
+    #[test]
+    fn it_regenerates_everything_when_options_change() {
+        let mut cache = GenerationCache::new();
+
+        let set = test_set();
+        set.generate_all_cached(&GeneratorOptions::default(), &mut cache).unwrap();
+
+        let options = GeneratorOptions { visibility: "pub(crate)".to_string(), ..GeneratorOptions::default() };
+        set.generate_all_cached(&options, &mut cache).unwrap();
+
+        assert_eq!((cache.hits(), cache.misses()), (0, 6));
+    }
+
+    #[test]
+    fn it_reads_the_dependencies_of_unchanged_templates_from_the_cache() {
+        let options = GeneratorOptions::default();
+        let mut cache = GenerationCache::new();
+
+        let set = test_set();
+        set.generate_all_cached(&options, &mut cache).unwrap();
+        assert_eq!(cache.entries["index.plt"].dependencies, [Dependency::Template("header.plt".to_string())]);
+
+        // They aren't parsed again while the source is unchanged, so the ones of the cache
+        // decide what the hash covers.
+        cache.entries.get_mut("index.plt").unwrap().dependencies.clear();
+        set.generate_all_cached(&options, &mut cache).unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (2, 4));
+        assert!(cache.entries["index.plt"].dependencies.is_empty());
+
+        // Adding templates may change which templates macros resolve to, so they're parsed again.
+        let mut set = set;
+        set.add_template("contact.plt", Vec::new(), "Contact".to_string());
+        set.generate_all_cached(&options, &mut cache).unwrap();
+        assert_eq!(cache.entries["index.plt"].dependencies, [Dependency::Template("header.plt".to_string())]);
+    }
+
+    #[test]
+    fn it_round_trips_the_manifest() {
+        let mut set = test_set();
+        set.add_template("footer.plt", Vec::new(), "<?rs @env \"CARGO_PKG_NAME\" ?><?rs @asset \"css/app.css\" ?>".to_string());
+        let options = GeneratorOptions { asset_dir: Some("src/test-files/assets".into()), ..GeneratorOptions::default() };
+        let mut cache = GenerationCache::new();
+        set.generate_all_cached(&options, &mut cache).unwrap();
+        assert_eq!(
+            cache.entries["footer.plt"].dependencies,
+            [Dependency::Asset("css/app.css".to_string()), Dependency::Constant(Directive::Env("CARGO_PKG_NAME".to_string()))]
+        );
+
+        let manifest_path = std::env::temp_dir().join(format!("plt-cache-test-{}", std::process::id()));
+        cache.save(&manifest_path).unwrap();
+        let loaded = GenerationCache::load(&manifest_path).unwrap();
+        std::fs::remove_file(&manifest_path).unwrap();
+
+        assert_eq!(loaded.entries, cache.entries);
+    }
+
+    #[test]
+    fn it_hashes_explicit_bytes() {
+        let hash = |values: &[&str]| {
+            let mut hasher = StableHasher::default();
+            for value in values {
+                hasher.write_str(value);
+            }
+            hasher.finish()
+        };
+
+        // The same on all platforms, as lengths are written as little-endian `u64`s.
+        assert_eq!(hash(&["ab"]), 0x9c6001d328a94690);
+        assert_ne!(hash(&["ab", "c"]), hash(&["a", "bc"]));
+    }
+
+    #[test]
+    fn it_rejects_paths_with_line_breaks() {
+        let mut set = TemplateSet::new();
+        set.add_template("a\nb.plt", Vec::new(), "text".to_string());
+        let mut cache = GenerationCache::new();
+        set.generate_all_cached(&GeneratorOptions::default(), &mut cache).unwrap();
+
+        let error = cache.to_manifest().unwrap_err();
+        assert_eq!(error.to_string(), "template path \"a\\nb.plt\" contains a line break, which the cache manifest can't hold");
+    }
+
+    #[test]
+    fn it_rejects_malformed_manifests() {
+        assert!(GenerationCache::parse("something else\n").is_err());
+        assert!(GenerationCache::parse("plt-cache 1\n0000000000000001 100 index.plt\nfn index() {}\n").is_err());
+        assert!(GenerationCache::parse("plt-cache 2\n0000000000000001 0000000000000002 1 13 index.plt\nfn index() {}\n").is_err());
+        assert!(GenerationCache::parse("plt-cache 2\n0000000000000001 0000000000000002 0 100 index.plt\nfn index() {}\n").is_err());
+        assert!(GenerationCache::parse("plt-cache 2\n0000000000000001 0000000000000002 0 13 index.plt\nfn index() {}\n").is_ok());
+    }
+}
@@ -0,0 +1,39 @@
+// Compression of large static text of templates, used by the code generated with
+// `GeneratorOptions::compress_text`. Text parts at least that long are stored deflated in the
+// binary and decompressed the first time they're rendered:
+//
+//     write!(output_buffer, "{}", {
+//         static TEXT: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+//         TEXT.get_or_init(|| plt::compress::decompress(&[..]))
+//     })?;
+//
+// Both the build script generating the code and the crate rendering it need plt's `compress`
+// feature.
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec;
+
+pub fn compress(text: &str) -> Vec<u8> {
+    compress_to_vec(text.as_bytes(), 9)
+}
+
+// Text compressed by `compress`. Panics on other data, as it's only called on data generated
+// along with the code.
+pub fn decompress(data: &[u8]) -> String {
+    let bytes = decompress_to_vec(data).expect("text compressed by plt should decompress");
+
+    String::from_utf8(bytes).expect("text compressed by plt should be UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compress::{compress, decompress};
+
+    #[test]
+    fn it_compresses_text_losslessly() {
+        let text = "<tr><td>é</td></tr>\n".repeat(200);
+        let compressed = compress(&text);
+
+        assert!(compressed.len() < text.len() / 10);
+        assert_eq!(decompress(&compressed), text);
+    }
+}
@@ -0,0 +1,146 @@
+// Config file templates, e.g. for infrastructure generated from Rust data. Templates ending with
+// `.toml.plt`, `.yaml.plt`, `.yml.plt` or `.ini.plt` escape their echoes for the double-quoted
+// strings of their format, whatever the escaping of the other templates:
+//
+//     <?rs @args service: &Service ?>
+//     [service]
+//     name = "<?= service.name ?>"
+//     port = <?= service.port ?>
+//
+// Quotes, backslashes and line breaks are escaped, so values can neither end their string nor
+// start new keys, even when echoed outside of quotes like numbers. The text isn't minified nor
+// checked against HTML rules. Instead, the `malformed_config` lint parses templates whose
+// structure doesn't depend on code, with `0` in place of their echoes, reporting where they
+// aren't valid documents. TOML and YAML templates are only parsed with plt's `config` feature.
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Ini,
+}
+
+impl ConfigFormat {
+    // Format of the template under `path`, e.g. `Toml` for `deploy/app.toml.plt`.
+    pub fn of_template(path: &str) -> Option<ConfigFormat> {
+        let file = path.split('#').next().unwrap_or(path);
+
+        match file.strip_suffix(".plt")?.rsplit_once('.')?.1 {
+            "toml" => Some(ConfigFormat::Toml),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            "ini" => Some(ConfigFormat::Ini),
+            _ => None,
+        }
+    }
+
+    pub fn escaping(self) -> Escaping {
+        match self {
+            ConfigFormat::Toml => Escaping::Toml,
+            ConfigFormat::Yaml => Escaping::Yaml,
+            ConfigFormat::Ini => Escaping::Ini,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ConfigFormat::Toml => "TOML",
+            ConfigFormat::Yaml => "YAML",
+            ConfigFormat::Ini => "INI",
+        }
+    }
+
+    // Checks that `document` is valid in the format, returning the offset of the first problem
+    // along with its message otherwise. `None` when the format can't be checked.
+    pub fn check(self, document: &str) -> Option<Result<(), (usize, String)>> {
+        match self {
+            #[cfg(feature = "config")]
+            ConfigFormat::Toml => Some(match document.parse::<toml::Table>() {
+                Ok(_) => Ok(()),
+                Err(error) => Err((error.span().map_or(0, |span| span.start), error.message().trim_end().to_string())),
+            }),
+            #[cfg(feature = "config")]
+            ConfigFormat::Yaml => Some(match yaml_rust2::YamlLoader::load_from_str(document) {
+                Ok(_) => Ok(()),
+                Err(error) => {
+                    let offset = document.char_indices().nth(error.marker().index()).map_or(document.len(), |(offset, _)| offset);
+                    Err((offset, error.info().to_string()))
+                }
+            }),
+            #[cfg(not(feature = "config"))]
+            ConfigFormat::Toml | ConfigFormat::Yaml => None,
+            ConfigFormat::Ini => Some(check_ini(document)),
+        }
+    }
+}
+
+// INI files have many dialects, so only lines which none of them accepts are reported. Indented
+// lines continue the value of the previous key.
+fn check_ini(document: &str) -> Result<(), (usize, String)> {
+    let mut offset = 0;
+    let mut has_key = false;
+
+    for line in document.split_inclusive('\n') {
+        let trimmed = line.trim();
+        let continues_value = has_key && line.starts_with([' ', '\t']);
+
+        if trimmed.starts_with('[') {
+            if !trimmed.ends_with(']') {
+                return Err((offset, "unclosed section header".to_string()));
+            }
+            has_key = false;
+        } else if !trimmed.is_empty() && !trimmed.starts_with([';', '#']) && !continues_value {
+            match trimmed.find(['=', ':']) {
+                Some(0) => return Err((offset, "missing key before the value".to_string())),
+                Some(_) => has_key = true,
+                None => return Err((offset, "expected a section, a comment or a `key = value` pair".to_string())),
+            }
+        }
+        offset += line.len();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ConfigFormat;
+    use crate::prelude::*;
+
+    #[test]
+    fn it_detects_config_templates() {
+        assert_eq!(ConfigFormat::of_template("deploy/app.toml.plt"), Some(ConfigFormat::Toml));
+        assert_eq!(ConfigFormat::of_template("k8s/service.yml.plt#port"), Some(ConfigFormat::Yaml));
+        assert_eq!(ConfigFormat::of_template("php.ini.plt"), Some(ConfigFormat::Ini));
+        assert_eq!(ConfigFormat::of_template("toml.plt"), None);
+    }
+
+    #[test]
+    fn it_checks_ini_documents() {
+        assert_eq!(ConfigFormat::Ini.check("; comment\n[main]\nname = a\n  continued\nport: 0\n"), Some(Ok(())));
+        assert_eq!(
+            ConfigFormat::Ini.check("[main]\nname\n"),
+            Some(Err((7, "expected a section, a comment or a `key = value` pair".to_string())))
+        );
+        assert_eq!(ConfigFormat::Ini.check("[main\n"), Some(Err((0, "unclosed section header".to_string()))));
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn it_checks_toml_and_yaml_documents() {
+        assert_eq!(ConfigFormat::Toml.check("[a]\nb = \"0\"\n"), Some(Ok(())));
+        assert!(matches!(ConfigFormat::Toml.check("[a]\nb = \n"), Some(Err((8, _)))));
+        assert_eq!(ConfigFormat::Yaml.check("a:\n  b: 0\n"), Some(Ok(())));
+        assert!(matches!(ConfigFormat::Yaml.check("a: [0\n"), Some(Err(_))));
+    }
+
+    #[test]
+    fn it_escapes_echoes_of_config_templates() {
+        let mut set = TemplateSet::new();
+        set.add_template("app.toml.plt", vec!["name: &str".to_string()], "name = \"<?= name ?>\"\n".to_string());
+        let options = GeneratorOptions { escaping: Escaping::Html, ..GeneratorOptions::default() };
+
+        let code = format_code(&set.generate_with("app.toml.plt", &options).unwrap().join("\n"));
+        assert!(code.contains("write!(output_buffer, \"{}\", plt::prelude::EscapeToml(& (name)))?;"), "{code}");
+    }
+}
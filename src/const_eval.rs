@@ -0,0 +1,201 @@
+// Evaluation of constant expressions during generation, so their values can be written as text:
+//
+//     <?= 60 * 60 * 24 ?> seconds, <?= concat!("v", "1.2") ?>
+//
+// Only literals, `concat!` of literals and operators on them are constant. The values are
+// displayed like the generated code would at runtime, so expressions which wouldn't compile,
+// e.g. overflowing ones or ones mixing types, are left to the compiler to report.
+use syn::{BinOp, Expr, Lit, UnOp};
+
+const INT_TYPES: [&str; 12] = ["i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64", "u128", "usize"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum ConstValue {
+    // Integers with their type, `None` until it's known from a suffix.
+    Int(i128, Option<&'static str>),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    Str(String),
+}
+
+// Displayed value of `code` if it's a constant expression.
+pub fn evaluate_const(code: &str) -> Option<String> {
+    let expr = syn::parse_str::<Expr>(code).ok()?;
+
+    let value = match evaluate(&expr)? {
+        // Integers of unknown type default to `i32`, like in Rust.
+        ConstValue::Int(value, None) => ConstValue::Int(checked_int(value, "i32")?, Some("i32")),
+        value => value,
+    };
+
+    Some(match value {
+        ConstValue::Int(value, _) => value.to_string(),
+        ConstValue::Float(value) => value.to_string(),
+        ConstValue::Bool(value) => value.to_string(),
+        ConstValue::Char(value) => value.to_string(),
+        ConstValue::Str(value) => value,
+    })
+}
+
+fn evaluate(expr: &Expr) -> Option<ConstValue> {
+    match expr {
+        Expr::Lit(lit) => literal(&lit.lit),
+        Expr::Paren(paren) => evaluate(&paren.expr),
+        Expr::Group(group) => evaluate(&group.expr),
+        Expr::Unary(unary) => {
+            let value = evaluate(&unary.expr)?;
+            match (&unary.op, value) {
+                (UnOp::Neg(_), ConstValue::Int(value, ty)) if !ty.is_some_and(|ty| ty.starts_with('u')) => {
+                    int(value.checked_neg()?, ty)
+                }
+                (UnOp::Neg(_), ConstValue::Float(value)) => Some(ConstValue::Float(-value)),
+                (UnOp::Not(_), ConstValue::Bool(value)) => Some(ConstValue::Bool(!value)),
+                _ => None,
+            }
+        }
+        Expr::Binary(binary) => binary_operation(&binary.op, evaluate(&binary.left)?, evaluate(&binary.right)?),
+        Expr::Macro(mac) if mac.mac.path.is_ident("concat") => {
+            let args = mac
+                .mac
+                .parse_body_with(syn::punctuated::Punctuated::<Expr, syn::Token![,]>::parse_terminated)
+                .ok()?;
+
+            let mut concatenated = String::new();
+            for arg in &args {
+                let Expr::Lit(lit) = arg else {
+                    return None;
+                };
+                concatenated.push_str(&evaluate_const(&quote::ToTokens::to_token_stream(lit).to_string())?);
+            }
+
+            Some(ConstValue::Str(concatenated))
+        }
+        _ => None,
+    }
+}
+
+fn literal(lit: &Lit) -> Option<ConstValue> {
+    match lit {
+        Lit::Str(lit) if lit.suffix().is_empty() => Some(ConstValue::Str(lit.value())),
+        Lit::Char(lit) if lit.suffix().is_empty() => Some(ConstValue::Char(lit.value())),
+        Lit::Bool(lit) => Some(ConstValue::Bool(lit.value)),
+        Lit::Int(lit) => {
+            let ty = match lit.suffix() {
+                "" => None,
+                suffix => Some(*INT_TYPES.iter().find(|ty| **ty == suffix)?),
+            };
+            int(lit.base10_parse::<i128>().ok()?, ty)
+        }
+        // `f32` arithmetic would round differently.
+        Lit::Float(lit) if matches!(lit.suffix(), "" | "f64") => Some(ConstValue::Float(lit.base10_parse::<f64>().ok()?)),
+        _ => None,
+    }
+}
+
+fn binary_operation(op: &BinOp, left: ConstValue, right: ConstValue) -> Option<ConstValue> {
+    use ConstValue::*;
+
+    match (left, right) {
+        (Int(left, left_ty), Int(right, right_ty)) => {
+            let ty = match (left_ty, right_ty) {
+                (Some(left_ty), Some(right_ty)) if left_ty != right_ty => return None,
+                (left_ty, right_ty) => left_ty.or(right_ty),
+            };
+
+            match op {
+                BinOp::Add(_) => int(left.checked_add(right)?, ty),
+                BinOp::Sub(_) => int(left.checked_sub(right)?, ty),
+                BinOp::Mul(_) => int(left.checked_mul(right)?, ty),
+                BinOp::Div(_) => int(left.checked_div(right)?, ty),
+                BinOp::Rem(_) => int(left.checked_rem(right)?, ty),
+                op => compare(op, &left, &right),
+            }
+        }
+        (Float(left), Float(right)) => match op {
+            BinOp::Add(_) => Some(Float(left + right)),
+            BinOp::Sub(_) => Some(Float(left - right)),
+            BinOp::Mul(_) => Some(Float(left * right)),
+            BinOp::Div(_) => Some(Float(left / right)),
+            BinOp::Rem(_) => Some(Float(left % right)),
+            op => compare(op, &left, &right),
+        },
+        (Bool(left), Bool(right)) => match op {
+            BinOp::And(_) => Some(Bool(left && right)),
+            BinOp::Or(_) => Some(Bool(left || right)),
+            op => compare(op, &left, &right),
+        },
+        (Char(left), Char(right)) => compare(op, &left, &right),
+        _ => None,
+    }
+}
+
+fn compare<T: PartialOrd>(op: &BinOp, left: &T, right: &T) -> Option<ConstValue> {
+    let result = match op {
+        BinOp::Eq(_) => left == right,
+        BinOp::Ne(_) => left != right,
+        BinOp::Lt(_) => left < right,
+        BinOp::Le(_) => left <= right,
+        BinOp::Gt(_) => left > right,
+        BinOp::Ge(_) => left >= right,
+        _ => return None,
+    };
+
+    Some(ConstValue::Bool(result))
+}
+
+// Integer of type `ty`, if the value is in its range.
+fn int(value: i128, ty: Option<&'static str>) -> Option<ConstValue> {
+    match ty {
+        Some(ty) => Some(ConstValue::Int(checked_int(value, ty)?, Some(ty))),
+        None => Some(ConstValue::Int(value, None)),
+    }
+}
+
+fn checked_int(value: i128, ty: &str) -> Option<i128> {
+    let in_range = match ty {
+        "i8" => i8::try_from(value).is_ok(),
+        "i16" => i16::try_from(value).is_ok(),
+        "i32" => i32::try_from(value).is_ok(),
+        // Pointer sized integers are assumed to be 64 bits wide.
+        "i64" | "isize" => i64::try_from(value).is_ok(),
+        "i128" => true,
+        "u8" => u8::try_from(value).is_ok(),
+        "u16" => u16::try_from(value).is_ok(),
+        "u32" => u32::try_from(value).is_ok(),
+        "u64" | "usize" => u64::try_from(value).is_ok(),
+        "u128" => value >= 0,
+        _ => false,
+    };
+
+    in_range.then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::const_eval::evaluate_const;
+
+    #[test]
+    fn it_evaluates_constant_expressions() {
+        assert_eq!(evaluate_const("60 * 60 * 24").as_deref(), Some("86400"));
+        assert_eq!(evaluate_const("-(7 / 2) + 10 % 4").as_deref(), Some("-1"));
+        assert_eq!(evaluate_const("1.5 * 2.0").as_deref(), Some("3"));
+        assert_eq!(evaluate_const("\"<b>\"").as_deref(), Some("<b>"));
+        assert_eq!(evaluate_const("concat!(\"v\", 1, '.', 2.5, true)").as_deref(), Some("v1.2.5true"));
+        assert_eq!(evaluate_const("2 > 1 && !false").as_deref(), Some("true"));
+        assert_eq!(evaluate_const("200u8 + 55").as_deref(), Some("255"));
+    }
+
+    #[test]
+    fn it_leaves_other_expressions_to_the_compiler() {
+        assert_eq!(evaluate_const("user.name"), None);
+        assert_eq!(evaluate_const("1 + count"), None);
+        assert_eq!(evaluate_const("2147483647 + 1"), None);
+        assert_eq!(evaluate_const("200u8 + 56"), None);
+        assert_eq!(evaluate_const("1u8 + 1u16"), None);
+        assert_eq!(evaluate_const("-1u32"), None);
+        assert_eq!(evaluate_const("1 / 0"), None);
+        assert_eq!(evaluate_const("1 + 1.0"), None);
+        assert_eq!(evaluate_const("1.0f32 / 3.0"), None);
+    }
+}
@@ -0,0 +1,68 @@
+// Nonces for strict Content-Security-Policy setups, which only run inline scripts and styles
+// carrying the nonce of the current response:
+//
+//     <script <?rs @nonce ?>>initialize();</script>
+//
+// Templates using `@nonce` take the nonce as their `csp_nonce: &plt::csp::Nonce` argument,
+// unless they already declare it. It is passed on to included templates like other arguments.
+//
+//     let nonce = Nonce::generate()?;
+//     let html = index(title, &nonce)?;
+//     response.insert_header("Content-Security-Policy", nonce.policy());
+use std::fmt;
+use anyhow::anyhow;
+use base64::Engine;
+use crate::prelude::*;
+
+// Name of the argument holding the nonce in templates using `@nonce`.
+pub const NONCE_ARG: &str = "csp_nonce";
+
+// Random value generated for each response, as base64.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nonce(String);
+
+impl Nonce {
+    // Generates a nonce from 16 random bytes.
+    pub fn generate() -> Result<Nonce> {
+        let mut bytes = [0; 16];
+        getrandom::fill(&mut bytes).map_err(|error| anyhow!("failed to generate a CSP nonce: {error}"))?;
+
+        Ok(Nonce(base64::engine::general_purpose::STANDARD.encode(bytes)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    // `nonce` attribute of inline `<script>` and `<style>` tags, as rendered by `@nonce`.
+    pub fn attribute(&self) -> String {
+        format!("nonce=\"{}\"", self.0)
+    }
+
+    // `Content-Security-Policy` header value allowing scripts and styles only when they come
+    // from the same origin or carry the nonce.
+    pub fn policy(&self) -> String {
+        format!("script-src 'self' 'nonce-{0}'; style-src 'self' 'nonce-{0}'; object-src 'none'; base-uri 'self'", self.0)
+    }
+}
+
+impl fmt::Display for Nonce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::csp::Nonce;
+
+    #[test]
+    fn it_generates_distinct_nonces() {
+        let nonce = Nonce::generate().unwrap();
+
+        assert_eq!(nonce.as_str().len(), 24);
+        assert_ne!(nonce, Nonce::generate().unwrap());
+        assert_eq!(nonce.attribute(), format!("nonce=\"{nonce}\""));
+        assert!(nonce.policy().contains(&format!("script-src 'self' 'nonce-{nonce}'")));
+    }
+}
@@ -0,0 +1,82 @@
+// CSRF tokens for forms, rendered as hidden inputs by `@csrf`:
+//
+//     <form method="post" action="/profile">
+//         <?rs @csrf ?>
+//     </form>
+//
+// Templates using `@csrf` take the provider of the token as their
+// `csrf: &dyn plt::csrf::CsrfTokenProvider` argument, unless they already declare it.
+// With the `axum` or `actix` feature, handlers can extract the `CsrfToken` which middleware
+// stored in the request extensions:
+//
+//     async fn profile(csrf: CsrfToken) -> ProfileTemplate<'static> { ... }
+use crate::prelude::*;
+
+// Name of the argument holding the token provider in templates using `@csrf`.
+pub const CSRF_ARG: &str = "csrf";
+
+pub trait CsrfTokenProvider: Sync {
+    // Token of the current session or request.
+    fn csrf_token(&self) -> Result<String>;
+
+    // Name of the form field holding the token, which the verifying middleware reads.
+    fn csrf_field_name(&self) -> &str {
+        "csrf_token"
+    }
+}
+
+impl<T: CsrfTokenProvider + ?Sized> CsrfTokenProvider for &T {
+    fn csrf_token(&self) -> Result<String> {
+        (**self).csrf_token()
+    }
+
+    fn csrf_field_name(&self) -> &str {
+        (**self).csrf_field_name()
+    }
+}
+
+// Token stored in the request extensions by middleware, e.g. after reading it from the session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsrfToken(pub String);
+
+impl CsrfTokenProvider for CsrfToken {
+    fn csrf_token(&self) -> Result<String> {
+        Ok(self.0.clone())
+    }
+}
+
+// Hidden input holding the token, as rendered by `@csrf`.
+pub fn hidden_input(provider: &dyn CsrfTokenProvider) -> Result<String> {
+    Ok(format!(
+        "<input type=\"hidden\" name=\"{}\" value=\"{}\">",
+        escape_html(provider.csrf_field_name()),
+        escape_html(provider.csrf_token()?)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::csrf::{hidden_input, CsrfToken, CsrfTokenProvider};
+    use crate::prelude::*;
+
+    struct Session;
+
+    impl CsrfTokenProvider for Session {
+        fn csrf_token(&self) -> Result<String> {
+            Ok("a\"b".to_string())
+        }
+
+        fn csrf_field_name(&self) -> &str {
+            "_token"
+        }
+    }
+
+    #[test]
+    fn it_renders_hidden_inputs() {
+        assert_eq!(
+            hidden_input(&CsrfToken("abc".to_string())).unwrap(),
+            "<input type=\"hidden\" name=\"csrf_token\" value=\"abc\">"
+        );
+        assert_eq!(hidden_input(&&Session).unwrap(), "<input type=\"hidden\" name=\"_token\" value=\"a&quot;b\">");
+    }
+}
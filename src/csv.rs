@@ -0,0 +1,106 @@
+// CSV and TSV templates, e.g. data exports. Templates ending with `.csv.plt` or `.tsv.plt` write
+// their echoes as fields using `Escaping::Csv`, whatever the escaping of the other templates:
+//
+//     <?rs @args users: &[User] ?>
+//     name,email
+//     <?rs @for user in users ?>
+//     <?= user.name ?>,<?= user.email ?>
+//     <?rs @endfor ?>
+//
+// Fields containing the delimiter, a quote or a line break are quoted, with their quotes doubled.
+// The delimiter of `.tsv.plt` templates is a tab, and the one of `.csv.plt` templates a comma
+// unless `GeneratorOptions::csv_delimiter` is set, e.g. to `;` for spreadsheets in locales using
+// decimal commas. Records end with `\r\n` as RFC 4180 specifies, whatever the line breaks of the
+// template, and the text isn't minified nor checked against HTML rules.
+//
+// Spreadsheets run fields starting with `=`, `+`, `-`, `@`, a tab or a carriage return as
+// formulas, which can e.g. exfiltrate other cells with `=HYPERLINK(...)`. Echoes of values which
+// users control should be wrapped in `NoFormula` when the file is meant to be opened in one:
+//
+//     <?= plt::csv::NoFormula(&user.name) ?>
+use std::fmt;
+use crate::prelude::*;
+
+// Byte order mark, which e.g. Excel needs to read CSV files as UTF-8 rather than in the encoding
+// of the locale, written at the start of the file by `<?= plt::csv::BOM ?>`.
+pub const BOM: &str = "\u{feff}";
+
+// First characters of the fields which spreadsheets run as formulas.
+const FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+// Displays the wrapped value with a `'` before it when it starts like a formula, so spreadsheets
+// show it as text. It's opt-in, as other readers of the file keep the `'`, e.g. of `'-5`.
+#[derive(Debug, Clone, Copy)]
+pub struct NoFormula<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for NoFormula<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.0.to_string();
+        if value.starts_with(FORMULA_PREFIXES) {
+            f.write_str("'")?;
+        }
+
+        f.write_str(&value)
+    }
+}
+
+// Whether the template under `path` renders CSV or TSV, e.g. `exports/users.csv.plt`.
+pub fn is_csv_template(path: &str) -> bool {
+    let file = path.split('#').next().unwrap_or(path);
+
+    file.ends_with(".csv.plt") || file.ends_with(".tsv.plt")
+}
+
+// Delimiter of the fields of the template under `path`, `csv_delimiter` unless it renders TSV.
+pub fn delimiter(path: &str, csv_delimiter: char) -> char {
+    match path.split('#').next().unwrap_or(path).ends_with(".tsv.plt") {
+        true => '\t',
+        false => csv_delimiter,
+    }
+}
+
+// `parts` with the line breaks of their text written as `\r\n`.
+pub fn crlf_line_breaks(parts: Vec<Part>) -> Vec<Part> {
+    parts
+        .into_iter()
+        .map(|part| match part {
+            Part::Text(text) => Part::Text(text.replace("\r\n", "\n").replace('\n', "\r\n")),
+            part => part,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::csv::{delimiter, is_csv_template, NoFormula};
+    use crate::prelude::*;
+
+    #[test]
+    fn it_detects_csv_templates() {
+        assert!(is_csv_template("exports/users.csv.plt"));
+        assert!(is_csv_template("users.tsv.plt#row"));
+        assert!(!is_csv_template("csv.plt"));
+        assert_eq!(delimiter("users.tsv.plt", ';'), '\t');
+        assert_eq!(delimiter("users.csv.plt", ';'), ';');
+    }
+
+    #[test]
+    fn it_neutralizes_formulas() {
+        assert_eq!(escape_csv(NoFormula("=HYPERLINK(\"https://evil.example\")"), ','), "\"'=HYPERLINK(\"\"https://evil.example\"\")\"");
+        assert_eq!(escape_csv(NoFormula("@SUM(A1)"), ','), "'@SUM(A1)");
+        assert_eq!(escape_csv(NoFormula("\t-1"), '\t'), "\"'\t-1\"");
+        assert_eq!(escape_csv(NoFormula("Ann"), ','), "Ann");
+        assert_eq!(escape_csv("=1+1", ','), "=1+1");
+    }
+
+    #[test]
+    fn it_generates_csv_templates() {
+        let mut set = TemplateSet::new();
+        set.add_template("users.csv.plt", vec!["names: &[&str]".to_string()], "name\n<?rs @for name in names ?><?= name ?>\n<?rs @endfor ?>".to_string());
+        let options = GeneratorOptions { escaping: Escaping::Html, csv_delimiter: ';', minify: Minify::Full, ..GeneratorOptions::default() };
+
+        let code = format_code(&set.generate_with("users.csv.plt", &options).unwrap().join("\n"));
+        assert!(code.contains("write!(output_buffer, \"{}\", \"name\\r\\n\")?;"), "{code}");
+        assert!(code.contains("write!(output_buffer, \"{}\", plt::prelude::EscapeCsv(& (name), ';'))?;"), "{code}");
+    }
+}
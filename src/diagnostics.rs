@@ -0,0 +1,381 @@
+// Errors of templates with their location, which can be written as JSON for editors and CI,
+// e.g. by `plt check --message-format json`:
+//
+//     {"file":"index.plt","span":{"start":31,"end":45,"line":3,"column":5,"end_line":3,"end_column":19},
+//      "severity":"error","code":"invalid-template","message":"`@endmatch` found without matching `@match`",
+//      "suggestion":null}
+//
+// Errors raised at a location in a template are `Diagnostic`s within the `anyhow::Error`, so
+// their message stays the same, e.g. `index.plt:3:5: ...`, while they can still be turned back
+// into a `Diagnostic`.
+//
+// In terminals they are rendered along with the excerpt of the template they point at:
+//
+//     error[unterminated-tag]: unterminated tag, expected `?>`
+//      --> index.plt:3:5
+//       |
+//     3 |     <?rs /* ?>
+//       |     ^^^^^^^^^^
+//       |
+//       = help: `?>` found inside a block comment at 3:13; close the comment or escape the tag
+use std::fmt;
+use std::str::FromStr;
+use anyhow::bail;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+// Byte range of a template's source, along with the 1-based line and column (in chars) of its
+// start and end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl Span {
+    pub fn new(source: &str, start: usize, end: usize) -> Span {
+        let (line, column) = line_column(source, start);
+        let (end_line, end_column) = line_column(source, end);
+
+        Span { start, end, line, column, end_line, end_column }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"start\":{},\"end\":{},\"line\":{},\"column\":{},\"end_line\":{},\"end_column\":{}}}",
+            self.start, self.end, self.line, self.column, self.end_line, self.end_column
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    pub span: Option<Span>,
+    pub severity: Severity,
+    // Kind of the diagnostic, e.g. `unterminated-tag`.
+    pub code: String,
+    pub message: String,
+    // How to fix the problem, e.g. "close the tag with `?>`".
+    pub suggestion: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(file: impl Into<String>, span: Option<Span>, code: impl Into<String>, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            file: file.into(),
+            span,
+            severity: Severity::Error,
+            code: code.into(),
+            message: message.into(),
+            suggestion: None,
+        }
+    }
+
+    pub fn with_suggestion(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+
+    // Diagnostic of an error of the template under `file`, either the one it was raised as or
+    // one without a location, with the messages of the errors which caused it.
+    pub fn from_error(file: &str, error: &anyhow::Error) -> Diagnostic {
+        let raised = error.downcast_ref::<Diagnostic>().filter(|diagnostic| diagnostic.to_string() == error.to_string());
+        if let Some(diagnostic) = raised {
+            let mut diagnostic = diagnostic.clone();
+            for cause in error.chain().skip(1) {
+                diagnostic.message.push_str(&format!(": {cause}"));
+            }
+            return diagnostic;
+        }
+
+        let message = format!("{error:#}");
+        let message = match message.strip_prefix(file).and_then(|message| message.strip_prefix(": ")) {
+            Some(message) => message.to_string(),
+            None => message,
+        };
+
+        Diagnostic::error(file, None, "error", message)
+    }
+
+    pub fn to_json(&self) -> String {
+        let span = match &self.span {
+            Some(span) => span.to_json(),
+            None => "null".to_string(),
+        };
+        let suggestion = match &self.suggestion {
+            Some(suggestion) => json_string(suggestion),
+            None => "null".to_string(),
+        };
+
+        format!(
+            "{{\"file\":{},\"span\":{span},\"severity\":\"{}\",\"code\":{},\"message\":{},\"suggestion\":{suggestion}}}",
+            json_string(&self.file),
+            self.severity.name(),
+            json_string(&self.code),
+            json_string(&self.message),
+        )
+    }
+
+    // Diagnostic along with the excerpt of `source` it points at, with its span underlined,
+    // using ANSI colors when `color` is set.
+    pub fn render(&self, source: Option<&str>, color: bool) -> String {
+        let paint = |code: &str, text: &str| if color { format!("\x1b[{code}m{text}\x1b[0m") } else { text.to_string() };
+        let severity_color = match self.severity {
+            Severity::Error => "1;31",
+            Severity::Warning => "1;33",
+        };
+
+        let mut heading = self.severity.name().to_string();
+        if self.code != "error" {
+            heading.push_str(&format!("[{}]", self.code));
+        }
+        let mut rendered = format!("{}{}\n", paint(severity_color, &heading), paint("1", &format!(": {}", self.message)));
+
+        let excerpt = self.span.and_then(|span| Some((span, source?.lines().nth(span.line - 1)?)));
+        let Some((span, line)) = excerpt else {
+            let location = match &self.span {
+                Some(span) => format!("{}:{}:{}", self.file, span.line, span.column),
+                None => self.file.clone(),
+            };
+            rendered.push_str(&format!("{} {location}\n", paint("1;34", "-->")));
+            if let Some(suggestion) = &self.suggestion {
+                rendered.push_str(&format!("{} {suggestion}\n", paint("1", "= help:")));
+            }
+            return rendered;
+        };
+
+        let number = span.line.to_string();
+        let gutter = " ".repeat(number.len());
+        let bar = paint("1;34", "|");
+
+        // Columns count chars, with tabs expanded so the underline stays below the span.
+        let chars: Vec<char> = line.chars().collect();
+        let width = |chars: &[char]| chars.iter().map(|c| if *c == '\t' { 4 } else { 1 }).sum::<usize>();
+        let start = (span.column - 1).min(chars.len());
+        let end = if span.end_line == span.line { (span.end_column - 1).clamp(start, chars.len()) } else { chars.len() };
+        let underline = format!("{}{}", " ".repeat(width(&chars[..start])), "^".repeat(width(&chars[start..end]).max(1)));
+
+        rendered.push_str(&format!("{gutter}{} {}:{}:{}\n", paint("1;34", "-->"), self.file, span.line, span.column));
+        rendered.push_str(&format!("{gutter} {bar}\n"));
+        rendered.push_str(&format!("{} {bar} {}\n", paint("1;34", &number), line.replace('\t', "    ")));
+        rendered.push_str(&format!("{gutter} {bar} {}\n", paint(severity_color, &underline)));
+        if let Some(suggestion) = &self.suggestion {
+            rendered.push_str(&format!("{gutter} {bar}\n"));
+            rendered.push_str(&format!("{gutter} {} {suggestion}\n", paint("1", "= help:")));
+        }
+
+        rendered
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.span {
+            Some(span) => write!(f, "{}:{}:{}: {}", self.file, span.line, span.column, self.message),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagnostics {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Self::default()
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    // JSON array of the diagnostics.
+    pub fn to_json(&self) -> String {
+        let diagnostics: Vec<_> = self.iter().map(Diagnostic::to_json).collect();
+
+        format!("[{}]", diagnostics.join(","))
+    }
+}
+
+impl Extend<Diagnostic> for Diagnostics {
+    fn extend<I: IntoIterator<Item = Diagnostic>>(&mut self, diagnostics: I) {
+        self.diagnostics.extend(diagnostics);
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.diagnostics.into_iter()
+    }
+}
+
+impl FromIterator<Diagnostic> for Diagnostics {
+    fn from_iter<I: IntoIterator<Item = Diagnostic>>(diagnostics: I) -> Self {
+        Diagnostics { diagnostics: diagnostics.into_iter().collect() }
+    }
+}
+
+// A diagnostic per line.
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, diagnostic) in self.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{diagnostic}")?;
+        }
+
+        Ok(())
+    }
+}
+
+// How the command line interface writes diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MessageFormat {
+    #[default]
+    Human,
+    // A JSON object per line, see `Diagnostic::to_json`.
+    Json,
+}
+
+impl FromStr for MessageFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<MessageFormat> {
+        match format {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => bail!("unknown message format `{format}`, expected `human` or `json`"),
+        }
+    }
+}
+
+pub(crate) fn json_string(value: &str) -> String {
+    let mut json = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+    use crate::prelude::*;
+
+    #[test]
+    fn it_writes_diagnostics_as_json() {
+        let span = Span::new("<p>\n  <?= \"a\" ?>", 6, 16);
+        let diagnostic = Diagnostic::error("index.plt", Some(span), "unterminated-tag", "unterminated tag, expected `?>`")
+            .with_suggestion("close the tag with `?>`");
+
+        assert_eq!(diagnostic.to_string(), "index.plt:2:3: unterminated tag, expected `?>`");
+        assert_eq!(
+            diagnostic.to_json(),
+            "{\"file\":\"index.plt\",\"span\":{\"start\":6,\"end\":16,\"line\":2,\"column\":3,\"end_line\":2,\"end_column\":13},\
+             \"severity\":\"error\",\"code\":\"unterminated-tag\",\"message\":\"unterminated tag, expected `?>`\",\
+             \"suggestion\":\"close the tag with `?>`\"}"
+        );
+
+        let diagnostics: Diagnostics = [Diagnostic::error("a\"b.plt", None, "error", "line\nbreak")].into_iter().collect();
+        assert_eq!(
+            diagnostics.to_json(),
+            "[{\"file\":\"a\\\"b.plt\",\"span\":null,\"severity\":\"error\",\"code\":\"error\",\"message\":\"line\\nbreak\",\"suggestion\":null}]"
+        );
+    }
+
+    #[test]
+    fn it_renders_diagnostics_with_the_source() {
+        let source = "<p>\n\t<?rs /* ?>\n</p>";
+        let mut set = TemplateSet::new();
+        set.add_template("index.plt", Vec::new(), source.to_string());
+        let error = set.generate_function("index.plt", &GeneratorOptions::default()).unwrap_err();
+        let diagnostic = Diagnostic::from_error("index.plt", &error);
+
+        assert_eq!(
+            diagnostic.render(Some(source), false),
+            "error[unterminated-tag]: unterminated tag, expected `?>`\n\
+             \x20--> index.plt:2:2\n\
+             \x20 |\n\
+             2 |     <?rs /* ?>\n\
+             \x20 |     ^^^^^^^^^^\n\
+             \x20 |\n\
+             \x20 = help: `?>` found inside a block comment at 2:10; close the comment or escape the tag\n"
+        );
+
+        let diagnostic = Diagnostic::error("index.plt", Some(Span::new("a\nbc", 2, 3)), "error", "oops");
+        assert_eq!(diagnostic.render(Some("a\nbc"), false), "error: oops\n --> index.plt:2:1\n  |\n2 | bc\n  | ^\n");
+        assert_eq!(diagnostic.render(None, false), "error: oops\n--> index.plt:2:1\n");
+        assert!(diagnostic.render(None, true).starts_with("\x1b[1;31merror\x1b[0m\x1b[1m: oops\x1b[0m\n"));
+    }
+
+    #[test]
+    fn it_turns_errors_into_diagnostics() {
+        let located = anyhow::Error::new(Diagnostic::error("index.plt", Some(Span::new("abc", 1, 2)), "invalid-template", "oops"));
+        assert_eq!(located.to_string(), "index.plt:1:2: oops");
+        assert_eq!(Diagnostic::from_error("index.plt", &located).code, "invalid-template");
+
+        let invalid_rust = Diagnostic::error("index.plt", None, "invalid-rust", "generated code is not valid Rust");
+        let caused = Err::<(), _>(anyhow!("expected `}}`")).context(invalid_rust).unwrap_err();
+        let diagnostic = Diagnostic::from_error("index.plt", &caused);
+        assert_eq!((diagnostic.span, diagnostic.code.as_str()), (None, "invalid-rust"));
+        assert_eq!(diagnostic.to_string(), "index.plt: generated code is not valid Rust: expected `}`");
+
+        let unlocated = anyhow!("template `index.plt` includes `missing.plt`");
+        let diagnostic = Diagnostic::from_error("index.plt", &unlocated);
+        assert_eq!(diagnostic.code, "error");
+        assert_eq!(diagnostic.to_string(), "index.plt: template `index.plt` includes `missing.plt`");
+    }
+}
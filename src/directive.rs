@@ -0,0 +1,939 @@
+use std::fmt;
+use anyhow::{bail, Result};
+use rustc_lexer::{LiteralKind, TokenKind};
+use proc_macro2::{TokenStream, TokenTree};
+use quote::ToTokens;
+use crate::const_eval::evaluate_const;
+use crate::lint::{Lint, LintLevel};
+use crate::text_code_fsa::Part;
+
+// Directives are code parts starting with `@`, e.g. `<?rs @match status ?>`.
+//
+// They are not emitted verbatim, instead the generator lowers them to Rust code.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Directive {
+    Match(String),
+    When(String),
+    EndMatch,
+    Let(String),
+    Capture(String),
+    EndCapture,
+    Include { path: String, args: String },
+    Extends { path: String, args: String },
+    Block(String),
+    EndBlock,
+    Yield(String),
+    Call { path: String, args: Vec<(String, String)> },
+    EndCall,
+    Slot,
+    Args(Vec<String>),
+    If(String),
+    ElseIf(String),
+    Else,
+    EndIf,
+    For { pattern: String, iterable: String },
+    EndFor,
+    // `@t("key", name = value)` renders the translation of `key`.
+    Translate { key: String, args: Vec<(String, String)> },
+    // `@t_plural("key", count, name = value)` renders the translation of `key` for `count`.
+    TranslatePlural { key: String, count: String, args: Vec<(String, String)> },
+    // `@asset "css/app.css"` renders the URL of the fingerprinted copy of an asset.
+    Asset(String),
+    // `@asset_sri "js/app.js"` renders the URL attribute of an asset along with its `integrity`.
+    AssetSri(String),
+    // `@nonce` renders the `nonce` attribute of inline scripts and styles.
+    Nonce,
+    // `@csrf` renders a hidden input holding the CSRF token.
+    Csrf,
+    // `@json value` renders the value as JSON which is safe inside of `<script>` tags.
+    Json(String),
+    // `@url posts.show(id = post.id)` renders the URL of a named route.
+    Url { route: String, args: Vec<(String, String)> },
+    // `@dump value` renders the debug representation of the value in debug builds.
+    Dump(String),
+    // `@try` renders a section, which is replaced by the section after `@rescue error` if it fails.
+    Try,
+    Rescue(Option<String>),
+    EndTry,
+    // `@cache("sidebar", ttl = 60)` renders a section through the fragment cache, expiring
+    // after `ttl` seconds if given.
+    Cache { key: String, ttl: Option<String> },
+    EndCache,
+    // `@const 60 * 60` renders the value of a constant expression evaluated during generation.
+    Const(String),
+    // `@env "NAME"` renders the value of an environment variable during generation.
+    Env(String),
+    // `@built_at` renders the time of the build.
+    BuiltAt,
+    // `@git_sha` renders the commit the templates are generated from.
+    GitSha,
+    // `@cfg(feature = "premium")` renders a section only in builds matching the predicate, with
+    // the section after `@else` rendered in the others.
+    Cfg(String),
+    EndCfg,
+    // `@template badge(label: &str)` starts a section generated as a function of its own, until
+    // `@endtemplate`, so small related templates can live in a single file.
+    Template { name: String, args: Vec<String> },
+    EndTemplate,
+    // `@def badge(label: &str)` defines a macro until `@enddef`, which is a section like the ones
+    // of `@template`, rendered with `@use badge("New")` from any template of the set.
+    Def { name: String, args: Vec<String> },
+    EndDef,
+    Use { name: String, args: String },
+    // `@allow(unused_parameters)`, `@warn(...)` and `@deny(...)` set the level of lints for the
+    // whole template.
+    Lint { level: LintLevel, lints: Vec<Lint> },
+    // `@example { "title": "Hello" }` holds sample arguments of the template as JSON, rendered by
+    // `plt doc`. It renders nothing.
+    Example(String),
+}
+
+impl Directive {
+    // Name of the directive as written after the `@`, e.g. `else if` for `@else if`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Directive::Match(_) => "match",
+            Directive::When(_) => "when",
+            Directive::EndMatch => "endmatch",
+            Directive::Let(_) => "let",
+            Directive::Capture(_) => "capture",
+            Directive::EndCapture => "endcapture",
+            Directive::Include { .. } => "include",
+            Directive::Extends { .. } => "extends",
+            Directive::Block(_) => "block",
+            Directive::EndBlock => "endblock",
+            Directive::Yield(_) => "yield",
+            Directive::Call { .. } => "call",
+            Directive::EndCall => "endcall",
+            Directive::Slot => "slot",
+            Directive::Args(_) => "args",
+            Directive::If(_) => "if",
+            Directive::ElseIf(_) => "else if",
+            Directive::Else => "else",
+            Directive::EndIf => "endif",
+            Directive::For { .. } => "for",
+            Directive::EndFor => "endfor",
+            Directive::Translate { .. } => "t",
+            Directive::TranslatePlural { .. } => "t_plural",
+            Directive::Asset(_) => "asset",
+            Directive::AssetSri(_) => "asset_sri",
+            Directive::Nonce => "nonce",
+            Directive::Csrf => "csrf",
+            Directive::Json(_) => "json",
+            Directive::Url { .. } => "url",
+            Directive::Dump(_) => "dump",
+            Directive::Try => "try",
+            Directive::Rescue(_) => "rescue",
+            Directive::EndTry => "endtry",
+            Directive::Cache { .. } => "cache",
+            Directive::EndCache => "endcache",
+            Directive::Const(_) => "const",
+            Directive::Env(_) => "env",
+            Directive::BuiltAt => "built_at",
+            Directive::GitSha => "git_sha",
+            Directive::Cfg(_) => "cfg",
+            Directive::EndCfg => "endcfg",
+            Directive::Template { .. } => "template",
+            Directive::EndTemplate => "endtemplate",
+            Directive::Def { .. } => "def",
+            Directive::EndDef => "enddef",
+            Directive::Use { .. } => "use",
+            Directive::Lint { level, .. } => level.name(),
+            Directive::Example(_) => "example",
+        }
+    }
+
+    // Parses the content of a code part. Returns `None` when the part is plain Rust code.
+    pub fn parse(code: &str) -> Result<Option<Directive>> {
+        let code = code.trim();
+
+        let Some(rest) = code.strip_prefix('@') else {
+            return Ok(None);
+        };
+
+        let name_len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let (name, argument) = rest.split_at(name_len);
+        let argument = argument.trim();
+
+        let directive = match name {
+            "match" => Directive::Match(Self::required_argument(name, argument)?),
+            "when" => Directive::When(Self::required_argument(name, argument)?),
+            "endmatch" => {
+                Self::no_argument(name, argument)?;
+                Directive::EndMatch
+            }
+            "let" => {
+                let binding = Self::required_argument(name, argument)?;
+                Directive::Let(binding.trim_end_matches(';').trim_end().to_string())
+            }
+            "capture" => Directive::Capture(Self::identifier_argument(name, argument)?),
+            "endcapture" => {
+                Self::no_argument(name, argument)?;
+                Directive::EndCapture
+            }
+            "include" => {
+                let (path, rest) = Self::string_literal_argument(name, argument)?;
+                Directive::Include { path, args: Self::with_arguments(name, rest)? }
+            }
+            "extends" => {
+                let (path, rest) = Self::string_literal_argument(name, argument)?;
+                Directive::Extends { path, args: Self::with_arguments(name, rest)? }
+            }
+            "block" => Directive::Block(Self::identifier_argument(name, argument)?),
+            "endblock" => {
+                Self::no_argument(name, argument)?;
+                Directive::EndBlock
+            }
+            "yield" => Directive::Yield(Self::identifier_argument(name, argument)?),
+            "call" => {
+                let (path, rest) = Self::string_literal_argument(name, argument)?;
+                Directive::Call { path, args: Self::named_arguments(name, rest)? }
+            }
+            "endcall" => {
+                Self::no_argument(name, argument)?;
+                Directive::EndCall
+            }
+            "slot" => {
+                Self::no_argument(name, argument)?;
+                Directive::Slot
+            }
+            "nonce" => {
+                Self::no_argument(name, argument)?;
+                Directive::Nonce
+            }
+            "json" => Directive::Json(Self::required_argument(name, argument)?),
+            "env" => {
+                let (variable, rest) = Self::string_literal_argument(name, argument)?;
+                Self::no_argument(name, rest)?;
+                Directive::Env(variable)
+            }
+            "built_at" => {
+                Self::no_argument(name, argument)?;
+                Directive::BuiltAt
+            }
+            "git_sha" => {
+                Self::no_argument(name, argument)?;
+                Directive::GitSha
+            }
+            "dump" => Directive::Dump(Self::required_argument(name, argument)?),
+            "const" => {
+                let code = Self::required_argument(name, argument)?;
+                if evaluate_const(&code).is_none() {
+                    bail!("directive `@{name}` expects a constant expression, found `{code}`");
+                }
+                Directive::Const(code)
+            }
+            "try" => {
+                Self::no_argument(name, argument)?;
+                Directive::Try
+            }
+            "rescue" if argument.is_empty() => Directive::Rescue(None),
+            "rescue" => Directive::Rescue(Some(Self::identifier_argument(name, argument)?)),
+            "cache" => {
+                let (key, ttl) = Self::cache_arguments(name, argument)?;
+                Directive::Cache { key, ttl }
+            }
+            "endcache" => {
+                Self::no_argument(name, argument)?;
+                Directive::EndCache
+            }
+            "cfg" => Directive::Cfg(Self::cfg_argument(name, argument)?),
+            "endcfg" => {
+                Self::no_argument(name, argument)?;
+                Directive::EndCfg
+            }
+            "endtry" => {
+                Self::no_argument(name, argument)?;
+                Directive::EndTry
+            }
+            "csrf" => {
+                Self::no_argument(name, argument)?;
+                Directive::Csrf
+            }
+            "url" => {
+                let (route, rest) = Self::route_argument(name, argument)?;
+                Directive::Url { route, args: Self::named_arguments(name, rest)? }
+            }
+            "template" => {
+                let (template_name, args) = Self::template_arguments(name, argument)?;
+                Directive::Template { name: template_name, args }
+            }
+            "endtemplate" => {
+                Self::no_argument(name, argument)?;
+                Directive::EndTemplate
+            }
+            "def" => {
+                let (macro_name, args) = Self::template_arguments(name, argument)?;
+                Directive::Def { name: macro_name, args }
+            }
+            "enddef" => {
+                Self::no_argument(name, argument)?;
+                Directive::EndDef
+            }
+            "use" => {
+                let (macro_name, args) = Self::use_arguments(name, argument)?;
+                Directive::Use { name: macro_name, args }
+            }
+            "allow" | "warn" | "deny" => Directive::Lint { level: name.parse()?, lints: Self::lint_arguments(name, argument)? },
+            "args" => Directive::Args(Self::fn_arguments(name, argument)?),
+            "example" => Directive::Example(Self::required_argument(name, argument)?),
+            "if" => Directive::If(Self::required_argument(name, argument)?),
+            "else" => match argument.strip_prefix("if") {
+                Some(condition) if condition.starts_with(char::is_whitespace) => {
+                    Directive::ElseIf(Self::required_argument("else if", condition.trim())?)
+                }
+                _ => {
+                    Self::no_argument(name, argument)?;
+                    Directive::Else
+                }
+            },
+            "endif" => {
+                Self::no_argument(name, argument)?;
+                Directive::EndIf
+            }
+            "for" => {
+                let (pattern, iterable) = Self::for_arguments(name, argument)?;
+                Directive::For { pattern, iterable }
+            }
+            "endfor" => {
+                Self::no_argument(name, argument)?;
+                Directive::EndFor
+            }
+            "t" => {
+                let (key, args) = Self::translation_arguments(name, argument)?;
+                Directive::Translate { key, args: Self::named_argument_list(name, args)? }
+            }
+            "t_plural" => {
+                let (key, mut args) = Self::translation_arguments(name, argument)?;
+                if args.is_empty() {
+                    bail!("directive `@{name}` expects a count after the key");
+                }
+                let count = args.remove(0).into_iter().collect::<TokenStream>().to_string();
+                Directive::TranslatePlural { key, count, args: Self::named_argument_list(name, args)? }
+            }
+            "asset" | "asset_sri" => {
+                let (path, rest) = Self::string_literal_argument(name, argument)?;
+                Self::no_argument(name, rest)?;
+                match name {
+                    "asset" => Directive::Asset(path),
+                    _ => Directive::AssetSri(path),
+                }
+            }
+            _ => bail!("unknown directive `@{name}`"),
+        };
+
+        Ok(Some(directive))
+    }
+
+    fn required_argument(name: &str, argument: &str) -> Result<String> {
+        if argument.is_empty() {
+            bail!("directive `@{name}` requires an argument");
+        }
+
+        Ok(argument.to_string())
+    }
+
+    fn identifier_argument(name: &str, argument: &str) -> Result<String> {
+        let argument = Self::required_argument(name, argument)?;
+
+        if syn::parse_str::<syn::Ident>(&argument).is_err() {
+            bail!("directive `@{name}` expects an identifier, found `{argument}`");
+        }
+
+        Ok(argument)
+    }
+
+    // Parses the `(unused_parameters, long_text)` list of lints.
+    fn lint_arguments(name: &str, argument: &str) -> Result<Vec<Lint>> {
+        let Some(lints) = argument.strip_prefix('(').and_then(|lints| lints.strip_suffix(')')) else {
+            bail!("directive `@{name}` expects a list of lints, e.g. `@{name}(unused_parameters)`, found `{argument}`");
+        };
+
+        lints
+            .split(',')
+            .map(str::trim)
+            .filter(|lint| !lint.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+
+    // Parses all directives found in the code parts, skipping plain code and text.
+    pub fn parse_all(parts: &[Part]) -> Result<Vec<Directive>> {
+        let mut directives = Vec::new();
+
+        for part in parts {
+            if let Part::Code(code) = part {
+                if let Some(directive) = Directive::parse(code)? {
+                    directives.push(directive);
+                }
+            }
+        }
+
+        Ok(directives)
+    }
+
+    // Splits a leading string literal, e.g. `"partials/header.plt" with (title)`, off the argument.
+    fn string_literal_argument<'a>(name: &str, argument: &'a str) -> Result<(String, &'a str)> {
+        if argument.is_empty() {
+            bail!("directive `@{name}` expects a string literal");
+        }
+
+        let token = rustc_lexer::first_token(argument);
+
+        let is_string = matches!(
+            token.kind,
+            TokenKind::Literal { kind: LiteralKind::Str { terminated: true }, .. }
+        );
+        if !is_string {
+            bail!("directive `@{name}` expects a string literal, found `{argument}`");
+        }
+
+        let (literal, rest) = argument.split_at(token.len);
+        let literal = syn::parse_str::<syn::LitStr>(literal)?;
+
+        Ok((literal.value(), rest.trim()))
+    }
+
+    // Splits a leading route name, e.g. `posts.show(id = 1)`, off the argument.
+    fn route_argument<'a>(name: &str, argument: &'a str) -> Result<(String, &'a str)> {
+        let route_len = argument
+            .find(|c: char| !(c.is_alphanumeric() || "_.:-".contains(c)))
+            .unwrap_or(argument.len());
+        if route_len == 0 {
+            bail!("directive `@{name}` expects a route name, found `{argument}`");
+        }
+
+        let (route, rest) = argument.split_at(route_len);
+        Ok((route.to_string(), rest.trim()))
+    }
+
+    // Parses the optional `with (a, b)` argument list. Returns the list without parentheses.
+    fn with_arguments(name: &str, rest: &str) -> Result<String> {
+        if rest.is_empty() {
+            return Ok(String::new());
+        }
+
+        let args = rest
+            .strip_prefix("with")
+            .map(str::trim)
+            .and_then(|args| args.strip_prefix('('))
+            .and_then(|args| args.strip_suffix(')'));
+
+        let Some(args) = args else {
+            bail!("directive `@{name}` expects `with (...)`, found `{rest}`");
+        };
+
+        Ok(args.trim().to_string())
+    }
+
+    // Parses the optional `(name = value, ...)` argument list.
+    fn named_arguments(name: &str, rest: &str) -> Result<Vec<(String, String)>> {
+        if rest.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let Some(args) = rest.strip_prefix('(').and_then(|args| args.strip_suffix(')')) else {
+            bail!("directive `@{name}` expects `(name = value, ...)`, found `{rest}`");
+        };
+
+        Self::named_argument_list(name, Self::split_arguments(name, args)?)
+    }
+
+    // Splits arguments at top-level commas, skipping empty ones like the one after a trailing comma.
+    fn split_arguments(name: &str, args: &str) -> Result<Vec<Vec<TokenTree>>> {
+        let Ok(tokens) = args.parse::<TokenStream>() else {
+            bail!("directive `@{name}` has malformed arguments `{args}`");
+        };
+
+        let mut segments: Vec<Vec<TokenTree>> = vec![Vec::new()];
+        for token in tokens {
+            match token {
+                TokenTree::Punct(punct) if punct.as_char() == ',' => segments.push(Vec::new()),
+                token => segments.last_mut().unwrap().push(token),
+            }
+        }
+        segments.retain(|segment| !segment.is_empty());
+
+        Ok(segments)
+    }
+
+    // Parses the key and the remaining arguments of `("key", ...)`.
+    fn translation_arguments(name: &str, argument: &str) -> Result<(String, Vec<Vec<TokenTree>>)> {
+        let Some(args) = argument.strip_prefix('(').and_then(|args| args.strip_suffix(')')) else {
+            bail!("directive `@{name}` expects `(\"key\", ...)`, found `{argument}`");
+        };
+
+        let mut args = Self::split_arguments(name, args)?;
+        let key = match args.first().map(Vec::as_slice) {
+            Some([TokenTree::Literal(literal)]) => syn::parse_str::<syn::LitStr>(&literal.to_string()).ok(),
+            _ => None,
+        };
+        let Some(key) = key else {
+            bail!("directive `@{name}` expects a string literal key, found `{argument}`");
+        };
+        args.remove(0);
+
+        Ok((key.value(), args))
+    }
+
+    // Parses the predicate of `(predicate)`, e.g. `(all(unix, feature = "premium"))`.
+    fn cfg_argument(name: &str, argument: &str) -> Result<String> {
+        let predicate = argument.strip_prefix('(').and_then(|predicate| predicate.strip_suffix(')'));
+        let Some(Ok(predicate)) = predicate.map(syn::parse_str::<syn::Meta>) else {
+            bail!("directive `@{name}` expects `(predicate)`, found `{argument}`");
+        };
+
+        Ok(predicate.to_token_stream().to_string())
+    }
+
+    // Parses the key and the optional `ttl` of `(key, ttl = seconds)`.
+    fn cache_arguments(name: &str, argument: &str) -> Result<(String, Option<String>)> {
+        let Some(args) = argument.strip_prefix('(').and_then(|args| args.strip_suffix(')')) else {
+            bail!("directive `@{name}` expects `(key, ttl = seconds)`, found `{argument}`");
+        };
+
+        let mut args = Self::split_arguments(name, args)?;
+        if args.is_empty() {
+            bail!("directive `@{name}` expects a key");
+        }
+        let key = args.remove(0).into_iter().collect::<TokenStream>().to_string();
+
+        let mut ttl = None;
+        for (arg_name, value) in Self::named_argument_list(name, args)? {
+            if arg_name != "ttl" {
+                bail!("directive `@{name}` only takes a `ttl` after the key, found `{arg_name}`");
+            }
+            if ttl.is_some() {
+                bail!("directive `@{name}` has more than one `ttl`");
+            }
+            ttl = Some(value);
+        }
+
+        Ok((key, ttl))
+    }
+
+    // Parses arguments split by `split_arguments` as `name = value`.
+    fn named_argument_list(name: &str, segments: Vec<Vec<TokenTree>>) -> Result<Vec<(String, String)>> {
+        let mut named_args = Vec::new();
+        for segment in segments {
+            let (arg_name, value) = match segment.as_slice() {
+                [TokenTree::Ident(arg_name), TokenTree::Punct(eq), value @ ..]
+                    if eq.as_char() == '=' && !value.is_empty() =>
+                {
+                    (arg_name.to_string(), value.iter().cloned().collect::<TokenStream>())
+                }
+                _ => {
+                    let segment = segment.into_iter().collect::<TokenStream>();
+                    bail!("directive `@{name}` expects `name = value`, found `{segment}`");
+                }
+            };
+
+            named_args.push((arg_name, value.to_string()));
+        }
+
+        Ok(named_args)
+    }
+
+    // Splits `pattern in iterable` at the first top-level `in`.
+    fn for_arguments(name: &str, argument: &str) -> Result<(String, String)> {
+        let Ok(tokens) = argument.parse::<TokenStream>() else {
+            bail!("directive `@{name}` has a malformed argument `{argument}`");
+        };
+
+        let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+        let position = tokens
+            .iter()
+            .position(|token| matches!(token, TokenTree::Ident(ident) if ident == "in"));
+
+        match position {
+            Some(position) if position > 0 && position + 1 < tokens.len() => {
+                let pattern: TokenStream = tokens[..position].iter().cloned().collect();
+                let iterable: TokenStream = tokens[position + 1..].iter().cloned().collect();
+
+                Ok((pattern.to_string(), iterable.to_string()))
+            }
+            _ => bail!("directive `@{name}` expects `pattern in iterable`, found `{argument}`"),
+        }
+    }
+
+    // Parses function arguments, e.g. `title: &str, users: HashMap<u32, User>`.
+    fn fn_arguments(name: &str, argument: &str) -> Result<Vec<String>> {
+        let Ok(signature) = syn::parse_str::<syn::Signature>(&format!("fn f({argument})")) else {
+            bail!("directive `@{name}` expects function arguments, found `{argument}`");
+        };
+
+        Ok(signature.inputs.iter().map(|input| input.to_token_stream().to_string()).collect())
+    }
+
+    // Parses the name and the optional arguments of `name(label: &str, ...)`.
+    fn template_arguments(name: &str, argument: &str) -> Result<(String, Vec<String>)> {
+        let Some((template_name, args)) = argument.split_once('(') else {
+            return Ok((Self::identifier_argument(name, argument)?, Vec::new()));
+        };
+        let Some(args) = args.trim_end().strip_suffix(')') else {
+            bail!("directive `@{name}` expects `name(arguments)`, found `{argument}`");
+        };
+
+        Ok((Self::identifier_argument(name, template_name.trim())?, Self::fn_arguments(name, args)?))
+    }
+
+    // Parses the name and the optional positional arguments of `name("New", color)`.
+    fn use_arguments(name: &str, argument: &str) -> Result<(String, String)> {
+        let Some((macro_name, args)) = argument.split_once('(') else {
+            return Ok((Self::identifier_argument(name, argument)?, String::new()));
+        };
+        let args = args.trim_end().strip_suffix(')').filter(|args| args.parse::<TokenStream>().is_ok());
+        let Some(args) = args else {
+            bail!("directive `@{name}` expects `name(arguments)`, found `{argument}`");
+        };
+
+        Ok((Self::identifier_argument(name, macro_name.trim())?, args.trim().to_string()))
+    }
+
+    fn no_argument(name: &str, argument: &str) -> Result<()> {
+        if !argument.is_empty() {
+            bail!("directive `@{name}` does not take an argument, found `{argument}`");
+        }
+
+        Ok(())
+    }
+}
+
+// Source of the directive, without the surrounding tag, which parses back to the same directive,
+// e.g. `@include "header.plt" with (title)`.
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let named = |args: &[(String, String)]| args.iter().map(|(name, value)| format!("{name} = {value}")).collect::<Vec<_>>().join(", ");
+
+        write!(f, "@{}", self.name())?;
+        match self {
+            Directive::Match(code)
+            | Directive::When(code)
+            | Directive::Let(code)
+            | Directive::Capture(code)
+            | Directive::Block(code)
+            | Directive::Yield(code)
+            | Directive::If(code)
+            | Directive::ElseIf(code)
+            | Directive::Json(code)
+            | Directive::Dump(code)
+            | Directive::Const(code)
+            | Directive::Example(code)
+            | Directive::Rescue(Some(code)) => write!(f, " {code}"),
+            Directive::Include { path, args } | Directive::Extends { path, args } if args.is_empty() => write!(f, " {path:?}"),
+            Directive::Include { path, args } | Directive::Extends { path, args } => write!(f, " {path:?} with ({args})"),
+            Directive::Call { path, args } if args.is_empty() => write!(f, " {path:?}"),
+            Directive::Call { path, args } => write!(f, " {path:?}({})", named(args)),
+            Directive::Args(args) => write!(f, " {}", args.join(", ")),
+            Directive::For { pattern, iterable } => write!(f, " {pattern} in {iterable}"),
+            Directive::Translate { key, args } if args.is_empty() => write!(f, "({key:?})"),
+            Directive::Translate { key, args } => write!(f, "({key:?}, {})", named(args)),
+            Directive::TranslatePlural { key, count, args } if args.is_empty() => write!(f, "({key:?}, {count})"),
+            Directive::TranslatePlural { key, count, args } => write!(f, "({key:?}, {count}, {})", named(args)),
+            Directive::Asset(path) | Directive::AssetSri(path) | Directive::Env(path) => write!(f, " {path:?}"),
+            Directive::Url { route, args } if args.is_empty() => write!(f, " {route}"),
+            Directive::Url { route, args } => write!(f, " {route}({})", named(args)),
+            Directive::Cache { key, ttl: Some(ttl) } => write!(f, "({key}, ttl = {ttl})"),
+            Directive::Cache { key, ttl: None } => write!(f, "({key})"),
+            Directive::Cfg(predicate) => write!(f, "({predicate})"),
+            Directive::Template { name, args } | Directive::Def { name, args } if args.is_empty() => write!(f, " {name}"),
+            Directive::Template { name, args } | Directive::Def { name, args } => write!(f, " {name}({})", args.join(", ")),
+            Directive::Use { name, args } if args.is_empty() => write!(f, " {name}"),
+            Directive::Use { name, args } => write!(f, " {name}({args})"),
+            Directive::Lint { lints, .. } => {
+                let lints: Vec<_> = lints.iter().map(Lint::name).collect();
+                write!(f, "({})", lints.join(", "))
+            }
+            Directive::EndMatch
+            | Directive::EndCapture
+            | Directive::EndBlock
+            | Directive::EndCall
+            | Directive::Slot
+            | Directive::Else
+            | Directive::EndIf
+            | Directive::EndFor
+            | Directive::Nonce
+            | Directive::Csrf
+            | Directive::Try
+            | Directive::Rescue(None)
+            | Directive::EndTry
+            | Directive::EndCache
+            | Directive::BuiltAt
+            | Directive::GitSha
+            | Directive::EndCfg
+            | Directive::EndTemplate
+            | Directive::EndDef => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::directive::Directive;
+    use crate::lint::{Lint, LintLevel};
+
+    #[test]
+    fn it_ignores_plain_rust_code() {
+        assert_eq!(Directive::parse(" for i in 0..10 { ").unwrap(), None);
+        assert_eq!(Directive::parse(" \"hello @match\" ").unwrap(), None);
+    }
+
+    #[test]
+    fn it_parses_match_directives() {
+        assert_eq!(Directive::parse(" @match status ").unwrap(), Some(Directive::Match("status".to_string())));
+        assert_eq!(Directive::parse("@when Status::Active").unwrap(), Some(Directive::When("Status::Active".to_string())));
+        assert_eq!(Directive::parse(" @endmatch ").unwrap(), Some(Directive::EndMatch));
+    }
+
+    #[test]
+    fn it_parses_let_and_capture_directives() {
+        assert_eq!(
+            Directive::parse(" @let total = items.iter().map(|i| i.price).sum::<u32>() ").unwrap(),
+            Some(Directive::Let("total = items.iter().map(|i| i.price).sum::<u32>()".to_string()))
+        );
+        assert_eq!(Directive::parse("@let x = 1;").unwrap(), Some(Directive::Let("x = 1".to_string())));
+        assert_eq!(Directive::parse(" @capture title ").unwrap(), Some(Directive::Capture("title".to_string())));
+        assert_eq!(Directive::parse(" @endcapture ").unwrap(), Some(Directive::EndCapture));
+    }
+
+    #[test]
+    fn it_parses_include_directives() {
+        assert_eq!(
+            Directive::parse(" @include \"partials/header.plt\" with (title, user) ").unwrap(),
+            Some(Directive::Include { path: "partials/header.plt".to_string(), args: "title, user".to_string() })
+        );
+        assert_eq!(
+            Directive::parse("@include \"footer.plt\"").unwrap(),
+            Some(Directive::Include { path: "footer.plt".to_string(), args: String::new() })
+        );
+    }
+
+    #[test]
+    fn it_parses_layout_directives() {
+        assert_eq!(
+            Directive::parse("@extends \"layout.plt\" with (title)").unwrap(),
+            Some(Directive::Extends { path: "layout.plt".to_string(), args: "title".to_string() })
+        );
+        assert_eq!(Directive::parse("@block content").unwrap(), Some(Directive::Block("content".to_string())));
+        assert_eq!(Directive::parse("@endblock").unwrap(), Some(Directive::EndBlock));
+        assert_eq!(Directive::parse("@yield content").unwrap(), Some(Directive::Yield("content".to_string())));
+    }
+
+    #[test]
+    fn it_parses_component_directives() {
+        assert_eq!(
+            Directive::parse("@call \"components/card.plt\" (title = \"Hi\", count = items.len())").unwrap(),
+            Some(Directive::Call {
+                path: "components/card.plt".to_string(),
+                args: vec![
+                    ("title".to_string(), "\"Hi\"".to_string()),
+                    ("count".to_string(), "items . len ()".to_string()),
+                ],
+            })
+        );
+        assert_eq!(
+            Directive::parse("@call \"modal.plt\"").unwrap(),
+            Some(Directive::Call { path: "modal.plt".to_string(), args: Vec::new() })
+        );
+        assert_eq!(Directive::parse("@endcall").unwrap(), Some(Directive::EndCall));
+        assert_eq!(Directive::parse("@slot").unwrap(), Some(Directive::Slot));
+    }
+
+    #[test]
+    fn it_parses_args_directives() {
+        assert_eq!(
+            Directive::parse("@args title: &str, users: HashMap<u32, User>").unwrap(),
+            Some(Directive::Args(vec!["title : & str".to_string(), "users : HashMap < u32 , User >".to_string()]))
+        );
+    }
+
+    #[test]
+    fn it_parses_example_directives() {
+        assert_eq!(
+            Directive::parse("@example { \"title\": \"Hello\" }").unwrap(),
+            Some(Directive::Example("{ \"title\": \"Hello\" }".to_string()))
+        );
+        assert!(Directive::parse("@example").is_err());
+    }
+
+    #[test]
+    fn it_parses_template_directives() {
+        assert_eq!(
+            Directive::parse("@template badge(label: &str, color: &str)").unwrap(),
+            Some(Directive::Template { name: "badge".to_string(), args: vec!["label : & str".to_string(), "color : & str".to_string()] })
+        );
+        assert_eq!(Directive::parse("@template footer").unwrap(), Some(Directive::Template { name: "footer".to_string(), args: Vec::new() }));
+        assert_eq!(Directive::parse("@endtemplate").unwrap(), Some(Directive::EndTemplate));
+        assert!(Directive::parse("@template badge(label: &str").is_err());
+        assert!(Directive::parse("@template 1(a: u8)").is_err());
+    }
+
+    #[test]
+    fn it_parses_macro_directives() {
+        assert_eq!(
+            Directive::parse("@def badge(label: &str, color: &str)").unwrap(),
+            Some(Directive::Def { name: "badge".to_string(), args: vec!["label : & str".to_string(), "color : & str".to_string()] })
+        );
+        assert_eq!(Directive::parse("@enddef").unwrap(), Some(Directive::EndDef));
+        assert_eq!(
+            Directive::parse("@use badge(\"New\", item.color())").unwrap(),
+            Some(Directive::Use { name: "badge".to_string(), args: "\"New\", item.color()".to_string() })
+        );
+        assert_eq!(Directive::parse("@use divider").unwrap(), Some(Directive::Use { name: "divider".to_string(), args: String::new() }));
+        assert!(Directive::parse("@use badge(\"New\"").is_err());
+    }
+
+    #[test]
+    fn it_parses_lint_directives() {
+        assert_eq!(
+            Directive::parse("@allow(unused_parameters, long_text)").unwrap(),
+            Some(Directive::Lint { level: LintLevel::Allow, lints: vec![Lint::UnusedParameters, Lint::LongText] })
+        );
+        assert!(Directive::parse("@deny unescaped_echo").is_err());
+        assert!(Directive::parse("@warn(unknown)").is_err());
+    }
+
+    #[test]
+    fn it_parses_control_flow_directives() {
+        assert_eq!(Directive::parse("@if user.is_admin").unwrap(), Some(Directive::If("user.is_admin".to_string())));
+        assert_eq!(Directive::parse("@else if count > 1").unwrap(), Some(Directive::ElseIf("count > 1".to_string())));
+        assert_eq!(Directive::parse("@else").unwrap(), Some(Directive::Else));
+        assert_eq!(Directive::parse("@endif").unwrap(), Some(Directive::EndIf));
+        assert_eq!(
+            Directive::parse("@for (index, item) in items.iter().enumerate()").unwrap(),
+            Some(Directive::For { pattern: "(index , item)".to_string(), iterable: "items . iter () . enumerate ()".to_string() })
+        );
+        assert_eq!(Directive::parse("@endfor").unwrap(), Some(Directive::EndFor));
+    }
+
+    #[test]
+    fn it_rejects_malformed_directives() {
+        assert!(Directive::parse("@match").is_err());
+        assert!(Directive::parse("@endmatch status").is_err());
+        assert!(Directive::parse("@unknown").is_err());
+        assert!(Directive::parse("@capture my title").is_err());
+        assert!(Directive::parse("@include footer.plt").is_err());
+        assert!(Directive::parse("@include \"footer.plt\" (title)").is_err());
+        assert!(Directive::parse("@call \"card.plt\" (\"Hi\")").is_err());
+        assert!(Directive::parse("@args title").is_err());
+        assert!(Directive::parse("@else iff").is_err());
+        assert!(Directive::parse("@else if").is_err());
+        assert!(Directive::parse("@for item").is_err());
+        assert!(Directive::parse("@for in items").is_err());
+        assert!(Directive::parse("@t(key)").is_err());
+        assert!(Directive::parse("@t(\"key\", name)").is_err());
+        assert!(Directive::parse("@t_plural(\"key\")").is_err());
+    }
+
+    #[test]
+    fn it_parses_translation_directives() {
+        assert_eq!(
+            Directive::parse("@t(\"checkout.title\")").unwrap(),
+            Some(Directive::Translate { key: "checkout.title".to_string(), args: Vec::new() })
+        );
+        assert_eq!(
+            Directive::parse("@t_plural(\"cart.items\", items.len(), name = user.name,)").unwrap(),
+            Some(Directive::TranslatePlural {
+                key: "cart.items".to_string(),
+                count: "items . len ()".to_string(),
+                args: vec![("name".to_string(), "user . name".to_string())],
+            })
+        );
+    }
+
+    #[test]
+    fn it_parses_nonce_directives() {
+        assert_eq!(Directive::parse(" @nonce ").unwrap(), Some(Directive::Nonce));
+        assert!(Directive::parse("@nonce value").is_err());
+        assert_eq!(Directive::parse("@csrf").unwrap(), Some(Directive::Csrf));
+    }
+
+    #[test]
+    fn it_parses_json_directives() {
+        assert_eq!(Directive::parse("@json state.user").unwrap(), Some(Directive::Json("state.user".to_string())));
+        assert!(Directive::parse("@json").is_err());
+    }
+
+    #[test]
+    fn it_parses_dump_directives() {
+        assert_eq!(Directive::parse("@dump user").unwrap(), Some(Directive::Dump("user".to_string())));
+        assert!(Directive::parse("@dump").is_err());
+    }
+
+    #[test]
+    fn it_parses_try_directives() {
+        assert_eq!(Directive::parse("@try").unwrap(), Some(Directive::Try));
+        assert_eq!(Directive::parse("@rescue").unwrap(), Some(Directive::Rescue(None)));
+        assert_eq!(Directive::parse("@rescue error").unwrap(), Some(Directive::Rescue(Some("error".to_string()))));
+        assert_eq!(Directive::parse("@endtry").unwrap(), Some(Directive::EndTry));
+        assert!(Directive::parse("@try widget").is_err());
+        assert!(Directive::parse("@rescue a b").is_err());
+    }
+
+    #[test]
+    fn it_parses_cache_directives() {
+        assert_eq!(
+            Directive::parse("@cache(\"sidebar\", ttl = 60)").unwrap(),
+            Some(Directive::Cache { key: "\"sidebar\"".to_string(), ttl: Some("60".to_string()) })
+        );
+        assert_eq!(
+            Directive::parse("@cache(format!(\"cart-{}\", user.id))").unwrap(),
+            Some(Directive::Cache { key: "format ! (\"cart-{}\" , user . id)".to_string(), ttl: None })
+        );
+        assert_eq!(Directive::parse("@endcache").unwrap(), Some(Directive::EndCache));
+        assert!(Directive::parse("@cache").is_err());
+        assert!(Directive::parse("@cache()").is_err());
+        assert!(Directive::parse("@cache(\"a\", expires = 1)").is_err());
+    }
+
+    #[test]
+    fn it_parses_const_directives() {
+        assert_eq!(Directive::parse("@const 60 * 60").unwrap(), Some(Directive::Const("60 * 60".to_string())));
+        assert!(Directive::parse("@const user.name").is_err());
+    }
+
+    #[test]
+    fn it_parses_build_constant_directives() {
+        assert_eq!(Directive::parse("@env \"APP_VERSION\"").unwrap(), Some(Directive::Env("APP_VERSION".to_string())));
+        assert_eq!(Directive::parse("@built_at").unwrap(), Some(Directive::BuiltAt));
+        assert_eq!(Directive::parse("@git_sha").unwrap(), Some(Directive::GitSha));
+        assert!(Directive::parse("@env APP_VERSION").is_err());
+        assert!(Directive::parse("@git_sha short").is_err());
+    }
+
+    #[test]
+    fn it_parses_cfg_directives() {
+        assert_eq!(Directive::parse("@cfg(feature = \"premium\")").unwrap(), Some(Directive::Cfg("feature = \"premium\"".to_string())));
+        assert_eq!(Directive::parse("@cfg(not(unix))").unwrap(), Some(Directive::Cfg("not (unix)".to_string())));
+        assert_eq!(Directive::parse("@endcfg").unwrap(), Some(Directive::EndCfg));
+        assert!(Directive::parse("@cfg feature").is_err());
+        assert!(Directive::parse("@cfg(1 + 1)").is_err());
+    }
+
+    #[test]
+    fn it_parses_url_directives() {
+        assert_eq!(
+            Directive::parse("@url posts.show(id = post.id, page = 2)").unwrap(),
+            Some(Directive::Url {
+                route: "posts.show".to_string(),
+                args: vec![("id".to_string(), "post . id".to_string()), ("page".to_string(), "2".to_string())],
+            })
+        );
+        assert_eq!(
+            Directive::parse("@url home").unwrap(),
+            Some(Directive::Url { route: "home".to_string(), args: Vec::new() })
+        );
+        assert!(Directive::parse("@url (id = 1)").is_err());
+        assert!(Directive::parse("@url posts.show(1)").is_err());
+    }
+
+    #[test]
+    fn it_parses_asset_directives() {
+        assert_eq!(Directive::parse("@asset \"css/app.css\"").unwrap(), Some(Directive::Asset("css/app.css".to_string())));
+        assert!(Directive::parse("@asset css/app.css").is_err());
+        assert!(Directive::parse("@asset \"css/app.css\" with (a)").is_err());
+        assert_eq!(Directive::parse("@asset_sri \"js/app.js\"").unwrap(), Some(Directive::AssetSri("js/app.js".to_string())));
+    }
+}
@@ -0,0 +1,118 @@
+// Generation of `render_by_name`, rendering templates chosen at runtime by their path, with
+// `GeneratorOptions::registry`:
+//
+//     let html = templates::render_by_name("emails/welcome.plt", &plt::json::json!({ "name": "Ann" }))?;
+//
+// The arguments of the template are deserialized from the fields of the JSON object using
+// `plt::registry::arg`, so the crate using the generated code needs plt's `json` feature.
+// References are deserialized into the types they borrow, e.g. `String` for `&str` and `Vec<T>`
+// for `&[T]`. Layouts, components and templates taking arguments which can't be deserialized,
+// e.g. trait objects or the nonce of `@nonce`, can't be rendered by name, nor can SQL templates.
+use quote::ToTokens;
+use syn::visit::Visit;
+use crate::prelude::*;
+
+// Arguments added to the ones declared by templates, which are provided by the application.
+pub(crate) const PROVIDED_ARGS: [&str; 4] =
+    [crate::csp::NONCE_ARG, crate::csrf::CSRF_ARG, crate::routes::ROUTES_ARG, crate::runtime::FRAGMENT_CACHE_ARG];
+
+// Arm of the `match` of `render_by_name` rendering `template` by calling `function`, a path
+// relative to the root module. `None` when it can't be rendered by name.
+pub fn dispatch_arm(template: &Template, function: &str) -> Result<Option<String>> {
+    if template.has_slot()? || !template.blocks()?.is_empty() || crate::sql::is_sql_template(&template.path) {
+        return Ok(None);
+    }
+
+    // Arguments are deserialized in place, as bindings could shadow `name` or `context`.
+    let mut call_args = Vec::new();
+    for arg in &template.args {
+        let arg = TemplateArg::parse(arg)?;
+        let Some((ty, borrow)) = owned_type(&arg.ty).filter(|_| !PROVIDED_ARGS.contains(&arg.name.as_str())) else {
+            return Ok(None);
+        };
+
+        call_args.push(format!("{borrow}plt::registry::arg::<{ty}>(name, context, {:?})?", arg.name));
+    }
+
+    Ok(Some(format!("{:?} => {function}({}),", template.path, call_args.join(", "))))
+}
+
+// Type deserialized for an argument of type `ty` along with how it's passed, e.g. `String` and
+// `&` for `&str`. `None` for types which can't be deserialized.
+fn owned_type(ty: &str) -> Option<(String, &'static str)> {
+    let ty = syn::parse_str::<syn::Type>(ty).ok()?;
+    let (owned, borrow) = match ty {
+        syn::Type::Reference(reference) => {
+            let borrow = if reference.mutability.is_some() { "&mut " } else { "&" };
+            let owned = match *reference.elem {
+                syn::Type::Path(path) if path.path.is_ident("str") => syn::parse_quote!(String),
+                syn::Type::Slice(slice) => {
+                    let elem = slice.elem;
+                    syn::parse_quote!(Vec<#elem>)
+                }
+                elem => elem,
+            };
+            (owned, borrow)
+        }
+        ty => (ty, ""),
+    };
+
+    let mut borrowed = BorrowedTypes::default();
+    borrowed.visit_type(&owned);
+    if borrowed.found {
+        return None;
+    }
+
+    Some((owned.to_token_stream().to_string(), borrow))
+}
+
+// Finds references, trait objects and other types which can't be deserialized into owned values.
+#[derive(Default)]
+struct BorrowedTypes {
+    found: bool,
+}
+
+impl Visit<'_> for BorrowedTypes {
+    fn visit_type(&mut self, ty: &syn::Type) {
+        match ty {
+            syn::Type::Reference(_) | syn::Type::TraitObject(_) | syn::Type::ImplTrait(_) | syn::Type::Infer(_) => self.found = true,
+            ty => syn::visit::visit_type(self, ty),
+        }
+    }
+
+    fn visit_lifetime(&mut self, _: &syn::Lifetime) {
+        self.found = true;
+    }
+}
+
+// Code of `render_by_name`, dispatching to the templates of `functions` which can be rendered
+// by name.
+pub fn generate_registry(functions: &[GeneratedFunction], options: &GeneratorOptions) -> String {
+    let visibility = &options.visibility;
+    let mut code = format!(
+        "{visibility} fn render_by_name(name: &str, context: &plt::registry::Value) -> plt::prelude::Result<String> {{\nmatch name {{\n"
+    );
+    for arm in functions.iter().filter_map(|function| function.dispatch.as_ref()) {
+        code.push_str(arm);
+        code.push('\n');
+    }
+    code.push_str("_ => Err(plt::registry::unknown_template(name)),\n}\n}\n");
+
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dispatch::owned_type;
+
+    #[test]
+    fn it_derives_owned_types_of_arguments() {
+        assert_eq!(owned_type("& str"), Some(("String".to_string(), "&")));
+        assert_eq!(owned_type("&'a [Item]"), Some(("Vec < Item >".to_string(), "&")));
+        assert_eq!(owned_type("&mut Vec<u8>"), Some(("Vec < u8 >".to_string(), "&mut ")));
+        assert_eq!(owned_type("Option<u32>"), Some(("Option < u32 >".to_string(), "")));
+        assert_eq!(owned_type("Option<&str>"), None);
+        assert_eq!(owned_type("&dyn plt::csrf::CsrfTokenProvider"), None);
+        assert_eq!(owned_type("impl Display"), None);
+    }
+}
@@ -0,0 +1,223 @@
+// Catalog of the templates of a set, for teams treating their templates as an API, listing the
+// arguments, blocks and includes of each template along with its source:
+//
+//     let engine = plt::dynamic::Engine::from_dir("templates")?;
+//     std::fs::write("templates.html", plt::doc::generate_docs(&engine, DocFormat::Html)?)?;
+//
+// Templates declaring sample arguments with `@example { "title": "Hello" }` also show their
+// output for them, rendered by the dynamic engine.
+use std::str::FromStr;
+use anyhow::{bail, Context};
+use serde_json::Value;
+use crate::dynamic::Engine;
+use crate::reflection::compact_type;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DocFormat {
+    #[default]
+    Html,
+    Markdown,
+}
+
+impl FromStr for DocFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<DocFormat> {
+        match format {
+            "html" => Ok(DocFormat::Html),
+            "markdown" => Ok(DocFormat::Markdown),
+            _ => bail!("unknown documentation format `{format}`, expected `html` or `markdown`"),
+        }
+    }
+}
+
+// What the catalog shows of a single template.
+#[derive(Debug, Clone, PartialEq)]
+struct TemplateDoc<'a> {
+    path: &'a str,
+    // Names and types of the arguments.
+    params: Vec<(&'a str, String)>,
+    blocks: Vec<String>,
+    includes: Vec<String>,
+    // Sample arguments along with the output rendered for them.
+    example: Option<(String, String)>,
+    source: &'a str,
+}
+
+// Documents all templates of `engine`, rendering the examples with it.
+pub fn generate_docs(engine: &Engine, format: DocFormat) -> Result<String> {
+    let set = engine.template_set();
+    let docs = set.paths().filter_map(|path| set.get(path)).map(|template| document_template(engine, template)).collect::<Result<Vec<_>>>()?;
+
+    Ok(match format {
+        DocFormat::Html => write_html(&docs),
+        DocFormat::Markdown => write_markdown(&docs),
+    })
+}
+
+fn document_template<'a>(engine: &Engine, template: &'a Template) -> Result<TemplateDoc<'a>> {
+    let path = template.path.as_str();
+    let example = match template.example()? {
+        Some(example) => {
+            let context: Value = serde_json::from_str(&example).with_context(|| format!("the example of `{path}` is not valid JSON"))?;
+            let output = engine.render(path, &context).with_context(|| format!("failed to render the example of `{path}`"))?;
+            Some((serde_json::to_string_pretty(&context)?, output))
+        }
+        None => None,
+    };
+    // Sections share the source of their file, of which only their own parts are shown.
+    let source = match (path.contains('#'), template.spans.first(), template.spans.last()) {
+        (true, Some(first), Some(last)) => &template.source[first.start..last.end],
+        _ => template.source.as_str(),
+    };
+
+    Ok(TemplateDoc {
+        path,
+        params: template
+            .args
+            .iter()
+            .map(|arg| {
+                let (name, ty) = arg.split_once(':').unwrap_or((arg, ""));
+                (name.trim(), compact_type(ty))
+            })
+            .collect(),
+        blocks: template.blocks()?,
+        includes: template.includes()?,
+        example,
+        source,
+    })
+}
+
+fn write_markdown(docs: &[TemplateDoc]) -> String {
+    let mut markdown = "# Templates\n".to_string();
+
+    for doc in docs {
+        markdown.push_str(&format!("\n## `{}`\n", doc.path));
+        if !doc.params.is_empty() {
+            markdown.push_str("\n### Arguments\n\n| Name | Type |\n| --- | --- |\n");
+            for (name, ty) in &doc.params {
+                markdown.push_str(&format!("| `{name}` | `{}` |\n", ty.replace('|', "\\|")));
+            }
+        }
+        for (title, names) in [("Blocks", &doc.blocks), ("Includes", &doc.includes)] {
+            if !names.is_empty() {
+                markdown.push_str(&format!("\n### {title}\n\n"));
+                for name in names {
+                    markdown.push_str(&format!("- `{name}`\n"));
+                }
+            }
+        }
+        if let Some((context, output)) = &doc.example {
+            markdown.push_str(&format!("\n### Example\n\n{}\n{}", fenced(context, "json"), fenced(output, "html")));
+        }
+        markdown.push_str(&format!("\n### Source\n\n{}", fenced(doc.source, "")));
+    }
+
+    markdown
+}
+
+// Code block holding `code`, fenced with more backticks than any run of them inside of it.
+fn fenced(code: &str, language: &str) -> String {
+    let longest_run = code.split(|c| c != '`').map(str::len).max().unwrap_or_default();
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    let newline = if code.ends_with('\n') { "" } else { "\n" };
+
+    format!("{fence}{language}\n{code}{newline}{fence}\n")
+}
+
+fn write_html(docs: &[TemplateDoc]) -> String {
+    let mut html = "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Templates</title>\n</head>\n<body>\n<h1>Templates</h1>\n".to_string();
+
+    html.push_str("<nav>\n<ul>\n");
+    for doc in docs {
+        html.push_str(&format!("<li><a href=\"#{0}\"><code>{1}</code></a></li>\n", escape_html(fragment(doc.path)), escape_html(doc.path)));
+    }
+    html.push_str("</ul>\n</nav>\n");
+
+    for doc in docs {
+        html.push_str(&format!("<section id=\"{}\">\n<h2><code>{}</code></h2>\n", escape_html(fragment(doc.path)), escape_html(doc.path)));
+        if !doc.params.is_empty() {
+            html.push_str("<h3>Arguments</h3>\n<table>\n<tr><th>Name</th><th>Type</th></tr>\n");
+            for (name, ty) in &doc.params {
+                html.push_str(&format!("<tr><td><code>{}</code></td><td><code>{}</code></td></tr>\n", escape_html(name), escape_html(ty)));
+            }
+            html.push_str("</table>\n");
+        }
+        for (title, names) in [("Blocks", &doc.blocks), ("Includes", &doc.includes)] {
+            if !names.is_empty() {
+                html.push_str(&format!("<h3>{title}</h3>\n<ul>\n"));
+                for name in names {
+                    html.push_str(&format!("<li><code>{}</code></li>\n", escape_html(name)));
+                }
+                html.push_str("</ul>\n");
+            }
+        }
+        if let Some((context, output)) = &doc.example {
+            html.push_str(&format!("<h3>Example</h3>\n<pre><code>{}</code></pre>\n", escape_html(context)));
+            // Sandboxed, so scripts of the output don't run in the catalog.
+            html.push_str(&format!("<iframe sandbox srcdoc=\"{}\"></iframe>\n", escape_html(output)));
+        }
+        html.push_str(&format!("<h3>Source</h3>\n<pre><code>{}</code></pre>\n</section>\n", escape_html(doc.source)));
+    }
+    html.push_str("</body>\n</html>\n");
+
+    html
+}
+
+// Id of the section of the template under `path`, e.g. `components.plt-badge` for
+// `components.plt#badge`, as `#` can't be part of URL fragments.
+fn fragment(path: &str) -> String {
+    path.replace('#', "-")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::doc::{fenced, generate_docs, DocFormat};
+    use crate::dynamic::Engine;
+
+    fn engine() -> Engine {
+        let mut engine = Engine::new();
+        engine.add_template(
+            "card.plt",
+            "<?rs @args title: &str, tags: &[String] ?><?rs @example { \"title\": \"Hi\", \"tags\": [] } ?>\
+             <?rs @include \"badge.plt\" ?><h2><?= title ?></h2><?rs @yield footer ?>",
+        );
+        engine.add_template("badge.plt", "<b>New</b>");
+        engine
+    }
+
+    #[test]
+    fn it_documents_templates_in_markdown() {
+        let markdown = generate_docs(&engine(), DocFormat::Markdown).unwrap();
+
+        assert!(markdown.starts_with("# Templates\n\n## `badge.plt`\n\n### Source\n\n```\n<b>New</b>\n```\n\n## `card.plt`\n"));
+        assert!(markdown.contains("| `title` | `&str` |\n| `tags` | `&[String]` |\n"));
+        assert!(markdown.contains("### Blocks\n\n- `footer`\n\n### Includes\n\n- `badge.plt`\n"));
+        assert!(markdown.contains("### Example\n\n```json\n{\n  \"tags\": [],\n  \"title\": \"Hi\"\n}\n```\n\n```html\n<b>New</b><h2>Hi</h2>\n```\n"));
+    }
+
+    #[test]
+    fn it_documents_templates_in_html() {
+        let html = generate_docs(&engine(), DocFormat::Html).unwrap();
+
+        assert!(html.contains("<li><a href=\"#card.plt\"><code>card.plt</code></a></li>"));
+        assert!(html.contains("<tr><td><code>title</code></td><td><code>&amp;str</code></td></tr>"));
+        assert!(html.contains("<iframe sandbox srcdoc=\"&lt;b&gt;New&lt;/b&gt;&lt;h2&gt;Hi&lt;/h2&gt;\"></iframe>"));
+        assert!(html.contains("<h3>Source</h3>\n<pre><code>&lt;b&gt;New&lt;/b&gt;</code></pre>"));
+    }
+
+    #[test]
+    fn it_reports_invalid_examples() {
+        let mut engine = Engine::new();
+        engine.add_template("page.plt", "<?rs @example { title: 1 } ?>");
+
+        let error = generate_docs(&engine, DocFormat::Markdown).unwrap_err();
+        assert_eq!(error.to_string(), "the example of `page.plt` is not valid JSON");
+    }
+
+    #[test]
+    fn it_fences_code_containing_backticks() {
+        assert_eq!(fenced("a ```` b", "md"), "`````md\na ```` b\n`````\n");
+    }
+}
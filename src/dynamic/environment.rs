@@ -0,0 +1,69 @@
+// Globals, functions and filters which the application registers for all templates rendered by
+// an engine, like the environments of tera or minijinja:
+//
+//     let mut engine = plt::dynamic::Engine::from_dir("templates")?;
+//     engine.env_mut().add_global("site_name", "Example");
+//     engine.env_mut().add_function("asset_url", |args| Ok(format!("/assets/{}", args[0].as_str().unwrap_or("")).into()));
+//     engine.env_mut().add_filter("money", |value, _| Ok(format!("${:.2}", value.as_f64().unwrap_or(0.0)).into()));
+//
+// lets templates write `<?= site_name ?>`, `<?= asset_url("app.css") ?>` and `<?= price | money ?>`.
+// Variables of the context shadow globals, and filters registered under the name of a builtin
+// one replace it. Compiled templates call Rust functions instead.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use serde_json::{Map, Value};
+use crate::prelude::*;
+
+// Function called as `name(args)`.
+pub type Function = Arc<dyn Fn(&[Value]) -> Result<Value> + Send + Sync>;
+// Filter applied as `value | name(args)` or `value.name(args)`.
+pub type Filter = Arc<dyn Fn(&Value, &[Value]) -> Result<Value> + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub struct Environment {
+    globals: Map<String, Value>,
+    functions: BTreeMap<String, Function>,
+    filters: BTreeMap<String, Filter>,
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        Self::default()
+    }
+
+    pub fn add_global(&mut self, name: impl Into<String>, value: impl Into<Value>) {
+        self.globals.insert(name.into(), value.into());
+    }
+
+    pub fn add_function(&mut self, name: impl Into<String>, function: impl Fn(&[Value]) -> Result<Value> + Send + Sync + 'static) {
+        self.functions.insert(name.into(), Arc::new(function));
+    }
+
+    pub fn add_filter(&mut self, name: impl Into<String>, filter: impl Fn(&Value, &[Value]) -> Result<Value> + Send + Sync + 'static) {
+        self.filters.insert(name.into(), Arc::new(filter));
+    }
+
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+
+    pub fn filter(&self, name: &str) -> Option<&Filter> {
+        self.filters.get(name)
+    }
+}
+
+// Functions and filters are listed by their names.
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Environment")
+            .field("globals", &self.globals)
+            .field("functions", &self.functions.keys().collect::<Vec<_>>())
+            .field("filters", &self.filters.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
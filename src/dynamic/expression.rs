@@ -0,0 +1,425 @@
+use std::cmp::Ordering;
+use std::fmt;
+use anyhow::{anyhow, bail, Context};
+use quote::ToTokens;
+use serde_json::{Map, Number, Value};
+use syn::{BinOp, Expr, Lit, Member, UnOp};
+use crate::dynamic::environment::Environment;
+use crate::dynamic::filters::apply_filter;
+use crate::prelude::*;
+
+// Variables visible to expressions, innermost frame last.
+#[derive(Debug)]
+pub(crate) struct Scope<'a> {
+    frames: Vec<Map<String, Value>>,
+    // Globals, functions and filters registered by the application.
+    env: &'a Environment,
+    // Maximum number of items of ranges, see `EngineOptions::max_range_len`.
+    max_range_len: usize,
+}
+
+impl<'a> Scope<'a> {
+    pub(crate) fn new(variables: Map<String, Value>, env: &'a Environment, max_range_len: usize) -> Scope<'a> {
+        Scope { frames: vec![variables], env, max_range_len }
+    }
+
+    pub(crate) fn push(&mut self) {
+        self.frames.push(Map::new());
+    }
+
+    pub(crate) fn pop(&mut self) {
+        self.frames.pop();
+    }
+
+    // Sets the variable in the innermost frame, shadowing variables of outer frames.
+    pub(crate) fn set(&mut self, name: impl Into<String>, value: Value) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.insert(name.into(), value);
+        }
+    }
+
+    // Variable or, if there is none, global under `name`.
+    pub(crate) fn get(&self, name: &str) -> Option<&Value> {
+        self.frames.iter().rev().find_map(|frame| frame.get(name)).or_else(|| self.env.global(name))
+    }
+
+    // Applies the filter registered under `name` or, if there is none, the builtin one.
+    pub(crate) fn filter(&self, name: &str, value: Value, args: &[Value]) -> Result<Value> {
+        match self.env.filter(name) {
+            Some(filter) => filter(&value, args).with_context(|| format!("filter `{name}` failed")),
+            None => apply_filter(name, value, args),
+        }
+    }
+
+    // All visible variables, e.g. for templates included without arguments.
+    pub(crate) fn variables(&self) -> Map<String, Value> {
+        let mut variables = Map::new();
+        for frame in &self.frames {
+            variables.extend(frame.clone());
+        }
+
+        variables
+    }
+}
+
+// Error of expressions using variables or fields missing from the context, which the engine can
+// render as empty or as placeholders instead, see `Undefined`.
+#[derive(Debug)]
+pub(crate) struct UndefinedError {
+    // Source-like text of the missing value, e.g. `user.name`.
+    pub(crate) path: String,
+    message: String,
+}
+
+impl fmt::Display for UndefinedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for UndefinedError {}
+
+// How a value is written into the output: strings without quotes, `null` as nothing.
+pub(crate) fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+// `null`, `false`, zero and empty strings, arrays and objects are falsy.
+pub(crate) fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(number) => number.as_f64().is_some_and(|number| number != 0.0),
+        Value::String(text) => !text.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(fields) => !fields.is_empty(),
+    }
+}
+
+// Source-like text of the expression for error messages, e.g. `user.name`.
+pub(crate) fn expression_text(expression: &Expr) -> String {
+    expression.to_token_stream().to_string().replace(" . ", ".")
+}
+
+// Evaluates the subset of Rust expressions supported by the dynamic engine: literals, variables,
+// field and index access, operators, ranges, arrays and filters, either as `value | filter(args)`
+// or as method calls.
+pub(crate) fn evaluate(expression: &Expr, scope: &Scope) -> Result<Value> {
+    let value = match expression {
+        Expr::Lit(literal) => evaluate_literal(&literal.lit)?,
+        Expr::Path(path) => {
+            let Some(name) = path.path.get_ident().map(|ident| ident.to_string()) else {
+                bail!("unsupported path `{}`", expression_text(expression));
+            };
+
+            match scope.get(&name) {
+                Some(value) => value.clone(),
+                None if name == "None" => Value::Null,
+                None => bail!(UndefinedError { message: format!("undefined variable `{name}`"), path: name }),
+            }
+        }
+        Expr::Field(field) => {
+            let base = evaluate(&field.base, scope)?;
+
+            match &field.member {
+                Member::Named(name) => match &base {
+                    Value::Object(fields) => match fields.get(&name.to_string()) {
+                        Some(value) => value.clone(),
+                        None => bail!(UndefinedError {
+                            path: expression_text(expression),
+                            message: format!("`{}` has no field `{name}`", expression_text(&field.base)),
+                        }),
+                    },
+                    _ => bail!("`{}` is not an object, found `{base}`", expression_text(&field.base)),
+                },
+                Member::Unnamed(index) => index_value(&base, &Value::from(index.index), &field.base)?,
+            }
+        }
+        Expr::Index(index) => {
+            let base = evaluate(&index.expr, scope)?;
+            index_value(&base, &evaluate(&index.index, scope)?, &index.expr)?
+        }
+        Expr::Reference(reference) => evaluate(&reference.expr, scope)?,
+        Expr::Paren(paren) => evaluate(&paren.expr, scope)?,
+        Expr::Group(group) => evaluate(&group.expr, scope)?,
+        Expr::Unary(unary) => {
+            let value = evaluate(&unary.expr, scope)?;
+
+            match unary.op {
+                UnOp::Not(_) => Value::Bool(!is_truthy(&value)),
+                UnOp::Neg(_) => arithmetic(&BinOp::Sub(Default::default()), &Value::from(0), &value)?,
+                UnOp::Deref(_) => value,
+                _ => bail!("unsupported operator in `{}`", expression_text(expression)),
+            }
+        }
+        Expr::Binary(binary) => evaluate_binary(binary, scope)?,
+        Expr::MethodCall(call) => {
+            let receiver = evaluate(&call.receiver, scope)?;
+            let args = call.args.iter().map(|arg| evaluate(arg, scope)).collect::<Result<Vec<_>>>()?;
+
+            scope.filter(&call.method.to_string(), receiver, &args)?
+        }
+        Expr::Call(call) => {
+            let name = match call.func.as_ref() {
+                Expr::Path(path) => path.path.get_ident().map(|ident| ident.to_string()),
+                _ => None,
+            };
+            let Some(name) = name else {
+                bail!("unsupported call `{}`", expression_text(expression));
+            };
+            let Some(function) = scope.env.function(&name) else {
+                bail!("unknown function `{name}`");
+            };
+            let args = call.args.iter().map(|arg| evaluate(arg, scope)).collect::<Result<Vec<_>>>()?;
+
+            function(&args).with_context(|| format!("function `{name}` failed"))?
+        }
+        Expr::Range(range) => {
+            let bound = |bound: &Option<Box<Expr>>| -> Result<i64> {
+                let Some(bound) = bound else {
+                    bail!("ranges need both bounds, found `{}`", expression_text(expression));
+                };
+
+                evaluate(bound, scope)?
+                    .as_i64()
+                    .ok_or_else(|| anyhow!("range bounds must be integers, found `{}`", expression_text(bound)))
+            };
+
+            let (start, end) = (bound(&range.start)?, bound(&range.end)?);
+            let len = match range.limits {
+                syn::RangeLimits::HalfOpen(_) => i128::from(end) - i128::from(start),
+                syn::RangeLimits::Closed(_) => i128::from(end) - i128::from(start) + 1,
+            };
+            if len > scope.max_range_len as i128 {
+                bail!("range `{}` has {len} items, more than the maximum of {}", expression_text(expression), scope.max_range_len);
+            }
+
+            match range.limits {
+                syn::RangeLimits::HalfOpen(_) => (start..end).map(Value::from).collect(),
+                syn::RangeLimits::Closed(_) => (start..=end).map(Value::from).collect(),
+            }
+        }
+        Expr::Array(array) => {
+            Value::Array(array.elems.iter().map(|item| evaluate(item, scope)).collect::<Result<_>>()?)
+        }
+        _ => bail!("unsupported expression `{}`", expression_text(expression)),
+    };
+
+    Ok(value)
+}
+
+fn evaluate_literal(literal: &Lit) -> Result<Value> {
+    let value = match literal {
+        Lit::Str(text) => Value::String(text.value()),
+        Lit::Char(c) => Value::String(c.value().to_string()),
+        Lit::Bool(value) => Value::Bool(value.value),
+        Lit::Int(int) => Value::from(int.base10_parse::<i64>()?),
+        Lit::Float(float) => match Number::from_f64(float.base10_parse::<f64>()?) {
+            Some(number) => Value::Number(number),
+            None => bail!("unsupported number `{float}`"),
+        },
+        _ => bail!("unsupported literal `{}`", literal.to_token_stream()),
+    };
+
+    Ok(value)
+}
+
+fn index_value(base: &Value, index: &Value, base_expression: &Expr) -> Result<Value> {
+    let value = match (base, index) {
+        (Value::Array(items), Value::Number(number)) => number
+            .as_u64()
+            .and_then(|index| items.get(index as usize))
+            .cloned()
+            .ok_or_else(|| anyhow!("index {number} is out of bounds of `{}`", expression_text(base_expression)))?,
+        (Value::Object(fields), Value::String(key)) => match fields.get(key) {
+            Some(value) => value.clone(),
+            None => bail!(UndefinedError {
+                path: format!("{}.{key}", expression_text(base_expression)),
+                message: format!("`{}` has no field `{key}`", expression_text(base_expression)),
+            }),
+        },
+        _ => bail!("`{}` can't be indexed with `{index}`", expression_text(base_expression)),
+    };
+
+    Ok(value)
+}
+
+fn evaluate_binary(binary: &syn::ExprBinary, scope: &Scope) -> Result<Value> {
+    match binary.op {
+        BinOp::BitOr(_) => return evaluate_filter(&binary.left, &binary.right, scope),
+        BinOp::And(_) => {
+            let value = is_truthy(&evaluate(&binary.left, scope)?) && is_truthy(&evaluate(&binary.right, scope)?);
+            return Ok(Value::Bool(value));
+        }
+        BinOp::Or(_) => {
+            let value = is_truthy(&evaluate(&binary.left, scope)?) || is_truthy(&evaluate(&binary.right, scope)?);
+            return Ok(Value::Bool(value));
+        }
+        _ => {}
+    }
+
+    let left = evaluate(&binary.left, scope)?;
+    let right = evaluate(&binary.right, scope)?;
+
+    let value = match binary.op {
+        BinOp::Eq(_) => Value::Bool(are_equal(&left, &right)),
+        BinOp::Ne(_) => Value::Bool(!are_equal(&left, &right)),
+        BinOp::Lt(_) => Value::Bool(compare(&left, &right)? == Ordering::Less),
+        BinOp::Le(_) => Value::Bool(compare(&left, &right)? != Ordering::Greater),
+        BinOp::Gt(_) => Value::Bool(compare(&left, &right)? == Ordering::Greater),
+        BinOp::Ge(_) => Value::Bool(compare(&left, &right)? != Ordering::Less),
+        BinOp::Add(_) if left.is_string() || right.is_string() => Value::String(display(&left) + &display(&right)),
+        BinOp::Add(_) | BinOp::Sub(_) | BinOp::Mul(_) | BinOp::Div(_) | BinOp::Rem(_) => {
+            arithmetic(&binary.op, &left, &right)?
+        }
+        _ => bail!("unsupported operator in `{}`", expression_text(&Expr::Binary(binary.clone()))),
+    };
+
+    Ok(value)
+}
+
+// `value | name` or `value | name(args)`.
+fn evaluate_filter(value: &Expr, filter: &Expr, scope: &Scope) -> Result<Value> {
+    let (name, args) = match filter {
+        Expr::Path(path) => (path.path.get_ident(), Vec::new()),
+        Expr::Call(call) => match call.func.as_ref() {
+            Expr::Path(path) => (path.path.get_ident(), call.args.iter().collect()),
+            _ => (None, Vec::new()),
+        },
+        _ => (None, Vec::new()),
+    };
+    let Some(name) = name.map(|name| name.to_string()) else {
+        bail!("expected a filter after `|`, found `{}`", expression_text(filter));
+    };
+
+    // `default` also covers undefined variables and fields.
+    let value = if name == "default" { evaluate(value, scope).unwrap_or(Value::Null) } else { evaluate(value, scope)? };
+    let args = args.into_iter().map(|arg| evaluate(arg, scope)).collect::<Result<Vec<_>>>()?;
+
+    scope.filter(&name, value, &args)
+}
+
+fn are_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(left), Value::Number(right)) => left.as_f64() == right.as_f64(),
+        _ => left == right,
+    }
+}
+
+fn compare(left: &Value, right: &Value) -> Result<Ordering> {
+    let ordering = match (left, right) {
+        (Value::Number(left), Value::Number(right)) => left.as_f64().partial_cmp(&right.as_f64()),
+        (Value::String(left), Value::String(right)) => Some(left.cmp(right)),
+        _ => None,
+    };
+
+    ordering.ok_or_else(|| anyhow!("can't compare `{left}` with `{right}`"))
+}
+
+// Integers stay integers, everything else is computed with floats.
+fn arithmetic(op: &BinOp, left: &Value, right: &Value) -> Result<Value> {
+    if let (Some(left), Some(right)) = (left.as_i64(), right.as_i64()) {
+        let value = match op {
+            BinOp::Add(_) => left.checked_add(right),
+            BinOp::Sub(_) => left.checked_sub(right),
+            BinOp::Mul(_) => left.checked_mul(right),
+            BinOp::Div(_) => left.checked_div(right),
+            _ => left.checked_rem(right),
+        };
+
+        return value.map(Value::from).ok_or_else(|| anyhow!("arithmetic overflow or division by zero"));
+    }
+
+    let (Some(left_number), Some(right_number)) = (left.as_f64(), right.as_f64()) else {
+        bail!("arithmetic needs numbers, found `{left}` and `{right}`");
+    };
+
+    let value = match op {
+        BinOp::Add(_) => left_number + right_number,
+        BinOp::Sub(_) => left_number - right_number,
+        BinOp::Mul(_) => left_number * right_number,
+        BinOp::Div(_) => left_number / right_number,
+        _ => left_number % right_number,
+    };
+
+    Number::from_f64(value).map(Value::Number).ok_or_else(|| anyhow!("arithmetic result `{value}` is not a number"))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::bail;
+    use crate::dynamic::environment::Environment;
+    use crate::dynamic::expression::{evaluate, Scope};
+    use serde_json::{json, Value};
+
+    fn evaluate_str(expression: &str) -> anyhow::Result<Value> {
+        let Value::Object(variables) = json!({
+            "user": { "name": "Ann", "roles": ["admin", "editor"], "age": 31 },
+            "count": 3,
+            "empty": "",
+        }) else {
+            unreachable!()
+        };
+
+        let mut env = Environment::new();
+        env.add_global("site", json!({ "name": "Example" }));
+        env.add_global("count", 10);
+        env.add_function("sum", |args| Ok(json!(args.iter().filter_map(Value::as_i64).sum::<i64>())));
+        env.add_function("fail", |_| bail!("no way"));
+        env.add_filter("shout", |value, _| Ok(json!(format!("{}!", value.as_str().unwrap_or("").to_uppercase()))));
+
+        evaluate(&syn::parse_str(expression).unwrap(), &Scope::new(variables, &env, 10))
+    }
+
+    #[test]
+    fn it_evaluates_lookups() {
+        assert_eq!(evaluate_str("user.name").unwrap(), json!("Ann"));
+        assert_eq!(evaluate_str("user.roles[1]").unwrap(), json!("editor"));
+        assert_eq!(evaluate_str("user[\"age\"]").unwrap(), json!(31));
+        assert_eq!(evaluate_str("&user.roles.0").unwrap(), json!("admin"));
+    }
+
+    #[test]
+    fn it_evaluates_operators() {
+        assert_eq!(evaluate_str("count * 2 + 1").unwrap(), json!(7));
+        assert_eq!(evaluate_str("count / 2.0").unwrap(), json!(1.5));
+        assert_eq!(evaluate_str("user.age >= 18 && !empty").unwrap(), json!(true));
+        assert_eq!(evaluate_str("user.name == \"Ann\" || missing").unwrap(), json!(true));
+        assert_eq!(evaluate_str("\"Hi \" + user.name").unwrap(), json!("Hi Ann"));
+        assert_eq!(evaluate_str("1..=3").unwrap(), json!([1, 2, 3]));
+        assert_eq!(evaluate_str("1..=10").unwrap().as_array().map(Vec::len), Some(10));
+        assert_eq!(evaluate_str("0..11").unwrap_err().to_string(), "range `0 .. 11` has 11 items, more than the maximum of 10");
+    }
+
+    #[test]
+    fn it_applies_filters_and_methods() {
+        assert_eq!(evaluate_str("user.name | upper").unwrap(), json!("ANN"));
+        assert_eq!(evaluate_str("user.roles | join(\", \") | upper").unwrap(), json!("ADMIN, EDITOR"));
+        assert_eq!(evaluate_str("user.email | default(\"none\")").unwrap(), json!("none"));
+        assert_eq!(evaluate_str("user.roles.len()").unwrap(), json!(2));
+    }
+
+    #[test]
+    fn it_uses_the_environment() {
+        assert_eq!(evaluate_str("site.name").unwrap(), json!("Example"));
+        assert_eq!(evaluate_str("count").unwrap(), json!(3));
+        assert_eq!(evaluate_str("sum(count, 2) * 2").unwrap(), json!(10));
+        assert_eq!(evaluate_str("user.name | shout").unwrap(), json!("ANN!"));
+        assert_eq!(evaluate_str("user.name.shout()").unwrap(), json!("ANN!"));
+        assert_eq!(evaluate_str("missing(1)").unwrap_err().to_string(), "unknown function `missing`");
+        assert_eq!(format!("{:#}", evaluate_str("fail()").unwrap_err()), "function `fail` failed: no way");
+    }
+
+    #[test]
+    fn it_reports_evaluation_errors() {
+        assert_eq!(evaluate_str("missing").unwrap_err().to_string(), "undefined variable `missing`");
+        assert_eq!(evaluate_str("user.email").unwrap_err().to_string(), "`user` has no field `email`");
+        assert_eq!(evaluate_str("user.roles[5]").unwrap_err().to_string(), "index 5 is out of bounds of `user.roles`");
+        assert_eq!(evaluate_str("user.name < 3").unwrap_err().to_string(), "can't compare `\"Ann\"` with `3`");
+        assert_eq!(evaluate_str("|x| x").unwrap_err().to_string(), "unsupported expression `| x | x`");
+    }
+}
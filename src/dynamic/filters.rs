@@ -0,0 +1,103 @@
+use anyhow::{bail, Context};
+use serde_json::Value;
+use crate::dynamic::expression::{display, is_truthy};
+use crate::prelude::*;
+
+// Applies the filter `name` to `value`, for both `value | name(args)` and `value.name(args)`.
+// Some filters have aliases named like the Rust methods, so that simple Rust expressions like
+// `name.to_uppercase()` also work in the dynamic engine.
+pub(crate) fn apply_filter(name: &str, value: Value, args: &[Value]) -> Result<Value> {
+    let value = match (name, args) {
+        ("upper" | "to_uppercase", []) => Value::String(display(&value).to_uppercase()),
+        ("lower" | "to_lowercase", []) => Value::String(display(&value).to_lowercase()),
+        ("capitalize", []) => {
+            let text = display(&value);
+            let mut chars = text.chars();
+            let capitalized = chars.next().map_or(String::new(), |first| first.to_uppercase().chain(chars).collect());
+            Value::String(capitalized)
+        }
+        ("trim", []) => Value::String(display(&value).trim().to_string()),
+        ("len" | "length", []) => Value::from(length(&value)?),
+        ("is_empty", []) => Value::Bool(!is_truthy(&value)),
+        ("first", []) => items(&value, name)?.first().cloned().unwrap_or(Value::Null),
+        ("last", []) => items(&value, name)?.last().cloned().unwrap_or(Value::Null),
+        ("reverse", []) => match value {
+            Value::String(text) => Value::String(text.chars().rev().collect()),
+            value => Value::Array(items(&value, name)?.iter().rev().cloned().collect()),
+        },
+        ("join", [separator]) => {
+            let items: Vec<String> = items(&value, name)?.iter().map(display).collect();
+            Value::String(items.join(&display(separator)))
+        }
+        ("default", [default]) => match value {
+            Value::Null => default.clone(),
+            value => value,
+        },
+        ("truncate", [length]) => {
+            let length = length.as_u64().context("filter `truncate` expects a length")? as usize;
+            let text = display(&value);
+
+            match text.char_indices().nth(length) {
+                Some((index, _)) => Value::String(format!("{}...", &text[..index])),
+                None => Value::String(text),
+            }
+        }
+        ("json", []) => Value::String(value.to_string()),
+        ("escape", []) => Value::String(escape_html(display(&value))),
+        ("to_string", []) => Value::String(display(&value)),
+        // Methods which are no-ops for JSON values.
+        ("iter" | "clone" | "as_str", []) => value,
+        (
+            "upper" | "to_uppercase" | "lower" | "to_lowercase" | "capitalize" | "trim" | "len" | "length" | "is_empty"
+            | "first" | "last" | "reverse" | "json" | "escape" | "to_string" | "iter" | "clone" | "as_str",
+            _,
+        ) => bail!("filter `{name}` takes no arguments"),
+        ("join" | "default" | "truncate", _) => bail!("filter `{name}` takes a single argument"),
+        _ => bail!("unknown filter `{name}`"),
+    };
+
+    Ok(value)
+}
+
+fn length(value: &Value) -> Result<usize> {
+    match value {
+        Value::String(text) => Ok(text.chars().count()),
+        Value::Array(items) => Ok(items.len()),
+        Value::Object(fields) => Ok(fields.len()),
+        _ => bail!("`{value}` has no length"),
+    }
+}
+
+fn items<'a>(value: &'a Value, filter: &str) -> Result<&'a Vec<Value>> {
+    match value {
+        Value::Array(items) => Ok(items),
+        _ => bail!("filter `{filter}` expects an array, found `{value}`"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dynamic::filters::apply_filter;
+    use serde_json::json;
+
+    #[test]
+    fn it_applies_filters() {
+        assert_eq!(apply_filter("upper", json!("abc"), &[]).unwrap(), json!("ABC"));
+        assert_eq!(apply_filter("capitalize", json!("żółw"), &[]).unwrap(), json!("Żółw"));
+        assert_eq!(apply_filter("len", json!([1, 2]), &[]).unwrap(), json!(2));
+        assert_eq!(apply_filter("join", json!(["a", 1]), &[json!(", ")]).unwrap(), json!("a, 1"));
+        assert_eq!(apply_filter("default", json!(null), &[json!("none")]).unwrap(), json!("none"));
+        assert_eq!(apply_filter("truncate", json!("abcdef"), &[json!(3)]).unwrap(), json!("abc..."));
+        assert_eq!(apply_filter("escape", json!("<b>"), &[]).unwrap(), json!("&lt;b&gt;"));
+    }
+
+    #[test]
+    fn it_rejects_unknown_filters_and_arguments() {
+        assert_eq!(apply_filter("shout", json!(""), &[]).unwrap_err().to_string(), "unknown filter `shout`");
+        assert_eq!(apply_filter("upper", json!(""), &[json!(1)]).unwrap_err().to_string(), "filter `upper` takes no arguments");
+        assert_eq!(
+            apply_filter("first", json!("abc"), &[]).unwrap_err().to_string(),
+            "filter `first` expects an array, found `\"abc\"`"
+        );
+    }
+}
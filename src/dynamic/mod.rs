@@ -0,0 +1,939 @@
+// Interpreter rendering templates at runtime against a JSON context, so templates can be
+// edited without recompiling, e.g. during development or when users write them:
+//
+//     let mut engine = plt::dynamic::Engine::new();
+//     engine.add_template("index.plt", "Hello <?= user.name | upper ?>!");
+//     let output = engine.render("index.plt", &serde_json::json!({ "user": { "name": "Ann" } }))?;
+//
+// Only a subset of Rust is supported: expressions made of variables, field and index access,
+// literals, operators and filters, and code parts holding directives like `@if` and `@for`.
+// Other Rust code is rejected, so templates relying on it still need to be compiled.
+//
+// Templates included, extended or called without arguments see the variables of their caller.
+// With arguments, they only see the arguments, bound to the names declared with `@args`.
+mod environment;
+mod expression;
+mod filters;
+
+pub use crate::dynamic::environment::{Environment, Filter, Function};
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+use anyhow::{anyhow, bail, Context};
+use serde_json::{Map, Value};
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::{Expr, Pat};
+use crate::dynamic::expression::{display, evaluate, expression_text, is_truthy, Scope, UndefinedError};
+use crate::runtime::DEFAULT_MAX_DEPTH;
+use crate::prelude::*;
+
+// Maximum number of items of ranges, unless configured otherwise.
+pub const DEFAULT_MAX_RANGE_LEN: usize = 100_000;
+
+#[derive(Debug, Clone)]
+pub struct EngineOptions {
+    pub escaping: Escaping,
+    // Maximum number of nested templates, e.g. of templates including themselves to render trees.
+    pub max_depth: usize,
+    // Maximum number of items of ranges like `0..count`, which are collected into arrays, so
+    // templates can't make them exhaust the memory.
+    pub max_range_len: usize,
+    pub undefined: Undefined,
+    // Whether `@env`, `@git_sha` and `@built_at` are evaluated. They're off by default, since
+    // they expose the environment of the server to templates which users may write, and
+    // `@git_sha` runs git.
+    pub build_constants: bool,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        EngineOptions {
+            escaping: Escaping::default(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_range_len: DEFAULT_MAX_RANGE_LEN,
+            undefined: Undefined::default(),
+            build_constants: false,
+        }
+    }
+}
+
+// What templates render for variables and fields missing from the context. Unless it's an error,
+// conditions using them are false and loops over them render nothing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Undefined {
+    #[default]
+    Error,
+    Empty,
+    // Renders e.g. `[[missing: user.name]]`, to find them in the output of user-written templates.
+    Placeholder,
+}
+
+impl FromStr for Undefined {
+    type Err = anyhow::Error;
+
+    fn from_str(undefined: &str) -> Result<Undefined> {
+        match undefined {
+            "error" => Ok(Undefined::Error),
+            "empty" => Ok(Undefined::Empty),
+            "placeholder" => Ok(Undefined::Placeholder),
+            _ => bail!("unknown undefined behavior `{undefined}`, expected `error`, `empty` or `placeholder`"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Engine {
+    set: TemplateSet,
+    options: EngineOptions,
+    env: Environment,
+}
+
+struct Node {
+    // Index of the part the node was parsed from, used to locate errors.
+    part: usize,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    Text(String),
+    Echo(Expr),
+    // Branches in order, the `@else` branch has no condition.
+    If(Vec<(Option<Expr>, Vec<Node>)>),
+    For { pattern: Pat, iterable: Expr, body: Vec<Node> },
+    Let { pattern: Pat, value: Expr },
+    Capture { name: String, body: Vec<Node> },
+    Include { path: String, args: Vec<Expr> },
+    // Macros are resolved when rendering, as they can be defined by any template of the set.
+    Use { name: String, args: Vec<Expr> },
+    Block { name: String, body: Vec<Node> },
+    Yield(String),
+    Call { path: String, args: Vec<(String, Expr)>, body: Vec<Node> },
+    Slot,
+    // Value of a directive resolved when the template is parsed, e.g. `@git_sha`, written like
+    // echoed values.
+    Constant(String),
+    // The fallback is rendered with the error message bound to `binding` if the body fails.
+    Try { body: Vec<Node>, binding: Option<String>, fallback: Vec<Node> },
+}
+
+struct ParsedTemplate {
+    nodes: Vec<Node>,
+    // Layout path and arguments of `@extends`, with the index of its part.
+    extends: Option<(String, Vec<Expr>, usize)>,
+}
+
+fn located(template: &Template, part: usize, error: anyhow::Error) -> anyhow::Error {
+    template.diagnostic(template.spans[part].clone(), "invalid-template", error).into()
+}
+
+// Directive ending a body, with the index of its part.
+type BodyEnd = Option<(usize, Directive)>;
+
+// Builds the node tree of a template from its parts.
+struct TreeParser<'a> {
+    template: &'a Template,
+    position: usize,
+    extends: Option<(String, Vec<Expr>, usize)>,
+    build_constants: bool,
+}
+
+impl TreeParser<'_> {
+    fn parse(template: &Template, options: &EngineOptions) -> Result<ParsedTemplate> {
+        if let Some(offset) = template.unterminated_tag {
+            return Err(template.unterminated_tag_error(offset));
+        }
+
+        let mut parser = TreeParser { template, position: 0, extends: None, build_constants: options.build_constants };
+        let (nodes, _) = parser.parse_until(&|_| false)?;
+
+        Ok(ParsedTemplate { nodes, extends: parser.extends })
+    }
+
+    // Parses nodes until a directive accepted by `is_end` or the end of the template.
+    // Returns the nodes and the end directive with the index of its part.
+    fn parse_until(&mut self, is_end: &dyn Fn(&Directive) -> bool) -> Result<(Vec<Node>, BodyEnd)> {
+        let mut nodes = Vec::new();
+
+        while let Some(part) = self.template.parts.get(self.position) {
+            let part_index = self.position;
+            self.position += 1;
+
+            let kind = match part {
+                Part::Text(text) => NodeKind::Text(text.clone()),
+                Part::EchoCode(code) => NodeKind::Echo(parse_expression(code).map_err(|error| self.located(part_index, error))?),
+                Part::Code(code) => {
+                    let directive = Directive::parse(code).map_err(|error| self.located(part_index, error))?;
+
+                    match directive {
+                        Some(directive) if is_end(&directive) => return Ok((nodes, Some((part_index, directive)))),
+                        Some(directive) => match self.parse_directive(directive, part_index)? {
+                            Some(kind) => kind,
+                            None => continue,
+                        },
+                        None if code.trim().is_empty() => continue,
+                        None => {
+                            return Err(self.located(
+                                part_index,
+                                anyhow!("Rust code isn't supported by the dynamic engine, use directives like `@if` and `@for` instead"),
+                            ));
+                        }
+                    }
+                }
+            };
+
+            nodes.push(Node { part: part_index, kind });
+        }
+
+        Ok((nodes, None))
+    }
+
+    fn parse_directive(&mut self, directive: Directive, part: usize) -> Result<Option<NodeKind>> {
+        let kind = match directive {
+            Directive::If(condition) => {
+                let mut branches = Vec::new();
+                let mut condition = Some(parse_expression(&condition).map_err(|error| self.located(part, error))?);
+
+                loop {
+                    let (body, end) = self.parse_until(&|directive| {
+                        matches!(directive, Directive::ElseIf(_) | Directive::Else | Directive::EndIf)
+                    })?;
+                    let has_else = condition.is_none();
+                    branches.push((condition, body));
+
+                    condition = match end {
+                        Some((end_part, Directive::ElseIf(next))) => {
+                            if has_else {
+                                return Err(self.located(end_part, anyhow!("`@else if {next}` found outside of `@if`")));
+                            }
+                            Some(parse_expression(&next).map_err(|error| self.located(end_part, error))?)
+                        }
+                        Some((end_part, Directive::Else)) => {
+                            if has_else {
+                                return Err(self.located(end_part, anyhow!("`@if` has more than one `@else`")));
+                            }
+                            None
+                        }
+                        Some(_) => break,
+                        None => return Err(self.located(part, anyhow!("`@if` is missing its `@endif`"))),
+                    };
+                }
+
+                NodeKind::If(branches)
+            }
+            Directive::For { pattern, iterable } => {
+                let pattern = Pat::parse_single.parse_str(&pattern).map_err(|error| self.located(part, error.into()))?;
+                let iterable = parse_expression(&iterable).map_err(|error| self.located(part, error))?;
+                let body = self.parse_body(part, "@for", "@endfor", |directive| *directive == Directive::EndFor)?;
+
+                NodeKind::For { pattern, iterable, body }
+            }
+            Directive::Let(binding) => {
+                let (pattern, value) = parse_let(&binding).map_err(|error| self.located(part, error))?;
+
+                NodeKind::Let { pattern, value }
+            }
+            Directive::Try => {
+                let (body, end) = self.parse_until(&|directive| matches!(directive, Directive::Rescue(_) | Directive::EndTry))?;
+                let (binding, fallback) = match end {
+                    Some((_, Directive::Rescue(binding))) => {
+                        (binding, self.parse_body(part, "@try", "@endtry", |directive| *directive == Directive::EndTry)?)
+                    }
+                    Some(_) => (None, Vec::new()),
+                    None => return Err(self.located(part, anyhow!("`@try` is missing its `@endtry`"))),
+                };
+
+                NodeKind::Try { body, binding, fallback }
+            }
+            Directive::Capture(name) => {
+                let body = self.parse_body(part, "@capture", "@endcapture", |directive| *directive == Directive::EndCapture)?;
+
+                NodeKind::Capture { name, body }
+            }
+            Directive::Include { path, args } => {
+                let args = parse_expressions(&args).map_err(|error| self.located(part, error))?;
+
+                NodeKind::Include { path: section_path(&self.template.path, &path), args }
+            }
+            Directive::Use { name, args } => {
+                let args = parse_expressions(&args).map_err(|error| self.located(part, error))?;
+
+                NodeKind::Use { name, args }
+            }
+            Directive::Extends { path, args } => {
+                if self.extends.is_some() {
+                    return Err(self.located(part, anyhow!("template `{}` extends more than one layout", self.template.path)));
+                }
+
+                let args = parse_expressions(&args).map_err(|error| self.located(part, error))?;
+                self.extends = Some((path, args, part));
+                return Ok(None);
+            }
+            Directive::Block(name) => {
+                let body = self.parse_body(part, "@block", "@endblock", |directive| *directive == Directive::EndBlock)?;
+
+                NodeKind::Block { name, body }
+            }
+            Directive::Yield(name) => NodeKind::Yield(name),
+            Directive::Call { path, args } => {
+                let args = args
+                    .into_iter()
+                    .map(|(name, value)| Ok((name, parse_expression(&value)?)))
+                    .collect::<Result<Vec<_>>>()
+                    .map_err(|error| self.located(part, error))?;
+                let body = self.parse_body(part, "@call", "@endcall", |directive| *directive == Directive::EndCall)?;
+
+                NodeKind::Call { path: section_path(&self.template.path, &path), args, body }
+            }
+            Directive::Slot => NodeKind::Slot,
+            Directive::Args(_) | Directive::Lint { .. } | Directive::Example(_) => return Ok(None),
+            // Sections are rendered as templates of their own.
+            Directive::Template { .. } => {
+                self.parse_body(part, "@template", "@endtemplate", |directive| *directive == Directive::EndTemplate)?;
+                return Ok(None);
+            }
+            Directive::Def { .. } => {
+                self.parse_body(part, "@def", "@enddef", |directive| *directive == Directive::EndDef)?;
+                return Ok(None);
+            }
+            Directive::Translate { .. } | Directive::TranslatePlural { .. } => {
+                return Err(self.located(part, anyhow!("translations aren't supported by the dynamic engine")));
+            }
+            Directive::Nonce => {
+                return Err(self.located(part, anyhow!("`@nonce` isn't supported by the dynamic engine")));
+            }
+            Directive::Json(_) => {
+                return Err(self.located(part, anyhow!("`@json` isn't supported by the dynamic engine")));
+            }
+            Directive::Csrf => {
+                return Err(self.located(part, anyhow!("`@csrf` isn't supported by the dynamic engine")));
+            }
+            Directive::Url { .. } => {
+                return Err(self.located(part, anyhow!("`@url` isn't supported by the dynamic engine")));
+            }
+            Directive::Cache { .. } => {
+                return Err(self.located(part, anyhow!("`@cache` isn't supported by the dynamic engine")));
+            }
+            Directive::EndCache => return Err(self.located(part, anyhow!("`@endcache` found without matching `@cache`"))),
+            // Evaluated like echoes, since the dynamic engine doesn't generate any code.
+            Directive::Const(code) => NodeKind::Echo(parse_expression(&code).map_err(|error| self.located(part, error))?),
+            Directive::Env(_) | Directive::BuiltAt | Directive::GitSha => {
+                if !self.build_constants {
+                    let error = anyhow!("`@{}` isn't allowed unless `EngineOptions::build_constants` is set", directive.name());
+                    return Err(self.located(part, error));
+                }
+                let value = build_constant(&directive).unwrap().map_err(|error| self.located(part, error))?;
+                NodeKind::Constant(value)
+            }
+            Directive::Cfg(_) => {
+                return Err(self.located(part, anyhow!("`@cfg` isn't supported by the dynamic engine")));
+            }
+            Directive::EndCfg => return Err(self.located(part, anyhow!("`@endcfg` found without matching `@cfg`"))),
+            Directive::Dump(_) => {
+                return Err(self.located(part, anyhow!("`@dump` isn't supported by the dynamic engine")));
+            }
+            Directive::Asset(path) | Directive::AssetSri(path) => {
+                return Err(self.located(part, anyhow!("assets like `{path}` aren't supported by the dynamic engine")));
+            }
+            Directive::Match(_) | Directive::When(_) => {
+                return Err(self.located(part, anyhow!("`@match` isn't supported by the dynamic engine, use `@if` instead")));
+            }
+            Directive::EndMatch => {
+                return Err(self.located(part, anyhow!("`@endmatch` found without matching `@match`")));
+            }
+            Directive::ElseIf(condition) => {
+                return Err(self.located(part, anyhow!("`@else if {condition}` found outside of `@if`")));
+            }
+            Directive::Else => return Err(self.located(part, anyhow!("`@else` found outside of `@if`"))),
+            Directive::EndIf => return Err(self.located(part, anyhow!("`@endif` found without matching `@if`"))),
+            Directive::EndFor => return Err(self.located(part, anyhow!("`@endfor` found without matching `@for`"))),
+            Directive::Rescue(_) => return Err(self.located(part, anyhow!("`@rescue` found outside of `@try`"))),
+            Directive::EndTry => return Err(self.located(part, anyhow!("`@endtry` found without matching `@try`"))),
+            Directive::EndCapture => {
+                return Err(self.located(part, anyhow!("`@endcapture` found without matching `@capture`")));
+            }
+            Directive::EndBlock => return Err(self.located(part, anyhow!("`@endblock` found without matching `@block`"))),
+            Directive::EndCall => return Err(self.located(part, anyhow!("`@endcall` found without matching `@call`"))),
+            Directive::EndTemplate => {
+                return Err(self.located(part, anyhow!("`@endtemplate` found without matching `@template`")));
+            }
+            Directive::EndDef => return Err(self.located(part, anyhow!("`@enddef` found without matching `@def`"))),
+        };
+
+        Ok(Some(kind))
+    }
+
+    fn parse_body(
+        &mut self,
+        part: usize,
+        open: &str,
+        close: &str,
+        is_end: impl Fn(&Directive) -> bool,
+    ) -> Result<Vec<Node>> {
+        let (body, end) = self.parse_until(&is_end)?;
+
+        if end.is_none() {
+            return Err(self.located(part, anyhow!("`{open}` is missing its `{close}`")));
+        }
+
+        Ok(body)
+    }
+
+    fn located(&self, part: usize, error: anyhow::Error) -> anyhow::Error {
+        located(self.template, part, error)
+    }
+}
+
+fn parse_expression(code: &str) -> Result<Expr> {
+    syn::parse_str(code).map_err(|error| anyhow!("invalid expression `{}`: {error}", code.trim()))
+}
+
+fn parse_expressions(code: &str) -> Result<Vec<Expr>> {
+    let expressions = Punctuated::<Expr, syn::Token![,]>::parse_terminated
+        .parse_str(code)
+        .map_err(|error| anyhow!("invalid arguments `{}`: {error}", code.trim()))?;
+
+    Ok(expressions.into_iter().collect())
+}
+
+// Parses the `pattern = value` binding of `@let`.
+fn parse_let(binding: &str) -> Result<(Pat, Expr)> {
+    let Ok(syn::Stmt::Local(local)) = syn::parse_str::<syn::Stmt>(&format!("let {binding};")) else {
+        bail!("expected `@let name = value`, found `@let {binding}`");
+    };
+    let Some(init) = local.init else {
+        bail!("expected `@let name = value`, found `@let {binding}`");
+    };
+
+    Ok((local.pat, *init.expr))
+}
+
+// Binds the names of `pattern` to `value`. Tuple patterns destructure arrays, e.g. the
+// `[key, value]` pairs produced by iterating objects.
+fn bind_pattern(pattern: &Pat, value: Value, scope: &mut Scope) -> Result<()> {
+    match pattern {
+        Pat::Ident(ident) => scope.set(ident.ident.to_string(), value),
+        Pat::Type(typed) => bind_pattern(&typed.pat, value, scope)?,
+        Pat::Reference(reference) => bind_pattern(&reference.pat, value, scope)?,
+        Pat::Wild(_) => {}
+        Pat::Tuple(tuple) => {
+            let Value::Array(items) = value else {
+                bail!("can't destructure `{value}` into `{}`", quote::ToTokens::to_token_stream(pattern));
+            };
+            if items.len() != tuple.elems.len() {
+                bail!("can't destructure {} values into `{}`", items.len(), quote::ToTokens::to_token_stream(pattern));
+            }
+
+            for (pattern, item) in tuple.elems.iter().zip(items) {
+                bind_pattern(pattern, item, scope)?;
+            }
+        }
+        _ => bail!("unsupported pattern `{}`", quote::ToTokens::to_token_stream(pattern)),
+    }
+
+    Ok(())
+}
+
+// Items iterated by `@for`: array items, `[key, value]` pairs of objects.
+fn iterate(value: Value, iterable: &Expr) -> Result<Vec<Value>> {
+    match value {
+        Value::Array(items) => Ok(items),
+        Value::Object(fields) => Ok(fields.into_iter().map(|(key, value)| Value::Array(vec![Value::String(key), value])).collect()),
+        value => bail!("`{}` is not iterable, found `{value}`", expression_text(iterable)),
+    }
+}
+
+// Blocks and slot passed to the template being rendered.
+#[derive(Debug, Default)]
+struct Inputs<'a> {
+    blocks: BTreeMap<String, String>,
+    slot: Option<&'a str>,
+    // Number of templates rendering the template receiving the inputs.
+    depth: usize,
+}
+
+impl Inputs<'_> {
+    // Inputs of a template rendered by the one receiving these inputs.
+    fn nested(&self) -> Inputs<'static> {
+        Inputs { depth: self.depth + 1, ..Inputs::default() }
+    }
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Self::default()
+    }
+
+    pub fn with_options(options: EngineOptions) -> Engine {
+        Engine { set: TemplateSet::new(), options, env: Environment::new() }
+    }
+
+    // Loads all `.plt` files under `dir`, keyed by their path relative to it.
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Engine> {
+        Self::from_dir_with(dir, EngineOptions::default())
+    }
+
+    pub fn from_dir_with(dir: impl AsRef<Path>, options: EngineOptions) -> Result<Engine> {
+        Ok(Engine { set: TemplateSet::load_dir(dir)?, options, env: Environment::new() })
+    }
+
+    pub fn from_template_set(set: TemplateSet, options: EngineOptions) -> Engine {
+        Engine { set, options, env: Environment::new() }
+    }
+
+    // Loads the templates under `paths` using `loader`, along with all templates they depend on.
+    pub fn from_loader(loader: &dyn TemplateLoader, paths: &[&str]) -> Result<Engine> {
+        Self::from_loader_with(loader, paths, EngineOptions::default())
+    }
+
+    pub fn from_loader_with(loader: &dyn TemplateLoader, paths: &[&str], options: EngineOptions) -> Result<Engine> {
+        Ok(Engine { set: TemplateSet::load_from(loader, paths)?, options, env: Environment::new() })
+    }
+
+    pub fn add_template(&mut self, path: impl Into<String>, source: impl Into<String>) {
+        self.set.add_template(path, Vec::new(), source.into());
+    }
+
+    pub fn template_set(&self) -> &TemplateSet {
+        &self.set
+    }
+
+    pub fn env(&self) -> &Environment {
+        &self.env
+    }
+
+    // Globals, functions and filters available to all templates, see `Environment`.
+    pub fn env_mut(&mut self) -> &mut Environment {
+        &mut self.env
+    }
+
+    // Renders the template under `path`. The context has to be an object, whose fields
+    // become the template's variables.
+    pub fn render(&self, path: &str, context: &Value) -> Result<String> {
+        let Value::Object(variables) = context else {
+            bail!("the context has to be a JSON object, found `{context}`");
+        };
+
+        self.set.check_dependencies(path, &mut Vec::new())?;
+
+        self.render_template(path, variables.clone(), &Inputs::default())
+    }
+
+    // Fails if the template under `path` can't be rendered, e.g. for directives which aren't
+    // supported or allowed by the options, without rendering it.
+    pub fn check(&self, path: &str) -> Result<()> {
+        TreeParser::parse(self.template(path)?, &self.options).map(|_| ())
+    }
+
+    fn template(&self, path: &str) -> Result<&Template> {
+        self.set.get(path).with_context(|| format!("template `{path}` is not part of the template set"))
+    }
+
+    fn render_template(&self, path: &str, variables: Map<String, Value>, inputs: &Inputs) -> Result<String> {
+        if inputs.depth > self.options.max_depth {
+            bail!("template `{path}` exceeds the maximum nesting depth of {}", self.options.max_depth);
+        }
+        if crate::sql::is_sql_template(path) {
+            bail!("template `{path}` renders a query, whose parameters only generated code can bind");
+        }
+        let template = self.template(path)?;
+        let parsed = TreeParser::parse(template, &self.options)?;
+        let mut scope = Scope::new(variables, &self.env, self.options.max_range_len);
+        let mut output = String::new();
+
+        let Some((layout, args, part)) = &parsed.extends else {
+            self.render_nodes(template, &parsed.nodes, &mut scope, inputs, &mut output)?;
+            return Ok(output);
+        };
+
+        // Templates extending a layout only render through their blocks.
+        let mut layout_inputs = inputs.nested();
+        for node in &parsed.nodes {
+            match &node.kind {
+                NodeKind::Block { name, body } => {
+                    let mut block = String::new();
+                    self.render_nodes(template, body, &mut scope, inputs, &mut block)?;
+                    layout_inputs.blocks.insert(name.clone(), block);
+                }
+                NodeKind::Let { .. } => self.render_nodes(template, std::slice::from_ref(node), &mut scope, inputs, &mut output)?,
+                NodeKind::Text(text) if text.trim().is_empty() => {}
+                _ => {
+                    let error = anyhow!("found content outside of `@block` in a template which extends a layout");
+                    return Err(located(template, node.part, error));
+                }
+            }
+        }
+
+        let variables = self.bind_args(template, layout, args, &scope).map_err(|error| located(template, *part, error))?;
+        self.render_template(layout, variables, &layout_inputs)
+    }
+
+    // Variables of an included, extended or called template receiving positional `args`.
+    fn bind_args(&self, caller: &Template, path: &str, args: &[Expr], scope: &Scope) -> Result<Map<String, Value>> {
+        if args.is_empty() {
+            return Ok(scope.variables());
+        }
+
+        let arg_names = self.template(path)?.arg_names();
+        if arg_names.len() != args.len() {
+            bail!(
+                "template `{}` passes {} arguments to `{path}`, which declares {}",
+                caller.path,
+                args.len(),
+                arg_names.len()
+            );
+        }
+
+        let mut variables = Map::new();
+        for (name, arg) in arg_names.into_iter().zip(args) {
+            variables.insert(name, self.evaluate(arg, scope)?.unwrap_or_default());
+        }
+
+        Ok(variables)
+    }
+
+    // Value of the expression, or `None` if it uses variables or fields missing from the context
+    // and `EngineOptions::undefined` allows them.
+    fn evaluate(&self, expression: &Expr, scope: &Scope) -> Result<Option<Value>> {
+        match evaluate(expression, scope) {
+            Ok(value) => Ok(Some(value)),
+            Err(error) if self.options.undefined != Undefined::Error && error.is::<UndefinedError>() => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn write_escaped(&self, template: &Template, value: &str, output: &mut String) {
+        let escaping = Escaping::of_template(&template.path).unwrap_or(self.options.escaping);
+
+        match escaping {
+            Escaping::None => output.push_str(value),
+            // The dynamic engine doesn't track the context of echoes, and doesn't render SQL
+            // templates, see `render_template`.
+            Escaping::Html | Escaping::Contextual | Escaping::Sql => output.push_str(&escape_html(value)),
+            Escaping::Xml => output.push_str(&escape_xml(value)),
+            Escaping::Shell => output.push_str(&escape_shell(value)),
+            Escaping::Csv(delimiter) => output.push_str(&escape_csv(value, delimiter)),
+            Escaping::Latex => output.push_str(&escape_latex(value)),
+            Escaping::Toml => output.push_str(&escape_toml(value)),
+            Escaping::Yaml => output.push_str(&escape_yaml(value)),
+            Escaping::Ini => output.push_str(&escape_ini(value)),
+        }
+    }
+
+    fn render_nodes(&self, template: &Template, nodes: &[Node], scope: &mut Scope, inputs: &Inputs, output: &mut String) -> Result<()> {
+        for node in nodes {
+            let at = |error: anyhow::Error| located(template, node.part, error);
+
+            match &node.kind {
+                NodeKind::Text(text) => output.push_str(text),
+                NodeKind::Echo(expression) => match evaluate(expression, scope) {
+                    Ok(value) => self.write_escaped(template, &display(&value), output),
+                    Err(error) => match (self.options.undefined, error.downcast_ref::<UndefinedError>()) {
+                        (Undefined::Empty, Some(_)) => {}
+                        (Undefined::Placeholder, Some(undefined)) => {
+                            self.write_escaped(template, &format!("[[missing: {}]]", undefined.path), output);
+                        }
+                        _ => return Err(at(error)),
+                    },
+                },
+                NodeKind::Constant(value) => self.write_escaped(template, value, output),
+                NodeKind::If(branches) => {
+                    for (condition, body) in branches {
+                        let is_taken = match condition {
+                            Some(condition) => self.evaluate(condition, scope).map_err(at)?.is_some_and(|value| is_truthy(&value)),
+                            None => true,
+                        };
+
+                        if is_taken {
+                            scope.push();
+                            let result = self.render_nodes(template, body, scope, inputs, output);
+                            scope.pop();
+                            result?;
+                            break;
+                        }
+                    }
+                }
+                NodeKind::For { pattern, iterable, body } => {
+                    let items = match self.evaluate(iterable, scope).map_err(at)? {
+                        Some(value) => iterate(value, iterable).map_err(at)?,
+                        None => Vec::new(),
+                    };
+
+                    for item in items {
+                        scope.push();
+                        let result = bind_pattern(pattern, item, scope)
+                            .map_err(at)
+                            .and_then(|()| self.render_nodes(template, body, scope, inputs, output));
+                        scope.pop();
+                        result?;
+                    }
+                }
+                NodeKind::Let { pattern, value } => {
+                    let value = self.evaluate(value, scope).map_err(at)?.unwrap_or_default();
+                    bind_pattern(pattern, value, scope).map_err(at)?;
+                }
+                NodeKind::Capture { name, body } => {
+                    let mut captured = String::new();
+                    scope.push();
+                    let result = self.render_nodes(template, body, scope, inputs, &mut captured);
+                    scope.pop();
+                    result?;
+
+                    scope.set(name.clone(), Value::String(captured));
+                }
+                NodeKind::Try { body, binding, fallback } => {
+                    let mut section = String::new();
+                    scope.push();
+                    let result = self.render_nodes(template, body, scope, inputs, &mut section);
+                    scope.pop();
+
+                    let Err(error) = result else {
+                        output.push_str(&section);
+                        continue;
+                    };
+
+                    scope.push();
+                    if let Some(binding) = binding {
+                        scope.set(binding.clone(), Value::String(format!("{error:#}")));
+                    }
+                    let result = self.render_nodes(template, fallback, scope, inputs, output);
+                    scope.pop();
+                    result?;
+                }
+                NodeKind::Include { path, args } => {
+                    let variables = self.bind_args(template, path, args, scope).map_err(at)?;
+                    output.push_str(&self.render_template(path, variables, &inputs.nested())?);
+                }
+                NodeKind::Use { name, args } => {
+                    let path = self.set.macro_path(&template.path, name).map_err(at)?;
+                    let variables = self.bind_args(template, &path, args, scope).map_err(at)?;
+                    output.push_str(&self.render_template(&path, variables, &inputs.nested())?);
+                }
+                NodeKind::Block { name, body } => match inputs.blocks.get(name) {
+                    Some(block) => output.push_str(block),
+                    None => self.render_nodes(template, body, scope, inputs, output)?,
+                },
+                NodeKind::Yield(name) => {
+                    if let Some(block) = inputs.blocks.get(name) {
+                        output.push_str(block);
+                    }
+                }
+                NodeKind::Call { path, args, body } => {
+                    let mut slot = String::new();
+                    scope.push();
+                    let result = self.render_nodes(template, body, scope, inputs, &mut slot);
+                    scope.pop();
+                    result?;
+
+                    let variables = if args.is_empty() {
+                        scope.variables()
+                    } else {
+                        let mut variables = Map::new();
+                        for (name, value) in args {
+                            variables.insert(name.clone(), self.evaluate(value, scope).map_err(at)?.unwrap_or_default());
+                        }
+                        variables
+                    };
+
+                    let inputs = Inputs { slot: Some(&slot), ..inputs.nested() };
+                    output.push_str(&self.render_template(path, variables, &inputs)?);
+                }
+                NodeKind::Slot => output.push_str(inputs.slot.unwrap_or_default()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dynamic::{Engine, EngineOptions, Undefined};
+    use crate::prelude::*;
+    use serde_json::json;
+
+    fn render(source: &str, context: serde_json::Value) -> Result<String> {
+        let mut engine = Engine::new();
+        engine.add_template("index.plt", source);
+        engine.render("index.plt", &context)
+    }
+
+    #[test]
+    fn it_renders_expressions() {
+        let output = render("Hello <?= user.name | upper ?>, you have <?= messages.len() ?> messages", json!({
+            "user": { "name": "Ann" },
+            "messages": ["a", "b"],
+        }));
+
+        assert_eq!(output.unwrap(), "Hello ANN, you have 2 messages");
+    }
+
+    #[test]
+    fn it_renders_control_flow() {
+        let source = "<?rs @for item in items ?><?= item.name ?><?rs @if item.stock == 0 ?> (sold out)<?rs @else if item.stock < 5 ?> (few left)<?rs @else ?><?rs @endif ?>;<?rs @endfor ?>";
+        let items = json!([{ "name": "a", "stock": 0 }, { "name": "b", "stock": 2 }, { "name": "c", "stock": 9 }]);
+        assert_eq!(render(source, json!({ "items": items })).unwrap(), "a (sold out);b (few left);c;");
+
+        let source = "<?rs @for (name, price) in prices ?><?rs @let total = price * 2 ?><?= name ?>=<?= total ?> <?rs @endfor ?>";
+        assert_eq!(render(source, json!({ "prices": { "a": 1, "b": 2 } })).unwrap(), "a=2 b=4 ");
+
+        let source = "<?rs @capture title ?>Hi <?= name ?><?rs @endcapture ?><?= title | upper ?>";
+        assert_eq!(render(source, json!({ "name": "Ann" })).unwrap(), "HI ANN");
+    }
+
+    #[test]
+    fn it_renders_fallbacks_of_failing_sections() {
+        let source = "<?rs @try ?>a<?= missing ?><?rs @rescue error ?>(<?= error ?>)<?rs @endtry ?><?rs @try ?>b<?rs @endtry ?>";
+        assert_eq!(render(source, json!({})).unwrap(), "(index.plt:1:17: undefined variable `missing`)b");
+
+        let source = "<?rs @try ?><?= missing ?><?rs @endtry ?>c";
+        assert_eq!(render(source, json!({})).unwrap(), "c");
+        assert_eq!(render("<?rs @try ?>", json!({})).unwrap_err().to_string(), "index.plt:1:5: `@try` is missing its `@endtry`");
+    }
+
+    #[test]
+    fn it_renders_includes_layouts_and_components() {
+        let mut engine = Engine::new();
+        engine.add_template("layout.plt", "<?rs @args title: &str ?><title><?= title ?></title><?rs @block content ?>default<?rs @endblock ?>");
+        engine.add_template("header.plt", "<h1><?= user ?></h1>");
+        engine.add_template("card.plt", "<div class=\"<?= class ?>\"><?rs @slot ?></div>");
+        engine.add_template(
+            "index.plt",
+            "<?rs @extends \"layout.plt\" with (user + \"'s page\") ?>\n<?rs @block content ?><?rs @include \"header.plt\" ?><?rs @call \"card.plt\" (class = \"card\") ?>Hi <?= user ?><?rs @endcall ?><?rs @endblock ?>",
+        );
+
+        let output = engine.render("index.plt", &json!({ "user": "Ann" })).unwrap();
+
+        assert_eq!(output, "<title>Ann's page</title><h1>Ann</h1><div class=\"card\">Hi Ann</div>");
+    }
+
+    #[test]
+    fn it_renders_sections_of_templates() {
+        let mut engine = Engine::new();
+        engine.add_template(
+            "components.plt",
+            "<?rs @template badge(label: &str) ?>[<?= label ?>]<?rs @endtemplate ?><?rs @template card(title: &str) ?><div><?rs @include \"#badge\" with (title) ?></div><?rs @endtemplate ?>",
+        );
+
+        assert_eq!(engine.render("components.plt", &json!({})).unwrap(), "");
+        assert_eq!(engine.render("components.plt#card", &json!({ "title": "New" })).unwrap(), "<div>[New]</div>");
+    }
+
+    #[test]
+    fn it_renders_macros_defined_by_other_templates() {
+        let mut engine = Engine::new();
+        engine.add_template("macros.plt", "<?rs @def badge(label: &str) ?>[<?= label ?>]<?rs @enddef ?>");
+        engine.add_template("index.plt", "<?rs @for item in items ?><?rs @use badge(item) ?><?rs @endfor ?><?rs @use missing ?>");
+
+        assert_eq!(engine.render("macros.plt#badge", &json!({ "label": "a" })).unwrap(), "[a]");
+        let error = engine.render("index.plt", &json!({ "items": ["a"] })).unwrap_err();
+        assert_eq!(error.to_string(), "template `index.plt` uses macro `missing`, which is not defined by `@def` in the template set");
+
+        engine.add_template("index.plt", "<?rs @for item in items ?><?rs @use badge(item) ?><?rs @endfor ?>");
+        assert_eq!(engine.render("index.plt", &json!({ "items": ["a", "b"] })).unwrap(), "[a][b]");
+    }
+
+    #[test]
+    fn it_renders_recursive_templates_up_to_the_maximum_depth() {
+        let mut engine = Engine::with_options(EngineOptions { max_depth: 3, ..EngineOptions::default() });
+        engine.add_template("tree.plt", "<?rs @args children: &[Node] ?><ul><?rs @for child in children ?><li><?= child.name ?><?rs @include \"tree.plt\" with (child.children) ?></li><?rs @endfor ?></ul>");
+        engine.add_template("index.plt", "<?rs @include \"tree.plt\" with (tree) ?>");
+
+        let tree = json!({ "tree": [{ "name": "a", "children": [{ "name": "b", "children": [] }] }] });
+        assert_eq!(engine.render("index.plt", &tree).unwrap(), "<ul><li>a<ul><li>b<ul></ul></li></ul></li></ul>");
+
+        let deep = json!({ "tree": [{ "name": "a", "children": [{ "name": "b", "children": [{ "name": "c", "children": [] }] }] }] });
+        let error = engine.render("index.plt", &deep).unwrap_err();
+        assert_eq!(error.to_string(), "template `tree.plt` exceeds the maximum nesting depth of 3");
+    }
+
+    #[test]
+    fn it_renders_templates_of_loaders() {
+        let mut loader = MemoryLoader::new();
+        loader.add_template("index.plt", "<?rs @include \"header.plt\" ?><p>Hi</p>");
+        loader.add_template("header.plt", "<h1><?= title ?></h1>");
+
+        let engine = Engine::from_loader(&loader, &["index.plt"]).unwrap();
+        assert_eq!(engine.render("index.plt", &json!({ "title": "Home" })).unwrap(), "<h1>Home</h1><p>Hi</p>");
+    }
+
+    #[test]
+    fn it_renders_with_the_environment() {
+        let mut engine = Engine::new();
+        engine.env_mut().add_global("site_name", "Example");
+        engine.env_mut().add_function("asset_url", |args| Ok(format!("/assets/{}", args[0].as_str().unwrap_or("")).into()));
+        engine.env_mut().add_filter("money", |value, _| Ok(format!("${:.2}", value.as_f64().unwrap_or(0.0)).into()));
+        engine.add_template("head.plt", "<?rs @args title: &str ?><title><?= title ?> - <?= site_name ?></title>");
+        engine.add_template("index.plt", "<?rs @include \"head.plt\" with (\"Home\") ?><link href=\"<?= asset_url(\"app.css\") ?>\"><?= price | money ?>");
+
+        let output = engine.render("index.plt", &json!({ "price": 4.5 })).unwrap();
+
+        assert_eq!(output, "<title>Home - Example</title><link href=\"/assets/app.css\">$4.50");
+    }
+
+    #[test]
+    fn it_renders_undefined_values_as_configured() {
+        let source = "<p><?= user.name ?>|<?= missing | upper ?>|<?= user[\"email\"] ?></p><?rs @if user.admin ?>admin<?rs @endif ?><?rs @for item in items ?><?= item ?><?rs @endfor ?>";
+        let render_with = |undefined| {
+            let mut engine = Engine::with_options(EngineOptions { undefined, ..EngineOptions::default() });
+            engine.add_template("index.plt", source);
+            engine.render("index.plt", &json!({ "user": {} }))
+        };
+
+        assert_eq!(render_with(Undefined::Error).unwrap_err().to_string(), "index.plt:1:7: `user` has no field `name`");
+        assert_eq!(render_with(Undefined::Empty).unwrap(), "<p>||</p>");
+        assert_eq!(render_with(Undefined::Placeholder).unwrap(), "<p>[[missing: user.name]]|[[missing: missing]]|[[missing: user.email]]</p>");
+        assert!("strict".parse::<Undefined>().unwrap_err().to_string().starts_with("unknown undefined behavior `strict`"));
+    }
+
+    #[test]
+    fn it_evaluates_build_constants_only_when_allowed() {
+        let mut engine = Engine::new();
+        engine.add_template("index.plt", "<?rs @env \"CARGO_PKG_NAME\" ?>");
+        assert_eq!(
+            engine.render("index.plt", &json!({})).unwrap_err().to_string(),
+            "index.plt:1:5: `@env` isn't allowed unless `EngineOptions::build_constants` is set"
+        );
+        assert!(engine.check("index.plt").is_err());
+
+        let mut engine = Engine::with_options(EngineOptions { build_constants: true, ..EngineOptions::default() });
+        engine.add_template("index.plt", "<?rs @env \"CARGO_PKG_NAME\" ?>");
+        assert_eq!(engine.render("index.plt", &json!({})).unwrap(), "plt");
+    }
+
+    #[test]
+    fn it_escapes_echoed_values() {
+        let mut engine = Engine::with_options(EngineOptions { escaping: Escaping::Html, ..EngineOptions::default() });
+        engine.add_template("index.plt", "<b><?= name ?></b>");
+
+        assert_eq!(engine.render("index.plt", &json!({ "name": "<i>" })).unwrap(), "<b>&lt;i&gt;</b>");
+
+        engine.add_template("feed.xml.plt", "<title><?= name ?></title>");
+        engine.add_template("run.sh.plt", "echo <?= name ?>");
+        assert_eq!(engine.render("feed.xml.plt", &json!({ "name": "Tom's" })).unwrap(), "<title>Tom&apos;s</title>");
+        assert_eq!(engine.render("run.sh.plt", &json!({ "name": "$(id)'" })).unwrap(), "echo '$(id)'\\'''");
+
+        engine.add_template("users.sql.plt", "SELECT * FROM users WHERE name = <?= name ?>");
+        assert_eq!(
+            engine.render("users.sql.plt", &json!({ "name": "Ann" })).unwrap_err().to_string(),
+            "template `users.sql.plt` renders a query, whose parameters only generated code can bind"
+        );
+    }
+
+    #[test]
+    fn it_reports_errors_with_locations() {
+        assert_eq!(render("\n<?= user.name ?>", json!({})).unwrap_err().to_string(), "index.plt:2:4: undefined variable `user`");
+        assert_eq!(
+            render("<?rs let a = 1; ?>", json!({})).unwrap_err().to_string(),
+            "index.plt:1:5: Rust code isn't supported by the dynamic engine, use directives like `@if` and `@for` instead"
+        );
+        assert_eq!(render("<?rs @if a ?>", json!({})).unwrap_err().to_string(), "index.plt:1:5: `@if` is missing its `@endif`");
+        assert_eq!(render("<?rs @endfor ?>", json!({})).unwrap_err().to_string(), "index.plt:1:5: `@endfor` found without matching `@for`");
+        assert_eq!(
+            render("<?rs @for x in count ?><?rs @endfor ?>", json!({ "count": 3 })).unwrap_err().to_string(),
+            "index.plt:1:5: `count` is not iterable, found `3`"
+        );
+        assert_eq!(render("", json!([])).unwrap_err().to_string(), "the context has to be a JSON object, found `[]`");
+    }
+}
@@ -0,0 +1,363 @@
+// Email templates: `.email.plt` files render the HTML body of an email, and additionally get a
+// `<name>_text` function rendering its plain text body, which transactional emails need as well:
+//
+//     let html = templates::welcome_email(&user.name)?;
+//     let text = templates::welcome_email_text(&user.name)?;
+//
+// The text is derived from the text parts of the template, without tags, with links written as
+// `label (url)` and line breaks where block elements start and end. Echoes inside of tags are
+// dropped, and the others are rendered without escaping. A `<name>.txt.plt` file next to the
+// email template, e.g. `welcome.txt.plt` for `welcome.email.plt`, is rendered instead when the
+// derived text isn't good enough. It takes the arguments of the email template.
+//
+// With plt's `email` feature, `plt::mail` assembles both bodies into a `lettre` message.
+use crate::prelude::*;
+
+// Stands for code and echo parts in the text of a template.
+const HOLE: char = '\u{0}';
+
+// Elements whose content isn't part of the text.
+const HIDDEN_ELEMENTS: [&str; 4] = ["head", "script", "style", "title"];
+
+// Elements starting and ending paragraphs of the text.
+const BLOCK_ELEMENTS: [&str; 22] = [
+    "address", "article", "aside", "blockquote", "body", "div", "dl", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6", "header",
+    "hr", "main", "ol", "p", "section", "ul",
+];
+
+// Whether the template under `path` renders an email, e.g. `emails/welcome.email.plt`.
+pub fn is_email_template(path: &str) -> bool {
+    path.ends_with(".email.plt")
+}
+
+// Path of the template rendering the text body of the email template under `path`, if there is
+// one, e.g. `emails/welcome.txt.plt` for `emails/welcome.email.plt`.
+pub fn text_template_path(path: &str) -> String {
+    format!("{}.txt.plt", path.strip_suffix(".email.plt").unwrap_or(path))
+}
+
+// `parts` with their text turned from HTML into plain text. Parts stay at the same indices, so
+// they still match the spans of the template, with echoes which end up inside of tags emptied.
+pub fn plain_text_parts(parts: &[Part]) -> Vec<Part> {
+    let mut stream = String::new();
+    for part in parts {
+        match part {
+            Part::Text(text) => stream.push_str(text),
+            _ => stream.push(HOLE),
+        }
+    }
+
+    let mut converter = PlainText::default();
+    converter.convert(&stream);
+    let mut pieces = converter.text.split(HOLE);
+    let mut hidden_holes = converter.hidden_holes.into_iter();
+
+    // The text between two holes goes into the first text part between them.
+    let mut piece = pieces.next();
+    let mut parts = parts.to_vec();
+    for part in &mut parts {
+        if let Part::Text(text) = part {
+            *text = piece.take().unwrap_or_default().to_string();
+            continue;
+        }
+
+        if hidden_holes.next() == Some(true) && matches!(part, Part::EchoCode(_)) {
+            *part = Part::Text(String::new());
+        }
+        piece = pieces.next();
+    }
+
+    parts
+}
+
+#[derive(Debug, Default)]
+struct PlainText {
+    text: String,
+    // Whether each hole is inside of a tag or of a hidden element.
+    hidden_holes: Vec<bool>,
+    // Target of the link being written, along with where its label starts in the text.
+    link: Option<(String, usize)>,
+}
+
+impl PlainText {
+    fn convert(&mut self, stream: &str) {
+        let mut hidden: Option<&str> = None;
+        let mut offset = 0;
+
+        while let Some(c) = stream[offset..].chars().next() {
+            let rest = &stream[offset..];
+
+            if c == HOLE {
+                self.text.push(HOLE);
+                self.hidden_holes.push(hidden.is_some());
+                offset += 1;
+                continue;
+            }
+            if let Some(name) = hidden {
+                if starts_with_ignore_case(rest, &format!("</{name}")) {
+                    hidden = None;
+                } else {
+                    offset += c.len_utf8();
+                    continue;
+                }
+            }
+
+            if rest.starts_with("<!--") {
+                let len = rest.find("-->").map_or(rest.len(), |end| end + 3);
+                self.skip(&rest[..len]);
+                offset += len;
+                continue;
+            }
+            if c == '<' && rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/') {
+                if let Some(len) = tag_len(rest) {
+                    let tag = &rest[..len];
+                    self.skip(tag);
+                    hidden = self.tag(tag);
+                    offset += len;
+                    continue;
+                }
+            }
+
+            if c == '&' {
+                if let Some((decoded, len)) = decode_entity(rest) {
+                    match decoded {
+                        ' ' | '\u{a0}' => self.space(),
+                        decoded => self.text.push(decoded),
+                    }
+                    offset += len;
+                    continue;
+                }
+            }
+
+            match c.is_whitespace() {
+                true => self.space(),
+                false => self.text.push(c),
+            }
+            offset += c.len_utf8();
+        }
+
+        let len = self.text.trim_end_matches([' ', '\n']).len();
+        self.text.truncate(len);
+        if !self.text.is_empty() {
+            self.text.push('\n');
+        }
+    }
+
+    // Writes what `tag` stands for, returning the name of the element whose content is hidden
+    // when it opens one.
+    fn tag(&mut self, tag: &str) -> Option<&'static str> {
+        let closing = tag.starts_with("</");
+        let name: String = tag.trim_start_matches(['<', '/']).chars().take_while(char::is_ascii_alphanumeric).collect();
+        let name = name.to_ascii_lowercase();
+
+        match name.as_str() {
+            name if !closing && HIDDEN_ELEMENTS.contains(&name) => return HIDDEN_ELEMENTS.into_iter().find(|hidden| *hidden == name),
+            "br" => self.newlines(1),
+            "li" if !closing => {
+                self.newlines(1);
+                self.text.push_str("- ");
+            }
+            "tr" | "li" | "dt" | "dd" => self.newlines(1),
+            "td" | "th" if closing => self.space(),
+            "a" if closing => {
+                if let Some((href, start)) = self.link.take() {
+                    if self.text[start..].trim() != href {
+                        self.text.push_str(&format!(" ({href})"));
+                    }
+                }
+            }
+            "a" => {
+                let href = attribute(tag, "href").filter(|href| !href.is_empty() && !href.starts_with('#') && !tag.contains(HOLE));
+                self.link = href.map(|href| (href, self.text.len()));
+            }
+            "img" => {
+                if let Some(alt) = attribute(tag, "alt").filter(|_| !tag.contains(HOLE)) {
+                    self.text.push_str(&alt);
+                }
+            }
+            name if BLOCK_ELEMENTS.contains(&name) => self.newlines(2),
+            _ => {}
+        }
+
+        None
+    }
+
+    // Keeps the holes of markup which isn't part of the text, as hidden ones.
+    fn skip(&mut self, markup: &str) {
+        for _ in markup.matches(HOLE) {
+            self.text.push(HOLE);
+            self.hidden_holes.push(true);
+        }
+    }
+
+    // Collapses whitespace into a single space, which is dropped at the start of lines.
+    fn space(&mut self) {
+        if !self.text.is_empty() && !self.text.ends_with([' ', '\n']) {
+            self.text.push(' ');
+        }
+    }
+
+    // Ends the current line with at least `count` line breaks, i.e. `count - 1` empty lines.
+    fn newlines(&mut self, count: usize) {
+        let len = self.text.trim_end_matches(' ').len();
+        self.text.truncate(len);
+        if self.text.is_empty() {
+            return;
+        }
+
+        let trailing = self.text.len() - self.text.trim_end_matches('\n').len();
+        for _ in trailing..count {
+            self.text.push('\n');
+        }
+    }
+}
+
+// Length of the tag at the start of `text`, up to its `>` outside of attribute values.
+fn tag_len(text: &str) -> Option<usize> {
+    let mut quote = None;
+    for (offset, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(open), _) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return Some(offset + 1),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// Value of the attribute `name` of `tag`, with its entities decoded.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut search = 0;
+
+    while let Some(found) = lower[search..].find(name).map(|found| search + found) {
+        search = found + name.len();
+        if !lower[..found].ends_with(char::is_whitespace) {
+            continue;
+        }
+        let Some(value) = tag[search..].trim_start().strip_prefix('=').map(str::trim_start) else {
+            continue;
+        };
+
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..].split(quote).next().unwrap_or_default(),
+            _ => value.split(|c: char| c.is_whitespace() || c == '>').next().unwrap_or_default(),
+        };
+        return Some(decode_entities(value));
+    }
+
+    None
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut offset = 0;
+
+    while let Some(c) = text[offset..].chars().next() {
+        match decode_entity(&text[offset..]).filter(|_| c == '&') {
+            Some((entity, len)) => {
+                decoded.push(entity);
+                offset += len;
+            }
+            None => {
+                decoded.push(c);
+                offset += c.len_utf8();
+            }
+        }
+    }
+
+    decoded
+}
+
+// Char of the entity at the start of `text`, along with its length.
+fn decode_entity(text: &str) -> Option<(char, usize)> {
+    let end = text.get(..12).unwrap_or(text).find(';')?;
+    let entity = &text[1..end];
+
+    let c = match entity {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        _ => {
+            let code = match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => entity.strip_prefix('#')?.parse().ok()?,
+            };
+            char::from_u32(code)?
+        }
+    };
+
+    Some((c, end + 1))
+}
+
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.get(..prefix.len()).is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    fn texts(parts: &[Part]) -> Vec<String> {
+        parts.iter().map(|part| part.get_content().to_string()).collect()
+    }
+
+    #[test]
+    fn it_derives_plain_text_from_html() {
+        let parts = [
+            Part::Text("<html><head><title>Welcome</title><style>p { color: red; }</style></head>\n<body>\n  <h1>Hello ".to_string()),
+            Part::EchoCode(" name ".to_string()),
+            Part::Text("!</h1>\n  <p>Thanks for   signing up &amp; welcome.<br>Start <a href=\"https://example.com/start?a=1&amp;b=2\">here</a>.</p>\n  <ul>\n    <li>One</li>\n    <li>Two</li>\n  </ul>\n  <p><a href=\"".to_string()),
+            Part::EchoCode(" url ".to_string()),
+            Part::Text("\">Confirm</a> <img src=\"logo.png\" alt=\"Logo\"></p>\n</body></html>\n".to_string()),
+        ];
+
+        assert_eq!(
+            texts(&plain_text_parts(&parts)),
+            vec![
+                "Hello ",
+                " name ",
+                "!\n\nThanks for signing up & welcome.\nStart here (https://example.com/start?a=1&b=2).\n\n- One\n- Two\n\n",
+                "",
+                "Confirm Logo\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_keeps_code_parts_in_place() {
+        let parts = [
+            Part::Text("<p>".to_string()),
+            Part::Code(" @if admin ".to_string()),
+            Part::Text("<b>Admin</b>".to_string()),
+            Part::Code(" @endif ".to_string()),
+            Part::Text("</p>".to_string()),
+        ];
+
+        assert_eq!(texts(&plain_text_parts(&parts)), vec!["", " @if admin ", "Admin", " @endif ", "\n"]);
+    }
+
+    #[test]
+    fn it_generates_text_functions_of_email_templates() {
+        let mut set = TemplateSet::new();
+        set.add_template("welcome.email.plt", vec!["name: &str".to_string()], "<p>Hi <?= name ?>, <b>welcome</b>!</p>".to_string());
+        set.add_template("reset.email.plt", vec!["url: &str".to_string()], "<p><a href=\"<?= url ?>\">Reset</a></p>".to_string());
+        set.add_template("reset.txt.plt", vec!["url: &str".to_string()], "Reset your password at <?= url ?>".to_string());
+        let options = GeneratorOptions { escaping: Escaping::Html, ..GeneratorOptions::default() };
+
+        let welcome = format_code(&set.generate_with("welcome.email.plt", &options).unwrap().join("\n"));
+        assert!(welcome.contains("pub fn welcome_email_text(name: &str) -> plt::prelude::Result<String> {"));
+        assert!(welcome.contains("write!(output_buffer, \"{}\", \"Hi \")?;\n    write!(output_buffer, \"{}\", (name))?;"));
+        assert!(welcome.contains("write!(output_buffer, \"{}\", \", welcome!\\n\")?;"));
+
+        let reset = format_code(&set.generate_with("reset.email.plt", &options).unwrap().join("\n"));
+        assert!(reset.contains("pub fn reset_email_text(url: &str) -> plt::prelude::Result<String> {"));
+        assert!(reset.contains("\"Reset your password at \""));
+    }
+}
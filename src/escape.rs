@@ -0,0 +1,684 @@
+use core::fmt;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::cell::Cell;
+#[cfg(not(feature = "std"))]
+use alloc::{format, string::{String, ToString}};
+use anyhow::bail;
+pub use crate::prelude::*;
+
+// How the generated code escapes echoed values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Escaping {
+    // Values are written as they are.
+    #[default]
+    None,
+    // `&`, `<`, `>`, `"` and `'` are replaced with HTML entities.
+    Html,
+    // Escaped depending on where in the HTML the value is echoed, see `HtmlContext`.
+    Contextual,
+    // `&`, `<`, `>`, `"` and `'` are replaced with XML entities, and characters XML documents
+    // can't contain are dropped, see `plt::xml`.
+    Xml,
+    // Values are single-quoted for POSIX shells, see `plt::shell`.
+    Shell,
+    // Values are bound as parameters of the query, with placeholders written instead of them,
+    // see `plt::sql`. Only used by SQL templates, as their functions return a query.
+    Sql,
+    // Values are written as fields separated by the delimiter, quoted when they contain it, a
+    // quote or a line break, see `plt::csv`.
+    Csv(char),
+    // LaTeX special characters are escaped by backslashes or replaced with commands, see
+    // `plt::latex`.
+    Latex,
+    // Values are escaped for double-quoted strings of TOML, YAML or INI files, see `plt::config`.
+    Toml,
+    Yaml,
+    Ini,
+}
+
+impl Escaping {
+    // Expression written by the generated code for `<?= code ?>`. Contextual escaping depends on
+    // the echo site, so it's resolved by `HtmlContext::echo_expression` instead.
+    pub fn echo_expression(&self, code: &str) -> String {
+        match self {
+            Escaping::None => format!("({code})"),
+            Escaping::Html | Escaping::Contextual => format!("plt::prelude::EscapeHtml(&({code}))"),
+            Escaping::Xml => format!("plt::prelude::EscapeXml(&({code}))"),
+            Escaping::Shell => format!("plt::prelude::EscapeShell(&({code}))"),
+            Escaping::Sql => format!("sql_params.bind(&({code}))"),
+            Escaping::Csv(delimiter) => format!("plt::prelude::EscapeCsv(&({code}), {delimiter:?})"),
+            Escaping::Latex => format!("plt::prelude::EscapeLatex(&({code}))"),
+            Escaping::Toml => format!("plt::prelude::EscapeToml(&({code}))"),
+            Escaping::Yaml => format!("plt::prelude::EscapeYaml(&({code}))"),
+            Escaping::Ini => format!("plt::prelude::EscapeIni(&({code}))"),
+        }
+    }
+
+    // Escaping of the templates under `path` which render a format other than HTML, used
+    // whatever the escaping of the other templates is.
+    #[cfg(feature = "std")]
+    pub fn of_template(path: &str) -> Option<Escaping> {
+        if crate::xml::is_xml_template(path) {
+            Some(Escaping::Xml)
+        } else if crate::shell::is_shell_template(path) {
+            Some(Escaping::Shell)
+        } else if crate::sql::is_sql_template(path) {
+            Some(Escaping::Sql)
+        } else if crate::csv::is_csv_template(path) {
+            Some(Escaping::Csv(crate::csv::delimiter(path, ',')))
+        } else if crate::latex::is_latex_template(path) {
+            Some(Escaping::Latex)
+        } else {
+            crate::config::ConfigFormat::of_template(path).map(crate::config::ConfigFormat::escaping)
+        }
+    }
+}
+
+impl FromStr for Escaping {
+    type Err = anyhow::Error;
+
+    fn from_str(escaping: &str) -> Result<Escaping> {
+        match escaping {
+            "none" => Ok(Escaping::None),
+            "html" => Ok(Escaping::Html),
+            "contextual" => Ok(Escaping::Contextual),
+            "xml" => Ok(Escaping::Xml),
+            "shell" => Ok(Escaping::Shell),
+            "csv" => Ok(Escaping::Csv(',')),
+            "tsv" => Ok(Escaping::Csv('\t')),
+            "latex" => Ok(Escaping::Latex),
+            "toml" => Ok(Escaping::Toml),
+            "yaml" => Ok(Escaping::Yaml),
+            "ini" => Ok(Escaping::Ini),
+            _ => bail!("unknown escaping mode `{escaping}`, expected `none`, `html`, `contextual`, `xml`, `shell`, `csv`, `tsv`, `latex`, `toml`, `yaml` or `ini`"),
+        }
+    }
+}
+
+// Displays the wrapped value with HTML special characters replaced by entities.
+#[derive(Debug, Clone, Copy)]
+pub struct EscapeHtml<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for EscapeHtml<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let _depth = DepthGuard::set(&ESCAPE_DEPTH, ESCAPE_DEPTH.get() + 1);
+
+        fmt::Write::write_fmt(&mut EscapingWriter::new(f, html_entity), format_args!("{}", self.0))
+    }
+}
+
+// Displays the wrapped value with XML special characters replaced by entities, dropping the
+// characters XML 1.0 doesn't allow, e.g. most control characters.
+#[derive(Debug, Clone, Copy)]
+pub struct EscapeXml<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for EscapeXml<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let _depth = DepthGuard::set(&ESCAPE_DEPTH, ESCAPE_DEPTH.get() + 1);
+
+        fmt::Write::write_fmt(&mut EscapingWriter::new(f, xml_entity), format_args!("{}", self.0))
+    }
+}
+
+// Displays the wrapped value as a single word of a POSIX shell command, quoted by `'`. `Raw`
+// parts of the value are written as they are, outside of the quotes.
+#[derive(Debug, Clone, Copy)]
+pub struct EscapeShell<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for EscapeShell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let _depth = DepthGuard::set(&ESCAPE_DEPTH, ESCAPE_DEPTH.get() + 1);
+
+        let mut writer = ShellWriter { f, depth: ESCAPE_DEPTH.get(), quoted: None, written: false };
+        fmt::Write::write_fmt(&mut writer, format_args!("{}", self.0))?;
+        match (writer.quoted.take(), writer.written) {
+            (Some(quoted), _) => writer.write_quoted(&quoted),
+            // An empty word, which would otherwise vanish.
+            (None, false) => writer.f.write_str("''"),
+            (None, true) => Ok(()),
+        }
+    }
+}
+
+// Collects the text to quote, so consecutive writes end up in the same quotes.
+struct ShellWriter<'a, 'b> {
+    f: &'a mut fmt::Formatter<'b>,
+    depth: usize,
+    quoted: Option<String>,
+    written: bool,
+}
+
+impl ShellWriter<'_, '_> {
+    // `'` can't be escaped inside of single quotes, so the quotes are closed around an escaped one.
+    // NUL bytes can't be part of arguments and are dropped.
+    fn write_quoted(&mut self, text: &str) -> fmt::Result {
+        write!(self.f, "'{}'", text.replace('\0', "").replace('\'', "'\\''"))
+    }
+}
+
+impl fmt::Write for ShellWriter<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.is_empty() {
+            return Ok(());
+        }
+
+        self.written = true;
+        if self.depth > RAW_DEPTH.get() {
+            self.quoted.get_or_insert_with(String::new).push_str(s);
+            return Ok(());
+        }
+
+        if let Some(quoted) = self.quoted.take() {
+            self.write_quoted(&quoted)?;
+        }
+        self.f.write_str(s)
+    }
+}
+
+// Displays the wrapped value as LaTeX text, with the characters LaTeX gives a meaning to escaped.
+#[derive(Debug, Clone, Copy)]
+pub struct EscapeLatex<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for EscapeLatex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let _depth = DepthGuard::set(&ESCAPE_DEPTH, ESCAPE_DEPTH.get() + 1);
+
+        fmt::Write::write_fmt(&mut EscapingWriter::new(f, latex_command), format_args!("{}", self.0))
+    }
+}
+
+// Displays the wrapped value as the content of a double-quoted TOML string, with quotes,
+// backslashes and control characters escaped.
+#[derive(Debug, Clone, Copy)]
+pub struct EscapeToml<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for EscapeToml<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let _depth = DepthGuard::set(&ESCAPE_DEPTH, ESCAPE_DEPTH.get() + 1);
+
+        fmt::Write::write_fmt(&mut EscapingWriter::new(f, toml_escape), format_args!("{}", self.0))
+    }
+}
+
+// Displays the wrapped value as the content of a double-quoted YAML string, with quotes,
+// backslashes, control characters and line separators escaped.
+#[derive(Debug, Clone, Copy)]
+pub struct EscapeYaml<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for EscapeYaml<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let _depth = DepthGuard::set(&ESCAPE_DEPTH, ESCAPE_DEPTH.get() + 1);
+
+        fmt::Write::write_fmt(&mut EscapingWriter::new(f, yaml_escape), format_args!("{}", self.0))
+    }
+}
+
+// Displays the wrapped value as the content of a double-quoted INI value, with quotes,
+// backslashes and line breaks escaped, and other control characters dropped.
+#[derive(Debug, Clone, Copy)]
+pub struct EscapeIni<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for EscapeIni<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let _depth = DepthGuard::set(&ESCAPE_DEPTH, ESCAPE_DEPTH.get() + 1);
+
+        fmt::Write::write_fmt(&mut EscapingWriter::new(f, ini_escape), format_args!("{}", self.0))
+    }
+}
+
+// Displays the wrapped value as a field of a CSV record separated by the delimiter, quoted by `"`
+// when it contains the delimiter, a quote or a line break. Values written entirely by `Raw` are
+// written as they are, e.g. fields which were already quoted. Fields starting with `=`, `+`, `-`,
+// `@`, a tab or a carriage return are run as formulas by spreadsheets, unless wrapped in
+// `plt::csv::NoFormula`.
+#[derive(Debug, Clone, Copy)]
+pub struct EscapeCsv<T>(pub T, pub char);
+
+impl<T: fmt::Display> fmt::Display for EscapeCsv<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let _depth = DepthGuard::set(&ESCAPE_DEPTH, ESCAPE_DEPTH.get() + 1);
+
+        let mut writer = FieldWriter { depth: ESCAPE_DEPTH.get(), field: String::new(), escaped: false };
+        fmt::Write::write_fmt(&mut writer, format_args!("{}", self.0))?;
+        if !writer.escaped {
+            return f.write_str(&writer.field);
+        }
+
+        // Line breaks are normalized, as fields may be read on other platforms.
+        let field = writer.field.replace("\r\n", "\n").replace('\r', "\n");
+        match field.contains([self.1, '"', '\n']) {
+            true => write!(f, "\"{}\"", field.replace('"', "\"\"")),
+            false => f.write_str(&field),
+        }
+    }
+}
+
+// Collects a field, as whether it's quoted depends on all of it.
+struct FieldWriter {
+    depth: usize,
+    field: String,
+    // Whether any of the field was written outside of a `Raw` value.
+    escaped: bool,
+}
+
+impl fmt::Write for FieldWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.escaped |= self.depth > RAW_DEPTH.get() && !s.is_empty();
+        self.field.push_str(s);
+        Ok(())
+    }
+}
+
+fn html_entity(c: char) -> Option<&'static str> {
+    match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        '"' => Some("&quot;"),
+        '\'' => Some("&#39;"),
+        _ => None,
+    }
+}
+
+fn xml_entity(c: char) -> Option<&'static str> {
+    match c {
+        '\'' => Some("&apos;"),
+        '\t' | '\n' | '\r' => None,
+        '\u{0}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => Some(""),
+        c => html_entity(c),
+    }
+}
+
+// `~` and `^` would be accents when escaped by a backslash, and `<`, `>` and `|` are printed
+// as other characters by the default font encoding, so they're written as commands.
+fn latex_command(c: char) -> Option<&'static str> {
+    match c {
+        '\\' => Some("\\textbackslash{}"),
+        '{' => Some("\\{"),
+        '}' => Some("\\}"),
+        '$' => Some("\\$"),
+        '&' => Some("\\&"),
+        '#' => Some("\\#"),
+        '%' => Some("\\%"),
+        '_' => Some("\\_"),
+        '~' => Some("\\textasciitilde{}"),
+        '^' => Some("\\textasciicircum{}"),
+        '<' => Some("\\textless{}"),
+        '>' => Some("\\textgreater{}"),
+        '|' => Some("\\textbar{}"),
+        _ => None,
+    }
+}
+
+// Escapes of the control characters below U+0020 in TOML and YAML strings, by code point.
+const UNICODE_ESCAPES: [&str; 32] = [
+    "\\u0000", "\\u0001", "\\u0002", "\\u0003", "\\u0004", "\\u0005", "\\u0006", "\\u0007", "\\u0008", "\\u0009", "\\u000A",
+    "\\u000B", "\\u000C", "\\u000D", "\\u000E", "\\u000F", "\\u0010", "\\u0011", "\\u0012", "\\u0013", "\\u0014", "\\u0015",
+    "\\u0016", "\\u0017", "\\u0018", "\\u0019", "\\u001A", "\\u001B", "\\u001C", "\\u001D", "\\u001E", "\\u001F",
+];
+
+fn toml_escape(c: char) -> Option<&'static str> {
+    match c {
+        '"' => Some("\\\""),
+        '\\' => Some("\\\\"),
+        '\n' => Some("\\n"),
+        '\r' => Some("\\r"),
+        '\t' => Some("\\t"),
+        '\u{0}'..='\u{1f}' => Some(UNICODE_ESCAPES[c as usize]),
+        '\u{7f}' => Some("\\u007F"),
+        _ => None,
+    }
+}
+
+// YAML also reads these as line breaks, which are folded in double-quoted strings.
+fn yaml_escape(c: char) -> Option<&'static str> {
+    match c {
+        '\u{85}' => Some("\\N"),
+        '\u{2028}' => Some("\\L"),
+        '\u{2029}' => Some("\\P"),
+        c => toml_escape(c),
+    }
+}
+
+// INI files have no standard escapes of control characters, so only the common ones are kept.
+fn ini_escape(c: char) -> Option<&'static str> {
+    match c {
+        '"' | '\\' | '\n' | '\r' | '\t' => toml_escape(c),
+        '\u{0}'..='\u{1f}' | '\u{7f}' => Some(""),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "std")]
+type Depth = std::thread::LocalKey<Cell<usize>>;
+
+#[cfg(feature = "std")]
+thread_local! {
+    // Number of the `EscapeHtml`, `EscapeXml` and other escaping values being displayed inside of each other.
+    static ESCAPE_DEPTH: Cell<usize> = const { Cell::new(0) };
+    // Depth up to which `EscapingWriter`s write as they are, set while a `Raw` value is displayed.
+    // Values escaped inside of the `Raw` one are written by deeper writers, which still escape.
+    static RAW_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+// Without `std` there are no thread locals, and statics would let concurrent renders turn off
+// each other's escaping. The depths are fixed instead, so all writers escape, including `Raw`
+// values displayed inside of escaping ones.
+#[cfg(not(feature = "std"))]
+struct Depth(usize);
+
+#[cfg(not(feature = "std"))]
+impl Depth {
+    fn get(&self) -> usize {
+        self.0
+    }
+
+    fn set(&self, _value: usize) {}
+
+    fn replace(&self, _value: usize) -> usize {
+        self.0
+    }
+}
+
+#[cfg(not(feature = "std"))]
+static ESCAPE_DEPTH: Depth = Depth(1);
+#[cfg(not(feature = "std"))]
+static RAW_DEPTH: Depth = Depth(0);
+
+// Sets a depth until dropped, so it's restored even if displaying a value panics.
+struct DepthGuard {
+    depth: &'static Depth,
+    previous: usize,
+}
+
+impl DepthGuard {
+    fn set(depth: &'static Depth, value: usize) -> DepthGuard {
+        DepthGuard { depth, previous: depth.replace(value) }
+    }
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        self.depth.set(self.previous);
+    }
+}
+
+// Writes chars replaced by `entity`, escaping them.
+struct EscapingWriter<'a, 'b> {
+    f: &'a mut fmt::Formatter<'b>,
+    depth: usize,
+    entity: fn(char) -> Option<&'static str>,
+}
+
+impl<'a, 'b> EscapingWriter<'a, 'b> {
+    fn new(f: &'a mut fmt::Formatter<'b>, entity: fn(char) -> Option<&'static str>) -> Self {
+        EscapingWriter { f, depth: ESCAPE_DEPTH.get(), entity }
+    }
+}
+
+impl fmt::Write for EscapingWriter<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.depth <= RAW_DEPTH.get() {
+            return self.f.write_str(s);
+        }
+
+        let mut unescaped_start = 0;
+
+        for (index, c) in s.char_indices() {
+            let Some(entity) = (self.entity)(c) else {
+                continue;
+            };
+
+            self.f.write_str(&s[unescaped_start..index])?;
+            self.f.write_str(entity)?;
+            unescaped_start = index + c.len_utf8();
+        }
+
+        self.f.write_str(&s[unescaped_start..])
+    }
+}
+
+pub fn escape_html(value: impl fmt::Display) -> String {
+    EscapeHtml(value).to_string()
+}
+
+pub fn escape_xml(value: impl fmt::Display) -> String {
+    EscapeXml(value).to_string()
+}
+
+pub fn escape_shell(value: impl fmt::Display) -> String {
+    EscapeShell(value).to_string()
+}
+
+pub fn escape_csv(value: impl fmt::Display, delimiter: char) -> String {
+    EscapeCsv(value, delimiter).to_string()
+}
+
+pub fn escape_latex(value: impl fmt::Display) -> String {
+    EscapeLatex(value).to_string()
+}
+
+pub fn escape_toml(value: impl fmt::Display) -> String {
+    EscapeToml(value).to_string()
+}
+
+pub fn escape_yaml(value: impl fmt::Display) -> String {
+    EscapeYaml(value).to_string()
+}
+
+pub fn escape_ini(value: impl fmt::Display) -> String {
+    EscapeIni(value).to_string()
+}
+
+// Pretty-printed debug representation of the value inside of a `<pre>` tag, as rendered by `@dump`.
+pub fn dump(value: &impl fmt::Debug) -> String {
+    format!("<pre class=\"plt-dump\">{}</pre>", escape_html(format_args!("{value:#?}")))
+}
+
+// Displays trusted HTML, e.g. rendered attributes, which `EscapeHtml` writes without escaping,
+// as do the escapers of other formats, e.g. `EscapeXml` or `EscapeLatex`.
+// Other escapers still escape it, since HTML isn't safe inside of URLs or scripts. Without the
+// `std` feature all escapers do.
+#[derive(Debug, Clone, Copy)]
+pub struct Raw<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for Raw<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let _depth = DepthGuard::set(&RAW_DEPTH, ESCAPE_DEPTH.get());
+
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+// Schemes of URLs which are safe to echo at the start of URL attributes.
+const SAFE_URL_SCHEMES: [&str; 4] = ["http", "https", "mailto", "tel"];
+
+// Displays the wrapped value as the start of a URL attribute. URLs with schemes other than
+// `SAFE_URL_SCHEMES`, e.g. `javascript:`, are replaced with `#`.
+#[derive(Debug, Clone, Copy)]
+pub struct EscapeUrl<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for EscapeUrl<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let url = self.0.to_string();
+
+        let scheme_end = url.find([':', '/', '?', '#']).filter(|&index| url[index..].starts_with(':'));
+        if let Some(scheme_end) = scheme_end {
+            if !SAFE_URL_SCHEMES.contains(&url[..scheme_end].trim().to_ascii_lowercase().as_str()) {
+                return f.write_str("#");
+            }
+        }
+
+        write_percent_encoded(f, &url, is_url_char)
+    }
+}
+
+// Displays the wrapped value as a part of the path of a URL attribute, keeping its `/`.
+#[derive(Debug, Clone, Copy)]
+pub struct NormalizeUrl<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for NormalizeUrl<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_percent_encoded(f, &self.0.to_string(), is_url_char)
+    }
+}
+
+// Displays the wrapped value as a component of the query or fragment of a URL attribute, with
+// all reserved characters percent-encoded.
+#[derive(Debug, Clone, Copy)]
+pub struct EscapeUrlComponent<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for EscapeUrlComponent<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_percent_encoded(f, &self.0.to_string(), |c| c.is_ascii_alphanumeric() || "-._~".contains(c))
+    }
+}
+
+// Characters allowed in URLs, except for `'`, which would end attributes quoted by it.
+fn is_url_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "-._~:/?#[]@!$&()*+,;=%".contains(c)
+}
+
+// Writes `value` with the characters not `allowed` percent-encoded, HTML escaping the rest since
+// it's written into an attribute.
+fn write_percent_encoded(f: &mut fmt::Formatter<'_>, value: &str, allowed: impl Fn(char) -> bool) -> fmt::Result {
+    let mut buffer = [0; 4];
+
+    for c in value.chars() {
+        if allowed(c) {
+            // Deeper than any `Raw` value, as the value was already displayed.
+            let mut writer = EscapingWriter { f, depth: ESCAPE_DEPTH.get() + 1, entity: html_entity };
+            fmt::Write::write_str(&mut writer, c.encode_utf8(&mut buffer))?;
+        } else {
+            for byte in c.encode_utf8(&mut buffer).bytes() {
+                write!(f, "%{byte:02X}")?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Displays the wrapped value as the content of a JavaScript string literal inside of a
+// `<script>` tag, quoted by `'`, `"` or `` ` ``.
+#[derive(Debug, Clone, Copy)]
+pub struct EscapeJsString<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for EscapeJsString<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.to_string().chars() {
+            match c {
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                // Quotes of all string literals, `<`, `>` and `&` of `</script>` and `<!--`, and `$`
+                // of `${` in template literals.
+                '\'' | '"' | '`' | '<' | '>' | '&' | '$' | '\u{2028}' | '\u{2029}' => write!(f, "\\u{:04x}", c as u32)?,
+                c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                c => fmt::Write::write_char(f, c)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    #[test]
+    fn it_escapes_html() {
+        assert_eq!(escape_html("<a href=\"x\">Tom & Jerry's</a>"), "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;");
+        assert_eq!(escape_html("żółw"), "żółw");
+        assert_eq!(escape_html(42), "42");
+    }
+
+    #[test]
+    fn it_escapes_xml() {
+        assert_eq!(escape_xml("<loc>Tom & Jerry's \"cafe\"</loc>"), "&lt;loc&gt;Tom &amp; Jerry&apos;s &quot;cafe&quot;&lt;/loc&gt;");
+        assert_eq!(escape_xml("a\u{1}b\u{fffe}\tc\nżółw"), "ab\tc\nżółw");
+        assert_eq!(escape_xml(format_args!("{}{}", Raw("<![CDATA[<]]>"), "<")), "<![CDATA[<]]>&lt;");
+    }
+
+    #[test]
+    fn it_quotes_shell_words() {
+        assert_eq!(escape_shell("it's $(rm -rf /)"), "'it'\\''s $(rm -rf /)'");
+        assert_eq!(escape_shell(""), "''");
+        assert_eq!(escape_shell(format_args!("{}{}{}", "~/my dir", Raw("/*"), "")), "'~/my dir'/*");
+        assert_eq!(escape_shell(Raw("$HOME")), "$HOME");
+    }
+
+    #[test]
+    fn it_escapes_csv_fields() {
+        assert_eq!(escape_csv("plain", ','), "plain");
+        assert_eq!(escape_csv("Smith, \"Jo\"", ','), "\"Smith, \"\"Jo\"\"\"");
+        assert_eq!(escape_csv("a\r\nb", ','), "\"a\nb\"");
+        assert_eq!(escape_csv("a,b", ';'), "a,b");
+        assert_eq!(escape_csv("a\tb", '\t'), "\"a\tb\"");
+        assert_eq!(escape_csv(Raw("\"a\",b"), ','), "\"a\",b");
+    }
+
+    #[test]
+    fn it_escapes_latex() {
+        assert_eq!(escape_latex("50% of R&D_costs #1 {x}"), "50\\% of R\\&D\\_costs \\#1 \\{x\\}");
+        assert_eq!(escape_latex("C:\\~user ^ $a < b | c"), "C:\\textbackslash{}\\textasciitilde{}user \\textasciicircum{} \\$a \\textless{} b \\textbar{} c");
+        assert_eq!(escape_latex(format_args!("{}{}", Raw("\\textbf{"), "a_b")), "\\textbf{a\\_b");
+    }
+
+    #[test]
+    fn it_escapes_config_strings() {
+        let value = "a \"b\" \\ c\nadmin = true\u{1}\u{2028}";
+
+        assert_eq!(escape_toml(value), "a \\\"b\\\" \\\\ c\\nadmin = true\\u0001\u{2028}");
+        assert_eq!(escape_yaml(value), "a \\\"b\\\" \\\\ c\\nadmin = true\\u0001\\L");
+        assert_eq!(escape_ini(value), "a \\\"b\\\" \\\\ c\\nadmin = true\u{2028}");
+    }
+
+    #[test]
+    fn it_parses_escaping_modes() {
+        assert_eq!("html".parse::<Escaping>().unwrap(), Escaping::Html);
+        assert_eq!("xml".parse::<Escaping>().unwrap(), Escaping::Xml);
+        assert_eq!("shell".parse::<Escaping>().unwrap(), Escaping::Shell);
+        assert_eq!("tsv".parse::<Escaping>().unwrap(), Escaping::Csv('\t'));
+        assert_eq!("latex".parse::<Escaping>().unwrap(), Escaping::Latex);
+        assert_eq!("yaml".parse::<Escaping>().unwrap(), Escaping::Yaml);
+        assert_eq!(
+            "json".parse::<Escaping>().unwrap_err().to_string(),
+            "unknown escaping mode `json`, expected `none`, `html`, `contextual`, `xml`, `shell`, `csv`, `tsv`, `latex`, `toml`, `yaml` or `ini`"
+        );
+    }
+
+    #[test]
+    fn it_writes_raw_values_without_escaping() {
+        assert_eq!(escape_html(format_args!("{}<{}", Raw("<b>"), "i>")), "<b>&lt;i&gt;");
+        assert_eq!(EscapeUrlComponent(Raw("a b")).to_string(), "a%20b");
+        // Values escaped inside of raw ones are still escaped, but only once.
+        assert_eq!(escape_html(Raw(format_args!("<b>{}</b>", EscapeHtml("<i>")))), "<b>&lt;i&gt;</b>");
+    }
+
+    #[test]
+    fn it_dumps_debug_representations() {
+        assert_eq!(dump(&Some("<b>")), "<pre class=\"plt-dump\">Some(\n    &quot;&lt;b&gt;&quot;,\n)</pre>");
+    }
+
+    #[test]
+    fn it_escapes_urls() {
+        assert_eq!(EscapeUrl("https://example.com/a b?q=\"x\"&y=1").to_string(), "https://example.com/a%20b?q=%22x%22&amp;y=1");
+        assert_eq!(EscapeUrl("/users/1#top").to_string(), "/users/1#top");
+        assert_eq!(EscapeUrl(" JavaScript:alert(1)").to_string(), "#");
+        assert_eq!(EscapeUrl("data:text/html,x").to_string(), "#");
+        assert_eq!(NormalizeUrl("ż/it's").to_string(), "%C5%BC/it%27s");
+        assert_eq!(EscapeUrlComponent("a&b=c/d").to_string(), "a%26b%3Dc%2Fd");
+    }
+
+    #[test]
+    fn it_escapes_javascript_strings() {
+        assert_eq!(
+            EscapeJsString("</script>\"'`${a}\\\n\u{2028}").to_string(),
+            "\\u003c/script\\u003e\\u0022\\u0027\\u0060\\u0024{a}\\\\\\n\\u2028"
+        );
+    }
+}
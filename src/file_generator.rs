@@ -1,53 +1,1497 @@
 pub use crate::prelude::*;
+use std::borrow::Cow;
+use std::io::Write;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::{fmt, hash};
+use anyhow::{bail, Context};
+use quote::ToTokens;
+use crate::lint::LintOptions;
+use crate::runtime::DEFAULT_MAX_DEPTH;
+use crate::sandbox::SandboxOptions;
 
-pub fn generate_file(
-    fn_name: impl Into<String>,
-    args: Vec<String>,
-    data: &Vec<Part>,
-) -> Vec<String> {
-    let fn_name = fn_name.into();
+// How the functions generated from a `TemplateSet` are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ModuleLayout {
+    // All functions live in a single module, named after their full path,
+    // e.g. `partials/header.plt` becomes `partials_header`.
+    #[default]
+    Flat,
+    // Modules mirror the template directories, e.g. `partials/header.plt` becomes `partials::header`.
+    Tree,
+    // Like `Tree`, with each template in its own module which the module of its directory
+    // re-exports, so `partials/header.plt` is still called as `partials::header`.
+    Modules,
+}
 
-    let args = args.join(", ");
-    let mut code_lines: Vec<String> = Vec::new();
-    code_lines.push(format!(
-        "fn {fn_name}({args}) -> plt::prelude::Result<String> {{"
-    ));
-    code_lines.push("use std::fmt::Write;".to_string());
-    code_lines.push("let mut output_buffer = String::new();".to_string());
+impl FromStr for ModuleLayout {
+    type Err = anyhow::Error;
+
+    fn from_str(layout: &str) -> Result<ModuleLayout> {
+        match layout {
+            "flat" => Ok(ModuleLayout::Flat),
+            "tree" => Ok(ModuleLayout::Tree),
+            "modules" => Ok(ModuleLayout::Modules),
+            _ => bail!("unknown module layout `{layout}`, expected `flat`, `tree` or `modules`"),
+        }
+    }
+}
+
+// How the names of templates and their directories are turned into Rust identifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum NameMangling {
+    // Lowercases the name and replaces other characters than letters and digits with `_`,
+    // e.g. `user-list.plt` becomes `user_list` and `404.plt` becomes `_404`.
+    #[default]
+    Underscore,
+    // Like `Underscore`, also splitting words of camel case names, e.g. `UserList.plt`
+    // becomes `user_list`.
+    Snake,
+    // Keeps the names, failing for the ones which aren't valid identifiers.
+    Reject,
+}
+
+impl FromStr for NameMangling {
+    type Err = anyhow::Error;
+
+    fn from_str(mangling: &str) -> Result<NameMangling> {
+        match mangling {
+            "underscore" => Ok(NameMangling::Underscore),
+            "snake" => Ok(NameMangling::Snake),
+            "reject" => Ok(NameMangling::Reject),
+            _ => bail!("unknown name mangling `{mangling}`, expected `underscore`, `snake` or `reject`"),
+        }
+    }
+}
+
+// How the names of the generated functions are derived from the paths of templates, before
+// `function_prefix` and `function_suffix` are added.
+#[derive(Clone, Default)]
+pub enum NamingStrategy {
+    // The whole path with `ModuleLayout::Flat`, e.g. `admin/user_list.plt` becomes
+    // `admin_user_list`, and the file name with the other layouts, whose modules hold the rest.
+    #[default]
+    Path,
+    // The file name, e.g. `admin/user_list.plt` becomes `user_list`.
+    FileName,
+    // Name returned for the path of the template, which has to be a valid identifier.
+    Custom(Arc<dyn Fn(&str) -> String + Send + Sync>),
+}
+
+impl NamingStrategy {
+    pub fn custom(name: impl Fn(&str) -> String + Send + Sync + 'static) -> NamingStrategy {
+        NamingStrategy::Custom(Arc::new(name))
+    }
+}
+
+impl fmt::Debug for NamingStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NamingStrategy::Path => write!(f, "Path"),
+            NamingStrategy::FileName => write!(f, "FileName"),
+            NamingStrategy::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+// The names custom strategies return are hashed along with the templates by the generation
+// cache instead.
+impl hash::Hash for NamingStrategy {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+    }
+}
+
+impl FromStr for NamingStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(naming: &str) -> Result<NamingStrategy> {
+        match naming {
+            "path" => Ok(NamingStrategy::Path),
+            "file-name" => Ok(NamingStrategy::FileName),
+            _ => bail!("unknown naming strategy `{naming}`, expected `path` or `file-name`"),
+        }
+    }
+}
+
+// How generated files are formatted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum CodeFormatter {
+    // In-process with prettyplease.
+    #[default]
+    Prettyplease,
+    // With the `rustfmt` binary, or the one `RUSTFMT` points at. It picks up the `rustfmt.toml`
+    // of the project it's run in, so generated files committed to a repository match its style
+    // and don't churn in diffs.
+    Rustfmt,
+    // Skips formatting, writing the code as a compact token stream, for generated files treated
+    // as build artifacts where generation speed matters more than readability.
+    Compact,
+}
+
+impl FromStr for CodeFormatter {
+    type Err = anyhow::Error;
+
+    fn from_str(formatter: &str) -> Result<CodeFormatter> {
+        match formatter {
+            "prettyplease" => Ok(CodeFormatter::Prettyplease),
+            "rustfmt" => Ok(CodeFormatter::Rustfmt),
+            "compact" => Ok(CodeFormatter::Compact),
+            _ => bail!("unknown code formatter `{formatter}`, expected `prettyplease`, `rustfmt` or `compact`"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Hash)]
+pub struct GeneratorOptions {
+    pub module_layout: ModuleLayout,
+    pub name_mangling: NameMangling,
+    // Visibility of the generated functions, e.g. `pub` or `pub(crate)`. Empty for private ones.
+    pub visibility: String,
+    // Also generates a struct per template holding its arguments, which implements `TemplateContext`.
+    pub struct_mode: bool,
+    // Prepended to the names of the generated functions, e.g. `render_` for `render_index`.
+    pub function_prefix: String,
+    // Appended to the names of the generated functions, e.g. `_html` for `index_html`.
+    pub function_suffix: String,
+    pub naming: NamingStrategy,
+    pub escaping: Escaping,
+    // Checks the Rust code of code and echo parts while generating, so invalid code is reported
+    // at its location in the template rather than by the compiler.
+    pub strict: bool,
+    // Implements axum's `IntoResponse` for the structs of struct mode, using plt's `axum` feature.
+    pub axum: bool,
+    // Implements actix-web's `Responder` for the structs of struct mode, using plt's `actix` feature.
+    pub actix: bool,
+    // Generates `render_stream` for the structs of struct mode, using plt's `stream` feature.
+    pub stream: bool,
+    // Also generates `<function>_write` functions rendering into a tokio `AsyncWrite`, using
+    // plt's `tokio` feature.
+    pub async_write: bool,
+    // Directory `@asset` paths are relative to.
+    pub asset_dir: Option<PathBuf>,
+    // Prefix of the URLs rendered by `@asset`, e.g. `/assets` or `https://cdn.example.com`.
+    pub asset_url: String,
+    // Writes echoes of constant expressions, e.g. `<?= 60 * 60 ?>`, as text evaluated during
+    // generation.
+    pub fold_constants: bool,
+    // Levels of the lints run over the templates by `plt check` and the build helpers.
+    pub lints: LintOptions,
+    // Rejects templates using `unsafe` code or denied paths and macros, see `SandboxOptions`.
+    pub sandbox: Option<SandboxOptions>,
+    // Formatter of the generated files.
+    pub formatter: CodeFormatter,
+    // Puts `#[rustfmt::skip]` on the generated items and modules, so `cargo fmt` leaves
+    // generated files alone.
+    pub rustfmt_skip: bool,
+    // Minification of the text of templates, see `Minify`.
+    pub minify: Minify,
+    // Removes the lines of `<?rs ?>` tags standing on their own line, see `trim_tag_newlines`.
+    pub trim_tag_newlines: bool,
+    pub trailing_newline: TrailingNewline,
+    // Minimum length of the text written at once which is stored compressed and decompressed on
+    // first render, using plt's `compress` feature, see `plt::compress`.
+    pub compress_text: Option<usize>,
+    // Maximum number of nested templates which render themselves, directly or through other
+    // templates, checked at runtime using `plt::runtime::enter_template`.
+    pub max_depth: usize,
+    // Generates `render_by_name` in the root module, rendering templates chosen by their path
+    // at runtime, see `plt::registry`.
+    pub registry: bool,
+    // Generates a `<NAME>_META` const along with each function and `ALL_TEMPLATES` in the root
+    // module, see `plt::reflection`.
+    pub metadata: bool,
+    // Placeholders of the parameters of SQL templates, see `plt::sql`.
+    pub sql_placeholder: crate::sql::Placeholder,
+    // Delimiter of the fields of `.csv.plt` templates, see `plt::csv`.
+    pub csv_delimiter: char,
+    // Generates functions for `no_std` crates using `alloc`, which render using plt without its
+    // `std` feature, taking `String` from `alloc` and `Write` from `core`.
+    pub no_std: bool,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> Self {
+        Self {
+            module_layout: ModuleLayout::default(),
+            name_mangling: NameMangling::default(),
+            visibility: "pub".to_string(),
+            struct_mode: false,
+            function_prefix: String::new(),
+            function_suffix: String::new(),
+            naming: NamingStrategy::default(),
+            escaping: Escaping::default(),
+            strict: false,
+            axum: false,
+            actix: false,
+            stream: false,
+            async_write: false,
+            asset_dir: None,
+            asset_url: "/assets".to_string(),
+            fold_constants: false,
+            lints: LintOptions::default(),
+            sandbox: None,
+            formatter: CodeFormatter::default(),
+            rustfmt_skip: false,
+            minify: Minify::default(),
+            trim_tag_newlines: false,
+            trailing_newline: TrailingNewline::default(),
+            compress_text: None,
+            max_depth: DEFAULT_MAX_DEPTH,
+            registry: false,
+            metadata: false,
+            sql_placeholder: crate::sql::Placeholder::default(),
+            csv_delimiter: ',',
+            no_std: false,
+        }
+    }
+}
+
+// Directive blocks which are currently open, innermost last.
+#[derive(Debug)]
+enum OpenBlock {
+    Match { has_arm: bool },
+    Capture,
+    // `overrides` is set for blocks of a template extending a layout, as opposed to
+    // the default content of blocks inside of the layout itself.
+    // `rendered` is set for the block rendered by a block function.
+    Block { overrides: bool, rendered: bool },
+    // Holds the component and its arguments to call once the slot has been rendered.
+    Call { function: String, args: String },
+    If { has_else: bool },
+    For,
+    // `rescued` is set once the section of `@try` ended and its fallback is generated.
+    Try { rescued: bool },
+    Cache,
+    Cfg { predicate: String, has_else: bool },
+    // Section of the file generated as a template of its own, which is left empty in the file.
+    Template,
+    // Section generated as a macro, i.e. a template rendered by `@use`.
+    Def,
+}
+
+// Layout extended by the template being generated.
+#[derive(Debug)]
+struct ExtendedLayout {
+    fn_name: String,
+    args: String,
+    blocks: Vec<String>,
+    defined_blocks: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct CodeGenerator<'a> {
+    code_lines: Vec<String>,
+    open_blocks: Vec<OpenBlock>,
+    // Indices of the parts which opened the blocks in `open_blocks`.
+    open_block_parts: Vec<usize>,
+    // Index of the part currently being generated.
+    current_part: usize,
+    // Set and path of the template being generated, used to resolve includes.
+    template_set: Option<(&'a TemplateSet, &'a str)>,
+    options: GeneratorOptions,
+    layout: Option<ExtendedLayout>,
+    target: OutputTarget,
+    // Block rendered on its own by the generated block function, into `rendered_block`.
+    rendered_block: Option<String>,
+    // Context of the text generated so far, for `Escaping::Contextual`.
+    html_context: HtmlContextTracker,
+    // Text written by the last lines of `code_lines`, which following text is merged into.
+    last_text: Option<WrittenText>,
+    // Names of the `@template` sections found so far.
+    sections: Vec<String>,
+}
+
+// Text written by the code lines from `start` up to `end`.
+#[derive(Debug)]
+struct WrittenText {
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+// Where the generated code writes output to, outside of captures, block overrides and slots,
+// which always write into their own `String`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum OutputTarget {
+    #[default]
+    Buffer,
+    // A `StreamBuffer`, which is flushed after writes.
+    Stream,
+    // The `AsyncWrite` named `output_writer`, which is written to directly.
+    AsyncWriter,
+}
+
+// Arguments declared with `@args` directives, in order of appearance. Templates using `@nonce`,
+// `@csrf`, `@url` or `@cache` also take the nonce, token provider, router or fragment cache,
+// unless they declare them themselves.
+pub fn declared_args(parts: &[Part]) -> Result<Vec<String>> {
+    let directives = Directive::parse_all(parts)?;
+    let mut args: Vec<String> = directives
+        .iter()
+        .flat_map(|directive| match directive {
+            Directive::Args(args) => args.clone(),
+            _ => Vec::new(),
+        })
+        .collect();
+
+    for directive in &directives {
+        let (name, ty) = match directive {
+            Directive::Nonce => (crate::csp::NONCE_ARG, "&plt::csp::Nonce"),
+            Directive::Csrf => (crate::csrf::CSRF_ARG, "&dyn plt::csrf::CsrfTokenProvider"),
+            Directive::Url { .. } => (crate::routes::ROUTES_ARG, "&dyn plt::routes::UrlFor"),
+            Directive::Cache { .. } => (crate::runtime::FRAGMENT_CACHE_ARG, "&dyn plt::runtime::FragmentCache"),
+            _ => continue,
+        };
+
+        let declared = args.iter().any(|arg| arg.split(':').next().unwrap_or_default().trim() == name);
+        if !declared {
+            args.push(format!("{name}: {ty}"));
+        }
+    }
+
+    Ok(args)
+}
+
+// Names of the blocks rendered by a layout, in order of appearance. Each of them becomes
+// a `block_<name>: Option<&str>` parameter of the generated function.
+//
+// Templates which extend a layout only override blocks, so they don't render any.
+pub fn layout_blocks(parts: &[Part]) -> Result<Vec<String>> {
+    let directives = Directive::parse_all(parts)?;
+
+    if directives.iter().any(|directive| matches!(directive, Directive::Extends { .. })) {
+        return Ok(Vec::new());
+    }
+
+    let mut blocks: Vec<String> = Vec::new();
+    for directive in directives {
+        if let Directive::Block(name) | Directive::Yield(name) = directive {
+            if !blocks.contains(&name) {
+                blocks.push(name);
+            }
+        }
+    }
+
+    Ok(blocks)
+}
+
+// Names of the blocks defined with `@block`, whether as overrides or as the default content
+// of a layout, in order of appearance. In struct mode each of them gets a block function.
+pub fn defined_blocks(parts: &[Part]) -> Result<Vec<String>> {
+    let mut blocks: Vec<String> = Vec::new();
+    for directive in Directive::parse_all(parts)? {
+        if let Directive::Block(name) = directive {
+            if !blocks.contains(&name) {
+                blocks.push(name);
+            }
+        }
+    }
+
+    Ok(blocks)
+}
+
+// Name of the function rendering only `block` of the template function `fn_name`.
+pub fn block_function_name(fn_name: &str, block: &str) -> String {
+    format!("{fn_name}_block_{block}")
+}
+
+impl CodeGenerator<'_> {
+    fn open_block(&mut self, block: OpenBlock) {
+        self.open_blocks.push(block);
+        self.open_block_parts.push(self.current_part);
+    }
+
+    fn close_block(&mut self) -> Option<OpenBlock> {
+        self.open_block_parts.pop();
+        self.open_blocks.pop()
+    }
+
+    // Text between `@match` and its first `@when` can't be written anywhere, so only
+    // whitespace is allowed there.
+    fn is_before_first_arm(&self) -> bool {
+        matches!(self.open_blocks.last(), Some(OpenBlock::Match { has_arm: false }))
+    }
+
+    // The target output is written to, which is a shadowing `String` inside of captures,
+    // block overrides, slots and the block rendered by block functions.
+    fn current_target(&self) -> OutputTarget {
+        let is_shadowed = self.open_blocks.iter().any(|block| {
+            matches!(
+                block,
+                OpenBlock::Capture
+                    | OpenBlock::Block { overrides: true, .. }
+                    | OpenBlock::Block { rendered: true, .. }
+                    | OpenBlock::Call { .. }
+                    | OpenBlock::Try { rescued: false }
+                    | OpenBlock::Cache
+            )
+        });
+
+        if is_shadowed {
+            OutputTarget::Buffer
+        } else {
+            self.target
+        }
+    }
+
+    // Flushes the stream after output was written.
+    fn push_flush(&mut self) {
+        if self.current_target() == OutputTarget::Stream {
+            self.code_lines.push("output_buffer.flush().await;".to_string());
+        }
+    }
+
+    // Writes the value of the expression `code`, escaped according to the options.
+    fn push_echo(&mut self, code: &str) -> Result<()> {
+        // Parenthesized rather than wrapped in a block, so echoed values are borrowed
+        // by `write!` instead of being moved.
+        let value = match self.options.escaping {
+            Escaping::Contextual => self.html_context.context().echo_expression(code)?,
+            escaping => escaping.echo_expression(code),
+        };
+        self.html_context.feed("_");
+
+        if self.current_target() == OutputTarget::AsyncWriter {
+            self.code_lines.push(format!("output_writer.write_all({value}.to_string().as_bytes()).await?;"));
+        } else {
+            self.code_lines.push(format!("write!(output_buffer, \"{{}}\", {value})?;"));
+        }
+        self.push_flush();
+
+        Ok(())
+    }
+
+    // Writes `text`, an expression of a `&str`.
+    fn push_str(&mut self, text: &str) {
+        if self.current_target() == OutputTarget::AsyncWriter {
+            self.code_lines.push(format!("output_writer.write_all({text}.as_bytes()).await?;"));
+        } else {
+            self.code_lines.push(format!("output_buffer.push_str({text});"));
+        }
+        self.push_flush();
+    }
+
+    // Writes the output of another template's function, rendering into the `AsyncWrite`
+    // directly when writing to it. Recursive templates write the output of the functions
+    // returning a `String` instead, as recursive `async fn`s would need to be boxed and the
+    // depth of nested templates is only tracked by the latter.
+    fn push_template_call(&mut self, function: &str, args: &str) {
+        let is_recursive = self.template_set.is_some_and(|(template_set, path)| template_set.is_recursive(path).unwrap_or(false));
+
+        if self.current_target() == OutputTarget::AsyncWriter && is_recursive {
+            self.code_lines.push(format!("output_writer.write_all({function}({args})?.as_bytes()).await?;"));
+        } else if self.current_target() == OutputTarget::AsyncWriter {
+            let args = if args.is_empty() { String::new() } else { format!(", {args}") };
+            self.code_lines.push(format!("{function}_write(output_writer{args}).await?;"));
+        } else {
+            self.code_lines.push(format!("output_buffer.push_str(&{function}({args})?);"));
+        }
+        self.push_flush();
+    }
+
+    // Ends the section of `@try`, writing its output if it succeeded and opening the arm of
+    // its fallback, which binds the error to `binding`.
+    fn push_try_output(&mut self, binding: &str) {
+        self.code_lines.push("Ok(output_buffer)".to_string());
+        self.code_lines.push("})();".to_string());
+        self.code_lines.push("match try_output {".to_string());
+        self.code_lines.push("Ok(try_output) => {".to_string());
+        self.push_str("&try_output");
+        self.code_lines.push("}".to_string());
+        self.code_lines.push(format!("Err({binding}) => {{"));
+    }
+
+    // Writes the value of a directive resolved during generation, e.g. `@git_sha`, as text.
+    fn push_build_constant(&mut self, code: &str, value: String) -> Result<()> {
+        if self.options.escaping == Escaping::Sql {
+            return self.push_echo(&format!("{value:?}"));
+        }
+        let Some(text) = self.escape_constant(code, value)? else {
+            bail!("`{code}` can't be written inside of a tag");
+        };
+
+        self.push_text(&text);
+        Ok(())
+    }
+
+    fn push_part(&mut self, part: &Part) -> Result<()> {
+        if let Part::Code(code) = part {
+            if let Some(directive) = Directive::parse(code)? {
+                return self.push_directive(directive);
+            }
+        }
+
+        if self.is_before_first_arm() {
+            if part.get_content().trim().is_empty() {
+                return Ok(());
+            }
+
+            bail!("expected `@when` after `@match`, found `{}`", part.get_content().trim());
+        }
+
+        // Templates extending a layout only render through their blocks.
+        if self.layout.is_some() && self.open_blocks.is_empty() && !matches!(part, Part::Code(_)) {
+            if part.get_content().trim().is_empty() {
+                return Ok(());
+            }
+
+            bail!(
+                "found `{}` outside of `@block` in a template which extends a layout",
+                part.get_content().trim()
+            );
+        }
+
+        if self.options.strict {
+            check_part(part)?;
+        }
 
-    for part in data {
         match part {
             Part::Code(code) => {
-                code_lines.push(code.to_string());
-            }
-            Part::EchoCode(code) => {
-                code_lines.push(format!("\twrite!(output_buffer, \"{{}}\", {{ {code} }})?;"));
+                self.code_lines.push(code.to_string());
             }
+            Part::EchoCode(code) if self.options.fold_constants => match self.fold_constant(code)? {
+                Some(text) => self.push_text(&text),
+                None => self.push_echo(code)?,
+            },
+            Part::EchoCode(code) => self.push_echo(code)?,
             Part::Text(text) => {
-                code_lines.push(format!(
-                    "write!(output_buffer, \"{{}}\", \"{}\")?;",
-                    text.escape_default()
+                self.html_context.feed(text);
+                if !text.is_empty() {
+                    self.push_text(text);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Writes static text, merged with the text written right before it, e.g. by a folded
+    // constant, into a single write.
+    fn push_text(&mut self, text: &str) {
+        let mut text = text.to_string();
+        if let Some(last_text) = self.last_text.take().filter(|last_text| last_text.end == self.code_lines.len()) {
+            self.code_lines.truncate(last_text.start);
+            text.insert_str(0, &last_text.text);
+        }
+
+        let start = self.code_lines.len();
+        let value = match self.options.compress_text {
+            #[cfg(feature = "compress")]
+            Some(min_len) if text.len() >= min_len => compressed_text(&text),
+            _ => format!("\"{}\"", text.escape_default()),
+        };
+        if self.current_target() == OutputTarget::AsyncWriter {
+            self.code_lines.push(format!("output_writer.write_all({value}.as_bytes()).await?;"));
+        } else {
+            self.code_lines.push(format!("write!(output_buffer, \"{{}}\", {value})?;"));
+        }
+        self.push_flush();
+
+        self.last_text = Some(WrittenText { start, end: self.code_lines.len(), text });
+    }
+
+    // Escaped value of the echoed `code` if it's a constant expression, which isn't echoed
+    // inside of a tag.
+    fn fold_constant(&mut self, code: &str) -> Result<Option<String>> {
+        match evaluate_const(code) {
+            Some(value) => self.escape_constant(code, value),
+            None => Ok(None),
+        }
+    }
+
+    // `value` of `code` known during generation, escaped like an echo of it, or `None` inside
+    // of tags, where only `Attributes` can be echoed.
+    fn escape_constant(&mut self, code: &str, value: String) -> Result<Option<String>> {
+        let escaped = match self.options.escaping {
+            Escaping::None => Some(value),
+            Escaping::Html => Some(escape_html(value)),
+            Escaping::Xml => Some(escape_xml(value)),
+            Escaping::Shell => Some(escape_shell(value)),
+            // Bound at runtime like any other value.
+            Escaping::Sql => None,
+            Escaping::Csv(delimiter) => Some(escape_csv(value, delimiter)),
+            Escaping::Latex => Some(escape_latex(value)),
+            Escaping::Toml => Some(escape_toml(value)),
+            Escaping::Yaml => Some(escape_yaml(value)),
+            Escaping::Ini => Some(escape_ini(value)),
+            Escaping::Contextual => self.html_context.context().escape(code, &value)?,
+        };
+        if escaped.is_some() {
+            self.html_context.feed("_");
+        }
+
+        Ok(escaped)
+    }
+
+    fn push_directive(&mut self, directive: Directive) -> Result<()> {
+        // The output of other functions is written as it is and skipped sections keep the
+        // values they bound, so their placeholders wouldn't match the query's parameters.
+        if self.options.escaping == Escaping::Sql
+            && matches!(
+                directive,
+                Directive::Include { .. } | Directive::Extends { .. } | Directive::Call { .. } | Directive::Use { .. } | Directive::Try | Directive::Cache { .. }
+            )
+        {
+            bail!("`@{}` can't be used in SQL templates, as its output wouldn't be bound as query parameters", directive.name());
+        }
+
+        match directive {
+            Directive::Match(scrutinee) => {
+                self.code_lines.push(format!("match {scrutinee} {{"));
+                self.open_block(OpenBlock::Match { has_arm: false });
+            }
+            Directive::When(pattern) => {
+                let Some(OpenBlock::Match { has_arm }) = self.open_blocks.last_mut() else {
+                    bail!("`@when {pattern}` found outside of `@match`");
+                };
+
+                if *has_arm {
+                    self.code_lines.push("}".to_string());
+                }
+                *has_arm = true;
+
+                self.code_lines.push(format!("{pattern} => {{"));
+            }
+            Directive::EndMatch => {
+                let Some(OpenBlock::Match { has_arm }) = self.close_block() else {
+                    bail!("`@endmatch` found without matching `@match`");
+                };
+
+                if has_arm {
+                    self.code_lines.push("}".to_string());
+                }
+                self.code_lines.push("}".to_string());
+            }
+            Directive::Let(binding) => {
+                self.code_lines.push(format!("let {binding};"));
+            }
+            Directive::Capture(name) => {
+                // The captured section writes into its own, shadowed buffer.
+                self.code_lines.push(format!("let {name} = {{"));
+                self.code_lines.push("let mut output_buffer = String::new();".to_string());
+                self.open_block(OpenBlock::Capture);
+            }
+            Directive::EndCapture => {
+                let Some(OpenBlock::Capture) = self.close_block() else {
+                    bail!("`@endcapture` found without matching `@capture`");
+                };
+
+                self.code_lines.push("output_buffer".to_string());
+                self.code_lines.push("};".to_string());
+            }
+            Directive::Include { path, args } => {
+                let Some((template_set, includer)) = self.template_set else {
+                    bail!("`@include \"{path}\"` requires the template to be generated from a `TemplateSet`");
+                };
+
+                let fn_name = template_set.resolve_include(includer, &path, &self.options)?;
+                self.push_template_call(&fn_name, &args);
+            }
+            Directive::Extends { path, args } => {
+                let Some((template_set, extender)) = self.template_set else {
+                    bail!("`@extends \"{path}\"` requires the template to be generated from a `TemplateSet`");
+                };
+                if self.layout.is_some() {
+                    bail!("template `{extender}` extends more than one layout");
+                }
+                if !self.open_blocks.is_empty() {
+                    bail!("`@extends \"{path}\"` must not be nested inside of other directives");
+                }
+
+                let (fn_name, blocks) = template_set.resolve_layout(extender, &path, &self.options)?;
+                self.layout = Some(ExtendedLayout { fn_name, args, blocks, defined_blocks: Vec::new() });
+            }
+            Directive::Block(name) => {
+                if let Some(layout) = &mut self.layout {
+                    if !self.open_blocks.is_empty() {
+                        bail!("`@block {name}` must not be nested inside of other directives");
+                    }
+                    if !layout.blocks.contains(&name) {
+                        bail!("the extended layout has no block named `{name}`");
+                    }
+                    if layout.defined_blocks.contains(&name) {
+                        bail!("block `{name}` is defined more than once");
+                    }
+                    layout.defined_blocks.push(name.clone());
+
+                    let rendered = self.rendered_block.as_ref() == Some(&name);
+                    // Block functions don't call the layout, so the other blocks go unused.
+                    let variable = if self.rendered_block.is_some() && !rendered { "_block" } else { "block" };
+
+                    self.code_lines.push(format!("let {variable}_{name} = {{"));
+                    self.code_lines.push("let mut output_buffer = String::new();".to_string());
+                    self.open_block(OpenBlock::Block { overrides: true, rendered });
+                } else if self.rendered_block.as_ref() == Some(&name) {
+                    self.code_lines.push(format!("rendered_block = Some(if let Some(block) = block_{name} {{"));
+                    self.code_lines.push("block.to_string()".to_string());
+                    self.code_lines.push("} else {".to_string());
+                    self.code_lines.push("let mut output_buffer = String::new();".to_string());
+                    self.open_block(OpenBlock::Block { overrides: false, rendered: true });
+                } else {
+                    // Inside of a layout the block's content is the default for when it isn't overridden.
+                    self.code_lines.push(format!("if let Some(block) = block_{name} {{"));
+                    self.push_str("block");
+                    self.code_lines.push("} else {".to_string());
+                    self.open_block(OpenBlock::Block { overrides: false, rendered: false });
+                }
+            }
+            Directive::EndBlock => {
+                let Some(OpenBlock::Block { overrides, rendered }) = self.close_block() else {
+                    bail!("`@endblock` found without matching `@block`");
+                };
+
+                match (overrides, rendered) {
+                    (true, _) => {
+                        self.code_lines.push("output_buffer".to_string());
+                        self.code_lines.push("};".to_string());
+
+                        if let (true, Some(name)) = (rendered, &self.rendered_block) {
+                            self.code_lines.push(format!("rendered_block = Some(block_{name});"));
+                        }
+                    }
+                    (false, true) => {
+                        self.code_lines.push("output_buffer".to_string());
+                        self.code_lines.push("});".to_string());
+                    }
+                    (false, false) => self.code_lines.push("}".to_string()),
+                }
+            }
+            Directive::Call { path, args } => {
+                let Some((template_set, caller)) = self.template_set else {
+                    bail!("`@call \"{path}\"` requires the template to be generated from a `TemplateSet`");
+                };
+
+                let (function, args) = template_set.resolve_component(caller, &path, &args, &self.options)?;
+
+                // The slot is rendered into its own buffer first, then handed to the component.
+                self.code_lines.push("{".to_string());
+                self.code_lines.push("let slot = {".to_string());
+                self.code_lines.push("let mut output_buffer = String::new();".to_string());
+                self.open_block(OpenBlock::Call { function, args });
+            }
+            Directive::EndCall => {
+                let Some(OpenBlock::Call { function, args }) = self.close_block() else {
+                    bail!("`@endcall` found without matching `@call`");
+                };
+
+                self.code_lines.push("output_buffer".to_string());
+                self.code_lines.push("};".to_string());
+                self.push_template_call(&function, &args);
+                self.code_lines.push("}".to_string());
+            }
+            Directive::Slot => {
+                self.push_str("slot");
+            }
+            // Declared arguments only end up in the function signature.
+            Directive::Args(_) => {}
+            // Lint levels only apply when the template is linted.
+            Directive::Lint { .. } => {}
+            // Sample arguments are only rendered by `plt doc`.
+            Directive::Example(_) => {}
+            // The parts of sections are split off into templates of their own when the file is
+            // added, so only their tags are left here.
+            Directive::Template { name, .. } => {
+                if !self.open_blocks.is_empty() {
+                    bail!("`@template {name}` must not be nested inside of other directives");
+                }
+                if self.sections.contains(&name) {
+                    bail!("`@template {name}` is defined more than once");
+                }
+                self.sections.push(name);
+
+                self.open_block(OpenBlock::Template);
+            }
+            Directive::EndTemplate => {
+                let Some(OpenBlock::Template) = self.close_block() else {
+                    bail!("`@endtemplate` found without matching `@template`");
+                };
+            }
+            Directive::Def { name, .. } => {
+                if !self.open_blocks.is_empty() {
+                    bail!("`@def {name}` must not be nested inside of other directives");
+                }
+                if self.sections.contains(&name) {
+                    bail!("`@def {name}` is defined more than once");
+                }
+                self.sections.push(name);
+
+                self.open_block(OpenBlock::Def);
+            }
+            Directive::EndDef => {
+                let Some(OpenBlock::Def) = self.close_block() else {
+                    bail!("`@enddef` found without matching `@def`");
+                };
+            }
+            Directive::Use { name, args } => {
+                let Some((template_set, user)) = self.template_set else {
+                    bail!("`@use {name}` requires the template to be generated from a `TemplateSet`");
+                };
+
+                let fn_name = template_set.resolve_macro(user, &name, &self.options)?;
+                self.push_template_call(&fn_name, &args);
+            }
+            Directive::If(condition) => {
+                self.code_lines.push(format!("if {condition} {{"));
+                self.open_block(OpenBlock::If { has_else: false });
+            }
+            Directive::ElseIf(condition) => {
+                let Some(OpenBlock::If { has_else: false }) = self.open_blocks.last() else {
+                    bail!("`@else if {condition}` found outside of `@if`");
+                };
+
+                self.code_lines.push(format!("}} else if {condition} {{"));
+            }
+            Directive::Else => match self.open_blocks.last_mut() {
+                Some(OpenBlock::If { has_else }) => {
+                    if *has_else {
+                        bail!("`@if` has more than one `@else`");
+                    }
+                    *has_else = true;
+
+                    self.code_lines.push("} else {".to_string());
+                }
+                Some(OpenBlock::Cfg { predicate, has_else }) => {
+                    if *has_else {
+                        bail!("`@cfg` has more than one `@else`");
+                    }
+                    *has_else = true;
+
+                    let predicate = format!("#[cfg(not({predicate}))]");
+                    self.code_lines.push("}".to_string());
+                    self.code_lines.push(predicate);
+                    self.code_lines.push("{".to_string());
+                }
+                _ => bail!("`@else` found outside of `@if`"),
+            },
+            Directive::EndIf => {
+                let Some(OpenBlock::If { .. }) = self.close_block() else {
+                    bail!("`@endif` found without matching `@if`");
+                };
+
+                self.code_lines.push("}".to_string());
+            }
+            Directive::For { pattern, iterable } => {
+                self.code_lines.push(format!("for {pattern} in {iterable} {{"));
+                self.open_block(OpenBlock::For);
+            }
+            Directive::EndFor => {
+                let Some(OpenBlock::For) = self.close_block() else {
+                    bail!("`@endfor` found without matching `@for`");
+                };
+
+                self.code_lines.push("}".to_string());
+            }
+            // Translations are looked up using the `translator` variable, which templates
+            // usually declare with `@args translator: &dyn plt::i18n::Translate`.
+            Directive::Translate { key, args } => {
+                let args = display_arguments(&args);
+                self.push_echo(&format!("plt::i18n::translate(&translator, {key:?}, {args})"))?;
+            }
+            Directive::TranslatePlural { key, count, args } => {
+                let args = display_arguments(&args);
+                self.push_echo(&format!("plt::i18n::translate_plural(&translator, {key:?}, ({count}) as u64, {args})"))?;
+            }
+            Directive::Asset(path) => {
+                let url = crate::assets::resolve_asset(&self.options, &path)?;
+                self.push_build_constant(&format!("@asset {path:?}"), url)?;
+            }
+            Directive::Nonce => self.push_str(&format!("&{}.attribute()", crate::csp::NONCE_ARG)),
+            // Written as it is, as HTML escaping would corrupt the JSON.
+            Directive::Json(value) => self.push_str(&format!("&plt::json::script_json(&({value}))?")),
+            Directive::Csrf => self.push_str(&format!("&plt::csrf::hidden_input(&{})?", crate::csrf::CSRF_ARG)),
+            // Decided when the generated code is compiled, so release builds of the crate leave
+            // the value out whatever generated it.
+            Directive::Dump(value) => {
+                self.code_lines.push("if cfg!(debug_assertions) {".to_string());
+                self.push_str(&format!("&plt::prelude::dump(&({value}))"));
+                self.code_lines.push("}".to_string());
+            }
+            Directive::Try => {
+                // The section renders into its own buffer inside of a closure, so errors
+                // returned by `?` only end the section.
+                self.code_lines.push("let try_output = (|| -> plt::prelude::Result<String> {".to_string());
+                self.code_lines.push("let mut output_buffer = String::new();".to_string());
+                self.open_block(OpenBlock::Try { rescued: false });
+            }
+            Directive::Rescue(binding) => {
+                let Some(OpenBlock::Try { rescued }) = self.open_blocks.last_mut() else {
+                    bail!("`@rescue` found outside of `@try`");
+                };
+                if *rescued {
+                    bail!("`@try` has more than one `@rescue`");
+                }
+                *rescued = true;
+
+                self.push_try_output(binding.as_deref().unwrap_or("_"));
+            }
+            Directive::EndTry => {
+                let Some(OpenBlock::Try { rescued }) = self.open_blocks.last_mut() else {
+                    bail!("`@endtry` found without matching `@try`");
+                };
+                // Without `@rescue`, failing sections are left out.
+                if !*rescued {
+                    *rescued = true;
+                    self.push_try_output("_");
+                }
+                self.close_block();
+
+                self.code_lines.push("}".to_string());
+                self.code_lines.push("}".to_string());
+            }
+            Directive::Cache { key, ttl } => {
+                let ttl = match ttl {
+                    Some(ttl) => format!("Some(std::time::Duration::from_secs(({ttl}) as u64))"),
+                    None => "None".to_string(),
+                };
+
+                // The section is only rendered, into its own buffer, when it isn't cached.
+                self.code_lines.push(format!(
+                    "let cache_output = plt::runtime::cached({}, &({key}), {ttl}, || -> plt::prelude::Result<String> {{",
+                    crate::runtime::FRAGMENT_CACHE_ARG
                 ));
+                self.code_lines.push("let mut output_buffer = String::new();".to_string());
+                self.open_block(OpenBlock::Cache);
+            }
+            Directive::EndCache => {
+                let Some(OpenBlock::Cache) = self.close_block() else {
+                    bail!("`@endcache` found without matching `@cache`");
+                };
+
+                self.code_lines.push("Ok(output_buffer)".to_string());
+                self.code_lines.push("})?;".to_string());
+                self.push_str("&cache_output");
+            }
+            Directive::Const(code) => {
+                let Some(text) = self.fold_constant(&code)? else {
+                    bail!("`@const {code}` can't be written inside of a tag");
+                };
+
+                self.push_text(&text);
+            }
+            Directive::Env(name) => self.push_build_constant(&format!("@env {name:?}"), env_value(&name)?)?,
+            Directive::BuiltAt => self.push_build_constant("@built_at", built_at()?)?,
+            Directive::GitSha => self.push_build_constant("@git_sha", git_sha()?)?,
+            // Blocks rather than `if cfg!(...)`, so sections may use items which only exist in
+            // the builds they're rendered in.
+            Directive::Cfg(predicate) => {
+                self.code_lines.push(format!("#[cfg({predicate})]"));
+                self.code_lines.push("{".to_string());
+                self.open_block(OpenBlock::Cfg { predicate, has_else: false });
+            }
+            Directive::EndCfg => {
+                let Some(OpenBlock::Cfg { .. }) = self.close_block() else {
+                    bail!("`@endcfg` found without matching `@cfg`");
+                };
+
+                self.code_lines.push("}".to_string());
+            }
+            Directive::Url { route, args } => {
+                let args = display_arguments(&args);
+                self.push_echo(&format!("{}.url_for({route:?}, {args})?", crate::routes::ROUTES_ARG))?;
+            }
+            Directive::AssetSri(path) => {
+                let attributes = crate::assets::resolve_asset_sri(&self.options, &path)?;
+                self.push_str(&format!("{attributes:?}"));
+            }
+            Directive::Yield(name) => {
+                if self.layout.is_some() {
+                    bail!("`@yield {name}` can only be used in layouts");
+                }
+
+                self.code_lines.push(format!("if let Some(block) = block_{name} {{"));
+                self.push_str("block");
+                self.code_lines.push("}".to_string());
             }
         }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<String>> {
+        if let Some(block) = self.open_blocks.last() {
+            match block {
+                OpenBlock::Match { .. } => bail!("`@match` is missing its `@endmatch`"),
+                OpenBlock::Capture => bail!("`@capture` is missing its `@endcapture`"),
+                OpenBlock::Block { .. } => bail!("`@block` is missing its `@endblock`"),
+                OpenBlock::Call { .. } => bail!("`@call` is missing its `@endcall`"),
+                OpenBlock::If { .. } => bail!("`@if` is missing its `@endif`"),
+                OpenBlock::For => bail!("`@for` is missing its `@endfor`"),
+                OpenBlock::Try { .. } => bail!("`@try` is missing its `@endtry`"),
+                OpenBlock::Cache => bail!("`@cache` is missing its `@endcache`"),
+                OpenBlock::Cfg { .. } => bail!("`@cfg` is missing its `@endcfg`"),
+                OpenBlock::Template => bail!("`@template` is missing its `@endtemplate`"),
+                OpenBlock::Def => bail!("`@def` is missing its `@enddef`"),
+            }
+        }
+
+        // Block functions only render their block, without the layout.
+        if let Some(layout) = self.layout.take().filter(|_| self.rendered_block.is_none()) {
+            let mut args: Vec<String> = Vec::new();
+            if !layout.args.is_empty() {
+                args.push(layout.args);
+            }
+            for block in &layout.blocks {
+                if layout.defined_blocks.contains(block) {
+                    args.push(format!("Some(&block_{block})"));
+                } else {
+                    args.push("None".to_string());
+                }
+            }
+
+            self.push_template_call(&layout.fn_name, &args.join(", "));
+        }
+
+        Ok(self.code_lines)
+    }
+}
+
+// Expression borrowing `text` from a static, decompressed into it on first use.
+#[cfg(feature = "compress")]
+fn compressed_text(text: &str) -> String {
+    let bytes: Vec<_> = crate::compress::compress(text).iter().map(ToString::to_string).collect();
+
+    format!(
+        "{{ static TEXT: std::sync::OnceLock<String> = std::sync::OnceLock::new(); TEXT.get_or_init(|| plt::compress::decompress(&[{}])) }}",
+        bytes.join(", ")
+    )
+}
+
+// Slice of the named arguments of translation and URL directives, as passed to
+// `plt::i18n::translate` and `UrlFor::url_for`.
+fn display_arguments(args: &[(String, String)]) -> String {
+    let args: Vec<String> = args
+        .iter()
+        .map(|(name, value)| format!("(\"{name}\", &({value}) as &dyn std::fmt::Display)"))
+        .collect();
+
+    format!("&[{}]", args.join(", "))
+}
+
+// Code parts may open or close blocks continued by other parts, so they are only checked for
+// malformed tokens, while echo parts have to be complete expressions.
+fn check_part(part: &Part) -> Result<()> {
+    match part {
+        Part::Code(code) => {
+            use rustc_lexer::{LiteralKind, TokenKind};
+
+            let mut offset = 0;
+            for token in rustc_lexer::tokenize(code) {
+                let text = &code[offset..offset + token.len];
+                offset += token.len;
+
+                let problem = match token.kind {
+                    TokenKind::BlockComment { terminated: false } => "unterminated block comment",
+                    TokenKind::Literal { kind, .. } => match kind {
+                        LiteralKind::Char { terminated: false } | LiteralKind::Byte { terminated: false } => {
+                            "unterminated character literal"
+                        }
+                        LiteralKind::Str { terminated: false }
+                        | LiteralKind::ByteStr { terminated: false }
+                        | LiteralKind::RawStr { terminated: false, .. }
+                        | LiteralKind::RawByteStr { terminated: false, .. } => "unterminated string literal",
+                        _ => continue,
+                    },
+                    TokenKind::Unknown => "unknown token",
+                    _ => continue,
+                };
+
+                bail!("{problem} `{}` in `{}`", text.trim(), code.trim());
+            }
+        }
+        Part::EchoCode(code) => {
+            if let Err(error) = syn::parse_str::<syn::Expr>(code) {
+                bail!("invalid Rust expression `{}`: {error}", code.trim());
+            }
+        }
+        Part::Text(_) => {}
+    }
+
+    Ok(())
+}
+
+pub fn generate_file(
+    fn_name: impl Into<String>,
+    mut args: Vec<String>,
+    data: &[Part],
+) -> Result<Vec<String>> {
+    args.extend(declared_args(data)?);
+
+    generate_function(fn_name.into(), "", &args, data, CodeGenerator::default(), &|_| None)
+}
+
+pub(crate) fn generate_template(
+    template: &Template,
+    template_set: &TemplateSet,
+    options: &GeneratorOptions,
+) -> Result<Vec<String>> {
+    let options = &*template_options(&template.path, options);
+    let fn_name = template_set.function_name(&template.path, options);
+    let locate = |part_index: usize| {
+        let span = &template.spans[part_index];
+        Some((template.path.clone(), Span::new(&template.source, span.start, span.end)))
+    };
+
+    let (mut code_lines, _) = generate_mapped_template(template, template_set, options)?;
+    if options.metadata {
+        code_lines.push(crate::reflection::generate_template_meta(template, &fn_name, options)?);
+    }
+
+    if is_email_template(&template.path) {
+        code_lines.extend(generate_email_text(template, template_set, &fn_name, options)?);
+    }
+    // Queries aren't written nor rendered into structs.
+    if options.escaping == Escaping::Sql {
+        return Ok(code_lines);
+    }
+
+    if options.async_write {
+        let generator = CodeGenerator {
+            template_set: Some((template_set, &template.path)),
+            options: options.clone(),
+            target: OutputTarget::AsyncWriter,
+            ..CodeGenerator::default()
+        };
+
+        code_lines.extend(generate_function(
+            format!("{fn_name}_write"),
+            &options.visibility,
+            &template.args,
+            &template.parts,
+            generator,
+            &locate,
+        )?);
+    }
+
+    if options.struct_mode {
+        let mut args = template.args.clone();
+        if template.has_slot()? {
+            args.push("slot: &str".to_string());
+        }
+        let blocks = template.blocks()?;
+        let defined_blocks = defined_blocks(&template.parts)?;
+
+        for block in &defined_blocks {
+            let generator = CodeGenerator {
+                template_set: Some((template_set, &template.path)),
+                options: options.clone(),
+                rendered_block: Some(block.clone()),
+                ..CodeGenerator::default()
+            };
+
+            code_lines.extend(generate_function(
+                block_function_name(&fn_name, block),
+                &options.visibility,
+                &template.args,
+                &template.parts,
+                generator,
+                &locate,
+            )?);
+        }
+
+        code_lines.extend(generate_template_struct(&fn_name, options, &args, &blocks, &defined_blocks)?);
+
+        if options.stream {
+            let generator = CodeGenerator {
+                template_set: Some((template_set, &template.path)),
+                options: options.clone(),
+                target: OutputTarget::Stream,
+                ..CodeGenerator::default()
+            };
+            let body = generate_body(generator, &template.parts, &locate)?;
+
+            code_lines.extend(generate_render_stream(&fn_name, options, &args, &blocks, body)?);
+        }
+    }
+
+    Ok(code_lines)
+}
+
+// Function rendering the plain text body of an email template, from the text template next to
+// it if there is one, see `plt::email`.
+fn generate_email_text(template: &Template, template_set: &TemplateSet, fn_name: &str, options: &GeneratorOptions) -> Result<Vec<String>> {
+    let text_template = template_set.get(&text_template_path(&template.path));
+    let source = text_template.unwrap_or(template);
+    let parts = match text_template {
+        Some(text_template) => text_template.parts.clone(),
+        None => plain_text_parts(&template.parts),
+    };
+    let locate = |part_index: usize| {
+        let span = &source.spans[part_index];
+        Some((source.path.clone(), Span::new(&source.source, span.start, span.end)))
+    };
+
+    // The text isn't HTML, so neither escaped nor minified.
+    let options = GeneratorOptions { escaping: Escaping::None, minify: Minify::Off, ..options.clone() };
+    let generator = CodeGenerator {
+        template_set: Some((template_set, &source.path)),
+        options: options.clone(),
+        ..CodeGenerator::default()
+    };
+
+    generate_function(format!("{fn_name}_text"), &options.visibility, &template.args, &parts, generator, &locate)
+}
+
+// Options generating the template under `path`, with the escaping of its format if it isn't
+// HTML, see `Escaping::of_template`.
+pub(crate) fn template_options<'a>(path: &str, options: &'a GeneratorOptions) -> Cow<'a, GeneratorOptions> {
+    match Escaping::of_template(path) {
+        // Scripts, queries, records, LaTeX and config files depend on their whitespace, e.g. the
+        // blank lines ending paragraphs or the indentation of YAML, which minifying would collapse.
+        Some(escaping @ (Escaping::Shell | Escaping::Sql | Escaping::Latex | Escaping::Toml | Escaping::Yaml | Escaping::Ini)) => Cow::Owned(GeneratorOptions { escaping, minify: Minify::Off, ..options.clone() }),
+        Some(Escaping::Csv(_)) => {
+            let escaping = Escaping::Csv(crate::csv::delimiter(path, options.csv_delimiter));
+            Cow::Owned(GeneratorOptions { escaping, minify: Minify::Off, ..options.clone() })
+        }
+        Some(escaping) => Cow::Owned(GeneratorOptions { escaping, ..options.clone() }),
+        None => Cow::Borrowed(options),
+    }
+}
+
+// Unformatted code of the function rendering the template, along with the lines each of its
+// code and echo parts was lowered to.
+pub(crate) fn generate_mapped_template(
+    template: &Template,
+    template_set: &TemplateSet,
+    options: &GeneratorOptions,
+) -> Result<(Vec<String>, PartLines)> {
+    let options = &*template_options(&template.path, options);
+    let generator = CodeGenerator {
+        template_set: Some((template_set, &template.path)),
+        options: options.clone(),
+        ..CodeGenerator::default()
+    };
+    let locate = |part_index: usize| {
+        let span = &template.spans[part_index];
+        Some((template.path.clone(), Span::new(&template.source, span.start, span.end)))
+    };
+
+    let fn_name = template_set.function_name(&template.path, options);
+    generate_mapped_function(fn_name, &options.visibility, &template.args, &template.parts, generator, &locate)
+}
+
+// `locate` turns the index of a part into the template and the span it comes from, used to
+// raise errors as `Diagnostic`s, e.g. `index.plt:3:5: ...`.
+fn generate_function(
+    fn_name: String,
+    visibility: &str,
+    args: &[String],
+    data: &[Part],
+    generator: CodeGenerator,
+    locate: &dyn Fn(usize) -> Option<(String, Span)>,
+) -> Result<Vec<String>> {
+    Ok(generate_mapped_function(fn_name, visibility, args, data, generator, locate)?.0)
+}
+
+// Same as `generate_function`, along with the lines each code part was lowered to.
+fn generate_mapped_function(
+    fn_name: String,
+    visibility: &str,
+    args: &[String],
+    data: &[Part],
+    mut generator: CodeGenerator,
+    locate: &dyn Fn(usize) -> Option<(String, Span)>,
+) -> Result<(Vec<String>, PartLines)> {
+    let mut args = args.to_vec();
+    for block in layout_blocks(data)? {
+        args.push(format!("block_{block}: Option<&str>"));
+    }
+    // Components receive the body they were called with as the last argument.
+    if Directive::parse_all(data)?.contains(&Directive::Slot) {
+        args.push("slot: &str".to_string());
+    }
+
+    if generator.target == OutputTarget::AsyncWriter {
+        args.insert(0, "output_writer: &mut (impl plt::tokio::AsyncWrite + Unpin)".to_string());
+        let args = args.join(", ");
+        generator.code_lines.push(format!(
+            "{visibility} async fn {fn_name}({args}) -> plt::prelude::Result<()> {{"
+        ));
+        // Captures, block overrides and slots still write into a buffer using `write!`.
+        generator.code_lines.push("#[allow(unused_imports)]".to_string());
+        generator.code_lines.push("use {plt::tokio::AsyncWriteExt as _, std::fmt::Write as _};".to_string());
+
+        let (mut code_lines, part_lines) = generate_mapped_body(generator, data, locate)?;
+        code_lines.push("Ok(())".to_string());
+        code_lines.push("}".to_string());
+
+        return Ok((code_lines, part_lines));
+    }
+
+    let args = args.join(", ");
+    // The output outside of the block rendered by block functions goes unused.
+    if generator.rendered_block.is_some() {
+        generator.code_lines.push("#[allow(unused_assignments, unused_mut, unused_variables)]".to_string());
+    }
+    let query = generator.options.escaping == Escaping::Sql;
+    let output = if query {
+        "plt::sql::Query"
+    } else if generator.options.no_std {
+        "alloc::string::String"
+    } else {
+        "String"
+    };
+    generator.code_lines.push(format!(
+        "{visibility} fn {fn_name}({args}) -> plt::prelude::Result<{output}> {{"
+    ));
+    if generator.options.no_std {
+        generator.code_lines.push("use core::fmt::Write;".to_string());
+        generator.code_lines.push("#[allow(unused_imports)]".to_string());
+        generator.code_lines.push("use alloc::string::{String, ToString};".to_string());
+    } else {
+        generator.code_lines.push("use std::fmt::Write;".to_string());
+    }
+    if let Some((template_set, path)) = generator.template_set {
+        if template_set.is_recursive(path)? {
+            let max_depth = generator.options.max_depth;
+            generator.code_lines.push(format!("let _template_depth = plt::runtime::enter_template({path:?}, {max_depth})?;"));
+        }
     }
+    generator.code_lines.push("let mut output_buffer = String::new();".to_string());
+    if query {
+        generator.code_lines.push(format!("let mut sql_params = plt::sql::Params::new(plt::sql::Placeholder::{:?});", generator.options.sql_placeholder));
+    }
+
+    if generator.rendered_block.is_some() {
+        // The rest of the template is rendered as usual, since the block may depend on it.
+        generator.code_lines.push("let mut rendered_block = None;".to_string());
 
-    code_lines.push("Ok(output_buffer)".to_string());
+        let (mut code_lines, part_lines) = generate_mapped_body(generator, data, locate)?;
+        code_lines.push("Ok(rendered_block.unwrap_or_default())".to_string());
+        code_lines.push("}".to_string());
+
+        return Ok((code_lines, part_lines));
+    }
+
+    let (mut code_lines, part_lines) = generate_mapped_body(generator, data, locate)?;
+    match query {
+        true => code_lines.push("Ok(sql_params.into_query(output_buffer))".to_string()),
+        false => code_lines.push("Ok(output_buffer)".to_string()),
+    }
 
     code_lines.push("}".to_string());
 
-    code_lines
+    Ok((code_lines, part_lines))
+}
+
+// Lowers the parts of a template, appending them to the code of `generator`.
+fn generate_body(
+    generator: CodeGenerator,
+    data: &[Part],
+    locate: &dyn Fn(usize) -> Option<(String, Span)>,
+) -> Result<Vec<String>> {
+    Ok(generate_mapped_body(generator, data, locate)?.0)
+}
+
+// Indices of the code and echo parts along with the lines of the generated code they were
+// lowered to.
+pub(crate) type PartLines = Vec<(usize, Range<usize>)>;
+
+fn generate_mapped_body(
+    mut generator: CodeGenerator,
+    data: &[Part],
+    locate: &dyn Fn(usize) -> Option<(String, Span)>,
+) -> Result<(Vec<String>, PartLines)> {
+    if generator.options.compress_text.is_some() && !cfg!(feature = "compress") {
+        bail!("compressing text requires plt's `compress` feature");
+    }
+    let normalized = normalize_parts(data, &generator.options);
+    let data = &normalized;
+    let with_location = |part_index: usize, error: anyhow::Error| match locate(part_index) {
+        Some((file, span)) => Diagnostic::error(file, Some(span), "invalid-template", error.to_string()).into(),
+        None => error,
+    };
+
+    let mut part_lines = Vec::new();
+    for (part_index, part) in data.iter().enumerate() {
+        generator.current_part = part_index;
+        let start = generator.code_lines.len();
+        generator.push_part(part).map_err(|error| with_location(part_index, error))?;
+        if !part.is_text() {
+            part_lines.push((part_index, start..generator.code_lines.len()));
+        }
+    }
+
+    let unclosed_block_part = generator.open_block_parts.last().copied();
+    let code_lines = generator.finish().map_err(|error| match unclosed_block_part {
+        Some(part_index) => with_location(part_index, error),
+        None => error,
+    })?;
+
+    Ok((code_lines, part_lines))
 }
 
 pub fn format_code(code: &str) -> String {
-    let syntax_tree = syn::parse_file(code).unwrap();
-    let formatted = prettyplease::unparse(&syntax_tree);
-    formatted
+    try_format_code(code).unwrap()
+}
+
+pub fn try_format_code(code: &str) -> Result<String> {
+    let syntax_tree = syn::parse_file(code)?;
+    Ok(prettyplease::unparse(&syntax_tree))
+}
+
+pub fn try_format_code_with(code: &str, formatter: CodeFormatter) -> Result<String> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::trace_span!("format_code", ?formatter, bytes = code.len()).entered();
+
+    match formatter {
+        CodeFormatter::Prettyplease => try_format_code(code),
+        CodeFormatter::Compact => Ok(syn::parse_file(code)?.to_token_stream().to_string()),
+        CodeFormatter::Rustfmt => {
+            // Invalid code is reported the same way with both formatters.
+            syn::parse_file(code)?;
+            rustfmt(code)
+        }
+    }
+}
+
+// Puts `#[rustfmt::skip]` on the items of the code generated for a template. Code which doesn't
+// parse is left as it is, to be reported once it's formatted.
+pub(crate) fn skip_rustfmt(code: String) -> String {
+    let Ok(mut file) = syn::parse_file(&code) else {
+        return code;
+    };
+
+    for item in &mut file.items {
+        let attrs = match item {
+            syn::Item::Fn(item) => &mut item.attrs,
+            syn::Item::Struct(item) => &mut item.attrs,
+            syn::Item::Impl(item) => &mut item.attrs,
+            syn::Item::Mod(item) => &mut item.attrs,
+            _ => continue,
+        };
+        attrs.push(syn::parse_quote!(#[rustfmt::skip]));
+    }
+
+    file.to_token_stream().to_string()
+}
+
+fn rustfmt(code: &str) -> Result<String> {
+    let program = std::env::var_os("RUSTFMT").unwrap_or_else(|| "rustfmt".into());
+    let mut child = Command::new(&program)
+        .args(["--edition", "2021", "--emit", "stdout"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run `{}`", program.to_string_lossy()))?;
+
+    // rustfmt reads all of its input before writing anything, so this can't block on its output.
+    child.stdin.take().context("rustfmt's stdin isn't piped")?.write_all(code.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!("`{}` failed: {}", program.to_string_lossy(), String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::file_generator::{format_code, generate_file};
+    use crate::file_generator::{format_code, generate_file, generate_function, try_format_code_with, CodeFormatter, CodeGenerator};
     use crate::prelude::*;
     use std::fs::read_to_string;
 
@@ -59,10 +1503,385 @@ mod tests {
 
         let result = fsa.run(file);
 
-        let generated_file = generate_file("test_template", Vec::new(), result);
+        let generated_file = generate_file("test_template", Vec::new(), result).unwrap();
 
         let code = generated_file.join("\r\n");
 
         println!("{}", format_code(&code));
     }
+
+    #[test]
+    fn it_borrows_echoed_values() {
+        let mut fsa = TextCodeFSA::new();
+
+        let result = fsa.run("<?rs let name = String::from(\"Ann\"); ?><?= name ?>, <?= name ?>".to_string());
+
+        let generated_file = generate_file("test_template", Vec::new(), result).unwrap();
+
+        let code = generated_file.concat().replace(' ', "");
+
+        // Wrapped in a block, the first echo would move `name`, so the second wouldn't compile.
+        assert_eq!(code.matches("(name)").count(), 2);
+        assert!(!code.contains("{name}"));
+    }
+
+    #[test]
+    fn it_lowers_match_directives() {
+        let file = read_to_string("src/test-files/file_generator_02.plt").unwrap();
+
+        let mut fsa = TextCodeFSA::new();
+
+        let result = fsa.run(file);
+
+        let generated_file = generate_file("test_template", vec!["status: Status".to_string()], result).unwrap();
+
+        let code = format_code(&generated_file.join("\r\n"));
+
+        assert!(code.contains("match status {"));
+        assert!(code.contains("Status::Active => {"));
+        assert!(code.contains("_ => {"));
+    }
+
+    #[test]
+    fn it_lowers_let_and_capture_directives() {
+        let file = read_to_string("src/test-files/file_generator_03.plt").unwrap();
+
+        let mut fsa = TextCodeFSA::new();
+
+        let result = fsa.run(file);
+
+        let generated_file = generate_file("test_template", vec!["items: &[Item]".to_string()], result).unwrap();
+
+        let code = format_code(&generated_file.join("\r\n"));
+
+        assert!(code.contains("let total = items.iter().map(|i| i.price).sum::<u32>();"));
+        assert!(code.contains("let title = {"));
+        assert!(code.contains("output_buffer\n    };"));
+    }
+
+    #[test]
+    fn it_rejects_unbalanced_match_directives() {
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<?rs @match status ?><?rs @when _ ?>".to_string());
+        let error = generate_file("test_template", Vec::new(), result).unwrap_err();
+        assert_eq!(error.to_string(), "`@match` is missing its `@endmatch`");
+
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<?rs @when _ ?><?rs @endmatch ?>".to_string());
+        let error = generate_file("test_template", Vec::new(), result).unwrap_err();
+        assert_eq!(error.to_string(), "`@when _` found outside of `@match`");
+
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<?rs @match status ?>text<?rs @when _ ?><?rs @endmatch ?>".to_string());
+        let error = generate_file("test_template", Vec::new(), result).unwrap_err();
+        assert_eq!(error.to_string(), "expected `@when` after `@match`, found `text`");
+    }
+
+    #[test]
+    fn it_rejects_includes_outside_of_template_sets() {
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<?rs @include \"header.plt\" ?>".to_string());
+        assert!(generate_file("test_template", Vec::new(), result).is_err());
+    }
+
+    #[test]
+    fn it_lowers_control_flow_directives() {
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run(
+            "<?rs @for item in items ?><?rs @if item > 1 ?>big<?rs @else if item == 1 ?>one<?rs @else ?>none<?rs @endif ?><?rs @endfor ?>"
+                .to_string(),
+        );
+
+        let generated_file = generate_file("test_template", vec!["items: &[u32]".to_string()], result).unwrap();
+        let code = format_code(&generated_file.join("\n"));
+
+        assert!(code.contains("for item in items {\n        if item > 1 {"));
+        assert!(code.contains("} else if item == 1 {"));
+        assert!(code.contains("} else {"));
+    }
+
+    #[test]
+    fn it_rejects_unbalanced_control_flow_directives() {
+        for source in [
+            "<?rs @if a ?>",
+            "<?rs @else ?>",
+            "<?rs @if a ?><?rs @else ?><?rs @else ?><?rs @endif ?>",
+            "<?rs @if a ?><?rs @else ?><?rs @else if b ?><?rs @endif ?>",
+            "<?rs @for a in b ?><?rs @endif ?>",
+            "<?rs @endfor ?>",
+        ] {
+            let mut fsa = TextCodeFSA::new();
+            let result = fsa.run(source.to_string());
+            assert!(generate_file("test_template", Vec::new(), result).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn it_escapes_echoed_values() {
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<?=name?>".to_string());
+
+        let generator = CodeGenerator {
+            options: GeneratorOptions { escaping: Escaping::Html, ..GeneratorOptions::default() },
+            ..CodeGenerator::default()
+        };
+        let code = generate_function("test_template".to_string(), "", &[], result, generator, &|_| None).unwrap();
+
+        assert!(code.contains(&"write!(output_buffer, \"{}\", plt::prelude::EscapeHtml(&(name)))?;".to_string()));
+    }
+
+    #[test]
+    fn it_checks_code_in_strict_mode() {
+        let generate = |source: &str| {
+            let mut fsa = TextCodeFSA::new();
+            let result = fsa.run(source.to_string());
+
+            let generator = CodeGenerator {
+                options: GeneratorOptions { strict: true, ..GeneratorOptions::default() },
+                ..CodeGenerator::default()
+            };
+            generate_function("test_template".to_string(), "", &[], result, generator, &|_| None)
+        };
+
+        assert!(generate("<?rs for i in 0..3 { ?><?= i + 1 ?><?rs } ?>").is_ok());
+        assert_eq!(generate("<?rs let a = 1 € 2; ?>").unwrap_err().to_string(), "unknown token `€` in `let a = 1 € 2;`");
+        assert!(generate("<?= 1 + ?>").unwrap_err().to_string().starts_with("invalid Rust expression `1 +`"));
+    }
+
+    #[test]
+    fn it_renders_fingerprinted_asset_urls() {
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<link href=\"<?rs @asset \"css/app.css\" ?>\">".to_string());
+
+        let generator = CodeGenerator {
+            options: GeneratorOptions { asset_dir: Some("src/test-files/assets".into()), ..GeneratorOptions::default() },
+            ..CodeGenerator::default()
+        };
+        let code = generate_function("test_template".to_string(), "", &[], result, generator, &|_| None).unwrap();
+
+        let content = std::fs::read("src/test-files/assets/css/app.css").unwrap();
+        let url = format!("/assets/css/app.{}.css", crate::assets::fingerprint(&content));
+        let text = format!("<link href=\"{url}\">");
+        assert!(code.contains(&format!("write!(output_buffer, \"{{}}\", {text:?})?;")), "{code:?}");
+
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<link href=\"<?rs @asset \"css/app.css\" ?>\">".to_string());
+        let generator = CodeGenerator {
+            options: GeneratorOptions {
+                asset_dir: Some("src/test-files/assets".into()),
+                asset_url: "/\"><script>".to_string(),
+                escaping: Escaping::Html,
+                ..GeneratorOptions::default()
+            },
+            ..CodeGenerator::default()
+        };
+        let code = generate_function("test_template".to_string(), "", &[], result, generator, &|_| None).unwrap().join("\n");
+        assert!(code.contains("&quot;&gt;&lt;script&gt;/css/app."), "{code}");
+    }
+
+    #[test]
+    fn it_escapes_echoed_values_by_context() {
+        let generate = |source: &str| {
+            let mut fsa = TextCodeFSA::new();
+            let result = fsa.run(source.to_string());
+
+            let generator = CodeGenerator {
+                options: GeneratorOptions { escaping: Escaping::Contextual, ..GeneratorOptions::default() },
+                ..CodeGenerator::default()
+            };
+            generate_function("test_template".to_string(), "", &[], result, generator, &|_| None)
+        };
+
+        let code = generate("<a href=\"<?=url?>?q=<?=query?>\"><?=name?></a><script>let a = '<?=a?>';</script>").unwrap();
+        for value in ["EscapeUrl(&(url))", "EscapeUrlComponent(&(query))", "EscapeHtml(&(name))", "EscapeJsString(&(a))"] {
+            assert!(code.contains(&format!("write!(output_buffer, \"{{}}\", plt::prelude::{value})?;")), "{value}");
+        }
+
+        assert_eq!(
+            generate("<div onclick=\"<?= handler ?>\">").unwrap_err().to_string(),
+            "ambiguous escaping context: `handler` is echoed into an event handler attribute"
+        );
+    }
+
+    #[test]
+    fn it_writes_json_without_html_escaping() {
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<script>let state = <?rs @json state ?>;</script>".to_string());
+
+        let generator = CodeGenerator {
+            options: GeneratorOptions { escaping: Escaping::Html, ..GeneratorOptions::default() },
+            ..CodeGenerator::default()
+        };
+        let code = generate_function("test_template".to_string(), "", &[], result, generator, &|_| None).unwrap();
+
+        assert!(code.contains(&"output_buffer.push_str(&plt::json::script_json(&(state))?);".to_string()));
+    }
+
+    #[test]
+    fn it_dumps_values_only_in_debug_builds() {
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<?rs @dump user ?>".to_string());
+
+        let code = generate_function("test_template".to_string(), "", &[], result, CodeGenerator::default(), &|_| None).unwrap();
+        assert!(code.join("\n").contains("if cfg!(debug_assertions) {\noutput_buffer.push_str(&plt::prelude::dump(&(user)));\n}"));
+    }
+
+    #[test]
+    fn it_renders_fallbacks_of_failing_sections() {
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<?rs @try ?><?= widget()? ?><?rs @rescue error ?>failed<?rs @endtry ?>".to_string());
+        let code = generate_file("test_template", Vec::new(), result).unwrap().join("\n");
+
+        assert!(code.contains("let try_output = (|| -> plt::prelude::Result<String> {\nlet mut output_buffer = String::new();"));
+        assert!(code.contains("Ok(output_buffer)\n})();\nmatch try_output {\nOk(try_output) => {\noutput_buffer.push_str(&try_output);\n}\nErr(error) => {"));
+
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<?rs @try ?>a<?rs @rescue ?>b<?rs @rescue ?>c<?rs @endtry ?>".to_string());
+        assert_eq!(generate_file("test_template", Vec::new(), result).unwrap_err().to_string(), "`@try` has more than one `@rescue`");
+
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<?rs @try ?>a".to_string());
+        assert_eq!(generate_file("test_template", Vec::new(), result).unwrap_err().to_string(), "`@try` is missing its `@endtry`");
+    }
+
+    #[test]
+    fn it_folds_constant_echoes_into_text() {
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<a title=\"<?= 1 + 1 ?>\" href=\"?q=<?= \"a b\" ?>\">&<?= \"<b>\" ?><?= name ?><?rs @const 60 * 60 ?></a>".to_string());
+
+        let generator = CodeGenerator {
+            options: GeneratorOptions { escaping: Escaping::Contextual, fold_constants: true, ..GeneratorOptions::default() },
+            ..CodeGenerator::default()
+        };
+        let code = generate_function("test_template".to_string(), "", &[], result, generator, &|_| None).unwrap();
+
+        assert!(code.contains(&"write!(output_buffer, \"{}\", \"<a title=\\\"2\\\" href=\\\"?q=a%20b\\\">&&lt;b&gt;\")?;".to_string()));
+        assert!(code.contains(&"write!(output_buffer, \"{}\", plt::prelude::EscapeHtml(&( name )))?;".to_string()));
+        assert!(code.contains(&"write!(output_buffer, \"{}\", \"3600</a>\")?;".to_string()));
+    }
+
+    #[test]
+    fn it_minifies_text_before_generating_code() {
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<ul>\n  <?rs @for item in items ?>\n  <li><?= item ?></li>\n  <?rs @endfor ?>\n</ul>\n".to_string());
+
+        let generator = CodeGenerator { options: GeneratorOptions { minify: Minify::Whitespace, ..GeneratorOptions::default() }, ..CodeGenerator::default() };
+        let code = generate_function("test_template".to_string(), "", &[], result, generator, &|_| None).unwrap();
+
+        assert!(code.contains(&"write!(output_buffer, \"{}\", \"<ul>\")?;".to_string()));
+        assert!(code.contains(&"write!(output_buffer, \"{}\", \"<li>\")?;".to_string()));
+        assert!(code.contains(&"write!(output_buffer, \"{}\", \"</ul>\")?;".to_string()));
+        assert!(!code.contains(&"write!(output_buffer, \"{}\", \"\")?;".to_string()));
+    }
+
+    #[cfg(feature = "compress")]
+    #[test]
+    fn it_compresses_long_text() {
+        let text = "<tr><td>a</td></tr>\n".repeat(100);
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run(format!("<p><?= a ?></p>{text}"));
+
+        let generator = CodeGenerator { options: GeneratorOptions { compress_text: Some(1024), ..GeneratorOptions::default() }, ..CodeGenerator::default() };
+        let code = generate_function("test_template".to_string(), "", &[], result, generator, &|_| None).unwrap().join("\n");
+        try_format_code(&code).unwrap();
+
+        assert!(code.contains("write!(output_buffer, \"{}\", \"<p>\")?;"));
+        let start = code.find("plt::compress::decompress(&[").unwrap() + 28;
+        let end = start + code[start..].find(']').unwrap();
+        let bytes: Vec<u8> = code[start..end].split(", ").map(|byte| byte.parse().unwrap()).collect();
+        assert_eq!(crate::compress::decompress(&bytes), format!("</p>{text}"));
+    }
+
+    #[test]
+    fn it_echoes_constants_without_folding_them_by_default() {
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("a<?= 1 + 1 ?>b<?rs @const 2 ?>".to_string());
+        let code = generate_file("test_template", Vec::new(), result).unwrap();
+
+        assert!(code.contains(&"write!(output_buffer, \"{}\", ( 1 + 1 ))?;".to_string()));
+        assert!(code.contains(&"write!(output_buffer, \"{}\", \"b2\")?;".to_string()));
+    }
+
+    #[test]
+    fn it_writes_build_constants_as_text() {
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<footer data-crate=\"<?rs @env \"CARGO_PKG_NAME\" ?>\">".to_string());
+        let code = generate_file("test_template", Vec::new(), result).unwrap();
+        assert!(code.contains(&"write!(output_buffer, \"{}\", \"<footer data-crate=\\\"plt\\\">\")?;".to_string()));
+
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<?rs @env \"PLT_TEST_MISSING\" ?>".to_string());
+        assert_eq!(
+            generate_file("test_template", Vec::new(), result).unwrap_err().to_string(),
+            "environment variable `PLT_TEST_MISSING` used by `@env` is not set"
+        );
+
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<div <?rs @built_at ?>>".to_string());
+        let generator = CodeGenerator {
+            options: GeneratorOptions { escaping: Escaping::Contextual, ..GeneratorOptions::default() },
+            ..CodeGenerator::default()
+        };
+        let error = generate_function("test_template".to_string(), "", &[], result, generator, &|_| None).unwrap_err();
+        assert_eq!(error.to_string(), "`@built_at` can't be written inside of a tag");
+    }
+
+    #[test]
+    fn it_guards_sections_by_cfg_predicates() {
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<?rs @cfg(feature = \"premium\") ?>a<?rs @else ?>b<?rs @endcfg ?>".to_string());
+        let code = generate_file("test_template", Vec::new(), result).unwrap().join("\n");
+
+        assert!(code.contains(
+            "#[cfg(feature = \"premium\")]\n{\nwrite!(output_buffer, \"{}\", \"a\")?;\n}\n#[cfg(not(feature = \"premium\"))]\n{\nwrite!(output_buffer, \"{}\", \"b\")?;\n}"
+        ));
+
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<?rs @cfg(unix) ?>a<?rs @else ?>b<?rs @else ?>c<?rs @endcfg ?>".to_string());
+        assert_eq!(generate_file("test_template", Vec::new(), result).unwrap_err().to_string(), "`@cfg` has more than one `@else`");
+
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<?rs @if a ?><?rs @endcfg ?>".to_string());
+        assert_eq!(generate_file("test_template", Vec::new(), result).unwrap_err().to_string(), "`@endcfg` found without matching `@cfg`");
+    }
+
+    #[test]
+    fn it_generates_functions_for_no_std_crates() {
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<p><?= name ?></p>".to_string());
+        let generator = CodeGenerator {
+            options: GeneratorOptions { escaping: Escaping::Html, no_std: true, ..GeneratorOptions::default() },
+            ..CodeGenerator::default()
+        };
+        let code = generate_function("test_template".to_string(), "pub", &["name: &str".to_string()], result, generator, &|_| None).unwrap().join("\n");
+
+        assert!(code.contains("-> plt::prelude::Result<alloc::string::String> {\nuse core::fmt::Write;\n"), "{code}");
+        assert!(code.contains("use alloc::string::{String, ToString};\nlet mut output_buffer = String::new();"), "{code}");
+        assert!(!code.contains("std::"), "{code}");
+    }
+
+    #[test]
+    fn it_rejects_unbalanced_capture_directives() {
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<?rs @capture title ?>Hello".to_string());
+        assert!(generate_file("test_template", Vec::new(), result).is_err());
+
+        let mut fsa = TextCodeFSA::new();
+        let result = fsa.run("<?rs @match status ?><?rs @when _ ?><?rs @endcapture ?>".to_string());
+        assert!(generate_file("test_template", Vec::new(), result).is_err());
+    }
+
+    #[test]
+    fn it_formats_code_with_rustfmt() {
+        assert_eq!("rustfmt".parse::<CodeFormatter>().unwrap(), CodeFormatter::Rustfmt);
+        assert!("rustfmt ".parse::<CodeFormatter>().is_err());
+        assert!(try_format_code_with("fn a( {}", CodeFormatter::Rustfmt).is_err());
+
+        // rustfmt isn't available everywhere, e.g. in minimal toolchains.
+        if std::process::Command::new("rustfmt").arg("--version").output().is_err() {
+            return;
+        }
+        let code = try_format_code_with("fn  a( )->u8{let b=[1,2];b[0]}", CodeFormatter::Rustfmt).unwrap();
+        assert_eq!(code, "fn a() -> u8 {\n    let b = [1, 2];\n    b[0]\n}\n");
+    }
 }
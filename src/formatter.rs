@@ -0,0 +1,212 @@
+use anyhow::bail;
+use rustc_lexer::TokenKind;
+pub use crate::prelude::*;
+
+#[derive(Debug, Clone, Default)]
+pub struct FormatOptions {
+    // Removes spaces and tabs at the end of lines in text parts. Unlike the rest of the
+    // formatting, this changes the rendered output.
+    pub trim_trailing_whitespace: bool,
+}
+
+// Formats a template: tags get a single space of padding, e.g. `<?rs  x?>` becomes `<?rs x ?>`,
+// and multi-line code blocks are re-indented with prettyplease.
+//
+// Code is only reformatted when it keeps the same tokens, and the result is parsed again
+// to make sure it consists of the same parts as `source`.
+pub fn format_template(source: &str, options: &FormatOptions) -> Result<String> {
+    let mut fsa = TextCodeFSA::new();
+    let parts = fsa.run(source.to_string()).clone();
+    let spans = fsa.spans().to_vec();
+
+    if let Some(offset) = fsa.unterminated_tag() {
+        let (line, column) = line_column(source, offset);
+        bail!("{line}:{column}: unterminated tag, expected `?>`");
+    }
+
+    let mut formatted = String::new();
+    let mut expected_parts = Vec::new();
+    for (part, span) in parts.iter().zip(&spans) {
+        match part {
+            Part::Text(text) => {
+                let text = if options.trim_trailing_whitespace { trim_trailing_whitespace(text) } else { text.clone() };
+
+                formatted.push_str(&text);
+                expected_parts.push(Part::Text(text));
+            }
+            Part::Code(code) | Part::EchoCode(code) => {
+                let open_tag = if matches!(part, Part::Code(_)) { "<?rs" } else { "<?=" };
+                let indentation = line_indentation(source, span.start);
+
+                formatted.push_str(&format_tag(open_tag, code, &indentation, part));
+                expected_parts.push(part.clone());
+            }
+        }
+    }
+
+    // Safety net for the checks done per tag, the template must still render the same.
+    let mut fsa = TextCodeFSA::new();
+    let reparsed = fsa.run(formatted.clone());
+    if reparsed.len() != expected_parts.len()
+        || reparsed.iter().zip(&expected_parts).any(|(reparsed, expected)| !is_equivalent(reparsed, expected))
+    {
+        bail!("formatting would change the template's parts");
+    }
+
+    Ok(formatted)
+}
+
+fn trim_trailing_whitespace(text: &str) -> String {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let last_line = lines.len() - 1;
+
+    lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| {
+            // The last line continues up to the next tag, so its whitespace isn't trailing.
+            if index == last_line {
+                return line.to_string();
+            }
+
+            match line.strip_suffix('\r') {
+                Some(line) => format!("{}\r", line.trim_end_matches([' ', '\t'])),
+                None => line.trim_end_matches([' ', '\t']).to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Whitespace at the start of the line containing the byte `offset`.
+fn line_indentation(source: &str, offset: usize) -> String {
+    let line_start = source[..offset].rfind('\n').map_or(0, |index| index + 1);
+
+    source[line_start..]
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .collect()
+}
+
+fn format_tag(open_tag: &str, code: &str, indentation: &str, part: &Part) -> String {
+    let original = format!("{open_tag}{code}?>");
+
+    let candidate = match reindent_code(code, indentation) {
+        Some(body) => format!("{open_tag}\n{body}{indentation}?>"),
+        None if !code.trim().contains('\n') => format!("{open_tag} {} ?>", code.trim()),
+        None => original.clone(),
+    };
+
+    let mut fsa = TextCodeFSA::new();
+    match fsa.run(candidate.clone()).as_slice() {
+        [reparsed] if is_equivalent(reparsed, part) => candidate,
+        _ => original,
+    }
+}
+
+// Formats multi-line code consisting of complete statements, indented one level deeper than
+// its tag. Code containing comments is left alone, since prettyplease drops them.
+fn reindent_code(code: &str, indentation: &str) -> Option<String> {
+    if !code.trim().contains('\n') || has_comments(code) {
+        return None;
+    }
+
+    let file = syn::parse_file(&format!("fn plt_format() {{\n{code}\n}}")).ok()?;
+    let unparsed = prettyplease::unparse(&file);
+
+    let mut body = String::new();
+    for line in unparsed.lines().skip(1) {
+        if line == "}" {
+            break;
+        }
+
+        let line = line.strip_prefix("    ").unwrap_or(line);
+        if !line.is_empty() {
+            body.push_str(indentation);
+            body.push_str("    ");
+            body.push_str(line);
+        }
+        body.push('\n');
+    }
+
+    Some(body)
+}
+
+fn has_comments(code: &str) -> bool {
+    rustc_lexer::tokenize(code)
+        .any(|token| matches!(token.kind, TokenKind::LineComment | TokenKind::BlockComment { .. }))
+}
+
+// Non-whitespace tokens of the code.
+fn code_tokens(code: &str) -> Vec<&str> {
+    let mut offset = 0;
+    let mut tokens = Vec::new();
+
+    for token in rustc_lexer::tokenize(code) {
+        if token.kind != TokenKind::Whitespace {
+            tokens.push(&code[offset..offset + token.len]);
+        }
+        offset += token.len;
+    }
+
+    tokens
+}
+
+// Parts are equivalent when they render the same, i.e. their text is equal or their code
+// consists of the same tokens.
+fn is_equivalent(a: &Part, b: &Part) -> bool {
+    match (a, b) {
+        (Part::Text(a), Part::Text(b)) => a == b,
+        (Part::Code(a), Part::Code(b)) | (Part::EchoCode(a), Part::EchoCode(b)) => code_tokens(a) == code_tokens(b),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    #[test]
+    fn it_normalizes_tag_spacing() {
+        let formatted = format_template("<p><?=name?></p><?rs   let a = 1;?>", &FormatOptions::default()).unwrap();
+
+        assert_eq!(formatted, "<p><?= name ?></p><?rs let a = 1; ?>");
+    }
+
+    #[test]
+    fn it_reindents_code_blocks() {
+        let source = "<ul>\n  <?rs\nlet items = vec![1,\n 2];\n      let total: u32 = items.iter().sum();\n?>\n</ul>";
+
+        let formatted = format_template(source, &FormatOptions::default()).unwrap();
+
+        assert_eq!(formatted, "<ul>\n  <?rs\n      let items = vec![1, 2];\n      let total: u32 = items.iter().sum();\n  ?>\n</ul>");
+    }
+
+    #[test]
+    fn it_keeps_code_it_cannot_reformat() {
+        // Blocks continued by other tags don't parse on their own, comments would be dropped.
+        let source = "<?rs for i in 0..3 {\n  let x = i; ?>\n<?rs }  ?><?rs\n// note\nlet a = 1;\n?>";
+
+        let formatted = format_template(source, &FormatOptions::default()).unwrap();
+
+        assert_eq!(formatted, "<?rs for i in 0..3 {\n  let x = i; ?>\n<?rs } ?><?rs\n// note\nlet a = 1;\n?>");
+    }
+
+    #[test]
+    fn it_optionally_trims_trailing_whitespace() {
+        let source = "<p>  \r\n  text\t\n  <?= a ?>  \n";
+
+        assert_eq!(format_template(source, &FormatOptions::default()).unwrap(), source);
+        assert_eq!(
+            format_template(source, &FormatOptions { trim_trailing_whitespace: true }).unwrap(),
+            "<p>\r\n  text\n  <?= a ?>\n"
+        );
+    }
+
+    #[test]
+    fn it_rejects_unterminated_tags() {
+        let error = format_template("a\n<?rs x", &FormatOptions::default()).unwrap_err();
+
+        assert_eq!(error.to_string(), "2:1: unterminated tag, expected `?>`");
+    }
+}
@@ -0,0 +1,226 @@
+// Form controls bound to values and validation errors:
+//
+//     <?rs let form = plt::forms::Form::new(&errors); ?>
+//     <?= form.label("email", "E-mail") ?>
+//     <?= form.input("email", "email", &user.email).flag("required", true) ?>
+//     <?= form.errors("email") ?>
+//     <?= form.select("role", [("admin", "Administrator"), ("user", "User")], &user.role) ?>
+//
+// Controls get an `id` derived from their name, which labels point to, and fields with errors
+// are marked with `aria-invalid` and described by their error list. Values and messages are
+// escaped, while the markup around them is written as it is by `EscapeHtml`.
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::attributes::Attributes;
+use crate::prelude::*;
+
+// Validation messages of form fields, by field name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FormErrors {
+    errors: BTreeMap<String, Vec<String>>,
+}
+
+impl FormErrors {
+    pub fn new() -> FormErrors {
+        Self::default()
+    }
+
+    pub fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.errors.entry(field.into()).or_default().push(message.into());
+    }
+
+    pub fn field(&self, field: &str) -> &[String] {
+        self.errors.get(field).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn has(&self, field: &str) -> bool {
+        !self.field(field).is_empty()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for FormErrors {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(errors: I) -> Self {
+        let mut form_errors = FormErrors::new();
+        for (field, message) in errors {
+            form_errors.add(field, message);
+        }
+        form_errors
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Form<'a> {
+    errors: &'a FormErrors,
+}
+
+impl<'a> Form<'a> {
+    pub fn new(errors: &'a FormErrors) -> Form<'a> {
+        Form { errors }
+    }
+
+    // `<label>` of the control for the field.
+    pub fn label(&self, name: &str, text: impl fmt::Display) -> Control {
+        Control::new("label", Attributes::new().set("for", field_id(name))).content(EscapeHtml(text).to_string())
+    }
+
+    // `<input>` of the given type, e.g. `text`, `email` or `number`, holding `value`.
+    pub fn input(&self, name: &str, kind: &str, value: impl fmt::Display) -> Control {
+        self.control("input", name, Attributes::new().set("type", kind)).attr("value", value)
+    }
+
+    // `<input type="password">`, which never renders its value back.
+    pub fn password(&self, name: &str) -> Control {
+        self.control("input", name, Attributes::new().set("type", "password"))
+    }
+
+    pub fn checkbox(&self, name: &str, checked: bool) -> Control {
+        self.control("input", name, Attributes::new().set("type", "checkbox"))
+            .attr("value", "1")
+            .flag("checked", checked)
+    }
+
+    pub fn textarea(&self, name: &str, value: impl fmt::Display) -> Control {
+        self.control("textarea", name, Attributes::new()).content(EscapeHtml(value).to_string())
+    }
+
+    // `<select>` of `(value, label)` options, with the option whose value is `selected` selected.
+    pub fn select<V: fmt::Display, L: fmt::Display>(
+        &self,
+        name: &str,
+        options: impl IntoIterator<Item = (V, L)>,
+        selected: impl fmt::Display,
+    ) -> Control {
+        let selected = selected.to_string();
+        let options = options
+            .into_iter()
+            .map(|(value, label)| {
+                let value = value.to_string();
+                let attributes = Attributes::new().set("value", &value).flag("selected", value == selected);
+                Control::new("option", attributes).content(EscapeHtml(label).to_string()).to_string()
+            })
+            .collect();
+
+        self.control("select", name, Attributes::new()).content(options)
+    }
+
+    // List of the errors of the field, or nothing if it has none.
+    pub fn errors(&self, name: &str) -> Raw<String> {
+        let errors = self.errors.field(name);
+        if errors.is_empty() {
+            return Raw(String::new());
+        }
+
+        let items: String = errors.iter().map(|error| format!("<li>{}</li>", escape_html(error))).collect();
+        Raw(format!("<ul class=\"field-errors\" id=\"{}\">{items}</ul>", errors_id(name)))
+    }
+
+    fn control(&self, tag: &'static str, name: &str, attributes: Attributes) -> Control {
+        let attributes = attributes.set("name", name).set("id", field_id(name));
+        let control = Control::new(tag, attributes);
+        if self.errors.has(name) {
+            control.attr("aria-invalid", "true").attr("aria-describedby", errors_id(name))
+        } else {
+            control
+        }
+    }
+}
+
+// Rendered form control, which can be given additional attributes before it is echoed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Control {
+    tag: &'static str,
+    attributes: Attributes,
+    // Escaped content of elements with a closing tag, `None` for void elements.
+    content: Option<String>,
+}
+
+impl Control {
+    fn new(tag: &'static str, attributes: Attributes) -> Control {
+        Control { tag, attributes, content: None }
+    }
+
+    fn content(mut self, content: String) -> Self {
+        self.content = Some(content);
+        self
+    }
+
+    pub fn attr(mut self, name: impl Into<String>, value: impl fmt::Display) -> Self {
+        self.attributes = self.attributes.set(name, value);
+        self
+    }
+
+    pub fn flag(mut self, name: impl Into<String>, enabled: bool) -> Self {
+        self.attributes = self.attributes.flag(name, enabled);
+        self
+    }
+}
+
+impl fmt::Display for Control {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tag = self.tag;
+        match &self.content {
+            Some(content) => write!(f, "{}", Raw(format_args!("<{tag}{}>{content}</{tag}>", self.attributes))),
+            None => write!(f, "{}", Raw(format_args!("<{tag}{}>", self.attributes))),
+        }
+    }
+}
+
+// `id` of the control for the field, with characters like the brackets of `user[email]` replaced.
+fn field_id(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect()
+}
+
+fn errors_id(name: &str) -> String {
+    format!("{}-errors", field_id(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::forms::{Form, FormErrors};
+    use crate::prelude::*;
+
+    #[test]
+    fn it_renders_controls_with_values() {
+        let errors = FormErrors::new();
+        let form = Form::new(&errors);
+
+        assert_eq!(
+            escape_html(form.input("user[name]", "text", "\"Bob\" <b>").flag("required", true)),
+            "<input type=\"text\" name=\"user[name]\" id=\"user_name_\" value=\"&quot;Bob&quot; &lt;b&gt;\" required>"
+        );
+        assert_eq!(form.label("user[name]", "Name & surname").to_string(), "<label for=\"user_name_\">Name &amp; surname</label>");
+        assert_eq!(form.textarea("bio", "</textarea>").to_string(), "<textarea name=\"bio\" id=\"bio\">&lt;/textarea&gt;</textarea>");
+        assert_eq!(form.checkbox("admin", true).to_string(), "<input type=\"checkbox\" name=\"admin\" id=\"admin\" value=\"1\" checked>");
+        assert_eq!(form.errors("bio").to_string(), "");
+    }
+
+    #[test]
+    fn it_renders_selects() {
+        let errors = FormErrors::new();
+        let form = Form::new(&errors);
+
+        assert_eq!(
+            escape_html(form.select("role", [("admin", "Admin"), ("user", "<User>")], "user")),
+            "<select name=\"role\" id=\"role\"><option value=\"admin\">Admin</option><option value=\"user\" selected>&lt;User&gt;</option></select>"
+        );
+    }
+
+    #[test]
+    fn it_marks_fields_with_errors() {
+        let errors: FormErrors = [("email", "is required"), ("email", "must contain <@>")].into_iter().collect();
+        let form = Form::new(&errors);
+
+        assert_eq!(
+            form.password("email").to_string(),
+            "<input type=\"password\" name=\"email\" id=\"email\" aria-invalid=\"true\" aria-describedby=\"email-errors\">"
+        );
+        assert_eq!(
+            escape_html(form.errors("email")),
+            "<ul class=\"field-errors\" id=\"email-errors\"><li>is required</li><li>must contain &lt;@&gt;</li></ul>"
+        );
+    }
+}
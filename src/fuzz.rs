@@ -0,0 +1,256 @@
+// Entry points for fuzzing the parser, used by the `cargo fuzz` targets in `fuzz/` and usable by
+// applications fuzzing their own pipelines:
+//
+//     fuzz_target!(|data: &[u8]| plt::fuzz::fuzz_parse(data));
+//     fuzz_target!(|template: plt::fuzz::TemplateBuilder| plt::fuzz::fuzz_round_trip(&template));
+//
+// Both panic when an invariant of the parser doesn't hold, while errors about malformed
+// templates are expected and ignored. `TemplateBuilder` implements `arbitrary::Arbitrary` with
+// the `arbitrary` feature, generating templates which are valid as far as parsing goes.
+use crate::highlight::semantic_tokens;
+use crate::prelude::*;
+
+// Builds the nodes of a template, merging text written in a row into a single node the way it's
+// parsed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TemplateBuilder {
+    nodes: Vec<Node>,
+}
+
+impl TemplateBuilder {
+    pub fn new() -> TemplateBuilder {
+        TemplateBuilder::default()
+    }
+
+    pub fn text(mut self, text: &str) -> TemplateBuilder {
+        match self.nodes.last_mut() {
+            _ if text.is_empty() => {}
+            Some(Node::Text(last)) => last.push_str(text),
+            _ => self.nodes.push(Node::Text(text.to_string())),
+        }
+        self
+    }
+
+    pub fn code(mut self, code: &str) -> TemplateBuilder {
+        self.nodes.push(Node::Code(code.to_string()));
+        self
+    }
+
+    pub fn echo(mut self, code: &str) -> TemplateBuilder {
+        self.nodes.push(Node::Echo(code.to_string()));
+        self
+    }
+
+    pub fn directive(mut self, directive: Directive) -> TemplateBuilder {
+        self.nodes.push(Node::Directive(directive));
+        self
+    }
+
+    pub fn node(self, node: Node) -> TemplateBuilder {
+        match node {
+            Node::Text(text) => self.text(&text),
+            Node::Code(code) => self.code(&code),
+            Node::Echo(code) => self.echo(&code),
+            Node::Directive(directive) => self.directive(directive),
+        }
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    // Source of the template, failing for nodes which wouldn't parse back the same.
+    pub fn source(&self) -> Result<String> {
+        let template = ParsedTemplate { path: "fuzz.plt".to_string(), nodes: self.nodes.clone(), spans: Vec::new() };
+
+        template.to_source()
+    }
+}
+
+// Parses `data` as the source of a template, along with everything done with the parts of
+// templates before generating code: highlighting, parsing directives and linting.
+pub fn fuzz_parse(data: &[u8]) {
+    let source = String::from_utf8_lossy(data).into_owned();
+    let mut set = TemplateSet::new();
+    set.add_template("fuzz.plt", Vec::new(), source.clone());
+    let Some(template) = set.get("fuzz.plt") else {
+        unreachable!("the template was just added");
+    };
+
+    assert_eq!(template.parts.len(), template.spans.len());
+    let mut previous_end = 0;
+    for (part, span) in template.parts.iter().zip(&template.spans) {
+        assert!(previous_end <= span.start, "spans of parts overlap at {}", span.start);
+        assert_eq!(source.get(span.clone()), Some(part.get_content().as_str()), "span {span:?} doesn't point at its part");
+        previous_end = span.end;
+    }
+    if let Some(offset) = template.unterminated_tag {
+        assert!(source.get(offset..).is_some_and(|tag| tag.starts_with("<?")), "unterminated tag at {offset} isn't an open tag");
+    }
+
+    let mut previous_end = 0;
+    for token in semantic_tokens(template) {
+        assert!(previous_end <= token.span.start && token.span.start < token.span.end, "token {:?} is out of order", token.span);
+        assert!(source.get(token.span.start..token.span.end).is_some(), "token {:?} isn't on char boundaries", token.span);
+        previous_end = token.span.end;
+    }
+
+    if let Ok(parsed) = ParsedTemplate::parse(template) {
+        // Writing fails for nodes which wouldn't parse back the same, so succeeding is enough.
+        let _ = parsed.to_source();
+    }
+    set.diagnostics(&GeneratorOptions::default());
+}
+
+// Writes the template and checks that it parses back to the same nodes.
+pub fn fuzz_round_trip(builder: &TemplateBuilder) {
+    let Ok(source) = builder.source() else {
+        return;
+    };
+
+    let mut set = TemplateSet::new();
+    set.add_template("fuzz.plt", Vec::new(), source.clone());
+    let parsed = set.get("fuzz.plt").map(ParsedTemplate::parse);
+    match parsed {
+        Some(Ok(parsed)) => assert_eq!(parsed.nodes, builder.nodes(), "`{source}` parsed to different nodes"),
+        _ => panic!("`{source}` doesn't parse"),
+    }
+    fuzz_parse(source.as_bytes());
+}
+
+// `text` without the `?` of each `<?`, so it can't open a tag.
+pub fn without_open_tags(text: &str) -> String {
+    let mut result = String::new();
+    for c in text.chars() {
+        if !(c == '?' && result.ends_with('<')) {
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_template {
+    use arbitrary::{Arbitrary, Unstructured};
+    use crate::fuzz::{without_open_tags, TemplateBuilder};
+    use crate::prelude::*;
+
+    const CODE_CHARS: &[u8] = b"abcxyz019_ .,;+-*=()[]{}&!<>";
+    const IDENTIFIER_CHARS: &[u8] = b"abcxyz019_";
+
+    // Code made of chars which can't end the tag early, wrapped in spaces so that it neither
+    // starts with `@` nor touches the tags.
+    fn code(u: &mut Unstructured) -> arbitrary::Result<String> {
+        let len = u.int_in_range(1..=24)?;
+        let code = (0..len).map(|_| u.choose(CODE_CHARS).map(|&c| c as char)).collect::<arbitrary::Result<String>>()?;
+
+        Ok(format!(" {} ", code.trim()))
+    }
+
+    fn identifier(u: &mut Unstructured) -> arbitrary::Result<String> {
+        let len = u.int_in_range(0..=8)?;
+        let rest = (0..len).map(|_| u.choose(IDENTIFIER_CHARS).map(|&c| c as char)).collect::<arbitrary::Result<String>>()?;
+
+        Ok(format!("{}{rest}", *u.choose(b"abcxyz")? as char))
+    }
+
+
+    fn directive(u: &mut Unstructured) -> arbitrary::Result<Directive> {
+        let directive = match u.int_in_range(0..=17)? {
+            0 => Directive::If(code(u)?.trim().to_string()),
+            1 => Directive::ElseIf(code(u)?.trim().to_string()),
+            2 => Directive::Else,
+            3 => Directive::EndIf,
+            4 => Directive::For { pattern: identifier(u)?, iterable: code(u)?.trim().to_string() },
+            5 => Directive::EndFor,
+            6 => Directive::Match(code(u)?.trim().to_string()),
+            7 => Directive::When(code(u)?.trim().to_string()),
+            8 => Directive::EndMatch,
+            9 => Directive::Block(identifier(u)?),
+            10 => Directive::EndBlock,
+            11 => Directive::Capture(identifier(u)?),
+            12 => Directive::EndCapture,
+            13 => Directive::Let(format!("{} = {}", identifier(u)?, code(u)?.trim())),
+            14 => Directive::Include { path: format!("{}.plt", identifier(u)?), args: String::new() },
+            15 => Directive::Try,
+            16 => Directive::Rescue(Some(identifier(u)?)),
+            _ => Directive::EndTry,
+        };
+
+        Ok(directive)
+    }
+
+    impl<'a> Arbitrary<'a> for TemplateBuilder {
+        fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<TemplateBuilder> {
+            let mut builder = TemplateBuilder::new();
+
+            for _ in 0..u.int_in_range(0..=32)? {
+                let candidate = match u.int_in_range(0..=3)? {
+                    0 => builder.clone().text(&without_open_tags(&String::arbitrary(u)?)),
+                    1 => builder.clone().code(&code(u)?),
+                    2 => builder.clone().echo(&code(u)?),
+                    _ => builder.clone().directive(directive(u)?),
+                };
+                // Generated code can still be rejected by directives, e.g. a keyword as a name.
+                if candidate.source().is_ok() {
+                    builder = candidate;
+                }
+            }
+
+            Ok(builder)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fuzz::{fuzz_parse, fuzz_round_trip, TemplateBuilder};
+    use crate::prelude::*;
+
+    #[test]
+    fn it_parses_adversarial_input() {
+        let inputs: [&[u8]; 8] = [
+            b"",
+            b"<?",
+            b"<?rs",
+            b"<?=\xff?>",
+            "é<?rs \"ü?>\" ?>ß<?= '?' ?>".as_bytes(),
+            b"<?rs /* ?> */ // ?>\n?>",
+            b"<?rs @if ?><?rs @endfor x ?><?rs @",
+            b"<?rs r#\"?>\"# ?><?rs b'?' ?>",
+        ];
+
+        for input in inputs {
+            fuzz_parse(input);
+        }
+    }
+
+    #[test]
+    fn it_builds_templates_which_parse_back_the_same() {
+        let builder = TemplateBuilder::new()
+            .text("<p>")
+            .text("a")
+            .directive(Directive::If("b".to_string()))
+            .echo(" b ")
+            .directive(Directive::EndIf)
+            .code(" let c = 1; ");
+
+        assert_eq!(builder.nodes()[0], Node::Text("<p>a".to_string()));
+        assert_eq!(builder.source().unwrap(), "<p>a<?rs @if b ?><?= b ?><?rs @endif ?><?rs let c = 1; ?>");
+        fuzz_round_trip(&builder);
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn it_generates_arbitrary_templates() {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        for seed in 0..64u32 {
+            let data: Vec<u8> = (0..512u32).map(|i| (i.wrapping_mul(2_654_435_761).wrapping_add(seed * 7919) >> 13) as u8).collect();
+            let builder = TemplateBuilder::arbitrary(&mut Unstructured::new(&data)).unwrap();
+
+            fuzz_round_trip(&builder);
+        }
+    }
+}
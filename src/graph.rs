@@ -0,0 +1,175 @@
+// Graph of which templates depend on which others, e.g. to see what a refactoring of a layout
+// affects, written as Mermaid or Graphviz:
+//
+//     println!("{}", set.dependency_graph()?.write(GraphFormat::Mermaid));
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use anyhow::bail;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DependencyKind {
+    Include,
+    Extends,
+    Call,
+    Use,
+}
+
+impl DependencyKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            DependencyKind::Include => "include",
+            DependencyKind::Extends => "extends",
+            DependencyKind::Call => "call",
+            DependencyKind::Use => "use",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DependencyEdge {
+    pub from: String,
+    pub to: String,
+    pub kind: DependencyKind,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DependencyGraph {
+    // Paths of the templates of the set, along with the ones they refer to which are missing.
+    pub templates: BTreeSet<String>,
+    pub edges: BTreeSet<DependencyEdge>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GraphFormat {
+    #[default]
+    Mermaid,
+    // Graphviz, rendered using e.g. `dot -Tsvg`.
+    Dot,
+}
+
+impl FromStr for GraphFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<GraphFormat> {
+        match format {
+            "mermaid" => Ok(GraphFormat::Mermaid),
+            "dot" => Ok(GraphFormat::Dot),
+            _ => bail!("unknown graph format `{format}`, expected `mermaid` or `dot`"),
+        }
+    }
+}
+
+impl TemplateSet {
+    // Templates each template includes, extends, calls as components and uses macros of.
+    pub fn dependency_graph(&self) -> Result<DependencyGraph> {
+        let mut graph = DependencyGraph::default();
+
+        for (path, template) in self.paths().filter_map(|path| Some((path, self.get(path)?))) {
+            graph.templates.insert(path.clone());
+
+            let mut dependencies: Vec<(String, DependencyKind)> = Vec::new();
+            dependencies.extend(template.includes()?.into_iter().map(|to| (to, DependencyKind::Include)));
+            dependencies.extend(template.extends()?.into_iter().map(|to| (to, DependencyKind::Extends)));
+            dependencies.extend(template.components()?.into_iter().map(|to| (to, DependencyKind::Call)));
+            for name in template.uses()? {
+                dependencies.push((self.macro_path(path, &name)?, DependencyKind::Use));
+            }
+
+            for (to, kind) in dependencies {
+                graph.templates.insert(to.clone());
+                graph.edges.insert(DependencyEdge { from: path.clone(), to, kind });
+            }
+        }
+
+        Ok(graph)
+    }
+}
+
+impl DependencyGraph {
+    pub fn write(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Mermaid => self.write_mermaid(),
+            GraphFormat::Dot => self.write_dot(),
+        }
+    }
+
+    // Nodes are numbered, as paths aren't valid Mermaid ids.
+    fn write_mermaid(&self) -> String {
+        let ids: Vec<&String> = self.templates.iter().collect();
+        let id = |path: &String| ids.binary_search(&path).unwrap_or_default();
+
+        let mut mermaid = "graph LR\n".to_string();
+        for (index, path) in ids.iter().enumerate() {
+            mermaid.push_str(&format!("    t{index}[\"{}\"]\n", path.replace('"', "#quot;")));
+        }
+        for edge in &self.edges {
+            mermaid.push_str(&format!("    t{} -->|{}| t{}\n", id(&edge.from), edge.kind.name(), id(&edge.to)));
+        }
+
+        mermaid
+    }
+
+    fn write_dot(&self) -> String {
+        let quote = |path: &str| format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""));
+
+        let mut dot = "digraph templates {\n    rankdir=LR;\n".to_string();
+        for path in &self.templates {
+            dot.push_str(&format!("    {};\n", quote(path)));
+        }
+        for edge in &self.edges {
+            dot.push_str(&format!("    {} -> {} [label=\"{}\"];\n", quote(&edge.from), quote(&edge.to), edge.kind.name()));
+        }
+        dot.push_str("}\n");
+
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    fn set() -> TemplateSet {
+        let mut set = TemplateSet::new();
+        set.add_template("layout.plt", Vec::new(), "<?rs @include \"header.plt\" ?><?rs @yield content ?>".to_string());
+        set.add_template("header.plt", Vec::new(), "<?rs @use logo ?>".to_string());
+        set.add_template("macros.plt", Vec::new(), "<?rs @def logo ?><img><?rs @enddef ?>".to_string());
+        set.add_template("page.plt", Vec::new(), "<?rs @extends \"layout.plt\" ?><?rs @call \"card.plt\" ?><?rs @endcall ?>".to_string());
+        set
+    }
+
+    #[test]
+    fn it_builds_the_dependency_graph() {
+        let graph = set().dependency_graph().unwrap();
+
+        let edges: Vec<_> = graph.edges.iter().map(|edge| (edge.from.as_str(), edge.kind.name(), edge.to.as_str())).collect();
+        assert_eq!(
+            edges,
+            vec![
+                ("header.plt", "use", "macros.plt#logo"),
+                ("layout.plt", "include", "header.plt"),
+                ("page.plt", "call", "card.plt"),
+                ("page.plt", "extends", "layout.plt"),
+            ]
+        );
+        assert!(graph.templates.contains("card.plt"));
+    }
+
+    #[test]
+    fn it_writes_the_dependency_graph() {
+        let graph = set().dependency_graph().unwrap();
+
+        let mermaid = graph.write(GraphFormat::Mermaid);
+        assert!(mermaid.starts_with("graph LR\n    t0[\"card.plt\"]\n    t1[\"header.plt\"]\n"));
+        assert!(mermaid.contains("    t5 -->|extends| t2\n"));
+
+        let dot = graph.write(GraphFormat::Dot);
+        assert!(dot.starts_with("digraph templates {\n    rankdir=LR;\n    \"card.plt\";\n"));
+        assert!(dot.contains("    \"page.plt\" -> \"layout.plt\" [label=\"extends\"];\n"));
+        assert_eq!(
+            "svg".parse::<GraphFormat>().unwrap_err().to_string(),
+            "unknown graph format `svg`, expected `mermaid` or `dot`"
+        );
+    }
+}
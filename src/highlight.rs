@@ -0,0 +1,302 @@
+// Classification of the source of templates for editor plugins highlighting `.plt` files, e.g.
+// by `plt tokens index.plt --format lsp`. Each byte of the source belongs to at most one token,
+// and tokens are in order of their span:
+//
+//     <h1><?= title ?></h1>
+//     ^^^^ text
+//         ^^^ open-tag
+//            ^^^^^^^ echo
+//                   ^^ close-tag
+//
+// `to_lsp` encodes them as the data of LSP semantic tokens, with the token types of
+// `TokenClass::ALL` as the legend, and `TokenClass::textmate_scope` names their TextMate scope.
+use std::ops::Range;
+use std::str::FromStr;
+use anyhow::bail;
+use rustc_lexer::{LiteralKind, TokenKind};
+use crate::diagnostics::json_string;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenClass {
+    Text,
+    // `<?rs` and `<?=`.
+    OpenTag,
+    // `?>`.
+    CloseTag,
+    // Rust code of `<?rs ?>` tags, including the arguments of directives.
+    Code,
+    // Rust expression of `<?= ?>` tags.
+    Echo,
+    // Name of a directive, e.g. `@if`.
+    Directive,
+    // String literals inside of code and echoes.
+    String,
+}
+
+impl TokenClass {
+    pub const ALL: [TokenClass; 7] = [
+        TokenClass::Text,
+        TokenClass::OpenTag,
+        TokenClass::CloseTag,
+        TokenClass::Code,
+        TokenClass::Echo,
+        TokenClass::Directive,
+        TokenClass::String,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenClass::Text => "text",
+            TokenClass::OpenTag => "open-tag",
+            TokenClass::CloseTag => "close-tag",
+            TokenClass::Code => "code",
+            TokenClass::Echo => "echo",
+            TokenClass::Directive => "directive",
+            TokenClass::String => "string",
+        }
+    }
+
+    pub fn textmate_scope(&self) -> &'static str {
+        match self {
+            TokenClass::Text => "text.html.plt",
+            TokenClass::OpenTag => "punctuation.section.embedded.begin.plt",
+            TokenClass::CloseTag => "punctuation.section.embedded.end.plt",
+            TokenClass::Code => "source.rust.embedded.plt",
+            TokenClass::Echo => "source.rust.embedded.echo.plt",
+            TokenClass::Directive => "keyword.control.directive.plt",
+            TokenClass::String => "string.quoted.double.rust",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemanticToken {
+    pub class: TokenClass,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenFormat {
+    // Data of LSP semantic tokens along with their legend, as a JSON object.
+    Lsp,
+    // A JSON object per token with its span and TextMate scope.
+    TextMate,
+}
+
+impl FromStr for TokenFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<TokenFormat> {
+        match format {
+            "lsp" => Ok(TokenFormat::Lsp),
+            "textmate" => Ok(TokenFormat::TextMate),
+            _ => bail!("unknown token format `{format}`, expected `lsp` or `textmate`"),
+        }
+    }
+}
+
+// Tokens of the source of the template, in order.
+pub fn semantic_tokens(template: &Template) -> Vec<SemanticToken> {
+    let source = &template.source;
+    let mut tokens = Vec::new();
+    let mut push = |class: TokenClass, range: Range<usize>| {
+        if !range.is_empty() {
+            tokens.push(SemanticToken { class, span: Span::new(source, range.start, range.end) });
+        }
+    };
+
+    for (index, (part, span)) in template.parts.iter().zip(&template.spans).enumerate() {
+        let (open_tag, class) = match part {
+            Part::Text(_) => {
+                push(TokenClass::Text, span.clone());
+                continue;
+            }
+            Part::Code(_) => ("<?rs", TokenClass::Code),
+            Part::EchoCode(_) => ("<?=", TokenClass::Echo),
+        };
+
+        // Markdown templates are rendered before being split into parts, so their spans don't
+        // point at the tags.
+        let open_start = span.start.saturating_sub(open_tag.len());
+        if source.get(open_start..span.start) != Some(open_tag) {
+            continue;
+        }
+        push(TokenClass::OpenTag, open_start..span.start);
+
+        let code = &source[span.clone()];
+        let mut offset = 0;
+        if let Some(name_len) = (class == TokenClass::Code).then(|| directive_name_len(code)).flatten() {
+            offset = code.len() - code.trim_start().len();
+            push(TokenClass::Directive, span.start + offset..span.start + offset + name_len);
+            offset += name_len;
+        }
+        for (string, range) in code_tokens(&code[offset..]) {
+            let class = if string { TokenClass::String } else { class };
+            push(class, span.start + offset + range.start..span.start + offset + range.end);
+        }
+
+        let is_unterminated = index + 1 == template.parts.len() && template.unterminated_tag.is_some();
+        if !is_unterminated && source.get(span.end..span.end + 2) == Some("?>") {
+            push(TokenClass::CloseTag, span.end..span.end + 2);
+        }
+    }
+
+    tokens
+}
+
+// Length of the `@name` of the directive `code` starts with, if it's one.
+fn directive_name_len(code: &str) -> Option<usize> {
+    let name = code.trim_start().strip_prefix('@')?;
+    let name_len = name.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(name.len());
+
+    Directive::parse(code).ok().flatten().map(|_| name_len + 1)
+}
+
+// Runs of code between string literals, along with whether they are a string literal.
+fn code_tokens(code: &str) -> Vec<(bool, Range<usize>)> {
+    let mut runs: Vec<(bool, Range<usize>)> = Vec::new();
+    let mut offset = 0;
+
+    for token in rustc_lexer::tokenize(code) {
+        let string = matches!(
+            token.kind,
+            TokenKind::Literal {
+                kind: LiteralKind::Str { .. } | LiteralKind::RawStr { .. } | LiteralKind::ByteStr { .. } | LiteralKind::RawByteStr { .. },
+                ..
+            }
+        );
+        let range = offset..offset + token.len;
+        offset += token.len;
+
+        match runs.last_mut() {
+            Some((last_string, last)) if !string && !*last_string => last.end = range.end,
+            _ => runs.push((string, range)),
+        }
+    }
+
+    runs
+}
+
+// Data of LSP semantic tokens, with positions in UTF-16 code units and tokens spanning several
+// lines split at their line breaks.
+pub fn to_lsp(tokens: &[SemanticToken], source: &str) -> Vec<u32> {
+    let mut data = Vec::new();
+    let (mut previous_line, mut previous_start) = (0, 0);
+
+    for token in tokens {
+        let class = TokenClass::ALL.iter().position(|class| *class == token.class).unwrap_or_default() as u32;
+        let mut line = token.span.line as u32 - 1;
+        let line_start = source[..token.span.start].rfind('\n').map_or(0, |newline| newline + 1);
+        let mut start = utf16_len(&source[line_start..token.span.start]);
+
+        for (index, piece) in source[token.span.start..token.span.end].split('\n').enumerate() {
+            if index > 0 {
+                line += 1;
+                start = 0;
+            }
+            let len = utf16_len(piece.strip_suffix('\r').unwrap_or(piece));
+            if len == 0 {
+                continue;
+            }
+
+            let delta_start = if line == previous_line { start - previous_start } else { start };
+            data.extend([line - previous_line, delta_start, len, class, 0]);
+            (previous_line, previous_start) = (line, start);
+        }
+    }
+
+    data
+}
+
+fn utf16_len(text: &str) -> u32 {
+    text.encode_utf16().count() as u32
+}
+
+pub fn tokens_to_json(tokens: &[SemanticToken], source: &str, format: TokenFormat) -> String {
+    match format {
+        TokenFormat::Lsp => {
+            let types: Vec<_> = TokenClass::ALL.iter().map(|class| json_string(class.name())).collect();
+            let data: Vec<_> = to_lsp(tokens, source).iter().map(ToString::to_string).collect();
+
+            format!("{{\"legend\":{{\"tokenTypes\":[{}],\"tokenModifiers\":[]}},\"data\":[{}]}}", types.join(","), data.join(","))
+        }
+        TokenFormat::TextMate => {
+            let tokens: Vec<_> = tokens
+                .iter()
+                .map(|token| {
+                    format!(
+                        "{{\"span\":{},\"class\":{},\"scope\":{}}}",
+                        token.span.to_json(),
+                        json_string(token.class.name()),
+                        json_string(token.class.textmate_scope())
+                    )
+                })
+                .collect();
+
+            tokens.join("\n")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::highlight::{semantic_tokens, to_lsp, TokenClass};
+    use crate::prelude::*;
+
+    fn tokens(source: &str) -> Vec<(TokenClass, String)> {
+        let mut set = TemplateSet::new();
+        set.add_template("index.plt", Vec::new(), source.to_string());
+
+        semantic_tokens(set.get("index.plt").unwrap())
+            .into_iter()
+            .map(|token| (token.class, source[token.span.start..token.span.end].to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn it_classifies_the_source_of_templates() {
+        use TokenClass::*;
+
+        assert_eq!(
+            tokens("<p><?rs @if name == \"?>\" ?><?= format!(\"{name}\") ?><?rs let a = 1; ?>"),
+            vec![
+                (Text, "<p>".to_string()),
+                (OpenTag, "<?rs".to_string()),
+                (Directive, "@if".to_string()),
+                (Code, " name == ".to_string()),
+                (String, "\"?>\"".to_string()),
+                (Code, " ".to_string()),
+                (CloseTag, "?>".to_string()),
+                (OpenTag, "<?=".to_string()),
+                (Echo, " format!(".to_string()),
+                (String, "\"{name}\"".to_string()),
+                (Echo, ") ".to_string()),
+                (CloseTag, "?>".to_string()),
+                (OpenTag, "<?rs".to_string()),
+                (Code, " let a = 1; ".to_string()),
+                (CloseTag, "?>".to_string()),
+            ]
+        );
+        assert_eq!(tokens("a <?= b")[2], (Echo, " b".to_string()));
+    }
+
+    #[test]
+    fn it_encodes_tokens_for_lsp() {
+        let source = "é<?= a ?>\nb";
+        let mut set = TemplateSet::new();
+        set.add_template("index.plt", Vec::new(), source.to_string());
+
+        let tokens = semantic_tokens(set.get("index.plt").unwrap());
+        assert_eq!(
+            to_lsp(&tokens, source),
+            vec![
+                0, 0, 1, 0, 0, //
+                0, 1, 3, 1, 0, //
+                0, 3, 3, 4, 0, //
+                0, 3, 2, 2, 0, //
+                1, 0, 1, 0, 0,
+            ]
+        );
+    }
+}
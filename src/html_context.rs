@@ -0,0 +1,438 @@
+// Minimal HTML context of echo sites, used by `Escaping::Contextual` to pick the escaping of
+// each echoed value. The context is tracked through the text parts in order of appearance,
+// without following the control flow of code parts, and text rendered by includes or
+// directives is assumed to leave it unchanged:
+//
+//     <a href="<?= url ?>?q=<?= query ?>" title="<?= title ?>"><?= name ?></a>
+//     <script>let name = "<?= name ?>";</script>
+//
+// echoes `url` using `EscapeUrl`, `query` using `EscapeUrlComponent`, `title` and `name` using
+// `EscapeHtml` and the script's `name` using `EscapeJsString`. Echo sites where no escaping is
+// known to be safe, e.g. outside of a string literal in a script, are reported as errors.
+// Inside of tags, only `Attributes` can be echoed.
+use anyhow::bail;
+use crate::prelude::*;
+
+// Attributes holding URLs.
+const URL_ATTRIBUTES: [&str; 9] = ["action", "background", "cite", "data", "formaction", "href", "poster", "src", "srcset"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeKind {
+    Plain,
+    Url,
+    // Event handlers like `onclick`.
+    Script,
+    Style,
+    // `srcdoc`, holding the HTML of an iframe.
+    Html,
+    // `content` of a `<meta>` tag which isn't named metadata, e.g. the URL of
+    // `<meta http-equiv="refresh" content="0; url=...">`.
+    MetaContent,
+}
+
+impl AttributeKind {
+    fn of(name: &str) -> AttributeKind {
+        let name = name.to_ascii_lowercase();
+
+        if URL_ATTRIBUTES.contains(&name.as_str()) {
+            AttributeKind::Url
+        } else if name == "srcdoc" {
+            AttributeKind::Html
+        } else if name.starts_with("on") {
+            AttributeKind::Script
+        } else if name == "style" {
+            AttributeKind::Style
+        } else {
+            AttributeKind::Plain
+        }
+    }
+}
+
+// Part of a URL attribute value an echo site is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UrlPart {
+    Start,
+    Path,
+    // Query or fragment, after `?` or `#`.
+    Query,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScriptState {
+    Code,
+    String { quote: char, escaped: bool },
+    LineComment,
+    BlockComment,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HtmlContext {
+    #[default]
+    Text,
+    // Inside of a tag, outside of attribute values.
+    Tag,
+    // Inside of an attribute value, `quote` being `None` for unquoted values.
+    AttributeValue { kind: AttributeKind, quote: Option<char>, url_part: UrlPart },
+    // Inside of `<script>`, within a string literal quoted by `string` if any.
+    Script { string: Option<char> },
+    Style,
+    Comment,
+}
+
+// Escapers of the echo sites of `HtmlContext`s, named like their types in `escape`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escaper {
+    Html,
+    Url,
+    NormalizeUrl,
+    UrlComponent,
+    JsString,
+}
+
+impl Escaper {
+    fn name(self) -> &'static str {
+        match self {
+            Escaper::Html => "EscapeHtml",
+            Escaper::Url => "EscapeUrl",
+            Escaper::NormalizeUrl => "NormalizeUrl",
+            Escaper::UrlComponent => "EscapeUrlComponent",
+            Escaper::JsString => "EscapeJsString",
+        }
+    }
+
+    fn escape(self, value: &str) -> String {
+        match self {
+            Escaper::Html => EscapeHtml(value).to_string(),
+            Escaper::Url => EscapeUrl(value).to_string(),
+            Escaper::NormalizeUrl => NormalizeUrl(value).to_string(),
+            Escaper::UrlComponent => EscapeUrlComponent(value).to_string(),
+            Escaper::JsString => EscapeJsString(value).to_string(),
+        }
+    }
+}
+
+impl HtmlContext {
+    // Expression echoing `code` in this context.
+    pub fn echo_expression(&self, code: &str) -> Result<String> {
+        match self.escaper(code)? {
+            Some(escaper) => Ok(format!("plt::prelude::{}(&({code}))", escaper.name())),
+            // Only `Attributes` can be echoed inside of tags, which is checked by the compiler.
+            None => Ok(format!("plt::attributes::Attributes::in_tag(&({code}))")),
+        }
+    }
+
+    // `value` of the constant `code` escaped like echoes in this context, or `None` inside of
+    // tags, where it isn't `Attributes`.
+    pub fn escape(&self, code: &str, value: &str) -> Result<Option<String>> {
+        Ok(self.escaper(code)?.map(|escaper| escaper.escape(value)))
+    }
+
+    fn escaper(&self, code: &str) -> Result<Option<Escaper>> {
+        let escaper = match self {
+            HtmlContext::Text | HtmlContext::Comment => Escaper::Html,
+            HtmlContext::AttributeValue { quote: None, .. } => {
+                bail!("ambiguous escaping context: `{}` is echoed into an unquoted attribute value, quote it", code.trim());
+            }
+            HtmlContext::AttributeValue { kind: AttributeKind::Plain, .. } => Escaper::Html,
+            HtmlContext::AttributeValue { kind: AttributeKind::Url, url_part, .. } => match url_part {
+                UrlPart::Start => Escaper::Url,
+                UrlPart::Path => Escaper::NormalizeUrl,
+                UrlPart::Query => Escaper::UrlComponent,
+            },
+            HtmlContext::AttributeValue { kind: AttributeKind::Script, .. } => {
+                bail!("ambiguous escaping context: `{}` is echoed into an event handler attribute", code.trim());
+            }
+            HtmlContext::AttributeValue { kind: AttributeKind::Style, .. } | HtmlContext::Style => {
+                bail!("ambiguous escaping context: `{}` is echoed into CSS", code.trim());
+            }
+            HtmlContext::AttributeValue { kind: AttributeKind::Html, .. } => {
+                bail!("ambiguous escaping context: `{}` is echoed into an attribute holding HTML", code.trim());
+            }
+            HtmlContext::AttributeValue { kind: AttributeKind::MetaContent, .. } => {
+                bail!(
+                    "ambiguous escaping context: `{}` is echoed into the content of a `<meta>` tag, which may be a refresh URL, \
+                     write its `name` or `property` first",
+                    code.trim()
+                );
+            }
+            HtmlContext::Tag => return Ok(None),
+            HtmlContext::Script { string: Some(_) } => Escaper::JsString,
+            HtmlContext::Script { string: None } => {
+                bail!(
+                    "ambiguous escaping context: `{}` is echoed into a script outside of string literals, use `@json` instead",
+                    code.trim()
+                );
+            }
+        };
+
+        Ok(Some(escaper))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Text,
+    // After `<`, reading the tag name.
+    TagName,
+    Tag,
+    AttributeName,
+    // After an attribute name, which may be followed by `=`.
+    AfterAttributeName,
+    BeforeAttributeValue,
+    AttributeValue { quote: Option<char>, url_part: UrlPart },
+    Script(ScriptState),
+    Style,
+    Comment,
+}
+
+// Tracks the `HtmlContext` through the text written by a template.
+#[derive(Debug, Clone)]
+pub struct HtmlContextTracker {
+    state: State,
+    tag_name: String,
+    attribute_name: String,
+    // Whether the current tag had a `name`, `property` or `itemprop` attribute, whose `<meta>`
+    // tags hold text rather than e.g. `http-equiv` refresh URLs.
+    named: bool,
+    // Text of the current script, style or comment, to find its end.
+    raw_text: String,
+}
+
+impl Default for HtmlContextTracker {
+    fn default() -> Self {
+        Self {
+            state: State::Text,
+            tag_name: String::new(),
+            attribute_name: String::new(),
+            named: false,
+            raw_text: String::new(),
+        }
+    }
+}
+
+impl HtmlContextTracker {
+    pub fn new() -> HtmlContextTracker {
+        Self::default()
+    }
+
+    pub fn context(&self) -> HtmlContext {
+        match self.state {
+            State::Text => HtmlContext::Text,
+            // `<` followed by a tag name is written around the echo, which is ambiguous.
+            State::TagName | State::Tag | State::AttributeName | State::AfterAttributeName => HtmlContext::Tag,
+            State::BeforeAttributeValue => HtmlContext::AttributeValue {
+                kind: self.attribute_kind(),
+                quote: None,
+                url_part: UrlPart::Start,
+            },
+            State::AttributeValue { quote, url_part } => HtmlContext::AttributeValue { kind: self.attribute_kind(), quote, url_part },
+            State::Script(ScriptState::String { quote, .. }) => HtmlContext::Script { string: Some(quote) },
+            State::Script(_) => HtmlContext::Script { string: None },
+            State::Style => HtmlContext::Style,
+            State::Comment => HtmlContext::Comment,
+        }
+    }
+
+    fn attribute_kind(&self) -> AttributeKind {
+        let is_meta = self.tag_name.eq_ignore_ascii_case("meta");
+        if is_meta && !self.named && self.attribute_name.eq_ignore_ascii_case("content") {
+            return AttributeKind::MetaContent;
+        }
+
+        AttributeKind::of(&self.attribute_name)
+    }
+
+    // Advances the context past `text` written by the template. Echoed values are fed as `_`,
+    // which moves past the start of URLs without changing the context otherwise.
+    pub fn feed(&mut self, text: &str) {
+        for c in text.chars() {
+            self.feed_char(c);
+        }
+    }
+
+    fn feed_char(&mut self, c: char) {
+        let previous = self.state;
+        self.state = match self.state {
+            State::Text if c == '<' => {
+                self.tag_name.clear();
+                self.named = false;
+                State::TagName
+            }
+            State::Text => State::Text,
+            State::TagName => {
+                if self.tag_name == "!-" && c == '-' {
+                    self.raw_text.clear();
+                    State::Comment
+                } else if c.is_ascii_alphanumeric() || (self.tag_name.is_empty() && (c == '/' || c == '!')) || c == '-' {
+                    self.tag_name.push(c);
+                    State::TagName
+                } else if self.tag_name.is_empty() {
+                    // A `<` which doesn't start a tag, e.g. `a < b`.
+                    State::Text
+                } else {
+                    self.after_tag_name(c)
+                }
+            }
+            State::Tag | State::AfterAttributeName if c == '>' => self.end_tag(),
+            State::Tag if c.is_whitespace() || c == '/' => State::Tag,
+            State::Tag => {
+                self.attribute_name = c.to_string();
+                State::AttributeName
+            }
+            State::AttributeName if c == '=' => State::BeforeAttributeValue,
+            State::AttributeName if c == '>' => self.end_tag(),
+            State::AttributeName if c.is_whitespace() || c == '/' => State::AfterAttributeName,
+            State::AttributeName => {
+                self.attribute_name.push(c);
+                State::AttributeName
+            }
+            State::AfterAttributeName if c == '=' => State::BeforeAttributeValue,
+            State::AfterAttributeName if c.is_whitespace() || c == '/' => State::AfterAttributeName,
+            State::AfterAttributeName => {
+                self.attribute_name = c.to_string();
+                State::AttributeName
+            }
+            State::BeforeAttributeValue if c.is_whitespace() => State::BeforeAttributeValue,
+            State::BeforeAttributeValue if c == '"' || c == '\'' => {
+                State::AttributeValue { quote: Some(c), url_part: UrlPart::Start }
+            }
+            State::BeforeAttributeValue if c == '>' => self.end_tag(),
+            State::BeforeAttributeValue => State::AttributeValue { quote: None, url_part: url_part_after(UrlPart::Start, c) },
+            State::AttributeValue { quote: Some(quote), .. } if c == quote => State::Tag,
+            State::AttributeValue { quote: None, .. } if c.is_whitespace() => State::Tag,
+            State::AttributeValue { quote: None, .. } if c == '>' => self.end_tag(),
+            State::AttributeValue { quote, url_part } => State::AttributeValue { quote, url_part: url_part_after(url_part, c) },
+            State::Script(script) => {
+                self.raw_text.push(c);
+                if self.raw_text.to_ascii_lowercase().ends_with("</script") {
+                    self.tag_name = "/script".to_string();
+                    State::Tag
+                } else {
+                    State::Script(script_state_after(script, c, &self.raw_text))
+                }
+            }
+            State::Style => {
+                self.raw_text.push(c);
+                if self.raw_text.to_ascii_lowercase().ends_with("</style") {
+                    self.tag_name = "/style".to_string();
+                    State::Tag
+                } else {
+                    State::Style
+                }
+            }
+            State::Comment => {
+                self.raw_text.push(c);
+                if self.raw_text.ends_with("-->") {
+                    State::Text
+                } else {
+                    State::Comment
+                }
+            }
+        };
+
+        let ends_attribute_name = previous == State::AttributeName && self.state != State::AttributeName;
+        if ends_attribute_name && ["name", "property", "itemprop"].iter().any(|name| self.attribute_name.eq_ignore_ascii_case(name)) {
+            self.named = true;
+        }
+    }
+
+    fn after_tag_name(&mut self, c: char) -> State {
+        match c {
+            '>' => self.end_tag(),
+            c if c.is_whitespace() || c == '/' => State::Tag,
+            c => {
+                self.attribute_name = c.to_string();
+                State::AttributeName
+            }
+        }
+    }
+
+    // State after the `>` of the current tag.
+    fn end_tag(&mut self) -> State {
+        self.raw_text.clear();
+
+        match self.tag_name.to_ascii_lowercase().as_str() {
+            "script" => State::Script(ScriptState::Code),
+            "style" => State::Style,
+            _ => State::Text,
+        }
+    }
+}
+
+fn url_part_after(url_part: UrlPart, c: char) -> UrlPart {
+    match (url_part, c) {
+        (_, '?' | '#') | (UrlPart::Query, _) => UrlPart::Query,
+        _ => UrlPart::Path,
+    }
+}
+
+fn script_state_after(script: ScriptState, c: char, raw_text: &str) -> ScriptState {
+    match script {
+        ScriptState::Code if c == '"' || c == '\'' || c == '`' => ScriptState::String { quote: c, escaped: false },
+        ScriptState::Code if raw_text.ends_with("//") => ScriptState::LineComment,
+        ScriptState::Code if raw_text.ends_with("/*") => ScriptState::BlockComment,
+        ScriptState::Code => ScriptState::Code,
+        ScriptState::String { quote, escaped: false } if c == quote => ScriptState::Code,
+        ScriptState::String { quote, escaped } => ScriptState::String { quote, escaped: !escaped && c == '\\' },
+        ScriptState::LineComment if c == '\n' => ScriptState::Code,
+        ScriptState::BlockComment if raw_text.ends_with("*/") => ScriptState::Code,
+        comment => comment,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    fn context_after(text: &str) -> HtmlContext {
+        let mut tracker = HtmlContextTracker::new();
+        tracker.feed(text);
+        tracker.context()
+    }
+
+    fn escaper_after(text: &str) -> Result<String> {
+        context_after(text).echo_expression("value")
+    }
+
+    #[test]
+    fn it_tracks_text_and_attributes() {
+        assert_eq!(context_after("<p class=\"a\">1 < 2 "), HtmlContext::Text);
+        assert_eq!(context_after("<!-- <a href=\" --><p>"), HtmlContext::Text);
+        assert_eq!(escaper_after("<p title='").unwrap(), "plt::prelude::EscapeHtml(&(value))");
+        assert_eq!(escaper_after("<a href=\"").unwrap(), "plt::prelude::EscapeUrl(&(value))");
+        assert_eq!(escaper_after("<a class=x HREF=\"/users/").unwrap(), "plt::prelude::NormalizeUrl(&(value))");
+        assert_eq!(escaper_after("<img src=\"/search?q=").unwrap(), "plt::prelude::EscapeUrlComponent(&(value))");
+        assert_eq!(context_after("<a href=\"/\" title=\"x\">"), HtmlContext::Text);
+    }
+
+    #[test]
+    fn it_tracks_scripts() {
+        assert_eq!(escaper_after("<script>let a = \"x\\\" ").unwrap(), "plt::prelude::EscapeJsString(&(value))");
+        assert_eq!(escaper_after("<script>// it's\nlet a = '").unwrap(), "plt::prelude::EscapeJsString(&(value))");
+        assert_eq!(context_after("<script>let a = \"</script>"), HtmlContext::Text);
+        assert!(escaper_after("<script>let a = ").unwrap_err().to_string().contains("use `@json` instead"));
+        assert_eq!(context_after("<style>a { color: red }</style><p>"), HtmlContext::Text);
+    }
+
+    #[test]
+    fn it_rejects_ambiguous_contexts() {
+        assert_eq!(escaper_after("<div ").unwrap(), "plt::attributes::Attributes::in_tag(&(value))");
+        assert!(escaper_after("<div class=").is_err());
+        assert!(escaper_after("<div onclick=\"").is_err());
+        assert!(escaper_after("<div style=\"").is_err());
+        assert!(escaper_after("<style>").is_err());
+    }
+
+    #[test]
+    fn it_rejects_echoes_into_html_and_refresh_attributes() {
+        let error = escaper_after("<iframe srcdoc=\"").unwrap_err();
+        assert_eq!(error.to_string(), "ambiguous escaping context: `value` is echoed into an attribute holding HTML");
+
+        assert!(escaper_after("<meta http-equiv=\"refresh\" content=\"0; url=").is_err());
+        assert!(escaper_after("<meta content=\"").is_err());
+        assert_eq!(escaper_after("<meta name=\"description\" content=\"").unwrap(), "plt::prelude::EscapeHtml(&(value))");
+        assert_eq!(escaper_after("<meta property=\"og:title\" content=\"").unwrap(), "plt::prelude::EscapeHtml(&(value))");
+        assert_eq!(escaper_after("<div name=\"a\"></div><meta content=\"x\"><p data-content=\"").unwrap(), "plt::prelude::EscapeHtml(&(value))");
+        assert!(escaper_after("<div name=\"a\"><meta content=\"").is_err());
+    }
+}
@@ -0,0 +1,438 @@
+// Translations for the `@t` and `@t_plural` directives:
+//
+//     <?rs @args translator: &dyn plt::i18n::Translate, items: &[Item] ?>
+//     <h1><?rs @t("checkout.title") ?></h1>
+//     <p><?rs @t_plural("cart.items", items.len(), name = user.name) ?></p>
+//
+// The directives look up translations using the `translator` variable, which can be any
+// `Translate`: a `HashMap` of messages, or `FluentTranslator` behind the `fluent` feature.
+// Translations are escaped like echoed values.
+//
+// The keys used by templates are collected into a catalog with `extract_messages` and
+// `write_catalog`, or `plt extract-messages`.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::str::FromStr;
+use anyhow::bail;
+use crate::prelude::*;
+
+pub trait Translate: Sync {
+    // Translation of `key`, with the `{name}` placeholders replaced by `args`.
+    fn translate(&self, key: &str, args: &[(&str, &dyn Display)]) -> String;
+
+    // Translation of `key` for `count` items, which is passed as the `count` argument.
+    fn translate_plural(&self, key: &str, count: u64, args: &[(&str, &dyn Display)]) -> String {
+        let mut args = args.to_vec();
+        args.push(("count", &count));
+
+        self.translate(key, &args)
+    }
+}
+
+impl<T: Translate + ?Sized> Translate for &T {
+    fn translate(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        (**self).translate(key, args)
+    }
+
+    fn translate_plural(&self, key: &str, count: u64, args: &[(&str, &dyn Display)]) -> String {
+        (**self).translate_plural(key, count, args)
+    }
+}
+
+// Messages by key. Keys without a message are rendered as they are. Plural messages are looked
+// up as `<key>.one` for a single item and `<key>.other` otherwise, falling back to `<key>`, which
+// suits English-like plural rules. Other locales need `FluentTranslator`.
+impl Translate for HashMap<String, String> {
+    fn translate(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        format_message(self.get(key).map_or(key, String::as_str), args)
+    }
+
+    fn translate_plural(&self, key: &str, count: u64, args: &[(&str, &dyn Display)]) -> String {
+        let mut args = args.to_vec();
+        args.push(("count", &count));
+
+        let form = if count == 1 { "one" } else { "other" };
+        match self.get(&format!("{key}.{form}")) {
+            Some(message) => format_message(message, &args),
+            None => self.translate(key, &args),
+        }
+    }
+}
+
+// Called by the code generated for `@t`, which works whether `translator` is a reference or not.
+pub fn translate(translator: &impl Translate, key: &str, args: &[(&str, &dyn Display)]) -> String {
+    translator.translate(key, args)
+}
+
+// Called by the code generated for `@t_plural`.
+pub fn translate_plural(translator: &impl Translate, key: &str, count: u64, args: &[(&str, &dyn Display)]) -> String {
+    translator.translate_plural(key, count, args)
+}
+
+// Replaces the `{name}` placeholders of `message` by the matching arguments. Other text in
+// braces is kept as it is.
+pub fn format_message(message: &str, args: &[(&str, &dyn Display)]) -> String {
+    let mut formatted = String::new();
+    let mut rest = message;
+
+    while let Some(start) = rest.find('{') {
+        formatted.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = rest
+            .find('}')
+            .and_then(|end| args.iter().find(|(name, _)| *name == &rest[1..end]).map(|(_, value)| (end, value)));
+        match value {
+            Some((end, value)) => {
+                formatted.push_str(&value.to_string());
+                rest = &rest[end + 1..];
+            }
+            None => {
+                formatted.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    formatted.push_str(rest);
+
+    formatted
+}
+
+// Message key used by the templates of a set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub key: String,
+    // Whether the key is used by `@t_plural`.
+    pub plural: bool,
+    // Where the key is used, e.g. `index.plt:3:5`.
+    pub locations: Vec<String>,
+}
+
+// Collects the keys of all translation directives, sorted by key.
+pub fn extract_messages(set: &TemplateSet) -> Result<Vec<Message>> {
+    let mut messages: BTreeMap<String, Message> = BTreeMap::new();
+
+    for path in set.paths() {
+        let template = set.get(path).unwrap();
+
+        for (part_index, part) in template.parts.iter().enumerate() {
+            let Part::Code(code) = part else {
+                continue;
+            };
+
+            let (key, plural) = match Directive::parse(code) {
+                Ok(Some(Directive::Translate { key, .. })) => (key, false),
+                Ok(Some(Directive::TranslatePlural { key, .. })) => (key, true),
+                Ok(_) => continue,
+                Err(error) => return Err(template.diagnostic(template.spans[part_index].clone(), "invalid-template", error).into()),
+            };
+
+            let message = messages.entry(key.clone()).or_insert_with(|| Message { key, plural, locations: Vec::new() });
+            message.plural |= plural;
+            message.locations.push(template.location(template.spans[part_index].start));
+        }
+    }
+
+    Ok(messages.into_values().collect())
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CatalogFormat {
+    // gettext template, translated into `.po` files.
+    #[default]
+    Pot,
+    // Fluent resource, where `checkout.title` becomes the `title` attribute of `checkout`.
+    Fluent,
+}
+
+impl FromStr for CatalogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<CatalogFormat> {
+        match format {
+            "pot" => Ok(CatalogFormat::Pot),
+            "ftl" => Ok(CatalogFormat::Fluent),
+            _ => bail!("unknown catalog format `{format}`, expected `pot` or `ftl`"),
+        }
+    }
+}
+
+// Writes a catalog of the messages to translate, with the keys as the untranslated text.
+pub fn write_catalog(messages: &[Message], format: CatalogFormat) -> String {
+    match format {
+        CatalogFormat::Pot => write_pot(messages),
+        CatalogFormat::Fluent => write_fluent(messages),
+    }
+}
+
+// Content of a double-quoted PO string.
+fn po_string(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn write_pot(messages: &[Message]) -> String {
+    let mut catalog = String::new();
+
+    for message in messages {
+        let key = po_string(&message.key);
+
+        catalog.push_str(&format!("#: {}\n", message.locations.join(" ")));
+        catalog.push_str(&format!("msgid \"{key}\"\n"));
+        if message.plural {
+            catalog.push_str(&format!("msgid_plural \"{key}\"\nmsgstr[0] \"\"\nmsgstr[1] \"\"\n\n"));
+        } else {
+            catalog.push_str("msgstr \"\"\n\n");
+        }
+    }
+
+    catalog
+}
+
+// Identifier and attribute of the Fluent message of `key`: `checkout.title` is the `title`
+// attribute of the `checkout` message. Characters which identifiers can't hold, including the
+// dots of further segments, are written as `-`, e.g. `checkout.items.count` is the
+// `items-count` attribute.
+pub fn fluent_identifiers(key: &str) -> (String, Option<String>) {
+    let identifier = |name: &str| {
+        let name: String = name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '-' }).collect();
+
+        match name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            true => name,
+            false => format!("m{name}"),
+        }
+    };
+
+    match key.split_once('.') {
+        Some((id, attribute)) => (identifier(id), Some(identifier(attribute))),
+        None => (identifier(key), None),
+    }
+}
+
+fn write_fluent(messages: &[Message]) -> String {
+    let pattern = |message: &Message, indentation: &str| {
+        // Braces start placeables, so they're written as string literals.
+        let key: String = message
+            .key
+            .chars()
+            .map(|c| match c {
+                '{' => "{\"{\"}".to_string(),
+                '}' => "{\"}\"}".to_string(),
+                '\n' | '\r' => " ".to_string(),
+                c => c.to_string(),
+            })
+            .collect();
+        if message.plural {
+            format!("\n{indentation}{{ $count ->\n{indentation}    [one] {key}\n{indentation}   *[other] {key}\n{indentation}}}")
+        } else {
+            format!(" {key}")
+        }
+    };
+
+    // Messages are grouped by their identifier, with the rest of the key as the attribute.
+    let mut entries: BTreeMap<String, Vec<(Option<String>, &Message)>> = BTreeMap::new();
+    for message in messages {
+        let (id, attribute) = fluent_identifiers(&message.key);
+
+        entries.entry(id).or_default().push((attribute, message));
+    }
+
+    let mut catalog = String::new();
+    for (id, entry) in entries {
+        for (_, message) in &entry {
+            catalog.push_str(&format!("# {}\n", message.locations.join(" ")));
+        }
+
+        catalog.push_str(&format!("{id} ="));
+        if let Some((_, message)) = entry.iter().find(|(attribute, _)| attribute.is_none()) {
+            catalog.push_str(&pattern(message, "    "));
+        }
+        for (attribute, message) in &entry {
+            if let Some(attribute) = attribute {
+                catalog.push_str(&format!("\n    .{attribute} ={}", pattern(message, "        ")));
+            }
+        }
+        catalog.push_str("\n\n");
+    }
+
+    catalog
+}
+
+// Translations of a single locale, from a Fluent resource. `checkout.title` is looked up
+// as the `title` attribute of the `checkout` message, see `fluent_identifiers`.
+#[cfg(feature = "fluent")]
+pub struct FluentTranslator {
+    bundle: fluent_bundle::concurrent::FluentBundle<fluent_bundle::FluentResource>,
+}
+
+#[cfg(feature = "fluent")]
+impl FluentTranslator {
+    pub fn new(locale: &str, source: &str) -> Result<FluentTranslator> {
+        use anyhow::Context;
+
+        let locale: unic_langid::LanguageIdentifier =
+            locale.parse().with_context(|| format!("invalid locale `{locale}`"))?;
+
+        let resource = match fluent_bundle::FluentResource::try_new(source.to_string()) {
+            Ok(resource) => resource,
+            Err((_, errors)) => bail!("failed to parse the Fluent resource: {}", errors[0]),
+        };
+
+        let mut bundle = fluent_bundle::concurrent::FluentBundle::new_concurrent(vec![locale]);
+        // Isolation marks around arguments would end up in the rendered HTML.
+        bundle.set_use_isolating(false);
+        if let Err(errors) = bundle.add_resource(resource) {
+            bail!("failed to add the Fluent resource: {}", errors[0]);
+        }
+
+        Ok(FluentTranslator { bundle })
+    }
+
+    fn format(&self, key: &str, args: &fluent_bundle::FluentArgs) -> Option<String> {
+        let (id, attribute) = fluent_identifiers(key);
+
+        let message = self.bundle.get_message(&id)?;
+        let pattern = match attribute {
+            Some(attribute) => message.get_attribute(&attribute)?.value(),
+            None => message.value()?,
+        };
+
+        let mut errors = Vec::new();
+        Some(self.bundle.format_pattern(pattern, Some(args), &mut errors).into_owned())
+    }
+
+    fn arguments<'a>(args: &[(&'a str, &dyn Display)]) -> fluent_bundle::FluentArgs<'a> {
+        let mut fluent_args = fluent_bundle::FluentArgs::new();
+        for (name, value) in args {
+            fluent_args.set(*name, value.to_string());
+        }
+
+        fluent_args
+    }
+}
+
+#[cfg(feature = "fluent")]
+impl Translate for FluentTranslator {
+    fn translate(&self, key: &str, args: &[(&str, &dyn Display)]) -> String {
+        self.format(key, &Self::arguments(args)).unwrap_or_else(|| format_message(key, args))
+    }
+
+    // The count is passed as a number, so Fluent selects the plural form of the locale.
+    fn translate_plural(&self, key: &str, count: u64, args: &[(&str, &dyn Display)]) -> String {
+        let mut fluent_args = Self::arguments(args);
+        fluent_args.set("count", count);
+
+        self.format(key, &fluent_args).unwrap_or_else(|| format_message(key, args))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::i18n::{extract_messages, fluent_identifiers, format_message, write_catalog, CatalogFormat, Message, Translate};
+    use crate::prelude::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn it_formats_messages() {
+        assert_eq!(format_message("Hi {name}, {count} new {unknown}", &[("name", &"Ann"), ("count", &3)]), "Hi Ann, 3 new {unknown}");
+
+        let messages = HashMap::from([("greeting".to_string(), "Hello {name}!".to_string())]);
+        assert_eq!(messages.translate("greeting", &[("name", &"Ann")]), "Hello Ann!");
+        assert_eq!(messages.translate_plural("missing {count}", 2, &[]), "missing 2");
+    }
+
+    #[test]
+    fn it_translates_plural_forms_of_messages() {
+        let messages = HashMap::from([
+            ("cart.items.one".to_string(), "One item".to_string()),
+            ("cart.items.other".to_string(), "{count} items".to_string()),
+            ("cart.total".to_string(), "Total of {count}".to_string()),
+        ]);
+
+        assert_eq!(messages.translate_plural("cart.items", 1, &[]), "One item");
+        assert_eq!(messages.translate_plural("cart.items", 0, &[]), "0 items");
+        assert_eq!(messages.translate_plural("cart.total", 1, &[]), "Total of 1");
+    }
+
+    fn message(key: &str, plural: bool) -> Message {
+        Message { key: key.to_string(), plural, locations: vec!["index.plt:1:5".to_string()] }
+    }
+
+    #[test]
+    fn it_escapes_pot_strings() {
+        assert_eq!(
+            write_catalog(&[message("Hello\n\"{name}\"\\", false)], CatalogFormat::Pot),
+            "#: index.plt:1:5\nmsgid \"Hello\\n\\\"{name}\\\"\\\\\"\nmsgstr \"\"\n\n"
+        );
+    }
+
+    #[test]
+    fn it_maps_keys_to_fluent_identifiers() {
+        assert_eq!(fluent_identifiers("checkout.title"), ("checkout".to_string(), Some("title".to_string())));
+        assert_eq!(fluent_identifiers("checkout.items.count"), ("checkout".to_string(), Some("items-count".to_string())));
+        assert_eq!(fluent_identifiers("1st place"), ("m1st-place".to_string(), None));
+
+        assert_eq!(
+            write_catalog(&[message("checkout.items.count", false), message("Hi {name}", false)], CatalogFormat::Fluent),
+            "# index.plt:1:5\nHi--name- = Hi {\"{\"}name{\"}\"}\n\n# index.plt:1:5\ncheckout =\n    .items-count = checkout.items.count\n\n"
+        );
+    }
+
+    #[test]
+    fn it_extracts_messages_into_catalogs() {
+        let mut set = TemplateSet::new();
+        set.add_template("index.plt", Vec::new(), "<?rs @t(\"checkout.title\") ?>\n<?rs @t_plural(\"checkout.items\", 2) ?>".to_string());
+        set.add_template("nav.plt", Vec::new(), "<?rs @t(\"home\") ?><?rs @t(\"checkout.title\") ?>".to_string());
+
+        let messages = extract_messages(&set).unwrap();
+
+        assert_eq!(messages.iter().map(|message| message.key.as_str()).collect::<Vec<_>>(), ["checkout.items", "checkout.title", "home"]);
+        assert_eq!(messages[1].locations, ["index.plt:1:5", "nav.plt:1:23"]);
+        assert_eq!(
+            write_catalog(&messages[1..], CatalogFormat::Pot),
+            "#: index.plt:1:5 nav.plt:1:23\nmsgid \"checkout.title\"\nmsgstr \"\"\n\n#: nav.plt:1:5\nmsgid \"home\"\nmsgstr \"\"\n\n"
+        );
+        assert_eq!(
+            write_catalog(&messages, CatalogFormat::Fluent),
+            "# index.plt:2:5\n# index.plt:1:5 nav.plt:1:23\ncheckout =\n    .items =\n        { $count ->\n            [one] checkout.items\n           *[other] checkout.items\n        }\n    .title = checkout.title\n\n# nav.plt:1:5\nhome = home\n\n"
+        );
+    }
+
+    #[cfg(feature = "fluent")]
+    #[test]
+    fn it_translates_using_fluent() {
+        use crate::i18n::FluentTranslator;
+
+        let source = "checkout =\n    .title = Checkout for { $name }\ncart-items = { $count ->\n    [one] One item\n   *[other] { $count } items\n}\n";
+        let translator = FluentTranslator::new("en-US", source).unwrap();
+
+        assert_eq!(translator.translate("checkout.title", &[("name", &"Ann")]), "Checkout for Ann");
+        assert_eq!(translator.translate_plural("cart-items", 1, &[]), "One item");
+        assert_eq!(translator.translate_plural("cart-items", 3, &[]), "3 items");
+        assert_eq!(translator.translate("missing", &[]), "missing");
+    }
+
+    #[cfg(feature = "fluent")]
+    #[test]
+    fn it_reads_the_fluent_catalogs_it_writes() {
+        use crate::i18n::FluentTranslator;
+
+        let messages = [message("checkout.items.count", false), message("1st place", true), message("Hi {name}", false)];
+        let translator = FluentTranslator::new("en-US", &write_catalog(&messages, CatalogFormat::Fluent)).unwrap();
+
+        assert_eq!(translator.translate("checkout.items.count", &[]), "checkout.items.count");
+        assert_eq!(translator.translate_plural("1st place", 2, &[]), "1st place");
+        assert_eq!(translator.translate("Hi {name}", &[]), "Hi {name}");
+    }
+}
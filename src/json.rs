@@ -0,0 +1,58 @@
+// JSON embedded into inline scripts, e.g. the initial state of a client side application:
+//
+//     <script>window.initialState = <?rs @json initial_state ?>;</script>
+//
+// HTML escaping would corrupt the JSON, while writing it as it is lets strings end the script
+// early with `</script>` or `<!--`. The value is serialized using serde and `<`, `>` and `&` are
+// written as unicode escapes instead, along with U+2028 and U+2029, which older JavaScript
+// engines don't allow in string literals. All of them only ever appear inside of JSON strings,
+// so the escapes decode to the same value.
+use std::fmt::Write;
+use serde::Serialize;
+use crate::prelude::*;
+
+pub use serde_json::json;
+
+// Serializes `value` into JSON which can be embedded into a `<script>` tag.
+pub fn script_json(value: &(impl Serialize + ?Sized)) -> Result<String> {
+    let json = serde_json::to_string(value)?;
+
+    Ok(escape_script_json(&json))
+}
+
+// Escapes the characters of serialized JSON which aren't safe inside of a `<script>` tag.
+pub fn escape_script_json(json: &str) -> String {
+    let mut escaped = String::with_capacity(json.len());
+
+    for c in json.chars() {
+        match c {
+            '<' | '>' | '&' | '\u{2028}' | '\u{2029}' => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::json::{json, script_json};
+
+    #[test]
+    fn it_escapes_script_endings() {
+        let state = json!({ "comment": "</script><!-- a & b >", "lines": "a\u{2028}b\u{2029}" });
+
+        assert_eq!(
+            script_json(&state).unwrap(),
+            r#"{"comment":"\u003c/script\u003e\u003c!-- a \u0026 b \u003e","lines":"a\u2028b\u2029"}"#
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_value_when_decoded() {
+        let state = json!(["</script>", { "a": 1 }]);
+        let decoded: serde_json::Value = serde_json::from_str(&script_json(&state).unwrap()).unwrap();
+
+        assert_eq!(decoded, state);
+    }
+}
@@ -0,0 +1,37 @@
+// LaTeX templates, e.g. reports turned into PDFs by `pdflatex`. Templates ending with `.tex.plt`
+// escape their echoes using `Escaping::Latex`, whatever the escaping of the other templates:
+//
+//     <?rs @args invoice: &Invoice ?>
+//     \section*{Invoice <?= invoice.number ?>}
+//     <?rs @for item in &invoice.items ?>
+//     <?= item.name ?> & <?= item.price ?> \\
+//     <?rs @endfor ?>
+//
+// writes e.g. `R\&D \& support` for the name `R&D & support`, so data can't break the document
+// nor run commands. Echoes of LaTeX code, e.g. rendered by other templates, are wrapped in
+// `Raw`. The text isn't minified, as blank lines end paragraphs, nor checked against HTML rules.
+
+// Whether the template under `path` renders LaTeX, e.g. `reports/invoice.tex.plt`.
+pub fn is_latex_template(path: &str) -> bool {
+    path.split('#').next().unwrap_or(path).ends_with(".tex.plt")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::latex::is_latex_template;
+    use crate::prelude::*;
+
+    #[test]
+    fn it_escapes_echoes_of_latex_templates() {
+        assert!(is_latex_template("reports/invoice.tex.plt"));
+        assert!(!is_latex_template("tex.plt"));
+
+        let mut set = TemplateSet::new();
+        set.add_template("title.tex.plt", vec!["title: &str".to_string()], "\\section{<?= title ?>}\n\n<?= Raw(\"\\\\newpage\") ?>".to_string());
+        let options = GeneratorOptions { escaping: Escaping::Html, minify: Minify::Full, ..GeneratorOptions::default() };
+
+        let code = format_code(&set.generate_with("title.tex.plt", &options).unwrap().join("\n"));
+        assert!(code.contains("write!(output_buffer, \"{}\", plt::prelude::EscapeLatex(& (title)))?;"), "{code}");
+        assert!(code.contains("write!(output_buffer, \"{}\", \"}\\n\\n\")?;"), "{code}");
+    }
+}
@@ -1,8 +1,177 @@
+// Without the default `std` feature, only the parser and the escaping runtime are built, for
+// `no_std` crates using `alloc`, e.g. on embedded targets or wasm, which render templates
+// generated with `GeneratorOptions::no_std`.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "actix")]
+pub mod actix;
+#[cfg(feature = "arena")]
+pub mod arena;
+#[cfg(feature = "std")]
+pub mod assets;
+#[cfg(feature = "std")]
+mod ast;
+#[cfg(feature = "std")]
+pub mod attributes;
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(feature = "std")]
+pub mod build;
+#[cfg(feature = "std")]
+mod build_constants;
+#[cfg(feature = "std")]
+mod cache;
+#[cfg(feature = "compress")]
+pub mod compress;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+mod const_eval;
+#[cfg(feature = "std")]
+pub mod csp;
+#[cfg(feature = "std")]
+pub mod csrf;
+#[cfg(feature = "std")]
+pub mod csv;
+#[cfg(feature = "std")]
+mod diagnostics;
+#[cfg(feature = "std")]
+mod directive;
+#[cfg(feature = "std")]
+mod dispatch;
+#[cfg(feature = "dynamic")]
+pub mod doc;
+#[cfg(feature = "std")]
+mod email;
+#[cfg(feature = "dynamic")]
+pub mod dynamic;
+mod escape;
+#[cfg(feature = "std")]
 mod file_generator;
+#[cfg(feature = "forms")]
+pub mod forms;
+#[cfg(feature = "std")]
+mod formatter;
+#[cfg(feature = "std")]
+pub mod fuzz;
+#[cfg(feature = "std")]
+mod graph;
+#[cfg(feature = "std")]
+pub mod highlight;
+#[cfg(feature = "std")]
+mod html_context;
+#[cfg(feature = "std")]
+pub mod i18n;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(feature = "std")]
+pub mod latex;
+#[cfg(feature = "std")]
+pub mod lint;
+#[cfg(feature = "std")]
+mod loader;
+#[cfg(feature = "email")]
+pub mod mail;
+#[cfg(feature = "markdown")]
+pub mod markdown;
+#[cfg(feature = "std")]
+pub mod migrate;
+#[cfg(feature = "std")]
+mod minify;
+#[cfg(feature = "std")]
+pub mod pagination;
+#[cfg(feature = "dynamic")]
+pub mod playground;
+#[cfg(feature = "project")]
+pub mod project;
+#[cfg(feature = "proptest")]
+pub mod proptest;
+#[cfg(feature = "std")]
+pub mod reflection;
+#[cfg(feature = "json")]
+pub mod registry;
+#[cfg(feature = "std")]
+mod report;
+#[cfg(feature = "std")]
+pub mod routes;
+#[cfg(feature = "std")]
+pub mod runtime;
+#[cfg(feature = "std")]
+pub mod sandbox;
+#[cfg(feature = "std")]
+pub mod scaffold;
+#[cfg(feature = "serve")]
+pub mod serve;
+#[cfg(feature = "std")]
+pub mod shell;
+#[cfg(feature = "std")]
+pub mod sql;
+#[cfg(feature = "dynamic")]
+pub mod store;
+#[cfg(feature = "stream")]
+pub mod stream;
+#[cfg(feature = "std")]
+mod template_context;
+#[cfg(feature = "std")]
+mod template_set;
+#[cfg(feature = "std")]
+pub mod testing;
 mod text_code_fsa;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+#[cfg(feature = "std")]
+mod virtual_document;
+#[cfg(feature = "wasm-bindgen")]
+pub mod wasm;
+#[cfg(feature = "watch")]
+pub mod watch;
+#[cfg(feature = "std")]
+mod whitespace;
+#[cfg(feature = "std")]
+pub mod xml;
 
 pub mod prelude {
+    #[cfg(feature = "std")]
+    pub use crate::ast::*;
+    #[cfg(feature = "std")]
+    pub use crate::build_constants::*;
+    #[cfg(feature = "std")]
+    pub use crate::cache::*;
+    #[cfg(feature = "std")]
+    pub use crate::const_eval::*;
+    #[cfg(feature = "std")]
+    pub use crate::diagnostics::*;
+    #[cfg(feature = "std")]
+    pub use crate::directive::*;
+    #[cfg(feature = "std")]
+    pub use crate::dispatch::*;
+    #[cfg(feature = "std")]
+    pub use crate::email::*;
+    pub use crate::escape::*;
+    #[cfg(feature = "std")]
     pub use crate::file_generator::*;
+    #[cfg(feature = "std")]
+    pub use crate::formatter::*;
+    #[cfg(feature = "std")]
+    pub use crate::graph::*;
+    #[cfg(feature = "std")]
+    pub use crate::html_context::*;
+    #[cfg(feature = "std")]
+    pub use crate::loader::*;
+    #[cfg(feature = "std")]
+    pub use crate::minify::*;
+    #[cfg(feature = "std")]
+    pub use crate::report::*;
+    #[cfg(feature = "std")]
+    pub use crate::template_context::*;
+    #[cfg(feature = "std")]
+    pub use crate::template_set::*;
     pub use crate::text_code_fsa::*;
+    #[cfg(feature = "std")]
+    pub use crate::virtual_document::*;
+    #[cfg(feature = "std")]
+    pub use crate::whitespace::*;
     pub use anyhow::Result;
 }
@@ -0,0 +1,59 @@
+// Checks config templates by parsing their text as a document of their format, with `0` in
+// place of each echo, which is valid both as a value and inside of a string. Templates with
+// other code aren't checked, as e.g. `@if` and `@else` would render duplicate keys.
+use std::ops::Range;
+use crate::config::ConfigFormat;
+use crate::prelude::*;
+
+const ECHO_PLACEHOLDER: &str = "0";
+
+// First problem of the document of a config template, as its span in the template along with the
+// message.
+pub fn config_problem(template: &Template) -> Option<(Range<usize>, String)> {
+    let format = ConfigFormat::of_template(&template.path)?;
+
+    let mut document = String::new();
+    // Start of each text and echo part in the document, along with its index.
+    let mut starts = Vec::new();
+    for (index, part) in template.parts.iter().enumerate() {
+        match part {
+            Part::Text(text) => {
+                starts.push((document.len(), index));
+                document.push_str(text);
+            }
+            Part::EchoCode(_) => {
+                starts.push((document.len(), index));
+                document.push_str(ECHO_PLACEHOLDER);
+            }
+            Part::Code(code) if shapes_output(code) => return None,
+            Part::Code(_) => {}
+        }
+    }
+
+    let (offset, message) = format.check(&document)?.err()?;
+    let message = format!("invalid {}: {message}", format.name());
+    let Some(&(start, index)) = starts.iter().rev().find(|(start, _)| *start <= offset) else {
+        return Some((0..0, message));
+    };
+
+    let span = template.spans.get(index)?;
+    let span = match &template.parts[index] {
+        Part::Text(_) => {
+            let position = (span.start + offset - start).min(span.end);
+            position..position
+        }
+        _ => span.clone(),
+    };
+
+    Some((span, message))
+}
+
+// Whether a code part may change what the template renders, i.e. directives other than the ones
+// declaring things and plain Rust code with blocks, e.g. `for` loops.
+fn shapes_output(code: &str) -> bool {
+    match Directive::parse(code) {
+        Ok(Some(Directive::Args(_) | Directive::Example(_) | Directive::Lint { .. } | Directive::Let(_))) => false,
+        Ok(None) => code.contains(['{', '}']),
+        _ => true,
+    }
+}
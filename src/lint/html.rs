@@ -0,0 +1,635 @@
+// Markup of the text parts of a template, as the tags it starts and ends. Echoes and directives
+// writing something, e.g. `@include`, are holes of unknown content: tags with a hole in their
+// name are skipped and attribute values with a hole aren't static. Directives rendering
+// sections instead of each other, e.g. `@if` and `@else`, or somewhere else, e.g. `@capture`,
+// become `Markup::Open`, `Markup::Alternate` and `Markup::Close`, while plain Rust code is
+// assumed to write nothing.
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+use crate::lint::Lint;
+use crate::prelude::*;
+
+// Elements which never have an end tag.
+const VOID_ELEMENTS: [&str; 14] =
+    ["area", "base", "br", "col", "embed", "hr", "img", "input", "keygen", "link", "meta", "param", "source", "track"];
+
+// Elements whose end tag can be left out, e.g. `<li>` followed by another `<li>`.
+const OPTIONAL_END_TAGS: [&str; 18] = [
+    "body", "colgroup", "dd", "dt", "head", "html", "li", "optgroup", "option", "p", "rp", "rt", "tbody", "td", "tfoot",
+    "th", "thead", "tr",
+];
+
+// Elements whose content is text up to their end tag.
+const RAW_TEXT_ELEMENTS: [&str; 4] = ["script", "style", "textarea", "title"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    // An attribute without a value, e.g. `disabled`.
+    None,
+    Static(String),
+    // A value with a hole, e.g. `class="item <?= class ?>"`.
+    Dynamic,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    // Lower case name of the attribute.
+    pub name: String,
+    pub value: AttributeValue,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    // Lower case name of the element.
+    pub name: String,
+    pub attributes: Vec<Attribute>,
+    // Whether the tag has a hole between its attributes, e.g. `<input <?= attributes ?>>`.
+    pub dynamic_attributes: bool,
+    pub self_closing: bool,
+    pub span: Range<usize>,
+}
+
+impl Tag {
+    pub fn attribute(&self, name: &str) -> Option<&Attribute> {
+        self.attributes.iter().find(|attribute| attribute.name == name)
+    }
+
+    pub fn is_void(&self) -> bool {
+        VOID_ELEMENTS.contains(&self.name.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    // Sections rendered instead of each other, separated by `Markup::Alternate`.
+    Branches,
+    // A section rendered somewhere else, e.g. by `@capture` or `@call`.
+    Fragment,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Markup {
+    StartTag(Tag),
+    EndTag { name: String, span: Range<usize> },
+    Open(Section),
+    Alternate,
+    Close,
+}
+
+// Markup of the template, in order of appearance.
+pub fn markup(template: &Template) -> Vec<Markup> {
+    let mut tokenizer = Tokenizer::default();
+    // Whether the `@match` on top of the sections has yet to reach its first `@when`.
+    let mut before_first_arm = Vec::new();
+
+    for (part, span) in template.parts.iter().zip(&template.spans) {
+        let code = match part {
+            Part::Text(text) => {
+                tokenizer.feed(text, span.start);
+                continue;
+            }
+            Part::EchoCode(_) => {
+                tokenizer.hole();
+                continue;
+            }
+            Part::Code(code) => code,
+        };
+
+        let Ok(Some(directive)) = Directive::parse(code) else {
+            continue;
+        };
+        let markup = match directive {
+            Directive::If(_) | Directive::Try | Directive::Cfg(_) => Markup::Open(Section::Branches),
+            Directive::Match(_) => {
+                before_first_arm.push(true);
+                Markup::Open(Section::Branches)
+            }
+            Directive::When(_) if before_first_arm.pop() == Some(true) => {
+                before_first_arm.push(false);
+                continue;
+            }
+            Directive::When(_) => {
+                before_first_arm.push(false);
+                Markup::Alternate
+            }
+            Directive::ElseIf(_) | Directive::Else | Directive::Rescue(_) => Markup::Alternate,
+            Directive::EndMatch => {
+                before_first_arm.pop();
+                Markup::Close
+            }
+            Directive::EndIf | Directive::EndTry | Directive::EndCfg => Markup::Close,
+            Directive::Capture(_) | Directive::Block(_) | Directive::Call { .. } => Markup::Open(Section::Fragment),
+            Directive::EndCapture | Directive::EndBlock | Directive::EndCall => Markup::Close,
+            Directive::For { .. }
+            | Directive::EndFor
+            | Directive::Cache { .. }
+            | Directive::EndCache
+            | Directive::Let(_)
+            | Directive::Args(_)
+            | Directive::Extends { .. }
+            | Directive::Lint { .. }
+            | Directive::Example(_) => continue,
+            _ => {
+                tokenizer.hole();
+                continue;
+            }
+        };
+        tokenizer.markup.push(markup);
+    }
+
+    tokenizer.markup
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Text,
+    // After `<`.
+    TagOpen,
+    StartTagName,
+    EndTagName,
+    // After the name of an end tag, up to its `>`.
+    EndTag,
+    Tag,
+    AttributeName,
+    AfterAttributeName,
+    BeforeAttributeValue,
+    AttributeValue(Option<char>),
+    // After `<!`, e.g. of a doctype, up to its `>`.
+    Declaration,
+    Comment,
+    // Content of `RAW_TEXT_ELEMENTS`.
+    RawText,
+}
+
+#[derive(Debug)]
+struct Tokenizer {
+    state: State,
+    // Tag being read, if its name has no holes.
+    tag: Option<Tag>,
+    // Start of the tag being read.
+    tag_start: usize,
+    end_tag_name: String,
+    // Text of the current comment or raw text element, to find its end.
+    raw_text: String,
+    raw_text_element: String,
+    markup: Vec<Markup>,
+}
+
+impl Default for Tokenizer {
+    fn default() -> Self {
+        Self {
+            state: State::Text,
+            tag: None,
+            tag_start: 0,
+            end_tag_name: String::new(),
+            raw_text: String::new(),
+            raw_text_element: String::new(),
+            markup: Vec::new(),
+        }
+    }
+}
+
+impl Tokenizer {
+    fn feed(&mut self, text: &str, start: usize) {
+        for (index, c) in text.char_indices() {
+            self.feed_char(c, start + index);
+        }
+    }
+
+    fn hole(&mut self) {
+        match self.state {
+            State::StartTagName | State::EndTagName | State::TagOpen => {
+                self.tag = None;
+                self.end_tag_name.clear();
+                self.state = if self.state == State::EndTagName { State::EndTag } else { State::Tag };
+            }
+            State::Tag | State::AttributeName | State::AfterAttributeName => {
+                if let Some(tag) = &mut self.tag {
+                    tag.dynamic_attributes = true;
+                }
+            }
+            State::BeforeAttributeValue | State::AttributeValue(_) => {
+                if let Some(attribute) = self.tag.as_mut().and_then(|tag| tag.attributes.last_mut()) {
+                    attribute.value = AttributeValue::Dynamic;
+                }
+                if self.state == State::BeforeAttributeValue {
+                    self.state = State::AttributeValue(None);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn feed_char(&mut self, c: char, offset: usize) {
+        self.state = match self.state {
+            State::Text if c == '<' => {
+                self.tag_start = offset;
+                State::TagOpen
+            }
+            State::Text => State::Text,
+            State::TagOpen if c == '/' => {
+                self.end_tag_name.clear();
+                State::EndTagName
+            }
+            State::TagOpen if c == '!' => {
+                self.raw_text.clear();
+                State::Declaration
+            }
+            State::TagOpen if c.is_ascii_alphabetic() => {
+                self.tag = Some(Tag {
+                    name: c.to_ascii_lowercase().to_string(),
+                    attributes: Vec::new(),
+                    dynamic_attributes: false,
+                    self_closing: false,
+                    span: self.tag_start..self.tag_start,
+                });
+                State::StartTagName
+            }
+            // A `<` which doesn't start a tag, e.g. `a < b`.
+            State::TagOpen => State::Text,
+            State::StartTagName if is_name_char(c) => {
+                if let Some(tag) = &mut self.tag {
+                    tag.name.push(c.to_ascii_lowercase());
+                }
+                State::StartTagName
+            }
+            State::EndTagName if is_name_char(c) => {
+                self.end_tag_name.push(c.to_ascii_lowercase());
+                State::EndTagName
+            }
+            State::EndTagName | State::EndTag if c == '>' => {
+                if !self.end_tag_name.is_empty() {
+                    let name = std::mem::take(&mut self.end_tag_name);
+                    self.markup.push(Markup::EndTag { name, span: self.tag_start..offset + 1 });
+                }
+                State::Text
+            }
+            State::EndTagName | State::EndTag => State::EndTag,
+            State::StartTagName | State::Tag | State::AfterAttributeName | State::BeforeAttributeValue if c == '>' => {
+                self.end_start_tag(offset)
+            }
+            State::AttributeName | State::AttributeValue(None) if c == '>' => self.end_start_tag(offset),
+            State::StartTagName | State::Tag if c.is_whitespace() => State::Tag,
+            State::StartTagName | State::Tag | State::AfterAttributeName if c == '/' => {
+                if let Some(tag) = &mut self.tag {
+                    tag.self_closing = true;
+                }
+                State::Tag
+            }
+            State::AfterAttributeName if c.is_whitespace() => State::AfterAttributeName,
+            State::AttributeName | State::AfterAttributeName if c == '=' => State::BeforeAttributeValue,
+            State::StartTagName | State::Tag | State::AfterAttributeName => {
+                if let Some(tag) = &mut self.tag {
+                    tag.self_closing = false;
+                    tag.attributes.push(Attribute {
+                        name: c.to_ascii_lowercase().to_string(),
+                        value: AttributeValue::None,
+                        span: offset..offset + c.len_utf8(),
+                    });
+                }
+                State::AttributeName
+            }
+            State::AttributeName if c.is_whitespace() || c == '/' => State::AfterAttributeName,
+            State::AttributeName => {
+                self.extend_attribute(offset, |attribute| attribute.name.push(c.to_ascii_lowercase()));
+                State::AttributeName
+            }
+            State::BeforeAttributeValue if c.is_whitespace() => State::BeforeAttributeValue,
+            State::BeforeAttributeValue => {
+                let quote = (c == '"' || c == '\'').then_some(c);
+                self.extend_attribute(offset, |attribute| {
+                    attribute.value = match quote {
+                        Some(_) => AttributeValue::Static(String::new()),
+                        None => AttributeValue::Static(c.to_string()),
+                    }
+                });
+                State::AttributeValue(quote)
+            }
+            State::AttributeValue(Some(quote)) if c == quote => {
+                self.extend_attribute(offset, |_| {});
+                State::Tag
+            }
+            State::AttributeValue(None) if c.is_whitespace() => State::Tag,
+            State::AttributeValue(quote) => {
+                self.extend_attribute(offset, |attribute| {
+                    if let AttributeValue::Static(value) = &mut attribute.value {
+                        value.push(c);
+                    }
+                });
+                State::AttributeValue(quote)
+            }
+            State::Declaration => {
+                self.raw_text.push(c);
+                if self.raw_text == "--" {
+                    self.raw_text.clear();
+                    State::Comment
+                } else if c == '>' {
+                    State::Text
+                } else {
+                    State::Declaration
+                }
+            }
+            State::Comment => {
+                self.raw_text.push(c);
+                if self.raw_text.ends_with("-->") {
+                    State::Text
+                } else {
+                    State::Comment
+                }
+            }
+            State::RawText => {
+                self.raw_text.push(c);
+                let end_tag = format!("</{}", self.raw_text_element);
+                if self.raw_text.to_ascii_lowercase().ends_with(&end_tag) {
+                    self.tag_start = offset + 1 - end_tag.len();
+                    self.end_tag_name = std::mem::take(&mut self.raw_text_element);
+                    State::EndTagName
+                } else {
+                    State::RawText
+                }
+            }
+        };
+    }
+
+    // Adds the char at `offset` to the last attribute of the current tag.
+    fn extend_attribute(&mut self, offset: usize, extend: impl FnOnce(&mut Attribute)) {
+        if let Some(attribute) = self.tag.as_mut().and_then(|tag| tag.attributes.last_mut()) {
+            extend(attribute);
+            attribute.span.end = offset + 1;
+        }
+    }
+
+    // State after the `>` at `offset` ending a start tag.
+    fn end_start_tag(&mut self, offset: usize) -> State {
+        let Some(mut tag) = self.tag.take() else {
+            return State::Text;
+        };
+        tag.span.end = offset + 1;
+
+        let state = match RAW_TEXT_ELEMENTS.contains(&tag.name.as_str()) && !tag.self_closing {
+            true => {
+                self.raw_text.clear();
+                self.raw_text_element = tag.name.clone();
+                State::RawText
+            }
+            false => State::Text,
+        };
+        self.markup.push(Markup::StartTag(tag));
+
+        state
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == ':' || c == '_'
+}
+
+// Accessibility problems of the tags whose attributes are fully static, as the lint reporting
+// them along with their span, message and suggestion.
+pub fn accessibility_problems(template: &Template) -> Vec<(Lint, Range<usize>, String, Option<String>)> {
+    let markup = markup(template);
+    let mut problems = Vec::new();
+
+    // `for` attributes of the labels, which are all unknown if any of them has a hole.
+    let mut labelled_ids = BTreeSet::new();
+    let mut any_dynamic_label = false;
+    for markup in &markup {
+        if let Markup::StartTag(tag) = markup {
+            if tag.name == "label" {
+                match tag.attribute("for").map(|attribute| &attribute.value) {
+                    Some(AttributeValue::Static(id)) => {
+                        labelled_ids.insert(id.clone());
+                    }
+                    Some(_) => any_dynamic_label = true,
+                    None => any_dynamic_label |= tag.dynamic_attributes,
+                }
+            }
+        }
+    }
+
+    let mut open_labels = 0usize;
+    for markup in &markup {
+        let tag = match markup {
+            Markup::StartTag(tag) => tag,
+            Markup::EndTag { name, .. } if name == "label" => {
+                open_labels = open_labels.saturating_sub(1);
+                continue;
+            }
+            _ => continue,
+        };
+        if tag.name == "label" && !tag.self_closing {
+            open_labels += 1;
+        }
+        if tag.dynamic_attributes {
+            continue;
+        }
+
+        match tag.name.as_str() {
+            "img" if tag.attribute("alt").is_none() => problems.push((
+                Lint::MissingAlt,
+                tag.span.clone(),
+                "`<img>` without an `alt` attribute".to_string(),
+                Some("describe the image with `alt`, or use `alt=\"\"` if it's decorative".to_string()),
+            )),
+            "html" if tag.attribute("lang").is_none() => problems.push((
+                Lint::MissingLang,
+                tag.span.clone(),
+                "`<html>` without a `lang` attribute".to_string(),
+                Some("set the language of the page, e.g. `<html lang=\"en\">`".to_string()),
+            )),
+            "input" | "select" | "textarea" if open_labels == 0 && !is_labelled(tag, &labelled_ids, any_dynamic_label) => {
+                problems.push((
+                    Lint::UnlabeledInput,
+                    tag.span.clone(),
+                    format!("`<{}>` without a label", tag.name),
+                    Some("wrap it in a `<label>`, point a `<label for>` at its `id` or set `aria-label`".to_string()),
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    problems
+}
+
+fn is_labelled(tag: &Tag, labelled_ids: &BTreeSet<String>, any_dynamic_label: bool) -> bool {
+    // Inputs which aren't filled in, or whose label is their value.
+    let unlabelled_type = match tag.attribute("type").map(|attribute| &attribute.value) {
+        Some(AttributeValue::Static(kind)) => {
+            ["hidden", "submit", "reset", "button", "image"].contains(&kind.to_ascii_lowercase().as_str())
+        }
+        Some(_) => true,
+        None => false,
+    };
+    let named = ["aria-label", "aria-labelledby", "title"].iter().any(|name| tag.attribute(name).is_some());
+    let labelled_by_id = match tag.attribute("id").map(|attribute| &attribute.value) {
+        Some(AttributeValue::Static(id)) => labelled_ids.contains(id) || any_dynamic_label,
+        Some(_) => true,
+        None => false,
+    };
+
+    (tag.name == "input" && unlabelled_type) || named || labelled_by_id
+}
+
+// Problems with the structure of the markup, as the span they were found at along with a
+// message and a suggestion: unclosed tags, mismatched nesting and duplicate static `id`s.
+pub fn structure_problems(template: &Template) -> Vec<(Range<usize>, String, Option<String>)> {
+    let mut checker = StructureChecker {
+        template,
+        open_tags: Vec::new(),
+        ids: BTreeMap::new(),
+        sections: Vec::new(),
+        problems: Vec::new(),
+    };
+    for markup in markup(template) {
+        checker.check(&markup);
+    }
+    checker.close_open_tags("is never closed");
+
+    checker.problems
+}
+
+// Open sections along with the state of the checker at their start.
+#[derive(Debug)]
+struct OpenSection {
+    section: Section,
+    open_tags: Vec<Tag>,
+    ids: BTreeMap<String, Range<usize>>,
+    // Open tags after the first branch, which the other branches are assumed to leave the same.
+    open_tags_after_first: Option<Vec<Tag>>,
+    // `id`s found in any of the branches so far.
+    branch_ids: BTreeMap<String, Range<usize>>,
+}
+
+struct StructureChecker<'a> {
+    template: &'a Template,
+    open_tags: Vec<Tag>,
+    // Static `id`s along with the span of the first attribute with them.
+    ids: BTreeMap<String, Range<usize>>,
+    sections: Vec<OpenSection>,
+    problems: Vec<(Range<usize>, String, Option<String>)>,
+}
+
+impl StructureChecker<'_> {
+    fn check(&mut self, markup: &Markup) {
+        match markup {
+            Markup::StartTag(tag) => {
+                if let Some(Attribute { value: AttributeValue::Static(id), span, .. }) = tag.attribute("id") {
+                    match self.ids.get(id) {
+                        Some(first) => self.problems.push((
+                            span.clone(),
+                            format!("duplicate `id` \"{id}\""),
+                            Some(format!("the `id` is already used at {}, make them unique", self.template.location(first.start))),
+                        )),
+                        None => {
+                            self.ids.insert(id.clone(), span.clone());
+                        }
+                    }
+                }
+                if !tag.is_void() && !tag.self_closing {
+                    self.open_tags.push(tag.clone());
+                }
+            }
+            Markup::EndTag { name, span } => self.end_tag(name, span),
+            Markup::Open(section) => {
+                let open_tags = match section {
+                    Section::Branches => self.open_tags.clone(),
+                    Section::Fragment => std::mem::take(&mut self.open_tags),
+                };
+                self.sections.push(OpenSection {
+                    section: *section,
+                    open_tags,
+                    ids: self.ids.clone(),
+                    open_tags_after_first: None,
+                    branch_ids: BTreeMap::new(),
+                });
+            }
+            Markup::Alternate => {
+                let Some(section) = self.sections.last_mut() else {
+                    return;
+                };
+                section.open_tags_after_first.get_or_insert_with(|| self.open_tags.clone());
+                section.branch_ids.extend(std::mem::take(&mut self.ids));
+                self.open_tags = section.open_tags.clone();
+                self.ids = section.ids.clone();
+            }
+            Markup::Close => {
+                let Some(section) = self.sections.pop() else {
+                    return;
+                };
+                match section.section {
+                    Section::Branches => {
+                        if let Some(open_tags) = section.open_tags_after_first {
+                            self.open_tags = open_tags;
+                        }
+                        self.ids.extend(section.branch_ids);
+                    }
+                    Section::Fragment => {
+                        self.close_open_tags("isn't closed within its section");
+                        self.open_tags = section.open_tags;
+                    }
+                }
+            }
+        }
+    }
+
+    fn end_tag(&mut self, name: &str, span: &Range<usize>) {
+        let Some(position) = self.open_tags.iter().rposition(|tag| tag.name == name) else {
+            if !VOID_ELEMENTS.contains(&name) && !OPTIONAL_END_TAGS.contains(&name) {
+                self.problems.push((span.clone(), format!("`</{name}>` closes an element which isn't open"), None));
+            }
+            return;
+        };
+
+        for tag in self.open_tags.split_off(position).into_iter().skip(1) {
+            if !OPTIONAL_END_TAGS.contains(&tag.name.as_str()) {
+                let message = format!("`<{}>` isn't closed before `</{name}>`", tag.name);
+                self.problems.push((tag.span.clone(), message, Some(format!("close it with `</{}>` first", tag.name))));
+            }
+        }
+    }
+
+    fn close_open_tags(&mut self, problem: &str) {
+        for tag in std::mem::take(&mut self.open_tags) {
+            if !OPTIONAL_END_TAGS.contains(&tag.name.as_str()) {
+                let message = format!("`<{}>` {problem}", tag.name);
+                self.problems.push((tag.span.clone(), message, Some(format!("close it with `</{}>`", tag.name))));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lint::html::{markup, AttributeValue, Markup};
+    use crate::prelude::*;
+
+    #[test]
+    fn it_tokenizes_markup_with_holes() {
+        let mut set = TemplateSet::new();
+        let source = "<!DOCTYPE html><a href=\"/<?= id ?>\" id=main hidden>x</a><<?= tag ?>></<?= tag ?>><script>\"</p>\"</script>";
+        set.add_template("index.plt", Vec::new(), source.to_string());
+
+        let markup = markup(set.get("index.plt").unwrap());
+        let Markup::StartTag(tag) = &markup[0] else {
+            panic!("expected a start tag, found {:?}", markup[0]);
+        };
+        assert_eq!(tag.name, "a");
+        assert_eq!(tag.attribute("href").unwrap().value, AttributeValue::Dynamic);
+        assert_eq!(tag.attribute("id").unwrap().value, AttributeValue::Static("main".to_string()));
+        assert_eq!(tag.attribute("id").unwrap().span, 36..43);
+        assert_eq!(tag.attribute("hidden").unwrap().value, AttributeValue::None);
+        assert_eq!(tag.span, 15..51);
+
+        let names: Vec<_> = markup
+            .iter()
+            .map(|markup| match markup {
+                Markup::StartTag(tag) => tag.name.clone(),
+                Markup::EndTag { name, .. } => format!("/{name}"),
+                markup => format!("{markup:?}"),
+            })
+            .collect();
+        assert_eq!(names, vec!["a", "/a", "script", "/script"]);
+    }
+}
@@ -0,0 +1,748 @@
+// Lints checking templates for likely mistakes which still generate valid code, run by
+// `plt check`, `plt compile` and the build helpers. Each lint is either allowed, reported as a
+// warning or denied, failing the template, as configured by `GeneratorOptions::lints` or by
+// directives in the template itself, which apply to the whole template:
+//
+//     <?rs @allow(unused_parameters, long_text) ?>
+//     <?rs @deny(unescaped_echo) ?>
+mod config;
+mod html;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+use anyhow::bail;
+use proc_macro2::{TokenStream, TokenTree};
+use rustc_lexer::TokenKind;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Lint {
+    // Block directives like `@if` without their closing directive, and the other way around.
+    UnbalancedDirectives,
+    // Parameters of the template which none of its code uses.
+    UnusedParameters,
+    // Variables the code uses without declaring them, which were likely meant to be parameters.
+    UndeclaredParameters,
+    // Echoes of parameters which aren't escaped, either because escaping is off or because of `Raw`.
+    UnescapedEcho,
+    // `<?rs ?>` and `<?= ?>` tags without any code.
+    EmptyCode,
+    // Text parts longer than `LintOptions::max_text_len`.
+    LongText,
+    // Unclosed tags, mismatched nesting and duplicate static `id`s in the HTML of the text parts.
+    MalformedHtml,
+    // `<img>` tags without an `alt` attribute.
+    MissingAlt,
+    // Form fields which aren't labelled by a `<label>` or an `aria-label`.
+    UnlabeledInput,
+    // `<html>` tags without a `lang` attribute.
+    MissingLang,
+    // Config templates whose text isn't a valid document of their format, see `plt::config`.
+    MalformedConfig,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 11] = [
+        Lint::UnbalancedDirectives,
+        Lint::UnusedParameters,
+        Lint::UndeclaredParameters,
+        Lint::UnescapedEcho,
+        Lint::EmptyCode,
+        Lint::LongText,
+        Lint::MalformedHtml,
+        Lint::MissingAlt,
+        Lint::UnlabeledInput,
+        Lint::MissingLang,
+        Lint::MalformedConfig,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnbalancedDirectives => "unbalanced_directives",
+            Lint::UnusedParameters => "unused_parameters",
+            Lint::UndeclaredParameters => "undeclared_parameters",
+            Lint::UnescapedEcho => "unescaped_echo",
+            Lint::EmptyCode => "empty_code",
+            Lint::LongText => "long_text",
+            Lint::MalformedHtml => "malformed_html",
+            Lint::MissingAlt => "missing_alt",
+            Lint::UnlabeledInput => "unlabeled_input",
+            Lint::MissingLang => "missing_lang",
+            Lint::MalformedConfig => "malformed_config",
+        }
+    }
+
+    pub fn default_level(&self) -> LintLevel {
+        match self {
+            Lint::UnbalancedDirectives | Lint::UndeclaredParameters => LintLevel::Deny,
+            // Templates generating other formats than HTML don't escape at all.
+            // Likewise, and the HTML of a template is often only part of a page.
+            Lint::UnescapedEcho | Lint::MalformedHtml => LintLevel::Allow,
+            Lint::UnusedParameters
+            | Lint::EmptyCode
+            | Lint::LongText
+            | Lint::MissingAlt
+            | Lint::UnlabeledInput
+            | Lint::MissingLang
+            | Lint::MalformedConfig => LintLevel::Warn,
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Lint {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Lint> {
+        match Lint::ALL.into_iter().find(|lint| lint.name() == name) {
+            Some(lint) => Ok(lint),
+            None => {
+                let names: Vec<_> = Lint::ALL.iter().map(|lint| format!("`{lint}`")).collect();
+                bail!("unknown lint `{name}`, expected one of {}", names.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl LintLevel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LintLevel::Allow => "allow",
+            LintLevel::Warn => "warn",
+            LintLevel::Deny => "deny",
+        }
+    }
+}
+
+impl FromStr for LintLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(level: &str) -> Result<LintLevel> {
+        match level {
+            "allow" => Ok(LintLevel::Allow),
+            "warn" => Ok(LintLevel::Warn),
+            "deny" => Ok(LintLevel::Deny),
+            _ => bail!("unknown lint level `{level}`, expected `allow`, `warn` or `deny`"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LintOptions {
+    // Levels of the lints which differ from their default level.
+    pub levels: BTreeMap<Lint, LintLevel>,
+    // Length in bytes from which text parts are reported by `long_text`.
+    pub max_text_len: usize,
+}
+
+impl Default for LintOptions {
+    fn default() -> Self {
+        Self {
+            levels: BTreeMap::new(),
+            max_text_len: 16 * 1024,
+        }
+    }
+}
+
+impl LintOptions {
+    // Levels for templates rendered by the dynamic engine, e.g. by `plt::playground`, whose
+    // variables come from the context rather than from `@args`.
+    pub fn dynamic() -> LintOptions {
+        let mut lints = LintOptions::default();
+        lints.set(Lint::UndeclaredParameters, LintLevel::Allow);
+        lints.set(Lint::UnusedParameters, LintLevel::Allow);
+
+        lints
+    }
+
+    pub fn level(&self, lint: Lint) -> LintLevel {
+        self.levels.get(&lint).copied().unwrap_or_else(|| lint.default_level())
+    }
+
+    pub fn set(&mut self, lint: Lint, level: LintLevel) {
+        self.levels.insert(lint, level);
+    }
+}
+
+// Diagnostics of the lints which aren't allowed for the template, as warnings or, for denied
+// lints, errors. The parameters are checked against the generated `code` of the template, which
+// is `None` if it couldn't be generated.
+pub fn lint_template(template: &Template, code: Option<&str>, options: &GeneratorOptions) -> Diagnostics {
+    let mut levels = options.lints.clone();
+    // Malformed directives are reported once the template is generated.
+    for directive in Directive::parse_all(&template.parts).unwrap_or_default() {
+        if let Directive::Lint { level, lints } = directive {
+            for lint in lints {
+                levels.set(lint, level);
+            }
+        }
+    }
+
+    let names = code.and_then(|code| syn::parse_file(code).ok()).map(|file| {
+        let mut names = Names::default();
+        syn::visit::visit_file(&mut names, &file);
+        names
+    });
+
+    let mut linter = Linter { template, levels: &levels, diagnostics: Diagnostics::new() };
+    linter.unbalanced_directives();
+    // Templates which can't be generated are reported by generating them.
+    if let Some(names) = &names {
+        linter.unused_parameters(names);
+        linter.undeclared_parameters(names);
+    }
+    linter.unescaped_echo(template_options(&template.path, options).escaping);
+    linter.empty_code();
+    linter.long_text(levels.max_text_len);
+    // Templates of other formats, e.g. XML or shell scripts, don't follow the rules of HTML.
+    if Escaping::of_template(&template.path).is_none() {
+        linter.malformed_html();
+        linter.accessibility();
+    }
+    linter.malformed_config();
+
+    linter.diagnostics
+}
+
+struct Linter<'a> {
+    template: &'a Template,
+    levels: &'a LintOptions,
+    diagnostics: Diagnostics,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, lint: Lint, part: Option<usize>, message: String, suggestion: Option<String>) {
+        let span = part.and_then(|part| self.template.spans.get(part)).cloned();
+        self.report_at(lint, span, message, suggestion);
+    }
+
+    fn report_at(&mut self, lint: Lint, span: Option<Range<usize>>, message: String, suggestion: Option<String>) {
+        let severity = match self.levels.level(lint) {
+            LintLevel::Allow => return,
+            LintLevel::Warn => Severity::Warning,
+            LintLevel::Deny => Severity::Error,
+        };
+
+        let mut diagnostic = match span {
+            Some(span) => self.template.diagnostic(span, lint.name(), message),
+            None => Diagnostic::error(&self.template.path, None, lint.name(), message),
+        };
+        diagnostic.severity = severity;
+        diagnostic.suggestion = suggestion;
+        self.diagnostics.push(diagnostic);
+    }
+
+    // Directives of the code parts along with the index of their part.
+    fn directives(&self) -> Vec<(usize, Directive)> {
+        self.template
+            .parts
+            .iter()
+            .enumerate()
+            .filter_map(|(index, part)| match part {
+                Part::Code(code) => Some((index, Directive::parse(code).ok()??)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn unbalanced_directives(&mut self) {
+        // Names of the open block directives along with their part.
+        let mut open: Vec<(&str, usize)> = Vec::new();
+
+        for (index, directive) in self.directives() {
+            let (name, parents): (&str, &[&str]) = match &directive {
+                Directive::When(_) => ("when", &["match"]),
+                Directive::ElseIf(_) => ("else if", &["if"]),
+                Directive::Else => ("else", &["if", "cfg"]),
+                Directive::Rescue(_) => ("rescue", &["try"]),
+                _ => ("", &[]),
+            };
+            if !name.is_empty() {
+                if !open.last().is_some_and(|(open, _)| parents.contains(open)) {
+                    let message = format!("`@{name}` found outside of `@{}`", parents[0]);
+                    self.report(Lint::UnbalancedDirectives, Some(index), message, None);
+                }
+                continue;
+            }
+
+            if let Some(name) = opened_block(&directive) {
+                open.push((name, index));
+                continue;
+            }
+            let Some(name) = closed_block(&directive) else {
+                continue;
+            };
+
+            let Some(position) = open.iter().rposition(|(open, _)| *open == name) else {
+                let message = format!("`@end{name}` found without matching `@{name}`");
+                self.report(Lint::UnbalancedDirectives, Some(index), message, None);
+                continue;
+            };
+            for (unclosed, part) in open.split_off(position).into_iter().skip(1) {
+                self.report_unclosed(unclosed, part);
+            }
+        }
+
+        for (unclosed, part) in open {
+            self.report_unclosed(unclosed, part);
+        }
+    }
+
+    fn report_unclosed(&mut self, name: &str, part: usize) {
+        let message = format!("`@{name}` is missing its `@end{name}`");
+        let suggestion = format!("close it with `<?rs @end{name} ?>`");
+        self.report(Lint::UnbalancedDirectives, Some(part), message, Some(suggestion));
+    }
+
+    // Parameters of the template, skipping patterns destructuring them.
+    fn parameters(&self) -> Vec<String> {
+        self.template
+            .arg_names()
+            .iter()
+            .map(|name| name.trim_start_matches("mut ").trim().to_string())
+            .filter(|name| syn::parse_str::<syn::Ident>(name).is_ok())
+            .collect()
+    }
+
+    // Index of the first code or echo part referring to `name`, also inside of format strings.
+    fn part_using(&self, name: &str) -> Option<usize> {
+        self.template.parts.iter().position(|part| {
+            !part.is_text() && (identifiers(part.get_content()).iter().any(|identifier| identifier == name) || part.get_content().contains(&format!("{{{name}")))
+        })
+    }
+
+    fn unused_parameters(&mut self, names: &Names) {
+        let declared_at = self
+            .directives()
+            .into_iter()
+            .find_map(|(index, directive)| matches!(directive, Directive::Args(_)).then_some(index));
+
+        for name in self.parameters() {
+            if name.starts_with('_') || names.used.contains(&name) || names.mentioned.contains(&name) {
+                continue;
+            }
+
+            let message = format!("parameter `{name}` is never used");
+            let suggestion = format!("remove the parameter or rename it to `_{name}`");
+            self.report(Lint::UnusedParameters, declared_at, message, Some(suggestion));
+        }
+    }
+
+    fn undeclared_parameters(&mut self, names: &Names) {
+        let parameters = self.parameters();
+        let mut undeclared: Vec<_> = names
+            .used
+            .iter()
+            .filter(|name| !names.bound.contains(*name) && !parameters.contains(*name))
+            .map(|name| (self.part_using(name), name))
+            .collect();
+        undeclared.sort();
+
+        for (part, name) in undeclared {
+            let message = format!("`{name}` is neither a parameter of the template nor declared by its code");
+            let suggestion = format!("declare it as a parameter, e.g. `<?rs @args {name}: &str ?>`");
+            self.report(Lint::UndeclaredParameters, part, message, Some(suggestion));
+        }
+    }
+
+    fn unescaped_echo(&mut self, escaping: Escaping) {
+        let parameters = self.parameters();
+
+        for (index, part) in self.template.parts.iter().enumerate() {
+            let Part::EchoCode(code) = part else {
+                continue;
+            };
+            let identifiers = identifiers(code);
+            let Some(parameter) = identifiers.iter().find(|identifier| parameters.contains(*identifier)) else {
+                continue;
+            };
+
+            let escaped_by_hand = identifiers.iter().any(|identifier| identifier == "EscapeHtml" || identifier == "escape_html");
+            let raw = identifiers.iter().any(|identifier| identifier == "Raw");
+            let suggestion = match escaping {
+                _ if escaped_by_hand => continue,
+                Escaping::None => "turn on HTML escaping or wrap the value in `EscapeHtml`",
+                _ if raw => "remove `Raw` unless the parameter always holds trusted HTML",
+                _ => continue,
+            };
+
+            let message = format!("parameter `{parameter}` is echoed without escaping");
+            self.report(Lint::UnescapedEcho, Some(index), message, Some(suggestion.to_string()));
+        }
+    }
+
+    fn empty_code(&mut self) {
+        for (index, part) in self.template.parts.iter().enumerate() {
+            let message = match part {
+                Part::Code(code) if code.trim().is_empty() => "empty code tag",
+                Part::EchoCode(code) if code.trim().is_empty() => "empty echo tag",
+                _ => continue,
+            };
+
+            self.report(Lint::EmptyCode, Some(index), message.to_string(), Some("remove the tag".to_string()));
+        }
+    }
+
+    fn long_text(&mut self, max_text_len: usize) {
+        for (index, part) in self.template.parts.iter().enumerate() {
+            let Part::Text(text) = part else {
+                continue;
+            };
+            if text.len() < max_text_len {
+                continue;
+            }
+
+            let message = format!("text of {} bytes is longer than {max_text_len} bytes", text.len());
+            let suggestion = "move it into its own template or a static asset".to_string();
+            self.report(Lint::LongText, Some(index), message, Some(suggestion));
+        }
+    }
+
+    fn malformed_html(&mut self) {
+        if self.levels.level(Lint::MalformedHtml) == LintLevel::Allow {
+            return;
+        }
+
+        for (span, message, suggestion) in html::structure_problems(self.template) {
+            self.report_at(Lint::MalformedHtml, Some(span), message, suggestion);
+        }
+    }
+
+    fn malformed_config(&mut self) {
+        if let Some((span, message)) = config::config_problem(self.template) {
+            self.report_at(Lint::MalformedConfig, Some(span), message, None);
+        }
+    }
+
+    fn accessibility(&mut self) {
+        for (lint, span, message, suggestion) in html::accessibility_problems(self.template) {
+            self.report_at(lint, Some(span), message, suggestion);
+        }
+    }
+}
+
+// Variables of the generated code of a template, which is where the directives are lowered into
+// the bindings they declare, e.g. `@for item in items`.
+#[derive(Default)]
+struct Names {
+    // Names declared by patterns, e.g. of `let`, `for` and closures, and of functions.
+    bound: BTreeSet<String>,
+    // Single lower case identifiers used as values, e.g. `title` in `title.len()`.
+    used: BTreeSet<String>,
+    // Identifiers inside of macros whose arguments aren't expressions, which might be uses.
+    mentioned: BTreeSet<String>,
+}
+
+impl<'ast> syn::visit::Visit<'ast> for Names {
+    fn visit_pat_ident(&mut self, pat: &'ast syn::PatIdent) {
+        self.bound.insert(pat.ident.to_string());
+        syn::visit::visit_pat_ident(self, pat);
+    }
+
+    fn visit_item_fn(&mut self, item: &'ast syn::ItemFn) {
+        self.bound.insert(item.sig.ident.to_string());
+        syn::visit::visit_item_fn(self, item);
+    }
+
+    // Functions called by name aren't variables.
+    fn visit_expr_call(&mut self, call: &'ast syn::ExprCall) {
+        match call.func.as_ref() {
+            syn::Expr::Path(path) if path.path.get_ident().is_some() => {
+                for arg in &call.args {
+                    self.visit_expr(arg);
+                }
+            }
+            _ => syn::visit::visit_expr_call(self, call),
+        }
+    }
+
+    fn visit_expr_path(&mut self, path: &'ast syn::ExprPath) {
+        let name = path.path.get_ident().map(ToString::to_string).unwrap_or_default();
+        if path.qself.is_none() && name != "self" && name.starts_with(|c: char| c.is_lowercase() || c == '_') {
+            self.used.insert(name);
+        }
+    }
+
+    // Arguments of macros like `format!`, along with the variables captured by their format string.
+    fn visit_macro(&mut self, mac: &'ast syn::Macro) {
+        let parser = syn::punctuated::Punctuated::<syn::Expr, syn::Token![,]>::parse_terminated;
+        let Ok(args) = mac.parse_body_with(parser) else {
+            self.mentioned.extend(token_identifiers(mac.tokens.clone()));
+            return;
+        };
+
+        // Only the first string literal is the format string, e.g. after the writer of `write!`.
+        let mut format_found = false;
+        for arg in &args {
+            match arg {
+                syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(format), .. }) if !format_found => {
+                    self.used.extend(format_captures(&format.value()));
+                    format_found = true;
+                }
+                // Named arguments, e.g. `format!("{name}", name = user.name)`.
+                syn::Expr::Assign(assign) => {
+                    if let syn::Expr::Path(name) = assign.left.as_ref() {
+                        self.bound.extend(name.path.get_ident().map(ToString::to_string));
+                    }
+                    self.visit_expr(&assign.right);
+                }
+                arg => self.visit_expr(arg),
+            }
+        }
+    }
+}
+
+fn token_identifiers(tokens: TokenStream) -> Vec<String> {
+    tokens
+        .into_iter()
+        .flat_map(|token| match token {
+            TokenTree::Ident(ident) => vec![ident.to_string()],
+            TokenTree::Group(group) => token_identifiers(group.stream()),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+// Variables captured by a format string, e.g. `name` in `"{name:?} {0} {{escaped}}"`.
+fn format_captures(format: &str) -> Vec<String> {
+    let mut captures = Vec::new();
+    let mut rest = format;
+
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start + 1..];
+        if let Some(escaped) = rest.strip_prefix('{') {
+            rest = escaped;
+            continue;
+        }
+
+        let end = rest.find(['}', ':']).unwrap_or(rest.len());
+        let name = rest[..end].trim();
+        if name.starts_with(|c: char| c.is_alphabetic() || c == '_') && name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            captures.push(name.to_string());
+        }
+        rest = &rest[end..];
+    }
+
+    captures
+}
+
+// Name of the block a directive opens, e.g. `if` for `@if`.
+fn opened_block(directive: &Directive) -> Option<&'static str> {
+    let name = match directive {
+        Directive::Match(_) => "match",
+        Directive::Capture(_) => "capture",
+        Directive::Block(_) => "block",
+        Directive::Call { .. } => "call",
+        Directive::If(_) => "if",
+        Directive::For { .. } => "for",
+        Directive::Try => "try",
+        Directive::Cache { .. } => "cache",
+        Directive::Cfg(_) => "cfg",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+// Name of the block a directive closes, e.g. `if` for `@endif`.
+fn closed_block(directive: &Directive) -> Option<&'static str> {
+    let name = match directive {
+        Directive::EndMatch => "match",
+        Directive::EndCapture => "capture",
+        Directive::EndBlock => "block",
+        Directive::EndCall => "call",
+        Directive::EndIf => "if",
+        Directive::EndFor => "for",
+        Directive::EndTry => "try",
+        Directive::EndCache => "cache",
+        Directive::EndCfg => "cfg",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+// Identifiers in `code`, skipping the contents of string literals and comments.
+fn identifiers(code: &str) -> Vec<String> {
+    let mut identifiers = Vec::new();
+    let mut offset = 0;
+
+    for token in rustc_lexer::tokenize(code) {
+        if matches!(token.kind, TokenKind::Ident | TokenKind::RawIdent) {
+            identifiers.push(code[offset..offset + token.len].trim_start_matches("r#").to_string());
+        }
+        offset += token.len;
+    }
+
+    identifiers
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lint::{format_captures, Lint, LintLevel, LintOptions};
+    use crate::prelude::*;
+
+    fn lint(args: &[&str], source: &str, options: &GeneratorOptions) -> Vec<(String, Severity, String)> {
+        let mut set = TemplateSet::new();
+        set.add_template("index.plt", args.iter().map(|arg| arg.to_string()).collect(), source.to_string());
+
+        set.lint("index.plt", options)
+            .iter()
+            .map(|diagnostic| (diagnostic.code.clone(), diagnostic.severity, diagnostic.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn it_reports_unbalanced_directives() {
+        let source = "<?rs @if a ?><?rs @for x in xs ?><?rs @endif ?>\n<?rs @endmatch ?><?rs @else ?><?rs @try ?>";
+        let diagnostics = lint(&["a: bool", "xs: &[u8]"], source, &GeneratorOptions::default());
+
+        let messages: Vec<_> = diagnostics.iter().map(|(_, severity, message)| (*severity, message.as_str())).collect();
+        assert_eq!(
+            messages,
+            vec![
+                (Severity::Error, "index.plt:1:18: `@for` is missing its `@endfor`"),
+                (Severity::Error, "index.plt:2:5: `@endmatch` found without matching `@match`"),
+                (Severity::Error, "index.plt:2:22: `@else` found outside of `@if`"),
+                (Severity::Error, "index.plt:2:35: `@try` is missing its `@endtry`"),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reports_unused_parameters_and_empty_tags() {
+        let source = "<?rs @args title: &str, _unused: u8, count: u8 ?><h1><?= title ?></h1><?rs ?><?= \"count\" ?>";
+        let diagnostics = lint(&[], source, &GeneratorOptions::default());
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                ("unused_parameters".to_string(), Severity::Warning, "index.plt:1:5: parameter `count` is never used".to_string()),
+                ("empty_code".to_string(), Severity::Warning, "index.plt:1:75: empty code tag".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_checks_parameters_against_the_generated_code() {
+        let source = "<?rs @for item in items ?><?= item ?> <?= itme ?><?rs @endfor ?>\
+                      <?= format!(\"{count}\") ?><?rs let total = helper(items); ?><?= total ?>";
+        let diagnostics = lint(&["items: &[u8]", "count: u8", "unused: u8"], source, &GeneratorOptions::default());
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                ("unused_parameters".to_string(), Severity::Warning, "index.plt: parameter `unused` is never used".to_string()),
+                (
+                    "undeclared_parameters".to_string(),
+                    Severity::Error,
+                    "index.plt:1:42: `itme` is neither a parameter of the template nor declared by its code".to_string()
+                ),
+            ]
+        );
+        assert!(lint(&[], "<?rs @cache(\"key\") ?>x<?rs @endcache ?>", &GeneratorOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn it_finds_variables_captured_by_format_strings() {
+        assert_eq!(format_captures("{name:?} {0} {{escaped}} { padded } {}"), vec!["name", "padded"]);
+    }
+
+    #[test]
+    fn it_reports_unescaped_echoes_when_asked_to() {
+        let source = "<?rs @deny(unescaped_echo) ?><?= title ?><?= EscapeHtml(title) ?>";
+        let diagnostics = lint(&["title: &str"], source, &GeneratorOptions::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].1, Severity::Error);
+        assert_eq!(diagnostics[0].2, "index.plt:1:33: parameter `title` is echoed without escaping");
+
+        let options = GeneratorOptions { escaping: Escaping::Html, ..GeneratorOptions::default() };
+        assert!(lint(&["title: &str"], source, &options).is_empty());
+        let diagnostics = lint(&["title: &str"], "<?rs @warn(unescaped_echo) ?><?= Raw(title) ?>", &options);
+        assert_eq!(diagnostics[0].1, Severity::Warning);
+    }
+
+    #[test]
+    fn it_reports_malformed_config() {
+        let mut set = TemplateSet::new();
+        set.add_template("app.ini.plt", vec!["name: &str".to_string()], "[app]\nname = \"<?= name ?>\"\nport\n".to_string());
+        set.add_template("flags.ini.plt", Vec::new(), "[flags]\n<?rs @if true ?>debug\n<?rs @endif ?>".to_string());
+        let messages = |path: &str| -> Vec<String> { set.lint(path, &GeneratorOptions::default()).iter().map(ToString::to_string).collect() };
+
+        assert_eq!(messages("app.ini.plt"), vec!["app.ini.plt:3:1: invalid INI: expected a section, a comment or a `key = value` pair"]);
+        assert!(messages("flags.ini.plt").is_empty());
+    }
+
+    #[test]
+    fn it_reports_malformed_html_when_asked_to() {
+        let source = "<?rs @warn(malformed_html) ?><main id=\"page\"><ul><li>a<li>b</ul>\n\
+                      <?rs @if a ?><div id=main><?rs @else ?><div id=main class=\"<?= a ?>\"><?rs @endif ?></div>\n\
+                      <section><span id=\"<?= a ?>\"></section></div></i><p id=main>";
+        let diagnostics: Vec<_> = lint(&["a: bool"], source, &GeneratorOptions::default()).into_iter().map(|(_, _, message)| message).collect();
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                "index.plt:3:10: `<span>` isn't closed before `</section>`",
+                "index.plt:3:40: `</div>` closes an element which isn't open",
+                "index.plt:3:46: `</i>` closes an element which isn't open",
+                "index.plt:3:53: duplicate `id` \"main\"",
+                "index.plt:1:30: `<main>` is never closed",
+            ]
+        );
+        assert!(lint(&["a: bool"], "<div><?= a ?>", &GeneratorOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn it_reports_inaccessible_markup() {
+        let source = "<html><img src=\"a.png\"><img alt=\"\"><img <?= attributes ?>>\n\
+                      <label>Name <input name=name></label><label for=email>Email</label><input id=email>\n\
+                      <input type=hidden name=token><select name=kind></select><textarea aria-label=Note></textarea>";
+        let diagnostics = lint(&["attributes: &str"], source, &GeneratorOptions::default());
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                ("missing_lang".to_string(), Severity::Warning, "index.plt:1:1: `<html>` without a `lang` attribute".to_string()),
+                ("missing_alt".to_string(), Severity::Warning, "index.plt:1:7: `<img>` without an `alt` attribute".to_string()),
+                ("unlabeled_input".to_string(), Severity::Warning, "index.plt:3:31: `<select>` without a label".to_string()),
+            ]
+        );
+        assert!(lint(&["attributes: &str"], &format!("<?rs @allow(missing_alt, unlabeled_input, missing_lang) ?>{source}"), &GeneratorOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn it_configures_lint_levels() {
+        let mut lints = LintOptions { max_text_len: 4, ..LintOptions::default() };
+        lints.set(Lint::EmptyCode, LintLevel::Allow);
+        let options = GeneratorOptions { lints, ..GeneratorOptions::default() };
+
+        let diagnostics = lint(&["a: u8"], "<?rs ?>long text", &options);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].0, "unused_parameters");
+        assert_eq!(diagnostics[1].2, "index.plt:1:8: text of 9 bytes is longer than 4 bytes");
+
+        let diagnostics = lint(&["a: u8"], "<?rs @allow(unused_parameters, long_text) ?>long text", &options);
+        assert!(diagnostics.is_empty());
+
+        assert_eq!("deny".parse::<LintLevel>().unwrap(), LintLevel::Deny);
+        assert_eq!(
+            "unused".parse::<Lint>().unwrap_err().to_string(),
+            "unknown lint `unused`, expected one of `unbalanced_directives`, `unused_parameters`, `undeclared_parameters`, `unescaped_echo`, `empty_code`, `long_text`, `malformed_html`, `missing_alt`, `unlabeled_input`, `missing_lang`, `malformed_config`"
+        );
+    }
+}
@@ -0,0 +1,169 @@
+// Sources of templates, used to load a `TemplateSet` from an entry template along with the
+// templates it includes, extends, calls and renders sections of, instead of from all files of a
+// directory, e.g. for templates embedded into the binary:
+//
+//     static TEMPLATES: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/templates");
+//
+//     let set = TemplateSet::load_from(&EmbeddedLoader::new(&TEMPLATES), &["index.plt"])?;
+//
+// Macros are resolved by their names rather than paths, so the templates defining the ones used
+// by others have to be loaded as entries too.
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use anyhow::{bail, Context};
+use crate::prelude::*;
+
+pub trait TemplateLoader {
+    // Source of the template under `name`, a path relative to the template root like
+    // `partials/header.plt`.
+    fn load(&self, name: &str) -> Result<Cow<'_, str>>;
+}
+
+// Loads templates from the files under `root`.
+#[derive(Debug, Clone)]
+pub struct FileSystemLoader {
+    pub root: PathBuf,
+}
+
+impl FileSystemLoader {
+    pub fn new(root: impl Into<PathBuf>) -> FileSystemLoader {
+        FileSystemLoader { root: root.into() }
+    }
+}
+
+impl TemplateLoader for FileSystemLoader {
+    fn load(&self, name: &str) -> Result<Cow<'_, str>> {
+        let file = self.root.join(name);
+        let source = std::fs::read_to_string(&file).with_context(|| format!("failed to read template `{}`", file.display()))?;
+
+        Ok(Cow::Owned(source))
+    }
+}
+
+// Loads templates from sources kept in memory, e.g. in tests.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLoader {
+    templates: BTreeMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> MemoryLoader {
+        Self::default()
+    }
+
+    pub fn add_template(&mut self, path: impl Into<String>, source: impl Into<String>) {
+        self.templates.insert(path.into(), source.into());
+    }
+}
+
+impl TemplateLoader for MemoryLoader {
+    fn load(&self, name: &str) -> Result<Cow<'_, str>> {
+        let Some(source) = self.templates.get(name) else {
+            bail!("template `{name}` is not part of the loader");
+        };
+
+        Ok(Cow::Borrowed(source))
+    }
+}
+
+// Loads templates embedded into the binary using `include_dir::include_dir!`.
+#[cfg(feature = "include_dir")]
+#[derive(Debug, Clone)]
+pub struct EmbeddedLoader<'a> {
+    dir: &'a include_dir::Dir<'a>,
+}
+
+#[cfg(feature = "include_dir")]
+impl<'a> EmbeddedLoader<'a> {
+    pub fn new(dir: &'a include_dir::Dir<'a>) -> EmbeddedLoader<'a> {
+        EmbeddedLoader { dir }
+    }
+}
+
+#[cfg(feature = "include_dir")]
+impl TemplateLoader for EmbeddedLoader<'_> {
+    fn load(&self, name: &str) -> Result<Cow<'_, str>> {
+        let Some(file) = self.dir.get_file(name) else {
+            bail!("template `{name}` is not embedded");
+        };
+        let Some(source) = file.contents_utf8() else {
+            bail!("template `{name}` is not valid UTF-8");
+        };
+
+        Ok(Cow::Borrowed(source))
+    }
+}
+
+impl TemplateSet {
+    // Loads the templates under `paths` using `loader`, along with all templates they depend on.
+    pub fn load_from(loader: &dyn TemplateLoader, paths: &[&str]) -> Result<TemplateSet> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("load_templates", entries = paths.len()).entered();
+        let mut set = Self::new();
+        let mut queue: Vec<String> = paths.iter().map(|path| path.to_string()).collect();
+
+        while let Some(path) = queue.pop() {
+            // Sections are loaded along with their file.
+            let file = path.split('#').next().unwrap_or(&path).to_string();
+            if set.get(&file).is_some() {
+                continue;
+            }
+
+            let source = loader.load(&file).with_context(|| format!("failed to load template `{file}`"))?;
+            // Checkouts can have either line endings, which would end up in the generated code.
+            set.add_template(file.clone(), Vec::new(), source.replace("\r\n", "\n"));
+
+            let sections = set.sections(&file);
+            for template in set.get(&file).into_iter().chain(sections) {
+                // Malformed directives are reported once the template is generated.
+                let dependencies = template.dependencies().unwrap_or_default();
+                #[cfg(feature = "tracing")]
+                tracing::trace!(template = %template.path, ?dependencies, "resolved dependencies");
+                queue.extend(dependencies);
+            }
+        }
+
+        Ok(set)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    #[test]
+    fn it_loads_the_templates_entries_depend_on() {
+        let mut loader = MemoryLoader::new();
+        loader.add_template("page.plt", "<?rs @extends \"layout.plt\" ?><?rs @block content ?><?rs @include \"components.plt#badge\" ?><?rs @endblock ?>");
+        loader.add_template("layout.plt", "<main><?rs @yield content ?></main>");
+        loader.add_template("components.plt", "<?rs @template badge ?><b>New</b><?rs @endtemplate ?>");
+        loader.add_template("unused.plt", "<p>Unused</p>");
+
+        let set = TemplateSet::load_from(&loader, &["page.plt"]).unwrap();
+        let paths: Vec<_> = set.paths().map(String::as_str).collect();
+        assert_eq!(paths, vec!["components.plt", "components.plt#badge", "layout.plt", "page.plt"]);
+
+        let error = TemplateSet::load_from(&loader, &["missing.plt"]).unwrap_err();
+        assert_eq!(format!("{error:#}"), "failed to load template `missing.plt`: template `missing.plt` is not part of the loader");
+    }
+
+    #[test]
+    fn it_loads_templates_from_files() {
+        let set = TemplateSet::load_from(&FileSystemLoader::new("src/test-files/build"), &["index.plt"]).unwrap();
+        let paths: Vec<_> = set.paths().map(String::as_str).collect();
+
+        assert_eq!(paths, vec!["index.plt", "partials/header.plt"]);
+        assert!(set.generate("index.plt").is_ok());
+    }
+
+    #[cfg(feature = "include_dir")]
+    #[test]
+    fn it_loads_embedded_templates() {
+        static TEMPLATES: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/src/test-files/build");
+
+        let set = TemplateSet::load_from(&EmbeddedLoader::new(&TEMPLATES), &["index.plt"]).unwrap();
+
+        assert!(set.get("partials/header.plt").is_some());
+    }
+}
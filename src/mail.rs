@@ -0,0 +1,126 @@
+// Assembly of emails out of the HTML and text bodies rendered by email templates, see
+// `plt::email`, into the body of a `lettre` message:
+//
+//     let body = plt::mail::email_body(&templates::welcome_email(name)?, &templates::welcome_email_text(name)?);
+//     let message = lettre::Message::builder().to(to).subject("Welcome").multipart(body)?;
+//
+// Images and other assets the HTML references through `@asset` or an `AssetManifest` can be
+// embedded into the message instead of being loaded from the server, which mail clients often
+// block. Their URLs are replaced with `cid:` URLs of inline attachments:
+//
+//     let assets = InlineAssets { manifest: &manifest, asset_dir: Path::new("static") };
+//     let body = plt::mail::email_body_with_assets(&html, &text, &assets)?;
+use std::path::Path;
+use anyhow::Context;
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, MultiPart, SinglePart};
+use crate::assets::AssetManifest;
+use crate::prelude::*;
+
+// Assets which are embedded into emails whose HTML references them.
+#[derive(Debug, Clone, Copy)]
+pub struct InlineAssets<'a> {
+    pub manifest: &'a AssetManifest,
+    // Directory holding the original assets, as given to `AssetManifest::load_dir`.
+    pub asset_dir: &'a Path,
+}
+
+// `multipart/alternative` body made of the text and HTML bodies, with the HTML one preferred by
+// clients which can display it.
+pub fn email_body(html: &str, text: &str) -> MultiPart {
+    MultiPart::alternative_plain_html(text.to_string(), html.to_string())
+}
+
+// Same as `email_body`, with the assets the HTML references embedded as inline attachments of
+// a `multipart/related` part along with it.
+pub fn email_body_with_assets(html: &str, text: &str, assets: &InlineAssets) -> Result<MultiPart> {
+    let mut html = html.to_string();
+    let mut attachments = Vec::new();
+
+    for path in assets.manifest.paths() {
+        let url = assets.manifest.url(path)?;
+        if !html.contains(&url) {
+            continue;
+        }
+
+        let content_id = content_id(assets.manifest.get(path).unwrap_or(path));
+        html = html.replace(&url, &format!("cid:{content_id}"));
+
+        let file = assets.asset_dir.join(path);
+        let content = std::fs::read(&file).with_context(|| format!("failed to read asset `{}`", file.display()))?;
+        let content_type = ContentType::parse(content_type(path)).with_context(|| format!("invalid content type of `{path}`"))?;
+        attachments.push(Attachment::new_inline(content_id).body(content, content_type));
+    }
+
+    if attachments.is_empty() {
+        return Ok(email_body(&html, text));
+    }
+
+    let related = attachments.into_iter().fold(MultiPart::related().singlepart(SinglePart::html(html)), MultiPart::singlepart);
+
+    Ok(MultiPart::alternative().singlepart(SinglePart::plain(text.to_string())).multipart(related))
+}
+
+// Content ID of the inline attachment of an asset, made of its fingerprinted path so it
+// changes along with the asset, e.g. `img.logo.3f2a9c0b1d4e5f67.png@plt`.
+fn content_id(fingerprinted: &str) -> String {
+    format!("{}@plt", fingerprinted.replace(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '-'), "."))
+}
+
+// Content type of an asset, guessed from its extension.
+fn content_type(path: &str) -> &'static str {
+    let extension = path.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase()).unwrap_or_default();
+
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "css" => "text/css",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::assets::AssetManifest;
+    use crate::mail::{email_body, email_body_with_assets, InlineAssets};
+
+    fn formatted(body: lettre::message::MultiPart) -> String {
+        String::from_utf8(body.formatted()).unwrap()
+    }
+
+    #[test]
+    fn it_assembles_text_and_html_bodies() {
+        let body = formatted(email_body("<p>Hi</p>", "Hi\n"));
+
+        assert!(body.contains("Content-Type: multipart/alternative;"));
+        assert!(body.find("Content-Type: text/plain; charset=utf-8").unwrap() < body.find("Content-Type: text/html; charset=utf-8").unwrap());
+        assert!(body.contains("<p>Hi</p>"));
+    }
+
+    #[test]
+    fn it_embeds_referenced_assets() {
+        let asset_dir = Path::new("src/test-files/assets");
+        let manifest = AssetManifest::load_dir(asset_dir, "/assets").unwrap();
+        let url = manifest.url("css/app.css").unwrap();
+        let assets = InlineAssets { manifest: &manifest, asset_dir };
+
+        let html = format!("<link rel=\"stylesheet\" href=\"{url}\"><p>Hi</p>");
+        let body = formatted(email_body_with_assets(&html, "Hi\n", &assets).unwrap());
+
+        let content_id = format!("{}@plt", manifest.get("css/app.css").unwrap().replace('/', "."));
+        assert!(body.contains("Content-Type: multipart/related;"));
+        assert!(body.contains(&format!("href=3D\"cid:{content_id}\"")));
+        assert!(body.contains(&format!("Content-ID: <{content_id}>")));
+        assert!(body.contains("Content-Disposition: inline"));
+        assert!(!body.contains(&url));
+
+        let body = formatted(email_body_with_assets("<p>Hi</p>", "Hi\n", &assets).unwrap());
+        assert!(!body.contains("multipart/related"));
+    }
+}
@@ -0,0 +1,187 @@
+// Markdown templates: the text of `.md.plt` files is converted from Markdown to HTML when they
+// are added to a `TemplateSet`, while code and echo parts are kept as they are:
+//
+//     # Hello <?= name ?>
+//
+//     <?rs for post in posts { ?>
+//
+//     - [<?= post.title ?>](<?= post.url ?>)
+//
+//     <?rs } ?>
+//
+// The whole text is rendered at once, with the code and echo parts standing in as placeholders,
+// so an echo inside of a heading or link stays inside of it. Code parts opening or closing Rust
+// blocks should be separated from the Markdown around them by blank lines, as Markdown would
+// continue a paragraph or list item into them otherwise.
+use std::ops::Range;
+use pulldown_cmark::{html, Options, Parser};
+use crate::prelude::*;
+
+// Consisting of ASCII letters only, so placeholders are kept as they are by Markdown, HTML
+// escaping and URL encoding.
+const PLACEHOLDER_START: &str = "PLTPART";
+const PLACEHOLDER_END: &str = "TRAPTLP";
+
+// Whether the template under `path` is written in Markdown, e.g. `posts/hello.md.plt`.
+pub fn is_markdown_template(path: &str) -> bool {
+    path.ends_with(".md.plt")
+}
+
+// Converts Markdown in the text parts into HTML. Returns the new parts with their spans, where
+// text parts span the source between the code and echo parts around them.
+pub fn render_markdown(parts: &[Part], spans: &[Range<usize>]) -> (Vec<Part>, Vec<Range<usize>>) {
+    let mut markdown = String::new();
+    for (index, part) in parts.iter().enumerate() {
+        match part {
+            Part::Text(text) => markdown.push_str(text),
+            Part::Code(_) | Part::EchoCode(_) => markdown.push_str(&placeholder(index)),
+        }
+    }
+
+    let mut output = String::new();
+    html::push_html(&mut output, Parser::new_ext(&markdown, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH));
+    let output = unwrap_code_paragraphs(&output, parts);
+
+    let mut new_parts = Vec::new();
+    let mut new_spans = Vec::new();
+    let mut text_start = 0;
+    let mut rest = output.as_str();
+
+    while let Some((text, index, after)) = next_placeholder(rest, parts) {
+        if !text.is_empty() {
+            new_parts.push(Part::Text(text.to_string()));
+            new_spans.push(text_start..spans[index].start);
+        }
+        new_parts.push(parts[index].clone());
+        new_spans.push(spans[index].clone());
+
+        text_start = spans[index].end;
+        rest = after;
+    }
+    if !rest.is_empty() {
+        new_parts.push(Part::Text(rest.to_string()));
+        new_spans.push(text_start..spans.last().map_or(0, |span| span.end).max(text_start));
+    }
+
+    (new_parts, new_spans)
+}
+
+fn placeholder(index: usize) -> String {
+    format!("{PLACEHOLDER_START}{index}{PLACEHOLDER_END}")
+}
+
+// Splits `output` at its first placeholder into the text before it, the index of the part and
+// the output after it.
+fn next_placeholder<'a>(output: &'a str, parts: &[Part]) -> Option<(&'a str, usize, &'a str)> {
+    let mut offset = 0;
+
+    while let Some(start) = output[offset..].find(PLACEHOLDER_START) {
+        let start = offset + start;
+        let digits_start = start + PLACEHOLDER_START.len();
+        let digits_end = output[digits_start..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(output.len(), |end| digits_start + end);
+
+        let index = output[digits_start..digits_end].parse::<usize>().ok();
+        if let Some(index) = index.filter(|&index| index < parts.len() && !parts[index].is_text()) {
+            if output[digits_end..].starts_with(PLACEHOLDER_END) {
+                return Some((&output[..start], index, &output[digits_end + PLACEHOLDER_END.len()..]));
+            }
+        }
+
+        offset = digits_start;
+    }
+
+    None
+}
+
+// Code parts on lines of their own become paragraphs, which would wrap the Rust blocks they
+// open or close in `<p>` tags. Removes the paragraphs consisting of code parts only.
+fn unwrap_code_paragraphs(output: &str, parts: &[Part]) -> String {
+    let mut unwrapped = String::with_capacity(output.len());
+
+    for line in output.split_inclusive('\n') {
+        let paragraph = line.trim_end().strip_prefix("<p>").and_then(|line| line.strip_suffix("</p>"));
+
+        match paragraph {
+            Some(content) if is_code_only(content, parts) => {
+                unwrapped.push_str(content);
+                unwrapped.push('\n');
+            }
+            _ => unwrapped.push_str(line),
+        }
+    }
+
+    unwrapped
+}
+
+fn is_code_only(mut content: &str, parts: &[Part]) -> bool {
+    let mut found = false;
+
+    while let Some((text, index, after)) = next_placeholder(content, parts) {
+        if !text.trim().is_empty() || !matches!(parts[index], Part::Code(_)) {
+            return false;
+        }
+
+        found = true;
+        content = after;
+    }
+
+    found && content.trim().is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markdown::{is_markdown_template, render_markdown};
+    use crate::prelude::*;
+
+    fn render(source: &str) -> (Vec<Part>, Vec<std::ops::Range<usize>>) {
+        let mut fsa = TextCodeFSA::new();
+        let parts = fsa.run(source.to_string()).clone();
+
+        render_markdown(&parts, fsa.spans())
+    }
+
+    #[test]
+    fn it_detects_markdown_templates() {
+        assert!(is_markdown_template("posts/hello.md.plt"));
+        assert!(!is_markdown_template("posts/hello.plt"));
+        assert!(!is_markdown_template("posts/md.plt"));
+    }
+
+    #[test]
+    fn it_renders_markdown_around_echo_parts() {
+        let (parts, _) = render("# Hello <?= name ?>\n\nSee [the docs](<?= url ?>).\n");
+
+        assert_eq!(
+            parts,
+            vec![
+                Part::Text("<h1>Hello ".to_string()),
+                Part::EchoCode(" name ".to_string()),
+                Part::Text("</h1>\n<p>See <a href=\"".to_string()),
+                Part::EchoCode(" url ".to_string()),
+                Part::Text("\">the docs</a>.</p>\n".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_keeps_code_blocks_out_of_paragraphs() {
+        let source = "<?rs for post in posts { ?>\n\n- <?= post ?>\n\n<?rs } ?>\n";
+        let (parts, spans) = render(source);
+
+        assert_eq!(
+            parts,
+            vec![
+                Part::Code(" for post in posts { ".to_string()),
+                Part::Text("\n<ul>\n<li>".to_string()),
+                Part::EchoCode(" post ".to_string()),
+                Part::Text("</li>\n</ul>\n".to_string()),
+                Part::Code(" } ".to_string()),
+                Part::Text("\n".to_string()),
+            ]
+        );
+        assert_eq!(&source[spans[2].clone()], " post ");
+        assert_eq!(&source[spans[4].clone()], " } ");
+    }
+}
@@ -0,0 +1,609 @@
+// Conversion of templates written for other template engines into plt templates, e.g. by
+// `plt migrate --from tera templates` or `plt migrate --from erb templates`. Constructs with a direct equivalent are converted, e.g.
+// `{{ user.name }}` into `<?= user.name ?>` and `{% for item in items %}` into
+// `<?rs @for item in items ?>`. The others are reported as warnings and marked in place with a
+// `TODO(plt migrate)` comment, to be converted by hand:
+//
+//     <?rs /* TODO(plt migrate): filter `truncate` has no equivalent: {{ title | truncate(length=10) }} */ ?><?= title ?>
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use anyhow::bail;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceSyntax {
+    // Tera, along with the subset of Jinja2 it shares.
+    Tera,
+    // `<% %>` and `<%= %>` tags, as used by Ruby's ERB and classic ASP, whose code is kept where
+    // it's valid Rust.
+    Erb,
+}
+
+impl SourceSyntax {
+    // Extensions of the template files of the engine.
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            SourceSyntax::Tera => &["tera", "html", "j2", "jinja", "jinja2"],
+            SourceSyntax::Erb => &["erb", "rhtml", "asp"],
+        }
+    }
+
+    // Path of the migrated template, e.g. `pages/index.plt` for `pages/index.html`.
+    pub fn migrated_path(&self, path: &Path) -> PathBuf {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if self.extensions().contains(&extension) => path.with_extension("plt"),
+            Some(extension) => path.with_extension(format!("{extension}.plt")),
+            None => path.with_extension("plt"),
+        }
+    }
+}
+
+impl FromStr for SourceSyntax {
+    type Err = anyhow::Error;
+
+    fn from_str(syntax: &str) -> Result<SourceSyntax> {
+        match syntax {
+            "tera" | "jinja" => Ok(SourceSyntax::Tera),
+            "erb" | "asp" => Ok(SourceSyntax::Erb),
+            _ => bail!("unknown template syntax `{syntax}`, expected `tera` or `erb`"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Migration {
+    pub source: String,
+    // Constructs which need to be converted by hand, as warnings pointing at the original source.
+    pub diagnostics: Diagnostics,
+}
+
+// Converts the template at `path` into a plt template.
+pub fn migrate(syntax: SourceSyntax, path: &str, source: &str) -> Migration {
+    let mut migrator = Migrator {
+        syntax,
+        path,
+        source,
+        output: String::new(),
+        diagnostics: Diagnostics::new(),
+        open_tags: Vec::new(),
+        trim_next_text: false,
+    };
+    match syntax {
+        SourceSyntax::Tera => migrator.tera(),
+        SourceSyntax::Erb => migrator.erb(),
+    }
+
+    Migration { source: migrator.output, diagnostics: migrator.diagnostics }
+}
+
+struct Migrator<'a> {
+    syntax: SourceSyntax,
+    path: &'a str,
+    source: &'a str,
+    output: String,
+    diagnostics: Diagnostics,
+    // Names of the open block tags, e.g. `for`.
+    open_tags: Vec<String>,
+    // Set by whitespace control, e.g. `-%}`, removing the whitespace at the start of the next text.
+    trim_next_text: bool,
+}
+
+impl Migrator<'_> {
+    fn text(&mut self, text: &str) {
+        let text = if std::mem::take(&mut self.trim_next_text) { text.trim_start() } else { text };
+        self.output.push_str(text);
+    }
+
+    // Marks the construct at `range` of the source as needing to be converted by hand.
+    fn todo(&mut self, range: Range<usize>, message: String) {
+        let original = self.source[range.clone()].replace("*/", "* /");
+        self.output.push_str(&format!("<?rs /* TODO(plt migrate): {message}: {original} */ ?>"));
+
+        let mut diagnostic = Diagnostic::error(self.path, Some(Span::new(self.source, range.start, range.end)), "manual-conversion", message)
+            .with_suggestion("convert it by hand, it's marked with a `TODO(plt migrate)` comment");
+        diagnostic.severity = Severity::Warning;
+        self.diagnostics.push(diagnostic);
+    }
+
+    // Converts an expression, marking the parts which can't be converted with a TODO at `range`.
+    fn expression(&mut self, expression: &str, range: Range<usize>) -> String {
+        let (converted, problems) = tera_expression(expression);
+        for problem in problems {
+            self.todo(range.clone(), problem);
+        }
+
+        converted
+    }
+
+    fn tera(&mut self) {
+        let mut offset = 0;
+
+        while let Some(start) = find_tera_tag(self.source, offset) {
+            self.text(&self.source[offset..start]);
+
+            let close = match &self.source[start..start + 2] {
+                "{{" => "}}",
+                "{%" => "%}",
+                _ => "#}",
+            };
+            let Some(end) = find_outside_strings(self.source, start + 2, close) else {
+                self.todo(start..self.source.len(), "tag is never closed".to_string());
+                return;
+            };
+            let range = start..end + 2;
+
+            let mut inner = &self.source[start + 2..end];
+            if let Some(trimmed) = inner.strip_prefix('-') {
+                self.output.truncate(self.output.trim_end().len());
+                inner = trimmed;
+            }
+            if let Some(trimmed) = inner.strip_suffix('-') {
+                self.trim_next_text = true;
+                inner = trimmed;
+            }
+            let inner = inner.trim();
+
+            offset = range.end;
+            match close {
+                "}}" => {
+                    let code = self.tera_value(inner, range);
+                    self.output.push_str(&format!("<?= {code} ?>"));
+                }
+                "#}" => self.output.push_str(&format!("<?rs /* {} */ ?>", inner.replace("*/", "* /"))),
+                _ => offset = self.tera_statement(inner, range),
+            }
+        }
+
+        self.text(&self.source[offset..]);
+    }
+
+    // Converts a value along with its filters, e.g. `title | upper`.
+    fn tera_value(&mut self, inner: &str, range: Range<usize>) -> String {
+        let mut filters = split_filters(inner).into_iter();
+        let mut code = self.expression(filters.next().unwrap_or_default(), range.clone());
+
+        for filter in filters {
+            let name = filter.split('(').next().unwrap_or_default().trim();
+            code = match name {
+                "safe" => format!("Raw({code})"),
+                "escape" | "e" => code,
+                "length" => format!("{code}.len()"),
+                "upper" => format!("{code}.to_uppercase()"),
+                "lower" => format!("{code}.to_lowercase()"),
+                "trim" => format!("{code}.trim()"),
+                _ => {
+                    self.todo(range.clone(), format!("filter `{name}` has no equivalent"));
+                    code
+                }
+            };
+        }
+
+        code
+    }
+
+    // Converts the statement of a `{% %}` tag at `range`, returning the offset to continue at.
+    fn tera_statement(&mut self, inner: &str, range: Range<usize>) -> usize {
+        let (name, argument) = inner.split_once(char::is_whitespace).unwrap_or((inner, ""));
+        let argument = argument.trim();
+
+        let directive = match name {
+            "if" => {
+                self.open_tags.push("if".to_string());
+                format!("@if {}", self.expression(argument, range.clone()))
+            }
+            "elif" => format!("@else if {}", self.expression(argument, range.clone())),
+            "else" if self.open_tags.last().is_some_and(|tag| tag == "for") => {
+                self.todo(range.clone(), "`{% else %}` of `{% for %}` has no equivalent, check whether the iterable is empty instead".to_string());
+                return range.end;
+            }
+            "else" => "@else".to_string(),
+            "for" => {
+                self.open_tags.push("for".to_string());
+                let Some((pattern, iterable)) = argument.split_once(" in ") else {
+                    self.todo(range.clone(), "malformed `{% for %}`".to_string());
+                    return range.end;
+                };
+                let pattern = match pattern.split_once(',') {
+                    Some((key, value)) => format!("({}, {})", key.trim(), value.trim()),
+                    None => pattern.trim().to_string(),
+                };
+                format!("@for {pattern} in {}", self.expression(iterable, range.clone()))
+            }
+            "endif" | "endfor" => {
+                self.open_tags.pop();
+                format!("@{name}")
+            }
+            "set" | "set_global" => {
+                let Some((variable, value)) = argument.split_once('=') else {
+                    self.todo(range.clone(), format!("`{{% {name} %}}` blocks have no equivalent"));
+                    return range.end;
+                };
+                format!("@let {} = {}", variable.trim(), self.tera_value(value.trim(), range.clone()))
+            }
+            "include" | "extends" => {
+                let Some(path) = string_literal(argument) else {
+                    self.todo(range.clone(), format!("`{{% {name} %}}` of a computed path has no equivalent"));
+                    return range.end;
+                };
+                if argument.len() > path.len() + 2 {
+                    self.todo(range.clone(), format!("options of `{{% {name} %}}` have no equivalent"));
+                }
+                format!("@{name} {:?}", self.syntax.migrated_path(Path::new(&path)).to_string_lossy().replace('\\', "/"))
+            }
+            "block" => format!("@block {argument}"),
+            "endblock" => "@endblock".to_string(),
+            "break" | "continue" => {
+                self.output.push_str(&format!("<?rs {name}; ?>"));
+                return range.end;
+            }
+            "raw" => {
+                let Some(end) = self.source[range.end..].find("{% endraw %}") else {
+                    self.todo(range.clone(), "`{% raw %}` is never closed".to_string());
+                    return range.end;
+                };
+                self.text(&self.source[range.end..range.end + end]);
+                return range.end + end + "{% endraw %}".len();
+            }
+            _ => {
+                self.todo(range.clone(), format!("`{{% {name} %}}` has no equivalent"));
+                return range.end;
+            }
+        };
+
+        self.output.push_str(&format!("<?rs {directive} ?>"));
+        range.end
+    }
+
+    fn erb(&mut self) {
+        let mut offset = 0;
+
+        while let Some(position) = self.source[offset..].find("<%") {
+            let start = offset + position;
+            self.text(&self.source[offset..start]);
+
+            // `<%%` writes a literal `<%`.
+            if self.source[start..].starts_with("<%%") {
+                self.text("<%");
+                offset = start + 3;
+                continue;
+            }
+            let Some(end) = find_outside_strings(self.source, start + 2, "%>") else {
+                self.todo(start..self.source.len(), "tag is never closed".to_string());
+                return;
+            };
+            let range = start..end + 2;
+            offset = range.end;
+
+            let mut inner = &self.source[start + 2..end];
+            if let Some(trimmed) = inner.strip_prefix('-') {
+                self.output.truncate(self.output.trim_end_matches([' ', '\t']).len());
+                inner = trimmed;
+            }
+            if let Some(trimmed) = inner.strip_suffix('-') {
+                // `-%>` removes the line break after the tag.
+                if self.source[offset..].starts_with('\n') {
+                    offset += 1;
+                }
+                inner = trimmed;
+            }
+
+            if let Some(code) = inner.strip_prefix("==") {
+                let code = self.erb_code(code.trim(), range);
+                self.output.push_str(&format!("<?= Raw({code}) ?>"));
+            } else if let Some(code) = inner.strip_prefix('=') {
+                let code = self.erb_code(code.trim(), range);
+                self.output.push_str(&format!("<?= {code} ?>"));
+            } else if let Some(comment) = inner.strip_prefix('#') {
+                self.output.push_str(&format!("<?rs /* {} */ ?>", comment.trim().replace("*/", "* /")));
+            } else if inner.starts_with('@') {
+                self.todo(range, "page directives have no equivalent".to_string());
+            } else {
+                self.erb_statement(inner.trim(), range);
+            }
+        }
+
+        self.text(&self.source[offset..]);
+    }
+
+    // Converts the expression of an ERB tag, keeping it as it is with a TODO unless it's valid Rust.
+    fn erb_code(&mut self, code: &str, range: Range<usize>) -> String {
+        // Instance variables of Ruby, e.g. `@user`, are what plt templates take as parameters.
+        let (converted, _) = tera_expression(&without_instance_variable_sigils(code));
+        if syn::parse_str::<syn::Expr>(&converted).is_ok() {
+            return converted;
+        }
+
+        self.todo(range, format!("`{code}` isn't a Rust expression"));
+        code.to_string()
+    }
+
+    fn erb_statement(&mut self, code: &str, range: Range<usize>) {
+        let lower = code.to_lowercase();
+        let keyword = lower.split_whitespace().next().unwrap_or_default();
+        // Argument of the statement without its keyword and, in ASP, `Then`.
+        let argument = code[keyword.len()..].trim();
+        let argument = match argument.to_lowercase().strip_suffix(" then") {
+            Some(without_then) => &argument[..without_then.len()],
+            None => argument,
+        };
+
+        let directive = match keyword {
+            "if" => {
+                self.open_tags.push("if".to_string());
+                format!("@if {}", self.erb_code(argument, range.clone()))
+            }
+            "unless" => {
+                self.open_tags.push("if".to_string());
+                format!("@if !({})", self.erb_code(argument, range.clone()))
+            }
+            "elsif" | "elseif" => format!("@else if {}", self.erb_code(argument, range.clone())),
+            "else" if argument.is_empty() => "@else".to_string(),
+            "end" if lower == "end if" || self.open_tags.last().is_some_and(|tag| tag == "if") => {
+                self.open_tags.pop();
+                "@endif".to_string()
+            }
+            "end" | "next" if self.open_tags.last().is_some_and(|tag| tag == "for") => {
+                self.open_tags.pop();
+                "@endfor".to_string()
+            }
+            "end" => {
+                self.open_tags.pop();
+                self.todo(range, "`end` of a Ruby block has no equivalent".to_string());
+                return;
+            }
+            "for" => {
+                let argument = match argument.to_lowercase().strip_prefix("each ") {
+                    Some(without_each) => &argument[argument.len() - without_each.len()..],
+                    None => argument,
+                };
+                let Some((pattern, iterable)) = argument.split_once(" in ").or_else(|| argument.split_once(" In ")) else {
+                    self.todo(range, format!("`{code}` isn't a loop over a collection"));
+                    return;
+                };
+                self.open_tags.push("for".to_string());
+                format!("@for {} in {}", pattern.trim(), self.erb_code(iterable.trim(), range.clone()))
+            }
+            _ if code.ends_with('|') || code.ends_with(" do") => {
+                let Some((iterable, pattern)) = code.split_once(".each do") else {
+                    self.open_tags.push("block".to_string());
+                    self.todo(range, "Ruby blocks have no equivalent".to_string());
+                    return;
+                };
+                self.open_tags.push("for".to_string());
+                let pattern = pattern.trim().trim_matches('|').trim();
+                let pattern = if pattern.contains(',') { format!("({pattern})") } else { pattern.to_string() };
+                format!("@for {pattern} in {}", self.erb_code(iterable.trim(), range.clone()))
+            }
+            _ if syn::parse_str::<syn::Block>(&format!("{{{code}}}")).is_ok() => {
+                self.output.push_str(&format!("<?rs {code} ?>"));
+                return;
+            }
+            _ => {
+                self.todo(range, format!("`{code}` isn't Rust code"));
+                return;
+            }
+        };
+
+        self.output.push_str(&format!("<?rs {directive} ?>"));
+    }
+}
+
+// Removes the `@` of Ruby's instance variables, e.g. `@user.name`, keeping the ones which are
+// part of a word, e.g. of `"ann@example.com"`.
+fn without_instance_variable_sigils(code: &str) -> String {
+    let mut converted = String::new();
+    let mut previous = ' ';
+
+    for (index, c) in code.char_indices() {
+        let next = code[index + 1..].chars().next().unwrap_or(' ');
+        if !(c == '@' && !previous.is_alphanumeric() && (next.is_alphabetic() || next == '_')) {
+            converted.push(c);
+        }
+        previous = c;
+    }
+
+    converted
+}
+
+// Offset of the next `{{`, `{%` or `{#` from `offset`.
+fn find_tera_tag(source: &str, offset: usize) -> Option<usize> {
+    let mut rest = &source[offset..];
+    let mut start = offset;
+
+    while let Some(position) = rest.find('{') {
+        if matches!(rest.as_bytes().get(position + 1), Some(b'{' | b'%' | b'#')) {
+            return Some(start + position);
+        }
+        start += position + 1;
+        rest = &rest[position + 1..];
+    }
+
+    None
+}
+
+// Offset of the next `pattern` from `offset` which isn't inside of a string literal.
+fn find_outside_strings(source: &str, offset: usize, pattern: &str) -> Option<usize> {
+    let mut quote = None;
+
+    for (index, c) in source[offset..].char_indices() {
+        match quote {
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None if matches!(c, '"' | '\'' | '`') => quote = Some(c),
+            None if source[offset + index..].starts_with(pattern) => return Some(offset + index),
+            None => {}
+        }
+    }
+
+    None
+}
+
+// Splits `value | filter(arg=1) | other` into the value and its filters.
+fn split_filters(expression: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quote = None;
+    let mut depth = 0;
+
+    for (index, c) in expression.char_indices() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'' | '`') => quote = Some(c),
+            (None, '(' | '[' | '{') => depth += 1,
+            (None, ')' | ']' | '}') => depth -= 1,
+            (None, '|') if depth == 0 => {
+                parts.push(expression[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(expression[start..].trim());
+
+    parts
+}
+
+// Value of a string literal making up all of `argument` or its start, e.g. `"header.html"`.
+fn string_literal(argument: &str) -> Option<String> {
+    let quote = argument.chars().next().filter(|c| matches!(c, '"' | '\'' | '`'))?;
+    let end = argument[1..].find(quote)?;
+
+    Some(argument[1..end + 1].to_string())
+}
+
+// Converts a Tera expression into Rust, along with why the parts which were left as they are
+// couldn't be converted.
+fn tera_expression(expression: &str) -> (String, Vec<String>) {
+    let mut converted = String::new();
+    let mut problems = Vec::new();
+    let mut rest = expression;
+
+    while let Some(c) = rest.chars().next() {
+        if matches!(c, '"' | '\'' | '`') {
+            match rest[1..].find(c) {
+                Some(end) => {
+                    converted.push_str(&format!("{:?}", &rest[1..end + 1]));
+                    rest = &rest[end + 2..];
+                }
+                None => {
+                    converted.push_str(rest);
+                    rest = "";
+                }
+            }
+            continue;
+        }
+
+        let word_len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        if word_len == 0 {
+            if c == '~' {
+                problems.push("string concatenation with `~` has no equivalent, use `format!`".to_string());
+            }
+            converted.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        let (word, after) = rest.split_at(word_len);
+        match word {
+            "and" => converted.push_str("&&"),
+            "or" => converted.push_str("||"),
+            "not" => {
+                converted.push('!');
+                rest = after.trim_start();
+                continue;
+            }
+            "is" | "in" => {
+                problems.push(format!("`{word}` has no equivalent"));
+                converted.push_str(word);
+            }
+            "loop" if after.starts_with('.') => {
+                problems.push("`loop` variables have no equivalent, use `.iter().enumerate()`".to_string());
+                converted.push_str(word);
+            }
+            _ => converted.push_str(word),
+        }
+        rest = after;
+    }
+
+    (converted.trim().to_string(), problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::migrate::{migrate, SourceSyntax};
+    use std::path::Path;
+
+    #[test]
+    fn it_migrates_tera_templates() {
+        let source = "{% extends \"base.html\" %}{% block content %}{# list #}\n\
+                      {% if user and not user.banned %}<h1>{{ user.name | upper }}</h1>{% elif guest %}Hi{% else %}-{% endif %}\n\
+                      {% for key, value in pairs -%}\n  {{ key }}={{ value | safe }}{% endfor %}\
+                      {% set total = items | length %}{% include 'footer.html' %}{% raw %}{{ x }}{% endraw %}{% endblock content %}";
+        let migration = migrate(SourceSyntax::Tera, "index.html", source);
+
+        assert_eq!(
+            migration.source,
+            "<?rs @extends \"base.plt\" ?><?rs @block content ?><?rs /* list */ ?>\n\
+             <?rs @if user && !user.banned ?><h1><?= user.name.to_uppercase() ?></h1><?rs @else if guest ?>Hi<?rs @else ?>-<?rs @endif ?>\n\
+             <?rs @for (key, value) in pairs ?><?= key ?>=<?= Raw(value) ?><?rs @endfor ?>\
+             <?rs @let total = items.len() ?><?rs @include \"footer.plt\" ?>{{ x }}<?rs @endblock ?>"
+        );
+        assert!(migration.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn it_marks_constructs_without_an_equivalent() {
+        let source = "<p>\n  {{ title | truncate(length=10) }}{% for x in xs %}{{ loop.index }}{% else %}none{% endfor %}{% macro m() %}";
+        let migration = migrate(SourceSyntax::Tera, "index.html", source);
+
+        let messages: Vec<_> = migration.diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "index.html:2:3: filter `truncate` has no equivalent",
+                "index.html:2:53: `loop` variables have no equivalent, use `.iter().enumerate()`",
+                "index.html:2:69: `{% else %}` of `{% for %}` has no equivalent, check whether the iterable is empty instead",
+                "index.html:2:95: `{% macro %}` has no equivalent",
+            ]
+        );
+        assert!(migration.source.starts_with(
+            "<p>\n  <?rs /* TODO(plt migrate): filter `truncate` has no equivalent: {{ title | truncate(length=10) }} */ ?><?= title ?>"
+        ));
+    }
+
+    #[test]
+    fn it_migrates_erb_templates() {
+        let source = "<%# list %>\n<% if @users.len() > 0 and not hidden -%>\n<ul>\n  <%- @users.each do |user| %><li><%= user.name %><%= \"a@b\" %></li><% end %>\n</ul>\n\
+                      <% elsif x %><%% <% else %><%== body %><% end %><% let n = 1; %>";
+        let migration = migrate(SourceSyntax::Erb, "index.erb", source);
+
+        assert_eq!(
+            migration.source,
+            "<?rs /* list */ ?>\n<?rs @if users.len() > 0 && !hidden ?><ul>\n<?rs @for user in users ?><li><?= user.name ?><?= \"a@b\" ?></li><?rs @endfor ?>\n</ul>\n\
+             <?rs @else if x ?><% <?rs @else ?><?= Raw(body) ?><?rs @endif ?><?rs let n = 1; ?>"
+        );
+        assert!(migration.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn it_migrates_classic_asp_templates() {
+        let source = "<%@ Language=\"VBScript\" %><% If count > 1 Then %>many<% Else %>one<% End If %>\
+                      <% For Each item In items %><%= item %><% Next %><% Response.Write(\"a\" & \"b\") : x = 1 %>";
+        let migration = migrate(SourceSyntax::Erb, "index.asp", source);
+
+        let messages: Vec<_> = migration.diagnostics.iter().map(|diagnostic| diagnostic.message.clone()).collect();
+        assert_eq!(messages, vec!["page directives have no equivalent", "`Response.Write(\"a\" & \"b\") : x = 1` isn't Rust code"]);
+        assert!(migration.source.ends_with(
+            "?><?rs @if count > 1 ?>many<?rs @else ?>one<?rs @endif ?><?rs @for item in items ?><?= item ?><?rs @endfor ?>\
+             <?rs /* TODO(plt migrate): `Response.Write(\"a\" & \"b\") : x = 1` isn't Rust code: <% Response.Write(\"a\" & \"b\") : x = 1 %> */ ?>"
+        ));
+    }
+
+    #[test]
+    fn it_names_migrated_templates() {
+        assert_eq!(SourceSyntax::Tera.migrated_path(Path::new("pages/index.html")), Path::new("pages/index.plt"));
+        assert_eq!(SourceSyntax::Tera.migrated_path(Path::new("mail.txt")), Path::new("mail.txt.plt"));
+    }
+}
@@ -0,0 +1,266 @@
+use std::str::FromStr;
+use anyhow::bail;
+use crate::prelude::*;
+
+// Minification of the text parts of templates, applied before generating code so the rendered
+// output is smaller without any work at runtime. The content of `<pre>`, `<textarea>`,
+// `<script>` and `<style>` elements is left as it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Minify {
+    #[default]
+    Off,
+    // Removes whitespace between tags spanning several lines, e.g. the indentation between
+    // `</li>` and `<li>`, and HTML comments other than conditional ones. This can remove the
+    // space between inline elements written on their own lines.
+    Whitespace,
+    // Also collapses any other run of whitespace, in text and between attributes, into a single
+    // space.
+    Full,
+}
+
+impl FromStr for Minify {
+    type Err = anyhow::Error;
+
+    fn from_str(minify: &str) -> Result<Minify> {
+        match minify {
+            "off" => Ok(Minify::Off),
+            "whitespace" => Ok(Minify::Whitespace),
+            "full" => Ok(Minify::Full),
+            _ => bail!("unknown minification `{minify}`, expected `off`, `whitespace` or `full`"),
+        }
+    }
+}
+
+// Stands for output which isn't known during generation, e.g. of echoes, in the text the
+// template renders.
+const HOLE: char = '\u{0}';
+
+const RAW_ELEMENTS: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Keep,
+    Drop,
+    Space,
+}
+
+// `parts` with their text minified, keeping the parts in place so their indices still match
+// the spans of the template. Text on both sides of directives which render nothing, e.g.
+// `@if`, is minified as if it was written in a row.
+pub fn minify_parts(parts: &[Part], minify: Minify) -> Vec<Part> {
+    if minify == Minify::Off {
+        return parts.to_vec();
+    }
+
+    let mut stream = String::new();
+    let mut texts = Vec::new();
+    for (index, part) in parts.iter().enumerate() {
+        match part {
+            Part::Text(text) => {
+                texts.push((index, stream.len()..stream.len() + text.len()));
+                stream.push_str(text);
+            }
+            Part::Code(code) if !renders_output(code) => {}
+            _ => stream.push(HOLE),
+        }
+    }
+
+    let edits = edits(&stream, minify);
+    let mut parts = parts.to_vec();
+    for (index, range) in texts {
+        let text = stream[range.clone()]
+            .char_indices()
+            .filter_map(|(offset, c)| match edits[range.start + offset] {
+                Edit::Keep => Some(c),
+                Edit::Drop => None,
+                Edit::Space => Some(' '),
+            })
+            .collect();
+        parts[index] = Part::Text(text);
+    }
+
+    parts
+}
+
+// Whether code may write to the output, unlike directives opening and closing blocks and plain
+// Rust code, which is assumed to only compute values.
+fn renders_output(code: &str) -> bool {
+    match Directive::parse(code) {
+        Ok(Some(directive)) => matches!(
+            directive,
+            Directive::Include { .. }
+                | Directive::Extends { .. }
+                | Directive::Yield(_)
+                | Directive::Call { .. }
+                | Directive::EndCall
+                | Directive::Use { .. }
+                | Directive::Slot
+                | Directive::Translate { .. }
+                | Directive::TranslatePlural { .. }
+                | Directive::Asset(_)
+                | Directive::AssetSri(_)
+                | Directive::Nonce
+                | Directive::Csrf
+                | Directive::Json(_)
+                | Directive::Url { .. }
+                | Directive::Dump(_)
+                | Directive::Env(_)
+                | Directive::BuiltAt
+                | Directive::GitSha
+        ),
+        Ok(None) => false,
+        Err(_) => true,
+    }
+}
+
+// What happens to each byte of `stream`, only read at the start of chars.
+fn edits(stream: &str, minify: Minify) -> Vec<Edit> {
+    let mut edits = vec![Edit::Keep; stream.len()];
+    // Last char which is kept outside of whitespace, `None` at the start of the template.
+    let mut previous = None;
+    let mut raw_end: Option<String> = None;
+    let mut quote = None;
+    let mut in_tag = false;
+    let mut offset = 0;
+
+    while let Some(c) = stream[offset..].chars().next() {
+        let rest = &stream[offset..];
+
+        if let Some(end) = &raw_end {
+            if starts_with_ignore_case(rest, end) {
+                raw_end = None;
+            } else {
+                previous = Some(c);
+                offset += c.len_utf8();
+                continue;
+            }
+        }
+
+        if in_tag {
+            match (quote, c) {
+                (Some(open), _) if c == open => quote = None,
+                (Some(_), _) => {}
+                (None, '"' | '\'') => quote = Some(c),
+                (None, '>') => in_tag = false,
+                (None, _) if c.is_ascii_whitespace() && minify == Minify::Full => {
+                    let len = whitespace_len(rest);
+                    collapse(&mut edits[offset..offset + len]);
+                    offset += len;
+                    continue;
+                }
+                _ => {}
+            }
+            previous = Some(c);
+            offset += c.len_utf8();
+            continue;
+        }
+
+        if rest.starts_with("<!--") && !rest.starts_with("<!--[") {
+            // Comments with output of echoes inside are kept along with it.
+            if let Some(len) = rest[4..].find("-->").map(|end| end + 7).filter(|&len| !rest[..len].contains(HOLE)) {
+                edits[offset..offset + len].fill(Edit::Drop);
+                offset += len;
+                continue;
+            }
+        }
+
+        if c.is_ascii_whitespace() {
+            let len = whitespace_len(rest);
+            let next = rest[len..].chars().next();
+            let between_tags = matches!(previous, None | Some('>')) && matches!(next, None | Some('<'));
+            if between_tags && rest[..len].contains('\n') {
+                edits[offset..offset + len].fill(Edit::Drop);
+            } else if minify == Minify::Full {
+                collapse(&mut edits[offset..offset + len]);
+            }
+            offset += len;
+            continue;
+        }
+
+        if c == '<' {
+            if let Some(name) = RAW_ELEMENTS.into_iter().find(|name| opens_element(rest, name)) {
+                raw_end = Some(format!("</{name}"));
+            }
+            in_tag = rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/');
+        }
+        previous = Some(c);
+        offset += c.len_utf8();
+    }
+
+    edits
+}
+
+fn whitespace_len(text: &str) -> usize {
+    text.len() - text.trim_start_matches(|c: char| c.is_ascii_whitespace()).len()
+}
+
+// Replaces a run of whitespace with a single space.
+fn collapse(edits: &mut [Edit]) {
+    edits.fill(Edit::Drop);
+    edits[0] = Edit::Space;
+}
+
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.get(..prefix.len()).is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+fn opens_element(text: &str, name: &str) -> bool {
+    starts_with_ignore_case(&text[1..], name)
+        && text[1 + name.len()..].starts_with(|c: char| c.is_whitespace() || c == '>' || c == '/')
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    fn minify(parts: &[Part], minify: Minify) -> Vec<String> {
+        minify_parts(parts, minify).iter().map(|part| part.get_content().to_string()).collect()
+    }
+
+    fn text(text: &str) -> Part {
+        Part::Text(text.to_string())
+    }
+
+    #[test]
+    fn it_removes_whitespace_between_tags_and_comments() {
+        let parts = [
+            text("<ul>\n  <!-- items -->\n  "),
+            Part::Code(" @for item in items ".to_string()),
+            text("\n  <li title=\"a  b\">"),
+            Part::EchoCode(" item ".to_string()),
+            text("</li>\n  "),
+            Part::Code(" @endfor ".to_string()),
+            text("\n</ul>\n<p>a  <b>b</b>\n  c</p>\n"),
+        ];
+
+        assert_eq!(
+            minify(&parts, Minify::Whitespace),
+            vec!["<ul>", " @for item in items ", "<li title=\"a  b\">", " item ", "</li>", " @endfor ", "</ul><p>a  <b>b</b>\n  c</p>"]
+        );
+        assert_eq!(minify(&parts, Minify::Full)[6], "</ul><p>a <b>b</b> c</p>");
+        assert_eq!(minify(&parts, Minify::Off), parts.iter().map(|part| part.get_content().to_string()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn it_keeps_whitespace_next_to_output_and_inside_of_raw_elements() {
+        let parts = [
+            text("<p>\n  "),
+            Part::EchoCode(" name ".to_string()),
+            text("\n</p>\n<pre>\n  a\n</pre>\n<script>\n  // <!-- b -->\n</script>\n<!--[if IE]>c<![endif]-->"),
+        ];
+
+        assert_eq!(
+            minify(&parts, Minify::Full),
+            vec!["<p> ", " name ", " </p><pre>\n  a\n</pre><script>\n  // <!-- b -->\n</script><!--[if IE]>c<![endif]-->"]
+        );
+    }
+
+    #[test]
+    fn it_parses_minification_levels() {
+        assert_eq!("full".parse::<Minify>().unwrap(), Minify::Full);
+        assert_eq!(
+            "all".parse::<Minify>().unwrap_err().to_string(),
+            "unknown minification `all`, expected `off`, `whitespace` or `full`"
+        );
+    }
+}
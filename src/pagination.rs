@@ -0,0 +1,192 @@
+// Pagination of listings, rendering links to the pages around the current one:
+//
+//     <?rs let paginator = plt::pagination::Paginator::new(page, 20, total); ?>
+//     <?rs for post in posts.iter().skip(paginator.offset()).take(paginator.per_page()) { ?> ... <?rs } ?>
+//     <?= paginator.links(|page| format!("/posts?page={page}")) ?>
+//
+// Pages are numbered from 1. The links always include the first and last page, with a gap
+// between them and the window around the current page where pages are left out.
+use std::fmt;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Paginator {
+    page: usize,
+    per_page: usize,
+    total: usize,
+    window: usize,
+}
+
+// Item of the rendered page links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageItem {
+    Page(usize),
+    Current(usize),
+    Gap,
+}
+
+impl Paginator {
+    // Paginator of `total` items, on `page`, which is clamped to the existing pages.
+    pub fn new(page: usize, per_page: usize, total: usize) -> Paginator {
+        let per_page = per_page.max(1);
+        let pages = total.div_ceil(per_page).max(1);
+
+        Paginator { page: page.clamp(1, pages), per_page, total, window: 2 }
+    }
+
+    // Sets how many pages before and after the current one are linked.
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn page(&self) -> usize {
+        self.page
+    }
+
+    pub fn per_page(&self) -> usize {
+        self.per_page
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    // Number of pages, at least 1 even if there are no items.
+    pub fn pages(&self) -> usize {
+        self.total.div_ceil(self.per_page).max(1)
+    }
+
+    // Index of the first item on the current page.
+    pub fn offset(&self) -> usize {
+        (self.page - 1) * self.per_page
+    }
+
+    pub fn previous(&self) -> Option<usize> {
+        (self.page > 1).then(|| self.page - 1)
+    }
+
+    pub fn next(&self) -> Option<usize> {
+        (self.page < self.pages()).then(|| self.page + 1)
+    }
+
+    pub fn items(&self) -> Vec<PageItem> {
+        let pages = self.pages();
+        let start = self.page.saturating_sub(self.window).max(1);
+        let end = (self.page + self.window).min(pages);
+
+        let mut numbers = vec![1];
+        numbers.extend(start..=end);
+        numbers.push(pages);
+        numbers.dedup();
+
+        let mut items = Vec::new();
+        let mut last = 0;
+        for page in numbers {
+            // A single left out page is linked instead of being replaced by a gap.
+            if page == last + 2 {
+                items.push(PageItem::Page(last + 1));
+            } else if page > last + 2 {
+                items.push(PageItem::Gap);
+            }
+
+            items.push(if page == self.page { PageItem::Current(page) } else { PageItem::Page(page) });
+            last = page;
+        }
+
+        items
+    }
+
+    // Links to the pages, using `url` to build the URL of a page.
+    pub fn links<F: Fn(usize) -> String>(&self, url: F) -> PageLinks<'_, F> {
+        PageLinks { paginator: self, url }
+    }
+}
+
+// `<nav>` with the links to the pages of a `Paginator`.
+pub struct PageLinks<'a, F> {
+    paginator: &'a Paginator,
+    url: F,
+}
+
+impl<F: Fn(usize) -> String> PageLinks<'_, F> {
+    fn link(&self, page: usize, label: &str, rel: Option<&str>) -> String {
+        let rel = rel.map(|rel| format!(" rel=\"{rel}\"")).unwrap_or_default();
+        format!("<a href=\"{}\"{rel}>{label}</a>", EscapeUrl((self.url)(page)))
+    }
+}
+
+impl<F: Fn(usize) -> String> fmt::Display for PageLinks<'_, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut html = String::from("<nav class=\"pagination\" aria-label=\"Pagination\">");
+
+        match self.paginator.previous() {
+            Some(page) => html.push_str(&self.link(page, "&laquo; Previous", Some("prev"))),
+            None => html.push_str("<span class=\"disabled\">&laquo; Previous</span>"),
+        }
+
+        for item in self.paginator.items() {
+            match item {
+                PageItem::Page(page) => html.push_str(&self.link(page, &page.to_string(), None)),
+                PageItem::Current(page) => html.push_str(&format!("<span aria-current=\"page\">{page}</span>")),
+                PageItem::Gap => html.push_str("<span class=\"gap\">&hellip;</span>"),
+            }
+        }
+
+        match self.paginator.next() {
+            Some(page) => html.push_str(&self.link(page, "Next &raquo;", Some("next"))),
+            None => html.push_str("<span class=\"disabled\">Next &raquo;</span>"),
+        }
+
+        html.push_str("</nav>");
+        write!(f, "{}", Raw(html))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pagination::{PageItem, Paginator};
+    use crate::prelude::*;
+
+    #[test]
+    fn it_computes_pages() {
+        let paginator = Paginator::new(3, 10, 95);
+
+        assert_eq!(paginator.pages(), 10);
+        assert_eq!(paginator.offset(), 20);
+        assert_eq!((paginator.previous(), paginator.next()), (Some(2), Some(4)));
+        assert_eq!(Paginator::new(20, 10, 95).page(), 10);
+        assert_eq!(Paginator::new(0, 10, 0).pages(), 1);
+        assert_eq!(Paginator::new(1, 10, 0).next(), None);
+    }
+
+    #[test]
+    fn it_links_pages_around_the_current_one() {
+        use PageItem::*;
+
+        assert_eq!(
+            Paginator::new(10, 10, 200).items(),
+            vec![Page(1), Gap, Page(8), Page(9), Current(10), Page(11), Page(12), Gap, Page(20)]
+        );
+        assert_eq!(Paginator::new(4, 10, 60).items(), vec![Page(1), Page(2), Page(3), Current(4), Page(5), Page(6)]);
+        assert_eq!(Paginator::new(1, 10, 100).with_window(1).items(), vec![Current(1), Page(2), Gap, Page(10)]);
+        assert_eq!(Paginator::new(1, 10, 5).items(), vec![Current(1)]);
+    }
+
+    #[test]
+    fn it_renders_links() {
+        let paginator = Paginator::new(2, 10, 30);
+
+        assert_eq!(
+            escape_html(paginator.links(|page| format!("/posts?page={page}&sort=new"))),
+            "<nav class=\"pagination\" aria-label=\"Pagination\">\
+            <a href=\"/posts?page=1&amp;sort=new\" rel=\"prev\">&laquo; Previous</a>\
+            <a href=\"/posts?page=1&amp;sort=new\">1</a>\
+            <span aria-current=\"page\">2</span>\
+            <a href=\"/posts?page=3&amp;sort=new\">3</a>\
+            <a href=\"/posts?page=3&amp;sort=new\" rel=\"next\">Next &raquo;</a>\
+            </nav>"
+        );
+        assert!(Paginator::new(1, 10, 20).links(|_| "javascript:alert(1)".to_string()).to_string().contains("<a href=\"#\" rel=\"next\">"));
+    }
+}
@@ -0,0 +1,136 @@
+// Previews of templates edited in the browser, e.g. by admin panels or CMS-like tools letting
+// users write their own pages, rendered by the dynamic engine against sample JSON data:
+//
+//     let playground = plt::playground::Playground::new();
+//     match playground.compile_and_preview(&template_src, &sample_json) {
+//         Ok(preview) => respond(preview.html, preview.warnings.to_json()),
+//         Err(diagnostics) => respond_errors(diagnostics.to_json()),
+//     }
+//
+// Problems are returned as diagnostics pointing into the template, or into the sample data when
+// it isn't a JSON object, so editors can underline them. Templates are linted first, with the
+// lints about parameters allowed, as their variables come from the sample data rather than from
+// `@args`.
+use serde_json::Value;
+use crate::dynamic::{Engine, EngineOptions};
+use crate::lint::LintOptions;
+use crate::prelude::*;
+
+// Names the template and the sample data are reported under.
+pub const TEMPLATE_FILE: &str = "template.plt";
+pub const SAMPLE_FILE: &str = "sample.json";
+
+#[derive(Debug, Clone)]
+pub struct Playground {
+    pub engine: EngineOptions,
+    pub lints: LintOptions,
+}
+
+impl Default for Playground {
+    fn default() -> Self {
+        Self {
+            engine: EngineOptions { escaping: Escaping::Html, ..EngineOptions::default() },
+            lints: LintOptions::dynamic(),
+        }
+    }
+}
+
+// Rendered template, along with the warnings of its lints.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreviewHtml {
+    pub html: String,
+    pub warnings: Diagnostics,
+}
+
+impl Playground {
+    pub fn new() -> Playground {
+        Self::default()
+    }
+
+    // Lints `template_src` and renders it against `sample_json`, which has to be a JSON object.
+    // The errors come along with the warnings found before them.
+    pub fn compile_and_preview(&self, template_src: &str, sample_json: &str) -> Result<PreviewHtml, Diagnostics> {
+        let context = sample_context(sample_json)?;
+
+        let mut set = TemplateSet::new();
+        set.add_template(TEMPLATE_FILE, Vec::new(), template_src.to_string());
+        let options = GeneratorOptions { escaping: self.engine.escaping, lints: self.lints.clone(), ..GeneratorOptions::default() };
+
+        let mut diagnostics = set.lint(TEMPLATE_FILE, &options);
+        if diagnostics.has_errors() {
+            return Err(diagnostics);
+        }
+
+        match Engine::from_template_set(set, self.engine.clone()).render(TEMPLATE_FILE, &context) {
+            Ok(html) => Ok(PreviewHtml { html, warnings: diagnostics }),
+            Err(error) => {
+                diagnostics.push(Diagnostic::from_error(TEMPLATE_FILE, &error));
+                Err(diagnostics)
+            }
+        }
+    }
+}
+
+fn sample_context(sample_json: &str) -> Result<Value, Diagnostics> {
+    let context = match serde_json::from_str(sample_json) {
+        Ok(context) => context,
+        Err(error) => {
+            // serde_json reports 1-based lines and columns, with line 0 for errors without one.
+            let span = (error.line() > 0).then(|| {
+                let line_start: usize = sample_json.split_inclusive('\n').take(error.line() - 1).map(str::len).sum();
+                let line = &sample_json[line_start..];
+                let column = line.char_indices().nth(error.column().saturating_sub(1)).map_or(line.len(), |(offset, _)| offset);
+                Span::new(sample_json, line_start + column, line_start + column)
+            });
+            let message = error.to_string();
+            let message = message.split(" at line ").next().unwrap_or(&message);
+            return Err(Diagnostics::from_iter([Diagnostic::error(SAMPLE_FILE, span, "invalid-sample", message)]));
+        }
+    };
+
+    match context {
+        Value::Object(_) => Ok(context),
+        _ => {
+            let message = "the sample data has to be a JSON object";
+            Err(Diagnostics::from_iter([Diagnostic::error(SAMPLE_FILE, None, "invalid-sample", message)]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::playground::Playground;
+
+    #[test]
+    fn it_previews_templates() {
+        let preview = Playground::new().compile_and_preview("<p><?= name ?></p><img src=\"a.png\">", r#"{"name": "<Ann>"}"#).unwrap();
+
+        assert_eq!(preview.html, "<p>&lt;Ann&gt;</p><img src=\"a.png\">");
+        assert_eq!(preview.warnings.iter().map(|warning| warning.code.as_str()).collect::<Vec<_>>(), ["missing_alt"]);
+    }
+
+    #[test]
+    fn it_reports_problems_as_diagnostics() {
+        let playground = Playground::new();
+
+        let diagnostics = playground.compile_and_preview("<?= name ?>", "{\n  \"name\": }").unwrap_err();
+        assert_eq!(diagnostics.to_string(), "sample.json:2:11: expected value");
+
+        let diagnostics = playground.compile_and_preview("<?= name ?>", "[]").unwrap_err();
+        assert_eq!(diagnostics.to_string(), "sample.json: the sample data has to be a JSON object");
+
+        let diagnostics = playground.compile_and_preview("<?rs @if a ?>", "{}").unwrap_err();
+        assert!(diagnostics.has_errors());
+        assert!(diagnostics.iter().all(|diagnostic| diagnostic.file == "template.plt"), "{diagnostics}");
+    }
+
+    #[test]
+    fn it_rejects_build_constants() {
+        let playground = Playground::new();
+
+        for directive in ["@env \"HOME\"", "@git_sha", "@built_at"] {
+            let diagnostics = playground.compile_and_preview(&format!("<?rs {directive} ?>"), "{}").unwrap_err();
+            assert!(diagnostics.to_string().contains("isn't allowed unless `EngineOptions::build_constants` is set"), "{diagnostics}");
+        }
+    }
+}
@@ -0,0 +1,191 @@
+// Settings of the templates of a crate, kept in a `plt.toml` next to its `Cargo.toml` or in the
+// root of its workspace, so the CLI, build scripts and the proc macros share them:
+//
+//     template_dir = "templates"
+//     out_dir = "src/generated"
+//     escape = "html"
+//     naming = "file-name"
+//     search_path = ["emails=../shared/emails"]
+//
+//     [lints]
+//     long_text = "deny"
+//     unused_parameters = "allow"
+//
+// Settings are named like the options of `plt compile`, and paths are relative to the directory
+// of the `plt.toml`. Build scripts compile the templates it points at with
+// `plt::build::compile_project()`. Tags are always `<?rs ?>` and `<?= ?>`, so there's no setting
+// for delimiters.
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use anyhow::{bail, Context};
+use crate::build::BuildOptions;
+use crate::lint::Lint;
+use crate::prelude::*;
+
+pub const CONFIG_FILE_NAME: &str = "plt.toml";
+
+#[derive(Debug, Clone)]
+pub struct ProjectConfig {
+    // Path of the `plt.toml`.
+    pub file: PathBuf,
+    pub template_dir: PathBuf,
+    // Directory `plt compile` writes into when it's given no `--out-dir`. Build scripts write
+    // into cargo's `OUT_DIR` instead.
+    pub out_dir: Option<PathBuf>,
+    pub build: BuildOptions,
+}
+
+impl ProjectConfig {
+    // Config of the nearest `plt.toml` in `dir` or in one of its ancestors, if there is one.
+    pub fn find(dir: &Path) -> Result<Option<ProjectConfig>> {
+        let Some(file) = dir.ancestors().map(|dir| dir.join(CONFIG_FILE_NAME)).find(|file| file.is_file()) else {
+            return Ok(None);
+        };
+
+        Self::load(&file).map(Some)
+    }
+
+    pub fn load(file: &Path) -> Result<ProjectConfig> {
+        let source = std::fs::read_to_string(file).with_context(|| format!("failed to read `{}`", file.display()))?;
+
+        Self::parse(&source, file).with_context(|| format!("invalid `{}`", file.display()))
+    }
+
+    // Parses the source of the `plt.toml` under `file`, whose directory paths are relative to.
+    pub fn parse(source: &str, file: &Path) -> Result<ProjectConfig> {
+        let root = file.parent().unwrap_or(Path::new(""));
+        let table: toml::Table = source.parse()?;
+
+        let mut config = ProjectConfig {
+            file: file.to_path_buf(),
+            template_dir: root.join("templates"),
+            out_dir: None,
+            build: BuildOptions::default(),
+        };
+        let options = &mut config.build.generator;
+        for (key, value) in &table {
+            match key.as_str() {
+                "template_dir" => config.template_dir = root.join(string(key, value)?),
+                "out_dir" => config.out_dir = Some(root.join(string(key, value)?)),
+                "module_name" => config.build.module_name = string(key, value)?.to_string(),
+                "split_files" => config.build.split_files = boolean(key, value)?,
+                "module_files" => config.build.module_files = boolean(key, value)?,
+                "search_path" => {
+                    let Some(roots) = value.as_array() else {
+                        bail!("`{key}` has to be an array of `[NAMESPACE=]DIR` strings");
+                    };
+                    for template_root in roots {
+                        let template_root: TemplateRoot = parsed(key, template_root)?;
+                        config.build.search_path.push(TemplateRoot { dir: root.join(template_root.dir), ..template_root });
+                    }
+                }
+                "layout" => options.module_layout = parsed(key, value)?,
+                "name_mangling" => options.name_mangling = parsed(key, value)?,
+                "naming" => options.naming = parsed(key, value)?,
+                "fn_prefix" => options.function_prefix = string(key, value)?.to_string(),
+                "fn_suffix" => options.function_suffix = string(key, value)?.to_string(),
+                "visibility" => options.visibility = string(key, value)?.to_string(),
+                "escape" => options.escaping = parsed(key, value)?,
+                "formatter" => options.formatter = parsed(key, value)?,
+                "minify" => options.minify = parsed(key, value)?,
+                "trim_tag_newlines" => options.trim_tag_newlines = boolean(key, value)?,
+                "trailing_newline" => options.trailing_newline = parsed(key, value)?,
+                "struct_mode" => options.struct_mode = boolean(key, value)?,
+                "strict" => options.strict = boolean(key, value)?,
+                "max_depth" => options.max_depth = integer(key, value)?,
+                "max_text_len" => options.lints.max_text_len = integer(key, value)?,
+                "lints" => {
+                    let Some(levels) = value.as_table() else {
+                        bail!("`{key}` has to be a table of lints and their levels");
+                    };
+                    for (lint, level) in levels {
+                        let lint: Lint = lint.parse()?;
+                        options.lints.set(lint, parsed(&format!("lints.{lint}"), level)?);
+                    }
+                }
+                _ => bail!("unknown setting `{key}`"),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn string<'a>(key: &str, value: &'a toml::Value) -> Result<&'a str> {
+    value.as_str().with_context(|| format!("`{key}` has to be a string, found `{value}`"))
+}
+
+fn boolean(key: &str, value: &toml::Value) -> Result<bool> {
+    value.as_bool().with_context(|| format!("`{key}` has to be a boolean, found `{value}`"))
+}
+
+fn integer(key: &str, value: &toml::Value) -> Result<usize> {
+    value
+        .as_integer()
+        .and_then(|integer| usize::try_from(integer).ok())
+        .with_context(|| format!("`{key}` has to be a positive integer, found `{value}`"))
+}
+
+fn parsed<T: FromStr<Err = anyhow::Error>>(key: &str, value: &toml::Value) -> Result<T> {
+    string(key, value)?.parse().with_context(|| format!("invalid `{key}`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::lint::{Lint, LintLevel};
+    use crate::prelude::*;
+    use crate::project::ProjectConfig;
+
+    #[test]
+    fn it_parses_project_configs() {
+        let source = r#"
+            template_dir = "views"
+            out_dir = "src/generated"
+            escape = "html"
+            naming = "file-name"
+            trim_tag_newlines = true
+            search_path = ["emails=../shared/emails"]
+
+            [lints]
+            long_text = "deny"
+        "#;
+
+        let config = ProjectConfig::parse(source, Path::new("site/plt.toml")).unwrap();
+        let options = &config.build.generator;
+
+        assert_eq!(config.template_dir, Path::new("site/views"));
+        assert_eq!(config.out_dir.as_deref(), Some(Path::new("site/src/generated")));
+        assert_eq!(config.build.search_path, [TemplateRoot::namespaced("emails", "site/../shared/emails")]);
+        assert_eq!(options.escaping, Escaping::Html);
+        assert!(matches!(options.naming, NamingStrategy::FileName));
+        assert!(options.trim_tag_newlines);
+        assert_eq!(options.module_layout, ModuleLayout::Tree);
+        assert_eq!(options.lints.level(Lint::LongText), LintLevel::Deny);
+    }
+
+    #[test]
+    fn it_rejects_invalid_settings() {
+        let error = |source: &str| format!("{:#}", ProjectConfig::parse(source, Path::new("plt.toml")).unwrap_err());
+
+        assert_eq!(error("delimiters = [\"{{\", \"}}\"]"), "unknown setting `delimiters`");
+        assert_eq!(error("trim_tag_newlines = \"yes\""), "`trim_tag_newlines` has to be a boolean, found `\"yes\"`");
+        assert!(error("escape = \"rot13\"").starts_with("invalid `escape`: unknown escaping mode `rot13`"));
+        assert!(error("[lints]\nlong_text = \"loud\"").starts_with("invalid `lints.long_text`"));
+        assert!(error("escape = ").contains("TOML parse error"));
+    }
+
+    #[test]
+    fn it_finds_the_nearest_config() {
+        let dir = std::env::temp_dir().join(format!("plt-project-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("crates/site/src")).unwrap();
+        std::fs::write(dir.join("plt.toml"), "escape = \"xml\"").unwrap();
+
+        let config = ProjectConfig::find(&dir.join("crates/site/src")).unwrap().unwrap();
+        assert_eq!(config.file, dir.join("plt.toml"));
+        assert_eq!(config.template_dir, dir.join("templates"));
+        assert_eq!(config.build.generator.escaping, Escaping::Xml);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+// proptest strategies generating templates, along with invariants of parsing and rendering them,
+// for property tests of this crate and of applications' template pipelines:
+//
+//     proptest! {
+//         #[test]
+//         fn static_templates_render_their_text(template in plt::proptest::static_template()) {
+//             plt::proptest::check_static_render(&template, |source| my_engine.render_source(source))?;
+//         }
+//     }
+//
+// Generated templates are valid as far as parsing goes, the code inside of them isn't meant to
+// compile.
+use ::proptest::prelude::{any, prop, Strategy, TestCaseError};
+use ::proptest::{prop_assert, prop_assert_eq, prop_oneof};
+use crate::fuzz::{without_open_tags, TemplateBuilder};
+use crate::prelude::*;
+
+// Any text which doesn't open a tag.
+pub fn text() -> impl Strategy<Value = String> {
+    any::<String>().prop_map(|text| without_open_tags(&text))
+}
+
+// Code which can't end its tag early, wrapped in spaces so that it neither starts with `@` nor
+// touches the tags.
+pub fn code() -> impl Strategy<Value = String> {
+    "[a-z0-9_ .,;+*=()\\[\\]{}&!<>-]{1,24}".prop_map(|code| format!(" {} ", code.trim()))
+}
+
+fn expression() -> impl Strategy<Value = String> {
+    code().prop_map(|code| code.trim().to_string())
+}
+
+fn identifier() -> impl Strategy<Value = String> {
+    "[a-z][a-z0-9_]{0,8}"
+}
+
+pub fn directive() -> impl Strategy<Value = Directive> {
+    prop_oneof![
+        expression().prop_map(Directive::If),
+        expression().prop_map(Directive::ElseIf),
+        (identifier(), expression()).prop_map(|(pattern, iterable)| Directive::For { pattern, iterable }),
+        expression().prop_map(Directive::Match),
+        expression().prop_map(Directive::When),
+        identifier().prop_map(Directive::Block),
+        identifier().prop_map(Directive::Capture),
+        (identifier(), expression()).prop_map(|(name, value)| Directive::Let(format!("{name} = {value}"))),
+        identifier().prop_map(|name| Directive::Include { path: format!("{name}.plt"), args: String::new() }),
+        prop::option::of(identifier()).prop_map(Directive::Rescue),
+        prop::sample::select(vec![
+            Directive::Else,
+            Directive::EndIf,
+            Directive::EndFor,
+            Directive::EndMatch,
+            Directive::EndBlock,
+            Directive::EndCapture,
+            Directive::Try,
+            Directive::EndTry,
+        ]),
+    ]
+}
+
+pub fn node() -> impl Strategy<Value = Node> {
+    prop_oneof![
+        text().prop_map(Node::Text),
+        code().prop_map(Node::Code),
+        code().prop_map(Node::Echo),
+        directive().prop_map(Node::Directive),
+    ]
+}
+
+// Nodes which would change the nodes before them once written, e.g. text completing a `<?rs`
+// left by the text before, or which directives reject, e.g. a keyword as a name, are left out.
+fn build(nodes: Vec<Node>) -> TemplateBuilder {
+    nodes.into_iter().fold(TemplateBuilder::new(), |builder, node| {
+        let candidate = builder.clone().node(node);
+        match candidate.source() {
+            Ok(_) => candidate,
+            Err(_) => builder,
+        }
+    })
+}
+
+pub fn template() -> impl Strategy<Value = TemplateBuilder> {
+    prop::collection::vec(node(), 0..16).prop_map(build)
+}
+
+// Templates made of text and tags holding only whitespace, which render as their text.
+pub fn static_template() -> impl Strategy<Value = TemplateBuilder> {
+    let node = prop_oneof![3 => text().prop_map(Node::Text), 1 => "[ \t\n]{1,3}".prop_map(Node::Code)];
+
+    prop::collection::vec(node, 0..16).prop_map(build)
+}
+
+// Sources made of tags, string and comment delimiters and other text, most of them malformed.
+pub fn adversarial_source() -> impl Strategy<Value = String> {
+    let piece = prop_oneof![
+        prop::sample::select(vec!["<?rs", "<?=", "?>", "<?", "\"", "'", "//", "/*", "*/", "r#\"", "\"#", "\n", "@if", "@endif"])
+            .prop_map(str::to_string),
+        ".{0,4}",
+    ];
+
+    prop::collection::vec(piece, 0..24).prop_map(|pieces| pieces.concat())
+}
+
+// Checks that the parts of `source` joined with the tags between them give back `source`, i.e.
+// that parsing neither drops nor duplicates any of it.
+pub fn check_parts_rejoin(source: &str) -> Result<(), TestCaseError> {
+    let mut fsa = TextCodeFSA::new();
+    let parts = fsa.run(source.to_string()).clone();
+    let spans = fsa.spans().to_vec();
+    prop_assert_eq!(parts.len(), spans.len());
+
+    let mut rejoined = String::new();
+    let mut end = 0;
+    for (part, span) in parts.iter().zip(&spans) {
+        let between = source.get(end..span.start);
+        prop_assert!(between.is_some_and(is_tags), "`{:?}` between the parts of `{}` isn't made of tags", between, source);
+        rejoined.push_str(between.unwrap_or_default());
+        rejoined.push_str(part.get_content());
+        end = span.end;
+    }
+    let rest = source.get(end..);
+    prop_assert!(rest.is_some_and(is_tags), "`{:?}` after the parts of `{}` isn't made of tags", rest, source);
+    rejoined.push_str(rest.unwrap_or_default());
+
+    prop_assert_eq!(rejoined, source);
+    Ok(())
+}
+
+fn is_tags(mut text: &str) -> bool {
+    while !text.is_empty() {
+        let Some(tag) = ["<?rs", "<?=", "?>"].into_iter().find(|tag| text.starts_with(tag)) else {
+            return false;
+        };
+        text = &text[tag.len()..];
+    }
+
+    true
+}
+
+// Checks that the source of the template parses back to the nodes it was built from.
+pub fn check_round_trip(template: &TemplateBuilder) -> Result<(), TestCaseError> {
+    let source = template.source().map_err(|error| TestCaseError::fail(error.to_string()))?;
+
+    let mut set = TemplateSet::new();
+    set.add_template("index.plt", Vec::new(), source.clone());
+    let parsed = set.get("index.plt").map(ParsedTemplate::parse);
+    match parsed {
+        Some(Ok(parsed)) => prop_assert_eq!(parsed.nodes, template.nodes(), "`{}` parsed to different nodes", source),
+        _ => return Err(TestCaseError::fail(format!("`{source}` doesn't parse"))),
+    }
+
+    Ok(())
+}
+
+// Checks that `render` renders the source of a template made of text and whitespace as the text,
+// e.g. with a function compiling the source or rendering it with `plt::dynamic::Engine`. Other
+// templates are rejected.
+pub fn check_static_render(template: &TemplateBuilder, render: impl FnOnce(&str) -> Result<String>) -> Result<(), TestCaseError> {
+    let mut text = String::new();
+    for node in template.nodes() {
+        match node {
+            Node::Text(content) => text.push_str(content),
+            Node::Code(code) if code.trim().is_empty() => {}
+            _ => return Err(TestCaseError::reject("the template isn't static")),
+        }
+    }
+    let source = template.source().map_err(|error| TestCaseError::fail(error.to_string()))?;
+
+    let output = render(&source).map_err(|error| TestCaseError::fail(format!("`{source}` failed to render: {error:#}")))?;
+    prop_assert_eq!(output, text);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ::proptest::prelude::*;
+    use crate::proptest::{adversarial_source, check_parts_rejoin, check_round_trip, template};
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn it_rejoins_parts_to_the_source(source in adversarial_source()) {
+            check_parts_rejoin(&source)?;
+        }
+
+        #[test]
+        fn it_parses_generated_templates_back_the_same(template in template()) {
+            check_parts_rejoin(&template.source().unwrap())?;
+            check_round_trip(&template)?;
+        }
+
+        #[cfg(feature = "dynamic")]
+        #[test]
+        fn it_renders_static_templates_as_their_text(template in crate::proptest::static_template()) {
+            crate::proptest::check_static_render(&template, |source| {
+                let mut engine = crate::dynamic::Engine::new();
+                engine.add_template("index.plt", source);
+                engine.render("index.plt", &serde_json::json!({}))
+            })?;
+        }
+    }
+}
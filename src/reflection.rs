@@ -0,0 +1,374 @@
+// Metadata about the generated templates, with `GeneratorOptions::metadata`, e.g. to list them
+// in an admin page or to check at startup that the ones an application needs exist:
+//
+//     for template in templates::ALL_TEMPLATES {
+//         println!("{} renders {} taking {:?}", template.name, template.path, template.params);
+//     }
+//
+// Each function comes with a `<NAME>_META` const, e.g. `PARTIALS_HEADER_META` for
+// `partials_header`, and the root module with `ALL_TEMPLATES` listing all of them.
+//
+// With the `json` feature, `TemplateMeta::validate_context` checks JSON payloads against the
+// arguments of templates before rendering them, e.g. when the context comes over the wire:
+//
+//     let errors = templates::EMAILS_WELCOME_META.validate_context(&payload);
+//
+// Types of arguments are recognized by their names, e.g. `Vec<T>` has to be an array. Values of
+// other types, e.g. of the application's structs, only have to be present.
+use std::fmt;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TemplateMeta {
+    // Name of the function rendering the template.
+    pub name: &'static str,
+    // Path of the template, e.g. `partials/header.plt`.
+    pub path: &'static str,
+    pub params: &'static [TemplateParam],
+    // Blocks the template renders when used as a layout.
+    pub blocks: &'static [&'static str],
+    // Paths of the templates included by this one.
+    pub includes: &'static [&'static str],
+}
+
+// Argument declared by `@args`, e.g. `title` of type `&str`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TemplateParam {
+    pub name: &'static str,
+    pub ty: &'static str,
+}
+
+// Problem of a JSON context of a template, found by `TemplateMeta::validate_context`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextError {
+    // Path of the value in the context, e.g. `items[1].name`, empty for the context itself.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.path.is_empty() {
+            true => f.write_str(&self.message),
+            false => write!(f, "`{}`: {}", self.path, self.message),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl TemplateMeta {
+    // Checks that `context` is a JSON object with a field of a compatible shape for each argument
+    // of the template, except for the ones provided by the application, like the nonce of `@nonce`.
+    // Fields of `Option`s can be left out.
+    pub fn validate_context(&self, context: &serde_json::Value) -> Vec<ContextError> {
+        let mut errors = Vec::new();
+        let Some(fields) = context.as_object() else {
+            let message = format!("expected an object, found {}", json_kind(context));
+            return vec![ContextError { path: String::new(), message }];
+        };
+
+        for param in self.params.iter().filter(|param| !crate::dispatch::PROVIDED_ARGS.contains(&param.name)) {
+            // Types which don't parse, e.g. `impl Display`, accept any value.
+            let Ok(ty) = syn::parse_str::<syn::Type>(param.ty) else {
+                continue;
+            };
+
+            match fields.get(param.name) {
+                Some(value) => check_value(&ty, value, param.name, &mut errors),
+                None if generic_type(&ty, "Option").is_some() => {}
+                None => errors.push(ContextError { path: param.name.to_string(), message: format!("missing argument of type `{}`", param.ty) }),
+            }
+        }
+
+        errors
+    }
+}
+
+// Checks that `value` has the shape of a value of type `ty` serialized to JSON.
+#[cfg(feature = "json")]
+fn check_value(ty: &syn::Type, value: &serde_json::Value, path: &str, errors: &mut Vec<ContextError>) {
+    use quote::ToTokens;
+    use serde_json::Value;
+
+    let mut expect = |expected: &str| {
+        let ty = compact_type(&ty.to_token_stream().to_string());
+        let message = format!("expected {expected} for `{ty}`, found {}", json_kind(value));
+        errors.push(ContextError { path: path.to_string(), message });
+    };
+
+    match ty {
+        syn::Type::Reference(reference) => check_value(&reference.elem, value, path, errors),
+        syn::Type::Paren(paren) => check_value(&paren.elem, value, path, errors),
+        syn::Type::Group(group) => check_value(&group.elem, value, path, errors),
+        syn::Type::Slice(syn::TypeSlice { elem, .. }) | syn::Type::Array(syn::TypeArray { elem, .. }) => match value {
+            Value::Array(items) => check_items(elem, items, path, errors),
+            _ => expect("an array"),
+        },
+        syn::Type::Tuple(tuple) => match value {
+            Value::Null if tuple.elems.is_empty() => {}
+            Value::Array(items) if items.len() == tuple.elems.len() => {
+                for (index, (ty, item)) in tuple.elems.iter().zip(items).enumerate() {
+                    check_value(ty, item, &format!("{path}[{index}]"), errors);
+                }
+            }
+            _ if tuple.elems.is_empty() => expect("null"),
+            _ => expect(&format!("an array of {} items", tuple.elems.len())),
+        },
+        syn::Type::Path(type_path) => {
+            let Some(segment) = type_path.path.segments.last() else {
+                return;
+            };
+            let args: Vec<&syn::Type> = match &segment.arguments {
+                syn::PathArguments::AngleBracketed(args) => args
+                    .args
+                    .iter()
+                    .filter_map(|arg| match arg {
+                        syn::GenericArgument::Type(ty) => Some(ty),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            };
+
+            match (segment.ident.to_string().as_str(), args.as_slice()) {
+                ("Option", [inner]) => {
+                    if !value.is_null() {
+                        check_value(inner, value, path, errors);
+                    }
+                }
+                ("Box" | "Rc" | "Arc" | "Cow", [inner]) => check_value(inner, value, path, errors),
+                ("str" | "String", _) => {
+                    if !value.is_string() {
+                        expect("a string");
+                    }
+                }
+                ("char", _) => {
+                    if value.as_str().is_none_or(|text| text.chars().count() != 1) {
+                        expect("a string of one character");
+                    }
+                }
+                ("bool", _) => {
+                    if !value.is_boolean() {
+                        expect("a boolean");
+                    }
+                }
+                ("f32" | "f64", _) => {
+                    if !value.is_number() {
+                        expect("a number");
+                    }
+                }
+                ("Vec" | "VecDeque" | "HashSet" | "BTreeSet" | "BinaryHeap", [inner]) => match value {
+                    Value::Array(items) => check_items(inner, items, path, errors),
+                    _ => expect("an array"),
+                },
+                ("HashMap" | "BTreeMap", [_, inner]) => match value {
+                    Value::Object(fields) => {
+                        for (key, field) in fields {
+                            check_value(inner, field, &format!("{path}.{key}"), errors);
+                        }
+                    }
+                    _ => expect("an object"),
+                },
+                (name, _) => {
+                    let Some((min, max)) = integer_range(name) else {
+                        return;
+                    };
+                    let integer = value.as_i64().map(i128::from).or_else(|| value.as_u64().map(i128::from));
+                    if !integer.is_some_and(|integer| (min..=max).contains(&integer)) {
+                        expect(&format!("an integer between {min} and {max}"));
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(feature = "json")]
+fn check_items(ty: &syn::Type, items: &[serde_json::Value], path: &str, errors: &mut Vec<ContextError>) {
+    for (index, item) in items.iter().enumerate() {
+        check_value(ty, item, &format!("{path}[{index}]"), errors);
+    }
+}
+
+// Argument of `ty` if it's e.g. `Option<T>` or `&Option<T>` for `name` `Option`.
+#[cfg(feature = "json")]
+fn generic_type<'a>(ty: &'a syn::Type, name: &str) -> Option<&'a syn::Type> {
+    let type_path = match ty {
+        syn::Type::Reference(reference) => return generic_type(&reference.elem, name),
+        syn::Type::Path(type_path) => type_path,
+        _ => return None,
+    };
+    let segment = type_path.path.segments.last().filter(|segment| segment.ident == name)?;
+    let syn::PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+
+    args.args.iter().find_map(|arg| match arg {
+        syn::GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    })
+}
+
+// Smallest and largest values of the integer type `name`, clamped to the ones JSON numbers
+// parsed by serde_json can hold.
+#[cfg(feature = "json")]
+fn integer_range(name: &str) -> Option<(i128, i128)> {
+    let range = match name {
+        "i8" => (i8::MIN.into(), i8::MAX.into()),
+        "i16" => (i16::MIN.into(), i16::MAX.into()),
+        "i32" => (i32::MIN.into(), i32::MAX.into()),
+        "u8" => (0, u8::MAX.into()),
+        "u16" => (0, u16::MAX.into()),
+        "u32" => (0, u32::MAX.into()),
+        "i64" | "i128" | "isize" => (i64::MIN.into(), i64::MAX.into()),
+        "u64" | "u128" | "usize" => (0, u64::MAX.into()),
+        _ => return None,
+    };
+
+    Some(range)
+}
+
+#[cfg(feature = "json")]
+fn json_kind(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "a boolean",
+        serde_json::Value::Number(_) => "a number",
+        serde_json::Value::String(_) => "a string",
+        serde_json::Value::Array(_) => "an array",
+        serde_json::Value::Object(_) => "an object",
+    }
+}
+
+// Name of the const holding the metadata of the function `fn_name`.
+pub fn meta_const_name(fn_name: &str) -> String {
+    format!("{}_META", fn_name.to_uppercase())
+}
+
+// Code of the const holding the metadata of `template`, rendered by the function `fn_name`.
+pub fn generate_template_meta(template: &Template, fn_name: &str, options: &GeneratorOptions) -> Result<String> {
+    let params: Vec<String> = template
+        .args
+        .iter()
+        .map(|arg| {
+            let (name, ty) = arg.split_once(':').unwrap_or((arg, ""));
+            format!("plt::reflection::TemplateParam {{ name: {:?}, ty: {:?} }}", name.trim(), compact_type(ty))
+        })
+        .collect();
+
+    Ok(format!(
+        "{} const {}: plt::reflection::TemplateMeta = plt::reflection::TemplateMeta {{\nname: {fn_name:?},\npath: {:?},\nparams: &[{}],\nblocks: &{:?},\nincludes: &{:?},\n}};",
+        options.visibility,
+        meta_const_name(fn_name),
+        template.path,
+        params.join(", "),
+        template.blocks()?,
+        template.includes()?,
+    ))
+}
+
+// `ty` without the spaces left between tokens by `@args`, e.g. `&str` for `& str`. Spaces are only
+// kept between words, after lifetimes and after commas, like in `impl Display`, `&'a [u8]` or
+// `HashMap<&'a str, u8>`.
+pub(crate) fn compact_type(ty: &str) -> String {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+
+    let mut compact = String::new();
+    let mut previous = "";
+    for token in ty.split_whitespace() {
+        let after_lifetime = previous.starts_with('\'') && !token.starts_with(['>', ',', ')']);
+        if after_lifetime || previous.ends_with(',') || (previous.ends_with(is_word_char) && token.starts_with(is_word_char)) {
+            compact.push(' ');
+        }
+        compact.push_str(token);
+        previous = token;
+    }
+
+    compact
+}
+
+// Code of `ALL_TEMPLATES`, listing the metadata of all `functions`.
+pub fn generate_all_templates(functions: &[GeneratedFunction], options: &GeneratorOptions) -> String {
+    let mut code = format!("{} const ALL_TEMPLATES: &[plt::reflection::TemplateMeta] = &[\n", options.visibility);
+    for function in functions {
+        let mut segments = function.module_path.clone();
+        // Templates in their own modules are reached through the re-exports of their directories.
+        if options.module_layout == ModuleLayout::Modules {
+            segments.pop();
+        }
+        segments.push(meta_const_name(&function.name));
+        code.push_str(&segments.join("::"));
+        code.push_str(",\n");
+    }
+    code.push_str("];\n");
+
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use crate::reflection::compact_type;
+
+    #[test]
+    fn it_generates_metadata_of_templates() {
+        let mut set = TemplateSet::new();
+        set.add_template("layout.plt", vec!["title: &str".to_string()], "<?rs @include \"partials/header.plt\" ?><?rs @yield content ?>".to_string());
+        set.add_template("partials/header.plt", Vec::new(), "<header></header>".to_string());
+        let options = GeneratorOptions { module_layout: ModuleLayout::Modules, metadata: true, ..GeneratorOptions::default() };
+
+        let code = set.generate_module("templates", &options).unwrap().split_whitespace().collect::<Vec<_>>().join(" ");
+        assert!(code.contains(
+            "pub const LAYOUT_META: plt::reflection::TemplateMeta = plt::reflection::TemplateMeta { name: \"layout\", path: \"layout.plt\", \
+             params: &[ plt::reflection::TemplateParam { name: \"title\", ty: \"&str\", }, ], blocks: &[\"content\"], includes: &[\"partials/header.plt\"], };"
+        ));
+        assert!(code.contains("pub const ALL_TEMPLATES: &[plt::reflection::TemplateMeta] = &[ LAYOUT_META, partials::HEADER_META, ];"));
+        syn::parse_file(&code).unwrap();
+
+        assert!(!set.generate_module("templates", &GeneratorOptions::default()).unwrap().contains("_META"));
+    }
+
+    #[test]
+    fn it_compacts_argument_types() {
+        assert_eq!(compact_type(" & 'a [ Item ] "), "&'a [Item]");
+        assert_eq!(compact_type("& dyn Fn (u8) -> String"), "&dyn Fn(u8)->String");
+        assert_eq!(compact_type("HashMap < & 'a str , Cow < 'a , str > >"), "HashMap<&'a str, Cow<'a, str>>");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn it_validates_contexts_against_the_arguments() {
+        use crate::reflection::{TemplateMeta, TemplateParam};
+
+        const META: TemplateMeta = TemplateMeta {
+            name: "index",
+            path: "index.plt",
+            params: &[
+                TemplateParam { name: "title", ty: "&str" },
+                TemplateParam { name: "items", ty: "&[(String, u8)]" },
+                TemplateParam { name: "scores", ty: "&BTreeMap<String, f64>" },
+                TemplateParam { name: "subtitle", ty: "Option<&str>" },
+                TemplateParam { name: "user", ty: "&User" },
+                TemplateParam { name: "csp_nonce", ty: "&str" },
+            ],
+            blocks: &[],
+            includes: &[],
+        };
+
+        let context = serde_json::json!({ "title": "Home", "items": [["a", 1]], "scores": { "ann": 1.5 }, "user": { "name": "Ann" } });
+        assert_eq!(META.validate_context(&context), Vec::new());
+
+        let context = serde_json::json!({ "items": [["a", 300], ["b"]], "scores": { "ann": "high" }, "subtitle": 1, "user": null });
+        let errors = META.validate_context(&context).iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(errors, [
+            "`title`: missing argument of type `&str`",
+            "`items[0][1]`: expected an integer between 0 and 255 for `u8`, found a number",
+            "`items[1]`: expected an array of 2 items for `(String, u8)`, found an array",
+            "`scores.ann`: expected a number for `f64`, found a string",
+            "`subtitle`: expected a string for `str`, found a number",
+        ]);
+
+        assert_eq!(META.validate_context(&serde_json::json!([]))[0].to_string(), "expected an object, found an array");
+    }
+}
@@ -0,0 +1,42 @@
+// Runtime support of `render_by_name`, generated with `GeneratorOptions::registry` for
+// applications picking templates at runtime, e.g. plugins rendering templates named in their
+// configuration:
+//
+//     let html = templates::render_by_name(&page.template, &serde_json::to_value(&page.context)?)?;
+use anyhow::{anyhow, Context};
+use serde::de::DeserializeOwned;
+use crate::prelude::*;
+
+pub use serde_json::Value;
+
+// The argument `name` of the template under `path`, deserialized from the field of the context
+// with the same name. Missing fields are deserialized from `null`, so `Option`s can be left out.
+pub fn arg<T: DeserializeOwned>(path: &str, context: &Value, name: &str) -> Result<T> {
+    let value = context.get(name).cloned().unwrap_or(Value::Null);
+
+    serde_json::from_value(value).with_context(|| format!("invalid argument `{name}` of template `{path}`"))
+}
+
+// Error of `render_by_name` for templates which aren't part of the registry.
+pub fn unknown_template(name: &str) -> anyhow::Error {
+    anyhow!("template `{name}` is not part of the registry")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::registry::arg;
+
+    #[test]
+    fn it_deserializes_arguments_from_the_context() {
+        let context = json!({ "title": "Home", "ids": [1, 2] });
+
+        assert_eq!(arg::<String>("index.plt", &context, "title").unwrap(), "Home");
+        assert_eq!(arg::<Vec<u32>>("index.plt", &context, "ids").unwrap(), vec![1, 2]);
+        assert_eq!(arg::<Option<String>>("index.plt", &context, "subtitle").unwrap(), None);
+        assert_eq!(
+            format!("{:#}", arg::<u32>("index.plt", &context, "title").unwrap_err()),
+            "invalid argument `title` of template `index.plt`: invalid type: string \"Home\", expected u32"
+        );
+    }
+}
@@ -0,0 +1,155 @@
+// Statistics of generating a `TemplateSet`, e.g. for finding the templates which are slow to
+// generate or produce large functions, as printed by `plt compile --stats`.
+use std::fmt;
+use std::time::Duration;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateStats {
+    pub path: String,
+    // Bytes of the text parts, which are written as they are.
+    pub text_bytes: usize,
+    pub code_parts: usize,
+    pub echo_parts: usize,
+    // Bytes of the formatted generated code.
+    pub code_bytes: usize,
+    // Time spent splitting the template into parts when it was added to the set.
+    pub parse_time: Duration,
+    pub generate_time: Duration,
+    pub format_time: Duration,
+}
+
+impl TemplateStats {
+    pub fn total_time(&self) -> Duration {
+        self.parse_time + self.generate_time + self.format_time
+    }
+}
+
+// Functions generated for all templates of a `TemplateSet`, along with their statistics, both
+// ordered by template path.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GenerationReport {
+    pub functions: Vec<GeneratedFunction>,
+    pub templates: Vec<TemplateStats>,
+    // Lints reported as warnings by the build helpers, which don't fail the build.
+    pub warnings: Diagnostics,
+}
+
+impl GenerationReport {
+    pub fn new() -> GenerationReport {
+        Self::default()
+    }
+
+    pub fn push(&mut self, function: GeneratedFunction, stats: TemplateStats) {
+        self.functions.push(function);
+        self.templates.push(stats);
+    }
+
+    pub fn text_bytes(&self) -> usize {
+        self.templates.iter().map(|stats| stats.text_bytes).sum()
+    }
+
+    pub fn code_bytes(&self) -> usize {
+        self.templates.iter().map(|stats| stats.code_bytes).sum()
+    }
+
+    pub fn total_time(&self) -> Duration {
+        self.templates.iter().map(TemplateStats::total_time).sum()
+    }
+
+    // Templates which took the longest to generate, slowest first.
+    pub fn slowest(&self, count: usize) -> Vec<&TemplateStats> {
+        let mut templates: Vec<_> = self.templates.iter().collect();
+        templates.sort_by_key(|stats| std::cmp::Reverse(stats.total_time()));
+        templates.truncate(count);
+
+        templates
+    }
+}
+
+// Notified while the templates of a directory are generated, e.g. to show the progress of big
+// sets or the templates which are slow to generate. With the `parallel` feature templates are
+// generated on several threads, so the calls come from all of them in any order.
+pub trait GenerationObserver: Sync {
+    // Called before generating `templates` templates.
+    fn started(&self, _templates: usize) {}
+
+    // Called after generating the template under `path`, with its statistics unless it failed.
+    fn template_generated(&self, _path: &str, _stats: Option<&TemplateStats>) {}
+
+    // Called after generating all templates, with the report of those which didn't fail.
+    fn finished(&self, _report: &GenerationReport) {}
+}
+
+// Observer ignoring the progress.
+impl GenerationObserver for () {}
+
+impl fmt::Display for GenerationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.templates.iter().map(|stats| stats.path.len()).max().unwrap_or(0).max("template".len());
+
+        writeln!(
+            f,
+            "{:width$} {:>10} {:>6} {:>6} {:>10} {:>10} {:>10} {:>10}",
+            "template", "text", "code", "echo", "generated", "parse", "generate", "format"
+        )?;
+        for stats in &self.templates {
+            writeln!(
+                f,
+                "{:width$} {:>10} {:>6} {:>6} {:>10} {:>10} {:>10} {:>10}",
+                stats.path,
+                stats.text_bytes,
+                stats.code_parts,
+                stats.echo_parts,
+                stats.code_bytes,
+                format!("{:.2?}", stats.parse_time),
+                format!("{:.2?}", stats.generate_time),
+                format!("{:.2?}", stats.format_time),
+            )?;
+        }
+
+        write!(
+            f,
+            "{} templates, {} bytes of text, {} bytes of generated code in {:.2?}",
+            self.templates.len(),
+            self.text_bytes(),
+            self.code_bytes(),
+            self.total_time()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::prelude::*;
+
+    fn stats(path: &str, millis: u64) -> TemplateStats {
+        TemplateStats {
+            path: path.to_string(),
+            text_bytes: 10,
+            code_parts: 2,
+            echo_parts: 1,
+            code_bytes: 100,
+            parse_time: Duration::ZERO,
+            generate_time: Duration::from_millis(millis),
+            format_time: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn it_sums_up_templates() {
+        let report = GenerationReport { templates: vec![stats("a.plt", 1), stats("b.plt", 3)], ..GenerationReport::default() };
+
+        assert_eq!((report.text_bytes(), report.code_bytes()), (20, 200));
+        assert_eq!(report.total_time(), Duration::from_millis(4));
+        assert_eq!(report.slowest(1)[0].path, "b.plt");
+        assert_eq!(
+            report.to_string(),
+            "template       text   code   echo  generated      parse   generate     format\n\
+             a.plt            10      2      1        100     0.00ns     1.00ms     0.00ns\n\
+             b.plt            10      2      1        100     0.00ns     3.00ms     0.00ns\n\
+             2 templates, 20 bytes of text, 200 bytes of generated code in 4.00ms"
+        );
+    }
+}
@@ -0,0 +1,143 @@
+// URLs built by the application's router, rendered by `@url`:
+//
+//     <a href="<?rs @url posts.show(id = post.id) ?>"><?= post.title ?></a>
+//
+// Templates using `@url` take the router as their `routes: &dyn plt::routes::UrlFor` argument,
+// unless they already declare it. Applications can implement `UrlFor` on top of their own
+// router, or register the paths they route, e.g. for axum, with `Routes`:
+//
+//     let routes = Routes::new().route("posts.show", "/posts/{id}");
+//     let app = Router::new().route(routes.path("posts.show")?, get(show_post));
+use std::collections::BTreeMap;
+use std::fmt;
+use anyhow::{anyhow, bail};
+use crate::prelude::*;
+
+// Name of the argument holding the router in templates using `@url`.
+pub const ROUTES_ARG: &str = "routes";
+
+pub trait UrlFor: Sync {
+    // URL of the named route, with `params` filling in its path parameters.
+    fn url_for(&self, route: &str, params: &[(&str, &dyn fmt::Display)]) -> Result<String>;
+}
+
+impl<T: UrlFor + ?Sized> UrlFor for &T {
+    fn url_for(&self, route: &str, params: &[(&str, &dyn fmt::Display)]) -> Result<String> {
+        (**self).url_for(route, params)
+    }
+}
+
+// Named route paths with `{param}` or `:param` segments, as used by axum and most routers.
+// Parameters which don't appear in the path are appended as the query string.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Routes {
+    paths: BTreeMap<String, String>,
+}
+
+impl Routes {
+    pub fn new() -> Routes {
+        Self::default()
+    }
+
+    pub fn route(mut self, name: impl Into<String>, path: impl Into<String>) -> Self {
+        self.paths.insert(name.into(), path.into());
+        self
+    }
+
+    pub fn path(&self, route: &str) -> Result<&str> {
+        self.paths
+            .get(route)
+            .map(String::as_str)
+            .ok_or_else(|| anyhow!("unknown route `{route}`"))
+    }
+}
+
+impl UrlFor for Routes {
+    fn url_for(&self, route: &str, params: &[(&str, &dyn fmt::Display)]) -> Result<String> {
+        let path = self.path(route)?;
+        let param = |name: &str| params.iter().find(|(param, _)| *param == name).map(|(_, value)| value);
+
+        let mut url = String::new();
+        let mut used = Vec::new();
+        for (index, segment) in path.split('/').enumerate() {
+            if index > 0 {
+                url.push('/');
+            }
+
+            let name = segment
+                .strip_prefix('{')
+                .and_then(|segment| segment.strip_suffix('}'))
+                .or_else(|| segment.strip_prefix(':'));
+            let Some(name) = name else {
+                url.push_str(segment);
+                continue;
+            };
+
+            // Wildcards, e.g. `{*path}`, keep the slashes of their value.
+            let (name, wildcard) = match name.strip_prefix('*') {
+                Some(name) => (name, true),
+                None => (name, false),
+            };
+            let Some(value) = param(name) else {
+                bail!("route `{route}` requires the parameter `{name}`");
+            };
+
+            if wildcard {
+                let segments: Vec<String> = value
+                    .to_string()
+                    .split('/')
+                    .map(|segment| EscapeUrlComponent(segment).to_string())
+                    .collect();
+                url.push_str(&segments.join("/"));
+            } else {
+                url.push_str(&EscapeUrlComponent(value).to_string());
+            }
+            used.push(name);
+        }
+
+        let query: Vec<String> = params
+            .iter()
+            .filter(|(name, _)| !used.contains(name))
+            .map(|(name, value)| format!("{}={}", EscapeUrlComponent(name), EscapeUrlComponent(value)))
+            .collect();
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&query.join("&"));
+        }
+
+        Ok(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::{Routes, UrlFor};
+
+    #[test]
+    fn it_fills_in_path_parameters() {
+        let routes = Routes::new()
+            .route("posts.show", "/posts/{id}/comments/:comment")
+            .route("files", "/files/{*path}");
+
+        assert_eq!(routes.url_for("posts.show", &[("id", &7), ("comment", &"a b/c")]).unwrap(), "/posts/7/comments/a%20b%2Fc");
+        assert_eq!(routes.url_for("files", &[("path", &"docs/a&b.md")]).unwrap(), "/files/docs/a%26b.md");
+    }
+
+    #[test]
+    fn it_appends_other_parameters_to_the_query() {
+        let routes = Routes::new().route("posts", "/posts");
+
+        assert_eq!(routes.url_for("posts", &[("page", &2), ("q", &"a&b")]).unwrap(), "/posts?page=2&q=a%26b");
+    }
+
+    #[test]
+    fn it_rejects_unknown_routes_and_missing_parameters() {
+        let routes = Routes::new().route("posts.show", "/posts/{id}");
+
+        assert_eq!(routes.url_for("users", &[]).unwrap_err().to_string(), "unknown route `users`");
+        assert_eq!(
+            routes.url_for("posts.show", &[]).unwrap_err().to_string(),
+            "route `posts.show` requires the parameter `id`"
+        );
+    }
+}
@@ -0,0 +1,182 @@
+// Caching of rendered fragments, used by `@cache`:
+//
+//     <?rs @cache("sidebar", ttl = 60) ?>
+//         <?rs @include "partials/sidebar.plt" ?>
+//     <?rs @endcache ?>
+//
+// Templates using `@cache` take the cache as their
+// `fragment_cache: &dyn plt::runtime::FragmentCache` argument, unless they already declare it.
+// Keys are shared by all templates rendering into the same cache, so they should include
+// whatever the fragment depends on, e.g. `@cache(format!("cart-{}", user.id))`.
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use anyhow::bail;
+use crate::prelude::*;
+
+// Name of the argument holding the cache in templates using `@cache`.
+pub const FRAGMENT_CACHE_ARG: &str = "fragment_cache";
+
+pub trait FragmentCache: Sync {
+    fn get(&self, key: &str) -> Option<String>;
+
+    // Stores the fragment, to be dropped after `ttl` if there is one.
+    fn set(&self, key: &str, fragment: String, ttl: Option<Duration>);
+}
+
+impl<T: FragmentCache + ?Sized> FragmentCache for &T {
+    fn get(&self, key: &str) -> Option<String> {
+        (**self).get(key)
+    }
+
+    fn set(&self, key: &str, fragment: String, ttl: Option<Duration>) {
+        (**self).set(key, fragment, ttl)
+    }
+}
+
+// Cache keeping the fragments in memory, dropping expired ones when they're read.
+#[derive(Debug, Default)]
+pub struct MemoryFragmentCache {
+    // Fragments by key, with the time they expire at.
+    fragments: Mutex<HashMap<String, (String, Option<Instant>)>>,
+}
+
+impl MemoryFragmentCache {
+    pub fn new() -> MemoryFragmentCache {
+        Self::default()
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.fragments.lock().unwrap().remove(key);
+    }
+
+    pub fn clear(&self) {
+        self.fragments.lock().unwrap().clear();
+    }
+}
+
+impl FragmentCache for MemoryFragmentCache {
+    fn get(&self, key: &str) -> Option<String> {
+        let mut fragments = self.fragments.lock().unwrap();
+        let (fragment, expires_at) = fragments.get(key)?;
+
+        if expires_at.is_some_and(|expires_at| expires_at <= Instant::now()) {
+            fragments.remove(key);
+            return None;
+        }
+
+        Some(fragment.clone())
+    }
+
+    fn set(&self, key: &str, fragment: String, ttl: Option<Duration>) {
+        let expires_at = ttl.map(|ttl| Instant::now() + ttl);
+        self.fragments.lock().unwrap().insert(key.to_string(), (fragment, expires_at));
+    }
+}
+
+// Returns the cached fragment under `key`, rendering and caching it if there is none.
+// Fragments which fail to render aren't cached.
+pub fn cached(
+    cache: &dyn FragmentCache,
+    key: impl fmt::Display,
+    ttl: Option<Duration>,
+    render: impl FnOnce() -> Result<String>,
+) -> Result<String> {
+    let key = key.to_string();
+    if let Some(fragment) = cache.get(&key) {
+        return Ok(fragment);
+    }
+
+    let fragment = render()?;
+    cache.set(&key, fragment.clone(), ttl);
+
+    Ok(fragment)
+}
+
+// Maximum number of nested recursive templates, unless configured otherwise.
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+thread_local! {
+    // Number of recursive templates currently rendered on this thread.
+    static TEMPLATE_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+// Keeps a recursive template counted as rendered until it's dropped.
+#[must_use]
+pub struct TemplateDepthGuard(());
+
+impl Drop for TemplateDepthGuard {
+    fn drop(&mut self) {
+        TEMPLATE_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+// Called by the functions of templates which include, call or use themselves, directly or
+// through other templates, e.g. to render trees of comments. Fails once more than `max_depth`
+// of them are nested, e.g. for data with cycles, instead of overflowing the stack.
+pub fn enter_template(path: &str, max_depth: usize) -> Result<TemplateDepthGuard> {
+    let depth = TEMPLATE_DEPTH.with(Cell::get) + 1;
+    if depth > max_depth {
+        bail!("template `{path}` exceeds the maximum nesting depth of {max_depth}");
+    }
+    TEMPLATE_DEPTH.with(|current| current.set(depth));
+
+    Ok(TemplateDepthGuard(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use anyhow::bail;
+    use crate::runtime::{cached, enter_template, FragmentCache, MemoryFragmentCache};
+
+    #[test]
+    fn it_renders_fragments_once() {
+        let cache = MemoryFragmentCache::new();
+        let mut renders = 0;
+
+        for _ in 0..2 {
+            let fragment = cached(&cache, "sidebar", None, || {
+                renders += 1;
+                Ok("<aside>".to_string())
+            });
+            assert_eq!(fragment.unwrap(), "<aside>");
+        }
+        assert_eq!(renders, 1);
+
+        cache.remove("sidebar");
+        assert_eq!(cache.get("sidebar"), None);
+    }
+
+    #[test]
+    fn it_expires_fragments() {
+        let cache = MemoryFragmentCache::new();
+        cache.set("a", "1".to_string(), Some(Duration::ZERO));
+        cache.set("b", "2".to_string(), Some(Duration::from_secs(60)));
+
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.get("b"), Some("2".to_string()));
+    }
+
+    #[test]
+    fn it_doesnt_cache_failed_fragments() {
+        let cache = MemoryFragmentCache::new();
+
+        assert!(cached(&cache, 1, None, || bail!("failed")).is_err());
+        assert_eq!(cache.get("1"), None);
+    }
+
+    #[test]
+    fn it_limits_the_nesting_depth_of_recursive_templates() {
+        let outer = enter_template("tree.plt", 2).unwrap();
+        let inner = enter_template("tree.plt", 2).unwrap();
+        let error = enter_template("tree.plt", 2).err().unwrap();
+        assert_eq!(error.to_string(), "template `tree.plt` exceeds the maximum nesting depth of 2");
+
+        drop(inner);
+        assert!(enter_template("tree.plt", 2).is_ok());
+        drop(outer);
+    }
+}
@@ -0,0 +1,281 @@
+// Sandbox keeping templates presentation-only, e.g. for teams where designers write them. When
+// `GeneratorOptions::sandbox` is set, templates whose code uses `unsafe`, raw pointers or any of
+// the denied paths, macros and directives fail to generate, pointing at the offending code:
+//
+//     error[sandbox]: `std::fs::read_to_string` uses `std::fs`, which isn't allowed in sandboxed templates
+//      --> index.plt:3:9
+//
+// It checks the tokens of the code, so it can't stop code from reaching denied items through
+// the crate's own functions or renamed imports of their parents, e.g. `use std as s;`. Glob
+// imports of their parents, e.g. `use std::*;` or `use std::{*};`, would bring them in the same
+// way, so they're rejected.
+use rustc_lexer::TokenKind;
+use crate::prelude::*;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SandboxOptions {
+    // Paths which can't be used, along with everything below them, e.g. `std::fs`.
+    pub denied_paths: Vec<String>,
+    // Names of the macros which can't be called, e.g. `include_str`.
+    pub denied_macros: Vec<String>,
+    // Names of the directives which can't be used, e.g. `env`.
+    pub denied_directives: Vec<String>,
+}
+
+impl Default for SandboxOptions {
+    fn default() -> Self {
+        Self {
+            // `std::os` holds platform extensions of the others, e.g. `std::os::unix::fs`.
+            denied_paths: ["std::process", "std::fs", "std::env", "std::os"].map(String::from).to_vec(),
+            // They read files or the environment during compilation.
+            denied_macros: ["include", "include_bytes", "include_str", "env", "option_env"].map(String::from).to_vec(),
+            // They expose the environment of the build, and `@git_sha` runs git.
+            denied_directives: ["env", "git_sha", "built_at"].map(String::from).to_vec(),
+        }
+    }
+}
+
+impl SandboxOptions {
+    // Denied path which `path` is part of, if any.
+    fn denied_path(&self, path: &str) -> Option<&str> {
+        self.denied_paths
+            .iter()
+            .find(|denied| path == denied.as_str() || path.starts_with(&format!("{denied}::")))
+            .map(String::as_str)
+    }
+
+    // Denied path below `parent`, which a glob import of `parent` brings in, if any. Globs of
+    // denied paths themselves are rejected as uses of them.
+    fn denied_glob(&self, parent: &str) -> Option<&str> {
+        self.denied_paths
+            .iter()
+            .find(|denied| denied.starts_with(&format!("{parent}::")))
+            .map(String::as_str)
+    }
+}
+
+fn glob_message(parent: &str, denied: &str) -> String {
+    format!("glob import `{parent}::*` brings in `{denied}`, which isn't allowed in sandboxed templates")
+}
+
+// Fails at the first code of the template which the sandbox doesn't allow.
+pub fn check_sandbox(template: &Template, sandbox: &SandboxOptions) -> Result<()> {
+    for (part, span) in template.parts.iter().zip(&template.spans) {
+        if part.is_text() {
+            continue;
+        }
+
+        // Malformed directives are reported once the template is generated.
+        if let Ok(Some(directive)) = Directive::parse(part.get_content()) {
+            if sandbox.denied_directives.iter().any(|denied| denied == directive.name()) {
+                let message = format!("`@{}` isn't allowed in sandboxed templates", directive.name());
+                return Err(template.diagnostic(span.clone(), "sandbox", message).into());
+            }
+        }
+
+        if let Some((offset, len, message)) = find_violation(part.get_content(), sandbox) {
+            let start = (span.start + offset).min(span.end);
+            let end = (start + len).min(span.end);
+
+            return Err(template
+                .diagnostic(start..end, "sandbox", message)
+                .with_suggestion("move the code into a function of the crate and pass its result to the template")
+                .into());
+        }
+    }
+
+    Ok(())
+}
+
+// Byte offset and length of the first code the sandbox doesn't allow, along with why.
+fn find_violation(code: &str, sandbox: &SandboxOptions) -> Option<(usize, usize, String)> {
+    let tokens = significant_tokens(code);
+    // Parent paths of the `{ ... }` groups of `use` trees, e.g. `std` in `use std::{fs, io}`.
+    let mut groups: Vec<Option<String>> = Vec::new();
+
+    let mut index = 0;
+    while index < tokens.len() {
+        let (kind, offset, text) = tokens[index];
+        let next = |ahead: usize| tokens.get(index + ahead).map(|(kind, _, text)| (*kind, *text));
+        let in_group = index > 0 && matches!(tokens[index - 1].0, TokenKind::OpenBrace | TokenKind::Comma);
+
+        match kind {
+            TokenKind::OpenBrace => groups.push(None),
+            TokenKind::CloseBrace => {
+                groups.pop();
+            }
+            TokenKind::Ident if text == "unsafe" => {
+                return Some((offset, text.len(), "`unsafe` isn't allowed in sandboxed templates".to_string()));
+            }
+            TokenKind::Star => {
+                if let Some((TokenKind::Ident, pointer @ ("const" | "mut"))) = next(1) {
+                    let message = format!("raw pointer type `*{pointer}` isn't allowed in sandboxed templates");
+                    return Some((offset, tokens[index + 1].1 + pointer.len() - offset, message));
+                }
+
+                // The glob of a `use` group, e.g. `std::{*}`.
+                if let (true, Some(Some(parent))) = (in_group, groups.last()) {
+                    if let Some(denied) = sandbox.denied_glob(parent) {
+                        return Some((offset, text.len(), glob_message(parent, denied)));
+                    }
+                }
+            }
+            // Whole paths are checked at once, continuing after their last segment.
+            TokenKind::Ident => {
+                let (segments, end) = path_at(&tokens, index);
+                let path = segments.join("::");
+                let path = match groups.last() {
+                    Some(Some(parent)) if in_group => format!("{parent}::{path}"),
+                    _ => path,
+                };
+
+                if let Some(denied) = sandbox.denied_path(&path) {
+                    let (_, last_offset, last_text) = tokens[end - 1];
+                    let message = format!("`{path}` uses `{denied}`, which isn't allowed in sandboxed templates");
+                    return Some((offset, last_offset + last_text.len() - offset, message));
+                }
+
+                let is_macro = tokens.get(end).is_some_and(|(kind, _, _)| *kind == TokenKind::Not)
+                    && tokens.get(end + 1).is_none_or(|(kind, _, _)| *kind != TokenKind::Eq);
+                let name = segments.last().copied().unwrap_or_default();
+                if is_macro && sandbox.denied_macros.iter().any(|denied| denied == name) {
+                    let (_, bang_offset, _) = tokens[end];
+                    let message = format!("macro `{name}!` isn't allowed in sandboxed templates");
+                    return Some((offset, bang_offset + 1 - offset, message));
+                }
+
+                // Token after the `::` ending the path of a `use` tree, e.g. `*` in `std::*`.
+                let after_path = tokens
+                    .get(end..end + 3)
+                    .filter(|tokens| tokens[0].0 == TokenKind::Colon && tokens[1].0 == TokenKind::Colon)
+                    .map(|tokens| tokens[2]);
+
+                if let Some((TokenKind::Star, star_offset, _)) = after_path {
+                    if let Some(denied) = sandbox.denied_glob(&path) {
+                        return Some((offset, star_offset + 1 - offset, glob_message(&path, denied)));
+                    }
+                }
+
+                // The group of a `use` tree, e.g. `std::{`, continues the path.
+                if after_path.is_some_and(|(kind, _, _)| kind == TokenKind::OpenBrace) {
+                    groups.push(Some(path));
+                    index = end + 3;
+                    continue;
+                }
+
+                index = end;
+                continue;
+            }
+            _ => {}
+        }
+
+        index += 1;
+    }
+
+    None
+}
+
+// Segments of the path starting at the identifier at `start`, e.g. `std::fs::read`, along with
+// the index of the token after it.
+fn path_at<'a>(tokens: &[(TokenKind, usize, &'a str)], start: usize) -> (Vec<&'a str>, usize) {
+    let mut segments = vec![tokens[start].2];
+    let mut index = start + 1;
+
+    while let [(TokenKind::Colon, _, _), (TokenKind::Colon, _, _), (TokenKind::Ident, _, segment), ..] = tokens[index.min(tokens.len())..] {
+        segments.push(segment);
+        index += 3;
+    }
+
+    (segments, index)
+}
+
+// Tokens of `code` along with their byte offset, without whitespace and comments.
+fn significant_tokens(code: &str) -> Vec<(TokenKind, usize, &str)> {
+    let mut tokens = Vec::new();
+    let mut offset = 0;
+
+    for token in rustc_lexer::tokenize(code) {
+        let text = &code[offset..offset + token.len];
+        if !matches!(token.kind, TokenKind::Whitespace | TokenKind::LineComment | TokenKind::BlockComment { .. }) {
+            tokens.push((token.kind, offset, text));
+        }
+        offset += token.len;
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use crate::sandbox::SandboxOptions;
+
+    fn generate(source: &str, sandbox: SandboxOptions) -> Result<Vec<String>> {
+        let mut set = TemplateSet::new();
+        set.add_template("index.plt", vec!["user: &User".to_string()], source.to_string());
+
+        set.generate_with("index.plt", &GeneratorOptions { sandbox: Some(sandbox), ..GeneratorOptions::default() })
+    }
+
+    fn error(source: &str) -> String {
+        generate(source, SandboxOptions::default()).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn it_rejects_dangerous_code() {
+        assert_eq!(
+            error("<p>\n<?= std::fs::read_to_string(\"/etc/passwd\")? ?>"),
+            "index.plt:2:5: `std::fs::read_to_string` uses `std::fs`, which isn't allowed in sandboxed templates"
+        );
+        assert_eq!(error("<?rs let name = unsafe { user.name() }; ?>"), "index.plt:1:17: `unsafe` isn't allowed in sandboxed templates");
+        assert_eq!(
+            error("<?rs let ptr = user as *const User; ?>"),
+            "index.plt:1:24: raw pointer type `*const` isn't allowed in sandboxed templates"
+        );
+        assert_eq!(
+            error("<?rs use std::{io, process::{self, Command}}; ?>"),
+            "index.plt:1:20: `std::process` uses `std::process`, which isn't allowed in sandboxed templates"
+        );
+        assert_eq!(error("<?= include_str!(\"secret\") ?>"), "index.plt:1:5: macro `include_str!` isn't allowed in sandboxed templates");
+        assert_eq!(error("<p>\n<?rs @git_sha ?>"), "index.plt:2:5: `@git_sha` isn't allowed in sandboxed templates");
+    }
+
+    #[test]
+    fn it_rejects_glob_imports_of_denied_paths() {
+        assert_eq!(
+            error("<?rs use std::*; fs::remove_file(\"a\")?; ?>"),
+            "index.plt:1:10: glob import `std::*` brings in `std::process`, which isn't allowed in sandboxed templates"
+        );
+        assert_eq!(
+            error("<?rs use std::{io, *}; ?>"),
+            "index.plt:1:20: glob import `std::*` brings in `std::process`, which isn't allowed in sandboxed templates"
+        );
+        assert_eq!(error("<?rs use std::fs::*; ?>"), "index.plt:1:10: `std::fs` uses `std::fs`, which isn't allowed in sandboxed templates");
+        assert!(generate("<?rs use std::fmt::*; ?><?rs use std::{collections::*}; ?>", SandboxOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn it_allows_presentation_code() {
+        let source = "<?rs @if user.is_admin != false ?><?= user.name ?> <?= 2 * mut_count ?><?rs @endif ?>\
+                      <?= format!(\"{}\", std::fs_like::name()) ?><?rs /* std::fs */ ?><?= \"unsafe\" ?>";
+        assert!(generate(source, SandboxOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn it_rejects_configured_paths_and_macros() {
+        let sandbox = SandboxOptions {
+            denied_paths: vec!["crate::db".to_string()],
+            denied_macros: vec!["dbg".to_string()],
+            denied_directives: Vec::new(),
+        };
+
+        let error = generate("<?= crate::db::users() ?>", sandbox.clone()).unwrap_err();
+        let diagnostic = Diagnostic::from_error("index.plt", &error);
+        assert_eq!(diagnostic.code, "sandbox");
+        assert_eq!(diagnostic.span.map(|span| (span.column, span.end_column)), Some((5, 21)));
+
+        assert!(generate("<?= dbg!(user) ?>", sandbox.clone()).is_err());
+        assert!(generate("<?= std::fs::read(\"a\")? ?>", sandbox.clone()).is_ok());
+        assert!(generate("<?rs @env \"CARGO_PKG_NAME\" ?>", sandbox).is_ok());
+    }
+}
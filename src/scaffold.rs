@@ -0,0 +1,115 @@
+// Files of a crate rendering templates, written by `plt new` for a new crate and by `plt init`
+// for an existing one: a layout and a page extending it under `templates/`, a build script
+// compiling them and a `plt.toml` holding the settings of the templates. New crates also get a
+// `Cargo.toml` and a `src/main.rs` printing the page, so `cargo run` renders it right away.
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScaffoldFile {
+    // Path relative to the directory of the crate.
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+impl ScaffoldFile {
+    fn new(path: &str, contents: impl Into<String>) -> ScaffoldFile {
+        ScaffoldFile { path: PathBuf::from(path), contents: contents.into() }
+    }
+}
+
+pub const LAYOUT_TEMPLATE: &str = r#"<?rs @args title: &str ?>
+<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8">
+    <title><?= title ?></title>
+</head>
+<body>
+<?rs @yield content ?>
+</body>
+</html>
+"#;
+
+pub const PAGE_TEMPLATE: &str = r#"<?rs @args name: &str ?>
+<?rs @extends "layout.plt" with ("Home") ?>
+<?rs @block content ?>
+    <h1>Hello, <?= name ?>!</h1>
+<?rs @endblock ?>
+"#;
+
+pub const CONFIG_FILE: &str = r#"# Settings of the templates of this crate.
+template_dir = "templates"
+escape = "html"
+trim_tag_newlines = true
+"#;
+
+pub const BUILD_SCRIPT: &str = r#"// Compiles the templates set up in `plt.toml` into `$OUT_DIR/templates.rs`, which the crate
+// includes with `include!(concat!(env!("OUT_DIR"), "/templates.rs"));`.
+fn main() {
+    plt::build::compile_project().unwrap();
+}
+"#;
+
+const MAIN_FILE: &str = r#"include!(concat!(env!("OUT_DIR"), "/templates.rs"));
+
+fn main() {
+    print!("{}", templates::index("world").unwrap());
+}
+"#;
+
+// Files `plt init` adds to an existing crate.
+pub fn init_files() -> Vec<ScaffoldFile> {
+    vec![
+        ScaffoldFile::new("templates/layout.plt", LAYOUT_TEMPLATE),
+        ScaffoldFile::new("templates/index.plt", PAGE_TEMPLATE),
+        ScaffoldFile::new("plt.toml", CONFIG_FILE),
+        ScaffoldFile::new("build.rs", BUILD_SCRIPT),
+    ]
+}
+
+// Files of a new binary crate named `name`, depending on this version of plt.
+pub fn new_files(name: &str) -> Vec<ScaffoldFile> {
+    let version = env!("CARGO_PKG_VERSION");
+    let manifest = format!(
+        "[package]\nname = {name:?}\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\nplt = \"{version}\"\n\n[build-dependencies]\nplt = {{ version = \"{version}\", features = [\"project\"] }}\n"
+    );
+
+    let mut files = vec![ScaffoldFile::new("Cargo.toml", manifest), ScaffoldFile::new("src/main.rs", MAIN_FILE)];
+    files.extend(init_files());
+    files.push(ScaffoldFile::new(".gitignore", "/target\n"));
+
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use crate::scaffold::{init_files, new_files};
+
+    #[test]
+    fn it_scaffolds_templates_which_compile() {
+        let mut set = TemplateSet::new();
+        for file in init_files().iter().filter(|file| file.path.starts_with("templates")) {
+            let path = file.path.strip_prefix("templates").unwrap().to_str().unwrap();
+            set.add_template(path, Vec::new(), file.contents.clone());
+        }
+        let options = GeneratorOptions { escaping: Escaping::Html, trim_tag_newlines: true, ..GeneratorOptions::default() };
+
+        assert!(set.diagnostics(&options).is_empty());
+        let code = set.generate_module("templates", &options).unwrap();
+        assert!(code.contains("pub fn index(name: &str)"), "{code}");
+        syn::parse_file(&code).unwrap();
+    }
+
+    #[test]
+    fn it_scaffolds_new_crates() {
+        let files = new_files("site");
+
+        let paths: Vec<_> = files.iter().map(|file| file.path.to_str().unwrap()).collect();
+        assert_eq!(paths, ["Cargo.toml", "src/main.rs", "templates/layout.plt", "templates/index.plt", "plt.toml", "build.rs", ".gitignore"]);
+        assert!(files[0].contents.starts_with("[package]\nname = \"site\"\n"));
+        for file in files.iter().filter(|file| file.path.extension().is_some_and(|extension| extension == "rs")) {
+            syn::parse_file(&file.contents).unwrap();
+        }
+    }
+}
@@ -0,0 +1,231 @@
+// Development server rendering templates with the dynamic engine, which reloads open pages
+// whenever a template changes:
+//
+//     plt::serve::serve("templates", &plt::serve::ServeOptions::default())?;
+//
+// `GET /` renders `index.plt`, `GET /docs` renders `docs.plt` or `docs/index.plt`. Templates are
+// loaded again for every request, so changes show up without restarting the server.
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use anyhow::{bail, Context};
+use notify::{RecursiveMode, Watcher as _};
+use serde_json::Value;
+use tungstenite::{Message, WebSocket};
+use crate::dynamic::{Engine, EngineOptions};
+use crate::prelude::*;
+
+// Path of the websocket pages connect to, to be told when to reload.
+pub const LIVE_RELOAD_PATH: &str = "/__plt/live-reload";
+
+const LIVE_RELOAD_SNIPPET: &str = "<script>new WebSocket(`ws://${location.host}/__plt/live-reload`).onmessage = () => location.reload();</script>";
+
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    pub address: String,
+    // JSON file holding the context of all templates, read again for every request.
+    pub context_file: Option<PathBuf>,
+    pub escaping: Escaping,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:8000".to_string(),
+            context_file: None,
+            escaping: Escaping::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+type Clients = Arc<Mutex<Vec<WebSocket<TcpStream>>>>;
+
+// Adds the live reload script before `</body>`, or at the end of pages without a body.
+pub fn inject_live_reload(html: &str) -> String {
+    match html.rfind("</body>") {
+        Some(index) => format!("{}{LIVE_RELOAD_SNIPPET}{}", &html[..index], &html[index..]),
+        None => format!("{html}{LIVE_RELOAD_SNIPPET}"),
+    }
+}
+
+// Templates which may render the URL path, in order of preference.
+fn template_candidates(url_path: &str) -> Vec<String> {
+    let path = url_path.split(['?', '#']).next().unwrap_or_default().trim_matches('/');
+
+    if path.is_empty() {
+        return vec!["index.plt".to_string()];
+    }
+    if path.split('/').any(|segment| segment.is_empty() || segment == "." || segment == "..") {
+        return Vec::new();
+    }
+
+    vec![format!("{path}.plt"), format!("{path}/index.plt")]
+}
+
+fn load_context(options: &ServeOptions) -> Result<Value> {
+    let Some(file) = &options.context_file else {
+        return Ok(Value::Object(Default::default()));
+    };
+
+    let context = std::fs::read_to_string(file).with_context(|| format!("failed to read `{}`", file.display()))?;
+    serde_json::from_str(&context).with_context(|| format!("failed to parse `{}`", file.display()))
+}
+
+// Renders the page for `url_path`. Errors are rendered as a page too, which reloads once
+// the template is fixed.
+pub fn render_page(template_dir: &Path, url_path: &str, options: &ServeOptions) -> Response {
+    let render = || -> Result<Option<String>> {
+        let engine_options = EngineOptions { escaping: options.escaping, build_constants: true, ..EngineOptions::default() };
+        let engine = Engine::from_dir_with(template_dir, engine_options)?;
+
+        let Some(path) = template_candidates(url_path)
+            .into_iter()
+            .find(|path| engine.template_set().get(path).is_some())
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(engine.render(&path, &load_context(options)?)?))
+    };
+
+    match render() {
+        Ok(Some(html)) => Response { status: 200, body: inject_live_reload(&html) },
+        Ok(None) => Response {
+            status: 404,
+            body: inject_live_reload(&format!("<pre>no template found for `{}`</pre>", escape_html(url_path))),
+        },
+        Err(error) => Response {
+            status: 500,
+            body: inject_live_reload(&format!("<pre>{}</pre>", escape_html(format!("{error:#}")))),
+        },
+    }
+}
+
+// Serves the templates under `template_dir` until the server fails.
+pub fn serve(template_dir: impl AsRef<Path>, options: &ServeOptions) -> Result<()> {
+    let template_dir = template_dir.as_ref().to_path_buf();
+    let listener = TcpListener::bind(&options.address).with_context(|| format!("failed to listen on `{}`", options.address))?;
+
+    let clients: Clients = Arc::default();
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).context("failed to start watching")?;
+    watcher
+        .watch(&template_dir, RecursiveMode::Recursive)
+        .with_context(|| format!("failed to watch `{}`", template_dir.display()))?;
+
+    let reload_clients = clients.clone();
+    std::thread::spawn(move || {
+        while receiver.recv().is_ok() {
+            // Waits until editors are done writing before reloading.
+            while receiver.recv_timeout(Duration::from_millis(100)).is_ok() {}
+
+            let mut clients = reload_clients.lock().unwrap();
+            clients.retain_mut(|client| client.send(Message::text("reload")).is_ok());
+        }
+    });
+
+    for stream in listener.incoming() {
+        let stream = stream.context("failed to accept a connection")?;
+        let (template_dir, options, clients) = (template_dir.clone(), options.clone(), clients.clone());
+
+        std::thread::spawn(move || {
+            if let Err(error) = handle_connection(stream, &template_dir, &options, &clients) {
+                eprintln!("error: {error:#}");
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn handle_connection(mut stream: TcpStream, template_dir: &Path, options: &ServeOptions, clients: &Clients) -> Result<()> {
+    // The request is only peeked at first, so websocket handshakes can still be read by tungstenite.
+    let mut buffer = [0; 1024];
+    let length = stream.peek(&mut buffer)?;
+    let head = String::from_utf8_lossy(&buffer[..length]);
+    let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+    let (method, url_path) = (request_line.next().unwrap_or_default(), request_line.next().unwrap_or_default());
+
+    if url_path == LIVE_RELOAD_PATH {
+        let socket = tungstenite::accept(stream).context("failed to accept the live reload websocket")?;
+        clients.lock().unwrap().push(socket);
+        return Ok(());
+    }
+
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let response = match method {
+        "GET" => render_page(template_dir, url_path, options),
+        _ => Response { status: 405, body: "method not allowed".to_string() },
+    };
+    let reason = match response.status {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {} {reason}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.body.len(),
+        response.body
+    )?;
+
+    if response.status == 500 {
+        bail!("failed to render `{url_path}`");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::serve::{inject_live_reload, render_page, template_candidates, ServeOptions, LIVE_RELOAD_SNIPPET};
+    use std::path::Path;
+
+    #[test]
+    fn it_maps_url_paths_to_templates() {
+        assert_eq!(template_candidates("/"), vec!["index.plt"]);
+        assert_eq!(template_candidates("/docs/?page=2"), vec!["docs.plt", "docs/index.plt"]);
+        assert!(template_candidates("/../secret").is_empty());
+    }
+
+    #[test]
+    fn it_injects_the_live_reload_snippet() {
+        assert_eq!(inject_live_reload("<body>a</body>"), format!("<body>a{LIVE_RELOAD_SNIPPET}</body>"));
+        assert_eq!(inject_live_reload("<p>a</p>"), format!("<p>a</p>{LIVE_RELOAD_SNIPPET}"));
+    }
+
+    #[test]
+    fn it_renders_pages() {
+        let options = ServeOptions { context_file: Some("src/test-files/serve/context.json".into()), ..ServeOptions::default() };
+        let template_dir = Path::new("src/test-files/serve");
+
+        let response = render_page(template_dir, "/", &options);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, format!("<html><body>Hello Ann!{LIVE_RELOAD_SNIPPET}</body></html>"));
+
+        let response = render_page(template_dir, "/docs", &options);
+        assert_eq!(response.body, format!("<p>ANN</p>{LIVE_RELOAD_SNIPPET}"));
+
+        let response = render_page(template_dir, "/broken", &options);
+        assert_eq!(response.status, 500);
+        assert!(response.body.starts_with("<pre>broken.plt:1:7: undefined variable `missing`</pre>"));
+
+        assert_eq!(render_page(template_dir, "/missing", &options).status, 404);
+    }
+}
@@ -0,0 +1,47 @@
+// Shell script templates, e.g. install scripts. Templates ending with `.sh.plt` or `.bash.plt`
+// quote their echoes as single words using `Escaping::Shell`, whatever the escaping of the other
+// templates, so values can't inject commands. They aren't minified nor checked against HTML rules,
+// and the whitespace before their shebang is left out, as it has to come first:
+//
+//     <?rs @args prefix: &str, user: &str ?>
+//     #!/bin/sh
+//     set -eu
+//     install -d -o <?= user ?> <?= prefix ?>/bin
+//
+// renders `install -d -o 'deploy' '/opt/my app'/bin` for `deploy` and `/opt/my app`. Other
+// outputs holding shell commands can use the `shell` escaping mode as well, e.g. through
+// `plt compile --escape shell`. Values which are meant to be expanded by the shell, e.g. `$HOME`,
+// are written as they are when wrapped in `Raw`.
+
+const SHELL_EXTENSIONS: [&str; 2] = [".sh.plt", ".bash.plt"];
+
+// Whether the template under `path` renders a shell script, e.g. `install.sh.plt`.
+pub fn is_shell_template(path: &str) -> bool {
+    let file = path.split('#').next().unwrap_or(path);
+
+    SHELL_EXTENSIONS.iter().any(|extension| file.ends_with(extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use crate::shell::is_shell_template;
+
+    #[test]
+    fn it_detects_shell_templates() {
+        assert!(is_shell_template("scripts/install.sh.plt"));
+        assert!(is_shell_template("setup.bash.plt#step"));
+        assert!(!is_shell_template("bash.plt"));
+    }
+
+    #[test]
+    fn it_quotes_echoes_of_shell_templates() {
+        let mut set = TemplateSet::new();
+        set.add_template("install.sh.plt", vec!["prefix: &str".to_string()], "<?rs @args ?>\n#!/bin/sh\nset -eu\n\nmkdir -p <?= prefix ?>/bin\n".to_string());
+        let options = GeneratorOptions { escaping: Escaping::Html, minify: Minify::Full, ..GeneratorOptions::default() };
+
+        let code = format_code(&set.generate_with("install.sh.plt", &options).unwrap().join("\n"));
+        assert!(code.contains("write!(output_buffer, \"{}\", \"#!/bin/sh\\nset -eu\\n\\nmkdir -p \")?;"), "{code}");
+        assert!(code.contains("plt::prelude::EscapeShell("), "{code}");
+    }
+}
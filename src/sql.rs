@@ -0,0 +1,214 @@
+// SQL templates: `.sql.plt` files render queries whose echoes are bound as parameters instead of
+// being written into the SQL, so values can't inject SQL whatever they hold:
+//
+//     <?rs @args name: &str, min_age: i64 ?>
+//     SELECT id FROM users WHERE name = <?= name ?> AND age >= <?= min_age ?>
+//
+// generates a function returning a `Query` made of `SELECT id FROM users WHERE name = $1 AND
+// age >= $2` and the values `[Text(name), Int(min_age)]`, which are bound using the database
+// driver. Echoes in loops bind a value for each iteration. Placeholders are numbered like `$1`
+// for PostgreSQL by default, or written as `?` for SQLite and MySQL with
+// `GeneratorOptions::sql_placeholder`. Includes, components, macros, layouts, `@try` and `@cache`
+// are rejected, as the text they render wouldn't have its values bound.
+//
+// Queries are generated only, as the dynamic engine renders text.
+use std::str::FromStr;
+use anyhow::bail;
+use crate::prelude::*;
+
+// Whether the template under `path` renders a query, e.g. `queries/find_user.sql.plt`.
+pub fn is_sql_template(path: &str) -> bool {
+    path.split('#').next().unwrap_or(path).ends_with(".sql.plt")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Placeholder {
+    // `$1`, `$2`, ... as used by PostgreSQL.
+    #[default]
+    Dollar,
+    // `?` as used by SQLite and MySQL.
+    Question,
+}
+
+impl FromStr for Placeholder {
+    type Err = anyhow::Error;
+
+    fn from_str(placeholder: &str) -> Result<Placeholder> {
+        match placeholder {
+            "dollar" => Ok(Placeholder::Dollar),
+            "question" => Ok(Placeholder::Question),
+            _ => bail!("unknown SQL placeholder `{placeholder}`, expected `dollar` or `question`"),
+        }
+    }
+}
+
+// Value of a parameter of a query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+// Values which can be echoed in SQL templates.
+pub trait ToValue {
+    fn to_value(&self) -> Value;
+}
+
+macro_rules! to_value {
+    ($variant:ident($ty:ty) for $($from:ty),+) => {
+        $(
+            impl ToValue for $from {
+                fn to_value(&self) -> Value {
+                    Value::$variant(<$ty>::from(*self))
+                }
+            }
+        )+
+    };
+}
+
+to_value!(Bool(bool) for bool);
+to_value!(Int(i64) for i8, i16, i32, i64, u8, u16, u32);
+to_value!(Float(f64) for f32, f64);
+
+impl ToValue for str {
+    fn to_value(&self) -> Value {
+        Value::Text(self.to_string())
+    }
+}
+
+impl ToValue for String {
+    fn to_value(&self) -> Value {
+        Value::Text(self.clone())
+    }
+}
+
+impl ToValue for [u8] {
+    fn to_value(&self) -> Value {
+        Value::Bytes(self.to_vec())
+    }
+}
+
+impl ToValue for Vec<u8> {
+    fn to_value(&self) -> Value {
+        Value::Bytes(self.clone())
+    }
+}
+
+impl ToValue for Value {
+    fn to_value(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> Value {
+        self.as_ref().map_or(Value::Null, ToValue::to_value)
+    }
+}
+
+impl<T: ToValue + ?Sized> ToValue for &T {
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+}
+
+// Query rendered by a SQL template.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    pub sql: String,
+    // Values of the placeholders, in order.
+    pub params: Vec<Value>,
+}
+
+// Parameters bound by the generated code of a SQL template as it echoes them.
+#[derive(Debug, Clone, Default)]
+pub struct Params {
+    placeholder: Placeholder,
+    values: Vec<Value>,
+}
+
+impl Params {
+    pub fn new(placeholder: Placeholder) -> Params {
+        Params { placeholder, values: Vec::new() }
+    }
+
+    // Binds `value`, returning the placeholder written into the SQL instead of it.
+    pub fn bind<T: ToValue + ?Sized>(&mut self, value: &T) -> String {
+        self.values.push(value.to_value());
+
+        match self.placeholder {
+            Placeholder::Dollar => format!("${}", self.values.len()),
+            Placeholder::Question => "?".to_string(),
+        }
+    }
+
+    pub fn into_query(self, sql: String) -> Query {
+        Query { sql, params: self.values }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use crate::sql::{is_sql_template, Params, Placeholder, Value};
+
+    #[test]
+    fn it_binds_parameters() {
+        let mut params = Params::new(Placeholder::Dollar);
+        let sql = format!("{} {} {}", params.bind("a'; DROP TABLE users; --"), params.bind(&Some(3u8)), params.bind(&None::<i64>));
+
+        assert_eq!(
+            params.into_query(sql),
+            crate::sql::Query {
+                sql: "$1 $2 $3".to_string(),
+                params: vec![Value::Text("a'; DROP TABLE users; --".to_string()), Value::Int(3), Value::Null],
+            }
+        );
+        assert_eq!(Params::new(Placeholder::Question).bind(&1.5), "?");
+        assert_eq!(
+            "colon".parse::<Placeholder>().unwrap_err().to_string(),
+            "unknown SQL placeholder `colon`, expected `dollar` or `question`"
+        );
+    }
+
+    #[test]
+    fn it_generates_queries_of_sql_templates() {
+        assert!(is_sql_template("queries/users.sql.plt"));
+
+        let mut set = TemplateSet::new();
+        set.add_template(
+            "users.sql.plt",
+            vec!["ids: &[i64]".to_string()],
+            "SELECT name FROM users WHERE id IN (<?rs @for (index, id) in ids.iter().enumerate() ?><?rs @if index > 0 ?>, <?rs @endif ?><?= id ?><?rs @endfor ?>)".to_string(),
+        );
+        let options = GeneratorOptions { escaping: Escaping::Html, sql_placeholder: Placeholder::Question, ..GeneratorOptions::default() };
+
+        let code = format_code(&set.generate_with("users.sql.plt", &options).unwrap().join("\n"));
+        assert!(code.contains("pub fn users_sql(ids: &[i64]) -> plt::prelude::Result<plt::sql::Query> {"), "{code}");
+        assert!(code.contains("let mut sql_params = plt::sql::Params::new(plt::sql::Placeholder::Question);"));
+        assert!(code.contains("write!(output_buffer, \"{}\", sql_params.bind(& (id)))?;"));
+        assert!(code.contains("Ok(sql_params.into_query(output_buffer))"));
+    }
+
+    #[test]
+    fn it_rejects_sections_rendered_elsewhere_in_sql_templates() {
+        let mut set = TemplateSet::new();
+        set.add_template("q/part.plt", vec!["name: &str".to_string()], "name = '<?= name ?>'".to_string());
+        set.add_template("include.sql.plt", vec!["name: &str".to_string()], "SELECT 1 WHERE <?rs @include \"q/part.plt\" with (name) ?>".to_string());
+        set.add_template("call.sql.plt", Vec::new(), "SELECT <?rs @call \"q/part.plt\" ?>1<?rs @endcall ?>".to_string());
+        set.add_template("cache.sql.plt", vec!["id: i64".to_string()], "SELECT <?rs @cache(\"ids\") ?><?= id ?><?rs @endcache ?>".to_string());
+        let options = GeneratorOptions::default();
+
+        for (path, directive) in [("include.sql.plt", "include"), ("call.sql.plt", "call"), ("cache.sql.plt", "cache")] {
+            let error = set.generate_with(path, &options).unwrap_err().to_string();
+            assert!(
+                error.contains(&format!("`@{directive}` can't be used in SQL templates, as its output wouldn't be bound as query parameters")),
+                "{error}"
+            );
+        }
+    }
+}
@@ -0,0 +1,266 @@
+// Templates written by the users of an application at runtime, e.g. the emails or pages of a
+// CMS, kept in a `TemplateStore` and rendered by the dynamic engine:
+//
+//     let templates = UserTemplates::new(MemoryTemplateStore::new());
+//     match templates.save("emails/welcome.plt", &source) {
+//         Ok(saved) => show_warnings(saved.warnings),
+//         Err(diagnostics) => show_errors(diagnostics),
+//     }
+//     let html = templates.render("emails/welcome.plt", &serde_json::json!({ "user": user }))?;
+//
+// Templates are checked before they're stored: they have to parse, pass the sandbox and the
+// lints denied by `StoreOptions::lints`, and the templates they include have to be stored
+// already. Each save stores a new version, so stores can keep the previous ones, e.g. to show
+// their history or roll back.
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use anyhow::Context;
+use serde_json::Value;
+use crate::dynamic::{Engine, EngineOptions};
+use crate::lint::LintOptions;
+use crate::sandbox::SandboxOptions;
+use crate::prelude::*;
+
+// Versions of a template, starting from 1 and increasing with each save.
+pub type Version = u64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredTemplate {
+    pub source: String,
+    pub version: Version,
+}
+
+// `Sync`, since applications share their templates between the threads handling requests.
+pub trait TemplateStore: Sync {
+    // Latest version of the template under `path`, if it's stored.
+    fn get(&self, path: &str) -> Result<Option<StoredTemplate>>;
+
+    // Stores `source` as the next version of the template under `path`, returning it.
+    fn put(&self, path: &str, source: &str) -> Result<Version>;
+
+    // Paths of the stored templates.
+    fn list(&self) -> Result<Vec<String>>;
+
+    // Source of the given version of the template under `path`, if the store keeps it.
+    fn version(&self, path: &str, version: Version) -> Result<Option<String>>;
+}
+
+// Store keeping all versions of the templates in memory, e.g. for tests.
+#[derive(Debug, Default)]
+pub struct MemoryTemplateStore {
+    // Versions of each template, oldest first.
+    templates: Mutex<BTreeMap<String, Vec<String>>>,
+}
+
+impl MemoryTemplateStore {
+    pub fn new() -> MemoryTemplateStore {
+        Self::default()
+    }
+}
+
+impl TemplateStore for MemoryTemplateStore {
+    fn get(&self, path: &str) -> Result<Option<StoredTemplate>> {
+        let templates = self.templates.lock().unwrap();
+        let Some(versions) = templates.get(path) else {
+            return Ok(None);
+        };
+
+        Ok(versions.last().map(|source| StoredTemplate { source: source.clone(), version: versions.len() as Version }))
+    }
+
+    fn put(&self, path: &str, source: &str) -> Result<Version> {
+        let mut templates = self.templates.lock().unwrap();
+        let versions = templates.entry(path.to_string()).or_default();
+        versions.push(source.to_string());
+
+        Ok(versions.len() as Version)
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        Ok(self.templates.lock().unwrap().keys().cloned().collect())
+    }
+
+    fn version(&self, path: &str, version: Version) -> Result<Option<String>> {
+        let templates = self.templates.lock().unwrap();
+        let source = templates.get(path).and_then(|versions| versions.get((version as usize).checked_sub(1)?));
+
+        Ok(source.cloned())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StoreOptions {
+    pub sandbox: SandboxOptions,
+    pub lints: LintOptions,
+    pub engine: EngineOptions,
+}
+
+impl Default for StoreOptions {
+    fn default() -> Self {
+        Self {
+            sandbox: SandboxOptions::default(),
+            lints: LintOptions::dynamic(),
+            engine: EngineOptions { escaping: Escaping::Html, build_constants: false, ..EngineOptions::default() },
+        }
+    }
+}
+
+// Template which passed the checks and was stored, along with the warnings of its lints.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedTemplate {
+    pub version: Version,
+    pub warnings: Diagnostics,
+}
+
+// Checks templates before saving them into the store and renders the stored ones.
+#[derive(Debug)]
+pub struct UserTemplates<S> {
+    pub store: S,
+    pub options: StoreOptions,
+}
+
+impl<S: TemplateStore> UserTemplates<S> {
+    pub fn new(store: S) -> UserTemplates<S> {
+        Self::with_options(store, StoreOptions::default())
+    }
+
+    pub fn with_options(store: S, options: StoreOptions) -> UserTemplates<S> {
+        UserTemplates { store, options }
+    }
+
+    // Checks `source` and stores it as the next version of the template under `path`. Nothing
+    // is stored when the checks fail, and the errors come along with the warnings.
+    pub fn save(&self, path: &str, source: &str) -> Result<SavedTemplate, Diagnostics> {
+        let warnings = self.check(path, source)?;
+
+        match self.store.put(path, source) {
+            Ok(version) => Ok(SavedTemplate { version, warnings }),
+            Err(error) => Err(Diagnostics::from_iter([Diagnostic::from_error(path, &error)])),
+        }
+    }
+
+    // Diagnostics of the template under `path` if it had the given source, warnings only unless
+    // it can't be saved.
+    pub fn check(&self, path: &str, source: &str) -> Result<Diagnostics, Diagnostics> {
+        let loader = StoreLoader { store: &self.store, pending: Some((path, source)) };
+        let set = match TemplateSet::load_from(&loader, &[path]) {
+            Ok(set) => set,
+            Err(error) => return Err(Diagnostics::from_iter([Diagnostic::from_error(path, &error)])),
+        };
+        let options = GeneratorOptions {
+            escaping: self.options.engine.escaping,
+            sandbox: Some(self.options.sandbox.clone()),
+            lints: self.options.lints.clone(),
+            ..GeneratorOptions::default()
+        };
+
+        let mut diagnostics = set.lint(path, &options);
+        if !diagnostics.has_errors() {
+            // Fails for unterminated tags, unbalanced directives and code the sandbox denies.
+            if let Err(error) = set.generate_with(path, &options) {
+                diagnostics.push(Diagnostic::from_error(path, &error));
+            }
+        }
+        if !diagnostics.has_errors() {
+            // Fails for directives which the engine rendering the template doesn't allow.
+            let engine = Engine::from_template_set(set, self.options.engine.clone());
+            if let Err(error) = engine.check(path) {
+                diagnostics.push(Diagnostic::from_error(path, &error));
+            }
+        }
+
+        match diagnostics.has_errors() {
+            true => Err(diagnostics),
+            false => Ok(diagnostics),
+        }
+    }
+
+    // Renders the latest version of the template under `path` against `context`, which has to be
+    // a JSON object, along with the stored templates it depends on.
+    pub fn render(&self, path: &str, context: &Value) -> Result<String> {
+        let loader = StoreLoader { store: &self.store, pending: None };
+        let engine = Engine::from_loader_with(&loader, &[path], self.options.engine.clone())?;
+
+        engine.render(path, context)
+    }
+}
+
+// Loads templates from the store, with the one being checked loaded from its unsaved source.
+struct StoreLoader<'a> {
+    store: &'a dyn TemplateStore,
+    pending: Option<(&'a str, &'a str)>,
+}
+
+impl TemplateLoader for StoreLoader<'_> {
+    fn load(&self, name: &str) -> Result<Cow<'_, str>> {
+        if let Some((_, source)) = self.pending.filter(|(path, _)| *path == name) {
+            return Ok(Cow::Borrowed(source));
+        }
+
+        let template = self.store.get(name)?.with_context(|| format!("template `{name}` is not stored"))?;
+
+        Ok(Cow::Owned(template.source))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::store::{MemoryTemplateStore, TemplateStore, UserTemplates};
+
+    #[test]
+    fn it_saves_checked_templates() {
+        let templates = UserTemplates::new(MemoryTemplateStore::new());
+
+        assert_eq!(templates.save("footer.plt", "<footer>old</footer>").unwrap().version, 1);
+        assert_eq!(templates.save("footer.plt", "<footer><?= company ?></footer>").unwrap().version, 2);
+        let saved = templates.save("index.plt", "<p><?= name ?></p><img src=\"a.png\"><?rs @include \"footer.plt\" ?>").unwrap();
+        assert_eq!(saved.version, 1);
+        assert_eq!(saved.warnings.iter().map(|warning| warning.code.as_str()).collect::<Vec<_>>(), ["missing_alt"]);
+
+        assert_eq!(templates.store.list().unwrap(), ["footer.plt", "index.plt"]);
+        assert_eq!(templates.store.version("footer.plt", 1).unwrap().as_deref(), Some("<footer>old</footer>"));
+        assert_eq!(templates.store.version("footer.plt", 3).unwrap(), None);
+        assert_eq!(
+            templates.render("index.plt", &json!({ "name": "<Ann>", "company": "ACME" })).unwrap(),
+            "<p>&lt;Ann&gt;</p><img src=\"a.png\"><footer>ACME</footer>"
+        );
+    }
+
+    #[test]
+    fn it_rejects_templates_failing_the_checks() {
+        let templates = UserTemplates::new(MemoryTemplateStore::new());
+
+        let diagnostics = templates.save("index.plt", "<?= std::fs::read_to_string(\"/etc/passwd\")? ?>").unwrap_err();
+        assert_eq!(diagnostics.iter().map(|diagnostic| diagnostic.code.as_str()).collect::<Vec<_>>(), ["sandbox"]);
+
+        assert!(templates.save("index.plt", "<?rs @if a ?>").is_err());
+        assert!(templates.save("index.plt", "<p><?= a").is_err());
+        assert!(templates.save("index.plt", "<?rs @include \"missing.plt\" ?>").is_err());
+        assert!(templates.save("index.plt", "<?rs @match a ?><?rs @when _ ?><?rs @endmatch ?>").is_err());
+
+        let diagnostics = templates.save("index.plt", "<?rs @env \"DATABASE_URL\" ?>").unwrap_err();
+        assert_eq!(diagnostics.to_string(), "index.plt:1:5: `@env` isn't allowed in sandboxed templates");
+        assert_eq!(templates.store.list().unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn it_rejects_templates_reading_the_environment() {
+        let templates = UserTemplates::new(MemoryTemplateStore::new());
+
+        for (source, message) in [
+            ("<?= env!(\"DATABASE_URL\") ?>", "macro `env!` isn't allowed in sandboxed templates"),
+            ("<?= option_env!(\"DATABASE_URL\").unwrap_or_default() ?>", "macro `option_env!` isn't allowed in sandboxed templates"),
+            ("<?= std::env::var(\"DATABASE_URL\")? ?>", "`std::env::var` uses `std::env`, which isn't allowed in sandboxed templates"),
+            (
+                "<?rs std::os::unix::fs::symlink(\"/etc/passwd\", \"a\")?; ?>",
+                "`std::os::unix::fs::symlink` uses `std::os`, which isn't allowed in sandboxed templates",
+            ),
+        ] {
+            let diagnostics = templates.save("index.plt", source).unwrap_err();
+            assert!(diagnostics.to_string().ends_with(message), "{diagnostics}");
+        }
+        assert_eq!(templates.store.list().unwrap(), Vec::<String>::new());
+    }
+}
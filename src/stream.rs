@@ -0,0 +1,178 @@
+// Streaming rendering, so large pages start arriving before they are fully rendered:
+//
+//     let body = axum::body::Body::from_stream(IndexTemplate { title }.render_stream());
+//
+// Structs get a `render_stream` method when generated with `GeneratorOptions::stream`. It runs
+// the template like its function does, but hands out the output whenever at least
+// `CHUNK_SIZE` bytes were written by a text, echo or include part.
+//
+// The rendering future has to be `Send` for servers to run it, so it can only hold references
+// to `Sync` values. That's why the traits which templates take by reference require `Sync`:
+// `FragmentCache`, `CsrfTokenProvider`, `UrlFor` and `Translate`.
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use crate::prelude::*;
+
+pub use bytes::Bytes;
+pub use futures_core::Stream;
+
+// Size of the buffered output from which on it is handed out as a chunk.
+pub const CHUNK_SIZE: usize = 8 * 1024;
+
+type Chunk = Arc<Mutex<Option<Bytes>>>;
+
+// Output buffer of streamed templates. Dereferences to a `String`, so the generated code writes
+// into it like into the buffer of template functions.
+#[derive(Debug)]
+pub struct StreamBuffer {
+    buffer: String,
+    chunk: Chunk,
+}
+
+impl StreamBuffer {
+    // Hands the buffered output to the stream once it reached `CHUNK_SIZE`, and waits until
+    // the stream is polled again.
+    pub async fn flush(&mut self) {
+        if self.buffer.len() < CHUNK_SIZE {
+            return;
+        }
+
+        *self.chunk.lock().unwrap() = Some(Bytes::from(std::mem::take(&mut self.buffer)));
+        Suspend { suspended: false }.await;
+    }
+}
+
+impl Deref for StreamBuffer {
+    type Target = String;
+
+    fn deref(&self) -> &String {
+        &self.buffer
+    }
+}
+
+impl DerefMut for StreamBuffer {
+    fn deref_mut(&mut self) -> &mut String {
+        &mut self.buffer
+    }
+}
+
+// Returns `Pending` once, giving control back to the stream.
+struct Suspend {
+    suspended: bool,
+}
+
+impl Future for Suspend {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        if self.suspended {
+            return Poll::Ready(());
+        }
+
+        self.suspended = true;
+        Poll::Pending
+    }
+}
+
+// Stream of the output of a template, returned by the generated `render_stream` methods.
+pub struct RenderStream<'a> {
+    render: Option<Pin<Box<dyn Future<Output = Result<StreamBuffer>> + Send + 'a>>>,
+    chunk: Chunk,
+}
+
+// Streams the output written by `render` into the buffer it's given.
+pub fn render_stream<'a, F, R>(render: F) -> RenderStream<'a>
+where
+    F: FnOnce(StreamBuffer) -> R,
+    R: Future<Output = Result<StreamBuffer>> + Send + 'a,
+{
+    let chunk = Chunk::default();
+    let buffer = StreamBuffer { buffer: String::new(), chunk: chunk.clone() };
+
+    RenderStream { render: Some(Box::pin(render(buffer))), chunk }
+}
+
+impl Stream for RenderStream<'_> {
+    type Item = Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes>>> {
+        let Some(render) = self.render.as_mut() else {
+            return Poll::Ready(None);
+        };
+
+        match render.as_mut().poll(cx) {
+            Poll::Ready(result) => {
+                self.render = None;
+
+                match result {
+                    Ok(buffer) if buffer.is_empty() => Poll::Ready(None),
+                    Ok(buffer) => Poll::Ready(Some(Ok(Bytes::from(buffer.buffer)))),
+                    Err(error) => Poll::Ready(Some(Err(error))),
+                }
+            }
+            Poll::Pending => match self.chunk.lock().unwrap().take() {
+                Some(chunk) => Poll::Ready(Some(Ok(chunk))),
+                // Code inside of the template awaits something else, which wakes the stream.
+                None => Poll::Pending,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stream::{render_stream, Bytes, RenderStream, Stream, CHUNK_SIZE};
+    use anyhow::Context as _;
+    use std::fmt::Write;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    fn collect(stream: RenderStream) -> Vec<Result<Bytes, String>> {
+        let mut stream = pin!(stream);
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut chunks = Vec::new();
+
+        while let Poll::Ready(Some(chunk)) = stream.as_mut().poll_next(&mut cx) {
+            chunks.push(chunk.map_err(|error| error.to_string()));
+        }
+
+        chunks
+    }
+
+    #[test]
+    fn it_streams_large_chunks() {
+        let text = "a".repeat(CHUNK_SIZE);
+        let stream = render_stream(|mut output_buffer| async move {
+            output_buffer.push_str("<html>");
+            output_buffer.flush().await;
+            write!(output_buffer, "{}", text)?;
+            output_buffer.flush().await;
+            output_buffer.push_str("</html>");
+            output_buffer.flush().await;
+            Ok(output_buffer)
+        });
+
+        let chunks = collect(stream);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].as_ref().unwrap().len(), CHUNK_SIZE + "<html>".len());
+        assert_eq!(chunks[1], Ok(Bytes::from("</html>")));
+    }
+
+    #[test]
+    fn it_ends_the_stream_with_render_errors() {
+        let stream = render_stream(|mut output_buffer| async move {
+            let user: Option<&str> = None;
+            write!(output_buffer, "{}", "a".repeat(CHUNK_SIZE))?;
+            output_buffer.flush().await;
+            write!(output_buffer, "{}", user.context("missing user")?)?;
+            Ok(output_buffer)
+        });
+
+        let chunks = collect(stream);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1], Err("missing user".to_string()));
+    }
+}
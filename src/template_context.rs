@@ -0,0 +1,301 @@
+use anyhow::{anyhow, bail};
+use quote::ToTokens;
+use syn::visit_mut::VisitMut;
+pub use crate::prelude::*;
+
+// Value holding everything a template needs to be rendered. Implemented by the structs
+// generated in struct mode and by `#[derive(TemplateContext)]` from plt_macros.
+pub trait TemplateContext {
+    fn render(&self) -> Result<String>;
+
+    // Renders only the block `name` of the template, e.g. for partial page updates.
+    fn render_block(&self, name: &str) -> Result<String> {
+        Err(unknown_block_error(std::any::type_name::<Self>(), name))
+    }
+}
+
+pub fn unknown_block_error(context: &str, name: &str) -> anyhow::Error {
+    anyhow!("`{context}` has no block named `{name}`")
+}
+
+// Argument of a generated function, split into its name and type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateArg {
+    pub name: String,
+    pub ty: String,
+    // Whether the argument is a reference, which is passed as `&value` instead of `value.clone()`.
+    pub is_reference: bool,
+}
+
+impl TemplateArg {
+    pub fn parse(arg: &str) -> Result<TemplateArg> {
+        let Ok(syn::FnArg::Typed(pat_type)) = syn::parse_str::<syn::FnArg>(arg) else {
+            bail!("expected an argument like `name: Type`, found `{arg}`");
+        };
+        let syn::Pat::Ident(pat_ident) = pat_type.pat.as_ref() else {
+            bail!("expected an argument like `name: Type`, found `{arg}`");
+        };
+
+        Ok(TemplateArg {
+            name: pat_ident.ident.to_string(),
+            ty: pat_type.ty.to_token_stream().to_string(),
+            is_reference: matches!(pat_type.ty.as_ref(), syn::Type::Reference(_)),
+        })
+    }
+}
+
+// Names elided lifetimes of references, so the type can be used as a struct field.
+struct NameElidedLifetimes {
+    lifetime: syn::Lifetime,
+    found: bool,
+}
+
+impl VisitMut for NameElidedLifetimes {
+    fn visit_type_reference_mut(&mut self, reference: &mut syn::TypeReference) {
+        if reference.lifetime.is_none() {
+            reference.lifetime = Some(self.lifetime.clone());
+        }
+        if reference.lifetime.as_ref() == Some(&self.lifetime) {
+            self.found = true;
+        }
+
+        syn::visit_mut::visit_type_reference_mut(self, reference);
+    }
+}
+
+// Name of the struct generated in struct mode, e.g. `IndexTemplate` for `index`.
+pub fn template_struct_name(fn_name: &str) -> String {
+    let mut name: String = fn_name
+        .split('_')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| {
+            let mut chars = segment.chars();
+            chars.next().map_or(String::new(), |first| first.to_ascii_uppercase().to_string() + chars.as_str())
+        })
+        .collect();
+
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        name.insert(0, 'T');
+    }
+    name.push_str("Template");
+
+    name
+}
+
+// Generates the struct holding the arguments of `fn_name` and its `TemplateContext`
+// implementation which calls the function, along with the web framework integrations
+// enabled in `options`. Blocks of layouts are rendered with their default content.
+//
+// `defined_blocks` are the blocks `render_block` renders using their block functions.
+pub fn generate_template_struct(
+    fn_name: &str,
+    options: &GeneratorOptions,
+    args: &[String],
+    blocks: &[String],
+    defined_blocks: &[String],
+) -> Result<Vec<String>> {
+    let struct_name = template_struct_name(fn_name);
+    let visibility = &options.visibility;
+
+    let mut lifetimes = NameElidedLifetimes {
+        lifetime: syn::parse_str("'a")?,
+        found: false,
+    };
+
+    let mut fields = Vec::new();
+    let mut call_args = Vec::new();
+    for arg in args {
+        let arg = TemplateArg::parse(arg)?;
+
+        let mut ty = syn::parse_str::<syn::Type>(&arg.ty)?;
+        lifetimes.visit_type_mut(&mut ty);
+
+        fields.push(format!("{visibility} {}: {},", arg.name, ty.to_token_stream()));
+        if arg.is_reference {
+            call_args.push(format!("self.{}", arg.name));
+        } else {
+            call_args.push(format!("self.{}.clone()", arg.name));
+        }
+    }
+    call_args.extend(blocks.iter().map(|_| "None".to_string()));
+
+    let (generics, impl_generics) = if lifetimes.found { ("<'a>", "<'_>") } else { ("", "") };
+
+    let mut code_lines = Vec::new();
+    code_lines.push(format!("{visibility} struct {struct_name}{generics} {{"));
+    code_lines.extend(fields);
+    code_lines.push("}".to_string());
+    code_lines.push(format!("impl plt::prelude::TemplateContext for {struct_name}{impl_generics} {{"));
+    code_lines.push("fn render(&self) -> plt::prelude::Result<String> {".to_string());
+    code_lines.push(format!("{fn_name}({})", call_args.join(", ")));
+    code_lines.push("}".to_string());
+    if !defined_blocks.is_empty() {
+        code_lines.push("fn render_block(&self, name: &str) -> plt::prelude::Result<String> {".to_string());
+        code_lines.push("match name {".to_string());
+        for block in defined_blocks {
+            code_lines.push(format!(
+                "\"{block}\" => {}({}),",
+                block_function_name(fn_name, block),
+                call_args.join(", ")
+            ));
+        }
+        code_lines.push(format!("_ => Err(plt::prelude::unknown_block_error(\"{struct_name}\", name)),"));
+        code_lines.push("}".to_string());
+        code_lines.push("}".to_string());
+    }
+    code_lines.push("}".to_string());
+
+    if options.axum {
+        code_lines.push(format!("impl plt::axum::IntoResponse for {struct_name}{impl_generics} {{"));
+        code_lines.push("fn into_response(self) -> plt::axum::Response {".to_string());
+        code_lines.push("plt::axum::into_response(&self)".to_string());
+        code_lines.push("}".to_string());
+        code_lines.push("}".to_string());
+    }
+
+    if options.actix {
+        code_lines.push(format!("impl plt::actix::Responder for {struct_name}{impl_generics} {{"));
+        code_lines.push("type Body = plt::actix::BoxBody;".to_string());
+        code_lines.push("fn respond_to(self, _request: &plt::actix::HttpRequest) -> plt::actix::HttpResponse {".to_string());
+        code_lines.push("plt::actix::respond(self)".to_string());
+        code_lines.push("}".to_string());
+        code_lines.push("}".to_string());
+    }
+
+    Ok(code_lines)
+}
+
+// Generates `render_stream` for the struct of `fn_name`. `body` is the template's code lowered
+// for streaming, which runs with the fields of the struct as variables.
+pub fn generate_render_stream(
+    fn_name: &str,
+    options: &GeneratorOptions,
+    args: &[String],
+    blocks: &[String],
+    body: Vec<String>,
+) -> Result<Vec<String>> {
+    let struct_name = template_struct_name(fn_name);
+
+    let mut lifetimes = NameElidedLifetimes {
+        lifetime: syn::parse_str("'a")?,
+        found: false,
+    };
+
+    let mut names = Vec::new();
+    for arg in args {
+        let arg = TemplateArg::parse(arg)?;
+        lifetimes.visit_type_mut(&mut syn::parse_str::<syn::Type>(&arg.ty)?);
+        names.push(arg.name);
+    }
+
+    let (generics, lifetime) = if lifetimes.found { ("<'a>", "'a") } else { ("", "'static") };
+
+    let mut code_lines = Vec::new();
+    code_lines.push(format!("impl{generics} {struct_name}{generics} {{"));
+    code_lines.push(format!(
+        "{} fn render_stream(self) -> plt::stream::RenderStream<{lifetime}> {{",
+        options.visibility
+    ));
+    code_lines.push(format!("let {struct_name} {{ {} }} = self;", names.join(", ")));
+    for block in blocks {
+        code_lines.push(format!("let block_{block}: Option<&str> = None;"));
+    }
+    code_lines.push("plt::stream::render_stream(move |mut output_buffer| async move {".to_string());
+    code_lines.push("use std::fmt::Write;".to_string());
+    code_lines.extend(body);
+    code_lines.push("Ok(output_buffer)".to_string());
+    code_lines.push("})".to_string());
+    code_lines.push("}".to_string());
+    code_lines.push("}".to_string());
+
+    Ok(code_lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    #[test]
+    fn it_parses_template_args() {
+        assert_eq!(
+            TemplateArg::parse("title : & str").unwrap(),
+            TemplateArg { name: "title".to_string(), ty: "& str".to_string(), is_reference: true }
+        );
+        assert_eq!(
+            TemplateArg::parse("items: Vec<u32>").unwrap(),
+            TemplateArg { name: "items".to_string(), ty: "Vec < u32 >".to_string(), is_reference: false }
+        );
+        assert!(TemplateArg::parse("(a, b): (u8, u8)").is_err());
+    }
+
+    #[test]
+    fn it_derives_struct_names() {
+        assert_eq!(template_struct_name("index"), "IndexTemplate");
+        assert_eq!(template_struct_name("partials_header"), "PartialsHeaderTemplate");
+        assert_eq!(template_struct_name("_404"), "T404Template");
+    }
+
+    #[test]
+    fn it_generates_template_structs() {
+        let code = generate_template_struct(
+            "index",
+            &GeneratorOptions::default(),
+            &["title: &str".to_string(), "items: Vec<Option<&str>>".to_string(), "count: u32".to_string()],
+            &["content".to_string()],
+            &[],
+        )
+        .unwrap();
+        let code = format_code(&code.join("\n"));
+
+        assert!(code.contains("pub struct IndexTemplate<'a> {\n    pub title: &'a str,\n    pub items: Vec<Option<&'a str>>,\n    pub count: u32,\n}"));
+        assert!(code.contains("impl plt::prelude::TemplateContext for IndexTemplate<'_> {"));
+        assert!(code.contains("index(self.title, self.items.clone(), self.count.clone(), None)"));
+        assert!(!code.contains("fn render_block"));
+    }
+
+    #[test]
+    fn it_renders_blocks_using_block_functions() {
+        let code = generate_template_struct(
+            "page",
+            &GeneratorOptions::default(),
+            &["title: &str".to_string()],
+            &[],
+            &["results".to_string()],
+        )
+        .unwrap();
+        let code = format_code(&code.join("\n"));
+
+        assert!(code.contains("\"results\" => page_block_results(self.title),"));
+        assert!(code.contains("_ => Err(plt::prelude::unknown_block_error(\"PageTemplate\", name)),"));
+    }
+
+    #[test]
+    fn it_omits_lifetimes_when_not_needed() {
+        let code = generate_template_struct("count", &GeneratorOptions::default(), &["count: u32".to_string()], &[], &[]).unwrap();
+        let code = format_code(&code.join("\n"));
+
+        assert!(code.contains("pub struct CountTemplate {"));
+        assert!(code.contains("impl plt::prelude::TemplateContext for CountTemplate {"));
+    }
+
+    #[test]
+    fn it_implements_responder_for_actix() {
+        let options = GeneratorOptions { actix: true, ..GeneratorOptions::default() };
+        let code = generate_template_struct("count", &options, &["count: u32".to_string()], &[], &[]).unwrap();
+        let code = format_code(&code.join("\n"));
+
+        assert!(code.contains("impl plt::actix::Responder for CountTemplate {\n    type Body = plt::actix::BoxBody;"));
+        assert!(code.contains("plt::actix::respond(self)"));
+    }
+
+    #[test]
+    fn it_implements_into_response_for_axum() {
+        let options = GeneratorOptions { axum: true, ..GeneratorOptions::default() };
+        let code = generate_template_struct("index", &options, &["title: &str".to_string()], &[], &[]).unwrap();
+        let code = format_code(&code.join("\n"));
+
+        assert!(code.contains(
+            "impl plt::axum::IntoResponse for IndexTemplate<'_> {\n    fn into_response(self) -> plt::axum::Response {\n        plt::axum::into_response(&self)\n    }\n}"
+        ));
+    }
+}
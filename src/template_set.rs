@@ -0,0 +1,1897 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use anyhow::{bail, Context};
+pub use crate::prelude::*;
+use crate::file_generator::skip_rustfmt;
+use crate::lint::lint_template;
+use crate::sandbox::check_sandbox;
+
+#[derive(Debug, Clone, Hash)]
+pub struct Template {
+    pub path: String,
+    pub args: Vec<String>,
+    pub parts: Vec<Part>,
+    pub source: String,
+    // Byte ranges of the parts in `source`.
+    pub spans: Vec<Range<usize>>,
+    // Byte offset of a `<?rs` or `<?=` tag which is never closed.
+    pub unterminated_tag: Option<usize>,
+    // Byte offset of a `?>` which didn't close the unterminated tag, being part of a string
+    // literal or a comment, along with which of them it was part of, e.g. `a block comment`.
+    pub ignored_end_tag: Option<(usize, &'static str)>,
+}
+
+// All `.plt` files under `dir`, sorted by path.
+pub fn template_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    files_with_extensions(dir, &["plt"])
+}
+
+// Files under `dir` with any of the `extensions`, sorted by path.
+pub fn files_with_extensions(dir: &Path, extensions: &[&str]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let entries = std::fs::read_dir(&dir)
+            .with_context(|| format!("failed to read template directory `{}`", dir.display()))?;
+
+        for entry in entries {
+            let path = entry?.path();
+
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().and_then(|extension| extension.to_str()).is_some_and(|extension| extensions.contains(&extension)) {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+// Path of `file` relative to `dir`, with `/` separators on all platforms.
+pub fn relative_template_path(dir: &Path, file: &Path) -> Result<String> {
+    let relative = file
+        .strip_prefix(dir)
+        .with_context(|| format!("template `{}` is outside of `{}`", file.display(), dir.display()))?;
+
+    let segments: Vec<_> = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+
+    Ok(segments.join("/"))
+}
+
+// 1-based line and column (in chars) of the byte `offset` in `source`.
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+impl Template {
+    // Location of the byte `offset` of the source, e.g. `index.plt:3:5`.
+    pub fn location(&self, offset: usize) -> String {
+        let (line, column) = line_column(&self.source, offset);
+
+        format!("{}:{line}:{column}", self.path)
+    }
+
+    // Error at the byte `range` of the source, displayed as e.g. `index.plt:3:5: <message>`.
+    pub fn diagnostic(&self, range: Range<usize>, code: &str, message: impl fmt::Display) -> Diagnostic {
+        Diagnostic::error(&self.path, Some(Span::new(&self.source, range.start, range.end)), code, message.to_string())
+    }
+
+    // Error of a `<?rs` or `<?=` tag starting at `offset` which is never closed, pointing at the
+    // `?>` which was probably meant to close it, if any.
+    pub fn unterminated_tag_error(&self, offset: usize) -> anyhow::Error {
+        let suggestion = match self.ignored_end_tag {
+            Some((end_tag, ignored_by)) => {
+                let (line, column) = line_column(&self.source, end_tag);
+                let closed = if ignored_by.ends_with("comment") { "comment" } else { "string" };
+
+                format!("`?>` found inside {ignored_by} at {line}:{column}; close the {closed} or escape the tag")
+            }
+            None => "close the tag with `?>`".to_string(),
+        };
+
+        self.diagnostic(offset..self.source.len(), "unterminated-tag", "unterminated tag, expected `?>`")
+            .with_suggestion(suggestion)
+            .into()
+    }
+
+    // Paths of the templates included by this one, in order of appearance.
+    pub fn includes(&self) -> Result<Vec<String>> {
+        let includes = Directive::parse_all(&self.parts)?
+            .into_iter()
+            .filter_map(|directive| match directive {
+                Directive::Include { path, .. } => Some(section_path(&self.path, &path)),
+                _ => None,
+            })
+            .collect();
+
+        Ok(includes)
+    }
+
+    // Paths of the components called by this one, in order of appearance.
+    pub fn components(&self) -> Result<Vec<String>> {
+        let components = Directive::parse_all(&self.parts)?
+            .into_iter()
+            .filter_map(|directive| match directive {
+                Directive::Call { path, .. } => Some(section_path(&self.path, &path)),
+                _ => None,
+            })
+            .collect();
+
+        Ok(components)
+    }
+
+    // Names of the macros this template renders using `@use`, in order of appearance.
+    pub fn uses(&self) -> Result<Vec<String>> {
+        let uses = Directive::parse_all(&self.parts)?
+            .into_iter()
+            .filter_map(|directive| match directive {
+                Directive::Use { name, .. } => Some(name),
+                _ => None,
+            })
+            .collect();
+
+        Ok(uses)
+    }
+
+    // Names of the macros this file defines using `@def`, whose tags are left in its parts.
+    pub fn macros(&self) -> Result<Vec<String>> {
+        let macros = Directive::parse_all(&self.parts)?
+            .into_iter()
+            .filter_map(|directive| match directive {
+                Directive::Def { name, .. } => Some(name),
+                _ => None,
+            })
+            .collect();
+
+        Ok(macros)
+    }
+
+    // Paths of the assets referenced using `@asset` or `@asset_sri`, in order of appearance.
+    pub fn assets(&self) -> Result<Vec<String>> {
+        let assets = Directive::parse_all(&self.parts)?
+            .into_iter()
+            .filter_map(|directive| match directive {
+                Directive::Asset(path) | Directive::AssetSri(path) => Some(path),
+                _ => None,
+            })
+            .collect();
+
+        Ok(assets)
+    }
+
+    // Directives resolved during generation, like `@env` and `@git_sha`.
+    pub fn build_constants(&self) -> Result<Vec<Directive>> {
+        let directives = Directive::parse_all(&self.parts)?
+            .into_iter()
+            .filter(|directive| matches!(directive, Directive::Env(_) | Directive::BuiltAt | Directive::GitSha))
+            .collect();
+
+        Ok(directives)
+    }
+
+    // Paths of all templates this one includes, calls or extends.
+    pub fn dependencies(&self) -> Result<Vec<String>> {
+        let mut dependencies = self.includes()?;
+        dependencies.extend(self.components()?);
+        dependencies.extend(self.extends()?);
+        dependencies.sort();
+        dependencies.dedup();
+
+        Ok(dependencies)
+    }
+
+    // Names of the declared arguments, e.g. `title` for `title: &str`.
+    pub fn arg_names(&self) -> Vec<String> {
+        self.args
+            .iter()
+            .map(|arg| arg.split(':').next().unwrap_or_default().trim().to_string())
+            .collect()
+    }
+
+    // Whether the template renders a `@slot` and so can be called as a component with a body.
+    pub fn has_slot(&self) -> Result<bool> {
+        Ok(Directive::parse_all(&self.parts)?.contains(&Directive::Slot))
+    }
+
+    // Path of the layout this template extends, if any.
+    pub fn extends(&self) -> Result<Option<String>> {
+        let extends = Directive::parse_all(&self.parts)?
+            .into_iter()
+            .find_map(|directive| match directive {
+                Directive::Extends { path, .. } => Some(path),
+                _ => None,
+            });
+
+        Ok(extends)
+    }
+
+    // Sample arguments of the template given by `@example`, as JSON.
+    pub fn example(&self) -> Result<Option<String>> {
+        let example = Directive::parse_all(&self.parts)?
+            .into_iter()
+            .find_map(|directive| match directive {
+                Directive::Example(context) => Some(context),
+                _ => None,
+            });
+
+        Ok(example)
+    }
+
+    // Names of the blocks this template renders, when used as a layout.
+    pub fn blocks(&self) -> Result<Vec<String>> {
+        layout_blocks(&self.parts)
+    }
+}
+
+// Collection of parsed templates keyed by their path relative to the template root,
+// e.g. `partials/header.plt`. Includes between templates are resolved against it.
+#[derive(Debug, Default)]
+pub struct TemplateSet {
+    templates: BTreeMap<String, Template>,
+    // Kept apart from the templates, whose hash must only depend on their content.
+    parse_times: BTreeMap<String, Duration>,
+    // Files the templates were loaded from, along with the files of later roots they shadow.
+    files: BTreeMap<String, PathBuf>,
+    shadowed: BTreeMap<String, Vec<PathBuf>>,
+}
+
+// Directory of templates searched by `TemplateSet::load_roots`. The templates of a root with a
+// namespace are keyed by their path prefixed with it, e.g. `emails::footer.plt`, and included
+// as `@include "emails::footer.plt"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateRoot {
+    pub dir: PathBuf,
+    pub namespace: Option<String>,
+}
+
+impl TemplateRoot {
+    pub fn new(dir: impl Into<PathBuf>) -> TemplateRoot {
+        TemplateRoot { dir: dir.into(), namespace: None }
+    }
+
+    pub fn namespaced(namespace: impl Into<String>, dir: impl Into<PathBuf>) -> TemplateRoot {
+        TemplateRoot { dir: dir.into(), namespace: Some(namespace.into()) }
+    }
+}
+
+// Parses `dir` or `namespace=dir`.
+impl FromStr for TemplateRoot {
+    type Err = anyhow::Error;
+
+    fn from_str(root: &str) -> Result<TemplateRoot> {
+        match root.split_once('=') {
+            Some((namespace, dir)) => Ok(TemplateRoot::namespaced(namespace, dir)),
+            None => Ok(TemplateRoot::new(root)),
+        }
+    }
+}
+
+// Function generated for a single template of a `TemplateSet`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedFunction {
+    // Path of the template, e.g. `partials/header.plt`.
+    pub path: String,
+    // Modules the function lives in, relative to the generated root module.
+    pub module_path: Vec<String>,
+    pub name: String,
+    pub code: String,
+    // Arm of `render_by_name` rendering the template, with `GeneratorOptions::registry`, unless
+    // it can't be rendered by name.
+    pub dispatch: Option<String>,
+}
+
+// Turns a file or directory name into a valid Rust identifier.
+fn sanitize_identifier(name: &str, mangling: NameMangling) -> String {
+    let chars: Vec<char> = name.chars().collect();
+
+    let mut name = String::new();
+    for (index, c) in chars.iter().enumerate() {
+        if mangling == NameMangling::Reject {
+            name.push(if *c == '/' { '_' } else { *c });
+            continue;
+        }
+
+        // Words start at upper case letters following lower case ones, or starting a word
+        // after an acronym, e.g. `HTMLPage` becomes `html_page`.
+        if mangling == NameMangling::Snake && c.is_ascii_uppercase() && index > 0 {
+            let previous = chars[index - 1];
+            let next = chars.get(index + 1).copied().unwrap_or_default();
+            if previous.is_ascii_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_ascii_uppercase() && next.is_ascii_lowercase())
+            {
+                name.push('_');
+            }
+        }
+        name.push(if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' });
+    }
+
+    if mangling == NameMangling::Reject {
+        return name;
+    }
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    // Keywords like `type` or `mod` can't be used as names.
+    if !is_identifier(&name) {
+        name.push('_');
+    }
+
+    name
+}
+
+fn is_identifier(name: &str) -> bool {
+    syn::parse_str::<syn::Ident>(name).is_ok()
+}
+
+// Template path without the extension, with the name of a section appended to the name of its
+// file, e.g. `components.plt#badge` becomes `components_badge`. Namespaces are treated like a
+// directory, e.g. `emails::footer.plt` becomes `emails/footer`.
+fn template_stem(path: &str) -> String {
+    let path = &path.replacen("::", "/", 1);
+    match path.split_once('#') {
+        Some((file, section)) => format!("{}_{section}", file.strip_suffix(".plt").unwrap_or(file)),
+        None => path.strip_suffix(".plt").unwrap_or(path).to_string(),
+    }
+}
+
+// Path of the template `path` refers to from `referrer`, resolving sections of the same file,
+// e.g. `#badge` from `components.plt`, to `components.plt#badge`.
+pub fn section_path(referrer: &str, path: &str) -> String {
+    match path.strip_prefix('#') {
+        Some(section) => format!("{}#{section}", referrer.split('#').next().unwrap_or(referrer)),
+        None => path.to_string(),
+    }
+}
+
+// Derives the name of the generated function from the template path,
+// e.g. `partials/header.plt` becomes `partials_header`.
+pub fn template_function_name(path: &str, mangling: NameMangling) -> String {
+    sanitize_identifier(&template_stem(path), mangling)
+}
+
+// Splits the template path into the modules and the function name used by `ModuleLayout::Tree`,
+// e.g. `admin/users/list.plt` becomes `(["admin", "users"], "list")`.
+pub fn template_module_path(path: &str, mangling: NameMangling) -> (Vec<String>, String) {
+    let path = template_stem(path);
+
+    let mut segments: Vec<String> = path.split('/').map(|segment| sanitize_identifier(segment, mangling)).collect();
+    let name = segments.pop().unwrap_or_default();
+
+    (segments, name)
+}
+
+// Fails for templates whose path isn't made of valid identifiers, when they're kept as they are.
+fn check_identifiers(path: &str, mangling: NameMangling) -> Result<()> {
+    if mangling != NameMangling::Reject {
+        return Ok(());
+    }
+
+    for segment in template_stem(path).split('/') {
+        if !is_identifier(segment) {
+            bail!("{path}: `{segment}` is not a valid identifier, rename it or use another name mangling");
+        }
+    }
+
+    Ok(())
+}
+
+// Parts of a `@template` or `@def` section along with its name and arguments.
+struct Section {
+    name: String,
+    args: Vec<String>,
+    is_macro: bool,
+    parts: Vec<Part>,
+    spans: Vec<Range<usize>>,
+}
+
+// Splits the parts inside of `@template` and `@def` sections off the parts of the file, which
+// keeps the tags of the sections. Nested, unclosed or duplicate sections leave the parts as they
+// are, for the generator to report.
+fn split_sections(parts: Vec<Part>, spans: Vec<Range<usize>>) -> (Vec<Part>, Vec<Range<usize>>, Vec<Section>) {
+    let mut file = (Vec::new(), Vec::new());
+    let mut sections = Vec::new();
+    let mut open: Option<Section> = None;
+
+    for (part, span) in parts.iter().zip(&spans) {
+        let directive = match part {
+            Part::Code(code) => Directive::parse(code).ok().flatten(),
+            _ => None,
+        };
+
+        let is_macro = matches!(directive, Some(Directive::Def { .. } | Directive::EndDef));
+        match (directive, &mut open) {
+            (Some(Directive::Template { .. } | Directive::Def { .. }), Some(_)) => return (parts, spans, Vec::new()),
+            (Some(Directive::Template { name, .. } | Directive::Def { name, .. }), None)
+                if sections.iter().any(|section: &Section| section.name == name) =>
+            {
+                return (parts, spans, Vec::new());
+            }
+            (Some(Directive::Template { name, args } | Directive::Def { name, args }), None) => {
+                open = Some(Section { name, args, is_macro, parts: Vec::new(), spans: Vec::new() });
+            }
+            (Some(Directive::EndTemplate | Directive::EndDef), Some(section)) if section.is_macro == is_macro => {
+                sections.extend(open.take());
+            }
+            (Some(Directive::EndTemplate | Directive::EndDef), Some(_)) => return (parts, spans, Vec::new()),
+            (_, Some(section)) => {
+                section.parts.push(part.clone());
+                section.spans.push(span.clone());
+                continue;
+            }
+            _ => {}
+        }
+        file.0.push(part.clone());
+        file.1.push(span.clone());
+    }
+
+    if open.is_some() {
+        return (parts, spans, Vec::new());
+    }
+
+    (file.0, file.1, sections)
+}
+
+impl TemplateSet {
+    pub fn new() -> TemplateSet {
+        Self::default()
+    }
+
+    // Adds the template, with `args` followed by the arguments it declares using `@args`.
+    pub fn add_template(&mut self, path: impl Into<String>, mut args: Vec<String>, source: String) {
+        let path = path.into();
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("parse_template", template = %path).entered();
+
+        let started = Instant::now();
+        let mut fsa = TextCodeFSA::new();
+        let parts = fsa.run(source.clone()).clone();
+        let spans = fsa.spans().to_vec();
+        #[cfg(feature = "markdown")]
+        let (parts, spans) = match crate::markdown::is_markdown_template(&path) {
+            true => crate::markdown::render_markdown(&parts, &spans),
+            false => (parts, spans),
+        };
+        let parts = match Escaping::of_template(&path) {
+            Some(Escaping::Xml) => strip_leading_whitespace(parts, "<?xml"),
+            Some(Escaping::Shell) => strip_leading_whitespace(parts, "#!"),
+            _ => parts,
+        };
+        self.remove_sections(&path);
+        let (parts, spans, sections) = split_sections(parts, spans);
+        // Malformed directives are reported once the template is generated.
+        args.extend(declared_args(&parts).unwrap_or_default());
+        for section in sections {
+            let mut section_args = section.args;
+            section_args.extend(declared_args(&section.parts).unwrap_or_default());
+            let section_path = format!("{path}#{}", section.name);
+
+            self.templates.insert(
+                section_path.clone(),
+                Template {
+                    path: section_path,
+                    args: section_args,
+                    parts: section.parts,
+                    source: source.clone(),
+                    spans: section.spans,
+                    unterminated_tag: None,
+                    ignored_end_tag: None,
+                },
+            );
+        }
+
+        let template = Template {
+            path: path.clone(),
+            args,
+            parts,
+            source,
+            spans,
+            unterminated_tag: fsa.unterminated_tag(),
+            ignored_end_tag: fsa.ignored_end_tag(),
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(parts = template.parts.len(), bytes = template.source.len(), duration = ?started.elapsed(), "parsed template");
+        self.parse_times.insert(path.clone(), started.elapsed());
+        self.templates.insert(path, template);
+    }
+
+    pub fn remove_template(&mut self, path: &str) -> Option<Template> {
+        self.remove_sections(path);
+        self.parse_times.remove(path);
+        self.files.remove(path);
+        self.shadowed.remove(path);
+        self.templates.remove(path)
+    }
+
+    // Templates of the `@template` and `@def` sections of the file `path`, in order of their names.
+    pub fn sections(&self, path: &str) -> Vec<&Template> {
+        let prefix = format!("{path}#");
+        self.templates
+            .range(prefix.clone()..)
+            .take_while(|(section_path, _)| section_path.starts_with(&prefix))
+            .map(|(_, template)| template)
+            .collect()
+    }
+
+    fn remove_sections(&mut self, path: &str) {
+        let prefix = format!("{path}#");
+        self.templates.retain(|section_path, _| !section_path.starts_with(&prefix));
+    }
+
+    // Loads all `.plt` files under `dir`, keyed by their path relative to it.
+    pub fn load_dir(dir: impl AsRef<Path>) -> Result<TemplateSet> {
+        Self::load_roots(&[TemplateRoot::new(dir.as_ref())])
+    }
+
+    // Loads all `.plt` files under the roots, which are searched in order: a template found
+    // under several roots with the same namespace is loaded from the first one, and its lints
+    // warn about the ones it shadows.
+    pub fn load_roots(roots: &[TemplateRoot]) -> Result<TemplateSet> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("load_templates", roots = roots.len()).entered();
+        let mut set = Self::new();
+
+        for root in roots {
+            if let Some(namespace) = root.namespace.as_deref().filter(|namespace| !is_identifier(namespace)) {
+                bail!("namespace `{namespace}` of `{}` is not a valid identifier", root.dir.display());
+            }
+
+            for file in template_files(&root.dir)? {
+                let relative = relative_template_path(&root.dir, &file)?;
+                let path = match &root.namespace {
+                    Some(namespace) => format!("{namespace}::{relative}"),
+                    None => relative,
+                };
+                if set.files.contains_key(&path) {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(template = %path, file = %file.display(), "template shadowed by an earlier root");
+                    set.shadowed.entry(path).or_default().push(file);
+                    continue;
+                }
+
+                let source = std::fs::read_to_string(&file)
+                    .with_context(|| format!("failed to read template `{}`", file.display()))?;
+
+                // Checkouts can have either line endings, which would end up in the generated code.
+                set.add_template(path.clone(), Vec::new(), source.replace("\r\n", "\n"));
+                set.files.insert(path, file);
+            }
+        }
+
+        Ok(set)
+    }
+
+    // File the template under `path`, or the file of the section under `path`, was loaded from.
+    pub fn file(&self, path: &str) -> Option<&Path> {
+        self.files.get(path.split('#').next().unwrap_or(path)).map(PathBuf::as_path)
+    }
+
+    pub fn get(&self, path: &str) -> Option<&Template> {
+        self.templates.get(path)
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &String> {
+        self.templates.keys()
+    }
+
+    // Paths of all templates whose generated code depends on the template under `path`,
+    // directly or through other templates, in path order.
+    pub fn dependents(&self, path: &str) -> Result<Vec<String>> {
+        let mut direct_dependents: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        for template in self.templates.values() {
+            for dependency in self.dependencies(template)? {
+                direct_dependents.entry(dependency).or_default().push(&template.path);
+            }
+        }
+
+        let mut dependents = BTreeSet::new();
+        let mut queue = vec![path];
+        while let Some(path) = queue.pop() {
+            for dependent in direct_dependents.get(path).into_iter().flatten() {
+                if dependents.insert(dependent.to_string()) {
+                    queue.push(dependent);
+                }
+            }
+        }
+
+        Ok(dependents.into_iter().filter(|dependent| dependent != path).collect())
+    }
+
+    // Regenerates only the templates affected by changes to `changed_paths`, i.e. the changed
+    // templates themselves and their dependents. Changed paths which are no longer part of the
+    // set are skipped, though their dependents are still regenerated.
+    pub fn regenerate_changed(
+        &self,
+        changed_paths: &[impl AsRef<str>],
+        options: &GeneratorOptions,
+    ) -> Result<Vec<GeneratedFunction>> {
+        let mut affected = BTreeSet::new();
+        for path in changed_paths {
+            let path = path.as_ref();
+
+            if self.templates.contains_key(path) {
+                affected.insert(path.to_string());
+            }
+            affected.extend(self.dependents(path)?);
+        }
+
+        let mut functions = Vec::new();
+        for path in affected {
+            functions.push(self.generate_function(&path, options)?);
+        }
+
+        Ok(functions)
+    }
+
+    fn get_included(&self, includer: &str, path: &str) -> Result<&Template> {
+        let Some(template) = self.get(path) else {
+            bail!("template `{includer}` includes `{path}`, which is not part of the template set");
+        };
+
+        Ok(template)
+    }
+
+    fn get_extended(&self, extender: &str, path: &str) -> Result<&Template> {
+        let Some(template) = self.get(path) else {
+            bail!("template `{extender}` extends `{path}`, which is not part of the template set");
+        };
+
+        Ok(template)
+    }
+
+    fn get_called(&self, caller: &str, path: &str) -> Result<&Template> {
+        let Some(template) = self.get(path) else {
+            bail!("template `{caller}` calls `{path}`, which is not part of the template set");
+        };
+
+        Ok(template)
+    }
+
+    // Paths of all templates `template` includes, calls or extends, along with the macros it uses
+    // and the text template of emails.
+    // Macros which don't resolve are left out, generating the template reports them.
+    pub(crate) fn dependencies(&self, template: &Template) -> Result<Vec<String>> {
+        let mut dependencies = template.dependencies()?;
+        dependencies.extend(template.uses()?.iter().filter_map(|name| self.macro_path(&template.path, name).ok()));
+        // The text body of emails may come from the text template next to them.
+        if is_email_template(&template.path) {
+            dependencies.extend(Some(text_template_path(&template.path)).filter(|path| self.get(path).is_some()));
+        }
+        dependencies.sort();
+        dependencies.dedup();
+
+        Ok(dependencies)
+    }
+
+    // Path of the section defining the macro `name` used by `user`. Macros of the user's own file
+    // take precedence over the ones of other files, which must be defined by a single file.
+    pub fn macro_path(&self, user: &str, name: &str) -> Result<String> {
+        let file = user.split('#').next().unwrap_or(user);
+        if let Some(template) = self.get(file) {
+            if template.macros()?.contains(&name.to_string()) {
+                return Ok(format!("{file}#{name}"));
+            }
+        }
+
+        let mut files = Vec::new();
+        for template in self.templates.values().filter(|template| !template.path.contains('#')) {
+            // Malformed directives are reported once the file itself is generated.
+            if template.macros().unwrap_or_default().contains(&name.to_string()) {
+                files.push(template.path.as_str());
+            }
+        }
+        match files.as_slice() {
+            [] => bail!("template `{user}` uses macro `{name}`, which is not defined by `@def` in the template set"),
+            [file] => Ok(format!("{file}#{name}")),
+            files => bail!("template `{user}` uses macro `{name}`, which is defined by more than one template: {}", files.join(", ")),
+        }
+    }
+
+    // Whether the template under `path` renders itself, directly or through the templates it
+    // renders, e.g. to render trees of comments.
+    pub fn is_recursive(&self, path: &str) -> Result<bool> {
+        let mut visited = BTreeSet::new();
+        let mut queue = vec![path.to_string()];
+        while let Some(current) = queue.pop() {
+            let Some(template) = self.get(&current) else {
+                continue;
+            };
+
+            for dependency in self.dependencies(template)? {
+                if dependency == path {
+                    return Ok(true);
+                }
+                if visited.insert(dependency.clone()) {
+                    queue.push(dependency);
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    // Walks the includes and layouts reachable from `path`, failing on missing templates.
+    // Templates rendering themselves are walked once, their depth is limited at runtime.
+    pub(crate) fn check_dependencies(&self, path: &str, stack: &mut Vec<String>) -> Result<()> {
+        if stack.iter().any(|entry| entry == path) {
+            return Ok(());
+        }
+
+        let Some(template) = self.get(path) else {
+            bail!("template `{path}` is not part of the template set");
+        };
+
+        stack.push(path.to_string());
+        for include in template.includes()? {
+            self.get_included(path, &include)?;
+            self.check_dependencies(&include, stack)?;
+        }
+        for component in template.components()? {
+            self.get_called(path, &component)?;
+            self.check_dependencies(&component, stack)?;
+        }
+        if let Some(layout) = template.extends()? {
+            self.get_extended(path, &layout)?;
+            self.check_dependencies(&layout, stack)?;
+        }
+        for name in template.uses()? {
+            self.check_dependencies(&self.macro_path(path, &name)?, stack)?;
+        }
+        stack.pop();
+
+        Ok(())
+    }
+
+    // Name of the function generated for the template under `path`.
+    pub fn function_name(&self, path: &str, options: &GeneratorOptions) -> String {
+        let name = match (&options.naming, options.module_layout) {
+            (NamingStrategy::Path, ModuleLayout::Flat) => template_function_name(path, options.name_mangling),
+            (NamingStrategy::Path | NamingStrategy::FileName, _) => template_module_path(path, options.name_mangling).1,
+            (NamingStrategy::Custom(name), _) => name(path),
+        };
+
+        format!("{}{name}{}", options.function_prefix, options.function_suffix)
+    }
+
+    // Modules containing the function generated for the template under `path`.
+    pub fn module_path(&self, path: &str, options: &GeneratorOptions) -> Vec<String> {
+        match options.module_layout {
+            ModuleLayout::Flat => Vec::new(),
+            ModuleLayout::Tree => template_module_path(path, options.name_mangling).0,
+            // The module of the template itself is named like its function.
+            ModuleLayout::Modules => {
+                let (mut modules, name) = template_module_path(path, options.name_mangling);
+                modules.push(name);
+                modules
+            }
+        }
+    }
+
+    // Path used to call the function of template `to` from the function of template `from`.
+    fn function_reference(&self, from: &str, to: &str, options: &GeneratorOptions) -> String {
+        let mut segments = vec!["super".to_string(); self.module_path(from, options).len()];
+        let mut modules = self.module_path(to, options);
+        // Templates in their own modules are called through the re-exports of their directories.
+        if options.module_layout == ModuleLayout::Modules {
+            modules.pop();
+        }
+        segments.extend(modules);
+        segments.push(self.function_name(to, options));
+
+        let reference = segments.join("::");
+        #[cfg(feature = "tracing")]
+        tracing::trace!(from, to, function = %reference, "resolved template");
+
+        reference
+    }
+
+    // Resolves an `@include` directive found in `includer` to the generated function name.
+    pub(crate) fn resolve_include(&self, includer: &str, path: &str, options: &GeneratorOptions) -> Result<String> {
+        let path = &section_path(includer, path);
+        self.get_included(includer, path)?;
+
+        Ok(self.function_reference(includer, path, options))
+    }
+
+    // Resolves a `@use` directive found in `user` to the macro's function name.
+    pub(crate) fn resolve_macro(&self, user: &str, name: &str, options: &GeneratorOptions) -> Result<String> {
+        let path = self.macro_path(user, name)?;
+
+        Ok(self.function_reference(user, &path, options))
+    }
+
+    // Resolves an `@extends` directive to the layout's function name and block names.
+    pub(crate) fn resolve_layout(
+        &self,
+        extender: &str,
+        path: &str,
+        options: &GeneratorOptions,
+    ) -> Result<(String, Vec<String>)> {
+        let layout = self.get_extended(extender, path)?;
+
+        if layout.extends()?.is_some() {
+            bail!("template `{extender}` extends `{path}`, which itself extends another layout");
+        }
+
+        Ok((self.function_reference(extender, path, options), layout.blocks()?))
+    }
+
+    // Resolves a `@call` directive to the component's function name and its arguments, with
+    // the named arguments reordered to match its declared arguments and the slot passed last.
+    pub(crate) fn resolve_component(
+        &self,
+        caller: &str,
+        path: &str,
+        args: &[(String, String)],
+        options: &GeneratorOptions,
+    ) -> Result<(String, String)> {
+        let path = &section_path(caller, path);
+        let component = self.get_called(caller, path)?;
+
+        if !component.has_slot()? {
+            bail!("template `{caller}` calls `{path}`, which does not render a `@slot`");
+        }
+        if !component.blocks()?.is_empty() {
+            bail!("template `{caller}` calls `{path}`, which is a layout");
+        }
+
+        let arg_names = component.arg_names();
+        for (name, _) in args {
+            if !arg_names.contains(name) {
+                bail!("template `{caller}` passes `{name}` to `{path}`, which has no such argument");
+            }
+        }
+
+        let mut call_args = Vec::new();
+        for arg_name in arg_names {
+            let Some((_, value)) = args.iter().find(|(name, _)| *name == arg_name) else {
+                bail!("template `{caller}` calls `{path}` without its `{arg_name}` argument");
+            };
+
+            call_args.push(value.clone());
+        }
+        call_args.push("&slot".to_string());
+
+        Ok((self.function_reference(caller, path, options), call_args.join(", ")))
+    }
+
+    // Generates the function for the template under `path`.
+    pub fn generate(&self, path: &str) -> Result<Vec<String>> {
+        self.generate_with(path, &GeneratorOptions::default())
+    }
+
+    pub fn generate_with(&self, path: &str, options: &GeneratorOptions) -> Result<Vec<String>> {
+        self.check_dependencies(path, &mut Vec::new())?;
+
+        let template = &self.templates[path];
+
+        if let Some(offset) = template.unterminated_tag {
+            return Err(template.unterminated_tag_error(offset));
+        }
+        if let Some(sandbox) = &options.sandbox {
+            check_sandbox(template, sandbox)?;
+        }
+
+        generate_template(template, self, options)
+    }
+
+    fn generate_unformatted_function(&self, path: &str, options: &GeneratorOptions) -> Result<GeneratedFunction> {
+        check_identifiers(path, options.name_mangling)?;
+        let name = self.function_name(path, options);
+        if !is_identifier(&name) {
+            bail!("{path}: function name `{name}` is not a valid identifier");
+        }
+
+        Ok(GeneratedFunction {
+            path: path.to_string(),
+            module_path: self.module_path(path, options),
+            name,
+            code: match options.rustfmt_skip {
+                true => skip_rustfmt(self.generate_with(path, options)?.join("\n")),
+                false => self.generate_with(path, options)?.join("\n"),
+            },
+            dispatch: self.dispatch(path, options)?,
+        })
+    }
+
+    // Arm of `render_by_name` rendering the template under `path`, see `dispatch_arm`.
+    pub(crate) fn dispatch(&self, path: &str, options: &GeneratorOptions) -> Result<Option<String>> {
+        let Some(template) = self.get(path).filter(|_| options.registry) else {
+            return Ok(None);
+        };
+        let mut segments = self.module_path(path, options);
+        // Templates in their own modules are called through the re-exports of their directories.
+        if options.module_layout == ModuleLayout::Modules {
+            segments.pop();
+        }
+        segments.push(self.function_name(path, options));
+
+        dispatch_arm(template, &segments.join("::"))
+    }
+
+    pub(crate) fn generate_function(&self, path: &str, options: &GeneratorOptions) -> Result<GeneratedFunction> {
+        Ok(self.generate_function_with_stats(path, options)?.0)
+    }
+
+    pub(crate) fn generate_function_with_stats(
+        &self,
+        path: &str,
+        options: &GeneratorOptions,
+    ) -> Result<(GeneratedFunction, TemplateStats)> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("generate_template", template = path).entered();
+        let started = Instant::now();
+        let mut function = self.generate_unformatted_function(path, options)?;
+        let generate_time = started.elapsed();
+
+        let started = Instant::now();
+        // rustfmt only runs over the files written, functions are formatted with prettyplease then.
+        let formatter = match options.formatter {
+            CodeFormatter::Rustfmt => CodeFormatter::Prettyplease,
+            formatter => formatter,
+        };
+        function.code = try_format_code_with(&function.code, formatter).with_context(|| invalid_rust(path))?;
+        let format_time = started.elapsed();
+
+        let parts = &self.templates[path].parts;
+        let stats = TemplateStats {
+            path: path.to_string(),
+            text_bytes: parts.iter().map(|part| if let Part::Text(text) = part { text.len() } else { 0 }).sum(),
+            code_parts: parts.iter().filter(|part| matches!(part, Part::Code(_))).count(),
+            echo_parts: parts.iter().filter(|part| matches!(part, Part::EchoCode(_))).count(),
+            code_bytes: function.code.len(),
+            parse_time: self.parse_times.get(path).copied().unwrap_or_default(),
+            generate_time,
+            format_time,
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            text_bytes = stats.text_bytes,
+            code_parts = stats.code_parts,
+            echo_parts = stats.echo_parts,
+            code_bytes = stats.code_bytes,
+            generate_time = ?stats.generate_time,
+            format_time = ?stats.format_time,
+            "generated template"
+        );
+
+        Ok((function, stats))
+    }
+
+    // Generates the functions of all templates without formatting their code.
+    fn generate_unformatted(&self, options: &GeneratorOptions) -> Result<Vec<GeneratedFunction>> {
+        self.templates
+            .keys()
+            .map(|path| self.generate_unformatted_function(path, options))
+            .collect()
+    }
+
+    // Generates the formatted functions of all templates, ordered by template path, along with
+    // their statistics.
+    pub fn generate_all(&self, options: &GeneratorOptions) -> Result<GenerationReport> {
+        let mut report = GenerationReport::new();
+        for path in self.templates.keys() {
+            let (function, stats) = self.generate_function_with_stats(path, options)?;
+            report.push(function, stats);
+        }
+
+        Ok(report)
+    }
+
+    // Lints the template under `path` and generates its formatted function, along with the
+    // diagnostics of both. Templates failing a denied lint aren't generated.
+    pub(crate) fn generate_checked(&self, path: &str, options: &GeneratorOptions) -> (Option<(GeneratedFunction, TemplateStats)>, Diagnostics) {
+        let mut diagnostics = self.lint(path, options);
+        if diagnostics.has_errors() {
+            return (None, diagnostics);
+        }
+
+        match self.generate_function_with_stats(path, options) {
+            Ok(generated) => (Some(generated), diagnostics),
+            Err(error) => {
+                diagnostics.push(Diagnostic::from_error(path, &error));
+                (None, diagnostics)
+            }
+        }
+    }
+
+    // `generate_checked` for all templates, ordered by template path, telling `observer` about
+    // each of them. With the `parallel` feature the templates are generated and formatted on
+    // rayon's thread pool.
+    pub(crate) fn generate_each_checked(
+        &self,
+        options: &GeneratorOptions,
+        observer: &dyn GenerationObserver,
+    ) -> Vec<(Option<(GeneratedFunction, TemplateStats)>, Diagnostics)> {
+        let generate = |path: &String| {
+            let generated = self.generate_checked(path, options);
+            observer.template_generated(path, generated.0.as_ref().map(|(_, stats)| stats));
+            generated
+        };
+        // Rayon's threads don't inherit the subscriber and the span of the caller.
+        #[cfg(feature = "tracing")]
+        let (dispatch, span) = (tracing::dispatcher::get_default(Clone::clone), tracing::Span::current());
+        #[cfg(feature = "tracing")]
+        let generate = |path: &String| tracing::dispatcher::with_default(&dispatch, || span.in_scope(|| generate(path)));
+
+        observer.started(self.templates.len());
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+
+            let paths: Vec<_> = self.templates.keys().collect();
+            paths.into_par_iter().map(generate).collect()
+        }
+        #[cfg(not(feature = "parallel"))]
+        self.templates.keys().map(generate).collect()
+    }
+
+    // Same as `generate_all` with the templates generated and formatted in parallel, which
+    // mostly speeds up formatting big sets. The output is still ordered by template path, the
+    // warnings of the lints end up in the report and the errors of all templates are reported
+    // as a `CompileError` rather than only the first one.
+    #[cfg(feature = "parallel")]
+    pub fn generate_all_parallel(&self, options: &GeneratorOptions) -> Result<GenerationReport> {
+        let mut report = GenerationReport::new();
+        let mut errors = Diagnostics::new();
+        for (generated, diagnostics) in self.generate_each_checked(options, &()) {
+            if let Some((function, stats)) = generated {
+                report.push(function, stats);
+            }
+            for diagnostic in diagnostics {
+                match diagnostic.severity {
+                    Severity::Error => errors.push(diagnostic),
+                    Severity::Warning => report.warnings.push(diagnostic),
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(crate::build::CompileError { diagnostics: errors }.into());
+        }
+
+        Ok(report)
+    }
+
+    // Checks all templates without generating any files: their syntax, directive balance,
+    // dependencies and the Rust code inside of them. Returns the errors of all templates,
+    // ordered by template path.
+    pub fn check(&self, options: &GeneratorOptions) -> Vec<anyhow::Error> {
+        self.templates
+            .keys()
+            .filter_map(|path| self.check_template(path, options).err())
+            .collect()
+    }
+
+    // Same as `check`, returning the errors as diagnostics, e.g. to be written as JSON, along
+    // with the warnings of the lints. Templates failing a denied lint aren't checked any further.
+    pub fn diagnostics(&self, options: &GeneratorOptions) -> Diagnostics {
+        let mut diagnostics = Diagnostics::new();
+        for path in self.templates.keys() {
+            let lints = self.lint(path, options);
+            let denied = lints.has_errors();
+            diagnostics.extend(lints);
+
+            if let (false, Err(error)) = (denied, self.check_template(path, options)) {
+                diagnostics.push(Diagnostic::from_error(path, &error));
+            }
+        }
+
+        diagnostics
+    }
+
+    // Diagnostics of the lints of the template under `path`, see `lint_template`.
+    pub fn lint(&self, path: &str, options: &GeneratorOptions) -> Diagnostics {
+        let Some(template) = self.templates.get(path) else {
+            return Diagnostics::new();
+        };
+        let function = self.generate_unformatted_function(path, options).ok();
+
+        let mut diagnostics = lint_template(template, function.as_ref().map(|function| function.code.as_str()), options);
+        for file in self.shadowed.get(path).into_iter().flatten() {
+            let message = format!("template `{path}` shadows `{}`, which comes later in the search path", file.display());
+            diagnostics.push(Diagnostic { severity: Severity::Warning, ..Diagnostic::error(path, None, "shadowed-template", message) });
+        }
+
+        diagnostics
+    }
+
+    fn check_template(&self, path: &str, options: &GeneratorOptions) -> Result<()> {
+        let options = GeneratorOptions { strict: true, ..options.clone() };
+        let function = self.generate_unformatted_function(path, &options)?;
+
+        // Code parts are only checked one by one, so e.g. unbalanced braces only
+        // show up once the whole function is parsed.
+        syn::parse_file(&function.code).with_context(|| invalid_rust(path))?;
+
+        Ok(())
+    }
+
+    // Generates a `mod <module_name> { ... }` containing the functions of all templates.
+    // With `ModuleLayout::Tree` and `ModuleLayout::Modules` it contains nested modules mirroring
+    // the template directories.
+    pub fn generate_module(&self, module_name: &str, options: &GeneratorOptions) -> Result<String> {
+        let functions = self.generate_unformatted(options)?;
+
+        try_format_code_with(&module_tree(module_name, &functions, options)?, options.formatter).context("generated code is not valid Rust")
+    }
+}
+
+// Context of errors parsing the code generated for the template under `path`.
+fn invalid_rust(path: &str) -> Diagnostic {
+    Diagnostic::error(path, None, "invalid-rust", "generated code is not valid Rust")
+}
+
+// Unformatted code of `mod <module_name> { ... }` holding the functions within their modules.
+pub fn module_tree(module_name: &str, functions: &[GeneratedFunction], options: &GeneratorOptions) -> Result<String> {
+    let root = ModuleNode::new(module_name, functions, options)?;
+
+    let mut code = if options.rustfmt_skip { "#[rustfmt::skip]\n".to_string() } else { String::new() };
+    code.push_str(&format!("pub mod {module_name} {{\n"));
+    root.write_body(options, true, &mut code);
+    if options.registry {
+        code.push_str(&generate_registry(functions, options));
+    }
+    if options.metadata {
+        code.push_str(&crate::reflection::generate_all_templates(functions, options));
+    }
+    code.push_str("}\n");
+
+    Ok(code)
+}
+
+// Unformatted files of the module `<module_name>`, declared using `mod <module_name>;`, with
+// `mod.rs` files for the root module and modules containing other ones, e.g.
+// `templates/mod.rs`, `templates/admin/mod.rs` and `templates/admin/users.rs`.
+pub fn module_files(
+    module_name: &str,
+    functions: &[GeneratedFunction],
+    options: &GeneratorOptions,
+) -> Result<Vec<(PathBuf, String)>> {
+    let root = ModuleNode::new(module_name, functions, options)?;
+
+    let mut files = Vec::new();
+    root.write_files(PathBuf::from(module_name).join("mod.rs"), options, &mut files);
+    if options.registry {
+        files[0].1.push_str(&generate_registry(functions, options));
+    }
+    if options.metadata {
+        files[0].1.push_str(&crate::reflection::generate_all_templates(functions, options));
+    }
+
+    Ok(files)
+}
+
+#[derive(Debug, Default)]
+struct ModuleNode<'a> {
+    functions: Vec<&'a GeneratedFunction>,
+    modules: BTreeMap<String, ModuleNode<'a>>,
+}
+
+impl<'a> ModuleNode<'a> {
+    fn new(module_name: &str, functions: &'a [GeneratedFunction], options: &GeneratorOptions) -> Result<ModuleNode<'a>> {
+        let mut root = ModuleNode::default();
+        for function in functions {
+            let mut node = &mut root;
+            for module in &function.module_path {
+                node = node.modules.entry(module.clone()).or_default();
+            }
+
+            // Different paths can end up with the same names once they're mangled.
+            if let Some(other) = node.functions.iter().find(|other| other.name == function.name) {
+                let mut segments = vec![module_name];
+                segments.extend(function.module_path.iter().map(String::as_str));
+                segments.push(&function.name);
+                bail!("templates `{}` and `{}` both generate `{}`", other.path, function.path, segments.join("::"));
+            }
+            node.functions.push(function);
+        }
+
+        if options.module_layout == ModuleLayout::Modules {
+            root.check_template_modules()?;
+        }
+
+        Ok(root)
+    }
+
+    // With `ModuleLayout::Modules`, modules of templates can't also be directories.
+    fn check_template_modules(&self) -> Result<()> {
+        if let (Some(function), Some(directory)) = (self.functions.first(), self.modules.keys().next()) {
+            bail!(
+                "template `{}` can't have the same module as the directory `{}`, which contains `{directory}`",
+                function.path,
+                function.path.strip_suffix(".plt").unwrap_or(&function.path)
+            );
+        }
+
+        self.modules.values().try_for_each(ModuleNode::check_template_modules)
+    }
+
+    fn is_template_module(&self, options: &GeneratorOptions) -> bool {
+        options.module_layout == ModuleLayout::Modules && !self.functions.is_empty()
+    }
+
+    // Writes the functions followed by the submodules, which are either written inline or
+    // declared as `mod <name>;` to be read from their own files.
+    fn write_body(&self, options: &GeneratorOptions, inline: bool, code: &mut String) {
+        for function in &self.functions {
+            code.push_str(&function.code);
+            code.push('\n');
+        }
+
+        for (name, module) in &self.modules {
+            let visibility = if module.is_template_module(options) { "" } else { "pub " };
+            if options.rustfmt_skip {
+                code.push_str("#[rustfmt::skip]\n");
+            }
+            if inline {
+                code.push_str(&format!("{visibility}mod {name} {{\n"));
+                module.write_body(options, true, code);
+                code.push_str("}\n");
+            } else {
+                code.push_str(&format!("{visibility}mod {name};\n"));
+            }
+
+            if module.is_template_module(options) {
+                code.push_str(&format!("{} use self::{name}::*;\n", options.visibility));
+            }
+        }
+    }
+
+    fn write_files(&self, file: PathBuf, options: &GeneratorOptions, files: &mut Vec<(PathBuf, String)>) {
+        let mut code = String::new();
+        self.write_body(options, false, &mut code);
+
+        let directory = file.parent().map(Path::to_path_buf).unwrap_or_default();
+        files.push((file, code));
+
+        for (name, module) in &self.modules {
+            let file = if module.modules.is_empty() {
+                directory.join(format!("{name}.rs"))
+            } else {
+                directory.join(name).join("mod.rs")
+            };
+            module.write_files(file, options, files);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use std::fs::read_to_string;
+
+    fn test_set() -> TemplateSet {
+        let mut set = TemplateSet::new();
+        set.add_template(
+            "index.plt",
+            vec!["title: &str".to_string(), "user: &str".to_string()],
+            read_to_string("src/test-files/template_set_01.plt").unwrap(),
+        );
+        set.add_template(
+            "partials/header.plt",
+            vec!["title: &str".to_string(), "user: &str".to_string()],
+            read_to_string("src/test-files/template_set_02.plt").unwrap(),
+        );
+        set
+    }
+
+    #[test]
+    fn it_derives_function_names_from_paths() {
+        assert_eq!(template_function_name("partials/header.plt", NameMangling::Underscore), "partials_header");
+        assert_eq!(template_function_name("404.plt", NameMangling::Underscore), "_404");
+        assert_eq!(template_function_name("user-list.html.plt", NameMangling::Underscore), "user_list_html");
+    }
+
+    #[test]
+    fn it_splits_paths_into_modules() {
+        assert_eq!(template_module_path("index.plt", NameMangling::Underscore), (Vec::new(), "index".to_string()));
+        assert_eq!(
+            template_module_path("admin/users/list.plt", NameMangling::Underscore),
+            (vec!["admin".to_string(), "users".to_string()], "list".to_string())
+        );
+        assert_eq!(
+            template_module_path("mod/type.plt", NameMangling::Underscore),
+            (vec!["mod_".to_string()], "type_".to_string())
+        );
+    }
+
+    #[test]
+    fn it_mangles_names_into_identifiers() {
+        assert_eq!(template_function_name("UserList.plt", NameMangling::Underscore), "userlist");
+        assert_eq!(template_function_name("admin/UserList.plt", NameMangling::Snake), "admin_user_list");
+        assert_eq!(template_function_name("HTMLPage2Col.plt", NameMangling::Snake), "html_page2_col");
+        assert_eq!(template_function_name("admin/user_list.plt", NameMangling::Reject), "admin_user_list");
+
+        let mut set = TemplateSet::new();
+        set.add_template("admin/user-list.plt", Vec::new(), "users".to_string());
+        let options = GeneratorOptions { name_mangling: NameMangling::Reject, ..GeneratorOptions::default() };
+        assert_eq!(
+            set.generate_all(&options).unwrap_err().to_string(),
+            "admin/user-list.plt: `user-list` is not a valid identifier, rename it or use another name mangling"
+        );
+    }
+
+    #[test]
+    fn it_generates_all_templates_in_path_order() {
+        let set = test_set();
+
+        let report = set.generate_all(&GeneratorOptions::default()).unwrap();
+        let functions = &report.functions;
+
+        assert_eq!(functions.len(), 2);
+        assert_eq!(functions[0].path, "index.plt");
+        assert_eq!(functions[0].name, "index");
+        assert_eq!(functions[1].path, "partials/header.plt");
+        assert_eq!(functions[1].name, "partials_header");
+        assert!(functions[1].code.starts_with("pub fn partials_header("));
+
+        let stats = &report.templates[1];
+        assert_eq!(stats.path, "partials/header.plt");
+        assert_eq!((stats.code_parts, stats.echo_parts), (0, 2));
+        assert_eq!(stats.code_bytes, functions[1].code.len());
+        assert_eq!(report.templates[0].code_parts, 1);
+    }
+
+    #[test]
+    fn it_skips_formatting_when_asked_to() {
+        let set = test_set();
+
+        let options = GeneratorOptions {
+            module_layout: ModuleLayout::Tree,
+            formatter: CodeFormatter::Compact,
+            rustfmt_skip: true,
+            ..GeneratorOptions::default()
+        };
+        let code = set.generate_module("templates", &options).unwrap();
+
+        assert_eq!(code.lines().count(), 1);
+        assert!(code.starts_with("# [rustfmt :: skip] pub mod templates { # [rustfmt :: skip] pub fn index ("));
+        assert!(code.contains("# [rustfmt :: skip] pub mod partials { # [rustfmt :: skip] pub fn header ("));
+    }
+
+    #[test]
+    fn it_generates_a_module_tree_mirroring_directories() {
+        let set = test_set();
+
+        let options = GeneratorOptions { module_layout: ModuleLayout::Tree, ..GeneratorOptions::default() };
+        let code = set.generate_module("templates", &options).unwrap();
+
+        assert!(code.starts_with("pub mod templates {\n    pub fn index("));
+        assert!(code.contains("output_buffer.push_str(&partials::header(title, user)?);"));
+        assert!(code.contains("    pub mod partials {\n        pub fn header("));
+    }
+
+    #[test]
+    fn it_generates_a_function_per_section() {
+        let mut set = TemplateSet::new();
+        set.add_template("components.plt", Vec::new(), read_to_string("src/test-files/sections.plt").unwrap());
+        set.add_template("index.plt", Vec::new(), "<?rs @include \"components.plt#card\" with (\"Hi\") ?>".to_string());
+
+        let functions = set.generate_all(&GeneratorOptions::default()).unwrap().functions;
+        let names: Vec<_> = functions.iter().map(|function| function.name.as_str()).collect();
+        assert_eq!(names, vec!["components", "components_badge", "components_card", "index"]);
+        assert!(!functions[0].code.contains("badge"));
+        assert!(functions[1].code.contains("pub fn components_badge(label: &str)"));
+        assert!(functions[2].code.contains("output_buffer.push_str(&components_badge(title)?);"));
+        assert!(functions[3].code.contains("output_buffer.push_str(&components_card(\"Hi\")?);"));
+
+        set.add_template("components.plt", Vec::new(), "<?rs @template badge ?><?rs @endtemplate ?>".to_string());
+        assert_eq!(set.sections("components.plt").len(), 1);
+
+        set.add_template("broken.plt", Vec::new(), "<?rs @template a ?><?rs @template b ?><?rs @endtemplate ?>".to_string());
+        let error = set.generate("broken.plt").unwrap_err();
+        assert!(error.to_string().contains("`@template b` must not be nested inside of other directives"), "{error:#}");
+    }
+
+    #[test]
+    fn it_generates_macros_called_across_templates() {
+        let mut set = TemplateSet::new();
+        set.add_template("macros.plt", Vec::new(), read_to_string("src/test-files/macros.plt").unwrap());
+        set.add_template("index.plt", Vec::new(), "<?rs @use badge(\"New\", \"green\") ?><?rs @use divider ?>".to_string());
+
+        let functions = set.generate_all(&GeneratorOptions::default()).unwrap().functions;
+        let names: Vec<_> = functions.iter().map(|function| function.name.as_str()).collect();
+        assert_eq!(names, vec!["index", "macros", "macros_badge", "macros_divider"]);
+        assert!(functions[0].code.contains("output_buffer.push_str(&macros_badge(\"New\", \"green\")?);"));
+        assert!(functions[0].code.contains("output_buffer.push_str(&macros_divider()?);"));
+        assert!(functions[2].code.contains("pub fn macros_badge(label: &str, color: &str)"));
+        assert_eq!(set.dependents("macros.plt#badge").unwrap(), vec!["index.plt"]);
+
+        // Macros of the user's own file take precedence over the ones of other files.
+        set.add_template("page.plt", Vec::new(), "<?rs @def divider ?><br><?rs @enddef ?><?rs @use divider ?>".to_string());
+        assert!(set.generate("page.plt").unwrap().join("\n").contains("output_buffer.push_str(&page_divider()?);"));
+        let error = set.generate("index.plt").unwrap_err();
+        assert!(error.to_string().contains("uses macro `divider`, which is defined by more than one template: macros.plt, page.plt"), "{error:#}");
+
+        set.add_template("broken.plt", Vec::new(), "<?rs @def a ?><?rs @endtemplate ?>".to_string());
+        let error = set.generate("broken.plt").unwrap_err();
+        assert!(error.to_string().contains("`@endtemplate` found without matching `@template`"), "{error:#}");
+    }
+
+    #[test]
+    fn it_loads_templates_from_a_search_path() {
+        let roots = [
+            TemplateRoot::new("src/test-files/roots/app"),
+            TemplateRoot::new("src/test-files/roots/shared"),
+            "emails=src/test-files/roots/emails".parse().unwrap(),
+        ];
+        let set = TemplateSet::load_roots(&roots).unwrap();
+
+        let paths: Vec<_> = set.paths().map(String::as_str).collect();
+        assert_eq!(paths, vec!["emails::footer.plt", "index.plt", "partials/footer.plt", "partials/header.plt"]);
+        assert_eq!(set.get("partials/header.plt").unwrap().source, "<header>App</header>\n");
+        assert_eq!(set.file("emails::footer.plt"), Some(std::path::Path::new("src/test-files/roots/emails/footer.plt")));
+
+        let options = GeneratorOptions { module_layout: ModuleLayout::Tree, ..GeneratorOptions::default() };
+        assert_eq!(set.module_path("emails::footer.plt", &options), vec!["emails"]);
+        assert_eq!(set.function_name("emails::footer.plt", &GeneratorOptions::default()), "emails_footer");
+        assert!(set.generate("index.plt").unwrap().join("\n").contains("output_buffer.push_str(&emails_footer()?);"));
+
+        let lints = set.lint("partials/header.plt", &GeneratorOptions::default());
+        let shadowed = lints.iter().find(|diagnostic| diagnostic.code == "shadowed-template").unwrap();
+        assert_eq!(shadowed.severity, Severity::Warning);
+        assert_eq!(
+            shadowed.message,
+            "template `partials/header.plt` shadows `src/test-files/roots/shared/partials/header.plt`, which comes later in the search path"
+        );
+
+        let error = TemplateSet::load_roots(&[TemplateRoot::namespaced("e-mails", "src/test-files/roots/emails")]).unwrap_err();
+        assert_eq!(error.to_string(), "namespace `e-mails` of `src/test-files/roots/emails` is not a valid identifier");
+    }
+
+    #[test]
+    fn it_generates_a_registry_rendering_templates_by_name() {
+        let mut set = TemplateSet::new();
+        set.add_template("emails/welcome.plt", vec!["name: &str".to_string(), "ids: &[u32]".to_string()], "<p><?= name ?></p>".to_string());
+        set.add_template("layout.plt", Vec::new(), "<?rs @yield content ?>".to_string());
+        set.add_template("csp.plt", Vec::new(), "<?rs @nonce ?>".to_string());
+        let options = GeneratorOptions { module_layout: ModuleLayout::Tree, registry: true, ..GeneratorOptions::default() };
+
+        let code = set.generate_module("templates", &options).unwrap().split_whitespace().collect::<Vec<_>>().join(" ");
+        assert!(code.contains("pub fn render_by_name( name: &str, context: &plt::registry::Value, ) -> plt::prelude::Result<String> { match name {"));
+        assert!(code.contains(
+            "\"emails/welcome.plt\" => { emails::welcome( &plt::registry::arg::<String>(name, context, \"name\")?, &plt::registry::arg::<Vec<u32>>(name, context, \"ids\")?, ) }"
+        ));
+        assert!(!code.contains("\"layout.plt\" =>"));
+        assert!(!code.contains("\"csp.plt\" =>"));
+        assert!(code.contains("_ => Err(plt::registry::unknown_template(name)),"));
+
+        assert!(!set.generate_module("templates", &GeneratorOptions::default()).unwrap().contains("render_by_name"));
+    }
+
+    #[test]
+    fn it_generates_a_module_per_template() {
+        let set = test_set();
+
+        let options = GeneratorOptions { module_layout: ModuleLayout::Modules, ..GeneratorOptions::default() };
+        let code = set.generate_module("templates", &options).unwrap();
+
+        assert!(code.starts_with("pub mod templates {\n    mod index {\n        pub fn index("));
+        assert!(code.contains("output_buffer.push_str(&super::partials::header(title, user)?);"));
+        assert!(code.contains("    pub use self::index::*;\n    pub mod partials {\n        mod header {"));
+        assert!(code.contains("        pub use self::header::*;"));
+
+        let functions = set.generate_all(&options).unwrap().functions;
+        let files = module_files("templates", &functions, &options).unwrap();
+        let paths: Vec<_> = files.iter().map(|(path, _)| path.to_str().unwrap()).collect();
+        assert_eq!(
+            paths,
+            vec!["templates/mod.rs", "templates/index.rs", "templates/partials/mod.rs", "templates/partials/header.rs"]
+        );
+        assert_eq!(files[0].1, "mod index;\npub use self::index::*;\npub mod partials;\n");
+    }
+
+    #[test]
+    fn it_rejects_clashing_names() {
+        let mut set = TemplateSet::new();
+        set.add_template("user-list.plt", Vec::new(), "a".to_string());
+        set.add_template("user_list.plt", Vec::new(), "b".to_string());
+
+        let error = set.generate_module("templates", &GeneratorOptions::default()).unwrap_err();
+        assert_eq!(error.to_string(), "templates `user-list.plt` and `user_list.plt` both generate `templates::user_list`");
+
+        let mut set = TemplateSet::new();
+        set.add_template("admin.plt", Vec::new(), "a".to_string());
+        set.add_template("admin/users.plt", Vec::new(), "b".to_string());
+
+        let options = GeneratorOptions { module_layout: ModuleLayout::Modules, ..GeneratorOptions::default() };
+        assert_eq!(
+            set.generate_module("templates", &options).unwrap_err().to_string(),
+            "template `admin.plt` can't have the same module as the directory `admin`, which contains `users`"
+        );
+    }
+
+    fn dependency_test_set() -> TemplateSet {
+        let mut set = TemplateSet::new();
+        set.add_template("layout.plt", Vec::new(), "<?rs @yield content ?>".to_string());
+        set.add_template("partials/header.plt", Vec::new(), "header".to_string());
+        set.add_template("components/card.plt", Vec::new(), "<?rs @include \"partials/header.plt\" ?><?rs @slot ?>".to_string());
+        set.add_template(
+            "index.plt",
+            Vec::new(),
+            "<?rs @extends \"layout.plt\" ?><?rs @block content ?><?rs @call \"components/card.plt\" ?>x<?rs @endcall ?><?rs @endblock ?>".to_string(),
+        );
+        set.add_template("about.plt", Vec::new(), "<?rs @extends \"layout.plt\" ?>".to_string());
+        set
+    }
+
+    #[test]
+    fn it_finds_transitive_dependents() {
+        let set = dependency_test_set();
+
+        assert_eq!(set.dependents("partials/header.plt").unwrap(), vec!["components/card.plt", "index.plt"]);
+        assert_eq!(set.dependents("layout.plt").unwrap(), vec!["about.plt", "index.plt"]);
+        assert!(set.dependents("about.plt").unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_regenerates_only_affected_templates() {
+        let set = dependency_test_set();
+
+        let functions = set.regenerate_changed(&["components/card.plt"], &GeneratorOptions::default()).unwrap();
+        let paths: Vec<_> = functions.iter().map(|function| function.path.as_str()).collect();
+        assert_eq!(paths, vec!["components/card.plt", "index.plt"]);
+
+        let functions = set.regenerate_changed(&["removed.plt", "about.plt"], &GeneratorOptions::default()).unwrap();
+        let paths: Vec<_> = functions.iter().map(|function| function.path.as_str()).collect();
+        assert_eq!(paths, vec!["about.plt"]);
+    }
+
+    #[test]
+    fn it_normalizes_line_endings_of_loaded_templates() {
+        let dir = std::env::temp_dir().join(format!("plt-line-endings-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.plt"), "<p>\r\n<?= 1 ?>\r\n</p>\r\n").unwrap();
+
+        let set = TemplateSet::load_dir(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(set.unwrap().get("index.plt").unwrap().source, "<p>\n<?= 1 ?>\n</p>\n");
+    }
+
+    #[test]
+    fn it_computes_line_and_column() {
+        assert_eq!(line_column("abc", 0), (1, 1));
+        assert_eq!(line_column("ab\r\nżcd", 6), (2, 2));
+    }
+
+    #[test]
+    fn it_uses_declared_args() {
+        let mut set = TemplateSet::new();
+        set.add_template("index.plt", vec!["a: u8".to_string()], "<?rs @args b: u16 ?><?= a + b as u8 ?>".to_string());
+
+        assert_eq!(set.get("index.plt").unwrap().args, vec!["a: u8", "b : u16"]);
+    }
+
+    #[test]
+    fn it_takes_the_nonce_of_templates_using_it() {
+        let mut set = TemplateSet::new();
+        set.add_template("index.plt", Vec::new(), "<script <?rs @nonce ?>></script>".to_string());
+        set.add_template("declared.plt", Vec::new(), "<?rs @args csp_nonce: &Nonce ?><style <?rs @nonce ?>></style>".to_string());
+
+        assert_eq!(set.get("index.plt").unwrap().args, vec!["csp_nonce: &plt::csp::Nonce"]);
+        assert_eq!(set.get("declared.plt").unwrap().args, vec!["csp_nonce : & Nonce"]);
+
+        let code = set.generate("index.plt").unwrap().join("\n");
+        assert!(code.contains("pub fn index(csp_nonce: &plt::csp::Nonce)"));
+        assert!(code.contains("output_buffer.push_str(&csp_nonce.attribute());"));
+    }
+
+    #[test]
+    fn it_takes_the_csrf_token_provider_of_templates_using_it() {
+        let mut set = TemplateSet::new();
+        set.add_template("form.plt", Vec::new(), "<?rs @args action: &str ?><form><?rs @csrf ?></form>".to_string());
+
+        assert_eq!(set.get("form.plt").unwrap().args, vec!["action : & str", "csrf: &dyn plt::csrf::CsrfTokenProvider"]);
+
+        let code = set.generate("form.plt").unwrap().join("\n");
+        assert!(code.contains("output_buffer.push_str(&plt::csrf::hidden_input(&csrf)?);"));
+    }
+
+    #[test]
+    fn it_takes_the_router_of_templates_using_it() {
+        let mut set = TemplateSet::new();
+        set.add_template("post.plt", Vec::new(), "<a href=\"<?rs @url posts.show(id = post.id) ?>\">".to_string());
+
+        assert_eq!(set.get("post.plt").unwrap().args, vec!["routes: &dyn plt::routes::UrlFor"]);
+
+        let code = set.generate("post.plt").unwrap().join("\n");
+        assert!(code.contains("routes.url_for(\"posts.show\", &[(\"id\", &(post . id) as &dyn std::fmt::Display)])?"));
+    }
+
+    #[test]
+    fn it_takes_the_fragment_cache_of_templates_using_it() {
+        let mut set = TemplateSet::new();
+        set.add_template("index.plt", Vec::new(), "<?rs @cache(\"sidebar\", ttl = 60) ?><aside></aside><?rs @endcache ?>".to_string());
+
+        assert_eq!(set.get("index.plt").unwrap().args, vec!["fragment_cache: &dyn plt::runtime::FragmentCache"]);
+
+        let code = set.generate("index.plt").unwrap().join("\n");
+        assert!(code.contains(
+            "let cache_output = plt::runtime::cached(fragment_cache, &(\"sidebar\"), Some(std::time::Duration::from_secs((60) as u64)), || -> plt::prelude::Result<String> {"
+        ));
+        assert!(code.contains("Ok(output_buffer)\n})?;\noutput_buffer.push_str(&cache_output);"));
+    }
+
+    #[test]
+    fn it_reports_error_locations() {
+        let mut set = TemplateSet::new();
+        set.add_template("unterminated.plt", Vec::new(), "line\n  <?= 1 ".to_string());
+        set.add_template("unbalanced.plt", Vec::new(), "line\n<?rs @endmatch ?>".to_string());
+        set.add_template("unclosed.plt", Vec::new(), "\n\n  <?rs @capture a ?>".to_string());
+
+        let error = set.generate("unterminated.plt").unwrap_err();
+        assert_eq!(error.to_string(), "unterminated.plt:2:3: unterminated tag, expected `?>`");
+
+        let error = set.generate("unbalanced.plt").unwrap_err();
+        assert_eq!(error.to_string(), "unbalanced.plt:2:5: `@endmatch` found without matching `@match`");
+
+        let error = set.generate("unclosed.plt").unwrap_err();
+        assert_eq!(error.to_string(), "unclosed.plt:3:7: `@capture` is missing its `@endcapture`");
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn it_generates_templates_in_parallel() {
+        let mut set = test_set();
+        for index in 0..16 {
+            set.add_template(format!("pages/page{index}.plt"), vec!["n: u32".to_string()], "<p><?= n ?></p>".to_string());
+        }
+
+        let options = GeneratorOptions::default();
+        let report = set.generate_all_parallel(&options).unwrap();
+        assert_eq!(report.functions, set.generate_all(&options).unwrap().functions);
+
+        set.add_template("broken.plt", Vec::new(), "<?rs @include \"missing.plt\" ?>".to_string());
+        set.add_template("unbalanced.plt", Vec::new(), "<?rs if true { ?>".to_string());
+        let error = set.generate_all_parallel(&options).unwrap_err();
+        let files: Vec<_> = error.downcast_ref::<crate::build::CompileError>().unwrap().diagnostics.iter().map(|diagnostic| diagnostic.file.as_str()).collect();
+        assert_eq!(files, vec!["broken.plt", "unbalanced.plt"]);
+    }
+
+    #[test]
+    fn it_checks_all_templates() {
+        let mut set = test_set();
+        assert!(set.check(&GeneratorOptions::default()).is_empty());
+
+        set.add_template("broken.plt", Vec::new(), "<?rs @include \"missing.plt\" ?>".to_string());
+        set.add_template("invalid.plt", Vec::new(), "<?= 1 + ?>".to_string());
+        set.add_template("unbalanced.plt", Vec::new(), "<?rs if true { ?>".to_string());
+
+        let errors: Vec<_> = set.check(&GeneratorOptions::default()).iter().map(|error| error.to_string()).collect();
+
+        assert_eq!(
+            errors,
+            vec![
+                "template `broken.plt` includes `missing.plt`, which is not part of the template set",
+                "invalid.plt:1:4: invalid Rust expression `1 +`: unexpected end of input, expected an expression",
+                "unbalanced.plt: generated code is not valid Rust",
+            ]
+        );
+
+        let diagnostics = set.diagnostics(&GeneratorOptions::default());
+        let codes: Vec<_> = diagnostics.iter().map(|diagnostic| diagnostic.code.as_str()).collect();
+        assert_eq!(codes, vec!["error", "missing_lang", "invalid-template", "invalid-rust"]);
+        assert_eq!(diagnostics.diagnostics[2].span.map(|span| (span.start, span.end)), Some((3, 8)));
+        assert!(diagnostics.diagnostics[3].message.starts_with("generated code is not valid Rust: "));
+    }
+
+    #[test]
+    fn it_prefixes_function_names() {
+        let set = test_set();
+
+        let options = GeneratorOptions { function_prefix: "render_".to_string(), ..GeneratorOptions::default() };
+        let functions = set.generate_all(&options).unwrap().functions;
+
+        assert_eq!(functions[1].name, "render_partials_header");
+        assert!(functions[0].code.contains("output_buffer.push_str(&render_partials_header(title, user)?);"));
+    }
+
+    #[test]
+    fn it_names_functions_with_naming_strategies() {
+        let set = test_set();
+
+        let options = GeneratorOptions { naming: NamingStrategy::FileName, function_suffix: "_html".to_string(), ..GeneratorOptions::default() };
+        let functions = set.generate_all(&options).unwrap().functions;
+        assert_eq!(functions[1].name, "header_html");
+        assert!(functions[0].code.contains("output_buffer.push_str(&header_html(title, user)?);"));
+
+        let naming = NamingStrategy::custom(|path| format!("render_{}", path.rsplit('/').next().unwrap().trim_end_matches(".plt")));
+        let functions = set.generate_all(&GeneratorOptions { naming, ..GeneratorOptions::default() }).unwrap().functions;
+        assert_eq!(functions[1].name, "render_header");
+
+        let naming = NamingStrategy::custom(|path| path.to_string());
+        let error = set.generate_all(&GeneratorOptions { naming, ..GeneratorOptions::default() }).unwrap_err();
+        assert!(error.to_string().contains("function name `index.plt` is not a valid identifier"), "{error:#}");
+    }
+
+    #[test]
+    fn it_generates_template_structs_in_struct_mode() {
+        let set = test_set();
+
+        let options = GeneratorOptions { struct_mode: true, ..GeneratorOptions::default() };
+        let code = set.generate_module("templates", &options).unwrap();
+
+        assert!(code.contains("pub struct IndexTemplate<'a> {"));
+        assert!(code.contains("pub struct PartialsHeaderTemplate<'a> {"));
+    }
+
+    #[test]
+    fn it_generates_block_functions_in_struct_mode() {
+        let mut set = TemplateSet::new();
+        set.add_template("page.plt", Vec::new(), "<?rs @extends \"layout.plt\" ?><?rs @block body ?>Results<?rs @endblock ?>".to_string());
+        set.add_template("layout.plt", Vec::new(), "<main><?rs @block body ?>Default<?rs @endblock ?></main>".to_string());
+
+        let options = GeneratorOptions { struct_mode: true, ..GeneratorOptions::default() };
+        let code = set.generate_module("templates", &options).unwrap();
+
+        assert!(code.contains("pub fn page_block_body() -> plt::prelude::Result<String> {"));
+        assert!(code.contains("rendered_block = Some(block_body);\n        Ok(rendered_block.unwrap_or_default())"));
+        assert!(code.contains("pub fn layout_block_body(block_body: Option<&str>) -> plt::prelude::Result<String> {"));
+        assert!(code.contains("rendered_block = Some(\n            if let Some(block) = block_body {\n                block.to_string()"));
+        assert!(code.contains("\"body\" => layout_block_body(None),"));
+    }
+
+    #[test]
+    fn it_generates_async_write_functions() {
+        let mut set = test_set();
+        set.add_template("page.plt", Vec::new(), "<?rs @extends \"layout.plt\" ?><?rs @block body ?><?= 1 ?><?rs @endblock ?>".to_string());
+        set.add_template("layout.plt", Vec::new(), "<main><?rs @yield body ?></main>".to_string());
+
+        let options = GeneratorOptions { async_write: true, ..GeneratorOptions::default() };
+        let code = set.generate_module("templates", &options).unwrap();
+
+        assert!(code.contains(
+            "pub async fn index_write(\n        output_writer: &mut (impl plt::tokio::AsyncWrite + Unpin),\n        title: &str,\n        user: &str,\n    ) -> plt::prelude::Result<()> {"
+        ));
+        assert!(code.contains("partials_header_write(output_writer, title, user).await?;"));
+        assert!(code.contains("output_writer.write_all(\"<main>\".as_bytes()).await?;"));
+        assert!(code.contains("output_writer.write_all(block.as_bytes()).await?;"));
+        // Block overrides are rendered into a buffer, which is handed to the layout.
+        assert!(code.contains("write!(output_buffer, \"{}\", (1))?;"));
+        assert!(code.contains("layout_write(output_writer, Some(&block_body)).await?;"));
+    }
+
+    #[test]
+    fn it_generates_render_stream_methods() {
+        let mut set = TemplateSet::new();
+        set.add_template(
+            "index.plt",
+            vec!["items: &[u32]".to_string()],
+            "<ul><?rs @for item in items ?><li><?= item ?></li><?rs @endfor ?></ul><?rs @capture x ?>a<?rs @endcapture ?>".to_string(),
+        );
+
+        let options = GeneratorOptions { struct_mode: true, stream: true, ..GeneratorOptions::default() };
+        let code = set.generate_module("templates", &options).unwrap();
+
+        assert!(code.contains("impl<'a> IndexTemplate<'a> {\n        pub fn render_stream(self) -> plt::stream::RenderStream<'a> {"));
+        assert!(code.contains("let IndexTemplate { items } = self;"));
+        assert!(code.contains("write!(output_buffer, \"{}\", (item))?;\n                    output_buffer.flush().await;"));
+        // Captures write into their own buffer, which isn't streamed.
+        assert!(code.contains("write!(output_buffer, \"{}\", \"a\")?;\n                    output_buffer\n"));
+    }
+
+    #[test]
+    fn it_compiles_includes_to_function_calls() {
+        let set = test_set();
+
+        let code = format_code(&set.generate("index.plt").unwrap().join("\r\n"));
+
+        assert!(code.contains("fn index(title: &str, user: &str)"));
+        assert!(code.contains("output_buffer.push_str(&partials_header(title, user)?);"));
+    }
+
+    #[test]
+    fn it_reports_missing_includes() {
+        let mut set = test_set();
+        set.add_template("broken.plt", Vec::new(), "<?rs @include \"missing.plt\" ?>".to_string());
+
+        let error = set.generate("broken.plt").unwrap_err();
+
+        assert_eq!(error.to_string(), "template `broken.plt` includes `missing.plt`, which is not part of the template set");
+    }
+
+    #[test]
+    fn it_limits_the_depth_of_recursive_includes() {
+        let mut set = TemplateSet::new();
+        set.add_template("a.plt", Vec::new(), "<?rs @include \"b.plt\" ?>".to_string());
+        set.add_template("b.plt", Vec::new(), "<?rs @include \"a.plt\" ?>".to_string());
+        set.add_template("c.plt", Vec::new(), "<?rs @include \"a.plt\" ?>".to_string());
+        let options = GeneratorOptions { max_depth: 8, async_write: true, ..GeneratorOptions::default() };
+
+        let code = set.generate_with("a.plt", &options).unwrap().join("\n");
+        assert!(set.is_recursive("b.plt").unwrap());
+        assert!(!set.is_recursive("c.plt").unwrap());
+        assert!(code.contains("let _template_depth = plt::runtime::enter_template(\"a.plt\", 8)?;"));
+        assert!(code.contains("output_writer.write_all(b()?.as_bytes()).await?;"));
+        assert!(!set.generate_with("c.plt", &options).unwrap().join("\n").contains("enter_template"));
+    }
+
+    #[test]
+    fn it_passes_blocks_to_the_extended_layout() {
+        let mut set = TemplateSet::new();
+        set.add_template(
+            "layout.plt",
+            vec!["lang: &str".to_string()],
+            read_to_string("src/test-files/template_set_03.plt").unwrap(),
+        );
+        set.add_template(
+            "page.plt",
+            vec!["name: &str".to_string()],
+            read_to_string("src/test-files/template_set_04.plt").unwrap(),
+        );
+
+        let layout = format_code(&set.generate("layout.plt").unwrap().join("\r\n"));
+        assert!(layout.contains("fn layout(\n    lang: &str,\n    block_title: Option<&str>,\n    block_content: Option<&str>,\n)"));
+
+        let page = format_code(&set.generate("page.plt").unwrap().join("\r\n"));
+        assert!(page.contains("let block_content = {"));
+        assert!(page.contains("output_buffer.push_str(&layout(\"en\", None, Some(&block_content))?);"));
+    }
+
+    #[test]
+    fn it_passes_the_rendered_slot_to_called_components() {
+        let mut set = TemplateSet::new();
+        set.add_template(
+            "components/card.plt",
+            vec!["title: &str".to_string(), "footer: &str".to_string()],
+            read_to_string("src/test-files/template_set_05.plt").unwrap(),
+        );
+        set.add_template(
+            "index.plt",
+            vec!["name: &str".to_string()],
+            read_to_string("src/test-files/template_set_06.plt").unwrap(),
+        );
+
+        let card = set.generate("components/card.plt").unwrap().join("\r\n");
+        assert!(card.contains("fn components_card(title: &str, footer: &str, slot: &str)"));
+        assert!(card.contains("output_buffer.push_str(slot);"));
+
+        let index = format_code(&set.generate("index.plt").unwrap().join("\r\n"));
+        assert!(index.contains("let slot = {"));
+        assert!(index.contains("output_buffer.push_str(&components_card(\"Hi\", \"Bye\", &slot)?);"));
+    }
+
+    #[test]
+    fn it_reports_mismatched_component_arguments() {
+        let mut set = TemplateSet::new();
+        set.add_template("card.plt", vec!["title: &str".to_string()], "<?rs @slot ?>".to_string());
+        set.add_template("missing.plt", Vec::new(), "<?rs @call \"card.plt\" ?><?rs @endcall ?>".to_string());
+        set.add_template(
+            "unknown.plt",
+            Vec::new(),
+            "<?rs @call \"card.plt\" (title = \"Hi\", color = \"red\") ?><?rs @endcall ?>".to_string(),
+        );
+
+        let error = set.generate("missing.plt").unwrap_err();
+        assert_eq!(error.to_string(), "missing.plt:1:5: template `missing.plt` calls `card.plt` without its `title` argument");
+
+        let error = set.generate("unknown.plt").unwrap_err();
+        assert_eq!(error.to_string(), "unknown.plt:1:5: template `unknown.plt` passes `color` to `card.plt`, which has no such argument");
+    }
+
+    #[test]
+    fn it_reports_missing_layouts() {
+        let mut set = TemplateSet::new();
+        set.add_template("page.plt", Vec::new(), "<?rs @extends \"layout.plt\" ?>".to_string());
+
+        let error = set.generate("page.plt").unwrap_err();
+
+        assert_eq!(error.to_string(), "template `page.plt` extends `layout.plt`, which is not part of the template set");
+    }
+}
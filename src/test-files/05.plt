@@ -0,0 +1,6 @@
+<!DOCTYPE html>
+<html>
+    <head>
+        <title><?rs "hello world" /* some ?> comment */ ?></title>
+    </head>
+</html>
\ No newline at end of file
@@ -0,0 +1,3 @@
+<p>
+</p>
+<?rs @endmatch ?>
\ No newline at end of file
@@ -0,0 +1,3 @@
+<?rs @args name: &str ?>
+<?rs @include "partials/header.plt" with (name) ?>
+<p>Hello, <?= name ?>!</p>
\ No newline at end of file
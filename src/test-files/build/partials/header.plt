@@ -0,0 +1,2 @@
+<?rs @args title: &str ?>
+<h1><?= title ?></h1>
\ No newline at end of file
@@ -0,0 +1,2 @@
+<?rs @args user: &str ?>
+<?rs @dump user ?>
@@ -0,0 +1,8 @@
+<ul>
+<?rs @match status ?>
+<?rs @when Status::Active ?>
+    <li>Active</li>
+<?rs @when _ ?>
+    <li>Inactive</li>
+<?rs @endmatch ?>
+</ul>
\ No newline at end of file
@@ -0,0 +1,6 @@
+<?rs @let total = items.iter().map(|i| i.price).sum::<u32>() ?>
+<?rs @capture title ?>Cart (<?= total ?>)<?rs @endcapture ?>
+<html>
+    <head><title><?= title ?></title></head>
+    <body><h1><?= title ?></h1></body>
+</html>
\ No newline at end of file
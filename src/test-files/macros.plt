@@ -0,0 +1,4 @@
+<?rs @def badge(label: &str, color: &str) ?>
+<span class="badge badge-<?= color ?>"><?= label ?></span>
+<?rs @enddef ?>
+<?rs @def divider ?><hr><?rs @enddef ?>
@@ -0,0 +1,3 @@
+<?rs @include "partials/header.plt" ?>
+<?rs @include "partials/footer.plt" ?>
+<?rs @include "emails::footer.plt" ?>
@@ -0,0 +1 @@
+<p>Unsubscribe</p>
@@ -0,0 +1 @@
+<footer>Shared</footer>
@@ -0,0 +1 @@
+<header>Shared</header>
@@ -0,0 +1,6 @@
+<?rs @template badge(label: &str) ?>
+<span class="badge"><?= label ?></span>
+<?rs @endtemplate ?>
+<?rs @template card(title: &str) ?>
+<div class="card"><?rs @include "#badge" with (title) ?></div>
+<?rs @endtemplate ?>
@@ -0,0 +1 @@
+<p><?= missing ?></p>
\ No newline at end of file
@@ -0,0 +1 @@
+<p><?= name | upper ?></p>
\ No newline at end of file
@@ -0,0 +1,5 @@
+<!DOCTYPE html>
+<html>
+<?rs @include "partials/header.plt" with (title, user) ?>
+    <body>Content</body>
+</html>
\ No newline at end of file
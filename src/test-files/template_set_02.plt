@@ -0,0 +1,4 @@
+    <head>
+        <title><?= title ?></title>
+    </head>
+    <p>Hello, <?= user ?>!</p>
\ No newline at end of file
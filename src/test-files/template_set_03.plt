@@ -0,0 +1,5 @@
+<!DOCTYPE html>
+<html lang="<?= lang ?>">
+<head><title><?rs @block title ?>Default title<?rs @endblock ?></title></head>
+<body><?rs @yield content ?></body>
+</html>
\ No newline at end of file
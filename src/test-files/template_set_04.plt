@@ -0,0 +1,4 @@
+<?rs @extends "layout.plt" with ("en") ?>
+<?rs @block content ?>
+    <p>Hello, <?= name ?>!</p>
+<?rs @endblock ?>
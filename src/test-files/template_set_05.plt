@@ -0,0 +1,5 @@
+<div class="card">
+    <h2><?= title ?></h2>
+    <div class="card-body"><?rs @slot ?></div>
+    <footer><?= footer ?></footer>
+</div>
\ No newline at end of file
@@ -0,0 +1,5 @@
+<main>
+<?rs @call "components/card.plt" (footer = "Bye", title = "Hi") ?>
+    <p>Hello, <?= name ?>!</p>
+<?rs @endcall ?>
+</main>
\ No newline at end of file
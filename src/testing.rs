@@ -0,0 +1,154 @@
+// Snapshot tests of rendered templates, comparing the output with a file next to the tests:
+//
+//     #[test]
+//     fn it_renders_the_index() {
+//         plt::assert_template_output!(Index { title: "Home".to_string() }, @"tests/snapshots/index.html");
+//     }
+//
+// Snapshot paths are relative to the crate being tested. Running the tests with
+// `PLT_UPDATE_SNAPSHOTS=1` writes the rendered output to the snapshot files instead of comparing
+// it, which creates missing snapshots and accepts intended changes.
+use std::fs;
+use std::path::Path;
+use anyhow::{bail, Context};
+use crate::prelude::*;
+
+pub const UPDATE_SNAPSHOTS_VAR: &str = "PLT_UPDATE_SNAPSHOTS";
+
+// Whether snapshots should be rewritten rather than compared.
+pub fn update_snapshots() -> bool {
+    std::env::var(UPDATE_SNAPSHOTS_VAR).is_ok_and(|value| !value.is_empty() && value != "0")
+}
+
+// Compares `output` with the content of the snapshot file, or writes it there when `update` is
+// set. Fails with the differing lines on a mismatch.
+pub fn check_snapshot(output: &str, snapshot: &Path, update: bool) -> Result<()> {
+    if update {
+        if let Some(dir) = snapshot.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("failed to create `{}`", dir.display()))?;
+        }
+        fs::write(snapshot, output).with_context(|| format!("failed to write snapshot `{}`", snapshot.display()))?;
+        return Ok(());
+    }
+
+    if !snapshot.exists() {
+        bail!("snapshot `{}` doesn't exist, run the tests with `{UPDATE_SNAPSHOTS_VAR}=1` to create it", snapshot.display());
+    }
+    let expected = fs::read_to_string(snapshot).with_context(|| format!("failed to read snapshot `{}`", snapshot.display()))?;
+    if expected != output {
+        bail!(
+            "rendered output doesn't match snapshot `{}`, run the tests with `{UPDATE_SNAPSHOTS_VAR}=1` to accept it\n{}",
+            snapshot.display(),
+            line_diff(&expected, output)
+        );
+    }
+
+    Ok(())
+}
+
+// Lines of `expected` and `actual` which differ, as `-` and `+` lines prefixed with their line
+// number.
+fn line_diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<_> = expected.split('\n').collect();
+    let actual: Vec<_> = actual.split('\n').collect();
+    let mut diff = String::new();
+
+    for index in 0..expected.len().max(actual.len()) {
+        let (old, new) = (expected.get(index), actual.get(index));
+        if old == new {
+            continue;
+        }
+        if let Some(old) = old {
+            diff.push_str(&format!("{:>4} - {old}\n", index + 1));
+        }
+        if let Some(new) = new {
+            diff.push_str(&format!("{:>4} + {new}\n", index + 1));
+        }
+    }
+
+    diff
+}
+
+// Value whose rendered output is compared with a snapshot: a `TemplateContext`, or the output of
+// a template function.
+pub trait RenderedOutput {
+    fn rendered_output(&self) -> Result<String>;
+}
+
+impl<T: TemplateContext> RenderedOutput for T {
+    fn rendered_output(&self) -> Result<String> {
+        self.render()
+    }
+}
+
+impl RenderedOutput for String {
+    fn rendered_output(&self) -> Result<String> {
+        Ok(self.clone())
+    }
+}
+
+impl RenderedOutput for Result<String> {
+    fn rendered_output(&self) -> Result<String> {
+        match self {
+            Ok(output) => Ok(output.clone()),
+            Err(error) => Err(anyhow::anyhow!("{error:#}")),
+        }
+    }
+}
+
+// Asserts that the rendered output of a template matches a snapshot file, see `plt::testing`:
+//
+//     plt::assert_template_output!(Index { title: "Home".to_string() }, @"tests/snapshots/index.html");
+//     plt::assert_template_output!(index("Home"), @"tests/snapshots/index.html");
+#[macro_export]
+macro_rules! assert_template_output {
+    ($template:expr, @$snapshot:literal $(,)?) => {{
+        let output = $crate::testing::RenderedOutput::rendered_output(&$template).expect("failed to render the template");
+        let snapshot = ::std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join($snapshot);
+        if let Err(error) = $crate::testing::check_snapshot(&output, &snapshot, $crate::testing::update_snapshots()) {
+            panic!("{error:#}");
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use crate::testing::check_snapshot;
+
+    struct Greeting {
+        name: &'static str,
+    }
+
+    impl TemplateContext for Greeting {
+        fn render(&self) -> Result<String> {
+            Ok(format!("<h1>Hello</h1>\n<p>{}</p>\n", self.name))
+        }
+    }
+
+    #[test]
+    fn it_compares_and_updates_snapshots() {
+        let dir = std::env::temp_dir().join(format!("plt-snapshots-{}", std::process::id()));
+        let snapshot = dir.join("snapshots/greeting.html");
+
+        let error = check_snapshot("a", &snapshot, false).unwrap_err();
+        assert!(error.to_string().contains("doesn't exist, run the tests with `PLT_UPDATE_SNAPSHOTS=1`"));
+
+        let output = Greeting { name: "Ann" }.render().unwrap();
+        check_snapshot(&output, &snapshot, true).unwrap();
+        check_snapshot(&output, &snapshot, false).unwrap();
+
+        let changed = Greeting { name: "Bob" }.render().unwrap();
+        let error = check_snapshot(&changed, &snapshot, false).unwrap_err();
+        assert!(error.to_string().ends_with("\n   2 - <p>Ann</p>\n   2 + <p>Bob</p>\n"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_asserts_rendered_templates_against_snapshot_files() {
+        crate::assert_template_output!(Greeting { name: "Ann" }, @"src/test-files/snapshots/greeting.html");
+        crate::assert_template_output!(Greeting { name: "Ann" }.render(), @"src/test-files/snapshots/greeting.html");
+        crate::assert_template_output!("<p>Ann</p>\n".to_string(), @"src/test-files/snapshots/paragraph.html");
+    }
+}
@@ -1,14 +1,19 @@
-use std::cmp::PartialEq;
+use core::cmp::PartialEq;
+use core::ops::Range;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
 use rustc_lexer::{LiteralKind, Token, TokenKind};
 
-#[derive(Debug, Clone)]
-enum TextCodeFSAState {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
+pub(crate) enum TextCodeFSAState {
     ParsingText,
     ParsingCode,
     ParsingEchoCode,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Hash)]
 pub enum Part {
     Text(String),
     Code(String),
@@ -17,10 +22,7 @@ pub enum Part {
 
 impl Part {
     pub fn is_text(&self) -> bool {
-        match self {
-            Part::Text(_) => true,
-            _ => false,
-        }
+        matches!(self, Part::Text(_))
     }
 
     pub fn add_char_to_content(&mut self, c: char) {
@@ -47,9 +49,16 @@ impl Part {
 pub struct TextCodeFSA {
     state: TextCodeFSAState,
     data: Vec<Part>,
+    // Byte ranges of the parts' content in the payload.
+    spans: Vec<Range<usize>>,
+    // Byte offset of the last opened tag.
+    tag_start: usize,
+    // Byte offset of the first `?>` within the open tag which didn't close it, being part of a
+    // string literal or a comment, along with which of them it was part of.
+    ignored_end_tag: Option<(usize, &'static str)>,
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 pub fn dbg_vec_token(tokens: Vec<Token>, content: &str) {
     let mut token_idx = 0;
     for token in tokens {
@@ -111,128 +120,281 @@ pub fn dbg_vec_token(tokens: Vec<Token>, content: &str) {
     }
 }
 
+impl Default for TextCodeFSA {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TextCodeFSA {
     pub fn new() -> TextCodeFSA {
         Self {
             state: TextCodeFSAState::ParsingText,
             data: Vec::new(),
+            spans: Vec::new(),
+            tag_start: 0,
+            ignored_end_tag: None,
         }
     }
 
-    fn get_last_part_content(&self) -> Option<&str> {
-        if self.data.last().is_some() {
-            Some(self.data.last().unwrap().get_content())
+    // Last token of the code, without collecting the others.
+    #[cfg(feature = "std")]
+    fn last_token(content: &str) -> Option<Token> {
+        rustc_lexer::tokenize(content).last()
+    }
+
+    // What the end of the code is part of, if it's an unterminated string literal or comment.
+    #[cfg(feature = "std")]
+    fn unterminated_token(content: &str) -> Option<&'static str> {
+        let token = Self::last_token(content);
+
+        if Self::is_inside_str_literal(token.as_ref()) {
+            Some("a string literal")
+        } else if Self::is_inside_block_comment(token.as_ref()) {
+            Some("a block comment")
+        } else if Self::is_inside_line_comment(token.as_ref()) {
+            Some("a line comment")
         } else {
             None
         }
     }
 
-    fn tokenize_code_from_str(content: &str) -> Vec<Token> {
-        rustc_lexer::tokenize(content).collect::<Vec<_>>()
+    #[cfg(not(feature = "std"))]
+    fn unterminated_token(content: &str) -> Option<&'static str> {
+        unterminated_token(content)
     }
 
-    fn is_inside_line_comment(tokens: &Vec<Token>) -> bool {
-        tokens.iter().last()
-            .map_or(false, |token| token.kind == TokenKind::LineComment)
+    #[cfg(feature = "std")]
+    fn is_inside_line_comment(token: Option<&Token>) -> bool {
+        token.is_some_and(|token| token.kind == TokenKind::LineComment)
     }
 
-    fn is_inside_str_literal(tokens: &Vec<Token>) -> bool {
-        tokens.iter().last()
-            .map_or(false, |token| {
-                match token.kind {
-                    TokenKind::Literal { kind, .. } => {
-                        match kind {
-                            LiteralKind::Str { terminated } => terminated == false,
-                            _ => false,
-                        }
-                    }
-                    _ => false
-                }
-            })
+    #[cfg(feature = "std")]
+    fn is_inside_block_comment(token: Option<&Token>) -> bool {
+        token.is_some_and(|token| token.kind == TokenKind::BlockComment { terminated: false })
     }
 
-    fn push_char_to_latest_entry(&mut self, c: char) {
-        let is_correct_type = match (&self.state, self.data.last()) {
-            (TextCodeFSAState::ParsingCode, Some(Part::Code(_))) => true,
-            (TextCodeFSAState::ParsingText, Some(Part::Text(_))) => true,
-            (TextCodeFSAState::ParsingEchoCode, Some(Part::EchoCode(_))) => true,
-            _ => false,
-        };
+    #[cfg(feature = "std")]
+    fn is_inside_str_literal(token: Option<&Token>) -> bool {
+        token.is_some_and(|token| {
+            matches!(
+                token.kind,
+                TokenKind::Literal { kind: LiteralKind::Str { terminated: false }, .. }
+            )
+        })
+    }
 
-        if self.data.last().is_some() && is_correct_type {
-            self.data.last_mut().unwrap().add_char_to_content(c);
-        } else {
-            match self.state {
-                TextCodeFSAState::ParsingText => self.data.push(Part::Text(c.to_string())),
-                TextCodeFSAState::ParsingCode => self.data.push(Part::Code(c.to_string())),
-                TextCodeFSAState::ParsingEchoCode => self.data.push(Part::EchoCode(c.to_string())),
-            }
+    // Checks whether the code forms a valid token stream, i.e. all literals and comments
+    // are terminated and all delimiters are balanced.
+    #[cfg(feature = "std")]
+    pub fn check_if_rust_code_is_valid(code: &str) -> bool {
+        code.parse::<proc_macro2::TokenStream>().is_ok()
+    }
+
+    // Byte ranges of the parts' content in the last payload, in the same order as the parts.
+    pub fn spans(&self) -> &[Range<usize>] {
+        &self.spans
+    }
+
+    // Byte offset of the tag which is still open at the end of the payload, if any.
+    pub fn unterminated_tag(&self) -> Option<usize> {
+        match self.state {
+            TextCodeFSAState::ParsingText => None,
+            TextCodeFSAState::ParsingCode | TextCodeFSAState::ParsingEchoCode => Some(self.tag_start),
         }
     }
 
+    // `?>` which didn't close the tag left open at the end of the payload, e.g. because it
+    // was commented out, with `string literal`, `block comment` or `line comment`.
+    pub fn ignored_end_tag(&self) -> Option<(usize, &'static str)> {
+        self.unterminated_tag().and(self.ignored_end_tag)
+    }
+
     pub fn run(&mut self, payload: String) -> &Vec<Part> {
-        let payload_chars = payload.chars().collect::<Vec<_>>();
+        let mut parts = Vec::new();
+        let mut spans = Vec::new();
+        self.scan(&payload, |state, span| {
+            let content = payload[span.clone()].to_string();
+            parts.push(match state {
+                TextCodeFSAState::ParsingText => Part::Text(content),
+                TextCodeFSAState::ParsingCode => Part::Code(content),
+                TextCodeFSAState::ParsingEchoCode => Part::EchoCode(content),
+            });
+            spans.push(span);
+        });
+        self.data.extend(parts);
+        self.spans.extend(spans);
+
+        &self.data
+    }
+
+    // Splits the payload into parts without copying their content, calling `emit` with the state
+    // each part was parsed in and the byte range of its content. Each part is found by searching
+    // for the delimiter ending it, so text and code are pushed as a whole.
+    pub(crate) fn scan(&mut self, payload: &str, mut emit: impl FnMut(TextCodeFSAState, Range<usize>)) {
+        // Byte offset into the payload, always on a char boundary.
+        let mut payload_index: usize = 0;
 
-        let mut payload_char_index: usize = 0;
+        while payload_index < payload.len() {
+            let rest = &payload[payload_index..];
 
-        while payload_char_index < payload_chars.len() {
             match self.state {
                 TextCodeFSAState::ParsingCode |
                 TextCodeFSAState::ParsingEchoCode => {
-                    if payload[payload_char_index..].starts_with("?>") {
-                        let latest_rust_code_part = self.get_last_part_content().unwrap_or("");
-
-                        let tokens = Self::tokenize_code_from_str(latest_rust_code_part);
-
-                        if Self::is_inside_str_literal(&tokens) {
-                            self.push_char_to_latest_entry(payload_chars[payload_char_index]);
-                            payload_char_index += 1;
-                            continue;
+                    // The first `?>` which isn't part of a string literal or a comment closes the
+                    // tag.
+                    let mut end_tag = None;
+                    for position in memchr::memmem::find_iter(rest.as_bytes(), "?>") {
+                        match Self::unterminated_token(&rest[..position]) {
+                            Some(ignored_by) => {
+                                self.ignored_end_tag.get_or_insert((payload_index + position, ignored_by));
+                            }
+                            None => {
+                                end_tag = Some(position);
+                                break;
+                            }
                         }
+                    }
 
-                        if Self::is_inside_line_comment(&tokens) {
-                            // dbg_vec_token(tokens, latest_rust_code_part);
-                            self.push_char_to_latest_entry(payload_chars[payload_char_index]);
-                            payload_char_index += 1;
-                            continue;
+                    let end = end_tag.unwrap_or(rest.len());
+                    if end > 0 {
+                        emit(self.state, payload_index..payload_index + end);
+                    }
+                    let Some(position) = end_tag else {
+                        break;
+                    };
+
+                    payload_index += position + "?>".len();
+                    self.state = TextCodeFSAState::ParsingText;
+                }
+                TextCodeFSAState::ParsingText => {
+                    // `<` which doesn't start a tag is part of the text.
+                    let open_tag = memchr::memchr_iter(b'<', rest.as_bytes()).find_map(|position| {
+                        if rest[position..].starts_with("<?rs") {
+                            Some((position, "<?rs", TextCodeFSAState::ParsingCode))
+                        } else if rest[position..].starts_with("<?=") {
+                            Some((position, "<?=", TextCodeFSAState::ParsingEchoCode))
+                        } else {
+                            None
                         }
+                    });
 
-                        // println!("inside line comment: {}", Self::is_inside_line_comment(&tokens));
-                        // dbg_vec_token(tokens, latest_rust_code_part);
+                    let end = open_tag.map_or(rest.len(), |(position, ..)| position);
+                    if end > 0 {
+                        emit(self.state, payload_index..payload_index + end);
+                    }
+                    let Some((position, tag, state)) = open_tag else {
+                        break;
+                    };
+
+                    self.tag_start = payload_index + position;
+                    self.ignored_end_tag = None;
+                    payload_index += position + tag.len();
+                    self.state = state;
+                }
+            }
+        }
+    }
+}
 
-                        payload_char_index += "?>".len();
-                        self.state = TextCodeFSAState::ParsingText;
-                        continue;
+// Lexes just enough Rust to tell what the end of the code is part of, for builds without `std`,
+// which `rustc_lexer` needs. Like it, only unterminated plain string literals count, not raw or
+// byte ones.
+#[cfg(any(test, not(feature = "std")))]
+fn unterminated_token(code: &str) -> Option<&'static str> {
+    let bytes = code.as_bytes();
+    // Byte offset of the `"` closing the string starting at `start`, skipping escapes.
+    let closing_quote = |start: usize| {
+        let mut index = start;
+        while index < bytes.len() {
+            match bytes[index] {
+                b'\\' => index += 2,
+                b'"' => return Some(index),
+                _ => index += 1,
+            }
+        }
+        None
+    };
+    let mut index = 0;
+
+    while index < bytes.len() {
+        match (bytes[index], bytes.get(index + 1)) {
+            (b'/', Some(b'/')) => match memchr::memchr(b'\n', &bytes[index..]) {
+                Some(position) => index += position + 1,
+                None => return Some("a line comment"),
+            },
+            (b'/', Some(b'*')) => {
+                // Block comments nest.
+                let mut depth = 0;
+                loop {
+                    if bytes[index..].starts_with(b"/*") {
+                        depth += 1;
+                        index += 2;
+                    } else if bytes[index..].starts_with(b"*/") {
+                        depth -= 1;
+                        index += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else if index < bytes.len() {
+                        index += 1;
                     } else {
-                        self.push_char_to_latest_entry(payload_chars[payload_char_index]);
+                        return Some("a block comment");
                     }
                 }
-                TextCodeFSAState::ParsingText => {
-                    if payload[payload_char_index..].starts_with("<?rs") {
-                        payload_char_index += "<?rs".len();
-                        self.state = TextCodeFSAState::ParsingCode;
-                        continue;
-                    } else if payload[payload_char_index..].starts_with("<?=") {
-                        payload_char_index += "<?=".len();
-                        self.state = TextCodeFSAState::ParsingEchoCode;
-                        continue;
-                    } else {
-                        self.push_char_to_latest_entry(payload_chars[payload_char_index]);
+            }
+            (b'"', _) => match closing_quote(index + 1) {
+                Some(end) => index = end + 1,
+                None => return Some("a string literal"),
+            },
+            // Char literals, which may hold quotes, unlike lifetimes.
+            (b'\'', Some(b'\\')) => {
+                let end = bytes.get(index + 3..).and_then(|rest| rest.iter().position(|&byte| byte == b'\''));
+                index = end.map_or(bytes.len(), |position| index + 4 + position);
+            }
+            (b'\'', _) => {
+                let next = code[index + 1..].chars().next().map_or(0, char::len_utf8);
+                index += match bytes.get(index + 1 + next) {
+                    Some(b'\'') => 2 + next,
+                    _ => 1,
+                };
+            }
+            (byte, _) if byte == b'_' || byte.is_ascii_alphabetic() => {
+                let start = index;
+                while index < bytes.len() && (bytes[index] == b'_' || bytes[index].is_ascii_alphanumeric()) {
+                    index += 1;
+                }
+                let hashes = bytes[index..].iter().take_while(|&&byte| byte == b'#').count();
+
+                match (&code[start..index], bytes.get(index + hashes)) {
+                    // Raw strings end with a quote followed by as many `#` as they start with.
+                    ("r" | "br" | "cr", Some(b'"')) => {
+                        let end = (index + hashes + 1..bytes.len()).find(|&end| {
+                            bytes[end] == b'"' && bytes[end + 1..].iter().take(hashes).filter(|&&byte| byte == b'#').count() == hashes
+                        });
+                        match end {
+                            Some(end) => index = end + 1 + hashes,
+                            None => return None,
+                        }
                     }
+                    ("b" | "c", Some(b'"')) if hashes == 0 => match closing_quote(index + 1) {
+                        Some(end) => index = end + 1,
+                        None => return None,
+                    },
+                    _ => {}
                 }
             }
-
-            payload_char_index += 1;
+            _ => index += 1,
         }
-
-        &self.data
     }
+
+    None
 }
 
 #[cfg(test)]
 mod tests {
     use std::fs::read_to_string;
-    use crate::text_code_fsa::{Part, TextCodeFSA};
+    use crate::text_code_fsa::{unterminated_token, Part, TextCodeFSA};
 
     #[test]
     fn it_works() {
@@ -303,10 +465,108 @@ mod tests {
         assert!(matches!(result[0].clone(), Part::Code(content) if content == "<?rs"));
     }
 
+    #[test]
+    fn it_splits_adjacent_code_parts() {
+        let mut fsa = TextCodeFSA::new();
+
+        let result = fsa.run("<?rs a ?><?rs b ?><?= c ?>".to_string());
+
+        assert_eq!(result.len(), 3);
+
+        assert!(matches!(result[0].clone(), Part::Code(content) if content == " a "));
+        assert!(matches!(result[1].clone(), Part::Code(content) if content == " b "));
+        assert!(matches!(result[2].clone(), Part::EchoCode(content) if content == " c "));
+    }
+
+    #[test]
+    fn it_handles_multibyte_characters() {
+        let mut fsa = TextCodeFSA::new();
+
+        let result = fsa.run("Zażółć <?= \"gęślą\" ?> jaźń".to_string());
+
+        assert_eq!(result.len(), 3);
+
+        assert!(matches!(result[0].clone(), Part::Text(content) if content == "Zażółć "));
+        assert!(matches!(result[1].clone(), Part::EchoCode(content) if content == " \"gęślą\" "));
+        assert!(matches!(result[2].clone(), Part::Text(content) if content == " jaźń"));
+    }
+
+    #[test]
+    fn it_records_part_spans() {
+        let mut fsa = TextCodeFSA::new();
+
+        let payload = "ż<?rs a ?>b<?= c".to_string();
+        fsa.run(payload.clone());
+
+        let spans = fsa.spans().to_vec();
+        assert_eq!(spans.len(), 4);
+        assert_eq!(&payload[spans[0].clone()], "ż");
+        assert_eq!(&payload[spans[1].clone()], " a ");
+        assert_eq!(&payload[spans[2].clone()], "b");
+        assert_eq!(&payload[spans[3].clone()], " c");
+        assert_eq!(fsa.unterminated_tag(), Some(12));
+    }
+
+    #[test]
+    fn it_keeps_delimiter_chars_which_dont_form_a_tag() {
+        let mut fsa = TextCodeFSA::new();
+
+        let payload = format!("{}<a>ü<?xml?><?= a < b ? c : d ?>?<", "x".repeat(1000));
+        let result = fsa.run(payload.clone());
+
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[0], Part::Text(format!("{}<a>ü<?xml?>", "x".repeat(1000))));
+        assert_eq!(result[1], Part::EchoCode(" a < b ? c : d ".to_string()));
+        assert_eq!(result[2], Part::Text("?<".to_string()));
+    }
+
     #[test]
     fn it_handles_block_comments_correctly() {
-        //TODO
-        unimplemented!()
+        let test_file = read_to_string("src/test-files/05.plt").unwrap();
+
+        let mut fsa = TextCodeFSA::new();
+
+        let result = fsa.run(test_file);
+
+        assert_eq!(result.len(), 3);
+
+        assert!(matches!(result[0].clone(), Part::Text(content) if content == "<!DOCTYPE html>\r\n<html>\r\n    <head>\r\n        <title>"));
+        assert!(matches!(result[1].clone(), Part::Code(content) if content == " \"hello world\" /* some ?> comment */ "));
+        assert!(matches!(result[2].clone(), Part::Text(content) if content == "</title>\r\n    </head>\r\n</html>"));
+        assert_eq!(fsa.ignored_end_tag(), None);
+    }
+
+    #[test]
+    fn it_remembers_end_tags_which_didnt_close_the_open_tag() {
+        let mut fsa = TextCodeFSA::new();
+        fsa.run("<?rs /* ?> <?= \"?>\" ?>".to_string());
+        assert_eq!(fsa.ignored_end_tag(), Some((8, "a block comment")));
+
+        let mut fsa = TextCodeFSA::new();
+        fsa.run("<?rs // ?>\n ?><?= \"?>".to_string());
+        assert_eq!(fsa.ignored_end_tag(), Some((19, "a string literal")));
+    }
+
+    #[test]
+    fn it_finds_unterminated_tokens_like_rustc_lexer() {
+        let codes = [
+            " \"a ",
+            " \"a\\\" b",
+            " \"a\" // b",
+            " /* a /* b */ ",
+            " /* a /* b */ */ c",
+            " '\"' \"a",
+            " '\\'' // a",
+            " 'a: loop { \"",
+            " r#\"a\" \"#",
+            " r#\"a\"# \"",
+            " b\"a",
+            " \"ż\" 'ż' /*",
+        ];
+
+        for code in codes {
+            assert_eq!(unterminated_token(code), TextCodeFSA::unterminated_token(code), "{code}");
+        }
     }
 
     #[test]
@@ -314,6 +574,6 @@ mod tests {
         assert!(TextCodeFSA::check_if_rust_code_is_valid(" \"hello world\" "));
         assert!(TextCodeFSA::check_if_rust_code_is_valid(" \"hello ?> world\" "));
 
-        assert_eq!(TextCodeFSA::check_if_rust_code_is_valid(" \"hello ?"), false);
+        assert!(!TextCodeFSA::check_if_rust_code_is_valid(" \"hello ?"));
     }
 }
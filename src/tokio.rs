@@ -0,0 +1,9 @@
+// Rendering into a tokio `AsyncWrite`, e.g. straight into a socket without buffering the page:
+//
+//     templates::index_write(&mut socket, title).await?;
+//
+// The `<function>_write` functions are generated with `GeneratorOptions::async_write`. They
+// write text and echoed values as they go and call the `_write` functions of included
+// templates, layouts and components. Captures, block overrides and slots are still rendered
+// into a `String` first, since their output is needed as a value.
+pub use ::tokio::io::{AsyncWrite, AsyncWriteExt};
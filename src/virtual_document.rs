@@ -0,0 +1,127 @@
+// Generated Rust code of a template for editor tooling, e.g. to have rust-analyzer check and
+// complete the code inside of tags. The code of each tag which appears as it is in the generated
+// function, e.g. `user.name` of `<?= user.name ?>` or `a > 1` of `<?rs @if a > 1 ?>`, is mapped
+// to it, so offsets can be translated in both directions.
+use std::ops::Range;
+use anyhow::bail;
+use crate::file_generator::generate_mapped_template;
+use crate::prelude::*;
+
+// Byte range of code in the template along with the range of the same code in the generated code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub template: Range<usize>,
+    pub generated: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualDocument {
+    pub path: String,
+    // Unformatted code of the function rendering the template.
+    pub code: String,
+    // Mappings in order of their offsets, which never overlap.
+    pub mappings: Vec<Mapping>,
+}
+
+impl VirtualDocument {
+    // Offset in the generated code of the byte `offset` of the template, if it's part of mapped code.
+    pub fn to_generated(&self, offset: usize) -> Option<usize> {
+        self.mappings
+            .iter()
+            .find(|mapping| mapping.template.start <= offset && offset <= mapping.template.end)
+            .map(|mapping| mapping.generated.start + offset - mapping.template.start)
+    }
+
+    // Offset in the template of the byte `offset` of the generated code, if it's part of mapped code.
+    pub fn to_template(&self, offset: usize) -> Option<usize> {
+        self.mappings
+            .iter()
+            .find(|mapping| mapping.generated.start <= offset && offset <= mapping.generated.end)
+            .map(|mapping| mapping.template.start + offset - mapping.generated.start)
+    }
+}
+
+impl TemplateSet {
+    pub fn virtual_document(&self, path: &str, options: &GeneratorOptions) -> Result<VirtualDocument> {
+        let Some(template) = self.get(path) else {
+            bail!("template `{path}` is not part of the template set");
+        };
+        if let Some(offset) = template.unterminated_tag {
+            return Err(template.unterminated_tag_error(offset));
+        }
+
+        let (lines, part_lines) = generate_mapped_template(template, self, options)?;
+        let mut line_starts = Vec::new();
+        let mut offset = 0;
+        for line in &lines {
+            line_starts.push(offset);
+            offset += line.len() + 1;
+        }
+        let code = lines.join("\n");
+
+        let mut mappings = Vec::new();
+        for (part, part_lines) in part_lines {
+            let span = &template.spans[part];
+            // Markdown templates are rendered before being split into parts, so their spans don't
+            // point at the code.
+            if part_lines.is_empty() || template.source.get(span.clone()) != Some(template.parts[part].get_content().as_str()) {
+                continue;
+            }
+            let Some(mapped) = mapped_code(&template.parts[part]) else {
+                continue;
+            };
+
+            let generated = line_starts[part_lines.start]..line_starts[part_lines.end - 1] + lines[part_lines.end - 1].len();
+            let needle = &template.source[span.start + mapped.start..span.start + mapped.end];
+            if let Some(position) = code[generated.clone()].find(needle) {
+                let start = generated.start + position;
+                mappings.push(Mapping { template: span.start + mapped.start..span.start + mapped.end, generated: start..start + needle.len() });
+            }
+        }
+
+        Ok(VirtualDocument { path: path.to_string(), code, mappings })
+    }
+}
+
+// Range of the code of a part which is expected to appear as it is in the generated code: the
+// code without surrounding whitespace, or the argument of directives.
+fn mapped_code(part: &Part) -> Option<Range<usize>> {
+    let content = part.get_content();
+    let mut start = content.len() - content.trim_start().len();
+
+    if matches!(part, Part::Code(_)) && Directive::parse(content).ok().flatten().is_some() {
+        let name = &content[start + 1..];
+        let name_len = name.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(name.len());
+        let argument = &name[name_len..];
+        start += 1 + name_len + argument.len() - argument.trim_start().len();
+    }
+
+    let end = content.trim_end().len();
+    (start < end).then_some(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    #[test]
+    fn it_maps_the_code_of_templates_to_the_generated_code() {
+        let source = "<p><?rs @if user.age > 17 ?><?= user.name ?><?rs let count = 2; ?><?rs @endif ?></p>";
+        let mut set = TemplateSet::new();
+        set.add_template("index.plt", vec!["user: &User".to_string()], source.to_string());
+
+        let document = set.virtual_document("index.plt", &GeneratorOptions::default()).unwrap();
+        let mapped: Vec<_> = document
+            .mappings
+            .iter()
+            .map(|mapping| (&source[mapping.template.clone()], &document.code[mapping.generated.clone()]))
+            .collect();
+        assert_eq!(mapped, vec![("user.age > 17", "user.age > 17"), ("user.name", "user.name"), ("let count = 2;", "let count = 2;")]);
+
+        let name = source.find("name").unwrap();
+        let generated = document.to_generated(name).unwrap();
+        assert_eq!(&document.code[generated..generated + 4], "name");
+        assert_eq!(document.to_template(generated), Some(name));
+        assert_eq!(document.to_generated(1), None);
+    }
+}
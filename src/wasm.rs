@@ -0,0 +1,118 @@
+// JavaScript API for browsers and edge runtimes, e.g. template playgrounds, built for
+// `wasm32-unknown-unknown` with the `wasm-bindgen` feature, e.g. by
+// `wasm-pack build --features wasm-bindgen`:
+//
+//     import { parse, renderDynamic } from "./pkg/plt.js";
+//
+//     parse("Hello <?= name ?>!").map((part) => part.kind);  // ["text", "echo", "text"]
+//     renderDynamic("Hello <?= name ?>!", JSON.stringify({ name: "Ann" }), "html");  // "Hello Ann!"
+//
+// Templates are rendered by the dynamic engine, see `plt::dynamic`, so they can only use what it
+// supports. Errors are thrown as JavaScript `Error`s.
+use anyhow::bail;
+use wasm_bindgen::prelude::*;
+use crate::dynamic::{Engine, EngineOptions};
+use crate::prelude::*;
+
+// Path of the template rendered by `renderDynamic`, which its errors refer to.
+const TEMPLATE_PATH: &str = "template.plt";
+
+// Part of a parsed template, with the byte range of its content in the source.
+#[wasm_bindgen(js_name = Part)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct WasmPart {
+    kind: &'static str,
+    content: String,
+    start: usize,
+    end: usize,
+}
+
+#[wasm_bindgen(js_class = Part)]
+impl WasmPart {
+    // `text`, `code` or `echo`.
+    #[wasm_bindgen(getter)]
+    pub fn kind(&self) -> String {
+        self.kind.to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn content(&self) -> String {
+        self.content.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn end(&self) -> usize {
+        self.end
+    }
+}
+
+#[wasm_bindgen]
+pub fn parse(source: &str) -> Result<Vec<WasmPart>, JsError> {
+    parse_parts(source).map_err(|error| JsError::new(&format!("{error:#}")))
+}
+
+// Renders `source` against the JSON object `context`, escaping echoes using the escaping mode
+// named `escaping`, e.g. `html`, or not at all when it's missing.
+#[wasm_bindgen(js_name = renderDynamic)]
+pub fn render_dynamic(source: &str, context: &str, escaping: Option<String>) -> Result<String, JsError> {
+    render(source, context, escaping.as_deref()).map_err(|error| JsError::new(&format!("{error:#}")))
+}
+
+fn parse_parts(source: &str) -> Result<Vec<WasmPart>> {
+    let mut fsa = TextCodeFSA::new();
+    let parts = fsa.run(source.to_string()).clone();
+
+    if let Some(offset) = fsa.unterminated_tag() {
+        let (line, column) = line_column(source, offset);
+        bail!("{line}:{column}: unterminated tag, expected `?>`");
+    }
+
+    let parts = parts.into_iter().zip(fsa.spans()).map(|(part, span)| {
+        let kind = match part {
+            Part::Text(_) => "text",
+            Part::Code(_) => "code",
+            Part::EchoCode(_) => "echo",
+        };
+
+        WasmPart { kind, content: part.get_content().clone(), start: span.start, end: span.end }
+    });
+
+    Ok(parts.collect())
+}
+
+fn render(source: &str, context: &str, escaping: Option<&str>) -> Result<String> {
+    let escaping = escaping.map_or(Ok(Escaping::None), str::parse)?;
+    let context = serde_json::from_str(context)?;
+
+    let mut engine = Engine::with_options(EngineOptions { escaping, ..EngineOptions::default() });
+    engine.add_template(TEMPLATE_PATH, source);
+
+    engine.render(TEMPLATE_PATH, &context)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wasm::{parse_parts, render, WasmPart};
+
+    #[test]
+    fn it_parses_templates_into_parts() {
+        let parts = parse_parts("Hello <?= name ?>!").unwrap();
+
+        assert_eq!(parts.iter().map(WasmPart::kind).collect::<Vec<_>>(), ["text", "echo", "text"]);
+        assert_eq!((parts[1].content(), parts[1].start(), parts[1].end()), (" name ".to_string(), 9, 15));
+        assert_eq!(parse_parts("a\n<?rs b").unwrap_err().to_string(), "2:1: unterminated tag, expected `?>`");
+    }
+
+    #[test]
+    fn it_renders_templates_dynamically() {
+        assert_eq!(render("Hello <?= name ?>!", r#"{"name": "<Ann>"}"#, Some("html")).unwrap(), "Hello &lt;Ann&gt;!");
+        assert_eq!(render("<?= name ?>", r#"{"name": "<Ann>"}"#, None).unwrap(), "<Ann>");
+        assert!(render("<?= name ?>", "{", None).is_err());
+        assert!(render("<?= name ?>", "{}", Some("rot13")).unwrap_err().to_string().starts_with("unknown escaping mode `rot13`"));
+    }
+}
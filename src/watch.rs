@@ -0,0 +1,295 @@
+// Regenerates templates whenever they change, for development:
+//
+//     let mut watcher = plt::watch::Watcher::new("templates", "src/generated")?;
+//     watcher.watch(|summary| println!("{summary}"))?;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+use anyhow::Context;
+use notify::{RecursiveMode, Watcher as _};
+use crate::build::{write_functions, BuildOptions};
+use crate::prelude::*;
+
+// What a single regeneration did, with template paths relative to the template directory.
+#[derive(Debug, Default, PartialEq)]
+pub struct WatchSummary {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+    // Templates whose functions were generated again, including dependents of changed templates.
+    pub regenerated: Vec<String>,
+    // Errors of all templates which fail to generate, including ones which failed in earlier
+    // regenerations. Nothing is written until they are fixed.
+    pub errors: Vec<String>,
+}
+
+impl WatchSummary {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl fmt::Display for WatchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (label, paths) in [("added", &self.added), ("changed", &self.changed), ("removed", &self.removed)] {
+            if !paths.is_empty() {
+                writeln!(f, "{label}: {}", paths.join(", "))?;
+            }
+        }
+
+        write!(f, "regenerated {} templates", self.regenerated.len())?;
+        for error in &self.errors {
+            write!(f, "\nerror: {error}")?;
+        }
+
+        Ok(())
+    }
+}
+
+// Keeps the generated code of a template directory up to date. Only the changed templates
+// and the templates depending on them are generated again.
+#[derive(Debug)]
+pub struct Watcher {
+    template_dir: PathBuf,
+    out_dir: PathBuf,
+    options: BuildOptions,
+    debounce: Duration,
+    set: TemplateSet,
+    functions: BTreeMap<String, GeneratedFunction>,
+    // Errors of the templates which failed to generate, keyed by their paths.
+    failed: BTreeMap<String, String>,
+}
+
+impl Watcher {
+    pub fn new(template_dir: impl AsRef<Path>, out_dir: impl AsRef<Path>) -> Result<Watcher> {
+        Self::with_options(template_dir, out_dir, BuildOptions::default())
+    }
+
+    // Generates all templates right away, so the output is complete before watching starts.
+    pub fn with_options(
+        template_dir: impl AsRef<Path>,
+        out_dir: impl AsRef<Path>,
+        options: BuildOptions,
+    ) -> Result<Watcher> {
+        let template_dir = template_dir.as_ref();
+        let template_dir = template_dir
+            .canonicalize()
+            .with_context(|| format!("failed to read template directory `{}`", template_dir.display()))?;
+
+        let mut watcher = Watcher {
+            template_dir,
+            out_dir: out_dir.as_ref().to_path_buf(),
+            options,
+            debounce: Duration::from_millis(100),
+            set: TemplateSet::new(),
+            functions: BTreeMap::new(),
+            failed: BTreeMap::new(),
+        };
+        watcher.rescan()?;
+
+        Ok(watcher)
+    }
+
+    // Errors of the templates which currently fail to generate, e.g. since the watcher was
+    // created.
+    pub fn errors(&self) -> Vec<String> {
+        self.failed.values().cloned().collect()
+    }
+
+    // How long to wait for further changes before regenerating, so e.g. editors saving
+    // several files at once only cause a single regeneration.
+    pub fn with_debounce(mut self, debounce: Duration) -> Watcher {
+        self.debounce = debounce;
+        self
+    }
+
+    // Compares the templates on disk with the loaded ones and regenerates the affected templates.
+    pub fn rescan(&mut self) -> Result<WatchSummary> {
+        let mut summary = WatchSummary::default();
+
+        let mut sources = BTreeMap::new();
+        for file in template_files(&self.template_dir)? {
+            let source = std::fs::read_to_string(&file)
+                .with_context(|| format!("failed to read template `{}`", file.display()))?;
+
+            sources.insert(relative_template_path(&self.template_dir, &file)?, source);
+        }
+
+        let removed: Vec<String> = self.set.paths().filter(|path| !sources.contains_key(*path)).cloned().collect();
+        for path in removed {
+            self.set.remove_template(&path);
+            self.functions.remove(&path);
+            self.failed.remove(&path);
+            summary.removed.push(path);
+        }
+
+        for (path, source) in sources {
+            match self.set.get(&path) {
+                Some(template) if template.source == source => continue,
+                Some(_) => summary.changed.push(path.clone()),
+                None => summary.added.push(path.clone()),
+            }
+
+            self.set.add_template(path, Vec::new(), source);
+        }
+
+        // Templates which failed are generated again, as the templates they include may have
+        // been fixed.
+        let mut affected: Vec<String> = self.failed.keys().cloned().collect();
+        for path in summary.added.iter().chain(&summary.changed).chain(&summary.removed) {
+            if self.set.get(path).is_some() {
+                affected.push(path.clone());
+            }
+            affected.extend(self.set.dependents(path)?);
+        }
+        affected.sort();
+        affected.dedup();
+
+        for path in affected {
+            match self.set.generate_function(&path, &self.options.generator) {
+                Ok(function) => {
+                    self.functions.insert(path.clone(), function);
+                    self.failed.remove(&path);
+                    summary.regenerated.push(path);
+                }
+                Err(error) => {
+                    self.failed.insert(path, format!("{error:#}"));
+                }
+            }
+        }
+        summary.errors = self.errors();
+
+        // The functions of failed templates are missing or outdated, so the module would be
+        // incomplete.
+        if summary.errors.is_empty() && !summary.is_empty() {
+            write_functions(&self.out_dir, self.functions.values().cloned().collect(), &self.options)?;
+        }
+
+        Ok(summary)
+    }
+
+    // Watches the template directory, calling `on_change` after each regeneration. Only
+    // returns when watching fails.
+    pub fn watch(&mut self, mut on_change: impl FnMut(Result<WatchSummary>)) -> Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender).context("failed to start watching")?;
+        watcher
+            .watch(&self.template_dir, RecursiveMode::Recursive)
+            .with_context(|| format!("failed to watch `{}`", self.template_dir.display()))?;
+
+        loop {
+            let event = receiver.recv().context("stopped watching")?;
+            event.context("failed to watch for changes")?;
+
+            // Waits until no more events arrive within the debounce duration.
+            while receiver.recv_timeout(self.debounce).is_ok() {}
+
+            let summary = self.rescan();
+            if summary.as_ref().is_ok_and(|summary| summary.is_empty()) {
+                continue;
+            }
+
+            on_change(summary);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::watch::{WatchSummary, Watcher};
+    use std::fs::{read_to_string, remove_file, write};
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("plt-watch-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("templates/partials")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn it_regenerates_changed_templates_and_their_dependents() {
+        let dir = temp_dir("rescan");
+        let template_dir = dir.join("templates");
+        write(template_dir.join("index.plt"), "<?rs @include \"partials/header.plt\" ?>Index").unwrap();
+        write(template_dir.join("about.plt"), "About").unwrap();
+        write(template_dir.join("partials/header.plt"), "Header").unwrap();
+
+        let mut watcher = Watcher::new(&template_dir, dir.join("out")).unwrap();
+        assert!(read_to_string(dir.join("out/templates.rs")).unwrap().contains("\"Header\""));
+
+        write(template_dir.join("partials/header.plt"), "New header").unwrap();
+        write(template_dir.join("contact.plt"), "Contact").unwrap();
+        remove_file(template_dir.join("about.plt")).unwrap();
+        let summary = watcher.rescan().unwrap();
+
+        let code = read_to_string(dir.join("out/templates.rs")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            summary,
+            WatchSummary {
+                added: vec!["contact.plt".to_string()],
+                changed: vec!["partials/header.plt".to_string()],
+                removed: vec!["about.plt".to_string()],
+                regenerated: vec!["contact.plt".to_string(), "index.plt".to_string(), "partials/header.plt".to_string()],
+                errors: Vec::new(),
+            }
+        );
+        assert!(code.contains("\"New header\""));
+        assert!(code.contains("pub fn contact()"));
+        assert!(!code.contains("pub fn about()"));
+    }
+
+    #[test]
+    fn it_keeps_the_output_until_errors_are_fixed() {
+        let dir = temp_dir("errors");
+        let template_dir = dir.join("templates");
+        write(template_dir.join("index.plt"), "Index").unwrap();
+
+        let mut watcher = Watcher::new(&template_dir, dir.join("out")).unwrap();
+
+        write(template_dir.join("index.plt"), "<?rs @endmatch ?>").unwrap();
+        let summary = watcher.rescan().unwrap();
+        let code = read_to_string(dir.join("out/templates.rs")).unwrap();
+
+        assert_eq!(summary.errors, vec!["index.plt:1:5: `@endmatch` found without matching `@match`"]);
+        assert!(code.contains("\"Index\""));
+
+        write(template_dir.join("index.plt"), "Fixed").unwrap();
+        let summary = watcher.rescan().unwrap();
+        let code = read_to_string(dir.join("out/templates.rs")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(summary.errors.is_empty());
+        assert!(code.contains("\"Fixed\""));
+    }
+
+    #[test]
+    fn it_keeps_reporting_errors_when_other_templates_change() {
+        let dir = temp_dir("other-changes");
+        let template_dir = dir.join("templates");
+        write(template_dir.join("index.plt"), "<?rs @endmatch ?>").unwrap();
+        write(template_dir.join("about.plt"), "About").unwrap();
+
+        let mut watcher = Watcher::new(&template_dir, dir.join("out")).unwrap();
+        assert_eq!(watcher.errors(), vec!["index.plt:1:5: `@endmatch` found without matching `@match`"]);
+        assert!(!dir.join("out/templates.rs").exists());
+
+        write(template_dir.join("about.plt"), "New about").unwrap();
+        let summary = watcher.rescan().unwrap();
+
+        assert_eq!(summary.changed, vec!["about.plt"]);
+        assert_eq!(summary.errors, vec!["index.plt:1:5: `@endmatch` found without matching `@match`"]);
+        assert!(!dir.join("out/templates.rs").exists());
+
+        write(template_dir.join("index.plt"), "Index").unwrap();
+        let summary = watcher.rescan().unwrap();
+        let code = read_to_string(dir.join("out/templates.rs")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(summary.errors.is_empty());
+        assert!(code.contains("\"New about\"") && code.contains("\"Index\""));
+    }
+}
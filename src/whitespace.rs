@@ -0,0 +1,199 @@
+use std::str::FromStr;
+use anyhow::bail;
+use crate::prelude::*;
+
+// What happens to the line breaks at the end of templates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TrailingNewline {
+    // Renders them as they're written.
+    #[default]
+    Keep,
+    // Removes them, e.g. so partials can be included in the middle of a line.
+    Strip,
+    // Renders exactly one, whether the template ends with none or several.
+    Single,
+}
+
+impl FromStr for TrailingNewline {
+    type Err = anyhow::Error;
+
+    fn from_str(trailing_newline: &str) -> Result<TrailingNewline> {
+        match trailing_newline {
+            "keep" => Ok(TrailingNewline::Keep),
+            "strip" => Ok(TrailingNewline::Strip),
+            "single" => Ok(TrailingNewline::Single),
+            _ => bail!("unknown trailing newline handling `{trailing_newline}`, expected `keep`, `strip` or `single`"),
+        }
+    }
+}
+
+// Parts of a template with the whitespace options applied to their text, before generating
+// code. Parts stay at the same indices, so they still match the spans of the template.
+pub fn normalize_parts(parts: &[Part], options: &GeneratorOptions) -> Vec<Part> {
+    let mut parts = parts.to_vec();
+    if options.trim_tag_newlines {
+        parts = trim_tag_newlines(&parts);
+    }
+    parts = with_trailing_newline(parts, options.trailing_newline);
+
+    let parts = minify_parts(&parts, options.minify);
+    match options.escaping {
+        Escaping::Csv(_) => crate::csv::crlf_line_breaks(parts),
+        _ => parts,
+    }
+}
+
+// Removes the lines of `<?rs ?>` tags which stand on their own line, i.e. the indentation before
+// them along with the line break after them, so e.g. `@if` and `@endif` don't leave empty lines
+// in the output.
+pub fn trim_tag_newlines(parts: &[Part]) -> Vec<Part> {
+    // Bytes removed from the start and the end of each text part.
+    let mut trimmed = vec![(0, 0); parts.len()];
+
+    for (index, part) in parts.iter().enumerate() {
+        if !matches!(part, Part::Code(_)) {
+            continue;
+        }
+        let indentation = match index.checked_sub(1).map(|previous| &parts[previous]) {
+            None => Some(0),
+            Some(Part::Text(text)) => {
+                let line = &text[text.rfind('\n').map_or(0, |newline| newline + 1)..];
+                // Text without a line break only starts a line at the start of the template.
+                let starts_line = index == 1 || text.contains('\n');
+                (starts_line && line.trim_start_matches([' ', '\t']).is_empty()).then_some(line.len())
+            }
+            Some(_) => None,
+        };
+        let line_end = match parts.get(index + 1) {
+            None => Some(0),
+            Some(Part::Text(text)) => {
+                let rest = text.trim_start_matches([' ', '\t']);
+                let newline = ["\n", "\r\n"].into_iter().find(|newline| rest.starts_with(newline));
+                newline.map(|newline| text.len() - rest.len() + newline.len())
+            }
+            Some(_) => None,
+        };
+
+        if let (Some(indentation), Some(line_end)) = (indentation, line_end) {
+            if index > 0 {
+                trimmed[index - 1].1 = indentation;
+            }
+            if index + 1 < parts.len() {
+                trimmed[index + 1].0 = line_end;
+            }
+        }
+    }
+
+    parts
+        .iter()
+        .zip(trimmed)
+        .map(|(part, (start, end))| match part {
+            Part::Text(text) => Part::Text(text.get(start..text.len() - end).unwrap_or_default().to_string()),
+            part => part.clone(),
+        })
+        .collect()
+}
+
+// Applies `trailing_newline` to the text at the end of the template. A template ending with a
+// tag gets a text part with the line break appended when one is expected.
+pub fn with_trailing_newline(mut parts: Vec<Part>, trailing_newline: TrailingNewline) -> Vec<Part> {
+    if trailing_newline == TrailingNewline::Keep {
+        return parts;
+    }
+
+    let text = match parts.last() {
+        Some(Part::Text(text)) => text.trim_end_matches(['\n', '\r']).to_string(),
+        _ => {
+            parts.push(Part::Text(String::new()));
+            String::new()
+        }
+    };
+    let len = parts.len();
+    parts[len - 1] = match trailing_newline {
+        TrailingNewline::Single => Part::Text(text + "\n"),
+        _ => Part::Text(text),
+    };
+
+    parts
+}
+
+// `parts` without the whitespace before the first text the template renders, when it starts with
+// `start`, e.g. the XML declaration or the shebang of scripts, which have to come first. Parts
+// stay at the same indices, so they still match the spans of the template.
+pub fn strip_leading_whitespace(mut parts: Vec<Part>, start: &str) -> Vec<Part> {
+    let Some(first) = parts.iter().position(|part| match part {
+        Part::Text(text) => !text.trim_start().is_empty(),
+        Part::EchoCode(_) => true,
+        Part::Code(code) => renders_output(code),
+    }) else {
+        return parts;
+    };
+    let Part::Text(text) = &parts[first] else {
+        return parts;
+    };
+    if !text.trim_start().starts_with(start) {
+        return parts;
+    }
+
+    parts[first] = Part::Text(text.trim_start().to_string());
+    for part in &mut parts[..first] {
+        if part.is_text() {
+            *part = Part::Text(String::new());
+        }
+    }
+
+    parts
+}
+
+// Whether a code part which comes before any text may write something. Only directives which
+// render nothing can come before the stripped whitespace, while plain Rust code is assumed to
+// compute values.
+fn renders_output(code: &str) -> bool {
+    !matches!(Directive::parse(code), Ok(None | Some(Directive::Args(_) | Directive::Example(_) | Directive::Lint { .. } | Directive::Let(_))))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    fn parse(source: &str) -> Vec<Part> {
+        TextCodeFSA::new().run(source.to_string()).clone()
+    }
+
+    fn texts(parts: &[Part]) -> String {
+        parts.iter().filter(|part| part.is_text()).map(|part| part.get_content().as_str()).collect()
+    }
+
+    #[test]
+    fn it_strips_the_whitespace_before_the_start_of_documents() {
+        let parts = strip_leading_whitespace(parse("<?rs @args title: &str ?>\n  <?xml version=\"1.0\"?>\n<a><?= title ?></a>"), "<?xml");
+        assert_eq!(parts[1], Part::Text("<?xml version=\"1.0\"?>\n<a>".to_string()));
+
+        let parts = parse("\n<?= title ?>\n<?xml version=\"1.0\"?>");
+        assert_eq!(strip_leading_whitespace(parts.clone(), "<?xml"), parts);
+    }
+
+    #[test]
+    fn it_trims_the_lines_of_tags_standing_on_their_own() {
+        let parts = parse("<?rs @args items: &[u8] ?>\n<ul>\n  <?rs @for item in items ?>\n  <li><?= item ?></li>\n  <?rs @endfor ?>\n</ul><?rs let a = 1; ?>\n");
+        let trimmed = trim_tag_newlines(&parts);
+
+        assert_eq!(trimmed.len(), parts.len());
+        assert_eq!(texts(&trimmed), "<ul>\n  <li></li>\n</ul>\n");
+        assert_eq!(texts(&trim_tag_newlines(&parse("a <?rs b ?>\nc\n  <?rs d ?> e"))), "a \nc\n   e");
+    }
+
+    #[test]
+    fn it_normalizes_the_trailing_newline() {
+        let parts = parse("<p><?= a ?></p>\n\n");
+
+        assert_eq!(texts(&with_trailing_newline(parts.clone(), TrailingNewline::Keep)), "<p></p>\n\n");
+        assert_eq!(texts(&with_trailing_newline(parts.clone(), TrailingNewline::Strip)), "<p></p>");
+        assert_eq!(texts(&with_trailing_newline(parts, TrailingNewline::Single)), "<p></p>\n");
+        assert_eq!(with_trailing_newline(parse("<?= a ?>"), TrailingNewline::Single)[1], Part::Text("\n".to_string()));
+        assert_eq!(
+            "all".parse::<TrailingNewline>().unwrap_err().to_string(),
+            "unknown trailing newline handling `all`, expected `keep`, `strip` or `single`"
+        );
+    }
+}
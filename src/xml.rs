@@ -0,0 +1,68 @@
+// XML templates, e.g. sitemaps, RSS and Atom feeds or SVG images. Templates ending with
+// `.xml.plt`, `.rss.plt`, `.atom.plt` or `.svg.plt` escape their echoes using `Escaping::Xml`
+// whatever the escaping of the other templates, and aren't checked against HTML rules:
+//
+//     <?rs @args urls: &[&str] ?>
+//     <?xml version="1.0" encoding="UTF-8"?>
+//     <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+//     <?rs @for url in urls ?>
+//       <url><loc><?= url ?></loc></url>
+//     <?rs @endfor ?>
+//     </urlset>
+//
+// An XML declaration has to come first in the document, so the whitespace before it, e.g. the
+// line break after `@args`, is left out. Text which shouldn't be escaped, e.g. the HTML content
+// of feed entries, can be written as a CDATA section using `<?= plt::xml::Cdata(content) ?>`.
+use std::fmt;
+use crate::prelude::*;
+
+const XML_EXTENSIONS: [&str; 4] = [".xml.plt", ".rss.plt", ".atom.plt", ".svg.plt"];
+
+// Whether the template under `path` renders XML, e.g. `sitemap.xml.plt`.
+pub fn is_xml_template(path: &str) -> bool {
+    let file = path.split('#').next().unwrap_or(path);
+
+    XML_EXTENSIONS.iter().any(|extension| file.ends_with(extension))
+}
+
+// Displays the wrapped value as a CDATA section, which XML parsers read as it is, splitting it
+// where it contains `]]>`. It's written as it is by `EscapeXml`.
+#[derive(Debug, Clone, Copy)]
+pub struct Cdata<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for Cdata<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let content = self.0.to_string().replace("]]>", "]]]]><![CDATA[>");
+
+        fmt::Display::fmt(&Raw(format_args!("<![CDATA[{content}]]>")), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use crate::xml::{is_xml_template, Cdata};
+
+    #[test]
+    fn it_detects_xml_templates() {
+        assert!(is_xml_template("sitemap.xml.plt"));
+        assert!(is_xml_template("feeds/blog.atom.plt#entry"));
+        assert!(!is_xml_template("index.plt"));
+    }
+
+    #[test]
+    fn it_writes_cdata_sections() {
+        assert_eq!(Cdata("<p>a]]>b</p>").to_string(), "<![CDATA[<p>a]]]]><![CDATA[>b</p>]]>");
+        assert_eq!(escape_xml(Cdata("<p>")), "<![CDATA[<p>]]>");
+    }
+
+    #[test]
+    fn it_escapes_echoes_of_xml_templates() {
+        let mut set = TemplateSet::new();
+        set.add_template("sitemap.xml.plt", vec!["url: &str".to_string()], "<?rs @args ?>\n<?xml version=\"1.0\"?>\n<loc><?= url ?></loc>".to_string());
+
+        let code = format_code(&set.generate("sitemap.xml.plt").unwrap().join("\n"));
+        assert!(code.contains("write!(output_buffer, \"{}\", \"<?xml version=\\\"1.0\\\"?>\\n<loc>\")?;"));
+        assert!(code.contains("write!(output_buffer, \"{}\", plt::prelude::EscapeXml(& (url)))?;"));
+    }
+}